@@ -1,16 +1,16 @@
 import os
 import subprocess
+import hashlib
 
 from migen import *
 from misoc.interconnect.csr import *
 from misoc.integration.builder import *
 
-from artiq.gateware.amp import AMPSoC
 from artiq import __version__ as artiq_version
 from artiq import __artiq_dir__ as artiq_dir
 
 
-__all__ = ["add_identifier", "build_artiq_soc"]
+__all__ = ["add_identifier", "build_artiq_soc_args", "build_artiq_soc"]
 
 
 def get_identifier_string(soc, suffix="", add_class_name=True):
@@ -23,16 +23,73 @@ def get_identifier_string(soc, suffix="", add_class_name=True):
     return r
 
 
+def get_compat_descriptor(soc):
+    """Returns a 32-bit hash of the CSR map and of the configuration
+    (feature flags) of the gateware, which the firmware built along with it
+    checks at boot. 0 is reserved for gateware without a descriptor."""
+    h = hashlib.sha256()
+    for name in soc.csr_devices:
+        h.update(name.encode() + b"\0")
+        dev = getattr(soc, name, None)
+        if hasattr(dev, "get_csrs"):
+            for csr in dev.get_csrs():
+                h.update("{}:{}\0".format(csr.name, csr.size).encode())
+    for k, v in sorted(soc.config.items()):
+        if k not in ("IDENTIFIER_STR", "COMPAT_DESCRIPTOR"):
+            h.update("{}={}\0".format(k, v).encode())
+    return int.from_bytes(h.digest()[:4], "big") or 1
+
+
+# The topology is read by the master over DRTIO aux in 32-byte chunks, with
+# an 8-bit length.
+MAX_TOPOLOGY_LENGTH = 255
+
+
+def get_topology(soc):
+    """Returns the RTIO topology of the gateware, as reported by the firmware
+    to host tools: ``key=value`` fields separated by semicolons, giving the
+    role, the number of downstream DRTIO links, the number of local RTIO
+    channels and the log channel, followed by the channel range of each EEM
+    peripheral as ``type@ports=first-last`` (last excluded), e.g.
+    ``role=master;links=2;channels=13;log=12;dio@0=0-8;urukul@1,2=8-12``.
+    The peripherals are left out if they do not fit."""
+    if hasattr(soc, "drtiosat"):
+        role = "satellite"
+    elif hasattr(soc, "drtio_cri"):
+        role = "master"
+    else:
+        role = "standalone"
+    fields = [
+        "role=" + role,
+        "links={}".format(len(getattr(soc, "drtio_cri", []))),
+        "channels={}".format(len(getattr(soc, "rtio_channels", [])))
+    ]
+    if "RTIO_LOG_CHANNEL" in soc.config:
+        fields.append("log={}".format(soc.config["RTIO_LOG_CHANNEL"]))
+    peripherals = ["{}@{}={}-{}".format(ty, ",".join(str(port) for port in ports), first, last)
+                   for ty, ports, first, last in getattr(soc, "rtio_peripherals", [])]
+    topology = ";".join(fields + peripherals)
+    if len(topology) > MAX_TOPOLOGY_LENGTH:
+        topology = ";".join(fields)
+    return topology
+
+
 class ReprogrammableIdentifier(Module, AutoCSR):
+    """ROM holding the identifier string, after its length, and the
+    compatibility descriptor, as 4 big-endian bytes after the string."""
     def __init__(self, ident):
         self.address = CSRStorage(8)
         self.data = CSRStatus(8)
 
-        contents = list(ident.encode())
-        l = len(contents)
-        if l > 255:
-            raise ValueError("Identifier string must be 255 characters or less")
-        contents.insert(0, l)
+        self.ident = ident
+        self.descriptor = 0
+        if len(ident.encode()) > 251:
+            raise ValueError("Identifier string must be 251 characters or less")
+
+    def do_finalize(self):
+        contents = list(self.ident.encode())
+        contents.insert(0, len(contents))
+        contents += list(self.descriptor.to_bytes(4, "big"))
 
         for i in range(8):
             self.specials += Instance("ROM256X1", name="identifier_str"+str(i),
@@ -52,20 +109,36 @@ def add_identifier(soc, *args, gateware_identifier_str=None, **kwargs):
     soc.config["IDENTIFIER_STR"] = identifier_str
 
 
-def build_artiq_soc(soc, argdict):
+def build_artiq_soc_args(parser):
+    parser.add_argument("--with-fault-injection", default=False,
+                        action="store_true",
+                        help="build the runtime with the management command "
+                             "injecting synthetic faults, to test monitoring "
+                             "(not for production)")
+
+
+def build_artiq_soc(soc, argdict, fault_injection=False):
+    if fault_injection:
+        soc.config["HAS_FAULT_INJECTION"] = None
+    soc.config["HAS_RTIO_TOPOLOGY"] = None
+    soc.config["RTIO_TOPOLOGY"] = get_topology(soc)
+    descriptor = get_compat_descriptor(soc)
+    soc.identifier.descriptor = descriptor
+    soc.config["COMPAT_DESCRIPTOR"] = descriptor
     firmware_dir = os.path.join(artiq_dir, "firmware")
     builder = Builder(soc, **argdict)
     builder.software_packages = []
     builder.add_software_package("bootloader", os.path.join(firmware_dir, "bootloader"))
-    if isinstance(soc, AMPSoC):
+    if hasattr(soc, "kernel_cpu"):
         builder.add_software_package("libm")
         builder.add_software_package("libprintf")
         builder.add_software_package("libunwind")
         builder.add_software_package("ksupport", os.path.join(firmware_dir, "ksupport"))
-        builder.add_software_package("runtime", os.path.join(firmware_dir, "runtime"))
-    else:
-        # Assume DRTIO satellite.
+    if hasattr(soc, "drtiosat") or not hasattr(soc, "kernel_cpu"):
+        # DRTIO satellite, possibly with a kernel CPU for the idle kernel.
         builder.add_software_package("satman", os.path.join(firmware_dir, "satman"))
+    else:
+        builder.add_software_package("runtime", os.path.join(firmware_dir, "runtime"))
     try:
         builder.build()
     except subprocess.CalledProcessError as e:
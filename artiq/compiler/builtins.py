@@ -237,6 +237,9 @@ def fn_at_mu():
 def fn_rtio_log():
     return types.TBuiltinFunction("rtio_log")
 
+def fn_watchdog():
+    return types.TBuiltinFunction("watchdog")
+
 # Accessors
 
 def is_none(typ):
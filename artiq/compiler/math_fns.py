@@ -58,22 +58,7 @@ unary_fp_runtime_calls = [
     ("arccosh", "acosh"),
     ("arctanh", "atanh"),
     ("expm1", "expm1"),
-    ("cbrt", "cbrt"),
-]
-
-#: float -> float numpy.* math functions lowered to runtime calls.
-unary_fp_runtime_calls = [
-    ("tan", "tan"),
-    ("arcsin", "asin"),
-    ("arccos", "acos"),
-    ("arctan", "atan"),
-    ("sinh", "sinh"),
-    ("cosh", "cosh"),
-    ("tanh", "tanh"),
-    ("arcsinh", "asinh"),
-    ("arccosh", "acosh"),
-    ("arctanh", "atanh"),
-    ("expm1", "expm1"),
+    ("log1p", "log1p"),
     ("cbrt", "cbrt"),
 ]
 
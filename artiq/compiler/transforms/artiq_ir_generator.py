@@ -734,8 +734,31 @@ class ARTIQIRGenerator(algorithm.Visitor):
             if handler_node.name is not None:
                 exn = self.append(ir.Builtin("exncast", [landingpad], handler_node.name_type))
                 self._set_local(handler_node.name, exn)
-            self.visit(handler_node.body)
+
+            # Tell the runtime when the handler is left other than by raising,
+            # so that it only records the exception as the context of those
+            # raised while it is being handled.
+            old_targets = self.break_target, self.continue_target, self.return_target
+            if self.break_target is not None:
+                self.break_target = self._end_catch_proxy("handler.break", self.break_target)
+            if self.continue_target is not None:
+                self.continue_target = self._end_catch_proxy("handler.continue",
+                                                             self.continue_target)
+            if self.return_target is not None:
+                self.return_target = self._end_catch_proxy("handler.return", self.return_target)
+            else:
+                return_action = self.add_block("handler.doreturn")
+                value = return_action.append(ir.GetLocal(self.current_private_env, "$return"))
+                return_action.append(ir.Builtin("end_catch", [], builtins.TNone()))
+                return_action.append(ir.Return(value))
+                self.return_target = return_action
+            try:
+                self.visit(handler_node.body)
+            finally:
+                self.break_target, self.continue_target, self.return_target = old_targets
             post_handler = self.current_block
+            if not post_handler.is_terminated():
+                post_handler.append(ir.Builtin("end_catch", [], builtins.TNone()))
 
             handlers.append((handler, post_handler))
 
@@ -788,6 +811,12 @@ class ARTIQIRGenerator(algorithm.Visitor):
                 if not post_handler.is_terminated():
                     post_handler.append(ir.Branch(tail))
 
+    def _end_catch_proxy(self, name, target):
+        proxy = self.add_block(name)
+        proxy.append(ir.Builtin("end_catch", [], builtins.TNone()))
+        proxy.append(ir.Branch(target))
+        return proxy
+
     def _try_finally(self, body_gen, finally_gen, name):
         dispatcher = self.add_block("{}.dispatch".format(name))
 
@@ -856,6 +885,27 @@ class ARTIQIRGenerator(algorithm.Visitor):
 
             self.append(ir.Builtin("at_mu", [end_mu], builtins.TNone()))
             return
+        elif isinstance(context_expr_node, asttyped.CallT) and \
+                types.is_builtin(context_expr_node.func.type, "watchdog"):
+            timeout        = self.visit(context_expr_node.args[0])
+            timeout_ms     = self.append(ir.Arith(ast.Mult(loc=None), timeout,
+                                                  ir.Constant(1000, builtins.TFloat())))
+            timeout_ms_int = self.append(ir.Builtin("round", [timeout_ms], builtins.TInt64()))
+
+            loc   = context_expr_node.loc
+            scope = ir.Constant("{}:{}".format(loc.source_buffer.name, loc.line()),
+                                builtins.TStr())
+
+            watchdog_id = self.append(ir.Builtin("watchdog_set", [timeout_ms_int, scope],
+                                                 builtins.TInt32()))
+            dispose     = lambda: \
+                self.append(ir.Builtin("watchdog_clear", [watchdog_id], builtins.TNone()))
+
+            self._try_finally(
+                body_gen=lambda: self.visit(node.body),
+                finally_gen=dispose,
+                name="watchdog")
+            return
 
         cleanup = []
         for item_node in node.items:
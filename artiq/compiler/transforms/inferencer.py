@@ -1248,6 +1248,9 @@ class Inferencer(algorithm.Visitor):
         elif types.is_builtin(typ, "delay"):
             simple_form("delay(time:float) -> None",
                         [builtins.TFloat()])
+        elif types.is_builtin(typ, "watchdog"):
+            simple_form("watchdog(time:float) -> [builtin context manager]",
+                        [builtins.TFloat()], builtins.TNone())
         elif types.is_builtin(typ, "at"):
             simple_form("at(time:float) -> None",
                         [builtins.TFloat()])
@@ -1504,7 +1507,9 @@ class Inferencer(algorithm.Visitor):
 
         typ = node.context_expr.type
         if (types.is_builtin(typ, "interleave") or types.is_builtin(typ, "sequential") or
-            types.is_builtin(typ, "parallel")):
+            types.is_builtin(typ, "parallel") or
+            (isinstance(node.context_expr, asttyped.CallT) and
+             types.is_builtin(node.context_expr.func.type, "watchdog"))):
             # builtin context managers
             if node.optional_vars is not None:
                 self._unify(node.optional_vars.type, builtins.TNone(),
@@ -1608,6 +1613,14 @@ class Inferencer(algorithm.Visitor):
                     {"kind": typ.name},
                     node.keyword_loc.join(node.colon_loc))
                 self.engine.process(diag)
+            elif (isinstance(item_node.context_expr, asttyped.CallT) and
+                  types.is_builtin(item_node.context_expr.func.type, "watchdog") and
+                  len(node.items) != 1):
+                diag = diagnostic.Diagnostic("error",
+                    "the 'watchdog' context manager must be the only one in a 'with' statement",
+                    {},
+                    node.keyword_loc.join(node.colon_loc))
+                self.engine.process(diag)
 
     def visit_ExceptHandlerT(self, node):
         self.generic_visit(node)
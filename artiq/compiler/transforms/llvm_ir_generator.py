@@ -374,6 +374,8 @@ class LLVMIRGenerator:
             llty = ll.FunctionType(llvoid, [self.llty_of_type(builtins.TException())])
         elif name == "__artiq_reraise":
             llty = ll.FunctionType(llvoid, [])
+        elif name == "__artiq_end_catch":
+            llty = ll.FunctionType(llvoid, [])
         elif name == "memcmp":
             llty = ll.FunctionType(lli32, [llptr, llptr, lli32])
         elif name == "rpc_send":
@@ -382,6 +384,10 @@ class LLVMIRGenerator:
             llty = ll.FunctionType(llvoid, [lli32, llsliceptr, llptrptr])
         elif name == "rpc_recv":
             llty = ll.FunctionType(lli32, [llptr])
+        elif name == "watchdog_set":
+            llty = ll.FunctionType(lli32, [lli64, llsliceptr])
+        elif name == "watchdog_clear":
+            llty = ll.FunctionType(llvoid, [lli32])
 
         # with now-pinning
         elif name == "now":
@@ -402,9 +408,11 @@ class LLVMIRGenerator:
             llglobal = ll.Function(self.llmodule, llty, name)
             if name in ("__artiq_raise", "__artiq_reraise", "llvm.trap"):
                 llglobal.attributes.add("noreturn")
-            if name in ("rtio_log", "rpc_send", "rpc_send_async",
+            if name in ("rtio_log", "rpc_send", "rpc_send_async", "__artiq_end_catch",
                         self.target.print_function):
                 llglobal.attributes.add("nounwind")
+            if name == "watchdog_set":
+                llglobal.args[1].add_attribute('byval')
             if name.find("__py_") == 0:
                 llglobal.linkage = 'linkonce_odr'
                 self.emit_intrinsic(name, llglobal)
@@ -1193,9 +1201,21 @@ class LLVMIRGenerator:
             func_name = self.target.print_function if insn.op == "printf" else insn.op
             return self.llbuilder.call(self.llbuiltin(func_name), lloperands,
                                        name=insn.name)
+        elif insn.op == "watchdog_set":
+            interval, scope = insn.operands
+            llscope = self.llbuilder.alloca(self.llty_of_type(scope.type))
+            self.llbuilder.store(self.map(scope), llscope)
+            return self.llbuilder.call(self.llbuiltin("watchdog_set"),
+                                       [self.map(interval), llscope],
+                                       name=insn.name)
+        elif insn.op == "watchdog_clear":
+            id, = insn.operands
+            return self.llbuilder.call(self.llbuiltin("watchdog_clear"), [self.map(id)])
         elif insn.op == "exncast":
             # This is an identity cast at LLVM IR level.
             return self.map(insn.operands[0])
+        elif insn.op == "end_catch":
+            return self.llbuilder.call(self.llbuiltin("__artiq_end_catch"), [])
         elif insn.op == "now_mu":
             if self.target.now_pinning:
                 return self.llbuilder.load(self.llbuiltin("now"), name=insn.name)
@@ -1,9 +1,10 @@
 from numpy import int32, int64
 
 from artiq.language.core import (
-    kernel, delay, portable, delay_mu, now_mu, at_mu)
+    kernel, delay, portable, delay_mu, now_mu, at_mu, syscall)
 from artiq.language.units import us, ms
-from artiq.language.types import TBool, TInt32, TInt64, TFloat, TList, TTuple
+from artiq.language.types import (
+    TBool, TInt32, TInt64, TFloat, TList, TTuple, TNone)
 
 from artiq.coredevice import spi2 as spi
 from artiq.coredevice import urukul
@@ -61,6 +62,12 @@ RAM_MODE_CONT_BIDIR_RAMP = 3
 RAM_MODE_CONT_RAMPUP = 4
 
 
+@syscall(flags={"nounwind", "nowrite"})
+def dds_sync_report(channel: TInt32, chip_select: TInt32, in_delay: TInt32,
+                    window: TInt32, io_update_delay: TInt32) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+
 class SyncDataUser:
     def __init__(self, core, sync_delay_seed, io_update_delay):
         self.core = core
@@ -1047,3 +1054,32 @@ class AD9910:
                 # the good delay is period//2 after the edge
                 return (i + 1 + period // 2) & (period - 1)
         raise ValueError("no IO_UPDATE-SYNC_CLK alignment edge found")
+
+    @kernel
+    def resync(self) -> TTuple([TInt32, TInt32, TInt32]):
+        """Re-run the SYNC_IN and IO_UPDATE alignment of an initialized
+        channel, e.g. to re-validate a phase-coherent setup after a
+        temperature drift without rebooting the core device.
+
+        The SYNC_IN delay is tuned with :meth:`tune_sync_delay`, starting
+        from the seed of the device database, and the IO_UPDATE delay found
+        by :meth:`tune_io_update_delay` is used by this device from then on.
+        The result is also recorded by the core device, where
+        ``artiq_coremgmt ddssync`` shows it.
+
+        This uses the digital ramp generator, which changes the frequency
+        of the channel: the profiles must be set again afterwards.
+
+        :return: Tuple of the SYNC_IN delay, the validation window size and
+            the IO_UPDATE delay.
+        """
+        seed = self.sync_data.sync_delay_seed
+        if seed < 0:
+            seed = 15
+        in_delay, window = self.tune_sync_delay(seed)
+        io_update_delay = self.tune_io_update_delay()
+        self.sync_data.io_update_delay = io_update_delay
+        dds_sync_report(self.bus.channel, self.chip_select,
+                        in_delay, window, io_update_delay)
+        delay(1 * ms)  # slack
+        return in_delay, window, io_update_delay
@@ -39,6 +39,13 @@ class CoreCache:
 
         To remove a value from the cache, call :meth:`put` with an empty list.
 
+        If quotas are configured on the core device (config keys
+        ``cache_max_entry_size`` and ``cache_max_size``, in bytes), a value
+        that would exceed them raises
+        :class:`artiq.coredevice.exceptions.CacheError`, unless the
+        ``cache_eviction`` key is set to ``lru``, in which case the least
+        recently used values are removed to make room for it.
+
         :param str key: cache key
         :param list value: a list of 32-bit integers
         """
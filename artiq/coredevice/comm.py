@@ -1,6 +1,9 @@
 import sys
 import socket
 import logging
+import hmac
+import hashlib
+from enum import IntFlag
 
 logger = logging.getLogger(__name__)
 
@@ -26,3 +29,61 @@ def initialize_connection(host, port):
     set_keepalive(sock, 10, 10, 3)
     logger.debug("connected to %s:%d", host, port)
     return sock
+
+
+class AuthenticationError(IOError):
+    """Raised when the core device refuses the connection because the
+    host did not present the key set in its ``auth_key`` config entry."""
+    pass
+
+
+def auth_response(key, challenge):
+    """Computes the response to an authentication challenge sent by the
+    core device."""
+    if isinstance(key, str):
+        key = key.encode("utf-8")
+    return hmac.new(key, bytes(challenge), hashlib.sha256).digest()
+
+
+def authenticate(read, write, key):
+    """Performs the authentication handshake that follows the endian byte
+    on session and management connections.
+
+    :param read: function reading exactly the given number of bytes.
+    :param write: function sending bytes to the core device.
+    :param key: the pre-shared key (``str`` or ``bytes``), or ``None``.
+    """
+    if not read(1)[0]:
+        return
+    challenge = read(32)
+    if key is None:
+        raise AuthenticationError("Core device requires authentication, "
+                                  "but no key is set")
+    write(auth_response(key, challenge))
+    if not read(1)[0]:
+        raise AuthenticationError("Core device rejected the authentication key")
+
+
+class Feature(IntFlag):
+    """Features of the core device, see ``mgmt_proto.rs``."""
+    DRTIO_MASTER = 1
+    DRTIO_ROUTING = 2
+    WRPLL = 4
+    SUBKERNELS = 8
+    ANALYZER = 16
+    ALERTS = 32
+    COMPRESSION = 64
+    FIRMWARE_UPDATE = 128
+    GATEWARE_UPDATE = 256
+    HEALTH_RECORDS = 512
+
+
+def decode_features(word):
+    """Decodes the feature word reported by the core device into a
+    dictionary with the ``flags`` (a :class:`Feature`), the number of
+    ``sfp_ports`` and the ``analyzer_size`` of the trace buffer in bytes."""
+    return {
+        "flags": Feature(word & 0xfff),
+        "sfp_ports": (word >> 12) & 0xf,
+        "analyzer_size": ((word >> 16) & 0xffff)*1024,
+    }
@@ -34,10 +34,23 @@ class ExceptionType(Enum):
     i_overflow = 0b100001
 
 
-def get_analyzer_dump(host, port=1382):
+class TriggerMode(Enum):
+    none = 0
+    channel = 1
+    exception = 2
+
+
+def get_analyzer_dump(host, port=1382, compression=True):
+    """Reads the analyzer buffer of the core device and returns it as a raw
+    dump. With ``compression``, the core device compresses the messages
+    before sending them, which is faster on slow or loaded networks."""
+    request = ANALYZER_REQUEST_DUMP
+    if compression:
+        request |= ANALYZER_REQUEST_COMPRESSED
     sock = socket.create_connection((host, port))
     try:
-        r = bytes()
+        sock.sendall(bytes([request]))
+        r = bytearray()
         while True:
             buf = sock.recv(8192)
             if not buf:
@@ -45,7 +58,134 @@ def get_analyzer_dump(host, port=1382):
             r += buf
     finally:
         sock.close()
-    return r
+    if r[:1] == b"C":
+        r = b"E" + r[1:16] + decompress_messages(
+            r[16:], struct.unpack(">I", r[1:5])[0])
+    return bytes(r)
+
+
+ANALYZER_REQUEST_DUMP = 0
+ANALYZER_REQUEST_STREAM = 1
+# Set in the request by hosts that can decompress dumps (see
+# decompress_messages); the core device then sends "C" instead of "E".
+ANALYZER_REQUEST_COMPRESSED = 0x80
+
+_DELTA_FIELDS = (12, 20)  # RTIO counter and timestamp
+
+
+def decompress_messages(data, sent_bytes):
+    """Decodes ``sent_bytes`` of messages from a compressed dump.
+
+    Each message is encoded against the previous one: the RTIO counter and
+    timestamp are replaced by their differences with those of the previous
+    message, and only the bytes that differ from the previous encoded message
+    are given, after a 32-bit mask of their positions."""
+    messages = bytearray()
+    last_message = bytes(32)
+    last_encoded = bytes(32)
+    position = 0
+    try:
+        while len(messages) < sent_bytes:
+            mask = struct.unpack(">I", data[position:position + 4])[0]
+            position += 4
+            encoded = bytearray(last_encoded)
+            for i in range(32):
+                if mask & (1 << i):
+                    encoded[i] = data[position]
+                    position += 1
+            message = bytearray(encoded)
+            for offset in _DELTA_FIELDS:
+                delta, = struct.unpack(">Q", encoded[offset:offset + 8])
+                last, = struct.unpack(">Q", last_message[offset:offset + 8])
+                message[offset:offset + 8] = struct.pack(
+                    ">Q", (last + delta) & 0xffffffffffffffff)
+            messages += message
+            last_message = message
+            last_encoded = encoded
+    except (IndexError, struct.error):
+        raise ValueError("compressed analyzer dump is truncated")
+    return bytes(messages)
+
+
+class AnalyzerStream:
+    """Streams the messages recorded by the analyzer of the core device,
+    for captures longer than its buffer.
+
+    Messages that the core device had to drop because the host or the
+    network could not keep up are counted in ``lost_bytes``.
+    """
+    def __init__(self, host, port=1382):
+        self.sock = socket.create_connection((host, port))
+        try:
+            self.sock.sendall(bytes([ANALYZER_REQUEST_STREAM]))
+            endian = self._read(1)
+            if endian == b"e":
+                self.endian = "<"
+            elif endian == b"E":
+                self.endian = ">"
+            else:
+                raise IOError("Incorrect reply from device: expected e/E.")
+            self.log_channel, dds_onehot_sel = struct.unpack("bb", self._read(2))
+            self.dds_onehot_sel = bool(dds_onehot_sel)
+        except:
+            self.sock.close()
+            raise
+        self.lost_bytes = 0
+        self.overflow_occurred = False
+
+    def _read(self, length):
+        r = bytes()
+        while len(r) < length:
+            buf = self.sock.recv(length - len(r))
+            if not buf:
+                raise ConnectionError("core device closed the analyzer stream")
+            r += buf
+        return r
+
+    def read(self):
+        """Waits for and returns the next messages, as raw bytes."""
+        sent_bytes, lost_bytes, overflow_occurred = struct.unpack(
+            self.endian + "IQb", self._read(13))
+        data = self._read(sent_bytes)
+        valid = self._read(1)[0]
+        self.lost_bytes = lost_bytes
+        if overflow_occurred:
+            self.overflow_occurred = True
+        if not valid:
+            # Overwritten on the core device while being sent.
+            self.lost_bytes += sent_bytes
+            return bytes()
+        return data
+
+    def close(self):
+        self.sock.close()
+
+
+def write_stream_dump(stream, f, max_bytes=2**32 - 32):
+    """Writes the messages of an :class:`AnalyzerStream` to the file ``f``
+    in the format of analyzer dumps, until ``KeyboardInterrupt`` or until
+    ``max_bytes`` are written. The dump header is written last, so ``f``
+    must be seekable."""
+    header = struct.Struct(">IQbbb")
+    f.write(b"E" + bytes(header.size))
+    sent_bytes = 0
+    try:
+        while sent_bytes < max_bytes:
+            data = stream.read()
+            data = data[:max_bytes - sent_bytes]
+            f.write(data)
+            sent_bytes += len(data)
+        logger.warning("maximum dump size reached, stopping")
+    except KeyboardInterrupt:
+        pass
+    if stream.lost_bytes:
+        logger.warning("%d bytes of messages were lost while streaming",
+                       stream.lost_bytes)
+    f.seek(1)
+    f.write(header.pack(sent_bytes, sent_bytes + stream.lost_bytes,
+                        stream.overflow_occurred or stream.lost_bytes != 0,
+                        stream.log_channel, stream.dds_onehot_sel))
+    return sent_bytes
 
 
 OutputMessage = namedtuple(
@@ -1,49 +1,178 @@
 import struct
 import logging
+import time
 import traceback
 import numpy
 import socket
-from enum import Enum
+from enum import Enum, IntFlag
 from fractions import Fraction
 from collections import namedtuple
 
 from artiq.coredevice import exceptions
-from artiq.coredevice.comm import initialize_connection
+from artiq.coredevice.comm import (initialize_connection, authenticate,
+                                   decode_features)
 from artiq import __version__ as software_version
 
 
 logger = logging.getLogger(__name__)
 
 
+# See session_proto.rs.
+PROTOCOL_VERSION = 1
+SESSION_TOKEN_SIZE = 16
+
+
+class Capability(IntFlag):
+    SYSTEM_INFO_EXTENDED = 1
+    EXCEPTION_DETAILS = 2
+    NOTIFICATIONS = 4
+    COMPRESSION = 8
+    DELTA_LOAD = 16
+    DATASET_APPEND = 32
+    RUN_STATS = 64
+    FEATURES = 128
+    RESUME_TOKEN = 256
+
+    ALL = 511
+
+
 class Request(Enum):
     SystemInfo = 3
+    Hello = 12
 
     LoadKernel = 5
+    LoadKernelDelta = 13
     RunKernel = 6
 
     RPCReply = 7
     RPCException = 8
 
+    GetLog = 9
+    GetHealth = 10
+    GetAnalyzerDump = 11
+
 
 class Reply(Enum):
     SystemInfo = 2
 
     LoadCompleted = 5
     LoadFailed = 6
+    LoadBaseMissing = 24
 
     KernelFinished = 7
     KernelStartupFailed = 8
     KernelException = 9
+    KernelRunStats = 27
 
     RPCRequest = 10
 
+    WatchdogExpired = 14
+    KernelTerminated = 25
     ClockFailure = 15
 
+    Log = 16
+    Health = 17
+    AnalyzerDump = 18
+    Unavailable = 19
+    SessionResumed = 20
+    Notification = 21
+    DatasetAppend = 26
+    Hello = 22
+    Compressed = 23
+
+
+def _lz4_decompress(data, length):
+    """Decompresses an LZ4 block that is ``length`` bytes long once
+    decompressed."""
+    def read_length(i, n):
+        if n == 15:
+            while True:
+                byte = data[i]
+                i += 1
+                n += byte
+                if byte != 255:
+                    break
+        return i, n
+
+    output = bytearray()
+    i = 0
+    while i < len(data):
+        token = data[i]
+        i, n = read_length(i + 1, token >> 4)
+        output += data[i:i + n]
+        i += n
+        if i >= len(data):
+            break
+        offset = data[i] | data[i + 1] << 8
+        i, n = read_length(i + 2, token & 15)
+        n += 4
+        start = len(output) - offset
+        if offset == 0 or start < 0:
+            raise IOError("Corrupted compressed message from device")
+        if offset >= n:
+            output += output[start:start + n]
+        else:
+            output += (output[start:] * (n // offset + 1))[:n]
+    if len(output) != length:
+        raise IOError("Corrupted compressed message from device")
+    return output
+
+
+def _fnv1a(data):
+    """Hash identifying kernels in delta uploads, see image_cache.rs."""
+    h = 0x811c9dc5
+    for byte in data:
+        h = ((h ^ byte) * 0x01000193) & 0xffffffff
+    return h
+
+
+# Length of the blocks of the base kernel looked up in the new one.
+_DELTA_BLOCK = 32
+
+
+def _kernel_delta(base, kernel):
+    """Returns the operations rebuilding ``kernel`` from ``base``, as
+    ``("copy", offset, length)`` and ``("insert", data)`` tuples."""
+    index = dict()
+    for offset in range(0, len(base) - _DELTA_BLOCK + 1, _DELTA_BLOCK):
+        index.setdefault(bytes(base[offset:offset + _DELTA_BLOCK]), offset)
+
+    ops = []
+    i = literal = 0
+    while i + _DELTA_BLOCK <= len(kernel):
+        offset = index.get(bytes(kernel[i:i + _DELTA_BLOCK]))
+        if offset is None:
+            i += 1
+            continue
+        while i > literal and offset > 0 and kernel[i - 1] == base[offset - 1]:
+            i -= 1
+            offset -= 1
+        length = _DELTA_BLOCK
+        while (i + length < len(kernel) and offset + length < len(base)
+               and kernel[i + length] == base[offset + length]):
+            length += 1
+        if i > literal:
+            ops.append(("insert", kernel[literal:i]))
+        if ops and ops[-1][0] == "copy" and sum(ops[-1][1:]) == offset:
+            ops[-1] = ("copy", ops[-1][1], ops[-1][2] + length)
+        else:
+            ops.append(("copy", offset, length))
+        i = literal = i + length
+    if literal < len(kernel):
+        ops.append(("insert", kernel[literal:]))
+    return ops
+
 
 class UnsupportedDevice(Exception):
     pass
 
 
+class UnexpectedDevice(Exception):
+    """Raised when the serial number of the core device is not the expected
+    one."""
+    pass
+
+
 class LoadError(Exception):
     pass
 
@@ -76,6 +205,11 @@ def _receive_list(kernel, embedding_map):
     elif tag == "f":
         buffer = kernel._read(8 * length)
         return list(struct.unpack(kernel.endian + "%sd" % length, buffer))
+    elif tag in _numpy_dtypes:
+        dtype = numpy.dtype(kernel.endian + _numpy_dtypes[tag])
+        buffer = kernel._read(dtype.itemsize * length)
+        elems = numpy.ndarray((length, ), dtype, buffer)
+        return list(elems.astype(_numpy_dtypes[tag]))
     else:
         fn = receivers[tag]
         elems = []
@@ -88,13 +222,31 @@ def _receive_list(kernel, embedding_map):
         return elems
 
 
+# Types without a native Python equivalent, received as NumPy scalars
+_numpy_dtypes = {"h": "f2", "c": "c8", "C": "c16"}
+_record_field_dtypes = {"b": "?", "i": "i4", "I": "i8", "f": "f8", **_numpy_dtypes}
+
+
+def _record_dtype(field_tags, endian=""):
+    return numpy.dtype([("f{}".format(i), endian + _record_field_dtypes[tag])
+                        for i, tag in enumerate(field_tags)])
+
+
 def _receive_array(kernel, embedding_map):
     num_dims = kernel._read_int8()
     shape = tuple(kernel._read_int32() for _ in range(num_dims))
     tag = chr(kernel._read_int8())
     fn = receivers[tag]
     length = numpy.prod(shape)
-    if tag == "b":
+    if tag == "t":
+        # array of records, see send_records in rpc_proto.rs
+        arity = kernel._read_int8()
+        field_tags = [chr(kernel._read_int8()) for _ in range(arity)]
+        dtype = _record_dtype(field_tags, kernel.endian)
+        buffer = kernel._read(dtype.itemsize * length)
+        elems = numpy.ndarray((length, ), dtype, buffer)
+        elems = elems.astype(_record_dtype(field_tags))
+    elif tag == "b":
         buffer = kernel._read(length)
         elems = numpy.ndarray((length, ), '?', buffer)
     elif tag == "i":
@@ -106,6 +258,11 @@ def _receive_array(kernel, embedding_map):
     elif tag == "f":
         buffer = kernel._read(8 * length)
         elems = numpy.ndarray((length, ), kernel.endian + 'd', buffer)
+    elif tag in _numpy_dtypes:
+        dtype = numpy.dtype(kernel.endian + _numpy_dtypes[tag])
+        buffer = kernel._read(dtype.itemsize * length)
+        elems = numpy.ndarray((length, ), dtype, buffer)
+        elems = elems.astype(_numpy_dtypes[tag])
     else:
         fn = receivers[tag]
         elems = []
@@ -118,6 +275,13 @@ def _receive_array(kernel, embedding_map):
     return elems.reshape(shape)
 
 
+def _receive_numpy_scalar(tag):
+    def receive(kernel, embedding_map):
+        dtype = numpy.dtype(kernel.endian + _numpy_dtypes[tag])
+        return numpy.ndarray((), dtype, kernel._read(dtype.itemsize))[()]
+    return receive
+
+
 def _receive_range(kernel, embedding_map):
     start = kernel._receive_rpc_value(embedding_map)
     stop = kernel._receive_rpc_value(embedding_map)
@@ -141,6 +305,9 @@ receivers = {
     "i": lambda kernel, embedding_map: numpy.int32(kernel._read_int32()),
     "I": lambda kernel, embedding_map: numpy.int64(kernel._read_int64()),
     "f": lambda kernel, embedding_map: kernel._read_float64(),
+    "h": _receive_numpy_scalar("h"),
+    "c": _receive_numpy_scalar("c"),
+    "C": _receive_numpy_scalar("C"),
     "s": lambda kernel, embedding_map: kernel._read_string(),
     "B": lambda kernel, embedding_map: kernel._read_bytes(),
     "A": lambda kernel, embedding_map: kernel._read_bytes(),
@@ -156,7 +323,7 @@ receivers = {
 
 class CommKernelDummy:
     def __init__(self):
-        pass
+        self.last_run_stats = None
 
     def load(self, kernel_library):
         pass
@@ -170,14 +337,43 @@ class CommKernelDummy:
     def check_system_info(self):
         pass
 
+    def set_notification_handler(self, tag, handler):
+        pass
+
+    def set_dataset_append_handler(self, handler):
+        pass
+
 
 class CommKernel:
     warned_of_mismatch = False
-
-    def __init__(self, host, port=1381):
+    # Boot count of each core device at the last connection.
+    boot_counts = dict()
+    # Core devices whose firmware predates the Hello request.
+    legacy_hosts = set()
+    magic = b"ARTIQ coredev\n"
+    resume_magic = b"ARTIQ coreres\n"
+
+    def __init__(self, host, port=1381, resume_timeout=10.0, auth_key=None,
+                 board_serial=None):
         self._read_type = None
         self.host = host
         self.port = port
+        self.auth_key = auth_key
+        self.board_serial = board_serial
+        self.board_identity = None
+        self.capabilities = Capability.ALL
+        # Features of the core device, see decode_features, or None if its
+        # firmware does not report them.
+        self.features = None
+        # Last kernel loaded, the base of delta uploads.
+        self.last_kernel = None
+        self.resume_timeout = resume_timeout
+        # Token of the kernel session, sent back to resume it, or None if
+        # the firmware does not issue one.
+        self.session_token = None
+        self.notification_handlers = dict()
+        self.dataset_append_handler = None
+        self.last_run_stats = None
         self.read_buffer = bytearray()
         self.write_buffer = bytearray()
 
@@ -185,8 +381,45 @@ class CommKernel:
     def open(self):
         if hasattr(self, "socket"):
             return
+        self.session_token = None
+        self._connect(self.magic)
+        if self.host not in CommKernel.legacy_hosts and not self._negotiate():
+            # Older firmware closes the connection on the unknown request.
+            logger.debug("core device does not negotiate the protocol version")
+            CommKernel.legacy_hosts.add(self.host)
+            self.close()
+            self.read_buffer = bytearray()
+            self.write_buffer = bytearray()
+            self._connect(self.magic)
+
+    def _negotiate(self):
+        """Tell the core device which optional parts of the protocol we
+        support, and keep those it supports too in ``capabilities``.
+        Returns ``False`` if the firmware is too old to negotiate."""
+        self._write_header(Request.Hello)
+        self._write_int32(PROTOCOL_VERSION)
+        self._write_int32(Capability.ALL)
+        try:
+            self._flush()
+            self._read_header()
+        except ConnectionError:
+            return False
+        self._read_expect(Reply.Hello)
+        version = self._read_int32()
+        self.capabilities = Capability(self._read_int32() & Capability.ALL)
+        if Capability.FEATURES in self.capabilities:
+            self.features = decode_features(self._read_int32() & 0xffffffff)
+        if Capability.RESUME_TOKEN in self.capabilities:
+            self.session_token = bytes(self._read(SESSION_TOKEN_SIZE))
+        if version != PROTOCOL_VERSION:
+            logger.info("core device uses session protocol version %d "
+                        "(ours is %d), capabilities: %s",
+                        version, PROTOCOL_VERSION, self.capabilities)
+        return True
+
+    def _connect(self, magic):
         self.socket = initialize_connection(self.host, self.port)
-        self.socket.sendall(b"ARTIQ coredev\n")
+        self.socket.sendall(magic)
         endian = self._read(1)
         if endian == b"e":
             self.endian = "<"
@@ -194,6 +427,7 @@ class CommKernel:
             self.endian = ">"
         else:
             raise IOError("Incorrect reply from device: expected e/E.")
+        authenticate(self._read, self.socket.sendall, self.auth_key)
         self.unpack_int32 = struct.Struct(self.endian + "l").unpack
         self.unpack_int64 = struct.Struct(self.endian + "q").unpack
         self.unpack_float64 = struct.Struct(self.endian + "d").unpack
@@ -234,18 +468,29 @@ class CommKernel:
     def _read_header(self):
         self.open()
 
-        # Wait for a synchronization sequence, 5a 5a 5a 5a.
-        sync_count = 0
-        while sync_count < 4:
-            sync_byte = self._read(1)[0]
-            if sync_byte == 0x5a:
-                sync_count += 1
-            else:
-                sync_count = 0
-
-        # Read message header.
-        raw_type = self._read(1)[0]
-        self._read_type = Reply(raw_type)
+        while True:
+            # Wait for a synchronization sequence, 5a 5a 5a 5a.
+            sync_count = 0
+            while sync_count < 4:
+                sync_byte = self._read(1)[0]
+                if sync_byte == 0x5a:
+                    sync_count += 1
+                else:
+                    sync_count = 0
+
+            # Read message header.
+            raw_type = self._read(1)[0]
+            self._read_type = Reply(raw_type)
+            if self._read_type != Reply.Compressed:
+                break
+
+            # The compressed messages are read as if they had been sent
+            # uncompressed.
+            length = self._read_int32()
+            data = self._read_bytes()
+            logger.debug("decompressing %d bytes into %d", len(data), length)
+            messages = _lz4_decompress(data, length)
+            self.read_buffer = messages + self.read_buffer
 
         logger.debug("receiving message: type=%r",
                      self._read_type)
@@ -283,6 +528,41 @@ class CommKernel:
     def _read_string(self):
         return self._read_bytes().decode("utf-8")
 
+    def _read_board_identity(self):
+        identity = {
+            "serial": self._read(6).hex(),
+            "name": None,
+            "board_id": None,
+            "hw_rev": None,
+            "variant": None,
+            "vendor": None,
+        }
+        if self._read_bool():
+            identity["name"] = self._read_string()
+            identity["board_id"] = struct.unpack(self.endian + "H", self._read(2))[0]
+            identity["hw_rev"] = "v{}.{}".format(self._read_int8(), self._read_int8())
+            identity["variant"] = self._read_int8()
+            identity["vendor"] = self._read_int8()
+        return identity
+
+    def _read_run_stats(self):
+        stats = {
+            "sequence": self._read_int32() & 0xffffffff,
+            "started_ms": self._read_int64(),
+            "unix_time": None,
+        }
+        if self._read_bool():
+            stats["unix_time"] = self._read_int64()/1e6
+        stats["source"] = self._read_string()
+        stats["duration"] = self._read_int64()/1e6
+        stats["outcome"] = ("finished", "exception", "stopped")[self._read_int8()]
+        stats["output_events"] = self._read_int32() & 0xffffffff
+        stats["min_slack_mu"] = None
+        if self._read_bool():
+            stats["min_slack_mu"] = self._read_int64()
+        stats["rtio_errors"] = self._read_int32() & 0xffffffff
+        return stats
+
     #
     # Writer interface
     #
@@ -356,6 +636,30 @@ class CommKernel:
             finished_cleanly = self._read_bool()
             if not finished_cleanly:
                 logger.warning("Previous kernel did not cleanly finish")
+
+            extended = Capability.SYSTEM_INFO_EXTENDED in self.capabilities
+            if extended and self._read_bool():
+                self.board_identity = self._read_board_identity()
+                logger.debug("board identity: %s", self.board_identity)
+            if (self.board_serial is not None and
+                    (self.board_identity is None or
+                     self.board_identity["serial"] != self.board_serial.lower())):
+                raise UnexpectedDevice(
+                    "Expected board with serial {}, connected to {}".format(
+                        self.board_serial,
+                        self.board_identity["serial"]
+                        if self.board_identity else "board without serial"))
+
+            if extended and self._read_bool():
+                boot_count = self._read_int32() & 0xffffffff
+                total_uptime = self._read_int32() & 0xffffffff
+                last_boot_count = CommKernel.boot_counts.get(self.host)
+                if last_boot_count is not None and boot_count != last_boot_count:
+                    logger.warning("Core device rebooted %d time(s) since the last "
+                                   "connection (boot #%d, %.1f h of uptime over all boots)",
+                                   boot_count - last_boot_count, boot_count,
+                                   total_uptime/3600)
+                CommKernel.boot_counts[self.host] = boot_count
         elif runtime_id == b"ARZQ":
             pass
         else:
@@ -363,16 +667,56 @@ class CommKernel:
                                     .format(runtime_id))
 
     def load(self, kernel_library):
-        self._write_header(Request.LoadKernel)
-        self._write_bytes(kernel_library)
-        self._flush()
+        if not self._load_delta(kernel_library):
+            self._write_header(Request.LoadKernel)
+            self._write_bytes(kernel_library)
+            self._flush()
 
-        self._read_header()
+            self._read_header()
+            self._read_load_reply()
+        self.last_kernel = kernel_library
+
+    def _read_load_reply(self):
         if self._read_type == Reply.LoadFailed:
             raise LoadError(self._read_string())
         else:
             self._read_expect(Reply.LoadCompleted)
 
+    def _load_delta(self, kernel_library):
+        """Uploads ``kernel_library`` as a patch against the last kernel
+        loaded, which the core device keeps in its kernel image cache, e.g.
+        between the points of a scan. Returns ``False`` if the whole kernel
+        has to be uploaded."""
+        if (Capability.DELTA_LOAD not in self.capabilities
+                or self.last_kernel is None):
+            return False
+        patch = bytearray()
+        for op in _kernel_delta(self.last_kernel, kernel_library):
+            if op[0] == "copy":
+                patch += b"\x00" + self.pack_int32(op[1]) + self.pack_int32(op[2])
+            else:
+                patch += b"\x01" + self.pack_int32(len(op[1])) + op[1]
+        if len(patch) > len(kernel_library) // 2:
+            return False
+        pack_hash = struct.Struct(self.endian + "L").pack
+
+        self._write_header(Request.LoadKernelDelta)
+        self._write(pack_hash(_fnv1a(self.last_kernel)))
+        self._write(pack_hash(_fnv1a(kernel_library)))
+        self._write_int32(len(kernel_library))
+        self._write_bytes(patch)
+        self._flush()
+
+        self._read_header()
+        if self._read_type == Reply.LoadBaseMissing:
+            logger.debug("core device cannot apply the kernel patch, "
+                         "uploading the whole kernel")
+            return False
+        self._read_load_reply()
+        logger.debug("kernel uploaded as a patch of %d bytes (%d bytes in full)",
+                     len(patch), len(kernel_library))
+        return True
+
     def run(self):
         self._write_empty(Request.RunKernel)
         self._flush()
@@ -449,6 +793,15 @@ class CommKernel:
             check(isinstance(value, float),
                   lambda: "float")
             self._write_float64(value)
+        elif tag == "h":
+            check(isinstance(value, (float, numpy.float16)),
+                  lambda: "float16")
+            self._write(numpy.array(value, self.endian + "f2").tobytes())
+        elif tag in ("c", "C"):
+            check(isinstance(value, (complex, float, numpy.complexfloating)),
+                  lambda: "complex")
+            dtype = self.endian + _numpy_dtypes[tag]
+            self._write(numpy.array(value, dtype).tobytes())
         elif tag == "F":
             check(isinstance(value, Fraction) and
                   (-2**63 < value.numerator < 2**63-1) and
@@ -484,6 +837,9 @@ class CommKernel:
             elif tag_element == "f":
                 self._write(struct.pack(self.endian + "%sd" %
                                         len(value), *value))
+            elif tag_element in _numpy_dtypes:
+                dtype = self.endian + _numpy_dtypes[tag_element]
+                self._write(numpy.array(value, dtype).tobytes())
             else:
                 for elt in value:
                     tags_copy = bytearray(tags)
@@ -498,7 +854,16 @@ class CommKernel:
             for s in value.shape:
                 self._write_int32(s)
             tag_element = chr(tags[0])
-            if tag_element == "b":
+            if tag_element == "t" and value.dtype.names is not None:
+                arity = tags[1]
+                field_tags = [chr(tag) for tag in tags[2:2 + arity]]
+                check(len(value.dtype.names) == arity and
+                      all(tag in _record_field_dtypes for tag in field_tags),
+                      lambda: "structured numpy.ndarray with {} fields".format(arity))
+                array = value.reshape((-1,), order="C").astype(
+                    _record_dtype(field_tags, self.endian))
+                self._write(array.tobytes())
+            elif tag_element == "b":
                 self._write(value.reshape((-1,), order="C").tobytes())
             elif tag_element == "i":
                 array = value.reshape(
@@ -512,6 +877,10 @@ class CommKernel:
                 array = value.reshape(
                     (-1,), order="C").astype(self.endian + 'd')
                 self._write(array.tobytes())
+            elif tag_element in _numpy_dtypes:
+                array = value.reshape((-1,), order="C").astype(
+                    self.endian + _numpy_dtypes[tag_element])
+                self._write(array.tobytes())
             else:
                 for elt in value.reshape((-1,), order="C"):
                     tags_copy = bytearray(tags)
@@ -610,6 +979,68 @@ class CommKernel:
                 self._write_string(function)
             self._flush()
 
+    def set_notification_handler(self, tag, handler):
+        """Calls ``handler(data)`` with the payload of every notification the
+        kernel posts under ``tag``. Pass ``None`` to remove the handler."""
+        if handler is None:
+            self.notification_handlers.pop(tag, None)
+        else:
+            self.notification_handlers[tag] = handler
+
+    def _serve_notification(self):
+        dropped = self._read_int32()
+        tag = self._read_string()
+        data = self._read_bytes()
+
+        if dropped:
+            logger.warning("%d kernel notification(s) dropped before %r",
+                           dropped, tag)
+        handler = self.notification_handlers.get(tag)
+        if handler is None:
+            logger.debug("unhandled kernel notification %r: %r", tag, data)
+            return
+        # The kernel does not wait for notifications, so an error in the
+        # handler cannot be reported back to it.
+        try:
+            handler(data)
+        except Exception:
+            logger.error("kernel notification handler for %r failed", tag,
+                         exc_info=True)
+
+    def set_dataset_append_handler(self, handler):
+        """Calls ``handler(key, value)`` for every value the kernel appends
+        to a dataset with :func:`artiq.coredevice.dataset.dataset_append`.
+        Pass ``None`` to remove the handler."""
+        self.dataset_append_handler = handler
+
+    def _serve_dataset_append(self):
+        count = self._read_int32()
+        data = self._read_bytes()
+
+        values = []
+        offset = 0
+        for _ in range(count):
+            (length, ) = struct.unpack_from(self.endian + "l", data, offset)
+            offset += 4
+            key = data[offset:offset + length].decode("utf-8")
+            offset += length
+            (value, ) = struct.unpack_from(self.endian + "d", data, offset)
+            offset += 8
+            values.append((key, value))
+        logger.debug("dataset append: %d value(s)", count)
+
+        if self.dataset_append_handler is None:
+            logger.warning("%d value(s) appended to datasets by the kernel "
+                           "dropped, no handler", count)
+            return
+        # As with notifications, the kernel does not wait for the values.
+        for key, value in values:
+            try:
+                self.dataset_append_handler(key, value)
+            except Exception:
+                logger.error("cannot append to dataset %r", key,
+                             exc_info=True)
+
     def _serve_exception(self, embedding_map, symbolizer, demangler):
         name = self._read_string()
         message = self._read_string()
@@ -621,29 +1052,185 @@ class CommKernel:
         function = self._read_string()
 
         backtrace = [self._read_int32() for _ in range(self._read_int32())]
-
-        traceback = list(reversed(symbolizer(backtrace))) + \
+        details = Capability.EXCEPTION_DETAILS in self.capabilities
+        # function names resolved by the core device, empty if unknown
+        symbols = [self._read_string() if details else "" for _ in backtrace]
+
+        # exceptions being handled when this one was raised, oldest first
+        context = []
+        for _ in range(self._read_int8() if details else 0):
+            ctx_name = self._read_string()
+            ctx_message = self._read_string()
+            ctx_params = [self._read_int64() for _ in range(3)]
+            ctx_location = (self._read_string(), self._read_int32(), self._read_int32())
+            ctx_function = self._read_string()
+            context.append(exceptions.CoreException(
+                ctx_name, ctx_message, ctx_params,
+                [(*ctx_location, *demangler([ctx_function]), None)]))
+
+        # Fall back to the core device symbols for frames addr2line cannot
+        # locate, e.g. in kernels built without debug information.
+        frames = dict()
+        for frame in symbolizer(list(set(backtrace))):
+            frames.setdefault(frame[4], []).append(frame)
+        unlocated = [symbol for address, symbol in zip(backtrace, symbols)
+                     if address not in frames and symbol]
+        demangled = dict(zip(unlocated, demangler(unlocated))) if unlocated else {}
+        located = []
+        for address, symbol in zip(backtrace, symbols):
+            if address in frames:
+                located += frames[address]
+            elif symbol:
+                located.append(("<unknown>", -1, -1, demangled[symbol], address))
+
+        traceback = list(reversed(located)) + \
             [(filename, line, column, *demangler([function]), None)]
-        core_exn = exceptions.CoreException(name, message, params, traceback)
+        core_exn = exceptions.CoreException(name, message, params, traceback,
+                                            context)
 
-        if core_exn.id == 0:
-            python_exn_type = getattr(exceptions, core_exn.name.split('.')[-1])
-        else:
-            python_exn_type = embedding_map.retrieve_object(core_exn.id)
+        def python_exception(core_exn):
+            if core_exn.id == 0:
+                python_exn_type = getattr(exceptions, core_exn.name.split('.')[-1])
+            else:
+                python_exn_type = embedding_map.retrieve_object(core_exn.id)
+
+            python_exn = python_exn_type(core_exn.message.format(*core_exn.params))
+            python_exn.artiq_core_exception = core_exn
+            return python_exn
 
-        python_exn = python_exn_type(message.format(*params))
-        python_exn.artiq_core_exception = core_exn
+        python_exn = None
+        for ctx_exn in context + [core_exn]:
+            previous_exn, python_exn = python_exn, python_exception(ctx_exn)
+            python_exn.__context__ = previous_exn
         raise python_exn
 
+    def _resume(self, error):
+        """Reconnect to the kernel session after the connection was lost.
+
+        The core device keeps the kernel running for the time configured in
+        its ``session_grace_ms`` key and replays any RPC request that has not
+        been answered yet. The core device only hands the session over once
+        it has noticed that the connection was lost, and answers
+        ``Unavailable`` until then. If the session cannot be resumed within
+        ``resume_timeout`` seconds, ``error`` is raised."""
+        self.close()
+        self.read_buffer = bytearray()
+        self.write_buffer = bytearray()
+        if self.session_token is None:
+            logger.error("connection to core device lost (%s), and its "
+                         "firmware does not support resuming the session",
+                         error)
+            raise error
+        logger.warning("connection to core device lost (%s), reconnecting", error)
+
+        deadline = time.monotonic() + self.resume_timeout
+        while True:
+            try:
+                self._connect(self.resume_magic)
+                self.socket.sendall(self.session_token)
+                self._read_header()
+            except OSError:
+                pass
+            else:
+                if self._read_type == Reply.SessionResumed:
+                    break
+                if self._read_type != Reply.Unavailable:
+                    self.close()
+                    raise error
+            self.close()
+            self.read_buffer = bytearray()
+            if time.monotonic() > deadline:
+                raise error
+            time.sleep(0.5)
+        logger.info("kernel session resumed")
+
     def serve(self, embedding_map, symbolizer, demangler):
+        self.last_run_stats = None
         while True:
-            self._read_header()
-            if self._read_type == Reply.RPCRequest:
-                self._serve_rpc(embedding_map)
-            elif self._read_type == Reply.KernelException:
+            try:
+                self._read_header()
+                if self._read_type == Reply.RPCRequest:
+                    self._serve_rpc(embedding_map)
+                    continue
+                if self._read_type == Reply.KernelRunStats:
+                    self.last_run_stats = self._read_run_stats()
+                    logger.debug("kernel run statistics: %s",
+                                 self.last_run_stats)
+                    continue
+                if self._read_type == Reply.Notification:
+                    self._serve_notification()
+                    continue
+                if self._read_type == Reply.DatasetAppend:
+                    self._serve_dataset_append()
+                    continue
+            except ConnectionError as error:
+                self._resume(error)
+                continue
+
+            if self._read_type == Reply.KernelException:
                 self._serve_exception(embedding_map, symbolizer, demangler)
+            elif self._read_type == Reply.WatchdogExpired:
+                scope = self._read_string()
+                timeout_ms = self._read_int64()
+                elapsed_ms = self._read_int64()
+                raise exceptions.WatchdogExpired(scope, timeout_ms/1000,
+                                                 elapsed_ms/1000)
+            elif self._read_type == Reply.KernelTerminated:
+                raise exceptions.TerminationRequested(self._read_string())
             elif self._read_type == Reply.ClockFailure:
                 raise exceptions.ClockFailure
             else:
                 self._read_expect(Reply.KernelFinished)
                 return
+
+
+class CommKernelReadOnly(CommKernel):
+    """Diagnostic connection to the core device that can be opened while
+    another client runs kernels.
+
+    Only system information, logs, health data and analyzer dumps are
+    available; kernels cannot be loaded.
+    """
+    magic = b"ARTIQ coremon\n"
+
+    def load(self, kernel_library):
+        raise IOError("Kernels cannot be loaded through a read-only session")
+
+    def run(self):
+        raise IOError("Kernels cannot be run through a read-only session")
+
+    def get_log(self):
+        self._write_empty(Request.GetLog)
+        self._flush()
+
+        self._read_header()
+        self._read_expect(Reply.Log)
+        return self._read_string()
+
+    def get_health(self):
+        self._write_empty(Request.GetHealth)
+        self._flush()
+
+        self._read_header()
+        self._read_expect(Reply.Health)
+        return {
+            "rtio_clock_ok": self._read_bool(),
+            "rtio_frequency": self._read_int32() & 0xffffffff,
+            "clock_fallback": self._read_bool(),
+            "uptime": self._read_int64()/1000
+        }
+
+    def get_analyzer_dump(self):
+        """Returns the analyzer dump in the same format as
+        :func:`artiq.coredevice.comm_analyzer.get_analyzer_dump`."""
+        self._write_empty(Request.GetAnalyzerDump)
+        self._flush()
+
+        self._read_header()
+        if self._read_type == Reply.Unavailable:
+            raise IOError("Core device has no RTIO analyzer")
+        self._read_expect(Reply.AnalyzerDump)
+        header = self._read(16)
+        endian = ">" if header[0] == ord("E") else "<"
+        sent_bytes, = struct.unpack(endian + "I", header[1:5])
+        return bytes(header + self._read(sent_bytes))
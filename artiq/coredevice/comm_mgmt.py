@@ -1,8 +1,13 @@
 from enum import Enum
+import functools
 import logging
+import socket
 import struct
+import zlib
 
-from artiq.coredevice.comm import initialize_connection
+from artiq.coredevice.comm import (initialize_connection, authenticate,
+                                   decode_features)
+from artiq.coredevice.comm_analyzer import TriggerMode
 
 
 logger = logging.getLogger(__name__)
@@ -14,20 +19,118 @@ class Request(Enum):
     PullLog = 7
     SetLogFilter = 3
     SetUartLogFilter = 6
+    SetModuleLogFilter = 28
+    RemoveModuleLogFilter = 29
+    SetLogFormat = 49
+    GetPanicLog = 30
+    ClearPanicLog = 31
+
+    SubscribeAlerts = 50
+    AcknowledgeAlert = 51
 
     ConfigRead = 12
     ConfigWrite = 13
     ConfigRemove = 14
     ConfigErase = 15
+    ConfigList = 16
+    ConfigBackup = 17
+    ConfigRestore = 18
 
     StartProfiler = 9
     StopProfiler = 10
     GetProfile = 11
 
+    StartKernelProfiler = 22
+    StopKernelProfiler = 23
+    GetKernelProfile = 24
+
     Hotswap = 4
+    FlashFirmware = 27
     Reboot = 5
+    RebootToBootloader = 26
+
+    Si549Trim = 19
+    WrpllStats = 20
+    GetRtioFrequency = 21
+    GetRtioCounter = 55
+
+    EepromRead = 56
+    EepromWrite = 57
+
+    FlashStorageRead = 58
+    FlashStorageErase = 59
+
+    GetSensorHistory = 60
+
+    InjectFault = 61
+
+    GetRtioErrorCounts = 62
+
+    KernelImageWrite = 63
+    KernelImageRemove = 64
+    KernelImageList = 65
+    KernelImageRun = 66
+
+    GetCompatibility = 67
+
+    PullLogCounted = 68
+    GetLostLogRecords = 69
+
+    GetThreadStats = 70
+
+    DrtioEyeScan = 71
+
+    GetClockRegisters = 72
+    GetRtioTopology = 73
+    TerminateKernel = 74
+    GetEemPresence = 75
+    I2cTransfer = 76
+    SpiTransfer = 77
+    GetRtc = 78
+    SetRtc = 79
+    GetMoninjLimits = 80
+    GetAuditLog = 81
+    GetDrtioLatency = 82
+    SetDrtioLatency = 83
+    SetLogSinkFilter = 84
+    GetLogSinks = 85
+    RunEemSelfTest = 86
+    PrepareForPowerOff = 87
+    GetKernelRunStats = 88
+    FlashGateware = 89
+    GetFeatures = 90
+    GetFlashHealth = 91
+    GetChannelEvents = 92
+    ReadChunk = 93
+    GetHealthRecords = 94
+    SubscribeAlertRecords = 95
 
     DebugAllocator = 8
+    AllocatorStats = 25
+
+    GetHealthSnapshot = 32
+    GetNetStats = 33
+
+    SetAnalyzerTrigger = 34
+    GetAnalyzerTrigger = 35
+    SetAnalyzerFilter = 36
+    GetAnalyzerFilter = 37
+
+    GetDrtioLinkStats = 38
+    GetSatelliteIdents = 39
+    ReloadRoutingTable = 41
+    GetTscStats = 42
+    GetSedStats = 43
+    GetCacheEntries = 44
+    RemoveCacheEntry = 45
+    ClearCache = 46
+    GetBoardIdentity = 47
+    GetGrabberStats = 48
+    RunSelfTest = 52
+    GetSelfTestReport = 53
+    GetDdsSyncReports = 54
+
+    FlashSatelliteFirmware = 40
 
 
 class Reply(Enum):
@@ -38,11 +141,73 @@ class Reply(Enum):
     LogContent = 2
 
     ConfigData = 7
+    ConfigKeys = 8
 
     Profile = 5
 
     RebootImminent = 3
 
+    WrpllStats = 9
+    RtioFrequency = 10
+
+    AllocatorStats = 11
+
+    HealthSnapshot = 12
+    NetStats = 13
+
+    AnalyzerTrigger = 14
+    AnalyzerFilter = 15
+
+    DrtioLinkStats = 16
+    SatelliteIdents = 17
+    TscStats = 18
+    SedStats = 19
+    CacheEntries = 20
+    BoardIdentity = 21
+    GrabberStats = 22
+
+    Alert = 23
+    SelfTestReport = 24
+    DdsSyncReports = 25
+    RtioCounter = 26
+    FlashStorageRecords = 27
+    SensorHistory = 28
+    RtioErrorCounts = 29
+    Compatibility = 30
+    LostLogRecords = 31
+    ThreadStats = 32
+    EyeScan = 33
+    ClockRegisters = 34
+    RtioTopology = 35
+    EemPresence = 36
+    SpiData = 37
+    Rtc = 38
+    MoninjLimits = 39
+    AuditLog = 40
+    DrtioLatency = 41
+    LogSinks = 42
+    PowerOffReady = 43
+    KernelRunStats = 44
+    Features = 45
+    FlashHealth = 46
+    ChannelEvents = 47
+    Chunk = 48
+    HealthRecords = 49
+
+
+ALERT_SEVERITIES = ["info", "warning", "critical"]
+ALERT_SOURCES = ["sfp", "clock", "drtio", "temperature", "watchdog",
+                 "self_test", "eem", "interlock"]
+SELF_TEST_OUTCOMES = ["pass", "fail", "skipped"]
+AUDIT_SERVICES = ["session", "mgmt", "moninj"]
+AUDIT_EVENTS = ["connected", "disconnected", "command"]
+KERNEL_RUN_OUTCOMES = ["finished", "exception", "stopped"]
+LOG_SINKS = ["uart", "buffer", "syslog"]
+# Clocking chips, with the config keys of their register profiles.
+CLOCK_CHIPS = [("si5324", "si5324_profile"),
+               ("si549_main", "si549_main_profile"),
+               ("si549_helper", "si549_helper_profile")]
+
 
 class LogLevel(Enum):
     OFF = 0
@@ -53,10 +218,167 @@ class LogLevel(Enum):
     TRACE = 5
 
 
+# Health records (see health_record.rs): names of the record kinds and of
+# their fields. Records and fields missing here, e.g. added by a newer
+# firmware, are named after their number.
+HEALTH_RECORD_VERSION = 1
+_SFP_RECORD_FIELDS = {1: "temperature", 2: "voltage", 3: "tx_bias",
+                      4: "tx_power", 5: "rx_power", 6: "alarms"}
+HEALTH_RECORD_KINDS = {
+    1: ("system", {1: "uptime", 2: "time", 3: "boot_count",
+                   4: "total_uptime", 5: "firmware_version",
+                   6: "gateware_version", 7: "rtio_clock_ok",
+                   8: "rtio_frequency"}),
+    2: ("heap", {1: "total", 2: "used", 3: "largest_free"}),
+    3: ("fpga", {1: "temperature", 2: "vccint", 3: "vccaux", 4: "vccbram"}),
+    4: ("sfp", _SFP_RECORD_FIELDS),
+    5: ("drtio_link", {1: "up", 2: "crc_errors", 3: "aux_timeouts",
+                       4: "link_drops", 5: "protocol_errors",
+                       6: "remote_rtio_errors"}),
+    6: ("power_rail", {1: "name", 2: "voltage", 3: "current",
+                       4: "min_voltage", 5: "brownouts"}),
+    7: ("satellite_sfp", {**_SFP_RECORD_FIELDS, 7: "port"}),
+}
+
+
+def parse_health_records(data):
+    """Decodes a health record set into a list of records, as dictionaries
+    with the ``kind`` of the record, the ``index`` of the instance (e.g. the
+    SFP port, DRTIO link or destination) and its fields. Numbers are in SI
+    units (degrees Celsius for temperatures); fields that the core device
+    could not read are missing."""
+    if not data:
+        return []
+    if data[0] != HEALTH_RECORD_VERSION:
+        raise IOError("Unsupported health record version {}".format(data[0]))
+    records = []
+    offset = 1
+    while offset + 4 <= len(data):
+        kind, index, length = struct.unpack(">BBH", data[offset:offset+4])
+        offset += 4
+        end = offset + length
+        name, field_names = HEALTH_RECORD_KINDS.get(
+            kind, ("kind_{}".format(kind), {}))
+        record = {"kind": name, "index": index}
+        while offset + 4 <= end:
+            tag, ty, exponent, size = struct.unpack(">BBbB", data[offset:offset+4])
+            value = data[offset+4:offset+4+size]
+            offset += 4 + size
+            if ty == 0:
+                value = value != b"\x00"
+            elif ty in (1, 2):
+                value = int.from_bytes(value, "big", signed=ty == 2)
+                if exponent:
+                    value *= 10**exponent
+            elif ty == 3:
+                value = value.decode("utf-8", errors="replace")
+            else:
+                value = bytes(value)
+            record[field_names.get(tag, "field_{}".format(tag))] = value
+        offset = end
+        records.append(record)
+    return records
+
+
+def parse_rtio_topology(topology):
+    """Parses the RTIO topology reported by the firmware (see
+    ``artiq.build_soc.get_topology``) into a dictionary with the ``role``,
+    the number of downstream DRTIO ``links``, the number of local RTIO
+    ``channels``, the ``log`` channel (or None), and the ``peripherals``, a
+    list of dictionaries with the ``type``, ``ports`` and ``channels``
+    (a range) of each EEM peripheral."""
+    result = {"role": None, "links": 0, "channels": 0, "log": None,
+              "peripherals": []}
+    for field in topology.split(";"):
+        key, _, value = field.partition("=")
+        if "@" in key:
+            ty, _, ports = key.partition("@")
+            first, _, last = value.partition("-")
+            result["peripherals"].append({
+                "type": ty,
+                "ports": [int(port) for port in ports.split(",")],
+                "channels": range(int(first), int(last))
+            })
+        elif key == "role":
+            result["role"] = value
+        elif key in ("links", "channels", "log"):
+            result[key] = int(value)
+    return result
+
+
+def clock_profile(chip, registers):
+    """Returns the config key and value of the register profile of
+    clocking chip ``chip``, from its registers as returned by
+    :meth:`CommMgmt.get_clock_registers`."""
+    key = dict(CLOCK_CHIPS)[chip]
+    return key, bytes(b for address, value, profile in registers
+                      if profile for b in (address, value))
+
+
+def drtio_latencies(host, port=1380, auth_key=None):
+    """Returns the one-way latency in seconds of the RT link to each DRTIO
+    satellite of the core device at ``host``, as a dictionary keyed by
+    destination: the calibration stored in the core device if any, else the
+    latency measured. Satellites whose latency is not known are left out.
+
+    Device databases can call it to provide the latencies to experiments,
+    which offset the timing of the channels of the satellites with them::
+
+        device_db["drtio_latencies"] = drtio_latencies(core_addr)
+
+    The core device must then be reachable when the device database is
+    loaded.
+    """
+    mgmt = CommMgmt(host, port, auth_key)
+    try:
+        satellites = mgmt.get_drtio_latency()
+    finally:
+        mgmt.close()
+    latencies = dict()
+    for satellite in satellites:
+        latency = satellite["calibrated"]
+        if latency is None:
+            latency = satellite["measured"]
+        if latency is not None:
+            latencies[satellite["destination"]] = latency
+    return latencies
+
+
+# Largest chunk of a reply that the core device sends at once.
+MAX_CHUNK_LENGTH = 16384
+# Interruptions of a chunked read after which it is abandoned.
+CHUNK_RETRIES = 3
+
+
+def _large_reply(method):
+    # Requests whose reply can be large. With the chunk_length of CommMgmt
+    # set, the reply is read in chunks, see CommMgmt._read_chunks.
+    @functools.wraps(method)
+    def wrapper(self, *args, **kwargs):
+        if self.chunk_length is None:
+            return method(self, *args, **kwargs)
+        self._chunk_request = bytearray()
+        try:
+            return method(self, *args, **kwargs)
+        finally:
+            self._chunk_request = None
+            self._chunk_reply = None
+    return wrapper
+
+
 class CommMgmt:
-    def __init__(self, host, port=1380):
+    def __init__(self, host, port=1380, auth_key=None, chunk_length=None):
+        """With ``chunk_length`` set (in bytes, at most
+        ``MAX_CHUNK_LENGTH``), the replies that can be large (log, config
+        backup, flash storage, sensor history, audit log and kernel run
+        statistics) are read in chunks of that size, one at a time. A read
+        interrupted by a lost connection is resumed from the last chunk."""
         self.host = host
         self.port = port
+        self.auth_key = auth_key
+        self.chunk_length = chunk_length
+        self._chunk_request = None
+        self._chunk_reply = None
 
     def open(self):
         if hasattr(self, "socket"):
@@ -70,6 +392,7 @@ class CommMgmt:
             self.endian = ">"
         else:
             raise IOError("Incorrect reply from device: expected e/E.")
+        authenticate(self._read, self._write, self.auth_key)
 
     def close(self):
         if not hasattr(self, "socket"):
@@ -81,7 +404,10 @@ class CommMgmt:
     # Protocol elements
 
     def _write(self, data):
-        self.socket.sendall(data)
+        if self._chunk_request is not None:
+            self._chunk_request += data
+        else:
+            self.socket.sendall(data)
 
     def _write_header(self, ty):
         self.open()
@@ -92,6 +418,9 @@ class CommMgmt:
     def _write_int8(self, value):
         self._write(struct.pack("B", value))
 
+    def _write_int16(self, value):
+        self._write(struct.pack(self.endian + "H", value))
+
     def _write_int32(self, value):
         self._write(struct.pack(self.endian + "l", value))
 
@@ -103,6 +432,15 @@ class CommMgmt:
         self._write_bytes(value.encode("utf-8"))
 
     def _read(self, length):
+        if self._chunk_request is not None:
+            request, self._chunk_request = self._chunk_request, None
+            self._chunk_reply = memoryview(self._read_chunks(request))
+        if self._chunk_reply is not None:
+            if len(self._chunk_reply) < length:
+                raise IOError("Incomplete chunked reply from device")
+            r = bytes(self._chunk_reply[:length])
+            self._chunk_reply = self._chunk_reply[length:]
+            return r
         r = bytes()
         while len(r) < length:
             rn = self.socket.recv(min(8192, length - len(r)))
@@ -122,6 +460,20 @@ class CommMgmt:
             raise IOError("Incorrect reply from device: {} (expected {})".
                           format(self._read_type, ty))
 
+    def _read_bool(self):
+        return self._read(1)[0] != 0
+
+    def _read_int8(self):
+        return self._read(1)[0]
+
+    def _read_uint16(self):
+        (value, ) = struct.unpack(self.endian + "H", self._read(2))
+        return value
+
+    def _read_int16(self):
+        (value, ) = struct.unpack(self.endian + "h", self._read(2))
+        return value
+
     def _read_int32(self):
         (value, ) = struct.unpack(self.endian + "l", self._read(4))
         return value
@@ -132,8 +484,66 @@ class CommMgmt:
     def _read_string(self):
         return self._read_bytes().decode("utf-8")
 
+    def _read_chunks(self, request):
+        # Reads the reply to the captured request in chunks, resuming after
+        # a lost connection. The core device snapshots the reply and keeps
+        # serving the snapshot with the CRC sent (0 to start a read); if it
+        # no longer has it, the reply may have changed (e.g. the log), and
+        # the read starts over with the new snapshot.
+        data = bytearray()
+        crc = None
+        failures = 0
+        while True:
+            try:
+                self._write_header(Request.ReadChunk)
+                self._write_int32(len(data))
+                self._write_int32(min(self.chunk_length, MAX_CHUNK_LENGTH))
+                self._write(struct.pack(self.endian + "L",
+                                        0 if crc is None else crc))
+                self._write(bytes(request))
+                ty = self._read_header()
+                if ty == Reply.Error:
+                    raise IOError("Reply cannot be read in chunks; "
+                                  "see the core device log")
+                elif ty != Reply.Chunk:
+                    raise IOError("Incorrect reply from device: {} (expected {})".
+                                  format(ty, Reply.Chunk))
+                total = self._read_int32() & 0xffffffff
+                chunk_crc = self._read_int32() & 0xffffffff
+                offset = self._read_int32() & 0xffffffff
+                chunk = self._read_bytes()
+            except (ConnectionError, socket.timeout) as e:
+                failures += 1
+                if failures > CHUNK_RETRIES:
+                    raise
+                logger.warning("chunked read interrupted (%s), resuming at "
+                               "byte %d", e, len(data))
+                self.close()
+                continue
+
+            if crc is not None and chunk_crc != crc:
+                failures += 1
+                if failures > CHUNK_RETRIES:
+                    raise IOError("Reply keeps changing during chunked read")
+                logger.warning("reply changed during chunked read, "
+                               "starting over")
+                data = bytearray()
+                crc = chunk_crc
+                continue
+            if offset != len(data) or (not chunk and len(data) < total):
+                raise IOError("Incorrect chunk from device")
+            crc = chunk_crc
+            data += chunk
+            logger.debug("read %d of %d bytes", len(data), total)
+            if len(data) >= total:
+                break
+        if zlib.crc32(data) != crc:
+            raise IOError("CRC mismatch in chunked reply from device")
+        return bytes(data)
+
     # External API
 
+    @_large_reply
     def get_log(self):
         self._write_header(Request.GetLog)
         self._read_expect(Reply.LogContent)
@@ -148,6 +558,73 @@ class CommMgmt:
         self._read_expect(Reply.LogContent)
         return self._read_string()
 
+    def get_lost_log_records(self):
+        """Returns the number of log records lost since boot, because the
+        log buffer was in use or, while a host pulls the log (e.g.
+        ``aqctl_corelog``), because the host did not keep up."""
+        self._write_header(Request.GetLostLogRecords)
+        self._read_expect(Reply.LostLogRecords)
+        return self._read_int32() & 0xffffffff
+
+    def subscribe_alerts(self, records=False):
+        """Yields the alerts raised by the core device as they arrive, as
+        dictionaries with the ``sequence`` number, ``timestamp_ms`` (uptime),
+        ``severity`` (``"info"``, ``"warning"`` or ``"critical"``),
+        ``source``, ``message`` and the count of alerts ``dropped`` since
+        boot because the queue of the core device was full. With
+        ``records``, alerts also have the health ``records`` of their
+        condition, e.g. the diagnostics of an SFP port (see
+        :meth:`get_health_records`), which the core device reports if it has
+        the ``HEALTH_RECORDS`` feature.
+
+        Each alert is acknowledged when the consumer asks for the next one,
+        and stays queued on the core device until then, so alerts raised
+        while no host is subscribed are delivered on the next subscription.
+        This blocks the management connection; use a separate one for other
+        requests."""
+        if records:
+            self._write_header(Request.SubscribeAlertRecords)
+        else:
+            self._write_header(Request.SubscribeAlerts)
+        while True:
+            self._read_expect(Reply.Alert)
+            sequence = self._read_int32() & 0xffffffff
+            (timestamp_ms, ) = struct.unpack(self.endian + "Q", self._read(8))
+            severity = self._read_int8()
+            source = self._read_int8()
+            alert = {
+                "sequence": sequence,
+                "timestamp_ms": timestamp_ms,
+                "severity": ALERT_SEVERITIES[severity]
+                            if severity < len(ALERT_SEVERITIES) else severity,
+                "source": ALERT_SOURCES[source]
+                          if source < len(ALERT_SOURCES) else source,
+                "dropped": self._read_int32() & 0xffffffff,
+                "message": self._read_string(),
+            }
+            if records:
+                alert["records"] = parse_health_records(self._read_bytes())
+            yield alert
+            self._write_header(Request.AcknowledgeAlert)
+            self._write(struct.pack(self.endian + "L", sequence))
+
+    @_large_reply
+    def get_panic_log(self):
+        """Returns the report of the last firmware panic or kernel CPU
+        exception saved in flash, or ``None`` if there is none."""
+        self._write_header(Request.GetPanicLog)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            return None
+        elif ty != Reply.LogContent:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.LogContent))
+        return self._read_string()
+
+    def clear_panic_log(self):
+        self._write_header(Request.ClearPanicLog)
+        self._read_expect(Reply.Success)
+
     def set_log_level(self, level):
         if level not in LogLevel.__members__:
             raise ValueError("invalid log level {}".format(level))
@@ -164,6 +641,70 @@ class CommMgmt:
         self._write_int8(getattr(LogLevel, level).value)
         self._read_expect(Reply.Success)
 
+    def set_module_log_level(self, module, level):
+        """Sets the log level of a firmware module and its submodules, such
+        as ``runtime::session``, overriding the global log level. The setting
+        is saved in the core device configuration and applied at boot."""
+        if level not in LogLevel.__members__:
+            raise ValueError("invalid log level {}".format(level))
+
+        self._write_header(Request.SetModuleLogFilter)
+        self._write_string(module)
+        self._write_int8(getattr(LogLevel, level).value)
+        self._read_expect(Reply.Success)
+
+    def remove_module_log_level(self, module):
+        """Makes a firmware module follow the global log level again."""
+        self._write_header(Request.RemoveModuleLogFilter)
+        self._write_string(module)
+        self._read_expect(Reply.Success)
+
+    def set_log_sink_level(self, sink, level):
+        """Sets the level of the records sent to a log sink of the core
+        device: ``"uart"``, ``"buffer"`` (the log read with :meth:`get_log`
+        and forwarded to the host) or ``"syslog"`` (the remote syslog server
+        of the ``syslog_addr`` config key). Each sink only takes records that
+        also pass the global and per-module log levels. The setting is saved
+        in the core device configuration and applied at boot."""
+        if sink not in LOG_SINKS:
+            raise ValueError("invalid log sink {}".format(sink))
+        if level not in LogLevel.__members__:
+            raise ValueError("invalid log level {}".format(level))
+
+        self._write_header(Request.SetLogSinkFilter)
+        self._write_int8(LOG_SINKS.index(sink))
+        self._write_int8(getattr(LogLevel, level).value)
+        self._read_expect(Reply.Success)
+
+    def get_log_sinks(self):
+        """Returns a dictionary with the ``levels`` of the log sinks, a
+        dictionary keyed by sink, the address of the ``syslog`` server, or
+        None if there is none, and the number of records dropped from the
+        syslog sink since boot (``syslog_lost``)."""
+        self._write_header(Request.GetLogSinks)
+        self._read_expect(Reply.LogSinks)
+        levels = {sink: LogLevel(self._read_int8()).name for sink in LOG_SINKS}
+        syslog = self._read_string()
+        return {
+            "levels": levels,
+            "syslog": syslog or None,
+            "syslog_lost": self._read_int32() & 0xffffffff,
+        }
+
+    def set_log_format(self, format):
+        """Sets the format of the log buffer, and so of the logs read with
+        :meth:`get_log` and forwarded by ``aqctl_corelog``: ``"text"``, or
+        ``"json"`` for one JSON object per line with the ``level``,
+        ``module``, ``timestamp``, ``uptime_us`` and ``message`` fields.
+        The UART log always uses text. The setting is saved in the core
+        device configuration and applied at boot."""
+        if format not in ("text", "json"):
+            raise ValueError("invalid log format {}".format(format))
+
+        self._write_header(Request.SetLogFormat)
+        self._write_int8(format == "json")
+        self._read_expect(Reply.Success)
+
     def config_read(self, key):
         self._write_header(Request.ConfigRead)
         self._write_string(key)
@@ -181,11 +722,291 @@ class CommMgmt:
             raise IOError("Incorrect reply from device: {} (expected {})".
                           format(ty, Reply.Success))
 
+    def _write_eeprom(self, ty, busno, port, address, size, page_size,
+                      offset):
+        self._write_header(ty)
+        self._write_int8(busno)
+        self._write_int8(0xff if port is None else port)
+        self._write_int8(address)
+        self._write_int32(size)
+        self._write_int32(page_size)
+        self._write_int32(offset)
+
+    def eeprom_read(self, busno, port, address, size, page_size, offset,
+                    length):
+        """Reads ``length`` bytes at ``offset`` of a 24xx I2C EEPROM, of
+        ``size`` bytes with pages of ``page_size`` bytes, at the 7-bit
+        ``address`` on I2C bus ``busno``, behind the I2C switch ``port`` of
+        Kasli (a number, see :mod:`artiq.coredevice.kasli_i2c`) or
+        ``None``."""
+        self._write_eeprom(Request.EepromRead, busno, port, address, size,
+                           page_size, offset)
+        self._write_int32(length)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Cannot read EEPROM; see the core device log")
+        elif ty != Reply.ConfigData:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.ConfigData))
+        return self._read_bytes()
+
+    def eeprom_write(self, busno, port, address, size, page_size, offset,
+                     data):
+        """Writes the bytes ``data`` at ``offset`` of a 24xx I2C EEPROM,
+        as :meth:`eeprom_read`."""
+        self._write_eeprom(Request.EepromWrite, busno, port, address, size,
+                           page_size, offset)
+        self._write_bytes(data)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Cannot write EEPROM; see the core device log")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def i2c_transfer(self, busno, port, address, write=b"", read_length=0):
+        """Performs a raw I2C transaction on I2C bus ``busno`` of the core
+        device: writes the bytes ``write`` to the device at the 7-bit
+        ``address``, behind the I2C switch ``port`` of Kasli (a number, see
+        :mod:`artiq.coredevice.kasli_i2c`) or ``None``, then reads
+        ``read_length`` bytes from it after a repeated start condition, and
+        returns them.
+
+        Raw transactions must be enabled with the ``bus_passthrough`` config
+        key set to ``1``, and are refused while a kernel is running."""
+        self._write_header(Request.I2cTransfer)
+        self._write_int8(busno)
+        self._write_int8(0xff if port is None else port)
+        self._write_int8(address)
+        self._write_bytes(bytes(write))
+        self._write_int16(read_length)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("I2C transaction failed; see the core device log")
+        elif ty != Reply.ConfigData:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.ConfigData))
+        return self._read_bytes()
+
+    def spi_transfer(self, busno, flags, length, div, cs, data):
+        """Performs a raw transaction on the non-realtime SPI bus ``busno``
+        of the core device: configures it with ``flags``, ``length``,
+        ``div`` and ``cs`` as :meth:`artiq.coredevice.spi2.NRTSPIMaster.set_config_mu`,
+        shifts out the words ``data``, and returns the list of the words
+        shifted in at the same time.
+
+        Raw transactions must be enabled as for :meth:`i2c_transfer`."""
+        self._write_header(Request.SpiTransfer)
+        for value in (busno, flags, length, div, cs):
+            self._write_int8(value)
+        self._write_int16(len(data))
+        for word in data:
+            self._write(struct.pack(self.endian + "L", word))
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("SPI transaction failed; see the core device log")
+        elif ty != Reply.SpiData:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.SpiData))
+        return [struct.unpack(self.endian + "L", self._read(4))[0]
+                for _ in range(self._read_uint16())]
+
+    def get_rtc(self):
+        """Returns the time of the battery-backed RTC of the core device,
+        described by its ``rtc`` config key, in seconds since the Unix epoch,
+        or None if the RTC has not kept the time since it was set."""
+        self._write_header(Request.GetRtc)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Core device has no RTC (rtc config key)")
+        elif ty == Reply.Error:
+            raise IOError("Cannot read the RTC; see the core device log")
+        elif ty != Reply.Rtc:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Rtc))
+        if not self._read_bool():
+            return None
+        (unix_s, ) = struct.unpack(self.endian + "Q", self._read(8))
+        return unix_s
+
+    def set_rtc(self, unix_s):
+        """Sets the RTC of the core device, and its wall clock, to
+        ``unix_s`` seconds since the Unix epoch."""
+        self._write_header(Request.SetRtc)
+        self._write(struct.pack(self.endian + "Q", int(unix_s)))
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Core device has no RTC (rtc config key)")
+        elif ty == Reply.Error:
+            raise IOError("Cannot set the RTC; see the core device log")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    @_large_reply
+    def flash_storage_read(self):
+        """Returns the contents of the flash storage area that kernels
+        write with :func:`artiq.coredevice.flash_storage.put`, as a
+        dictionary with the number of bytes ``used`` (including superseded
+        records), the ``size`` of the area and the stored ``records`` (a
+        dictionary of keys to values as bytes)."""
+        self._write_header(Request.FlashStorageRead)
+        self._read_expect(Reply.FlashStorageRecords)
+        used = self._read_int32()
+        size = self._read_int32()
+        records = dict()
+        for _ in range(self._read_int32()):
+            key = self._read_string()
+            records[key] = self._read_bytes()
+        return {"used": used, "size": size, "records": records}
+
+    def flash_storage_erase(self):
+        """Removes all records of the flash storage area."""
+        self._write_header(Request.FlashStorageErase)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Cannot erase flash storage; see the core device log")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def get_flash_health(self, scrub=False):
+        """Returns the health of the flash areas that the firmware writes at
+        run time, as a dictionary with the uptime at the end of the last
+        scrub in ``scrubbed_ms`` (``None`` before the first one), the number
+        of ``scrubs`` since boot, the ``areas`` (a dictionary of area names
+        to the numbers of values ``checked`` and found ``corrupted``), the
+        erase and program ``failures`` since boot, the number of
+        ``spares_free`` and the ``remapped`` sectors (a list of pairs of
+        flash addresses of the sector and its spare). With ``scrub``, the
+        areas are scrubbed first."""
+        self._write_header(Request.GetFlashHealth)
+        self._write_int8(scrub)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Core device has no flash")
+        elif ty != Reply.FlashHealth:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.FlashHealth))
+        scrubbed_ms = None
+        if self._read_bool():
+            (scrubbed_ms, ) = struct.unpack(self.endian + "Q", self._read(8))
+        scrubs = self._read_int32()
+        areas = dict()
+        for _ in range(self._read_int32()):
+            name = self._read_string()
+            checked = self._read_int32()
+            areas[name] = {"checked": checked, "corrupted": self._read_int32()}
+        failures = self._read_int32()
+        spares_free = self._read_int8()
+        remapped = []
+        for _ in range(self._read_int32()):
+            sector = self._read_int32()
+            remapped.append((sector, self._read_int32()))
+        return {
+            "scrubbed_ms": scrubbed_ms,
+            "scrubs": scrubs,
+            "areas": areas,
+            "failures": failures,
+            "spares_free": spares_free,
+            "remapped": remapped,
+        }
+
     def config_remove(self, key):
         self._write_header(Request.ConfigRemove)
         self._write_string(key)
         self._read_expect(Reply.Success)
 
+    def config_list(self):
+        self._write_header(Request.ConfigList)
+        self._read_expect(Reply.ConfigKeys)
+        keys = []
+        for _ in range(self._read_int32()):
+            key = self._read_string()
+            size = self._read_int32()
+            keys.append((key, size))
+        return keys
+
+    def kernel_image_write(self, name, image):
+        """Stores the compiled kernel ``image`` (e.g. from
+        ``artiq_compile``) in the flash of the core device under ``name``,
+        which is at most 32 characters long and has no ``/``."""
+        self._write_header(Request.KernelImageWrite)
+        self._write_string(name)
+        self._write_bytes(image)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Cannot store kernel image; see the core device log")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def kernel_image_remove(self, name):
+        self._write_header(Request.KernelImageRemove)
+        self._write_string(name)
+        self._read_expect(Reply.Success)
+
+    def kernel_image_list(self):
+        """Returns a list of the names and sizes of the stored kernel
+        images."""
+        self._write_header(Request.KernelImageList)
+        self._read_expect(Reply.ConfigKeys)
+        images = []
+        for _ in range(self._read_int32()):
+            name = self._read_string()
+            size = self._read_int32()
+            images.append((name, size))
+        return images
+
+    def kernel_image_run(self, name):
+        """Runs the stored kernel image ``name`` in place of the startup or
+        idle kernel. Fails if a host holds a kernel session. The idle kernel
+        is started again once the stored kernel finishes."""
+        self._write_header(Request.KernelImageRun)
+        self._write_string(name)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Cannot run kernel image; see the core device log")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def terminate_kernel(self, reason):
+        """Requests the termination of the running kernel, e.g. to rescue a
+        kernel that is stuck without dropping its session. The next
+        synchronous RPC of the kernel raises
+        :class:`artiq.coredevice.exceptions.TerminationRequested` with
+        ``reason`` as message; if the kernel has not ended after the
+        ``kernel_termination_grace_ms`` config key (1000 ms by default), the
+        core device stops it and the host raises that exception instead.
+
+        Returns False if no kernel is running."""
+        self._write_header(Request.TerminateKernel)
+        self._write_string(reason)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            return False
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+        return True
+
+    @_large_reply
+    def config_backup(self):
+        self._write_header(Request.ConfigBackup)
+        self._read_expect(Reply.ConfigData)
+        return self._read_bytes()
+
+    def config_restore(self, image):
+        self._write_header(Request.ConfigRestore)
+        self._write_bytes(image)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Configuration backup rejected by device")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
     def config_erase(self):
         self._write_header(Request.ConfigErase)
         self._read_expect(Reply.Success)
@@ -205,8 +1026,26 @@ class CommMgmt:
         self._write_header(Request.StopProfiler)
         self._read_expect(Reply.Success)
 
+    def start_kernel_profiler(self, interval, hits_size, edges_size):
+        self._write_header(Request.StartKernelProfiler)
+        self._write_int32(interval)
+        self._write_int32(hits_size)
+        self._write_int32(edges_size)
+        self._read_expect(Reply.Success)
+
+    def stop_kernel_profiler(self):
+        self._write_header(Request.StopKernelProfiler)
+        self._read_expect(Reply.Success)
+
     def get_profile(self):
         self._write_header(Request.GetProfile)
+        return self._read_profile()
+
+    def get_kernel_profile(self):
+        self._write_header(Request.GetKernelProfile)
+        return self._read_profile()
+
+    def _read_profile(self):
         self._read_expect(Reply.Profile)
 
         hits = {}
@@ -229,9 +1068,1156 @@ class CommMgmt:
         self._write_bytes(firmware)
         self._read_expect(Reply.RebootImminent)
 
+    def flash_firmware(self, image):
+        """Writes a firmware image (``.fbi`` file) into the inactive flash
+        bank and reboots into it on trial. The bootloader returns to the
+        previous firmware if the new one restarts before checking in."""
+        self._write_header(Request.FlashFirmware)
+        self._write_bytes(image)
+        self._read_expect(Reply.RebootImminent)
+
+    def flash_satellite_firmware(self, destination, image):
+        """Writes a firmware image (``.fbi`` file) into the inactive flash
+        bank of the DRTIO satellite at ``destination``, through the master,
+        and reboots the satellite into it on trial. The satellite returns to
+        its previous firmware if the new one restarts before its link has
+        been up for the trial time. Only firmware is updated; gateware must
+        be written with ``artiq_flash``."""
+        self._write_header(Request.FlashSatelliteFirmware)
+        self._write_int8(destination)
+        self._write_bytes(image)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Cannot write firmware of destination {} "
+                          "(see core log)".format(destination))
+        if ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def flash_gateware(self, image, reconfigure=False):
+        """Writes a gateware image (bitstream with the same length and CRC32
+        header as firmware images) into the gateware update slot of the
+        flash, after the golden gateware, and schedules it to be booted on
+        trial by the bootloader. If ``reconfigure`` is true, the FPGA is
+        reconfigured with it right away. The golden gateware is booted again
+        if the update fails to configure the FPGA or restarts before the
+        firmware checks in."""
+        self._write_header(Request.FlashGateware)
+        self._write_bytes(image)
+        self._write_int8(reconfigure)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Cannot write gateware; see the core device log")
+        expected = Reply.RebootImminent if reconfigure else Reply.Success
+        if ty != expected:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, expected))
+
+    def disable_gateware_update(self):
+        """Stops booting the gateware update written with
+        :meth:`flash_gateware`, so that the golden gateware is booted from
+        the next power-up."""
+        self._write_header(Request.FlashGateware)
+        self._write_bytes(b"")
+        self._write_int8(False)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Cannot disable the gateware update; see the core device log")
+        if ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
     def reboot(self):
         self._write_header(Request.Reboot)
         self._read_expect(Reply.RebootImminent)
 
+    def reboot_to_bootloader(self):
+        """Reboots the core device and keeps it in the bootloader, waiting
+        for a firmware image over the network instead of booting the one in
+        flash. Only the next boot is affected."""
+        self._write_header(Request.RebootToBootloader)
+        self._read_expect(Reply.RebootImminent)
+
+    def prepare_power_off(self):
+        """Prepares the core device to be powered off: terminates the
+        running kernel, drives the outputs listed in the ``safe_state``
+        configuration key to their safe state, saves its pending state to
+        flash, sends the pending log records to the syslog server and
+        disables the SFP transmitters other than SFP0. Returns once done.
+
+        Until the core device is restarted, it starts no kernel and accepts
+        no new kernel session. Returns a dictionary with ``kernel_stopped``
+        (whether a kernel was running), the number of ``safe_state_outputs``
+        and the SFP ports whose transmitter is disabled, ``sfp_tx_disabled``,
+        as a list of indices."""
+        self._write_header(Request.PrepareForPowerOff)
+        self._read_expect(Reply.PowerOffReady)
+        kernel_stopped = self._read_bool()
+        safe_state_outputs = self._read_int32() & 0xffffffff
+        sfp_tx_disabled = self._read_int8()
+        return {
+            "kernel_stopped": kernel_stopped,
+            "safe_state_outputs": safe_state_outputs,
+            "sfp_tx_disabled": [i for i in range(8)
+                                if sfp_tx_disabled & (1 << i)]
+        }
+
+    def si549_trim(self, destination, ppb):
+        self._write_header(Request.Si549Trim)
+        self._write_int8(destination)
+        self._write_int32(ppb)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Si549 frequency trim rejected by destination {}"
+                          .format(destination))
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def get_clock_registers(self, destination=0):
+        """Reads the registers of the clocking chips (Si5324, Si549) of
+        ``destination``.
+
+        Returns a dictionary with, for each chip present, a list of
+        ``(address, value, profile)`` tuples, where ``profile`` is set for
+        the registers that hold its configuration. These make up the
+        register profile that programs the chip at boot when it is saved
+        in the config key given by :func:`clock_profile`."""
+        self._write_header(Request.GetClockRegisters)
+        self._write_int8(destination)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Cannot read the clocking chip registers; "
+                          "see the core device log")
+        elif ty != Reply.ClockRegisters:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.ClockRegisters))
+        chips = dict()
+        for _ in range(self._read_int8()):
+            name = CLOCK_CHIPS[self._read_int8()][0]
+            registers = []
+            for _ in range(self._read_int8()):
+                address, value, profile = self._read(3)
+                registers.append((address, value, bool(profile)))
+            chips[name] = registers
+        return chips
+
+    def get_rtio_topology(self, destination=0):
+        """Returns the RTIO topology of the gateware of ``destination``, as
+        parsed by :func:`parse_rtio_topology`, or None if the gateware does
+        not describe it."""
+        self._write_header(Request.GetRtioTopology)
+        self._write_int8(destination)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            return None
+        elif ty == Reply.Error:
+            raise IOError("Cannot read the RTIO topology of destination {}; "
+                          "see the core device log".format(destination))
+        elif ty != Reply.RtioTopology:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.RtioTopology))
+        return parse_rtio_topology(self._read_string())
+
+    def get_eem_presence(self):
+        """Returns the EEM connectors of Kasli as probed at boot, as a list of
+        dictionaries with the ``eem`` number, the type of the peripheral of
+        the gateware on it (``expected``, or None), whether a card answered
+        on the I2C bus of the connector (``detected``, or None if the
+        connector was not probed, e.g. the second EEM of a peripheral), and
+        the board ``name`` in the EEPROM of the card (or None). Returns None
+        if the core device has no EEM connectors."""
+        self._write_header(Request.GetEemPresence)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            return None
+        elif ty != Reply.EemPresence:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.EemPresence))
+        eems = []
+        for _ in range(self._read_int8()):
+            eem = self._read_int8()
+            expected = self._read_string()
+            detected = self._read_int8()
+            name = self._read_string()
+            eems.append({
+                "eem": eem,
+                "expected": expected or None,
+                "detected": None if detected == 2 else bool(detected),
+                "name": name or None,
+            })
+        return eems
+
+    @_large_reply
+    def get_audit_log(self):
+        """Returns the audit log of the core device, which records the
+        connections to its session, mgmt and moninj ports and the mgmt
+        commands that change its state, such as reboots and config writes,
+        as a tuple of the number of entries dropped since boot to make room
+        for newer ones, and of the entries, oldest first.
+
+        Each entry is a dictionary with its ``sequence`` number, its
+        ``timestamp_ms`` (uptime of the core device) and ``unix_time``
+        (seconds since the Unix epoch, or None if the wall clock of the core
+        device was not set), the ``service``, the ``event`` (``connected``,
+        ``disconnected`` or ``command``), the ``peer`` address, the
+        ``duration_ms`` of the connection for disconnections, and the
+        ``detail`` of the event. The command that restarted the core device
+        is kept across the restart, with a ``timestamp_ms`` of 0."""
+        self._write_header(Request.GetAuditLog)
+        self._read_expect(Reply.AuditLog)
+        dropped = self._read_int32() & 0xffffffff
+        entries = []
+        for _ in range(self._read_int32()):
+            sequence = self._read_int32() & 0xffffffff
+            (timestamp_ms, ) = struct.unpack(self.endian + "Q", self._read(8))
+            unix_time = None
+            if self._read_bool():
+                (unix_us, ) = struct.unpack(self.endian + "Q", self._read(8))
+                unix_time = unix_us/1e6
+            service = self._read_int8()
+            event = self._read_int8()
+            peer = self._read_string()
+            (duration_ms, ) = struct.unpack(self.endian + "Q", self._read(8))
+            detail = self._read_string()
+            entries.append({
+                "sequence": sequence,
+                "timestamp_ms": timestamp_ms,
+                "unix_time": unix_time,
+                "service": AUDIT_SERVICES[service]
+                           if service < len(AUDIT_SERVICES) else service,
+                "event": AUDIT_EVENTS[event]
+                         if event < len(AUDIT_EVENTS) else event,
+                "peer": peer,
+                "duration_ms": duration_ms,
+                "detail": detail,
+            })
+        return dropped, entries
+
+    @_large_reply
+    def get_kernel_run_stats(self):
+        """Returns the statistics of the last kernel runs, as a tuple of the
+        number of runs dropped since boot to make room for newer ones, and of
+        the runs, oldest first.
+
+        Each run is a dictionary with its ``sequence`` number, its
+        ``started_ms`` (uptime of the core device) and ``unix_time`` (seconds
+        since the Unix epoch, or None if the wall clock of the core device was
+        not set), its ``source`` (``"host"`` or the config key of a kernel
+        run from flash, e.g. ``"idle_kernel"``), its ``duration`` in seconds,
+        its ``outcome`` (``"finished"``, ``"exception"`` or ``"stopped"``,
+        e.g. by a watchdog or because the host connection was lost), the
+        number of ``output_events`` written by the kernel (not counting DMA),
+        their minimum slack ``min_slack_mu`` in machine units (None if there
+        was none, or if the gateware does not measure it) and the number of
+        ``rtio_errors`` counted during the run (see
+        :meth:`get_rtio_error_counts`)."""
+        self._write_header(Request.GetKernelRunStats)
+        self._read_expect(Reply.KernelRunStats)
+        dropped = self._read_int32() & 0xffffffff
+        runs = []
+        for _ in range(self._read_int32()):
+            sequence = self._read_int32() & 0xffffffff
+            (started_ms, ) = struct.unpack(self.endian + "Q", self._read(8))
+            unix_time = None
+            if self._read_bool():
+                (unix_us, ) = struct.unpack(self.endian + "Q", self._read(8))
+                unix_time = unix_us/1e6
+            source = self._read_string()
+            (duration_us, ) = struct.unpack(self.endian + "Q", self._read(8))
+            outcome = self._read_int8()
+            output_events = self._read_int32() & 0xffffffff
+            min_slack_mu = None
+            if self._read_bool():
+                (min_slack_mu, ) = struct.unpack(self.endian + "q", self._read(8))
+            rtio_errors = self._read_int32() & 0xffffffff
+            runs.append({
+                "sequence": sequence,
+                "started_ms": started_ms,
+                "unix_time": unix_time,
+                "source": source,
+                "duration": duration_us/1e6,
+                "outcome": KERNEL_RUN_OUTCOMES[outcome]
+                           if outcome < len(KERNEL_RUN_OUTCOMES) else outcome,
+                "output_events": output_events,
+                "min_slack_mu": min_slack_mu,
+                "rtio_errors": rtio_errors,
+            })
+        return dropped, runs
+
+    def get_moninj_limits(self):
+        """Returns the rate limits of moninj on the core device, set by the
+        ``moninj_request_rate`` and ``moninj_probe_rate`` config keys and
+        applied to new connections, as a dictionary with the requests per
+        second allowed per connection (``request_rate``), the reads of the
+        probes of a channel per second allowed per connection
+        (``probe_rate``), both 0 if not limited, and the number of requests
+        held back (``requests_throttled``) and of reads postponed
+        (``reads_postponed``) since boot. Returns None if the core device
+        has no moninj."""
+        self._write_header(Request.GetMoninjLimits)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            return None
+        elif ty != Reply.MoninjLimits:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.MoninjLimits))
+        return {
+            "request_rate": self._read_int32(),
+            "probe_rate": self._read_int32(),
+            "requests_throttled": self._read_int32(),
+            "reads_postponed": self._read_int32(),
+        }
+
+    def get_drtio_latency(self):
+        """Returns a list with a dictionary for each DRTIO satellite of the
+        routing table of the core device, giving its ``destination`` and the
+        one-way latency of the RT link to it in seconds: ``measured`` when
+        the link came up, for the satellites on a link of the master, and
+        ``calibrated``, the calibration stored in the config of the core
+        device. Latencies that are not known are None."""
+        self._write_header(Request.GetDrtioLatency)
+        self._read_expect(Reply.DrtioLatency)
+        satellites = []
+        for _ in range(self._read_int8()):
+            satellite = {"destination": self._read_int8()}
+            for name in "measured", "calibrated":
+                satellite[name] = None
+                if self._read_bool():
+                    satellite[name] = (self._read_int32() & 0xffffffff)*1e-12
+            satellites.append(satellite)
+        return satellites
+
+    def set_drtio_latency(self, destination, latency=None):
+        """Stores ``latency``, in seconds, as the latency calibration of
+        DRTIO satellite ``destination`` in the config of the core device, or
+        the latency measured if None."""
+        self._write_header(Request.SetDrtioLatency)
+        self._write_int8(destination)
+        if latency is None:
+            self._write_int8(0)
+        else:
+            self._write_int8(1)
+            self._write_int32(round(latency*1e12))
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Cannot set the latency calibration of destination "
+                          "{}; see the core device log".format(destination))
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def wrpll_stats(self, destination):
+        self._write_header(Request.WrpllStats)
+        self._write_int8(destination)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("WRPLL statistics unavailable for destination {}"
+                          .format(destination))
+        elif ty != Reply.WrpllStats:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.WrpllStats))
+        return {
+            "locked": bool(self._read(1)[0]),
+            "lock_time_ms": self._read_int32(),
+            "unlock_count": self._read_int32(),
+            "samples": self._read_int32(),
+            "helper_rms_tags": self._read_int32()/1000,
+            "main_rms_tags": self._read_int32()/1000,
+        }
+
+    def get_rtio_frequency(self):
+        """Returns the RTIO clock frequency measured by the core device
+        against its system clock, in Hz."""
+        self._write_header(Request.GetRtioFrequency)
+        self._read_expect(Reply.RtioFrequency)
+        return self._read_int32() & 0xffffffff
+
+    def get_rtio_counter(self):
+        """Latches the RTIO counter of the core device and returns a
+        dictionary with its value in machine units (``counter``), the uptime
+        at which it was latched in microseconds (``uptime_us``) and the wall
+        clock time then in seconds since the Unix epoch (``time``, ``None``
+        if the core device has not set its clock, e.g. with SNTP).
+
+        Two readings correlate the RTIO timeline with the system time."""
+        self._write_header(Request.GetRtioCounter)
+        self._read_expect(Reply.RtioCounter)
+        counter, uptime_us = struct.unpack(self.endian + "QQ", self._read(16))
+        if self._read_bool():
+            (time_us, ) = struct.unpack(self.endian + "Q", self._read(8))
+            time = time_us/1e6
+        else:
+            time = None
+        return {"counter": counter, "uptime_us": uptime_us, "time": time}
+
     def debug_allocator(self):
         self._write_header(Request.DebugAllocator)
+
+    def allocator_stats(self):
+        """Returns a dictionary summarizing the heap of the communications
+        CPU, in bytes."""
+        self._write_header(Request.AllocatorStats)
+        self._read_expect(Reply.AllocatorStats)
+        return {
+            "total": self._read_int32(),
+            "used": self._read_int32(),
+            "largest_free": self._read_int32(),
+            "free_blocks": self._read_int32(),
+        }
+
+    def get_health_snapshot(self):
+        """Returns a dictionary describing the state of the core device:
+        uptime, wall clock time, number of boots and uptime over all of
+        them (``None`` if the core device has no flash to keep them), versions, RTIO clock, heap usage, FPGA
+        sensors, DRTIO links (see :meth:`get_drtio_link_stats`), SFP
+        transceiver diagnostics and active alarms, power rails monitored with
+        INA2xx chips (last reading, lowest voltage since boot and number of
+        brownouts), and the SFP ports of the DRTIO satellites that are up.
+
+        The wall clock time is in seconds since the Unix epoch, or ``None``
+        if the core device has not synchronized it with an SNTP server.
+
+        Temperatures are in degrees Celsius, voltages in volts, currents in
+        amperes and optical powers in watts. FPGA sensors, the diagnostics
+        of an SFP port and the reading of a power rail are ``None`` when they
+        are not available."""
+        self._write_header(Request.GetHealthSnapshot)
+        self._read_expect(Reply.HealthSnapshot)
+        (uptime_ms, ) = struct.unpack(self.endian + "Q", self._read(8))
+        if self._read_bool():
+            (time_us, ) = struct.unpack(self.endian + "Q", self._read(8))
+            time = time_us/1e6
+        else:
+            time = None
+        if self._read_bool():
+            boot_count = self._read_int32() & 0xffffffff
+            total_uptime = self._read_int32() & 0xffffffff
+        else:
+            boot_count = total_uptime = None
+        snapshot = {
+            "uptime": uptime_ms/1000,
+            "time": time,
+            "boot_count": boot_count,
+            "total_uptime": total_uptime,
+            "firmware_version": self._read_string(),
+            "gateware_version": self._read_string(),
+            "rtio_clock_ok": self._read_bool(),
+            "rtio_frequency": self._read_int32() & 0xffffffff,
+            "heap": {
+                "total": self._read_int32(),
+                "used": self._read_int32(),
+                "largest_free": self._read_int32(),
+            },
+        }
+
+        snapshot["fpga"] = self._read_fpga_sensors()
+        snapshot["drtio_links"] = self._read_drtio_link_stats()
+
+        snapshot["sfp"] = [self._read_sfp() for _ in range(self._read_int8())]
+
+        power = []
+        for _ in range(self._read_int8()):
+            rail = {"name": self._read_string()}
+            if self._read_bool():
+                rail["voltage"] = (self._read_int32() & 0xffffffff)*1e-3
+                rail["current"] = self._read_int32()*1e-6
+            else:
+                rail["voltage"] = None
+                rail["current"] = None
+            rail["min_voltage"] = (self._read_int32() & 0xffffffff)*1e-3
+            rail["brownouts"] = self._read_int32() & 0xffffffff
+            power.append(rail)
+        snapshot["power"] = power
+
+        satellite_sfp = []
+        for _ in range(self._read_int8()):
+            destination = self._read_int8()
+            port = self._read_int8()
+            satellite_sfp.append({
+                "destination": destination,
+                "port": port,
+                "diagnostics": self._read_sfp()
+            })
+        snapshot["satellite_sfp"] = satellite_sfp
+        return snapshot
+
+    def get_health_records(self):
+        """Returns the state of the core device as a list of health
+        records (see :func:`parse_health_records`): ``system`` (uptime, wall
+        clock time, boots, versions, RTIO clock), ``heap``, ``fpga``
+        sensors, ``sfp`` diagnostics, ``drtio_link`` statistics,
+        ``power_rail`` readings and ``satellite_sfp`` diagnostics. Unlike
+        :meth:`get_health_snapshot`, the records describe themselves, so
+        that records added by newer firmware are decoded too."""
+        self._write_header(Request.GetHealthRecords)
+        self._read_expect(Reply.HealthRecords)
+        return parse_health_records(self._read_bytes())
+
+    def _read_fpga_sensors(self):
+        if not self._read_bool():
+            return None
+        # XADC codes are 12-bit; supplies are measured on a 3V scale.
+        return {
+            "temperature": self._read_uint16()*503.975/4096 - 273.15,
+            "vccint": self._read_uint16()*3/4096,
+            "vccaux": self._read_uint16()*3/4096,
+            "vccbram": self._read_uint16()*3/4096,
+        }
+
+    @_large_reply
+    def get_sensor_history(self):
+        """Returns the samples of the FPGA sensors and of the optical power
+        of the SFP ports that the core device keeps over the span set by the
+        ``sensor_history_span`` configuration key (in minutes, 24 hours by
+        default), one every ``sensor_history_interval`` seconds (60 by
+        default, 0 to disable the history), oldest first.
+
+        Each sample is a dictionary with the uptime at which it was taken
+        (``timestamp``, in seconds), the ``fpga`` sensors as in
+        :meth:`get_health_snapshot`, and for each SFP port a dictionary with
+        its ``tx_power`` and ``rx_power`` in watts (``sfp``). Values are
+        ``None`` when they could not be read, e.g. SFP ports while a kernel
+        was running."""
+        self._write_header(Request.GetSensorHistory)
+        self._read_expect(Reply.SensorHistory)
+        self._read_int32()  # interval
+        sfp_ports = self._read_int8()
+        samples = []
+        for _ in range(self._read_int32()):
+            (timestamp_ms, ) = struct.unpack(self.endian + "Q", self._read(8))
+            fpga = self._read_fpga_sensors()
+            sfp = []
+            for _ in range(sfp_ports):
+                if self._read_bool():
+                    # SFF-8472 unit: 0.1 uW.
+                    sfp.append({
+                        "tx_power": self._read_uint16()*0.1e-6,
+                        "rx_power": self._read_uint16()*0.1e-6,
+                    })
+                else:
+                    sfp.append(None)
+            samples.append({
+                "timestamp": timestamp_ms/1000,
+                "fpga": fpga,
+                "sfp": sfp,
+            })
+        return samples
+
+    def _inject_fault(self, fault, *fields):
+        self._write_header(Request.InjectFault)
+        self._write_int8(fault)
+        for write, value in fields:
+            write(value)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Fault injection is not built into the core device "
+                          "gateware (build with --with-fault-injection)")
+        elif ty == Reply.Error:
+            raise IOError("Cannot inject fault; see the core device log")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def inject_sfp_alarms(self, port, alarms):
+        """Makes SFP port ``port`` report the ``alarms`` (a list of names
+        as returned by :meth:`get_health_snapshot`) in addition to its own,
+        if its transceiver provides diagnostics. An empty list stops
+        injecting alarms.
+
+        Fault injection is only available in gateware built with
+        ``--with-fault-injection``, and exercises the monitoring of the core
+        device (log, alerts, status LED, health snapshot) as a real fault
+        would."""
+        flags = 0
+        for alarm in alarms:
+            flags |= 1 << (15 - self._sfp_alarms.index(alarm))
+        self._inject_fault(1, (self._write_int8, port),
+                           (self._write_int16, flags))
+
+    def inject_drtio_link_drop(self, linkno):
+        """Makes the core device see DRTIO link ``linkno`` go down once,
+        after which the link is brought up again as usual. See
+        :meth:`inject_sfp_alarms`."""
+        self._inject_fault(2, (self._write_int8, linkno))
+
+    def inject_fpga_temperature(self, temperature, duration):
+        """Replaces the FPGA die temperature read by the core device with
+        ``temperature`` (in degrees Celsius) for ``duration`` seconds. The
+        ``fpga_overtemp_action`` configured on the core device is taken if
+        the temperature is critical. See :meth:`inject_sfp_alarms`."""
+        self._inject_fault(3,
+                           (self._write_int32, round(temperature*1000)),
+                           (self._write_int32, round(duration)))
+
+    def clear_faults(self):
+        """Stops injecting all faults."""
+        self._inject_fault(0)
+
+    # SFF-8472 alarm flags, from bit 15 down.
+    _sfp_alarms = [
+        "temperature high", "temperature low",
+        "voltage high", "voltage low",
+        "TX bias high", "TX bias low",
+        "TX power high", "TX power low",
+        "RX power high", "RX power low"
+    ]
+
+    def _read_sfp(self):
+        if not self._read_bool():
+            return None
+        # SFF-8472 units: 1/256 degC, 100 uV, 2 uA and 0.1 uW.
+        sfp = {
+            "temperature": self._read_int16()/256,
+            "voltage": self._read_uint16()*100e-6,
+            "tx_bias": self._read_uint16()*2e-6,
+            "tx_power": self._read_uint16()*0.1e-6,
+            "rx_power": self._read_uint16()*0.1e-6,
+        }
+        alarms = self._read_uint16()
+        sfp["alarms"] = [name for i, name in enumerate(self._sfp_alarms)
+                         if alarms & (1 << (15 - i))]
+        return sfp
+
+    def _read_drtio_link_stats(self):
+        return [{
+            "up": self._read_bool(),
+            "crc_errors": self._read_int32() & 0xffffffff,
+            "aux_timeouts": self._read_int32() & 0xffffffff,
+            "link_drops": self._read_int32() & 0xffffffff,
+            "protocol_errors": self._read_int32() & 0xffffffff,
+            "remote_rtio_errors": self._read_int32() & 0xffffffff,
+        } for _ in range(self._read_int8())]
+
+    def get_drtio_link_stats(self):
+        """Returns, for each DRTIO link of the core device, a dictionary
+        with whether it is up and the counts since boot of the events that
+        indicate a marginal link: corrupted aux packets (``crc_errors``),
+        unanswered aux transactions (``aux_timeouts``), losses of the link
+        after it was initialized (``link_drops``), errors reported by the
+        DRTIO core (``protocol_errors``) and RTIO errors reported by the
+        destinations behind the link (``remote_rtio_errors``). Errors while
+        a link is being initialized are not counted."""
+        self._write_header(Request.GetDrtioLinkStats)
+        self._read_expect(Reply.DrtioLinkStats)
+        return self._read_drtio_link_stats()
+
+    def reload_routing_table(self):
+        """Applies the ``routing_table`` config entry without rebooting:
+        the core device sends the new table to the satellites and brings the
+        remote destinations up again through their new routes. The
+        destinations are briefly unreachable meanwhile."""
+        self._write_header(Request.ReloadRoutingTable)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Core device does not support DRTIO")
+        if ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def get_satellites(self):
+        """Returns a list with a dictionary for each DRTIO satellite of
+        the routing table of the core device, giving its ``destination``,
+        its ``route`` (the hops of the routing table), and, if it answered,
+        its ``firmware_version``, ``gateware_version``, ``variant`` and
+        ``serial`` (``None`` if the board has none). Satellites that did not
+        answer have ``None`` versions."""
+        self._write_header(Request.GetSatelliteIdents)
+        self._read_expect(Reply.SatelliteIdents)
+        satellites = []
+        for _ in range(self._read_int8()):
+            satellite = {
+                "destination": self._read_int8(),
+                "route": list(self._read_bytes()),
+                "firmware_version": None,
+                "gateware_version": None,
+                "variant": None,
+                "serial": None,
+            }
+            if self._read_bool():
+                satellite["firmware_version"] = self._read_string()
+                satellite["gateware_version"] = self._read_string()
+                satellite["variant"] = \
+                    satellite["gateware_version"].partition(";")[2] or None
+                if self._read_bool():
+                    satellite["serial"] = self._read(6).hex()
+            satellites.append(satellite)
+        return satellites
+
+    def _read_self_test_report(self):
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Core device has not run its self test since boot")
+        elif ty == Reply.Error:
+            raise IOError("Core device cannot run the self test, see its log")
+        elif ty != Reply.SelfTestReport:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.SelfTestReport))
+        (timestamp_ms, ) = struct.unpack(self.endian + "Q", self._read(8))
+        checks = []
+        for _ in range(self._read_int8()):
+            subsystem = self._read_string()
+            outcome = self._read_int8()
+            checks.append({
+                "subsystem": subsystem,
+                "outcome": SELF_TEST_OUTCOMES[outcome]
+                           if outcome < len(SELF_TEST_OUTCOMES) else outcome,
+                "detail": self._read_string(),
+            })
+        return {"timestamp_ms": timestamp_ms, "checks": checks}
+
+    def run_self_test(self):
+        """Runs the self test of the core device and returns its report: a
+        dictionary with the uptime at which it started (``timestamp_ms``)
+        and the list of ``checks``, each a dictionary with the
+        ``subsystem`` (e.g. ``"i2c0"``, ``"sfp1"``, ``"si5324"``,
+        ``"sdram"``, ``"flash"`` or ``"drtio2"``), the ``outcome``
+        (``"pass"``, ``"fail"`` or ``"skipped"``) and a ``detail`` string.
+
+        The I2C checks are skipped while a kernel is running, and DRTIO
+        transceivers are only tested in loopback when their link is down."""
+        self._write_header(Request.RunSelfTest)
+        return self._read_self_test_report()
+
+    def get_self_test_report(self):
+        """Returns the report of the last self test since boot, as
+        :meth:`run_self_test`, e.g. the one run at boot when the ``post``
+        configuration key is ``1``."""
+        self._write_header(Request.GetSelfTestReport)
+        return self._read_self_test_report()
+
+    def run_eem_self_test(self):
+        """Runs the self test of the Urukul, Sampler and Zotino cards and
+        returns its report, as :meth:`run_self_test`. The ``subsystem`` of
+        each check names the card by its type and first EEM connector, e.g.
+        ``"urukul1:att2"``, ``"sampler5:pgia0"`` or ``"zotino3:ch12"``, and
+        the Zotino channels wired to Sampler channels in the
+        ``eem_loopback`` configuration key are tested as e.g.
+        ``"zotino3:ch0>sampler5:ch0"``.
+
+        The cards must be initialized again by experiments afterwards. The
+        test is refused while a kernel is running, and by gateware without
+        the RTIO initiator of the comms CPU."""
+        self._write_header(Request.RunEemSelfTest)
+        return self._read_self_test_report()
+
+    def get_dds_sync_reports(self):
+        """Returns the results of the last SYNC_IN and IO_UPDATE alignment
+        of each AD9910 channel re-aligned by a kernel with
+        :meth:`artiq.coredevice.ad9910.AD9910.resync` since boot, as a list
+        of dictionaries with the RTIO ``channel`` of its SPI bus, its
+        ``chip_select``, the SYNC_IN delay (``in_delay``), the validation
+        ``window`` size, the ``io_update_delay`` and the uptime at which it
+        was recorded (``timestamp_ms``)."""
+        self._write_header(Request.GetDdsSyncReports)
+        self._read_expect(Reply.DdsSyncReports)
+        reports = []
+        for _ in range(self._read_int8()):
+            channel, chip_select, in_delay, window, io_update_delay, \
+                timestamp_ms = struct.unpack(self.endian + "LBBBBQ",
+                                             self._read(16))
+            reports.append({
+                "channel": channel,
+                "chip_select": chip_select,
+                "in_delay": in_delay,
+                "window": window,
+                "io_update_delay": io_update_delay,
+                "timestamp_ms": timestamp_ms,
+            })
+        return reports
+
+    def get_board_identity(self):
+        """Returns a dictionary with the ``serial`` number of the core device
+        (the EUI-48 of its EEPROM, in hexadecimal) and, if the EEPROM holds
+        Sinara identification data, the board ``name``, ``board_id``,
+        hardware revision (``hw_rev``), ``variant`` and ``vendor`` (``None``
+        otherwise)."""
+        self._write_header(Request.GetBoardIdentity)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Core device has no identification EEPROM")
+        elif ty != Reply.BoardIdentity:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.BoardIdentity))
+        identity = {
+            "serial": self._read(6).hex(),
+            "name": None,
+            "board_id": None,
+            "hw_rev": None,
+            "variant": None,
+            "vendor": None,
+        }
+        if self._read_bool():
+            identity["name"] = self._read_string()
+            identity["board_id"] = self._read_uint16()
+            identity["hw_rev"] = "v{}.{}".format(self._read_int8(), self._read_int8())
+            identity["variant"] = self._read_int8()
+            identity["vendor"] = self._read_int8()
+        return identity
+
+    def get_compatibility(self):
+        """Returns a dictionary with the compatibility descriptors that the
+        ``firmware`` was built for and that the ``gateware`` holds (0 for
+        gateware without one), and whether they are ``compatible``. On a
+        mismatch, the runtime only starts networking and management."""
+        self._write_header(Request.GetCompatibility)
+        self._read_expect(Reply.Compatibility)
+        firmware = self._read_int32() & 0xffffffff
+        gateware = self._read_int32() & 0xffffffff
+        return {
+            "firmware": firmware,
+            "gateware": gateware,
+            "compatible": gateware == 0 or gateware == firmware,
+        }
+
+    def get_features(self):
+        """Returns the features of the core device, as a dictionary with
+        the ``flags`` (a :class:`~artiq.coredevice.comm.Feature`), the number
+        of ``sfp_ports`` and the ``analyzer_size`` of the trace buffer in
+        bytes (0 without an analyzer)."""
+        self._write_header(Request.GetFeatures)
+        self._read_expect(Reply.Features)
+        return decode_features(self._read_int32() & 0xffffffff)
+
+    def get_grabber_stats(self):
+        """Returns a list with a dictionary for each grabber of the core
+        device, giving whether it is ``aligned`` to the Camera Link clock,
+        the ``frame_size`` (width, height) of the last frame, and counts of
+        ``frames``, ``dropped_frames`` (with a size different from the
+        previous frame, e.g. because of a loss of sync) and
+        ``clock_glitches`` of the Camera Link clock while aligned, since
+        boot."""
+        self._write_header(Request.GetGrabberStats)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Core device has no grabbers")
+        elif ty != Reply.GrabberStats:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.GrabberStats))
+        return [{
+            "aligned": self._read_bool(),
+            "frame_size": (self._read_uint16(), self._read_uint16()),
+            "frames": self._read_int32() & 0xffffffff,
+            "dropped_frames": self._read_int32() & 0xffffffff,
+            "clock_glitches": self._read_int32() & 0xffffffff,
+        } for _ in range(self._read_int8())]
+
+    def get_tsc_stats(self):
+        """Returns a list with a dictionary for each DRTIO satellite of
+        the routing table of the core device, giving its ``destination``
+        and, if it answered, the number of ``syncs`` of its timestamp
+        counter with the master since boot, the offset from the time of the
+        master found at the last one and the largest in magnitude
+        (``offset`` and ``max_offset``), in coarse RTIO cycles, the time
+        elapsed between the last load of the counter and the last sync
+        (``interval_ms``), and the phase of the RTIO clock of the satellite
+        after its recovered clock at the last sync (``phase_fs``), in
+        femtoseconds, or ``None`` if the satellite does not measure it. The
+        statistics of satellites that did not answer are ``None``."""
+        self._write_header(Request.GetTscStats)
+        self._read_expect(Reply.TscStats)
+        satellites = []
+        for _ in range(self._read_int8()):
+            satellite = {
+                "destination": self._read_int8(),
+                "syncs": None,
+                "offset": None,
+                "max_offset": None,
+                "interval_ms": None,
+                "phase_fs": None,
+            }
+            if self._read_bool():
+                satellite["syncs"] = self._read_int32() & 0xffffffff
+                satellite["offset"] = self._read_int32()
+                satellite["max_offset"] = self._read_int32() & 0xffffffff
+                satellite["interval_ms"] = self._read_int32() & 0xffffffff
+                if self._read_bool():
+                    satellite["phase_fs"] = self._read_int32()
+            satellites.append(satellite)
+        return satellites
+
+    def get_sed_stats(self, clear=False):
+        """Returns a dictionary of the counters of the SED (the dispatcher of
+        RTIO output events over FIFO lanes) of the core device since boot or
+        the last clear: for each lane, the number of events written into it
+        (``lane_writes``) and of times it became full (``lane_full``), and
+        the numbers of ``collisions``, ``busy_errors`` and
+        ``sequence_errors``. Clears the counters if ``clear`` is set."""
+        self._write_header(Request.GetSedStats)
+        self._write_int8(clear)
+        self._read_expect(Reply.SedStats)
+        lane_writes = []
+        lane_full = []
+        for _ in range(self._read_int8()):
+            lane_writes.append(self._read_int32() & 0xffffffff)
+            lane_full.append(self._read_int32() & 0xffffffff)
+        return {
+            "lane_writes": lane_writes,
+            "lane_full": lane_full,
+            "collisions": self._read_int32() & 0xffffffff,
+            "busy_errors": self._read_int32() & 0xffffffff,
+            "sequence_errors": self._read_int32() & 0xffffffff,
+        }
+
+    def get_channel_events(self, clear=False):
+        """Returns the number of consecutive local RTIO channels in each
+        group, and a list with the number of output events accepted into the
+        SED lanes for each group since boot or the last clear. Events
+        rejected by an underflow are not counted, nor are the events sent to
+        DRTIO satellites. Clears the counters if ``clear`` is set."""
+        self._write_header(Request.GetChannelEvents)
+        self._write_int8(clear)
+        self._read_expect(Reply.ChannelEvents)
+        group_size = self._read_uint16()
+        events = [self._read_int32() & 0xffffffff
+                  for _ in range(self._read_int32())]
+        return group_size, events
+
+    def get_thread_stats(self, clear=False):
+        """Returns the time in microseconds over which the statistics of the
+        comms CPU threads were accumulated (since boot or the last clear),
+        and a list with, for each thread name, a dictionary with the
+        ``name``, the number of ``threads`` running under it, the number of
+        ``wakeups``, the total ``run_time_us`` and the ``longest_run_us``
+        before a thread suspended itself. The time not spent in threads goes
+        to networking and idling. Clears the statistics if ``clear`` is
+        set."""
+        self._write_header(Request.GetThreadStats)
+        self._write_int8(clear)
+        self._read_expect(Reply.ThreadStats)
+        (elapsed_us, ) = struct.unpack(self.endian + "Q", self._read(8))
+        threads = []
+        for _ in range(self._read_int32()):
+            name = self._read_string()
+            count = self._read_int32()
+            wakeups, run_time_us = struct.unpack(self.endian + "QQ", self._read(16))
+            longest_run_us = self._read_int32() & 0xffffffff
+            threads.append({
+                "name": name,
+                "threads": count,
+                "wakeups": wakeups,
+                "run_time_us": run_time_us,
+                "longest_run_us": longest_run_us,
+            })
+        return elapsed_us, threads
+
+    def drtio_eye_scan(self, linkno, horizontal_step=4, vertical_step=8,
+                       prescale=0):
+        """Scans the eye of the transceiver of DRTIO link ``linkno``, by
+        sweeping the offset sampler over the eye in steps of the given
+        sizes, while the link keeps running. Each point is sampled
+        2**(1 + ``prescale``) times longer, which lowers the smallest bit
+        error ratio measured.
+
+        Returns a list with a dictionary for each point, giving the
+        ``horizontal`` offset in UI (from -0.5 to 0.5), the ``vertical``
+        offset in steps of the sampler (from -127 to 127), the number of
+        ``errors`` and of compared ``bits``, and the bit error ratio
+        ``ber``. Where no error was counted, ``ber`` is 0 and the ratio is
+        below 1/``bits``.
+
+        The link must be up, and the gateware built with GTP transceivers."""
+        self._write_header(Request.DrtioEyeScan)
+        self._write_int8(linkno)
+        self._write_int8(horizontal_step)
+        self._write_int8(vertical_step)
+        self._write_int8(prescale)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Gateware does not support eye scans")
+        elif ty == Reply.Error:
+            raise IOError("Cannot scan the eye; see the core device log")
+        elif ty != Reply.EyeScan:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.EyeScan))
+        ui_steps = self._read_uint16()
+        bits_per_sample = self._read_int32() & 0xffffffff
+        points = []
+        for _ in range(self._read_int32()):
+            horizontal, vertical, errors, samples = struct.unpack(
+                self.endian + "hhHH", self._read(8))
+            bits = samples*bits_per_sample
+            points.append({
+                "horizontal": horizontal/ui_steps,
+                "vertical": vertical,
+                "errors": errors,
+                "bits": bits,
+                "ber": errors/bits if bits else 0.0,
+            })
+        return points
+
+    def get_rtio_error_counts(self, clear=False):
+        """Returns a list with, for each RTIO destination that had errors
+        since boot or the last clear, a dictionary with the ``destination``,
+        the numbers of ``underflows``, input ``overflows`` and errors
+        because the destination was ``unreachable`` that ended a kernel (the
+        errors caught by kernels are not counted), the numbers of
+        asynchronous ``collisions``, ``busy_errors`` and
+        ``sequence_errors``, and the last channel involved
+        (``last_channel``, including the destination in its upper bits).
+
+        ``satellite`` is a dictionary with the errors counted by the DRTIO
+        satellite of the destination itself, all 0 if the destination is not
+        a satellite or its link is down: write ``underflows`` and
+        ``overflows``, ``buffer_space_timeouts``, ``protocol_errors`` (DRTIO
+        packets of an unknown type or truncated) and ``tsc_resyncs`` (TSC
+        synchronizations that corrected the TSC of the satellite).
+
+        Clears the counters, including those of the satellites, if ``clear``
+        is set."""
+        self._write_header(Request.GetRtioErrorCounts)
+        self._write_int8(clear)
+        self._read_expect(Reply.RtioErrorCounts)
+        counts = []
+        for _ in range(self._read_int32()):
+            destination = self._read_int8()
+            values = struct.unpack(self.endian + "7L", self._read(28))
+            satellite = struct.unpack(self.endian + "5L", self._read(20))
+            counts.append(dict(zip(
+                ["underflows", "overflows", "unreachable", "collisions",
+                 "busy_errors", "sequence_errors", "last_channel"], values),
+                destination=destination,
+                satellite=dict(zip(
+                    ["underflows", "overflows", "buffer_space_timeouts",
+                     "protocol_errors", "tsc_resyncs"], satellite))))
+        return counts
+
+    def get_cache_entries(self):
+        """Returns a list with a dictionary for each entry of the kernel
+        cache of the core device, giving its ``key``, its ``size`` in bytes
+        and whether it is ``borrowed`` by the running kernel."""
+        self._write_header(Request.GetCacheEntries)
+        self._read_expect(Reply.CacheEntries)
+        entries = []
+        for _ in range(self._read_int32()):
+            entries.append({
+                "key": self._read_string(),
+                "size": self._read_int32() & 0xffffffff,
+                "borrowed": self._read_bool(),
+            })
+        return entries
+
+    def remove_cache_entry(self, key):
+        self._write_header(Request.RemoveCacheEntry)
+        self._write_string(key)
+        self._read_expect(Reply.Success)
+
+    def clear_cache(self):
+        self._write_header(Request.ClearCache)
+        self._read_expect(Reply.Success)
+
+    def get_net_stats(self):
+        """Returns a dictionary of the Ethernet counters of the core device
+        since boot, and the list of its TCP sockets."""
+        self._write_header(Request.GetNetStats)
+        self._read_expect(Reply.NetStats)
+        stats = {
+            "rx_packets": self._read_int32() & 0xffffffff,
+            "rx_bytes": struct.unpack(self.endian + "Q", self._read(8))[0],
+            "tx_packets": self._read_int32() & 0xffffffff,
+            "tx_bytes": struct.unpack(self.endian + "Q", self._read(8))[0],
+            "rx_preamble_errors": self._read_int32() & 0xffffffff,
+            "rx_crc_errors": self._read_int32() & 0xffffffff,
+            "rx_dropped": self._read_int32() & 0xffffffff,
+            "network_errors": self._read_int32() & 0xffffffff,
+            "tcp_retransmissions": self._read_int32() & 0xffffffff,
+        }
+        stats["sockets"] = [{
+            "state": self._read_string(),
+            "local": self._read_string(),
+            "remote": self._read_string(),
+            "recv_queue": self._read_int32(),
+            "send_queue": self._read_int32(),
+        } for _ in range(self._read_int8())]
+        return stats
+
+    def _write_analyzer_trigger(self, trigger):
+        if trigger is None:
+            self._write_int8(TriggerMode.none.value)
+            self._write_int32(0)
+        elif trigger == "exception":
+            self._write_int8(TriggerMode.exception.value)
+            self._write_int32(0)
+        else:
+            self._write_int8(TriggerMode.channel.value)
+            self._write_int32(trigger)
+
+    def _read_analyzer_trigger(self):
+        mode = TriggerMode(self._read_int8())
+        channel = self._read_int32()
+        if mode == TriggerMode.channel:
+            return channel
+        elif mode == TriggerMode.exception:
+            return "exception"
+        else:
+            return None
+
+    def set_analyzer_trigger(self, start=None, stop=None):
+        """Sets the conditions that start and stop the RTIO analyzer
+        recording. Each is ``None`` (record from the beginning, or until the
+        trace is read), an RTIO channel number (any event on that channel),
+        or ``"exception"`` (any RTIO exception). The triggers are rearmed
+        whenever a new capture begins, i.e. after each analyzer dump."""
+        self._write_header(Request.SetAnalyzerTrigger)
+        self._write_analyzer_trigger(start)
+        self._write_analyzer_trigger(stop)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Core device has no RTIO analyzer")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def get_analyzer_trigger(self):
+        """Returns a dictionary with the ``start`` and ``stop`` triggers of
+        the RTIO analyzer (see :meth:`set_analyzer_trigger`), and whether
+        they have fired during the current capture (``started`` and
+        ``stopped``)."""
+        self._write_header(Request.GetAnalyzerTrigger)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Core device has no RTIO analyzer")
+        elif ty != Reply.AnalyzerTrigger:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.AnalyzerTrigger))
+        return {
+            "start": self._read_analyzer_trigger(),
+            "stop": self._read_analyzer_trigger(),
+            "started": self._read_bool(),
+            "stopped": self._read_bool(),
+        }
+
+    def set_analyzer_filter(self, channels=(), exclude=True):
+        """Makes the RTIO analyzer record the input and output events of
+        the given RTIO channels only, or with ``exclude``, of all channels
+        but them. RTIO exceptions are always recorded. The default records
+        all channels."""
+        self._write_header(Request.SetAnalyzerFilter)
+        self._write_int8(exclude)
+        self._write_int8(len(channels))
+        for channel in channels:
+            self._write_int32(channel)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Core device has no RTIO analyzer")
+        elif ty == Reply.Error:
+            raise IOError("Too many channels for the analyzer filter")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def get_analyzer_filter(self):
+        """Returns the channels of the RTIO analyzer filter and whether they
+        are excluded (see :meth:`set_analyzer_filter`)."""
+        self._write_header(Request.GetAnalyzerFilter)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Core device has no RTIO analyzer")
+        elif ty != Reply.AnalyzerFilter:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.AnalyzerFilter))
+        exclude = self._read_bool()
+        channels = [self._read_int32() for _ in range(self._read_int8())]
+        return channels, exclude
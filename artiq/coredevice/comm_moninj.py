@@ -1,6 +1,7 @@
 import asyncio
 import logging
 import struct
+import time
 from enum import Enum
 
 
@@ -21,13 +22,42 @@ class TTLOverride(Enum):
     oe = 2
 
 
+_UDP_MAGIC = b"AMNJ"
+_UDP_RESEND_INTERVAL = 0.1
+_UDP_KEEPALIVE_INTERVAL = 2.
+_UDP_TIMEOUT = 10.
+
+
+class _DatagramProtocol(asyncio.DatagramProtocol):
+    def __init__(self, comm):
+        self.comm = comm
+
+    def datagram_received(self, data, addr):
+        self.comm._datagram_received(data)
+
+    def error_received(self, exc):
+        logger.debug("moninj datagram error: %s", exc)
+
+
 class CommMonInj:
     def __init__(self, monitor_cb, injection_status_cb, disconnect_cb=None):
         self.monitor_cb = monitor_cb
         self.injection_status_cb = injection_status_cb
         self.disconnect_cb = disconnect_cb
+        self._transport = None
+
+    async def connect(self, host, port=1383, udp=False):
+        """Connects to the moninj service of the core device, over TCP or,
+        if ``udp`` is set, over UDP (which must be enabled on the core device
+        by setting the ``moninj_udp`` config key to ``1``).
 
-    async def connect(self, host, port=1383):
+        Over UDP, lost datagrams do not hold up the following ones: requests
+        are resent until the core device acknowledges them, and when updates
+        from the core device are lost, all the monitored values are sent
+        again."""
+        if udp:
+            await self._connect_udp(host, port)
+            return
         self._reader, self._writer = await asyncio.open_connection(host, port)
         try:
             self._writer.write(b"ARTIQ moninj\n")
@@ -46,6 +76,31 @@ class CommMonInj:
             del self._writer
             raise
 
+    async def _connect_udp(self, host, port):
+        loop = asyncio.get_event_loop()
+        self._transport, _ = await loop.create_datagram_endpoint(
+            lambda: _DatagramProtocol(self), remote_addr=(host, port))
+        self.endian = ">"
+        self._sequence = 0
+        self._received = None
+        self._unacked = []
+        self._outgoing = b""
+        self._last_datagram = None
+        self._last_sent = 0.
+        self._last_reply = time.monotonic()
+        self._connected = asyncio.Event()
+        self._receive_task = asyncio.ensure_future(self._resend_cr())
+        try:
+            # An empty datagram registers us.
+            self._flush()
+            await asyncio.wait_for(self._connected.wait(), _UDP_TIMEOUT)
+        except:
+            self.disconnect_cb = None
+            self._receive_task.cancel()
+            self._transport.close()
+            self._transport = None
+            raise
+
     async def close(self):
         self.disconnect_cb = None
         try:
@@ -55,25 +110,137 @@ class CommMonInj:
             except asyncio.CancelledError:
                 pass
         finally:
-            self._writer.close()
-            del self._reader
-            del self._writer
+            if self._transport is not None:
+                self._transport.close()
+                self._transport = None
+            else:
+                self._writer.close()
+                del self._reader
+                del self._writer
+
+    def _write(self, packet):
+        if self._transport is None:
+            self._writer.write(packet)
+        else:
+            # Messages written together go in the same datagram.
+            if not self._outgoing:
+                asyncio.get_event_loop().call_soon(self._flush)
+            self._outgoing += packet
+
+    def _flush(self):
+        if self._transport is None:
+            return
+        self._sequence = (self._sequence + 1) & 0xffffffff
+        datagram = struct.pack(">4sL", _UDP_MAGIC, self._sequence) + self._outgoing
+        self._outgoing = b""
+        self._unacked.append((self._sequence, datagram))
+        self._send_datagram(datagram)
+
+    def _send_datagram(self, datagram):
+        self._last_datagram = datagram
+        self._last_sent = time.monotonic()
+        self._transport.sendto(datagram)
+
+    def _datagram_received(self, data):
+        if len(data) < 12 or data[:4] != _UDP_MAGIC:
+            return
+        sequence, ack = struct.unpack(">LL", data[4:12])
+        self._last_reply = time.monotonic()
+        self._connected.set()
+        self._unacked = [(s, d) for s, d in self._unacked
+                         if (s - ack) & 0xffffffff < 0x80000000 and s != ack]
+        if self._received is not None:
+            gap = (sequence - self._received) & 0xffffffff
+            if gap == 0 or gap >= 0x80000000:
+                # duplicate or reordered
+                return
+            if gap > 1:
+                logger.debug("lost %d moninj datagrams, resynchronizing",
+                             gap - 1)
+                self.resync()
+        self._received = sequence
+        try:
+            self._parse_messages(data[12:])
+        except ValueError:
+            logger.warning("malformed moninj datagram", exc_info=True)
+
+    def _parse_messages(self, data):
+        while data:
+            ty = data[0]
+            if ty == 0:
+                channel, probe, value = struct.unpack(">lbl", data[1:10])
+                self.monitor_cb(channel, probe, value)
+                data = data[10:]
+            elif ty == 1:
+                channel, override, value = struct.unpack(">lbb", data[1:7])
+                self.injection_status_cb(channel, override, value)
+                data = data[7:]
+            else:
+                raise ValueError("Unknown packet type", ty)
+
+    async def _resend_cr(self):
+        try:
+            while True:
+                await asyncio.sleep(_UDP_RESEND_INTERVAL)
+                now = time.monotonic()
+                if now - self._last_reply > _UDP_TIMEOUT:
+                    logger.error("no moninj datagram from the core device "
+                                 "for %.0f s", _UDP_TIMEOUT)
+                    return
+                if self._unacked:
+                    for _, datagram in self._unacked:
+                        self._send_datagram(datagram)
+                elif now - self._last_sent > _UDP_KEEPALIVE_INTERVAL:
+                    # The core device acknowledges it again, without
+                    # executing it.
+                    self._send_datagram(self._last_datagram)
+        finally:
+            if self.disconnect_cb is not None:
+                self.disconnect_cb()
 
     def monitor_probe(self, enable, channel, probe):
         packet = struct.pack(self.endian + "bblb", 0, enable, channel, probe)
-        self._writer.write(packet)
+        self._write(packet)
 
     def monitor_injection(self, enable, channel, overrd):
         packet = struct.pack(self.endian + "bblb", 3, enable, channel, overrd)
-        self._writer.write(packet)
+        self._write(packet)
 
     def inject(self, channel, override, value):
+        """Sets an override of a channel. The core device clears the
+        overrides set by a connection, here or with :meth:`inject_pattern`,
+        when the connection is closed."""
         packet = struct.pack(self.endian + "blbb", 1, channel, override, value)
-        self._writer.write(packet)
+        self._write(packet)
+
+    def inject_pattern(self, channel, override, high_ms, low_ms=0, count=1):
+        """Makes the core device set the override to 1 for ``high_ms`` and
+        then to 0 for ``low_ms`` milliseconds, ``count`` times (0 repeats
+        until :meth:`inject` is called for the same override or the
+        connection is closed). For example, a 10 ms pulse is
+        ``inject_pattern(channel, TTLOverride.level.value, 10)`` and a 50 Hz
+        square wave is ``inject_pattern(channel, TTLOverride.level.value,
+        10, 10, 0)``. As with :meth:`inject`, the ``en`` (and ``oe``)
+        overrides must be set for the pattern to reach the output, and all
+        are cleared when the connection is closed."""
+        packet = struct.pack(self.endian + "blblll", 5, channel, override,
+                             high_ms, low_ms, count)
+        self._write(packet)
 
     def get_injection_status(self, channel, override):
         packet = struct.pack(self.endian + "blb", 2, channel, override)
-        self._writer.write(packet)
+        self._write(packet)
+
+    def set_probe_interval(self, interval_ms):
+        """Sets how often the core device checks the monitored probes and
+        injection states of this connection. Only changed values are sent."""
+        packet = struct.pack(self.endian + "bl", 4, interval_ms)
+        self._write(packet)
+
+    def resync(self):
+        """Makes the core device send all the monitored values again."""
+        packet = struct.pack(self.endian + "b", 6)
+        self._write(packet)
 
     async def _receive_cr(self):
         try:
@@ -47,10 +47,18 @@ def rtio_init() -> TNone:
 def rtio_get_destination_status(linkno: TInt32) -> TBool:
     raise NotImplementedError("syscall not simulated")
 
+@syscall(flags={"nounwind", "nowrite"})
+def rtio_get_destination_age(destination: TInt32) -> TInt64:
+    raise NotImplementedError("syscall not simulated")
+
 @syscall(flags={"nounwind", "nowrite"})
 def rtio_get_counter() -> TInt64:
     raise NotImplementedError("syscall not simulated")
 
+@syscall(flags={"nounwind", "nowrite"})
+def rpc_low_latency(duration_ms: TInt32) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
 
 class Core:
     """Core device driver.
@@ -65,13 +73,19 @@ class Core:
     :param ref_multiplier: ratio between the RTIO fine timestamp frequency
         and the RTIO coarse timestamp frequency (e.g. SERDES multiplication
         factor).
+    :param auth_key: key presented to the core device if its ``auth_key``
+        config entry is set.
+    :param board_serial: serial number (EUI-48 in hexadecimal) of the core
+        device. If set, connecting to a board with another serial number, or
+        without one, raises :class:`artiq.coredevice.comm_kernel.UnexpectedDevice`.
     """
 
     kernel_invariants = {
         "core", "ref_period", "coarse_ref_period", "ref_multiplier",
     }
 
-    def __init__(self, dmgr, host, ref_period, ref_multiplier=8, target="or1k"):
+    def __init__(self, dmgr, host, ref_period, ref_multiplier=8, target="or1k",
+                 auth_key=None, board_serial=None):
         self.ref_period = ref_period
         self.ref_multiplier = ref_multiplier
         if target == "or1k":
@@ -84,7 +98,8 @@ class Core:
         if host is None:
             self.comm = CommKernelDummy()
         else:
-            self.comm = CommKernel(host)
+            self.comm = CommKernel(host, auth_key=auth_key,
+                                   board_serial=board_serial)
 
         self.first_run = True
         self.dmgr = dmgr
@@ -94,6 +109,28 @@ class Core:
     def close(self):
         self.comm.close()
 
+    def set_notification_handler(self, tag, handler):
+        """Registers ``handler`` to be called with the payload (``bytes``) of
+        every notification posted under ``tag`` by
+        :func:`artiq.coredevice.notify.notify` in kernels run on this core
+        device. Pass ``None`` to remove the handler."""
+        self.comm.set_notification_handler(tag, handler)
+
+    def get_last_run_stats(self):
+        """Returns the statistics of the last kernel run on this core
+        device, sent by the core device when the kernel ends, or ``None`` if
+        its firmware does not send them.
+
+        They are a dictionary with the ``duration`` of the run in seconds,
+        the number of ``output_events`` written by the kernel (not counting
+        DMA), their minimum slack ``min_slack_mu`` in machine units, or
+        ``None`` if there was none or the gateware does not measure it, the
+        number of ``rtio_errors`` counted by the core device during the run,
+        and its ``outcome`` (``"finished"`` or ``"exception"``), as returned
+        by :meth:`~artiq.coredevice.comm_mgmt.CommMgmt.get_kernel_run_stats`.
+        """
+        return self.comm.last_run_stats
+
     def compile(self, function, args, kwargs, set_result=None,
                 attribute_writeback=True, print_as_rpc=True):
         try:
@@ -132,6 +169,11 @@ class Core:
             self.comm.check_system_info()
             self.first_run = False
 
+        # Values appended with dataset_append go to the datasets of the
+        # experiment whose kernel this is.
+        self.comm.set_dataset_append_handler(
+            getattr(getattr(function, "__self__", None),
+                    "append_to_dataset", None))
         self.comm.load(kernel_library)
         self.comm.run()
         self.comm.serve(embedding_map, symbolizer, demangler)
@@ -186,6 +228,31 @@ class Core:
         startup until certain DRTIO destinations are up."""
         return rtio_get_destination_status(destination)
 
+    @kernel
+    def get_rtio_destination_age(self, destination):
+        """Returns the time in milliseconds since the specified RTIO
+        destination was last reported up by its satellite, or -1 if it is
+        down. Local destinations always report 0. The core device surveys
+        the remote destinations every 200 ms or so, so a value well above
+        that means that the satellite stopped answering and is about to be
+        marked down.
+
+        Kernels can check this before a sequence that targets a satellite,
+        and skip or delay it while the satellite is unreachable, instead of
+        raising :class:`~artiq.coredevice.exceptions.RTIODestinationUnreachable`
+        in the middle of the sequence."""
+        return rtio_get_destination_age(destination)
+
+    @kernel
+    def low_latency_rpc(self, duration_ms):
+        """Put the comms CPU in its bounded-latency mode for the next
+        ``duration_ms`` milliseconds (at most ``low_latency_max_ms``, 1000 by
+        default), for a phase of the kernel that makes many RPCs. Background
+        tasks of the core device are held back meanwhile, so that RPC
+        replies are handled as soon as they arrive. A duration of 0 ends the
+        mode, which also ends with the kernel."""
+        rpc_low_latency(duration_ms)
+
     @kernel
     def reset(self):
         """Clear RTIO FIFOs, release RTIO PHY reset, and set the time cursor
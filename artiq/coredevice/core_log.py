@@ -0,0 +1,43 @@
+"""
+Leveled and tagged logging from kernels.
+
+Records are routed into the core device log under the ``kernel::<tag>``
+target and are subject to the same UART and buffer level filters as the
+runtime's own messages, unlike the plain ``core_log`` stream which is
+always logged at the INFO level.
+"""
+
+from artiq.language.core import syscall, kernel
+from artiq.language.types import TInt32, TStr, TNone
+
+
+ERROR = 1
+WARN = 2
+INFO = 3
+DEBUG = 4
+TRACE = 5
+
+
+@syscall(flags={"nounwind", "nowrite"})
+def core_log_record(level: TInt32, tag: TStr, message: TStr) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+
+@kernel
+def error(tag, message):
+    core_log_record(ERROR, tag, message)
+
+
+@kernel
+def warning(tag, message):
+    core_log_record(WARN, tag, message)
+
+
+@kernel
+def info(tag, message):
+    core_log_record(INFO, tag, message)
+
+
+@kernel
+def debug(tag, message):
+    core_log_record(DEBUG, tag, message)
@@ -0,0 +1,29 @@
+"""
+Appending to datasets from kernels without a round trip to the host.
+
+Streaming results point by point with an RPC to
+:meth:`~artiq.language.environment.HasEnvironment.append_to_dataset` makes
+the kernel wait for the host at every point. Values appended with
+:func:`dataset_append` are instead only queued towards the communications
+CPU, which batches them and sends them to the host every 100 ms, when the
+batch grows large, or before anything else the kernel sends to the host, so
+that they are appended before the RPCs that follow them are served. Unlike
+notifications, values are never dropped: the kernel waits when the queue is
+full, as it does for asynchronous RPCs.
+
+The values are appended to the datasets of the experiment whose kernel is
+run, with :meth:`~artiq.language.environment.HasEnvironment.append_to_dataset`;
+the datasets must have been set to lists beforehand.
+"""
+
+from artiq.language.core import syscall
+from artiq.language.types import TStr, TFloat, TNone
+
+
+@syscall(flags={"nowrite"})
+def dataset_append(key: TStr, value: TFloat) -> TNone:
+    """Appends ``value`` to the dataset ``key``.
+
+    :raises ValueError: if the key is too long.
+    """
+    raise NotImplementedError("syscall not simulated")
@@ -0,0 +1,134 @@
+"""Discovery of core devices on the local network through the multicast DNS
+service records (``_artiq._tcp``) advertised by the runtime."""
+
+import socket
+import struct
+import time
+
+
+MDNS_GROUP = "224.0.0.251"
+MDNS_PORT = 5353
+SERVICE = "_artiq._tcp.local"
+
+TYPE_A = 1
+TYPE_PTR = 12
+TYPE_TXT = 16
+TYPE_AAAA = 28
+TYPE_SRV = 33
+
+
+def _encode_name(name):
+    return b"".join(bytes([len(label)]) + label.encode()
+                    for label in name.split(".")) + b"\x00"
+
+
+def _read_name(packet, offset):
+    labels = []
+    end = None
+    for _ in range(64):
+        length = packet[offset]
+        if length & 0xc0 == 0xc0:
+            if end is None:
+                end = offset + 2
+            offset = (length & 0x3f) << 8 | packet[offset + 1]
+        elif length == 0:
+            return ".".join(labels), offset + 1 if end is None else end
+        else:
+            labels.append(packet[offset + 1:offset + 1 + length]
+                          .decode(errors="replace"))
+            offset += 1 + length
+    raise ValueError("name compression loop")
+
+
+def _read_records(packet):
+    _, flags, qdcount, ancount, nscount, arcount = \
+        struct.unpack(">HHHHHH", packet[:12])
+    if not flags & 0x8000:
+        return
+    offset = 12
+    for _ in range(qdcount):
+        _, offset = _read_name(packet, offset)
+        offset += 4
+    for _ in range(ancount + nscount + arcount):
+        name, offset = _read_name(packet, offset)
+        ty, _, _, length = struct.unpack(">HHIH", packet[offset:offset + 10])
+        offset += 10
+        yield name.lower(), ty, packet, offset, length
+        offset += length
+
+
+def discover(timeout=1.0):
+    """Queries the local network for core devices.
+
+    Returns a list of dictionaries with the keys ``name``, ``host``
+    (the ``.local`` host name), ``port`` (of the session service),
+    ``addresses`` (IPv4 first) and ``properties`` (``variant``, ``serial``
+    and ``version`` of the device).
+    """
+    query = struct.pack(">HHHHHH", 0, 0, 1, 0, 0, 0) + \
+        _encode_name(SERVICE) + struct.pack(">HH", TYPE_PTR, 1)
+
+    instances = set()
+    services = dict()
+    properties = dict()
+    addresses = dict()
+
+    sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+    try:
+        sock.setsockopt(socket.IPPROTO_IP, socket.IP_MULTICAST_TTL, 255)
+        sock.sendto(query, (MDNS_GROUP, MDNS_PORT))
+        deadline = time.monotonic() + timeout
+        while True:
+            remaining = deadline - time.monotonic()
+            if remaining <= 0:
+                break
+            sock.settimeout(remaining)
+            try:
+                packet, _ = sock.recvfrom(9000)
+            except socket.timeout:
+                break
+            try:
+                records = list(_read_records(packet))
+            except (IndexError, ValueError, struct.error):
+                continue
+            for name, ty, packet, offset, length in records:
+                rdata = packet[offset:offset + length]
+                if ty == TYPE_PTR and name == SERVICE:
+                    instances.add(_read_name(packet, offset)[0])
+                elif ty == TYPE_SRV:
+                    _, _, port = struct.unpack(">HHH", rdata[:6])
+                    services[name] = (_read_name(packet, offset + 6)[0].lower(),
+                                      port)
+                elif ty == TYPE_TXT:
+                    entries = dict()
+                    i = 0
+                    while i < len(rdata):
+                        entry = rdata[i + 1:i + 1 + rdata[i]].decode(errors="replace")
+                        key, _, value = entry.partition("=")
+                        entries[key] = value
+                        i += 1 + rdata[i]
+                    properties[name] = entries
+                elif ty == TYPE_A:
+                    addresses.setdefault(name, set()).add(
+                        socket.inet_ntop(socket.AF_INET, rdata))
+                elif ty == TYPE_AAAA:
+                    addresses.setdefault(name, set()).add(
+                        socket.inet_ntop(socket.AF_INET6, rdata))
+    finally:
+        sock.close()
+
+    devices = []
+    for instance in sorted(instances):
+        key = instance.lower()
+        if key not in services:
+            continue
+        host, port = services[key]
+        devices.append({
+            "name": instance.split(".")[0],
+            "host": host,
+            "port": port,
+            "addresses": sorted(addresses.get(host, set()),
+                                key=lambda address: (":" in address, address)),
+            "properties": properties.get(key, dict()),
+        })
+    return devices
@@ -6,7 +6,7 @@ alone could achieve.
 """
 
 from artiq.language.core import syscall, kernel
-from artiq.language.types import TInt32, TInt64, TStr, TNone, TTuple
+from artiq.language.types import TInt32, TInt64, TStr, TNone, TTuple, TBool
 from artiq.coredevice.exceptions import DMAError
 
 from numpy import int64
@@ -16,6 +16,10 @@ from numpy import int64
 def dma_record_start(name: TStr) -> TNone:
     raise NotImplementedError("syscall not simulated")
 
+@syscall
+def dma_record_mark(name: TStr, timestamp: TInt64) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
 @syscall
 def dma_record_stop(duration: TInt64) -> TNone:
     raise NotImplementedError("syscall not simulated")
@@ -28,10 +32,32 @@ def dma_erase(name: TStr) -> TNone:
 def dma_retrieve(name: TStr) -> TTuple([TInt64, TInt32]):
     raise NotImplementedError("syscall not simulated")
 
+@syscall
+def dma_retrieve_segment(name: TStr, marker: TStr, offset: TInt32,
+                         duration: TInt64) -> TTuple([TInt64, TInt32]):
+    raise NotImplementedError("syscall not simulated")
+
 @syscall
 def dma_playback(timestamp: TInt64, ptr: TInt32) -> TNone:
     raise NotImplementedError("syscall not simulated")
 
+@syscall
+def dma_playback_repeat(timestamp: TInt64, ptr: TInt32, period: TInt64,
+                        count: TInt32) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+@syscall
+def dma_playback_loop(timestamp: TInt64, ptr: TInt32, period: TInt64) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+@syscall
+def dma_playback_stop() -> TInt32:
+    raise NotImplementedError("syscall not simulated")
+
+@syscall
+def dma_chain(name: TStr, trace: TStr, first: TBool) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
 
 class DMARecordContextManager:
     """Context manager returned by :meth:`CoreDMA.record()`.
@@ -72,6 +98,8 @@ class CoreDMA:
         self.core     = dmgr.get(core_device)
         self.recorder = DMARecordContextManager()
         self.epoch    = 0
+        self.loop_start_mu  = int64(0)
+        self.loop_period_mu = int64(0)
 
     @kernel
     def record(self, name):
@@ -82,6 +110,29 @@ class CoreDMA:
         self.recorder.name = name
         return self.recorder
 
+    @kernel
+    def mark(self, name):
+        """Names the current position of the trace being recorded, for use
+        with :meth:`playback_segment`. The marker refers to the events
+        recorded after this call and to the current time on the timeline of
+        the trace. Any previous marker with the same name is overwritten."""
+        dma_record_mark(name, now_mu())
+
+    @kernel
+    def chain(self, name, traces):
+        """Stores a DMA trace called ``name`` that plays the traces named in
+        the list ``traces`` one after the other, each starting when the
+        previous one ends. The traces are copied, so that they can be
+        recorded again or erased without affecting the chain. Any previously
+        recorded trace called ``name`` is overwritten."""
+        if len(traces) == 0:
+            raise DMAError("Empty DMA chain")
+        self.epoch += 1
+        first = True
+        for trace in traces:
+            dma_chain(name, trace, first)
+            first = False
+
     @kernel
     def erase(self, name):
         """Removes the DMA trace with the given name from storage."""
@@ -96,6 +147,27 @@ class CoreDMA:
         dma_playback(now_mu(), ptr)
         delay_mu(advance_mu)
 
+    @kernel
+    def playback_segment(self, name, marker="", offset=0, duration_mu=int64(-1)):
+        """Replays part of a previously recorded DMA trace, so that a long
+        trace can serve as a library of segments.
+
+        The segment starts at the marker called ``marker`` set with
+        :meth:`mark` or, if ``marker`` is empty, at the event ``offset``
+        bytes into the trace as recorded, and lasts ``duration_mu``, or until
+        the end of the trace if negative. It consists of the events recorded
+        from there on whose timestamps fall within that time, played back
+        relative to the current time, which is then advanced by the
+        duration of the segment. :class:`DMAError` is raised if the marker
+        does not exist, the offset is not at the start of an event, or the
+        segment extends past the end of the trace.
+
+        The segment is extracted by the runtime on every call, which takes
+        time proportional to the length of the trace."""
+        (advance_mu, ptr) = dma_retrieve_segment(name, marker, offset, duration_mu)
+        dma_playback(now_mu(), ptr)
+        delay_mu(advance_mu)
+
     @kernel
     def get_handle(self, name):
         """Returns a handle to a previously recorded DMA trace. The returned handle
@@ -113,3 +185,44 @@ class CoreDMA:
             raise DMAError("Invalid handle")
         dma_playback(now_mu(), ptr)
         delay_mu(advance_mu)
+
+    @kernel
+    def playback_handle_repeat(self, handle, count):
+        """Replays a handle obtained with :meth:`get_handle` ``count`` times,
+        each time starting when the previous one ends. The gateware restarts
+        the playback by itself, so that this costs the kernel CPU no more than
+        a single :meth:`playback_handle`. This function blocks until the
+        last repetition is submitted to the RTIO FIFOs, and advances the
+        timeline by the duration of all repetitions."""
+        (epoch, advance_mu, ptr) = handle
+        if self.epoch != epoch:
+            raise DMAError("Invalid handle")
+        dma_playback_repeat(now_mu(), ptr, advance_mu, count)
+        delay_mu(int64(count)*advance_mu)
+
+    @kernel
+    def playback_handle_loop(self, handle):
+        """Starts replaying a handle obtained with :meth:`get_handle` over and
+        over, each time starting when the previous one ends, until
+        :meth:`stop_loop` is called. This function returns immediately and
+        does not advance the timeline.
+
+        While the loop runs, the DMA engine holds the RTIO core: the kernel
+        may do other work, e.g. RPCs, but accessing RTIO channels, starting
+        another playback, recording a trace or erasing one raise
+        :exc:`DMAError` until the loop is stopped."""
+        (epoch, advance_mu, ptr) = handle
+        if self.epoch != epoch:
+            raise DMAError("Invalid handle")
+        self.loop_start_mu = now_mu()
+        self.loop_period_mu = advance_mu
+        dma_playback_loop(now_mu(), ptr, advance_mu)
+
+    @kernel
+    def stop_loop(self):
+        """Stops the loop started by :meth:`playback_handle_loop` at the end
+        of the current repetition, and returns the number of repetitions
+        played. The timeline is set to the end of the last repetition."""
+        passes = dma_playback_stop()
+        at_mu(self.loop_start_mu + int64(passes)*self.loop_period_mu)
+        return passes
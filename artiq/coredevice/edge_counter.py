@@ -206,7 +206,7 @@ class EdgeCounter:
         count = rtio_input_data(self.channel)
         if count == self.counter_max:
             raise CounterOverflow(
-                "Input edge counter overflow on RTIO channel {0}",
+                "Input edge counter overflow on RTIO channel {rtio_channel_info:0}",
                 int64(self.channel))
         return count
 
@@ -231,6 +231,6 @@ class EdgeCounter:
                                                        self.channel)
         if count == self.counter_max:
             raise CounterOverflow(
-                "Input edge counter overflow on RTIO channel {0}",
+                "Input edge counter overflow on RTIO channel {rtio_channel_info:0}",
                 int64(self.channel))
         return timestamp, count
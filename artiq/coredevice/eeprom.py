@@ -0,0 +1,90 @@
+"""Driver for 24xx I2C EEPROMs, e.g. those of EEM cards, for small amounts of
+per-fixture calibration data.
+
+The page writes, the polling of the write cycle and the addressing of the
+device are done by the core device firmware, which also lets
+``artiq_coremgmt eeprom`` read and write the EEPROM when no kernel is
+running. Only the I2C buses of the DRTIO master or standalone core device
+are supported.
+"""
+
+from numpy import int32
+
+from artiq.language.core import syscall, kernel
+from artiq.language.types import TInt32, TList, TNone
+from artiq.coredevice.kasli_i2c import port_mapping
+
+
+@syscall
+def eeprom_read(busno: TInt32, port: TInt32, address: TInt32, size: TInt32,
+                page_size: TInt32, offset: TInt32,
+                data: TList(TInt32)) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+
+@syscall(flags={"nowrite"})
+def eeprom_write(busno: TInt32, port: TInt32, address: TInt32, size: TInt32,
+                 page_size: TInt32, offset: TInt32,
+                 data: TList(TInt32)) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+
+class EEPROM24xx:
+    """24xx I2C EEPROM.
+
+    Errors, including accesses beyond the end of the EEPROM, raise
+    :class:`artiq.coredevice.exceptions.I2CError`.
+
+    :param port: Port of the I2C switches of Kasli the EEPROM is behind,
+        e.g. ``"EEM3"``, or ``None``.
+    :param busno: I2C bus number.
+    :param address: 7-bit I2C address of the EEPROM.
+    :param size: Size of the EEPROM in bytes, e.g. 256 for a 24C02.
+    :param page_size: Size of a write page of the EEPROM in bytes, e.g. 8
+        for a 24C02.
+    :param core_device: Core device name.
+    """
+    kernel_invariants = {"core", "busno", "port", "address", "size",
+                         "page_size"}
+
+    def __init__(self, dmgr, port=None, busno=0, address=0x50, size=256,
+                 page_size=8, core_device="core"):
+        self.core = dmgr.get(core_device)
+        self.busno = busno
+        self.port = -1 if port is None else port_mapping[port]
+        self.address = address
+        self.size = size
+        self.page_size = page_size
+
+    @kernel
+    def read(self, offset: TInt32, data: TList(TInt32)):
+        """Reads ``len(data)`` bytes from ``offset`` into ``data``."""
+        eeprom_read(self.busno, self.port, self.address, self.size,
+                    self.page_size, offset, data)
+
+    @kernel
+    def write(self, offset: TInt32, data: TList(TInt32)):
+        """Writes the bytes of ``data`` from ``offset``, and waits for the
+        EEPROM to complete the write."""
+        eeprom_write(self.busno, self.port, self.address, self.size,
+                     self.page_size, offset, data)
+
+    @kernel
+    def read_i32(self, offset: TInt32) -> TInt32:
+        """Reads a big-endian 32-bit integer at ``offset``."""
+        data = [0]*4
+        self.read(offset, data)
+        value = int32(0)
+        for i in range(4):
+            value <<= 8
+            value |= data[i]
+        return value
+
+    @kernel
+    def write_i32(self, offset: TInt32, value: TInt32):
+        """Writes a big-endian 32-bit integer at ``offset``."""
+        data = [0]*4
+        for i in range(4):
+            data[i] = (value >> 24) & 0xff
+            value <<= 8
+        self.write(offset, data)
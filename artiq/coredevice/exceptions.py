@@ -4,6 +4,7 @@ import re
 import os
 
 from artiq import __artiq_dir__ as artiq_dir
+from artiq.language.core import TerminationRequested as _TerminationRequested
 from artiq.coredevice.runtime import source_loader
 
 
@@ -17,7 +18,7 @@ AssertionError = builtins.AssertionError
 class CoreException:
     """Information about an exception raised or passed through the core device."""
 
-    def __init__(self, name, message, params, traceback):
+    def __init__(self, name, message, params, traceback, context=[]):
         if ':' in name:
             exn_id, self.name = name.split(':', 2)
             self.id = int(exn_id)
@@ -25,8 +26,21 @@ class CoreException:
             self.id, self.name = 0, name
         self.message, self.params = message, params
         self.traceback = list(traceback)
+        # exceptions that were being handled when this one was raised, oldest first
+        self.context = list(context)
 
     def __str__(self):
+        lines = []
+        for exn in self.context:
+            lines.append(exn._format())
+            lines.append("")
+            lines.append("During handling of the above exception, "
+                         "another exception occurred:")
+            lines.append("")
+        lines.append(self._format())
+        return "\n".join(lines)
+
+    def _format(self):
         lines = []
         lines.append("Core Device Traceback (most recent call last):")
         last_address = 0
@@ -68,7 +82,8 @@ class InternalError(Exception):
 
 
 class CacheError(Exception):
-    """Raised when putting a value into a cache row would violate memory safety."""
+    """Raised when putting a value into a cache row would violate memory safety,
+    or exceed the cache quotas."""
     artiq_builtin = True
 
 
@@ -104,6 +119,33 @@ class DMAError(Exception):
     artiq_builtin = True
 
 
+class WatchdogExpired(Exception):
+    """Raised when a ``with watchdog(t):`` scope in a kernel exceeds its
+    timeout. The core device terminates the kernel.
+
+    :attr scope: source location of the expired watchdog.
+    :attr timeout: watchdog timeout, in seconds.
+    :attr elapsed: time spent in the scope when the watchdog expired, in seconds.
+    """
+    def __init__(self, scope, timeout, elapsed):
+        Exception.__init__(self,
+            "watchdog at {} expired after {:.3f} s (timeout {:.3f} s)"
+            .format(scope, elapsed, timeout))
+        self.scope = scope
+        self.timeout = timeout
+        self.elapsed = elapsed
+
+
+class TerminationRequested(_TerminationRequested):
+    """Raised when the termination of the running kernel is requested
+    through the management interface, with the reason given as message.
+    It is raised in the kernel at its next synchronous RPC, and can be
+    caught there to end cleanly; if the kernel does not end within the
+    grace period, the core device stops it and this exception is raised
+    on the host instead."""
+    artiq_builtin = True
+
+
 class ClockFailure(Exception):
     """Raised when RTIO PLL has lost lock."""
 
@@ -116,3 +158,8 @@ class I2CError(Exception):
 class SPIError(Exception):
     """Raised when a SPI transaction fails."""
     pass
+
+
+class FlashStorageError(Exception):
+    """Raised when a value cannot be stored in the flash storage area."""
+    pass
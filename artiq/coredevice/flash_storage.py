@@ -0,0 +1,62 @@
+"""
+Persistent storage of small values by kernels, e.g. calibration results, in
+an area of the core device flash separate from the configuration.
+
+Values are lists of 32-bit integers stored under string keys, and survive
+reboots and firmware updates. Writing a value identical to the stored one
+does not wear the flash. The area can be listed and erased with
+``artiq_coremgmt storage``.
+"""
+
+from artiq.language.core import syscall, kernel
+from artiq.language.types import TStr, TInt32, TList, TNone
+
+
+#: Longest key, in bytes.
+MAX_KEY_LENGTH = 64
+#: Largest number of words of a value.
+MAX_VALUE_LENGTH = 256
+
+
+@syscall
+def flash_storage_get(key: TStr, value: TList(TInt32)) -> TInt32:
+    raise NotImplementedError("syscall not simulated")
+
+
+@syscall(flags={"nowrite"})
+def flash_storage_put(key: TStr, value: TList(TInt32)) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+
+class FlashStorage:
+    """Core device persistent flash storage access"""
+    def __init__(self, dmgr, core_device="core"):
+        self.core = dmgr.get(core_device)
+
+    @kernel
+    def get(self, key, value):
+        """Read the value stored under ``key`` into ``value``, up to
+        ``len(value)`` words.
+
+        :param str key: storage key
+        :param list value: a list of 32-bit integers to read the value into
+        :return: the number of words of the stored value, or -1 if ``key``
+            is not stored
+        """
+        return flash_storage_get(key, value)
+
+    @kernel
+    def put(self, key, value):
+        """Store ``value`` under ``key``, replacing the previous value. The
+        value persists across reboots.
+
+        To remove a value, call :meth:`put` with an empty list.
+
+        A key longer than :data:`MAX_KEY_LENGTH` bytes, a value longer than
+        :data:`MAX_VALUE_LENGTH` words or a full storage area raises
+        :class:`artiq.coredevice.exceptions.FlashStorageError`.
+
+        :param str key: storage key
+        :param list value: a list of 32-bit integers
+        """
+        flash_storage_put(key, value)
@@ -5,6 +5,11 @@ from artiq.language.types import *
 from artiq.coredevice.rtio import rtio_output, rtio_input_data
 
 
+# Config channel addresses, see artiq.gateware.rtio.phy.grabber.
+ROI_COMMIT = 0x40
+ROI_READ = 0x80
+
+
 class OutOfSyncException(Exception):
     """Raised when an incorrect number of ROI engine outputs has been
     retrieved from the RTIO input FIFO."""
@@ -31,11 +36,23 @@ class Grabber:
         Defines the coordinates of a ROI.
 
         The coordinates are set around the current position of the RTIO time
-        cursor.
+        cursor, and are used by the ROI engine from the next gap between
+        two video frames. Any other ROI staged with :meth:`stage_roi` is
+        applied at the same time.
+
+        Advances the timeline by 5 coarse RTIO cycles.
+        """
+        self.stage_roi(n, x0, y0, x1, y1)
+        self.commit_roi()
+
+    @kernel
+    def stage_roi(self, n, x0, y0, x1, y1):
+        """
+        Stages the coordinates of a ROI, without changing those used by the
+        ROI engine until :meth:`commit_roi` is called.
 
-        The user must keep the ROI engine disabled for the duration of more 
-        than one video frame after calling this function, as the output
-        generated for that video frame is undefined.
+        Staging several ROIs and committing them once updates them all for
+        the same video frame.
 
         Advances the timeline by 4 coarse RTIO cycles.
         """
@@ -49,6 +66,42 @@ class Grabber:
         rtio_output((self.channel_base << 8) | (4*n+3), y1)
         delay_mu(c)
 
+    @kernel
+    def commit_roi(self):
+        """
+        Makes the ROI engines use the staged coordinates.
+
+        The coordinates are applied at the current position of the RTIO time
+        cursor if no video frame is being received, or else at the end of
+        the current frame, so that no frame is processed with a mix of old
+        and new coordinates. The ROI engines keep running, and their gates
+        are unchanged.
+
+        Advances the timeline by 1 coarse RTIO cycle.
+        """
+        rtio_output((self.channel_base << 8) | ROI_COMMIT, 0)
+        delay_mu(int64(self.core.ref_multiplier))
+
+    @kernel
+    def read_roi(self, n, coords):
+        """
+        Reads back the coordinates currently used by a ROI engine.
+
+        The coordinates are read at the current position of the RTIO time
+        cursor. Coordinates that have been committed are only reported once
+        they are applied, i.e. after the end of the current video frame.
+        Blocks until the coordinates are available.
+
+        :param coords: list of 4 integers, replaced with x0, y0, x1 and y1.
+
+        Advances the timeline by 4 coarse RTIO cycles.
+        """
+        c = int64(self.core.ref_multiplier)
+        for i in range(4):
+            rtio_output((self.channel_base << 8) | ROI_READ | (4*n+i), 0)
+            delay_mu(c)
+            coords[i] = rtio_input_data(self.channel_base)
+
     @kernel
     def gate_roi(self, mask):
         """
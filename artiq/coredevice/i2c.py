@@ -4,7 +4,7 @@ Non-realtime drivers for I2C chips on the core device.
 
 
 from artiq.language.core import syscall, kernel
-from artiq.language.types import TBool, TInt32, TNone
+from artiq.language.types import TBool, TInt32, TNone, TList
 from artiq.coredevice.exceptions import I2CError
 
 
@@ -33,6 +33,30 @@ def i2c_read(busno: TInt32, ack: TBool) -> TInt32:
     raise NotImplementedError("syscall not simulated")
 
 
+@syscall(flags={"nounwind", "nowrite"})
+def i2c_write_buffer(busno: TInt32, data: TList(TInt32)) -> TInt32:
+    raise NotImplementedError("syscall not simulated")
+
+
+@syscall(flags={"nounwind"})
+def i2c_read_buffer(busno: TInt32, data: TList(TInt32)) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+
+@syscall(flags={"nowrite"})
+def i2c_smbus_block_write(busno: TInt32, address: TInt32, ten_bit: TBool,
+                          command: TInt32, data: TList(TInt32),
+                          pec: TBool) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+
+@syscall
+def i2c_smbus_block_read(busno: TInt32, address: TInt32, ten_bit: TBool,
+                         command: TInt32, data: TList(TInt32),
+                         pec: TBool) -> TInt32:
+    raise NotImplementedError("syscall not simulated")
+
+
 @kernel
 def i2c_poll(busno, busaddr):
     """Poll I2C device at address.
@@ -103,10 +127,9 @@ def i2c_write_many(busno, busaddr, addr, data, ack_last=True):
             raise I2CError("failed to ack bus address")
         if not i2c_write(busno, addr):
             raise I2CError("failed to ack data address")
-        for i in range(n):
-            if not i2c_write(busno, data[i]) and (
-                    i < n - 1 or ack_last):
-                raise I2CError("failed to ack write data")
+        acked = i2c_write_buffer(busno, data)
+        if acked < n - 1 or (acked < n and ack_last):
+            raise I2CError("failed to ack write data")
     finally:
         i2c_stop(busno)
 
@@ -121,7 +144,6 @@ def i2c_read_many(busno, busaddr, addr, data):
     :param data: List of integers to be filled with the data read.
         One entry ber byte.
     """
-    m = len(data)
     i2c_start(busno)
     try:
         if not i2c_write(busno, busaddr):
@@ -131,12 +153,42 @@ def i2c_read_many(busno, busaddr, addr, data):
         i2c_restart(busno)
         if not i2c_write(busno, busaddr | 1):
             raise I2CError("failed to ack bus read address")
-        for i in range(m):
-            data[i] = i2c_read(busno, ack=i < m - 1)
+        i2c_read_buffer(busno, data)
     finally:
         i2c_stop(busno)
 
 
+@kernel
+def smbus_block_write(busno, address, command, data, pec=True, ten_bit=False):
+    """SMBus block write, e.g. to a PMBus power monitor or hot-swap
+    controller.
+
+    The transfer is driven by the core device firmware, on the I2C buses of
+    the DRTIO master or standalone core device only. Errors, including a
+    NACK, raise :class:`artiq.coredevice.exceptions.I2CError`.
+
+    :param busno: I2C bus number
+    :param address: 7 bit I2C device address, or 10 bit if ``ten_bit``
+    :param command: SMBus command code
+    :param data: Data bytes to be written (at most 255)
+    :param pec: Append the SMBus packet error code
+    :param ten_bit: Use 10 bit addressing
+    """
+    i2c_smbus_block_write(busno, address, ten_bit, command, data, pec)
+
+
+@kernel
+def smbus_block_read(busno, address, command, data, pec=True, ten_bit=False):
+    """SMBus block read. See :func:`smbus_block_write`; a packet error code
+    that does not match raises :class:`artiq.coredevice.exceptions.I2CError`.
+
+    :param data: List of integers to be filled with the data read. It must
+        be long enough for the block sent by the device.
+    :returns: Number of bytes read
+    """
+    return i2c_smbus_block_read(busno, address, ten_bit, command, data, pec)
+
+
 class PCA9548:
     """Driver for the PCA9548 I2C bus switch.
 
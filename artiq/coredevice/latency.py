@@ -0,0 +1,108 @@
+"""Latency measurements of the RTIO system on a TTL output looped back to a
+TTL input, to quantify the round-trip and slack budgets of a gateware build.
+
+The pair is designated in the device database::
+
+    device_db["latency"] = {
+        "type": "local",
+        "module": "artiq.coredevice.latency",
+        "class": "LoopbackLatency",
+        "arguments": {"ttl_out": "loop_out", "ttl_in": "loop_in"}
+    }
+
+and measured from the host, e.g. in the ``run`` method of an experiment::
+
+    stats = self.latency.measure(samples=1000)
+    print(stats["loopback"]["mean"], stats["input"]["max"])
+
+The RTIO counter of the core device and the system time at which it was read
+are shown by ``artiq_coremgmt clock counter``.
+"""
+
+import statistics
+
+from artiq.language.core import kernel, rpc, delay, at_mu, now_mu
+from artiq.language.units import us
+from artiq.language.types import TInt32, TInt64
+
+
+class LoopbackLatency:
+    """Measures latencies on a TTL output connected to a TTL input.
+
+    For each sample, a pulse is sent on the output while the input is gated,
+    and the following latencies are recorded:
+
+    * ``loopback``: from the timestamp of the output pulse to the timestamp
+      of its rising edge on the input, i.e. the latencies of the output and
+      input PHYs and of the connection between them;
+    * ``input``: from the timestamp of the rising edge on the input to the
+      RTIO counter value at which the kernel has read it, i.e. the slack
+      that a kernel reacting to an input event loses before it can schedule
+      an output.
+
+    :param ttl_out: Name of the TTL output device.
+    :param ttl_in: Name of the TTL input device (:class:`TTLInOut`), connected
+        to the output.
+    :param core_device: Name of the core device.
+    """
+    kernel_invariants = {"core", "ttl_out", "ttl_in"}
+
+    def __init__(self, dmgr, ttl_out, ttl_in, core_device="core"):
+        self.core = dmgr.get(core_device)
+        self.ttl_out = dmgr.get(ttl_out)
+        self.ttl_in = dmgr.get(ttl_in)
+        self._loopback = []
+        self._input = []
+
+    @rpc(flags={"async"})
+    def _record(self, loopback_mu: TInt64, input_mu: TInt64):
+        self._loopback.append(loopback_mu)
+        self._input.append(input_mu)
+
+    @kernel
+    def _run(self, samples: TInt32):
+        self.core.break_realtime()
+        self.ttl_in.input()
+        self.ttl_out.output()
+        self.ttl_out.off()
+        delay(10*us)
+        for i in range(samples):
+            self.core.break_realtime()
+            t_gate = now_mu()
+            t_end = self.ttl_in.gate_rising(10*us)
+            at_mu(t_gate)
+            delay(1*us)
+            t_out = now_mu()
+            self.ttl_out.pulse(1*us)
+            t_in = self.ttl_in.timestamp_mu(t_end)
+            t_read = self.core.get_rtio_counter_mu()
+            if t_in < 0:
+                raise ValueError("no pulse received on the loopback input")
+            self._record(t_in - t_out, t_read - t_in)
+            at_mu(t_end)
+
+    def _stats(self, values_mu):
+        values = [self.core.mu_to_seconds(value) for value in values_mu]
+        return {
+            "min": min(values),
+            "mean": statistics.mean(values),
+            "max": max(values),
+            "stdev": statistics.pstdev(values),
+        }
+
+    def measure(self, samples=100):
+        """Measures the latencies over ``samples`` pulses, and returns a
+        dictionary with the statistics of the ``loopback`` and ``input``
+        latencies, each a dictionary with the ``min``, ``mean``, ``max`` and
+        standard deviation (``stdev``) in seconds.
+
+        This method runs a kernel and must be called from the host."""
+        if samples < 1:
+            raise ValueError("at least one sample is required")
+        self._loopback = []
+        self._input = []
+        self._run(samples)
+        return {
+            "loopback": self._stats(self._loopback),
+            "input": self._stats(self._input),
+        }
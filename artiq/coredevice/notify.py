@@ -0,0 +1,30 @@
+"""
+Asynchronous notifications from kernels to the host.
+
+A notification is a tag and a small payload that the kernel posts without
+waiting for the host or the communications CPU, unlike RPCs (including
+asynchronous RPCs, which block the kernel once the queue towards the
+communications CPU is full). When the queue is full, the notification is
+dropped instead, and the host is told how many notifications were lost.
+This makes notifications suitable for progress reporting and live plotting
+from timing-critical kernels.
+
+On the host, notifications are delivered to the handler registered with
+:meth:`artiq.coredevice.core.Core.set_notification_handler` for their tag.
+"""
+
+from artiq.language.core import syscall
+from artiq.language.types import TStr, TBytes, TNone
+
+
+#: Largest combined size, in bytes, of the tag and payload of a notification.
+MAX_SIZE = 0x1000 - 16
+
+
+@syscall(flags={"nowrite"})
+def notify(tag: TStr, data: TBytes) -> TNone:
+    """Posts the notification ``data`` under ``tag`` to the host.
+
+    :raises ValueError: if the tag and payload exceed :data:`MAX_SIZE` bytes.
+    """
+    raise NotImplementedError("syscall not simulated")
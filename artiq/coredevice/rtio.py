@@ -29,3 +29,22 @@ def rtio_input_timestamped_data(timeout_mu: TInt64,
     return a tuple of timestamp and attached data, or (-1, 0) if the timeout is
     reached."""
     raise NotImplementedError("syscall not simulated")
+
+
+@syscall
+def rtio_input_timestamps(timeout_mu: TInt64, channel: TInt32,
+                          timestamps: TList(TInt64)) -> TInt32:
+    """Wait for input events up to timeout_mu on the given channel, store
+    their timestamps into the given list until it is full or the timeout is
+    reached, and return the number of events stored."""
+    raise NotImplementedError("syscall not simulated")
+
+
+@syscall
+def rtio_input_timestamped_data_batch(timeout_mu: TInt64, channel: TInt32,
+                                      timestamps: TList(TInt64),
+                                      data: TList(TInt32)) -> TInt32:
+    """Wait for input events up to timeout_mu on the given channel, store
+    their timestamps and attached data into the given lists until either is
+    full or the timeout is reached, and return the number of events stored."""
+    raise NotImplementedError("syscall not simulated")
@@ -8,7 +8,7 @@ time is an error.
 """
 
 from artiq.language.core import syscall, kernel, portable, delay_mu
-from artiq.language.types import TInt32, TNone
+from artiq.language.types import TInt32, TNone, TList
 from artiq.coredevice.rtio import rtio_output, rtio_input_data
 
 
@@ -253,6 +253,21 @@ def spi_read(busno: TInt32) -> TInt32:
     raise NotImplementedError("syscall not simulated")
 
 
+@syscall(flags={"nounwind", "nowrite"})
+def spi_write_buffer(busno: TInt32, data: TList(TInt32)) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+
+@syscall(flags={"nounwind"})
+def spi_read_buffer(busno: TInt32, data: TList(TInt32)) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+
+@syscall(flags={"nounwind"})
+def spi_transfer(busno: TInt32, data: TList(TInt32)) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+
 class NRTSPIMaster:
     """Core device non-realtime Serial Peripheral Interface (SPI) bus master.
     Owns one non-realtime SPI bus.
@@ -286,3 +301,22 @@ class NRTSPIMaster:
     @kernel
     def read(self):
         return spi_read(self.busno)
+
+    @kernel
+    def write_buffer(self, data):
+        """Write each word of a list, as with :meth:`write`, in a single
+        call to the firmware.
+        """
+        spi_write_buffer(self.busno, data)
+
+    @kernel
+    def read_buffer(self, data):
+        """Fill a list with words read from the bus, shifting out zeros."""
+        spi_read_buffer(self.busno, data)
+
+    @kernel
+    def transfer(self, data):
+        """Full-duplex transfer: write each word of a list and replace it
+        with the word read during the same transfer.
+        """
+        spi_transfer(self.busno, data)
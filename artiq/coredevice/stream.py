@@ -0,0 +1,122 @@
+"""
+Lossy UDP stream of sample records from kernels to the host.
+
+Kernels push records of up to :data:`MAX_SAMPLES` 32-bit integers with
+:func:`stream_push`, for instance for live plotting. Like notifications,
+records never make the kernel wait: when the queue towards the
+communications CPU is full, they are dropped, and the host is told how many
+were. Unlike notifications, they do not go through the kernel session, but
+are sent over UDP to the hosts that subscribe with a :class:`StreamReceiver`,
+even while another host runs the kernel; datagrams lost on the network are
+not retransmitted.
+"""
+
+import socket
+import struct
+import time
+
+from artiq.language.core import syscall
+from artiq.language.types import TInt32, TList, TNone
+
+
+#: Largest number of samples in a record.
+MAX_SAMPLES = 14
+
+#: UDP port of the stream on the core device.
+PORT = 1385
+
+_MAGIC = b"ARST"
+_SUBSCRIPTION_INTERVAL = 5.0
+
+
+@syscall(flags={"nowrite"})
+def stream_push(stream: TInt32, samples: TList(TInt32)) -> TNone:
+    """Pushes a record of ``samples`` to the UDP stream, under the stream
+    number ``stream`` (0 to 65535) that tells records apart on the host.
+
+    :raises ValueError: if the record holds more than :data:`MAX_SAMPLES`
+        samples, or the stream number is out of range.
+    """
+    raise NotImplementedError("syscall not simulated")
+
+
+class StreamReceiver:
+    """Subscribes to the UDP stream of the core device at ``host``, and
+    receives its records.
+
+    The subscription is renewed while records are received, and lapses on
+    the core device 10 seconds after the last renewal, e.g. when the
+    receiver is no longer used; :meth:`close` ends it at once.
+    """
+    def __init__(self, host, port=PORT):
+        self.address = (host, port)
+        self.socket = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+        self.subscribed_at = None
+        self.sequence = None
+        #: Number of records dropped by the core device, and of datagrams
+        #: lost on the network, since the receiver was created.
+        self.dropped_records = 0
+        self.lost_datagrams = 0
+
+    def _subscribe(self):
+        now = time.monotonic()
+        if (self.subscribed_at is None
+                or now - self.subscribed_at > _SUBSCRIPTION_INTERVAL):
+            self.socket.sendto(b"S", self.address)
+            self.subscribed_at = now
+
+    def receive(self, timeout=None):
+        """Waits for the next datagram and returns its records, as a list
+        of ``(stream, samples)`` tuples, or ``None`` if no datagram arrives
+        within ``timeout`` seconds."""
+        self._subscribe()
+        # Wake up to renew the subscription while no records arrive.
+        self.socket.settimeout(_SUBSCRIPTION_INTERVAL
+                               if timeout is None
+                               else min(timeout, _SUBSCRIPTION_INTERVAL))
+        deadline = None if timeout is None else time.monotonic() + timeout
+        while True:
+            try:
+                datagram, address = self.socket.recvfrom(65536)
+            except socket.timeout:
+                if deadline is not None and time.monotonic() >= deadline:
+                    return None
+                self._subscribe()
+                continue
+            if len(datagram) < 14 or datagram[:4] != _MAGIC:
+                continue
+            sequence, dropped, count = struct.unpack(">LLH", datagram[4:14])
+            if self.sequence is not None:
+                self.lost_datagrams += \
+                    (sequence - self.sequence - 1) & 0xffffffff
+            self.sequence = sequence
+            self.dropped_records += dropped
+            records = []
+            offset = 14
+            for _ in range(count):
+                stream, length = struct.unpack(
+                    ">HH", datagram[offset:offset + 4])
+                offset += 4
+                records.append((stream, list(struct.unpack(
+                    ">{}l".format(length),
+                    datagram[offset:offset + 4*length]))))
+                offset += 4*length
+            self._subscribe()
+            return records
+
+    def __iter__(self):
+        """Yields the records of the stream as ``(stream, samples)``
+        tuples, as they are received."""
+        while True:
+            yield from self.receive()
+
+    def close(self):
+        """Ends the subscription."""
+        self.socket.sendto(b"U", self.address)
+        self.socket.close()
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, type, value, traceback):
+        self.close()
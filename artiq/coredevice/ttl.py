@@ -11,7 +11,7 @@ import numpy
 from artiq.language.core import *
 from artiq.language.types import *
 from artiq.coredevice.rtio import (rtio_output, rtio_input_timestamp,
-                                   rtio_input_data)
+                                   rtio_input_data, rtio_input_timestamps)
 from artiq.coredevice.exceptions import RTIOOverflow
 
 
@@ -372,6 +372,34 @@ class TTLInOut:
         """
         return rtio_input_timestamp(up_to_timestamp_mu + self.gate_latency_mu, self.channel)
 
+    @kernel
+    def timestamps_mu(self, up_to_timestamp_mu, timestamps):
+        """Store the timestamps of the next RTIO input events into a list,
+        until the list is full or the hardware timestamp counter reaches the
+        given value, and return the number of timestamps stored.
+
+        This retrieves all events in a single call into the core device
+        firmware, which is much faster than calling :meth:`timestamp_mu` for
+        each event when many events are expected, e.g. when photons are
+        counted with time resolution.
+
+        This function does not interact with the timeline cursor.
+
+        :param up_to_timestamp_mu: The timestamp up to which execution is
+            blocked, as for :meth:`timestamp_mu`.
+        :param timestamps: List of ``int64`` to fill with timestamps, in
+            machine units. Entries past the returned count are left unchanged.
+
+        :return: The number of timestamps stored.
+
+        Example::
+
+            timestamps = [int64(0)]*1000
+            n = ttl_input.timestamps_mu(ttl_input.gate_rising(100*us), timestamps)
+        """
+        return rtio_input_timestamps(up_to_timestamp_mu + self.gate_latency_mu,
+                                     self.channel, timestamps)
+
     # Input API: sampling
     @kernel
     def sample_input(self):
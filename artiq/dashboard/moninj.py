@@ -7,6 +7,7 @@ from PyQt5 import QtCore, QtWidgets, QtGui
 from sipyco.sync_struct import Subscriber
 
 from artiq.coredevice.comm_moninj import *
+from artiq.coredevice.sampler import adc_mu_to_volt
 from artiq.gui.tools import LayoutWidget
 from artiq.gui.flowlayout import FlowLayout
 
@@ -183,6 +184,28 @@ class _DDSWidget(_SimpleDisplayWidget):
         return (self.bus_channel, self.channel)
 
 
+# Shows the profile last written by kernels, which the gateware picks up on
+# the SPI bus; the AD9910 itself is not read back.
+class _UrukulWidget(_SimpleDisplayWidget):
+    def __init__(self, dm, spi_channel, channel, sysclk, title):
+        self.spi_channel = spi_channel
+        self.channel = channel
+        self.sysclk = sysclk
+        self.cur_ftw = 0
+        self.cur_pow = 0
+        self.cur_asf = 0
+        _SimpleDisplayWidget.__init__(self, title)
+
+    def refresh_display(self):
+        self.value.setText("<font size=\"4\">{:.7f}</font><font size=\"2\"> MHz</font><br/>"
+                           "<font size=\"2\">{:.4f} turns, amplitude {:.4f} (last written)</font>"
+                           .format(self.cur_ftw*self.sysclk/2**32/1e6,
+                                   self.cur_pow/2**16, self.cur_asf/0x3fff))
+
+    def sort_key(self):
+        return (self.spi_channel, self.channel)
+
+
 class _DACWidget(_SimpleDisplayWidget):
     def __init__(self, dm, spi_channel, channel, title):
         self.spi_channel = spi_channel
@@ -198,11 +221,32 @@ class _DACWidget(_SimpleDisplayWidget):
         return (self.spi_channel, self.channel)
 
 
+# Shows the samples last acquired by kernels; the dashboard does not trigger
+# conversions itself.
+class _ADCWidget(_SimpleDisplayWidget):
+    def __init__(self, dm, spi_channel, channel, title):
+        self.spi_channel = spi_channel
+        self.channel = channel
+        self.cur_value = 0
+        self.cur_gain = 0
+        _SimpleDisplayWidget.__init__(self, "{} ch{}".format(title, channel))
+
+    def refresh_display(self):
+        value = self.cur_value - (1 << 16) if self.cur_value & 0x8000 else self.cur_value
+        self.value.setText("<font size=\"4\">{:.4f}</font><font size=\"2\"> V</font><br/>"
+                           "<font size=\"2\">(last sampled)</font>"
+                           .format(adc_mu_to_volt(value, self.cur_gain)))
+
+    def sort_key(self):
+        return (self.spi_channel, self.channel)
+
+
 _WidgetDesc = namedtuple("_WidgetDesc", "uid comment cls arguments")
 
 
 def setup_from_ddb(ddb):
     core_addr = None
+    core_udp = False
     dds_sysclk = None
     description = set()
 
@@ -214,6 +258,7 @@ def setup_from_ddb(ddb):
             if isinstance(v, dict) and v["type"] == "local":
                 if k == "core":
                     core_addr = v["arguments"]["host"]
+                    core_udp = v.get("moninj_udp", False)
                 elif v["module"] == "artiq.coredevice.ttl":
                     channel = v["arguments"]["channel"]
                     force_out = v["class"] == "TTLOut"
@@ -226,6 +271,24 @@ def setup_from_ddb(ddb):
                     dds_sysclk = v["arguments"]["sysclk"]
                     widget = _WidgetDesc(k, comment, _DDSWidget, (bus_channel, channel, k))
                     description.add(widget)
+                elif (v["module"] == "artiq.coredevice.ad9910"
+                        and v["class"] == "AD9910"
+                        and v["arguments"]["chip_select"] >= 4):
+                    # Requires the AD9910 monitor in the Urukul gateware.
+                    channel = v["arguments"]["chip_select"] - 4
+                    cpld = ddb[v["arguments"]["cpld_device"]]
+                    spi_device = ddb[cpld["arguments"]["spi_device"]]
+                    while isinstance(spi_device, str):
+                        spi_device = ddb[spi_device]
+                    spi_channel = spi_device["arguments"]["channel"]
+                    clk = (cpld["arguments"].get("refclk", 125e6)
+                           / [4, 1, 2, 4][cpld["arguments"].get("clk_div", 0)])
+                    if v["arguments"].get("pll_en", 1):
+                        sysclk = clk*v["arguments"].get("pll_n", 40)
+                    else:
+                        sysclk = clk
+                    widget = _WidgetDesc(k, comment, _UrukulWidget, (spi_channel, channel, sysclk, k))
+                    description.add(widget)
                 elif (   (v["module"] == "artiq.coredevice.ad53xx" and v["class"] == "AD53XX")
                       or (v["module"] == "artiq.coredevice.zotino" and v["class"] == "Zotino")):
                     spi_device = v["arguments"]["spi_device"]
@@ -236,14 +299,23 @@ def setup_from_ddb(ddb):
                     for channel in range(32):
                         widget = _WidgetDesc((k, channel), comment, _DACWidget, (spi_channel, channel, k))
                         description.add(widget)
+                elif v["module"] == "artiq.coredevice.sampler" and v["class"] == "Sampler":
+                    spi_device = ddb[v["arguments"]["spi_adc_device"]]
+                    while isinstance(spi_device, str):
+                        spi_device = ddb[spi_device]
+                    spi_channel = spi_device["arguments"]["channel"]
+                    for channel in range(8):
+                        widget = _WidgetDesc((k, channel), comment, _ADCWidget, (spi_channel, channel, k))
+                        description.add(widget)
         except KeyError:
             pass
-    return core_addr, dds_sysclk, description
+    return core_addr, core_udp, dds_sysclk, description
 
 
 class _DeviceManager:
     def __init__(self):
         self.core_addr = None
+        self.core_udp = False
         self.reconnect_core = asyncio.Event()
         self.core_connection = None
         self.core_connector_task = asyncio.ensure_future(self.core_connector())
@@ -257,18 +329,22 @@ class _DeviceManager:
         self.ttl_widgets = dict()
         self.dds_cb = lambda: None
         self.dds_widgets = dict()
+        self.urukul_widgets = dict()
         self.dac_cb = lambda: None
         self.dac_widgets = dict()
+        self.adc_cb = lambda: None
+        self.adc_widgets = dict()
 
     def init_ddb(self, ddb):
         self.ddb = ddb
         return ddb
 
     def notify(self, mod):
-        core_addr, dds_sysclk, description = setup_from_ddb(self.ddb)
+        core_addr, core_udp, dds_sysclk, description = setup_from_ddb(self.ddb)
 
-        if core_addr != self.core_addr:
+        if core_addr != self.core_addr or core_udp != self.core_udp:
             self.core_addr = core_addr
+            self.core_udp = core_udp
             self.reconnect_core.set()
 
         self.dds_sysclk = dds_sysclk
@@ -287,11 +363,21 @@ class _DeviceManager:
                 widget.deleteLater()
                 del self.dds_widgets[(widget.bus_channel, widget.channel)]
                 self.dds_cb()
+            elif isinstance(widget, _UrukulWidget):
+                self.setup_urukul_monitoring(False, widget.spi_channel, widget.channel)
+                widget.deleteLater()
+                del self.urukul_widgets[(widget.spi_channel, widget.channel)]
+                self.dds_cb()
             elif isinstance(widget, _DACWidget):
                 self.setup_dac_monitoring(False, widget.spi_channel, widget.channel)
                 widget.deleteLater()
                 del self.dac_widgets[(widget.spi_channel, widget.channel)]
                 self.dac_cb()     
+            elif isinstance(widget, _ADCWidget):
+                self.setup_adc_monitoring(False, widget.spi_channel, widget.channel)
+                widget.deleteLater()
+                del self.adc_widgets[(widget.spi_channel, widget.channel)]
+                self.adc_cb()
             else:
                 raise ValueError
 
@@ -309,10 +395,18 @@ class _DeviceManager:
                 self.dds_widgets[(widget.bus_channel, widget.channel)] = widget
                 self.dds_cb()
                 self.setup_dds_monitoring(True, widget.bus_channel, widget.channel)
+            elif isinstance(widget, _UrukulWidget):
+                self.urukul_widgets[(widget.spi_channel, widget.channel)] = widget
+                self.dds_cb()
+                self.setup_urukul_monitoring(True, widget.spi_channel, widget.channel)
             elif isinstance(widget, _DACWidget):
                 self.dac_widgets[(widget.spi_channel, widget.channel)] = widget
                 self.dac_cb()
                 self.setup_dac_monitoring(True, widget.spi_channel, widget.channel)
+            elif isinstance(widget, _ADCWidget):
+                self.adc_widgets[(widget.spi_channel, widget.channel)] = widget
+                self.adc_cb()
+                self.setup_adc_monitoring(True, widget.spi_channel, widget.channel)
             else:
                 raise ValueError
 
@@ -354,10 +448,21 @@ class _DeviceManager:
         if self.core_connection is not None:
             self.core_connection.monitor_probe(enable, bus_channel, channel)
 
+    def setup_urukul_monitoring(self, enable, spi_channel, channel):
+        if self.core_connection is not None:
+            for probe in range(3*channel, 3*channel + 3):
+                self.core_connection.monitor_probe(enable, spi_channel, probe)
+
     def setup_dac_monitoring(self, enable, spi_channel, channel):
         if self.core_connection is not None:
             self.core_connection.monitor_probe(enable, spi_channel, channel)
 
+    def setup_adc_monitoring(self, enable, spi_channel, channel):
+        if self.core_connection is not None:
+            self.core_connection.monitor_probe(enable, spi_channel, channel)
+            # PGIA gains
+            self.core_connection.monitor_probe(enable, spi_channel, 8)
+
     def monitor_cb(self, channel, probe, value):
         if channel in self.ttl_widgets:
             widget = self.ttl_widgets[channel]
@@ -370,10 +475,28 @@ class _DeviceManager:
             widget = self.dds_widgets[(channel, probe)]
             widget.cur_frequency = value*self.dds_sysclk/2**32
             widget.refresh_display()
+        if (channel, probe//3) in self.urukul_widgets:
+            widget = self.urukul_widgets[(channel, probe//3)]
+            if probe % 3 == 0:
+                widget.cur_ftw = value
+            elif probe % 3 == 1:
+                widget.cur_pow = value
+            else:
+                widget.cur_asf = value
+            widget.refresh_display()
         if (channel, probe) in self.dac_widgets:
             widget = self.dac_widgets[(channel, probe)]
             widget.cur_value = value
             widget.refresh_display()
+        if (channel, probe) in self.adc_widgets:
+            widget = self.adc_widgets[(channel, probe)]
+            widget.cur_value = value
+            widget.refresh_display()
+        elif probe == 8:
+            for (spi_channel, adc_channel), widget in self.adc_widgets.items():
+                if spi_channel == channel:
+                    widget.cur_gain = (value >> (2*adc_channel)) & 0b11
+                    widget.refresh_display()
 
     def injection_status_cb(self, channel, override, value):
         if channel in self.ttl_widgets:
@@ -398,7 +521,8 @@ class _DeviceManager:
             new_core_connection = CommMonInj(self.monitor_cb, self.injection_status_cb,
                     self.disconnect_cb)
             try:
-                await new_core_connection.connect(self.core_addr, 1383)
+                await new_core_connection.connect(self.core_addr, 1383,
+                                                  udp=self.core_udp)
             except asyncio.CancelledError:
                 logger.info("cancelled connection to core device moninj")
                 break
@@ -412,8 +536,12 @@ class _DeviceManager:
                     self.setup_ttl_monitoring(True, ttl_channel)
                 for bus_channel, channel in self.dds_widgets.keys():
                     self.setup_dds_monitoring(True, bus_channel, channel)
+                for spi_channel, channel in self.urukul_widgets.keys():
+                    self.setup_urukul_monitoring(True, spi_channel, channel)
                 for spi_channel, channel in self.dac_widgets.keys():
                     self.setup_dac_monitoring(True, spi_channel, channel)
+                for spi_channel, channel in self.adc_widgets.keys():
+                    self.setup_adc_monitoring(True, spi_channel, channel)
 
     async def close(self):
         self.core_connector_task.cancel()
@@ -452,14 +580,18 @@ class MonInj:
         self.ttl_dock = _MonInjDock("TTL")
         self.dds_dock = _MonInjDock("DDS")
         self.dac_dock = _MonInjDock("DAC")
+        self.adc_dock = _MonInjDock("ADC")
 
         self.dm = _DeviceManager()
         self.dm.ttl_cb = lambda: self.ttl_dock.layout_widgets(
                             self.dm.ttl_widgets.values())
         self.dm.dds_cb = lambda: self.dds_dock.layout_widgets(
-                            self.dm.dds_widgets.values())
+                            list(self.dm.dds_widgets.values()) +
+                            list(self.dm.urukul_widgets.values()))
         self.dm.dac_cb = lambda: self.dac_dock.layout_widgets(
                             self.dm.dac_widgets.values())
+        self.dm.adc_cb = lambda: self.adc_dock.layout_widgets(
+                            self.dm.adc_widgets.values())
 
         self.subscriber = Subscriber("devices", self.dm.init_ddb, self.dm.notify)
 
@@ -10,13 +10,20 @@ extern crate board_misoc;
 use core::{ptr, slice};
 use crc::crc32;
 use byteorder::{ByteOrder, BigEndian};
-use board_misoc::{ident, cache, sdram, config, boot, mem as board_mem};
+use board_misoc::{ident, cache, sdram, config, firmware, boot_key, boot, bad_ram,
+                  mem as board_mem};
 #[cfg(has_slave_fpga_cfg)]
 use board_misoc::slave_fpga;
+use board_misoc::clock;
+#[cfg(has_uart)]
+use board_misoc::uart;
 #[cfg(has_ethmac)]
-use board_misoc::{clock, ethmac, net_settings};
+use board_misoc::{ethmac, net_settings};
 use board_misoc::uart_console::Console;
 
+#[cfg(has_uart)]
+mod xmodem;
+
 fn check_integrity() -> bool {
     extern {
         static _begin: u8;
@@ -32,11 +39,89 @@ fn check_integrity() -> bool {
     }
 }
 
-fn memory_test(total: &mut usize, wrong: &mut usize) -> bool {
-    const MEMORY: *mut u32 = board_mem::MAIN_RAM_BASE as *mut u32;
+// Words tested by the address tests, and the size of the blocks that the
+// failure map and the excluded region are made of.
+const MEMORY_TEST_WORDS: usize = 0x100000;
+const MEMORY_BLOCK_WORDS: usize = 0x4000;
+const MEMORY_BLOCKS: usize = MEMORY_TEST_WORDS / MEMORY_BLOCK_WORDS;
+
+struct MemoryTest {
+    total:  usize,
+    wrong:  usize,
+    // Bits that read back wrong at least once.
+    bits:   u32,
+    // Lowest and highest failing word.
+    first:  usize,
+    last:   usize,
+    // Failing words in each block.
+    blocks: [u32; MEMORY_BLOCKS]
+}
+
+impl MemoryTest {
+    fn new() -> MemoryTest {
+        MemoryTest {
+            total:  0,
+            wrong:  0,
+            bits:   0,
+            first:  usize::max_value(),
+            last:   0,
+            blocks: [0; MEMORY_BLOCKS]
+        }
+    }
+
+    fn fail(&mut self, index: usize, expected: u32, actual: u32) {
+        self.wrong += 1;
+        self.bits |= expected ^ actual;
+        if index < self.first { self.first = index }
+        if index > self.last { self.last = index }
+        self.blocks[index / MEMORY_BLOCK_WORDS] += 1;
+    }
+
+    fn passed(&self) -> bool {
+        self.wrong == 0
+    }
+
+    // Main RAM address range made of the blocks with failing words.
+    fn failing_range(&self) -> Option<(usize, usize)> {
+        if self.passed() { return None }
+        let first_block = self.first / MEMORY_BLOCK_WORDS;
+        let last_block = self.last / MEMORY_BLOCK_WORDS;
+        Some((board_mem::MAIN_RAM_BASE + first_block * MEMORY_BLOCK_WORDS * 4,
+              board_mem::MAIN_RAM_BASE + (last_block + 1) * MEMORY_BLOCK_WORDS * 4))
+    }
+}
+
+// Stuck or always flipping bits over the whole range point to a dead chip or
+// connection; errors scattered over the bits and addresses rather point to
+// marginal timing.
+impl core::fmt::Display for MemoryTest {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        writeln!(f, "Memory test failed ({}/{} words incorrect)", self.wrong, self.total)?;
+        writeln!(f, "  failing bits:      0x{:08x}", self.bits)?;
+        writeln!(f, "  failing addresses: 0x{:08x}-0x{:08x}",
+                 board_mem::MAIN_RAM_BASE + self.first * 4,
+                 board_mem::MAIN_RAM_BASE + self.last * 4 + 3)?;
+        writeln!(f, "  failure map ({} KiB per block; . none, o <16, O <256, X more):",
+                 MEMORY_BLOCK_WORDS * 4 / 1024)?;
+        for (line, blocks) in self.blocks.chunks(32).enumerate() {
+            write!(f, "  0x{:08x} ",
+                   board_mem::MAIN_RAM_BASE + line * 32 * MEMORY_BLOCK_WORDS * 4)?;
+            for &count in blocks {
+                write!(f, "{}", match count {
+                    0 => '.',
+                    1...15 => 'o',
+                    16...255 => 'O',
+                    _ => 'X'
+                })?;
+            }
+            writeln!(f, "")?;
+        }
+        Ok(())
+    }
+}
 
-    *total = 0;
-    *wrong = 0;
+fn memory_test(result: &mut MemoryTest) {
+    const MEMORY: *mut u32 = board_mem::MAIN_RAM_BASE as *mut u32;
 
     macro_rules! test {
         (
@@ -48,7 +133,7 @@ fn memory_test(total: &mut usize, wrong: &mut usize) -> bool {
             $prepare;
             for $i in $range {
                 unsafe { ptr::write_volatile(MEMORY.offset($index as isize), $data) };
-                *total += 1;
+                result.total += 1;
             }
 
             cache::flush_cpu_dcache();
@@ -56,8 +141,11 @@ fn memory_test(total: &mut usize, wrong: &mut usize) -> bool {
 
             $prepare;
             for $i in $range {
-                if unsafe { ptr::read_volatile(MEMORY.offset($index as isize)) } != $data {
-                    *wrong += 1;
+                let index = $index as usize;
+                let expected = $data;
+                let actual = unsafe { ptr::read_volatile(MEMORY.offset(index as isize)) };
+                if actual != expected {
+                    result.fail(index, expected, actual);
                 }
             }
         })
@@ -73,13 +161,35 @@ fn memory_test(total: &mut usize, wrong: &mut usize) -> bool {
 
         // Test counter addressing with random data
         test!(let mut seed = 0;
-            for i in (0..0x100000) { MEMORY[i] = prng32(&mut seed) });
+            for i in (0..MEMORY_TEST_WORDS) { MEMORY[i] = prng32(&mut seed) });
 
         // Test random addressing with counter data
         test!(let mut seed = 0;
             for i in (0..0x10000) { MEMORY[prng16(&mut seed)] = i });
     }
-    *wrong == 0
+}
+
+// Text of the memory test failure report, also sent to netboot clients.
+static mut MEMORY_REPORT: [u8; 512] = [0; 512];
+static mut MEMORY_REPORT_LENGTH: usize = 0;
+
+struct MemoryReportWriter;
+
+impl core::fmt::Write for MemoryReportWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        unsafe {
+            let length = core::cmp::min(s.len(), MEMORY_REPORT.len() - MEMORY_REPORT_LENGTH);
+            MEMORY_REPORT[MEMORY_REPORT_LENGTH..MEMORY_REPORT_LENGTH + length]
+                .copy_from_slice(&s.as_bytes()[..length]);
+            MEMORY_REPORT_LENGTH += length;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(has_ethmac)]
+fn memory_report() -> &'static [u8] {
+    unsafe { &MEMORY_REPORT[..MEMORY_REPORT_LENGTH] }
 }
 
 fn startup() -> bool {
@@ -101,12 +211,23 @@ fn startup() -> bool {
         return false
     }
 
-    let (mut total, mut wrong) = (0, 0);
-    if memory_test(&mut total, &mut wrong) {
+    let mut result = MemoryTest::new();
+    memory_test(&mut result);
+    if result.passed() {
         println!("Memory test passed");
+        bad_ram::set(None);
     } else {
-        println!("Memory test failed ({}/{} words incorrect)", wrong, total);
-        return false
+        use core::fmt::Write;
+        let _ = write!(MemoryReportWriter, "{}", result);
+        print!("{}", result);
+        if config::read_str("memtest_continue", |r| r == Ok("1")) {
+            let (start, end) = result.failing_range().unwrap();
+            println!("Continuing with 0x{:08x}-0x{:08x} marked as failing", start, end - 1);
+            bad_ram::set(Some((start, end)));
+        } else {
+            bad_ram::set(None);
+            return false
+        }
     }
 
     true
@@ -148,13 +269,75 @@ fn load_slave_fpga() {
     println!("  ...done");
 }
 
-fn flash_boot() {
-    const FIRMWARE: *mut u8 = board_mem::FLASH_BOOT_ADDRESS as *mut u8;
+// Reconfigures the FPGA with the gateware update, if it is to be booted.
+#[cfg(all(has_spiflash, has_icap))]
+fn boot_gateware() {
+    use board_misoc::gateware;
+
+    println!("Running the {}", gateware::running());
+    match gateware::select() {
+        Ok(true) => {
+            println!("Reconfiguring the FPGA with the gateware update...");
+            unsafe { gateware::reconfigure() }
+        }
+        Ok(false) => (),
+        Err(err) => println!("Gateware update not booted: {}", err)
+    }
+}
+
+// Returns the bank to boot, and the bank to fall back to if it is invalid.
+fn select_bank() -> (firmware::Bank, Option<firmware::Bank>) {
+    let active = firmware::active();
+    match firmware::trial() {
+        None => (active, None),
+        Some(trial) if firmware::trial_started() => {
+            println!("Firmware in bank {} did not check in, rolling back to bank {}",
+                     trial, active);
+            if let Err(err) = firmware::abort_trial() {
+                println!("Failed to clear firmware trial: {}", err);
+            }
+            (active, None)
+        }
+        Some(trial) => {
+            if let Err(err) = firmware::start_trial() {
+                println!("Failed to record firmware trial: {}", err);
+                return (active, None)
+            }
+            println!("Booting firmware in bank {} on trial", trial);
+            (trial, Some(active))
+        }
+    }
+}
+
+// Reports the result of checking the signature of the firmware, and returns
+// whether it may be booted.
+fn check_signature(firmware: &[u8], signature: Option<&[u8; 64]>) -> bool {
+    let status = boot_key::verify(firmware, signature);
+    match status {
+        boot_key::Status::Valid => println!("Firmware signature valid"),
+        boot_key::Status::Unsigned => println!("Firmware is not signed"),
+        boot_key::Status::Invalid => println!("Firmware signature INVALID"),
+        boot_key::Status::NoKey => {
+            if signature.is_some() {
+                println!("No boot key in flash, firmware signature not checked")
+            }
+        }
+    }
+    if status != boot_key::Status::Valid && boot_key::required() {
+        println!("Signed firmware is required, not booting");
+        false
+    } else {
+        true
+    }
+}
+
+fn flash_boot(bank: firmware::Bank) {
+    let firmware_address = bank.address() as *mut u8;
     const MAIN_RAM: *mut u8 = board_mem::MAIN_RAM_BASE as *mut u8;
 
-    println!("Booting from flash...");
+    println!("Booting from flash bank {}...", bank);
 
-    let header = unsafe { slice::from_raw_parts(FIRMWARE, 8) };
+    let header = unsafe { slice::from_raw_parts(firmware_address, 8) };
     let length = BigEndian::read_u32(&header[0..]) as usize;
     let expected_crc = BigEndian::read_u32(&header[4..]);
 
@@ -166,7 +349,7 @@ fn flash_boot() {
         return
     }
 
-    let firmware_in_flash = unsafe { slice::from_raw_parts(FIRMWARE.offset(8), length) };
+    let firmware_in_flash = unsafe { slice::from_raw_parts(firmware_address.offset(8), length) };
     let actual_crc_flash = crc32::checksum_ieee(firmware_in_flash);
 
     if actual_crc_flash == expected_crc {
@@ -175,6 +358,10 @@ fn flash_boot() {
 
         let actual_crc_sdram = crc32::checksum_ieee(firmware_in_sdram);
         if actual_crc_sdram == expected_crc {
+            let signature = firmware::signature(bank, length);
+            if !check_signature(firmware_in_sdram, signature.as_ref()) {
+                return
+            }
             println!("Starting firmware.");
             unsafe { boot::jump(MAIN_RAM as usize) }
         } else {
@@ -187,6 +374,114 @@ fn flash_boot() {
     }
 }
 
+// Serial recovery is requested by sending SERIAL_RECOVERY_KEY, e.g. by
+// holding it down during reset, as the UART keeps what it received during
+// startup. The `serial_recovery_wait_ms` config key gives some more time
+// after startup, which is not waited for by default.
+#[cfg(has_uart)]
+const SERIAL_RECOVERY_KEY: u8 = b'r';
+
+#[cfg(has_uart)]
+fn serial_recovery_requested(timeout_ms: u64) -> bool {
+    let deadline = clock::get_ms() + timeout_ms;
+    loop {
+        match uart::read() {
+            Some(SERIAL_RECOVERY_KEY) => return true,
+            Some(_) => (),
+            None if clock::get_ms() >= deadline => return false,
+            None => ()
+        }
+    }
+}
+
+// Receives a firmware image over the UART and writes it into the bank that is
+// not active, to be booted on trial. Does not return once an image has been
+// written.
+#[cfg(has_uart)]
+fn serial_recovery() {
+    const SIGNATURE_MAGIC: &'static [u8] = b"ASIG";
+
+    loop {
+        println!("Serial recovery: send a firmware image (.fbi file) with XMODEM or YMODEM,");
+        println!("or wait {} s to continue booting.", xmodem::START_TIMEOUT_MS / 1000);
+
+        let buffer = unsafe {
+            slice::from_raw_parts_mut(board_mem::MAIN_RAM_BASE as *mut u8, firmware::BANK_SIZE)
+        };
+        let received = match xmodem::receive(buffer) {
+            Ok(received) => received,
+            Err(xmodem::Error::Timeout) => {
+                println!("");
+                println!("No image received.");
+                return
+            }
+            Err(err) => {
+                // Let the terminal program of the sender finish.
+                let deadline = clock::get_ms() + 2_000;
+                while clock::get_ms() < deadline {}
+                println!("");
+                println!("Serial recovery failed: {}", err);
+                continue
+            }
+        };
+        let buffer = &buffer[..received];
+        println!("");
+        println!("Received {} bytes", received);
+
+        // XMODEM pads the image; find its end from the header.
+        if received < 8 {
+            println!("Image too short");
+            continue
+        }
+        let length = BigEndian::read_u32(&buffer[0..]) as usize;
+        if 8 + length > received {
+            println!("Image truncated (expected {} bytes of firmware)", length);
+            continue
+        }
+        let firmware = &buffer[8..8 + length];
+        let trailer = &buffer[8 + length..];
+        let signature = if trailer.len() >= 4 + 64 && &trailer[..4] == SIGNATURE_MAGIC {
+            let mut signature = [0; 64];
+            signature.copy_from_slice(&trailer[4..4 + 64]);
+            Some(signature)
+        } else {
+            None
+        };
+        let image_length = 8 + length + signature.map_or(0, |_| 4 + 64);
+
+        if crc32::checksum_ieee(firmware) != BigEndian::read_u32(&buffer[4..]) {
+            println!("Firmware CRC failed");
+            continue
+        }
+        if !check_signature(firmware, signature.as_ref()) {
+            continue
+        }
+
+        // A trial that failed to check in is superseded by this image.
+        if let Err(err) = firmware::abort_trial() {
+            println!("Failed to clear firmware trial: {}", err);
+        }
+        println!("Writing firmware to flash...");
+        if firmware::BANKS == 1 {
+            match firmware::overwrite(&buffer[..image_length]) {
+                Ok(()) => {
+                    println!("Firmware written, rebooting");
+                    unsafe { boot::reset() }
+                }
+                Err(err) => println!("Failed to write firmware: {}", err)
+            }
+            continue
+        }
+        match firmware::update(&buffer[..image_length]) {
+            Ok(bank) => {
+                println!("Firmware written to bank {}, rebooting to boot it on trial", bank);
+                unsafe { boot::reset() }
+            }
+            Err(err) => println!("Failed to write firmware: {}", err)
+        }
+    }
+}
+
 #[cfg(has_ethmac)]
 enum NetConnState {
     WaitCommand,
@@ -194,6 +489,7 @@ enum NetConnState {
     FirmwareDownload(usize, usize),
     FirmwareWaitO,
     FirmwareWaitK,
+    Signature(usize),
     #[cfg(has_slave_fpga_cfg)]
     GatewareLength(usize, u8),
     #[cfg(has_slave_fpga_cfg)]
@@ -204,10 +500,20 @@ enum NetConnState {
     GatewareWaitK
 }
 
+// Prints the progress of a download on the UART every 10%.
+#[cfg(has_ethmac)]
+fn report_progress(what: &str, previous: usize, current: usize, total: usize) {
+    if previous * 10 / total != current * 10 / total {
+        println!("{} download: {}% ({}/{} bytes)", what, current * 100 / total, current, total);
+    }
+}
+
 #[cfg(has_ethmac)]
 struct NetConn {
     state: NetConnState,
-    firmware_downloaded: bool
+    firmware_downloaded: bool,
+    firmware_length: usize,
+    signature: Option<[u8; 64]>
 }
 
 #[cfg(has_ethmac)]
@@ -215,13 +521,24 @@ impl NetConn {
     pub fn new() -> NetConn {
         NetConn {
             state: NetConnState::WaitCommand,
-            firmware_downloaded: false
+            firmware_downloaded: false,
+            firmware_length: 0,
+            signature: None
         }
     }
 
     pub fn reset(&mut self) {
         self.state = NetConnState::WaitCommand;
         self.firmware_downloaded = false;
+        self.signature = None;
+    }
+
+    // Whether a command is being received.
+    pub fn in_progress(&self) -> bool {
+        match self.state {
+            NetConnState::WaitCommand => false,
+            _ => true
+        }
     }
 
     // buf must contain at least one byte
@@ -235,6 +552,12 @@ impl NetConn {
                         self.state = NetConnState::FirmwareLength(0, 0);
                         Ok(1)
                     },
+                    b'S' => {
+                        println!("Received firmware signature command");
+                        self.state = NetConnState::Signature(0);
+                        self.signature = Some([0; 64]);
+                        Ok(1)
+                    },
                     #[cfg(has_slave_fpga_cfg)]
                     b'G' => {
                         println!("Received gateware load command");
@@ -244,6 +567,13 @@ impl NetConn {
                     b'B' => {
                         if self.firmware_downloaded {
                             println!("Received boot command");
+                            let firmware = unsafe {
+                                slice::from_raw_parts(board_mem::MAIN_RAM_BASE as *const u8,
+                                                      self.firmware_length)
+                            };
+                            if !check_signature(firmware, self.signature.as_ref()) {
+                                return Err(())
+                            }
                             boot_callback();
                             self.state = NetConnState::WaitCommand;
                             Ok(1)
@@ -263,6 +593,12 @@ impl NetConn {
                 let firmware_length = (firmware_length << 8) | (buf[0] as usize);
                 let recv_bytes = recv_bytes + 1;
                 if recv_bytes == 4 {
+                    if firmware_length == 0 {
+                        println!("Received empty firmware");
+                        return Err(())
+                    }
+                    println!("Downloading {} bytes of firmware", firmware_length);
+                    self.firmware_length = firmware_length;
                     self.state = NetConnState::FirmwareDownload(firmware_length, 0);
                 } else {
                     self.state = NetConnState::FirmwareLength(firmware_length, recv_bytes);
@@ -281,6 +617,7 @@ impl NetConn {
                 let firmware_in_sdram = unsafe { slice::from_raw_parts_mut((board_mem::MAIN_RAM_BASE + recv_bytes) as *mut u8, length) };
                 firmware_in_sdram.copy_from_slice(buf);
 
+                report_progress("Firmware", recv_bytes, recv_bytes + length, firmware_length);
                 let recv_bytes = recv_bytes + length;
                 if recv_bytes == firmware_length {
                     self.state = NetConnState::FirmwareWaitO;
@@ -310,12 +647,30 @@ impl NetConn {
                     Err(())
                 }
             }
+            NetConnState::Signature(recv_bytes) => {
+                let length = if buf.len() > 64 - recv_bytes { 64 - recv_bytes } else { buf.len() };
+                if let Some(ref mut signature) = self.signature {
+                    signature[recv_bytes..recv_bytes + length].copy_from_slice(&buf[..length]);
+                }
+                if recv_bytes + length == 64 {
+                    println!("Firmware signature received");
+                    self.state = NetConnState::WaitCommand;
+                } else {
+                    self.state = NetConnState::Signature(recv_bytes + length);
+                }
+                Ok(length)
+            }
 
             #[cfg(has_slave_fpga_cfg)]
             NetConnState::GatewareLength(gateware_length, recv_bytes) => {
                 let gateware_length = (gateware_length << 8) | (buf[0] as usize);
                 let recv_bytes = recv_bytes + 1;
                 if recv_bytes == 4 {
+                    if gateware_length == 0 {
+                        println!("Received empty gateware");
+                        return Err(())
+                    }
+                    println!("Downloading {} bytes of gateware", gateware_length);
                     if let Err(e) = slave_fpga::prepare() {
                         println!(" Error during slave FPGA preparation: {}", e);
                         return Err(())
@@ -341,6 +696,7 @@ impl NetConn {
                     return Err(())
                 }
 
+                report_progress("Gateware", recv_bytes, recv_bytes + length, gateware_length);
                 let recv_bytes = recv_bytes + length;
                 if recv_bytes == gateware_length {
                     self.state = NetConnState::GatewareWaitO;
@@ -388,9 +744,17 @@ impl NetConn {
     }
 }
 
+// Time without data after which a transfer in progress is abandoned, so that
+// a host that went away does not keep the only connection slot.
 #[cfg(has_ethmac)]
-fn network_boot() {
+const NETBOOT_STALL_MS: i64 = 10_000;
+
+// Returns if `timeout_ms` is given and it elapses with no connection and no
+// firmware downloaded.
+#[cfg(has_ethmac)]
+fn network_boot(timeout_ms: Option<u64>) {
     use smoltcp::wire::IpCidr;
+    use smoltcp::time::{Duration, Instant};
 
     println!("Initializing network...");
 
@@ -400,6 +764,8 @@ fn network_boot() {
     let mut neighbor_map = [None; 2];
     let neighbor_cache =
         smoltcp::iface::NeighborCache::new(&mut neighbor_map[..]);
+    let mut route_map = [None; 1];
+    let routes = smoltcp::iface::Routes::new(&mut route_map[..]);
     let net_addresses = net_settings::get_adresses();
     println!("Network addresses: {}", net_addresses);
     let mut ip_addrs = [
@@ -409,11 +775,12 @@ fn network_boot() {
     ];
     let mut interface = match net_addresses.ipv6_addr {
         Some(addr) => {
-            ip_addrs[2] = IpCidr::new(addr, 0);
+            ip_addrs[2] = addr;
             smoltcp::iface::EthernetInterfaceBuilder::new(net_device)
                        .ethernet_addr(net_addresses.hardware_addr)
                        .ip_addrs(&mut ip_addrs[..])
                        .neighbor_cache(neighbor_cache)
+                       .routes(routes)
                        .finalize()
         }
         None =>
@@ -421,45 +788,105 @@ fn network_boot() {
                        .ethernet_addr(net_addresses.hardware_addr)
                        .ip_addrs(&mut ip_addrs[..2])
                        .neighbor_cache(neighbor_cache)
+                       .routes(routes)
                        .finalize()
     };
 
     let mut rx_storage = [0; 4096];
-    let mut tx_storage = [0; 128];
+    let mut tx_storage = [0; 512];
+
+    let mut dhcp_rx_metadata = [smoltcp::socket::RawPacketMetadata::EMPTY; 1];
+    let mut dhcp_rx_storage = [0; 600];
+    let mut dhcp_tx_metadata = [smoltcp::socket::RawPacketMetadata::EMPTY; 1];
+    let mut dhcp_tx_storage = [0; 600];
 
-    let mut socket_set_entries: [_; 1] = Default::default();
+    let mut socket_set_entries: [_; 2] = Default::default();
     let mut sockets =
         smoltcp::socket::SocketSet::new(&mut socket_set_entries[..]);
 
     let tcp_rx_buffer = smoltcp::socket::TcpSocketBuffer::new(&mut rx_storage[..]);
     let tcp_tx_buffer = smoltcp::socket::TcpSocketBuffer::new(&mut tx_storage[..]);
-    let tcp_socket = smoltcp::socket::TcpSocket::new(tcp_rx_buffer, tcp_tx_buffer);
+    let mut tcp_socket = smoltcp::socket::TcpSocket::new(tcp_rx_buffer, tcp_tx_buffer);
+    // Drop connections to hosts that stopped acknowledging our data.
+    tcp_socket.set_timeout(Some(Duration::from_millis(NETBOOT_STALL_MS as u64)));
     let tcp_handle = sockets.add(tcp_socket);
 
+    // The fallback address stays in use until a lease is obtained.
+    let mut dhcp = if net_addresses.ipv4_dhcp {
+        println!("Requesting an IPv4 address by DHCP...");
+        let rx_buffer = smoltcp::socket::RawSocketBuffer::new(
+            &mut dhcp_rx_metadata[..], &mut dhcp_rx_storage[..]);
+        let tx_buffer = smoltcp::socket::RawSocketBuffer::new(
+            &mut dhcp_tx_metadata[..], &mut dhcp_tx_storage[..]);
+        Some(smoltcp::dhcp::Dhcpv4Client::new(&mut sockets, rx_buffer, tx_buffer,
+                                              Instant::from_millis(clock::get_ms() as i64)))
+    } else {
+        None
+    };
+    let mut dhcp_address = None;
+
     let mut net_conn = NetConn::new();
     let mut boot_time = None;
+    let mut last_data = clock::get_ms() as i64;
+    let mut report_sent = false;
+    let deadline = timeout_ms.map(|timeout_ms| clock::get_ms() + timeout_ms);
 
-    println!("Waiting for connections...");
+    match timeout_ms {
+        Some(timeout_ms) => println!("Waiting for connections for {} ms...", timeout_ms),
+        None => println!("Waiting for connections...")
+    }
 
     loop {
+        #[cfg(has_uart)]
+        {
+            if boot_time.is_none() && uart::read() == Some(SERIAL_RECOVERY_KEY) {
+                serial_recovery();
+                println!("Waiting for connections...");
+            }
+        }
+
         let timestamp = clock::get_ms() as i64;
         {
             let socket = &mut *sockets.get::<smoltcp::socket::TcpSocket>(tcp_handle);
 
             match boot_time {
                 None => {
+                    if !socket.is_active() &&
+                            deadline.map_or(false, |deadline| clock::get_ms() > deadline) {
+                        println!("No network boot within {} ms.", timeout_ms.unwrap());
+                        return
+                    }
                     if !socket.is_open() {
-                        socket.listen(4269).unwrap() // 0x10ad
+                        socket.listen(4269).unwrap(); // 0x10ad
+                        report_sent = false;
+                    }
+                    // Clients that read from the connection get to see why
+                    // the board may misbehave.
+                    if !report_sent && socket.can_send() {
+                        let _ = socket.send_slice(memory_report());
+                        report_sent = true;
                     }
 
                     if socket.may_recv() {
+                        if socket.can_recv() {
+                            last_data = timestamp;
+                        }
                         if socket.recv(|data| {
                                     (data.len(), net_conn.input(data, || { boot_time = Some(timestamp + 20); }).is_err())
                                 }).unwrap() {
+                            println!("Network boot failed, closing connection");
                             net_conn.reset();
                             socket.close();
+                        } else if net_conn.in_progress() && timestamp > last_data + NETBOOT_STALL_MS {
+                            println!("No data received for {} ms, aborting connection",
+                                     NETBOOT_STALL_MS);
+                            net_conn.reset();
+                            socket.abort();
                         }
                     } else if socket.may_send() {
+                        if net_conn.in_progress() {
+                            println!("Connection closed during transfer");
+                        }
                         net_conn.reset();
                         socket.close();
                     }
@@ -473,7 +900,28 @@ fn network_boot() {
             }
         }
 
-        match interface.poll(&mut sockets, smoltcp::time::Instant::from_millis(timestamp)) {
+        if let Some(ref mut dhcp) = dhcp {
+            match dhcp.poll(&mut interface, &mut sockets, Instant::from_millis(timestamp)) {
+                Ok(Some(config)) => {
+                    if let Some(cidr) = config.address {
+                        if dhcp_address != Some(cidr) {
+                            println!("DHCP lease obtained: address {}", cidr);
+                            interface.update_ip_addrs(|addrs| addrs[0] = IpCidr::Ipv4(cidr));
+                            dhcp_address = Some(cidr);
+                        }
+                    }
+                    if let Some(router) = config.router {
+                        if let Err(err) = interface.routes_mut().add_default_ipv4_route(router) {
+                            println!("Cannot set default gateway {}: {}", router, err);
+                        }
+                    }
+                }
+                Ok(None) => (),
+                Err(err) => println!("DHCP error: {}", err)
+            }
+        }
+
+        match interface.poll(&mut sockets, Instant::from_millis(timestamp)) {
             Ok(_) => (),
             Err(smoltcp::Error::Unrecognized) => (),
             Err(err) => println!("Network error: {}", err)
@@ -483,6 +931,11 @@ fn network_boot() {
 
 #[no_mangle]
 pub extern fn main() -> i32 {
+    // The runtime may have enabled the hardware watchdog before restarting,
+    // and memory tests or network boot take longer than its timeout.
+    #[cfg(has_watchdog)]
+    unsafe { board_misoc::csr::watchdog::timeout_ms_write(0) }
+
     println!("");
     println!(r" __  __ _ ____         ____ ");
     println!(r"|  \/  (_) ___|  ___  / ___|");
@@ -494,20 +947,55 @@ pub extern fn main() -> i32 {
     println!("Copyright (c) 2017-2021 M-Labs Limited");
     println!("");
 
-    #[cfg(has_ethmac)]
     clock::init();
+    #[cfg(all(has_spiflash, has_icap))]
+    boot_gateware();
 
     if startup() {
         println!("");
+        #[cfg(has_uart)]
+        {
+            let wait_ms = config::read_str("serial_recovery_wait_ms",
+                |r| r.ok().and_then(|s| s.parse().ok())).unwrap_or(0);
+            if wait_ms > 0 {
+                println!("Press '{}' within {} ms for serial recovery...",
+                         SERIAL_RECOVERY_KEY as char, wait_ms);
+            }
+            if serial_recovery_requested(wait_ms) {
+                serial_recovery();
+            }
+        }
+        let netboot_once = cfg!(has_ethmac) && config::read_str("netboot_once", |r| r == Ok("1"));
+        if netboot_once {
+            println!("Network boot requested by the firmware.");
+            if let Err(err) = config::remove("netboot_once") {
+                println!("Failed to clear network boot request: {}", err);
+            }
+            // Boot from flash after all if nobody shows up, rather than
+            // waiting forever.
+            #[cfg(has_ethmac)]
+            network_boot(Some(config::read_str("netboot_timeout_ms",
+                |r| r.ok().and_then(|s| s.parse().ok())).unwrap_or(60_000)));
+        }
         if !config::read_str("no_flash_boot", |r| r == Ok("1")) {
             #[cfg(has_slave_fpga_cfg)]
             load_slave_fpga();
-            flash_boot();
+            let (bank, fallback) = select_bank();
+            flash_boot(bank);
+            if let Some(fallback) = fallback {
+                flash_boot(fallback);
+            }
         } else {
             println!("Flash booting has been disabled.");
         }
         #[cfg(has_ethmac)]
-        network_boot();
+        network_boot(None);
+        // Without networking, the console is the only way left to get
+        // firmware onto the board.
+        #[cfg(has_uart)]
+        loop {
+            serial_recovery();
+        }
     } else {
         println!("Halting.");
     }
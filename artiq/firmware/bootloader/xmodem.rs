@@ -0,0 +1,207 @@
+use core::fmt;
+use board_misoc::{clock, uart};
+
+// Receiver for XMODEM-CRC and XMODEM-1K, and for single files sent with
+// YMODEM, whose header block is accepted and whose size is used.
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC: u8 = b'C';
+
+// Time before the transfer is started during which the sender is solicited
+// every START_INTERVAL_MS.
+pub const START_TIMEOUT_MS: u64 = 60_000;
+const START_INTERVAL_MS: u64 = 3_000;
+const BLOCK_TIMEOUT_MS: u64 = 10_000;
+const BYTE_TIMEOUT_MS: u64 = 1_000;
+const MAX_ERRORS: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Timeout,
+    Cancelled,
+    TooLarge,
+    OutOfSequence,
+    TooManyErrors
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::Timeout => write!(f, "timed out"),
+            &Error::Cancelled => write!(f, "cancelled by the sender"),
+            &Error::TooLarge => write!(f, "file too large"),
+            &Error::OutOfSequence => write!(f, "block out of sequence"),
+            &Error::TooManyErrors => write!(f, "too many errors")
+        }
+    }
+}
+
+fn read_byte(timeout_ms: u64) -> Option<u8> {
+    let deadline = clock::get_ms() + timeout_ms;
+    while clock::get_ms() < deadline {
+        if let Some(c) = uart::read() {
+            return Some(c)
+        }
+    }
+    None
+}
+
+// Discards the rest of a bad block.
+fn purge() {
+    while read_byte(BYTE_TIMEOUT_MS).is_some() {}
+}
+
+fn cancel() {
+    for _ in 0..3 {
+        uart::write(CAN)
+    }
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// Reads the rest of a block of `size` bytes after its start byte, and
+// returns its number if it was received intact.
+fn read_block(data: &mut [u8; 1024], size: usize) -> Option<u8> {
+    let number = read_byte(BYTE_TIMEOUT_MS)?;
+    let complement = read_byte(BYTE_TIMEOUT_MS)?;
+    for byte in data[..size].iter_mut() {
+        *byte = read_byte(BYTE_TIMEOUT_MS)?;
+    }
+    let crc = ((read_byte(BYTE_TIMEOUT_MS)? as u16) << 8) |
+               (read_byte(BYTE_TIMEOUT_MS)? as u16);
+    if number != !complement || crc != crc16(&data[..size]) {
+        return None
+    }
+    Some(number)
+}
+
+// Parses the file size out of a YMODEM header block: file name, NUL, size in
+// decimal, optionally followed by other fields.
+fn header_size(header: &[u8]) -> Option<usize> {
+    let name_end = header.iter().position(|&c| c == 0)?;
+    let mut size = None;
+    for &c in header[name_end + 1..].iter() {
+        match c {
+            b'0'...b'9' => size = Some(size.unwrap_or(0) * 10 + (c - b'0') as usize),
+            _ => break
+        }
+    }
+    size
+}
+
+/// Receives a file into `buffer` and returns its length. Without a YMODEM
+/// header, that includes the padding of the last block.
+///
+/// Nothing else may be written to the UART during the transfer.
+pub fn receive(buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut data = [0; 1024];
+    let mut length = 0;
+    let mut file_size = None;
+    let mut next_block = 1u8;
+    let mut started = false;
+    let mut errors = 0;
+    let start_deadline = clock::get_ms() + START_TIMEOUT_MS;
+
+    loop {
+        let start = if started {
+            read_byte(BLOCK_TIMEOUT_MS)
+        } else if clock::get_ms() > start_deadline {
+            return Err(Error::Timeout)
+        } else {
+            uart::write(CRC);
+            read_byte(START_INTERVAL_MS)
+        };
+
+        let size = match start {
+            Some(SOH) => 128,
+            Some(STX) => 1024,
+            Some(EOT) if started => {
+                uart::write(ACK);
+                if file_size.is_some() {
+                    // Receive the empty header block ending the YMODEM batch.
+                    uart::write(CRC);
+                    if let Some(STX) = read_byte(BLOCK_TIMEOUT_MS) {
+                        read_block(&mut data, 1024);
+                    } else {
+                        read_block(&mut data, 128);
+                    }
+                    uart::write(ACK);
+                }
+                return Ok(file_size.map_or(length, |size| if size < length { size } else { length }))
+            }
+            Some(CAN) => {
+                if read_byte(BYTE_TIMEOUT_MS) == Some(CAN) {
+                    return Err(Error::Cancelled)
+                }
+                continue
+            }
+            None if started => {
+                errors += 1;
+                if errors > MAX_ERRORS {
+                    cancel();
+                    return Err(Error::TooManyErrors)
+                }
+                uart::write(NAK);
+                continue
+            }
+            // Anything typed before the transfer starts is ignored.
+            _ => continue
+        };
+
+        let number = match read_block(&mut data, size) {
+            Some(number) => number,
+            None => {
+                purge();
+                errors += 1;
+                if errors > MAX_ERRORS {
+                    cancel();
+                    return Err(Error::TooManyErrors)
+                }
+                uart::write(NAK);
+                continue
+            }
+        };
+
+        if !started && number == 0 {
+            file_size = header_size(&data[..size]);
+            if file_size.is_none() {
+                // Empty batch, or a header without a size.
+                uart::write(ACK);
+                cancel();
+                return Err(Error::Cancelled)
+            }
+            uart::write(ACK);
+            continue
+        } else if number == next_block {
+            if length + size > buffer.len() {
+                cancel();
+                return Err(Error::TooLarge)
+            }
+            buffer[length..length + size].copy_from_slice(&data[..size]);
+            length += size;
+            next_block = next_block.wrapping_add(1);
+            started = true;
+            errors = 0;
+        } else if started && number == next_block.wrapping_sub(1) {
+            // Our acknowledgement was lost, and the block was sent again.
+        } else {
+            cancel();
+            return Err(Error::OutOfSequence)
+        }
+        uart::write(ACK);
+    }
+}
@@ -66,7 +66,8 @@ static mut API: &'static [(&'static str, *const ())] = &[
     api!(memcmp, extern { fn memcmp(a: *const u8, b: *mut u8, size: usize); }),
 
     /* libm */
-    // commented out functions are not available with the libm used here, but are available in NAR3.
+    // commented out functions are not available with the libm used here, and ::math
+    // provides some of those it lacks.
     api!(acos),
     api!(acosh),
     api!(asin),
@@ -82,13 +83,13 @@ static mut API: &'static [(&'static str, *const ())] = &[
     api!(erf),
     api!(erfc),
     api!(exp),
-    //api!(exp2),
+    api!(exp2 = ::math::exp2),
     //api!(exp10),
     api!(expm1),
     api!(fabs),
     api!(floor),
-    // api!(fmax),
-    // api!(fmin),
+    api!(fmax = ::math::fmax),
+    api!(fmin = ::math::fmin),
     //api!(fma),
     api!(fmod),
     api!(hypot),
@@ -97,7 +98,8 @@ static mut API: &'static [(&'static str, *const ())] = &[
     api!(jn),
     api!(lgamma),
     api!(log),
-    //api!(log2),
+    api!(log1p = ::math::log1p),
+    api!(log2 = ::math::log2),
     api!(log10),
     api!(nextafter),
     api!(pow),
@@ -107,8 +109,8 @@ static mut API: &'static [(&'static str, *const ())] = &[
     api!(sqrt),
     api!(tan),
     api!(tanh),
-    //api!(tgamma),
-    //api!(trunc),
+    api!(tgamma = ::math::tgamma),
+    api!(trunc = ::math::trunc),
     api!(y0),
     api!(y1),
     api!(yn),
@@ -118,25 +120,41 @@ static mut API: &'static [(&'static str, *const ())] = &[
     api!(__artiq_personality = ::eh_artiq::personality),
     api!(__artiq_raise = ::eh_artiq::raise),
     api!(__artiq_reraise = ::eh_artiq::reraise),
+    api!(__artiq_end_catch = ::eh_artiq::end_catch),
 
     /* proxified syscalls */
     api!(core_log),
+    api!(core_log_record = ::core_log_record),
 
     api!(now = csr::rtio::NOW_HI_ADDR as *const _),
 
     api!(rpc_send = ::rpc_send),
     api!(rpc_send_async = ::rpc_send_async),
     api!(rpc_recv = ::rpc_recv),
+    api!(notify = ::notify),
+    api!(stream_push = ::stream_push),
+    api!(dataset_append = ::dataset_append),
 
     api!(cache_get = ::cache_get),
     api!(cache_put = ::cache_put),
 
+    api!(flash_storage_get = ::flash_storage_get),
+    api!(flash_storage_put = ::flash_storage_put),
+
+    api!(watchdog_set = ::watchdog_set),
+    api!(watchdog_clear = ::watchdog_clear),
+
+    api!(rpc_low_latency = ::rpc_low_latency),
+
+    api!(dds_sync_report = ::dds_sync_report),
+
     api!(mfspr = ::board_misoc::spr::mfspr),
     api!(mtspr = ::board_misoc::spr::mtspr),
 
     /* direct syscalls */
     api!(rtio_init = ::rtio::init),
     api!(rtio_get_destination_status = ::rtio::get_destination_status),
+    api!(rtio_get_destination_age = ::rtio::get_destination_age),
     api!(rtio_get_counter = ::rtio::get_counter),
     api!(rtio_log),
     api!(rtio_output = ::rtio::output),
@@ -144,20 +162,37 @@ static mut API: &'static [(&'static str, *const ())] = &[
     api!(rtio_input_timestamp = ::rtio::input_timestamp),
     api!(rtio_input_data = ::rtio::input_data),
     api!(rtio_input_timestamped_data = ::rtio::input_timestamped_data),
+    api!(rtio_input_timestamps = ::rtio::input_timestamps),
+    api!(rtio_input_timestamped_data_batch = ::rtio::input_timestamped_data_batch),
 
     api!(dma_record_start = ::dma_record_start),
     api!(dma_record_stop = ::dma_record_stop),
     api!(dma_erase = ::dma_erase),
+    api!(dma_record_mark = ::dma_record_mark),
     api!(dma_retrieve = ::dma_retrieve),
+    api!(dma_retrieve_segment = ::dma_retrieve_segment),
     api!(dma_playback = ::dma_playback),
+    api!(dma_playback_repeat = ::dma_playback_repeat),
+    api!(dma_playback_loop = ::dma_playback_loop),
+    api!(dma_playback_stop = ::dma_playback_stop),
+    api!(dma_chain = ::dma_chain),
 
     api!(i2c_start = ::nrt_bus::i2c::start),
     api!(i2c_restart = ::nrt_bus::i2c::restart),
     api!(i2c_stop = ::nrt_bus::i2c::stop),
     api!(i2c_write = ::nrt_bus::i2c::write),
     api!(i2c_read = ::nrt_bus::i2c::read),
+    api!(i2c_write_buffer = ::nrt_bus::i2c::write_buffer),
+    api!(i2c_read_buffer = ::nrt_bus::i2c::read_buffer),
+    api!(i2c_smbus_block_write = ::nrt_bus::i2c::smbus_block_write),
+    api!(i2c_smbus_block_read = ::nrt_bus::i2c::smbus_block_read),
+    api!(eeprom_read = ::nrt_bus::eeprom::read),
+    api!(eeprom_write = ::nrt_bus::eeprom::write),
 
     api!(spi_set_config = ::nrt_bus::spi::set_config),
     api!(spi_write = ::nrt_bus::spi::write),
     api!(spi_read = ::nrt_bus::spi::read),
+    api!(spi_write_buffer = ::nrt_bus::spi::write_buffer),
+    api!(spi_read_buffer = ::nrt_bus::spi::read_buffer),
+    api!(spi_transfer = ::nrt_bus::spi::transfer),
 ];
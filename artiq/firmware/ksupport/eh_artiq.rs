@@ -10,8 +10,8 @@
 // except according to those terms.
 #![allow(private_no_mangle_fns, non_camel_case_types)]
 
-use core::{ptr, mem};
-use cslice::CSlice;
+use core::{cmp, ptr, slice};
+use cslice::{CSlice, AsCSlice};
 use unwind as uw;
 use libc::{c_int, c_void};
 
@@ -46,11 +46,61 @@ const EXCEPTION_CLASS: uw::_Unwind_Exception_Class = 0x4d_4c_42_53_41_52_54_51;
 
 const MAX_BACKTRACE_SIZE: usize = 128;
 
+// Number of exceptions being handled that are reported together with a newly
+// raised one; the oldest ones are dropped beyond that.
+pub const MAX_CONTEXT_SIZE: usize = 4;
+
+// Exceptions outlive the frames that raised them, while they are handled and
+// as the context of others, and their strings may be on the stack of these
+// frames, e.g. a message returned by an RPC. The strings are therefore
+// copied when an exception is raised, the name whole and the others as far
+// as they fit, into buffers that last until the kernel ends: two for the
+// exception in flight, so that a new one can be copied while the previous
+// one is still needed, and one for each exception of the context.
+const STRINGS_SIZE: usize = 512;
+
+static mut INFLIGHT_STRINGS: [[u8; STRINGS_SIZE]; 2] = [[0; STRINGS_SIZE]; 2];
+static mut INFLIGHT_BUFFER: usize = 0;
+static mut CONTEXT_STRINGS: [[u8; STRINGS_SIZE]; MAX_CONTEXT_SIZE] =
+    [[0; STRINGS_SIZE]; MAX_CONTEXT_SIZE];
+
+unsafe fn copy_string(string: CSlice<u8>, buffer: &mut [u8]) -> (CSlice<'static, u8>, usize) {
+    let string = string.as_ref();
+    let length = cmp::min(string.len(), buffer.len());
+    buffer[..length].copy_from_slice(&string[..length]);
+    (slice::from_raw_parts(buffer.as_ptr(), length).as_c_slice(), length)
+}
+
+// Copies the strings of `exception` into `buffer`.
+unsafe fn own(exception: &Exception, buffer: &mut [u8; STRINGS_SIZE]) -> Exception<'static> {
+    let (name, used) = copy_string(exception.name, &mut buffer[..]);
+    let (file, length) = copy_string(exception.file, &mut buffer[used..]);
+    let used = used + length;
+    let (function, length) = copy_string(exception.function, &mut buffer[used..]);
+    let used = used + length;
+    let (message, _) = copy_string(exception.message, &mut buffer[used..]);
+    Exception {
+        name:     name,
+        file:     file,
+        line:     exception.line,
+        column:   exception.column,
+        function: function,
+        message:  message,
+        param:    exception.param
+    }
+}
+
 #[repr(C)]
 struct ExceptionInfo {
     uw_exception:   uw::_Unwind_Exception,
     exception:      Option<Exception<'static>>,
     handled:        bool,
+    // Whether a landing pad for `exception` is running, i.e. an exception raised
+    // now happens during handling of it.
+    active:         bool,
+    // Exceptions that were being handled when `exception` was raised, oldest first.
+    context:        [Option<Exception<'static>>; MAX_CONTEXT_SIZE],
+    context_size:   usize,
     backtrace:      [usize; MAX_BACKTRACE_SIZE],
     backtrace_size: usize
 }
@@ -96,6 +146,7 @@ pub extern fn personality(version: c_int,
                     if actions as u32 & uw::_UA_HANDLER_FRAME as u32 != 0 {
                         exception_info.handled = true
                     }
+                    exception_info.active = true;
 
                     // Pass a pair of the unwinder exception and ARTIQ exception
                     // (which immediately follows).
@@ -139,6 +190,7 @@ extern fn uncaught_exception(_version: c_int,
 
         if actions as u32 & uw::_UA_END_OF_STACK as u32 != 0 {
             ::terminate(&exception_info.exception.unwrap(),
+                        &exception_info.context[..exception_info.context_size],
                         exception_info.backtrace[..exception_info.backtrace_size].as_mut())
         } else {
             uw::_URC_NO_REASON
@@ -156,6 +208,9 @@ static mut INFLIGHT: ExceptionInfo = ExceptionInfo {
     },
     exception:      None,
     handled:        true,
+    active:         false,
+    context:        [None; MAX_CONTEXT_SIZE],
+    context_size:   0,
     backtrace:      [0; MAX_BACKTRACE_SIZE],
     backtrace_size: 0
 };
@@ -163,11 +218,43 @@ static mut INFLIGHT: ExceptionInfo = ExceptionInfo {
 #[export_name="__artiq_raise"]
 #[unwind(allowed)]
 pub unsafe extern fn raise(exception: *const Exception) -> ! {
-    // Zing! The Exception<'a> to Exception<'static> transmute is not really sound in case
-    // the exception is ever captured. Fortunately, they currently aren't, and we save
-    // on the hassle of having to allocate exceptions somewhere except on stack.
-    INFLIGHT.exception = Some(mem::transmute::<Exception, Exception<'static>>(*exception));
+    let reraised = match INFLIGHT.exception {
+        Some(ref inflight) => inflight as *const Exception<'static> as usize == exception as usize,
+        None => false
+    };
+    // The caught exception itself is raised again from its handler.
+    if reraised {
+        let exception = INFLIGHT.exception.unwrap();
+        raise_inflight(exception)
+    }
+
+    // The strings of the new exception may be those of the previous one, which
+    // are in the other buffer.
+    INFLIGHT_BUFFER ^= 1;
+    let exception = own(&*exception, &mut INFLIGHT_STRINGS[INFLIGHT_BUFFER]);
+    match INFLIGHT.exception {
+        Some(previous) if INFLIGHT.active => {
+            if INFLIGHT.context_size == MAX_CONTEXT_SIZE {
+                for index in 1..MAX_CONTEXT_SIZE {
+                    INFLIGHT.context[index - 1] = INFLIGHT.context[index].map(|context|
+                        own(&context, &mut CONTEXT_STRINGS[index - 1]));
+                }
+                INFLIGHT.context_size -= 1;
+            }
+            INFLIGHT.context[INFLIGHT.context_size] =
+                Some(own(&previous, &mut CONTEXT_STRINGS[INFLIGHT.context_size]));
+            INFLIGHT.context_size += 1;
+        }
+        _ => INFLIGHT.context_size = 0
+    }
+    raise_inflight(exception)
+}
+
+// Raises `exception` keeping the context of the exception in flight.
+unsafe fn raise_inflight(exception: Exception<'static>) -> ! {
+    INFLIGHT.exception = Some(exception);
     INFLIGHT.handled   = false;
+    INFLIGHT.active    = false;
 
     let result = uw::_Unwind_RaiseException(&mut INFLIGHT.uw_exception);
     assert!(result == uw::_URC_END_OF_STACK);
@@ -185,7 +272,7 @@ pub unsafe extern fn reraise() -> ! {
 
     if INFLIGHT.handled {
         match INFLIGHT.exception {
-            Some(ref exception) => raise(exception),
+            Some(exception) => raise_inflight(exception),
             None => raise(&Exception {
                 name:     "0:artiq.coredevice.exceptions.RuntimeError".as_c_slice(),
                 file:     file!().as_c_slice(),
@@ -201,3 +288,10 @@ pub unsafe extern fn reraise() -> ! {
         uw::_Unwind_Resume(&mut INFLIGHT.uw_exception)
     }
 }
+
+// Called when a handler finishes, so that exceptions raised afterwards are not
+// reported as happening during handling of the exception it caught.
+#[export_name="__artiq_end_catch"]
+pub extern fn end_catch() {
+    unsafe { INFLIGHT.active = false }
+}
@@ -14,20 +14,34 @@ extern crate board_artiq;
 extern crate proto_artiq;
 
 use core::{mem, ptr, slice, str};
-use cslice::{CSlice, AsCSlice};
-use io::Cursor;
+use cslice::{CSlice, CMutSlice, AsCSlice};
 use dyld::Library;
-use board_artiq::{mailbox, rpc_queue};
+use board_misoc::exception;
+use board_artiq::{mailbox, rpc_queue, stream_queue};
 use proto_artiq::{kernel_proto, rpc_proto};
 use kernel_proto::*;
 #[cfg(has_rtio_dma)]
 use board_misoc::csr;
 
 fn send(request: &Message) {
+    if profiler::is_full() {
+        send_profile_samples()
+    }
     unsafe { mailbox::send(request as *const _ as usize) }
     while !mailbox::acknowledged() {}
 }
 
+fn send_profile_samples() {
+    profiler::drain(|samples, dropped| {
+        if samples.len() == 0 && dropped == 0 {
+            return
+        }
+        let request = ProfileSamples { samples: samples, dropped: dropped };
+        unsafe { mailbox::send(&request as *const _ as usize) }
+        while !mailbox::acknowledged() {}
+    })
+}
+
 fn recv<R, F: FnOnce(&Message) -> R>(f: F) -> R {
     while mailbox::receive() == 0 {}
     let result = f(unsafe { &*(mailbox::receive() as *const Message) });
@@ -100,6 +114,8 @@ mod eh_artiq;
 mod api;
 mod rtio;
 mod nrt_bus;
+mod profiler;
+mod math;
 
 static mut LIBRARY: Option<Library<'static>> = None;
 
@@ -114,6 +130,15 @@ pub extern fn send_to_core_log(text: CSlice<u8>) {
     }
 }
 
+#[unwind(aborts)]
+extern fn core_log_record(level: i32, tag: CSlice<u8>, text: CSlice<u8>) {
+    send(&LogRecord {
+        level: level as u8,
+        tag:   str::from_utf8(tag.as_ref()).unwrap_or("(invalid utf-8)"),
+        text:  str::from_utf8(text.as_ref()).unwrap_or("(invalid utf-8)")
+    })
+}
+
 #[no_mangle]
 pub extern fn send_to_rtio_log(text: CSlice<u8>) {
     rtio::log(text.as_ref())
@@ -132,25 +157,61 @@ extern fn rpc_send(service: u32, tag: CSlice<u8>, data: *const *const ()) {
 
 #[unwind(aborts)]
 extern fn rpc_send_async(service: u32, tag: CSlice<u8>, data: *const *const ()) {
-    while rpc_queue::full() {}
-    rpc_queue::enqueue(|mut slice| {
-        let length = {
-            let mut writer = Cursor::new(&mut slice[4..]);
-            rpc_proto::send_args(&mut writer, service, tag.as_ref(), data)?;
-            writer.position()
-        };
-        io::ProtoWrite::write_u32(&mut slice, length as u32)
-    }).unwrap_or_else(|err| {
-        assert!(err == io::Error::UnexpectedEnd);
-
-        while !rpc_queue::empty() {}
-        send(&RpcSend {
-            async:   true,
-            service: service,
-            tag:     tag.as_ref(),
-            data:    data
-        })
-    })
+    // Large arguments are streamed through the queue in chunks, so the kernel
+    // only blocks if the comms CPU cannot keep up.
+    let mut writer = rpc_queue::Writer::new();
+    rpc_proto::send_args(&mut writer, service, tag.as_ref(), data)
+        .expect("cannot serialize async RPC");
+    writer.finish()
+}
+
+static mut NOTIFICATIONS_DROPPED: u32 = 0;
+
+#[unwind(allowed)]
+extern fn notify(tag: CSlice<u8>, data: CSlice<u8>) {
+    if tag.len() + data.len() > rpc_queue::NOTIFICATION_MAX {
+        raise!("ValueError", "notification payload too large")
+    }
+    // Unlike an async RPC, a notification never waits for the comms CPU;
+    // it is dropped if the queue is full, and the host is told how many were.
+    unsafe {
+        if rpc_queue::try_notify(NOTIFICATIONS_DROPPED, tag.as_ref(), data.as_ref()) {
+            NOTIFICATIONS_DROPPED = 0
+        } else {
+            NOTIFICATIONS_DROPPED = NOTIFICATIONS_DROPPED.saturating_add(1)
+        }
+    }
+}
+
+#[unwind(allowed)]
+extern fn dataset_append(key: CSlice<u8>, value: f64) {
+    if key.len() > rpc_queue::DATASET_KEY_MAX {
+        raise!("ValueError", "dataset key too long")
+    }
+    // The comms CPU batches the values and sends them to the host on its own
+    // schedule, so this costs no more than queueing them.
+    rpc_queue::append_dataset(key.as_ref(), value)
+}
+
+static mut STREAM_RECORDS_DROPPED: u32 = 0;
+
+#[unwind(allowed)]
+extern fn stream_push(stream: i32, samples: CSlice<i32>) {
+    if stream < 0 || stream > 0xffff {
+        raise!("ValueError", "invalid stream number {0}", stream as i64, 0, 0)
+    }
+    if samples.len() > stream_queue::MAX_SAMPLES {
+        raise!("ValueError", "stream record of {0} samples exceeds {1}",
+               samples.len() as i64, stream_queue::MAX_SAMPLES as i64, 0)
+    }
+    // Like notifications, records are dropped rather than waited for.
+    unsafe {
+        if stream_queue::try_push(stream as u16, STREAM_RECORDS_DROPPED, samples.as_ref()) {
+            STREAM_RECORDS_DROPPED = 0
+        } else {
+            STREAM_RECORDS_DROPPED = STREAM_RECORDS_DROPPED.saturating_add(1)
+        }
+    }
 }
 
 #[unwind(allowed)]
@@ -175,7 +236,20 @@ extern fn rpc_recv(slot: *mut ()) -> usize {
     })
 }
 
-fn terminate(exception: &eh_artiq::Exception, backtrace: &mut [usize]) -> ! {
+fn proto_exception<'a>(exception: &'a eh_artiq::Exception) -> kernel_proto::Exception<'a> {
+    kernel_proto::Exception {
+        name:     str::from_utf8(exception.name.as_ref()).unwrap(),
+        file:     str::from_utf8(exception.file.as_ref()).unwrap(),
+        line:     exception.line,
+        column:   exception.column,
+        function: str::from_utf8(exception.function.as_ref()).unwrap(),
+        message:  str::from_utf8(exception.message.as_ref()).unwrap(),
+        param:    exception.param,
+    }
+}
+
+fn terminate(exception: &eh_artiq::Exception, context: &[Option<eh_artiq::Exception>],
+             backtrace: &mut [usize]) -> ! {
     let mut cursor = 0;
     for index in 0..backtrace.len() {
         if backtrace[index] > kernel_proto::KERNELCPU_PAYLOAD_ADDRESS {
@@ -185,16 +259,18 @@ fn terminate(exception: &eh_artiq::Exception, backtrace: &mut [usize]) -> ! {
     }
     let backtrace = &mut backtrace.as_mut()[0..cursor];
 
+    profiler::stop();
+    send_profile_samples();
+    let mut context_buf = [proto_exception(exception); eh_artiq::MAX_CONTEXT_SIZE];
+    let mut context_size = 0;
+    for exception in context.iter().filter_map(|exception| exception.as_ref()) {
+        context_buf[context_size] = proto_exception(exception);
+        context_size += 1;
+    }
+
     send(&RunException {
-        exception: kernel_proto::Exception {
-            name:     str::from_utf8(exception.name.as_ref()).unwrap(),
-            file:     str::from_utf8(exception.file.as_ref()).unwrap(),
-            line:     exception.line,
-            column:   exception.column,
-            function: str::from_utf8(exception.function.as_ref()).unwrap(),
-            message:  str::from_utf8(exception.message.as_ref()).unwrap(),
-            param:    exception.param,
-        },
+        exception: proto_exception(exception),
+        context:   &context_buf[..context_size],
         backtrace: backtrace
     });
     loop {}
@@ -214,13 +290,76 @@ extern fn cache_put(key: CSlice<u8>, list: CSlice<i32>) {
         key:   str::from_utf8(key.as_ref()).unwrap(),
         value: list.as_ref()
     });
-    recv!(&CachePutReply { succeeded } => {
+    recv!(&CachePutReply { succeeded, quota_exceeded } => {
+        if quota_exceeded {
+            raise!("CacheError", "cannot put {0} words into the cache: quota exceeded",
+                   list.len() as i64, 0, 0)
+        }
         if !succeeded {
             raise!("CacheError", "cannot put into a busy cache row")
         }
     })
 }
 
+#[unwind(allowed)]
+extern fn flash_storage_get(key: CSlice<u8>, mut value: CMutSlice<i32>) -> i32 {
+    send(&FlashStorageGetRequest {
+        key: str::from_utf8(key.as_ref()).unwrap()
+    });
+    recv!(&FlashStorageGetReply { value: stored } => match stored {
+        Some(stored) => {
+            // Values are stored as big-endian words.
+            for (word, bytes) in value.as_mut().iter_mut().zip(stored.chunks(4)) {
+                *word = bytes.iter().fold(0, |word, &byte| word << 8 | byte as i32)
+            }
+            (stored.len() / 4) as i32
+        }
+        None => -1
+    })
+}
+
+#[unwind(allowed)]
+extern fn flash_storage_put(key: CSlice<u8>, value: CSlice<i32>) {
+    send(&FlashStoragePutRequest {
+        key:   str::from_utf8(key.as_ref()).unwrap(),
+        value: value.as_ref()
+    });
+    recv!(&FlashStoragePutReply { error } => if let Some(error) = error {
+        raise!("FlashStorageError", error)
+    })
+}
+
+#[unwind(aborts)]
+extern fn watchdog_set(ms: i64, scope: CSlice<u8>) -> i32 {
+    send(&WatchdogSetRequest {
+        ms:    if ms < 0 { 0 } else { ms as u64 },
+        scope: str::from_utf8(scope.as_ref()).unwrap()
+    });
+    recv!(&WatchdogSetReply { id } => id) as i32
+}
+
+#[unwind(aborts)]
+extern fn watchdog_clear(id: i32) {
+    send(&WatchdogClear { id: id as usize })
+}
+
+#[unwind(aborts)]
+extern fn rpc_low_latency(duration_ms: i32) {
+    send(&LowLatencyRequest { duration_ms: if duration_ms < 0 { 0 } else { duration_ms as u32 } })
+}
+
+#[unwind(aborts)]
+extern fn dds_sync_report(channel: i32, chip_select: i32, in_delay: i32, window: i32,
+                          io_update_delay: i32) {
+    send(&DdsSyncReport {
+        channel:         channel as u32,
+        chip_select:     chip_select as u8,
+        in_delay:        in_delay as u8,
+        window:          window as u8,
+        io_update_delay: io_update_delay as u8
+    })
+}
+
 const DMA_BUFFER_SIZE: usize = 64 * 1024;
 
 struct DmaRecorder {
@@ -250,6 +389,8 @@ extern fn dma_record_start(name: CSlice<u8>) {
         if DMA_RECORDER.active {
             raise!("DMAError", "DMA is already recording")
         }
+        // The trace recorded over may be the one playing back.
+        dma_check_looping();
 
         let library = LIBRARY.as_ref().unwrap();
         library.rebind(b"rtio_output",
@@ -262,6 +403,24 @@ extern fn dma_record_start(name: CSlice<u8>) {
     }
 }
 
+#[unwind(allowed)]
+extern fn dma_record_mark(name: CSlice<u8>, timestamp: i64) {
+    let name = str::from_utf8(name.as_ref()).unwrap();
+
+    unsafe {
+        if !DMA_RECORDER.active {
+            raise!("DMAError", "DMA is not recording")
+        }
+
+        // The marker refers to the position after the records so far.
+        dma_record_flush();
+        send(&DmaRecordMark {
+            name:      name,
+            timestamp: timestamp as u64
+        });
+    }
+}
+
 #[unwind(allowed)]
 extern fn dma_record_stop(duration: i64) {
     unsafe {
@@ -354,10 +513,12 @@ extern fn dma_record_output_wide(target: i32, words: CSlice<i32>) {
     }
 }
 
-#[unwind(aborts)]
+#[unwind(allowed)]
 extern fn dma_erase(name: CSlice<u8>) {
     let name = str::from_utf8(name.as_ref()).unwrap();
 
+    // The trace erased may be the one playing back.
+    unsafe { dma_check_looping(); }
     send(&DmaEraseRequest { name: name });
 }
 
@@ -387,36 +548,169 @@ extern fn dma_retrieve(name: CSlice<u8>) -> DmaTrace {
     })
 }
 
+#[unwind(allowed)]
+extern fn dma_retrieve_segment(name: CSlice<u8>, marker: CSlice<u8>, offset: i32,
+                               duration: i64) -> DmaTrace {
+    let name = str::from_utf8(name.as_ref()).unwrap();
+    let marker = str::from_utf8(marker.as_ref()).unwrap();
+
+    if offset < 0 {
+        raise!("DMAError", "DMA segment offset {0} is negative", offset as i64, 0, 0)
+    }
+
+    send(&DmaSegmentRequest {
+        name:     name,
+        marker:   marker,
+        offset:   offset as usize,
+        duration: duration
+    });
+    recv!(&DmaSegmentReply(segment) => {
+        segment.map(|(bytes, duration)| DmaTrace {
+            address:  bytes.as_ptr() as i32,
+            duration: duration as i64
+        })
+    }).unwrap_or_else(|error| {
+        match error {
+            DmaSegmentError::TraceNotFound => {
+                println!("DMA trace called {:?} not found", name);
+                raise!("DMAError", "DMA trace not found")
+            }
+            DmaSegmentError::MarkerNotFound => {
+                println!("DMA marker called {:?} not found in trace {:?}", marker, name);
+                raise!("DMAError", "DMA marker not found")
+            }
+            DmaSegmentError::InvalidOffset =>
+                raise!("DMAError", "DMA segment offset {0} is not at the start of a record",
+                       offset as i64, 0, 0),
+            DmaSegmentError::InvalidDuration =>
+                raise!("DMAError", "DMA segment extends past the end of the trace")
+        }
+    })
+}
+
+#[unwind(allowed)]
+extern fn dma_chain(name: CSlice<u8>, trace: CSlice<u8>, first: bool) {
+    let name = str::from_utf8(name.as_ref()).unwrap();
+    let trace = str::from_utf8(trace.as_ref()).unwrap();
+
+    send(&DmaChainRequest { name: name, trace: trace, first: first });
+    if !recv!(&DmaChainReply { found } => found) {
+        println!("DMA trace called {:?} not found", trace);
+        raise!("DMAError",
+            "DMA trace not found");
+    }
+}
+
+// Playback that runs in the background until dma_playback_stop, if any.
+#[cfg(has_rtio_dma)]
+static mut DMA_LOOPING: bool = false;
+
+// Traces cannot change while the DMA engine may be reading them.
+#[cfg(has_rtio_dma)]
+unsafe fn dma_check_looping() {
+    if DMA_LOOPING {
+        raise!("DMAError", "DMA is playing back in a loop")
+    }
+}
+
+#[cfg(not(has_rtio_dma))]
+unsafe fn dma_check_looping() {}
+
+#[cfg(has_rtio_dma)]
+unsafe fn dma_playback_start(timestamp: i64, ptr: i32, period: i64, passes: i32) {
+    assert!(ptr % 64 == 0);
+
+    if DMA_LOOPING {
+        raise!("DMAError", "DMA is already playing back in a loop")
+    }
+
+    csr::rtio_dma::base_address_write(ptr as u64);
+    csr::rtio_dma::time_offset_write(timestamp as u64);
+    csr::rtio_dma::period_write(period as u64);
+    csr::rtio_dma::passes_write(passes as u32);
+
+    csr::cri_con::selected_write(1);
+    csr::rtio_dma::enable_write(1);
+}
+
+// Stops a loop left running by a previous kernel, which would keep the
+// RTIO core from the CPU.
+#[cfg(has_rtio_dma)]
+unsafe fn dma_playback_reset() {
+    if csr::rtio_dma::enable_read() != 0 {
+        csr::rtio_dma::stop_write(1);
+        while csr::rtio_dma::enable_read() != 0 {}
+    }
+    csr::cri_con::selected_write(0);
+    csr::rtio_dma::error_write(1);
+}
+
+#[cfg(has_rtio_dma)]
+unsafe fn dma_playback_finish() {
+    while csr::rtio_dma::enable_read() != 0 {}
+    csr::cri_con::selected_write(0);
+
+    let error = csr::rtio_dma::error_read();
+    if error != 0 {
+        let timestamp = csr::rtio_dma::error_timestamp_read();
+        let channel = csr::rtio_dma::error_channel_read();
+        csr::rtio_dma::error_write(1);
+        if error & 1 != 0 {
+            raise!("RTIOUnderflow",
+                "RTIO underflow at {0} mu, channel {rtio_channel_info:1}",
+                timestamp as i64, channel as i64, 0);
+        }
+        if error & 2 != 0 {
+            raise!("RTIODestinationUnreachable",
+                "RTIO destination unreachable, output, at {0} mu, channel {rtio_channel_info:1}",
+                timestamp as i64, channel as i64, 0);
+        }
+    }
+}
+
 #[cfg(has_rtio_dma)]
 #[unwind(allowed)]
 extern fn dma_playback(timestamp: i64, ptr: i32) {
-    assert!(ptr % 64 == 0);
+    unsafe {
+        dma_playback_start(timestamp, ptr, 0, 1);
+        dma_playback_finish();
+    }
+}
+
+#[cfg(has_rtio_dma)]
+#[unwind(allowed)]
+extern fn dma_playback_repeat(timestamp: i64, ptr: i32, period: i64, count: i32) {
+    if count <= 0 {
+        raise!("DMAError", "DMA playback repeat count must be positive")
+    }
 
     unsafe {
-        csr::rtio_dma::base_address_write(ptr as u64);
-        csr::rtio_dma::time_offset_write(timestamp as u64);
+        dma_playback_start(timestamp, ptr, period, count);
+        dma_playback_finish();
+    }
+}
 
-        csr::cri_con::selected_write(1);
-        csr::rtio_dma::enable_write(1);
-        while csr::rtio_dma::enable_read() != 0 {}
-        csr::cri_con::selected_write(0);
-
-        let error = csr::rtio_dma::error_read();
-        if error != 0 {
-            let timestamp = csr::rtio_dma::error_timestamp_read();
-            let channel = csr::rtio_dma::error_channel_read();
-            csr::rtio_dma::error_write(1);
-            if error & 1 != 0 {
-                raise!("RTIOUnderflow",
-                    "RTIO underflow at {0} mu, channel {1}",
-                    timestamp as i64, channel as i64, 0);
-            }
-            if error & 2 != 0 {
-                raise!("RTIODestinationUnreachable",
-                    "RTIO destination unreachable, output, at {0} mu, channel {1}",
-                    timestamp as i64, channel as i64, 0);
-            }
+#[cfg(has_rtio_dma)]
+#[unwind(allowed)]
+extern fn dma_playback_loop(timestamp: i64, ptr: i32, period: i64) {
+    unsafe {
+        dma_playback_start(timestamp, ptr, period, 0);
+        DMA_LOOPING = true;
+    }
+}
+
+#[cfg(has_rtio_dma)]
+#[unwind(allowed)]
+extern fn dma_playback_stop() -> i32 {
+    unsafe {
+        if !DMA_LOOPING {
+            raise!("DMAError", "DMA is not playing back in a loop")
         }
+        DMA_LOOPING = false;
+
+        csr::rtio_dma::stop_write(1);
+        dma_playback_finish();
+        csr::rtio_dma::passes_done_read() as i32
     }
 }
 
@@ -426,6 +720,24 @@ extern fn dma_playback(_timestamp: i64, _ptr: i32) {
     unimplemented!("not(has_rtio_dma)")
 }
 
+#[cfg(not(has_rtio_dma))]
+#[unwind(allowed)]
+extern fn dma_playback_repeat(_timestamp: i64, _ptr: i32, _period: i64, _count: i32) {
+    unimplemented!("not(has_rtio_dma)")
+}
+
+#[cfg(not(has_rtio_dma))]
+#[unwind(allowed)]
+extern fn dma_playback_loop(_timestamp: i64, _ptr: i32, _period: i64) {
+    unimplemented!("not(has_rtio_dma)")
+}
+
+#[cfg(not(has_rtio_dma))]
+#[unwind(allowed)]
+extern fn dma_playback_stop() -> i32 {
+    unimplemented!("not(has_rtio_dma)")
+}
+
 unsafe fn attribute_writeback(typeinfo: *const ()) {
     struct Attr {
         offset: usize,
@@ -471,14 +783,18 @@ pub unsafe fn main() {
                                           kernel_proto::KERNELCPU_LAST_ADDRESS -
                                           kernel_proto::KERNELCPU_PAYLOAD_ADDRESS);
 
-    let library = recv!(&LoadRequest(library) => {
-        match Library::load(library, image, &api::resolve) {
+    let library = recv!(&LoadRequest { library, relocated } => {
+        let result = match relocated {
+            Some(relocated) => Library::load_relocated(library, image, relocated),
+            None => Library::load(library, image, &api::resolve)
+        };
+        match result {
             Err(error) => {
                 send(&LoadReply(Err(error)));
                 loop {}
             },
             Ok(library) => {
-                send(&LoadReply(Ok(())));
+                send(&LoadReply(Ok(library.image_len())));
                 library
             }
         }
@@ -491,6 +807,12 @@ pub unsafe fn main() {
 
     LIBRARY = Some(library);
 
+    #[cfg(has_rtio_dma)]
+    dma_playback_reset();
+
+    send(&ProfilerRequest);
+    recv!(&ProfilerReply { interval_cycles } => profiler::start(interval_cycles));
+
     ptr::write_bytes(__bss_start as *mut u8, 0, (_end - __bss_start) as usize);
 
     (mem::transmute::<u32, fn()>(__modinit__))();
@@ -512,6 +834,8 @@ pub unsafe fn main() {
     // the async RPC would be missed.
     send(&RpcFlush);
 
+    profiler::stop();
+    send_profile_samples();
     send(&RunFinished);
 
     loop {}
@@ -519,7 +843,15 @@ pub unsafe fn main() {
 
 #[no_mangle]
 #[unwind(allowed)]
-pub extern fn exception(vect: u32, _regs: *const u32, pc: u32, ea: u32) {
+pub extern fn exception(vect: u32, regs: *const u32, pc: u32, ea: u32, sr: u32) {
+    if vect == 5 {
+        // Tick timer; r9 (the link register) is saved at regs[7].
+        let lr = unsafe { *regs.offset(7) };
+        profiler::sample(pc as usize, lr as usize);
+        return
+    }
+    let dump = unsafe { exception::Dump::capture(vect, regs, pc, ea, sr) };
+    send(&ExceptionDump(format_args!("{}", dump)));
     panic!("exception {:?} at PC 0x{:x}, EA 0x{:x}", vect, pc, ea)
 }
 
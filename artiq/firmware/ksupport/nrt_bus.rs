@@ -1,4 +1,5 @@
 pub mod i2c {
+    use cslice::{CSlice, CMutSlice};
     use ::send;
     use ::recv;
     use kernel_proto::*;
@@ -43,9 +44,95 @@ pub mod i2c {
             data
         }) as i32
     }
+
+    pub extern fn write_buffer(busno: i32, data: CSlice<i32>) -> i32 {
+        send(&I2cWriteBufferRequest { busno: busno as u32, data: data.as_ref() });
+        recv!(&I2cWriteBufferReply { succeeded, acked } => {
+            if !succeeded {
+                raise!("I2CError", "I2C bus could not be accessed");
+            }
+            acked
+        }) as i32
+    }
+
+    pub extern fn read_buffer(busno: i32, mut data: CMutSlice<i32>) {
+        let data = data.as_mut();
+        send(&I2cReadBufferRequest { busno: busno as u32, length: data.len() });
+        recv!(&I2cReadBufferReply { succeeded, data: reply } => {
+            if !succeeded {
+                raise!("I2CError", "I2C bus could not be accessed");
+            }
+            for (dst, &src) in data.iter_mut().zip(reply.iter()) {
+                *dst = src as i32;
+            }
+        })
+    }
+
+    pub extern fn smbus_block_write(busno: i32, address: i32, ten_bit: bool, command: i32,
+                                    data: CSlice<i32>, pec: bool) {
+        send(&SmbusBlockWriteRequest {
+            busno: busno as u32, address: address as u16, ten_bit: ten_bit,
+            command: command as u8, data: data.as_ref(), pec: pec
+        });
+        recv!(&SmbusBlockWriteReply(ref result) => if let Err(error) = *result {
+            raise!("I2CError", error)
+        })
+    }
+
+    pub extern fn smbus_block_read(busno: i32, address: i32, ten_bit: bool, command: i32,
+                                   mut data: CMutSlice<i32>, pec: bool) -> i32 {
+        let data = data.as_mut();
+        send(&SmbusBlockReadRequest {
+            busno: busno as u32, address: address as u16, ten_bit: ten_bit,
+            command: command as u8, length: data.len(), pec: pec
+        });
+        recv!(&SmbusBlockReadReply(ref result) => match *result {
+            Ok(reply) => {
+                for (dst, &src) in data.iter_mut().zip(reply.iter()) {
+                    *dst = src as i32;
+                }
+                reply.len() as i32
+            }
+            Err(error) => raise!("I2CError", error)
+        })
+    }
+}
+
+pub mod eeprom {
+    use cslice::{CSlice, CMutSlice};
+    use ::send;
+    use ::recv;
+    use kernel_proto::*;
+
+    pub extern fn read(busno: i32, port: i32, address: i32, size: i32, page_size: i32,
+                       offset: i32, mut data: CMutSlice<i32>) {
+        let data = data.as_mut();
+        send(&EepromReadRequest {
+            busno: busno as u32, port: port, address: address as u8, size: size as u32,
+            page_size: page_size as u32, offset: offset as u32, length: data.len()
+        });
+        recv!(&EepromReadReply(ref result) => match *result {
+            Ok(reply) => for (dst, &src) in data.iter_mut().zip(reply.iter()) {
+                *dst = src as i32;
+            },
+            Err(error) => raise!("I2CError", error)
+        })
+    }
+
+    pub extern fn write(busno: i32, port: i32, address: i32, size: i32, page_size: i32,
+                        offset: i32, data: CSlice<i32>) {
+        send(&EepromWriteRequest {
+            busno: busno as u32, port: port, address: address as u8, size: size as u32,
+            page_size: page_size as u32, offset: offset as u32, data: data.as_ref()
+        });
+        recv!(&EepromWriteReply(ref result) => if let Err(error) = *result {
+            raise!("I2CError", error)
+        })
+    }
 }
 
 pub mod spi {
+    use cslice::{CSlice, CMutSlice};
     use ::send;
     use ::recv;
     use kernel_proto::*;
@@ -74,4 +161,34 @@ pub mod spi {
             data
         }) as i32
     }
+
+    pub extern fn write_buffer(busno: i32, data: CSlice<i32>) {
+        send(&SpiWriteBufferRequest { busno: busno as u32, data: data.as_ref() });
+        recv!(&SpiBasicReply { succeeded } => if !succeeded {
+            raise!("SPIError", "SPI bus could not be accessed");
+        });
+    }
+
+    fn receive_buffer(data: &mut [i32]) {
+        recv!(&SpiReadBufferReply { succeeded, data: reply } => {
+            if !succeeded {
+                raise!("SPIError", "SPI bus could not be accessed");
+            }
+            for (dst, &src) in data.iter_mut().zip(reply.iter()) {
+                *dst = src as i32;
+            }
+        })
+    }
+
+    pub extern fn read_buffer(busno: i32, mut data: CMutSlice<i32>) {
+        let data = data.as_mut();
+        send(&SpiReadBufferRequest { busno: busno as u32, length: data.len() });
+        receive_buffer(data)
+    }
+
+    pub extern fn transfer(busno: i32, mut data: CMutSlice<i32>) {
+        let data = data.as_mut();
+        send(&SpiTransferRequest { busno: busno as u32, data: &*data });
+        receive_buffer(data)
+    }
 }
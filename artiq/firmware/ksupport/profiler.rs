@@ -0,0 +1,76 @@
+// Samples the kernel CPU program counter using the OR1K tick timer.
+//
+// The tick timer exception only records samples; they are handed over to
+// the comms CPU from regular code, since the mailbox may be in use by the
+// code that was interrupted.
+
+use board_misoc::spr::*;
+use kernel_proto::KERNELCPU_PAYLOAD_ADDRESS;
+
+const BUFFER_SIZE: usize = 256;
+
+static mut SAMPLES: [(usize, usize); BUFFER_SIZE] = [(0, 0); BUFFER_SIZE];
+static mut LENGTH: usize = 0;
+static mut DROPPED: u32 = 0;
+static mut INTERVAL: u32 = 0;
+
+pub fn start(interval_cycles: u32) {
+    if interval_cycles == 0 {
+        return
+    }
+
+    unsafe {
+        INTERVAL = interval_cycles & SPR_TTMR_TP;
+        mtspr(SPR_TTCR, 0);
+        mtspr(SPR_TTMR, SPR_TTMR_RT | SPR_TTMR_IE | INTERVAL);
+        mtspr(SPR_SR, mfspr(SPR_SR) | SPR_SR_TEE);
+    }
+}
+
+pub fn stop() {
+    unsafe {
+        mtspr(SPR_SR, mfspr(SPR_SR) & !SPR_SR_TEE);
+        mtspr(SPR_TTMR, SPR_TTMR_DI);
+        INTERVAL = 0;
+    }
+}
+
+// Called from the tick timer exception with the interrupted PC and the
+// link register, which approximates the call site of the current function.
+pub fn sample(pc: usize, lr: usize) {
+    unsafe {
+        if pc > KERNELCPU_PAYLOAD_ADDRESS {
+            let caller = if lr > KERNELCPU_PAYLOAD_ADDRESS {
+                // Return address points past the delay slot of the call.
+                lr - 2 * 4 - KERNELCPU_PAYLOAD_ADDRESS
+            } else {
+                0
+            };
+            if LENGTH < BUFFER_SIZE {
+                SAMPLES[LENGTH] = (pc - KERNELCPU_PAYLOAD_ADDRESS, caller);
+                LENGTH += 1;
+            } else {
+                DROPPED += 1;
+            }
+        }
+
+        // Acknowledge the interrupt and re-arm the timer.
+        mtspr(SPR_TTMR, SPR_TTMR_RT | SPR_TTMR_IE | INTERVAL);
+    }
+}
+
+// Whether the buffer should be drained before it overflows.
+pub fn is_full() -> bool {
+    unsafe { LENGTH >= BUFFER_SIZE / 2 }
+}
+
+pub fn drain<F: FnOnce(&[(usize, usize)], u32)>(f: F) {
+    unsafe {
+        let sr = mfspr(SPR_SR);
+        mtspr(SPR_SR, sr & !SPR_SR_TEE);
+        f(&SAMPLES[..LENGTH], DROPPED);
+        LENGTH = 0;
+        DROPPED = 0;
+        mtspr(SPR_SR, sr);
+    }
+}
@@ -7,7 +7,7 @@ pub struct TimestampedData {
 #[cfg(has_rtio)]
 mod imp {
     use core::ptr::{read_volatile, write_volatile};
-    use cslice::CSlice;
+    use cslice::{CSlice, CMutSlice};
     use rtio::TimestampedData;
 
     use board_misoc::csr;
@@ -30,12 +30,23 @@ mod imp {
     pub extern fn get_destination_status(destination: i32) -> bool {
         if 0 <= destination && destination <= 255 {
             send(&RtioDestinationStatusRequest { destination: destination as u8 });
-            recv!(&RtioDestinationStatusReply { up } => up)
+            recv!(&RtioDestinationStatusReply { up, .. } => up)
         } else {
             false
         }
     }
 
+    pub extern fn get_destination_age(destination: i32) -> i64 {
+        if 0 <= destination && destination <= 255 {
+            send(&RtioDestinationStatusRequest { destination: destination as u8 });
+            recv!(&RtioDestinationStatusReply { up, age_ms } => {
+                if up { age_ms as i64 } else { -1 }
+            })
+        } else {
+            -1
+        }
+    }
+
     pub extern fn get_counter() -> i64 {
         unsafe {
             csr::rtio::counter_update_write(1);
@@ -65,18 +76,37 @@ mod imp {
         }
         if status & RTIO_O_STATUS_UNDERFLOW != 0 {
             raise!("RTIOUnderflow",
-                "RTIO underflow at {0} mu, channel {1}, slack {2} mu",
+                "RTIO underflow at {0} mu, channel {rtio_channel_info:1}, slack {2} mu",
                 timestamp, channel as i64, timestamp - get_counter());
         }
         if status & RTIO_O_STATUS_DESTINATION_UNREACHABLE != 0 {
             raise!("RTIODestinationUnreachable",
-                "RTIO destination unreachable, output, at {0} mu, channel {1}",
+                "RTIO destination unreachable, output, at {0} mu, channel {rtio_channel_info:1}",
                 timestamp, channel as i64, 0);
         }
     }
 
+    // While a DMA loop plays back, the RTIO core listens to the DMA engine
+    // and would silently drop the events of the kernel.
+    #[inline(always)]
+    unsafe fn check_dma_looping() {
+        #[cfg(has_rtio_dma)]
+        {
+            if ::DMA_LOOPING {
+                dma_looping()
+            }
+        }
+    }
+
+    #[cfg(has_rtio_dma)]
+    #[inline(never)]
+    unsafe fn dma_looping() {
+        raise!("DMAError", "RTIO is in use by a DMA loop playing back")
+    }
+
     pub extern fn output(target: i32, data: i32) {
         unsafe {
+            check_dma_looping();
             csr::rtio::target_write(target as u32);
             // writing target clears o_data
             rtio_o_data_write(0, data as _);
@@ -89,6 +119,7 @@ mod imp {
 
     pub extern fn output_wide(target: i32, data: CSlice<i32>) {
         unsafe {
+            check_dma_looping();
             csr::rtio::target_write(target as u32);
             // writing target clears o_data
             for i in (0..data.len()).rev() {
@@ -103,6 +134,7 @@ mod imp {
 
     pub extern fn input_timestamp(timeout: i64, channel: i32) -> i64 {
         unsafe {
+            check_dma_looping();
             csr::rtio::target_write((channel as u32) << 8);
             csr::rtio::i_timeout_write(timeout as u64);
 
@@ -113,7 +145,7 @@ mod imp {
 
             if status & RTIO_I_STATUS_OVERFLOW != 0 {
                 raise!("RTIOOverflow",
-                    "RTIO input overflow on channel {0}",
+                    "RTIO input overflow on channel {rtio_channel_info:0}",
                     channel as i64, 0, 0);
             }
             if status & RTIO_I_STATUS_WAIT_EVENT != 0 {
@@ -121,7 +153,7 @@ mod imp {
             }
             if status & RTIO_I_STATUS_DESTINATION_UNREACHABLE != 0 {
                 raise!("RTIODestinationUnreachable",
-                    "RTIO destination unreachable, input, on channel {0}",
+                    "RTIO destination unreachable, input, on channel {rtio_channel_info:0}",
                     channel as i64, 0, 0);
             }
 
@@ -131,6 +163,7 @@ mod imp {
 
     pub extern fn input_data(channel: i32) -> i32 {
         unsafe {
+            check_dma_looping();
             csr::rtio::target_write((channel as u32) << 8);
             csr::rtio::i_timeout_write(0xffffffff_ffffffff);
 
@@ -141,12 +174,12 @@ mod imp {
 
             if status & RTIO_I_STATUS_OVERFLOW != 0 {
                 raise!("RTIOOverflow",
-                    "RTIO input overflow on channel {0}",
+                    "RTIO input overflow on channel {rtio_channel_info:0}",
                     channel as i64, 0, 0);
             }
             if status & RTIO_I_STATUS_DESTINATION_UNREACHABLE != 0 {
                 raise!("RTIODestinationUnreachable",
-                    "RTIO destination unreachable, input, on channel {0}",
+                    "RTIO destination unreachable, input, on channel {rtio_channel_info:0}",
                     channel as i64, 0, 0);
             }
 
@@ -156,6 +189,7 @@ mod imp {
 
     pub extern fn input_timestamped_data(timeout: i64, channel: i32) -> TimestampedData {
         unsafe {
+            check_dma_looping();
             csr::rtio::target_write((channel as u32) << 8);
             csr::rtio::i_timeout_write(timeout as u64);
 
@@ -166,7 +200,7 @@ mod imp {
 
             if status & RTIO_I_STATUS_OVERFLOW != 0 {
                 raise!("RTIOOverflow",
-                    "RTIO input overflow on channel {0}",
+                    "RTIO input overflow on channel {rtio_channel_info:0}",
                     channel as i64, 0, 0);
             }
             if status & RTIO_I_STATUS_WAIT_EVENT != 0 {
@@ -174,7 +208,7 @@ mod imp {
             }
             if status & RTIO_I_STATUS_DESTINATION_UNREACHABLE != 0 {
                 raise!("RTIODestinationUnreachable",
-                    "RTIO destination unreachable, input, on channel {0}",
+                    "RTIO destination unreachable, input, on channel {rtio_channel_info:0}",
                     channel as i64, 0, 0);
             }
 
@@ -185,6 +219,59 @@ mod imp {
         }
     }
 
+    // Reads up to timestamps.len() events, stopping at the first timeout,
+    // and returns how many were read.
+    unsafe fn input_batch(timeout: i64, channel: i32, timestamps: &mut [i64],
+                          mut data: Option<&mut [i32]>) -> i32 {
+        check_dma_looping();
+        let length = match data {
+            Some(ref data) => timestamps.len().min(data.len()),
+            None => timestamps.len()
+        };
+        csr::rtio::target_write((channel as u32) << 8);
+
+        for i in 0..length {
+            // writing i_timeout requests the next event
+            csr::rtio::i_timeout_write(timeout as u64);
+
+            let mut status = RTIO_I_STATUS_WAIT_STATUS;
+            while status & RTIO_I_STATUS_WAIT_STATUS != 0 {
+                status = csr::rtio::i_status_read();
+            }
+
+            if status & RTIO_I_STATUS_OVERFLOW != 0 {
+                raise!("RTIOOverflow",
+                    "RTIO input overflow on channel {rtio_channel_info:0}",
+                    channel as i64, 0, 0);
+            }
+            if status & RTIO_I_STATUS_WAIT_EVENT != 0 {
+                return i as i32
+            }
+            if status & RTIO_I_STATUS_DESTINATION_UNREACHABLE != 0 {
+                raise!("RTIODestinationUnreachable",
+                    "RTIO destination unreachable, input, on channel {rtio_channel_info:0}",
+                    channel as i64, 0, 0);
+            }
+
+            timestamps[i] = csr::rtio::i_timestamp_read() as i64;
+            if let Some(ref mut data) = data {
+                data[i] = rtio_i_data_read(0) as i32;
+            }
+        }
+        length as i32
+    }
+
+    pub extern fn input_timestamps(timeout: i64, channel: i32,
+                                   mut timestamps: CMutSlice<i64>) -> i32 {
+        unsafe { input_batch(timeout, channel, timestamps.as_mut(), None) }
+    }
+
+    pub extern fn input_timestamped_data_batch(timeout: i64, channel: i32,
+                                               mut timestamps: CMutSlice<i64>,
+                                               mut data: CMutSlice<i32>) -> i32 {
+        unsafe { input_batch(timeout, channel, timestamps.as_mut(), Some(data.as_mut())) }
+    }
+
     #[cfg(has_rtio_log)]
     pub fn log(data: &[u8]) {
         unsafe {
@@ -214,7 +301,7 @@ mod imp {
 
 #[cfg(not(has_rtio))]
 mod imp {
-    use cslice::CSlice;
+    use cslice::{CSlice, CMutSlice};
     use rtio::TimestampedData;
 
     pub extern fn init() {
@@ -225,6 +312,10 @@ mod imp {
         unimplemented!("not(has_rtio)")
     }
 
+    pub extern fn get_destination_age(_destination: i32) -> i64 {
+        unimplemented!("not(has_rtio)")
+    }
+
     pub extern fn get_counter() -> i64 {
         unimplemented!("not(has_rtio)")
     }
@@ -249,6 +340,17 @@ mod imp {
         unimplemented!("not(has_rtio)")
     }
 
+    pub extern fn input_timestamps(_timeout: i64, _channel: i32,
+                                   _timestamps: CMutSlice<i64>) -> i32 {
+        unimplemented!("not(has_rtio)")
+    }
+
+    pub extern fn input_timestamped_data_batch(_timeout: i64, _channel: i32,
+                                               _timestamps: CMutSlice<i64>,
+                                               _data: CMutSlice<i32>) -> i32 {
+        unimplemented!("not(has_rtio)")
+    }
+
     pub fn log(_data: &[u8]) {
         unimplemented!("not(has_rtio)")
     }
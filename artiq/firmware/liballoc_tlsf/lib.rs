@@ -0,0 +1,381 @@
+#![no_std]
+
+//! Two-level segregated fit allocator.
+//!
+//! Free blocks are kept in lists by size class: the first level splits sizes
+//! by powers of two and the second level splits each power of two into
+//! `SL_INDEX_COUNT` linear steps. Bitmaps of the non-empty lists let `alloc`
+//! find a block that is large enough in constant time, and freed blocks are
+//! immediately joined with their free neighbours, so that fragmentation stays
+//! low over long uptimes.
+
+use core::{ptr, mem, fmt, cmp};
+use core::alloc::{GlobalAlloc, Layout};
+
+// The minimum alignment guaranteed by the architecture.
+const MIN_ALIGN: usize = 4;
+
+const SL_INDEX_COUNT_LOG2: u32 = 4;
+const SL_INDEX_COUNT: usize = 1 << SL_INDEX_COUNT_LOG2;
+const FL_INDEX_SHIFT: u32 = SL_INDEX_COUNT_LOG2 + 2; // log2(MIN_ALIGN)
+const FL_INDEX_MAX: u32 = 30;
+const FL_INDEX_COUNT: usize = (FL_INDEX_MAX - FL_INDEX_SHIFT + 1) as usize;
+// Blocks smaller than this are all in the first level, in steps of MIN_ALIGN.
+const SMALL_BLOCK_SIZE: usize = 1 << FL_INDEX_SHIFT;
+const MAX_BLOCK_SIZE: usize = 1 << FL_INDEX_MAX;
+
+const MAGIC_FREE: usize = 0xDEADDEAD;
+const MAGIC_BUSY: usize = 0xFEEDFEED;
+// Zero-sized block that ends every range, so that it is never joined.
+const MAGIC_LAST: usize = 0xCAFECAFE;
+
+#[derive(Debug)]
+#[repr(C)]
+struct Block {
+    magic:     usize,
+    // Size of the payload, which follows the first three fields.
+    size:      usize,
+    prev_phys: *mut Block,
+    // Only valid in free blocks, where they overlap the payload.
+    next_free: *mut Block,
+    prev_free: *mut Block
+}
+
+const HEADER_SIZE: usize = mem::size_of::<usize>() * 3;
+const MIN_BLOCK_SIZE: usize = mem::size_of::<*mut Block>() * 2;
+
+// Start of every range given to `add`, linking them for `stats`.
+struct Range {
+    next: *mut Range,
+    size: usize
+}
+
+struct Control {
+    fl_bitmap: u32,
+    sl_bitmap: [u32; FL_INDEX_COUNT],
+    blocks:    [[*mut Block; SL_INDEX_COUNT]; FL_INDEX_COUNT],
+    ranges:    *mut Range
+}
+
+pub struct TlsfAlloc {
+    // Kept at the start of the first range given to `add`.
+    control: *mut Control
+}
+
+/// Heap usage summary. Sizes are in bytes and exclude block headers, except
+/// for `total`, which is the whole managed area.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub total:        usize,
+    pub used:         usize,
+    pub free:         usize,
+    /// Largest allocation that can currently succeed, that is, the size of the
+    /// largest free block rounded down to its size class.
+    pub largest_free: usize,
+    pub free_blocks:  usize,
+    pub busy_blocks:  usize
+}
+
+fn round_up(size: usize) -> usize {
+    (size + MIN_ALIGN - 1) & !(MIN_ALIGN - 1)
+}
+
+// Index of the most significant bit set.
+fn fls(word: usize) -> u32 {
+    (mem::size_of::<usize>() * 8) as u32 - 1 - word.leading_zeros()
+}
+
+// Size class of a block of `size` bytes.
+fn mapping_insert(size: usize) -> (usize, usize) {
+    if size < SMALL_BLOCK_SIZE {
+        (0, size / (SMALL_BLOCK_SIZE / SL_INDEX_COUNT))
+    } else {
+        let fl = fls(size);
+        let sl = (size >> (fl - SL_INDEX_COUNT_LOG2)) ^ SL_INDEX_COUNT;
+        ((fl - FL_INDEX_SHIFT + 1) as usize, sl)
+    }
+}
+
+// Size class of which all blocks can hold `size` bytes.
+fn mapping_search(size: usize) -> (usize, usize) {
+    if size < SMALL_BLOCK_SIZE {
+        mapping_insert(size)
+    } else {
+        mapping_insert(size + (1 << (fls(size) - SL_INDEX_COUNT_LOG2)) - 1)
+    }
+}
+
+// Largest size that `mapping_search` maps to the class of a block of `size` bytes.
+fn class_floor(size: usize) -> usize {
+    if size < SMALL_BLOCK_SIZE {
+        size
+    } else {
+        size & !((1 << (fls(size) - SL_INDEX_COUNT_LOG2)) - 1)
+    }
+}
+
+unsafe fn payload(block: *mut Block) -> *mut u8 {
+    (block as *mut u8).offset(HEADER_SIZE as isize)
+}
+
+unsafe fn from_payload(ptr: *mut u8) -> *mut Block {
+    ptr.offset(-(HEADER_SIZE as isize)) as *mut Block
+}
+
+unsafe fn next_phys(block: *mut Block) -> *mut Block {
+    payload(block).offset((*block).size as isize) as *mut Block
+}
+
+unsafe fn first_block(range: *mut Range) -> *mut Block {
+    range.offset(1) as *mut Block
+}
+
+pub const EMPTY: TlsfAlloc = TlsfAlloc { control: 0 as *mut Control };
+
+impl Control {
+    unsafe fn insert(&mut self, block: *mut Block) {
+        let (fl, sl) = mapping_insert((*block).size);
+        let head = self.blocks[fl][sl];
+        (*block).next_free = head;
+        (*block).prev_free = ptr::null_mut();
+        if !head.is_null() {
+            (*head).prev_free = block;
+        }
+        self.blocks[fl][sl] = block;
+        self.fl_bitmap |= 1 << fl;
+        self.sl_bitmap[fl] |= 1 << sl;
+    }
+
+    unsafe fn remove(&mut self, block: *mut Block) {
+        let (fl, sl) = mapping_insert((*block).size);
+        let (next, prev) = ((*block).next_free, (*block).prev_free);
+        if !next.is_null() {
+            (*next).prev_free = prev;
+        }
+        if !prev.is_null() {
+            (*prev).next_free = next;
+        } else {
+            self.blocks[fl][sl] = next;
+            if next.is_null() {
+                self.sl_bitmap[fl] &= !(1 << sl);
+                if self.sl_bitmap[fl] == 0 {
+                    self.fl_bitmap &= !(1 << fl);
+                }
+            }
+        }
+    }
+
+    // Returns a free block of at least `size` bytes, if any.
+    fn find_suitable(&self, size: usize) -> *mut Block {
+        let (mut fl, sl) = mapping_search(size);
+        if fl >= FL_INDEX_COUNT { return ptr::null_mut() }
+
+        let mut sl_map = self.sl_bitmap[fl] & (!0 << sl);
+        if sl_map == 0 {
+            let fl_map = if fl + 1 < FL_INDEX_COUNT { self.fl_bitmap & (!0 << (fl + 1)) }
+                         else { 0 };
+            if fl_map == 0 { return ptr::null_mut() }
+
+            fl = fl_map.trailing_zeros() as usize;
+            sl_map = self.sl_bitmap[fl];
+        }
+        self.blocks[fl][sl_map.trailing_zeros() as usize]
+    }
+}
+
+impl TlsfAlloc {
+    pub unsafe fn add(&mut self, ptr: *mut u8, size: usize) {
+        let mut begin = round_up(ptr as usize);
+        let end = (ptr as usize + size) & !(MIN_ALIGN - 1);
+        if end <= begin { return }
+
+        if self.control.is_null() {
+            if end - begin < mem::size_of::<Control>() { return }
+
+            self.control = begin as *mut Control;
+            ptr::write(self.control, Control {
+                fl_bitmap: 0,
+                sl_bitmap: [0; FL_INDEX_COUNT],
+                blocks:    [[ptr::null_mut(); SL_INDEX_COUNT]; FL_INDEX_COUNT],
+                ranges:    ptr::null_mut()
+            });
+            begin += mem::size_of::<Control>();
+        }
+
+        let overhead = mem::size_of::<Range>() + HEADER_SIZE * 2;
+        if end - begin < overhead + MIN_BLOCK_SIZE { return }
+
+        let control = &mut *self.control;
+        let range = begin as *mut Range;
+        (*range).next = control.ranges;
+        (*range).size = end - begin;
+        control.ranges = range;
+
+        let block = first_block(range);
+        (*block).magic     = MAGIC_FREE;
+        (*block).size      = cmp::min(end - begin - overhead, MAX_BLOCK_SIZE - 1);
+        (*block).prev_phys = ptr::null_mut();
+
+        let last = next_phys(block);
+        (*last).magic     = MAGIC_LAST;
+        (*last).size      = 0;
+        (*last).prev_phys = block;
+
+        control.insert(block);
+    }
+
+    pub unsafe fn add_range(&mut self, begin: *mut u8, end: *mut u8) {
+        self.add(begin, end as usize - begin as usize)
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        if self.control.is_null() { return stats }
+
+        unsafe {
+            stats.total = mem::size_of::<Control>();
+            let mut range = (*self.control).ranges;
+            while !range.is_null() {
+                stats.total += (*range).size;
+
+                let mut curr = first_block(range);
+                loop {
+                    match (*curr).magic {
+                        MAGIC_FREE => {
+                            stats.free += (*curr).size;
+                            stats.free_blocks += 1;
+                            stats.largest_free = cmp::max(stats.largest_free,
+                                                          class_floor((*curr).size));
+                        }
+                        MAGIC_BUSY => {
+                            stats.used += (*curr).size;
+                            stats.busy_blocks += 1;
+                        }
+                        _ => break
+                    }
+
+                    curr = next_phys(curr);
+                }
+
+                range = (*range).next;
+            }
+        }
+
+        stats
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "used {:#x} of {:#x} in {} blocks, {} free blocks, largest free {:#x}",
+               self.used, self.total, self.busy_blocks, self.free_blocks, self.largest_free)
+    }
+}
+
+unsafe impl GlobalAlloc for TlsfAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.align() > MIN_ALIGN {
+            panic!("cannot allocate with alignment {}", layout.align())
+        }
+        if self.control.is_null() || layout.size() >= MAX_BLOCK_SIZE {
+            return ptr::null_mut()
+        }
+
+        let control = &mut *self.control;
+        let size = cmp::max(round_up(layout.size()), MIN_BLOCK_SIZE);
+        let block = control.find_suitable(size);
+        if block.is_null() {
+            return ptr::null_mut()
+        }
+        if (*block).magic != MAGIC_FREE {
+            panic!("heap corruption detected at {:p}", block)
+        }
+        control.remove(block);
+
+        if (*block).size >= size + HEADER_SIZE + MIN_BLOCK_SIZE {
+            // Split
+            let rest = payload(block).offset(size as isize) as *mut Block;
+            (*rest).magic     = MAGIC_FREE;
+            (*rest).size      = (*block).size - size - HEADER_SIZE;
+            (*rest).prev_phys = block;
+            (*block).size = size;
+            (*next_phys(rest)).prev_phys = rest;
+            control.insert(rest);
+        }
+
+        (*block).magic = MAGIC_BUSY;
+        payload(block)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        let control = &mut *self.control;
+        let mut block = from_payload(ptr);
+        if (*block).magic != MAGIC_BUSY {
+            panic!("heap corruption detected at {:p}", block)
+        }
+        (*block).magic = MAGIC_FREE;
+
+        // Join
+        let prev = (*block).prev_phys;
+        if !prev.is_null() && (*prev).magic == MAGIC_FREE {
+            control.remove(prev);
+            (*prev).size += HEADER_SIZE + (*block).size;
+            (*block).magic = 0;
+            block = prev;
+            (*next_phys(block)).prev_phys = block;
+        }
+        let next = next_phys(block);
+        if (*next).magic == MAGIC_FREE {
+            control.remove(next);
+            (*block).size += HEADER_SIZE + (*next).size;
+            (*next).magic = 0;
+            (*next_phys(block)).prev_phys = block;
+        }
+
+        control.insert(block);
+    }
+}
+
+impl fmt::Display for TlsfAlloc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.control.is_null() { return Ok(()) }
+
+        unsafe {
+            let mut total_busy = 0;
+            let mut total_idle = 0;
+            let mut total_meta = mem::size_of::<Control>();
+
+            let mut range = (*self.control).ranges;
+            while !range.is_null() {
+                total_meta += mem::size_of::<Range>();
+
+                let mut curr = first_block(range);
+                loop {
+                    total_meta += HEADER_SIZE;
+
+                    let desc = match (*curr).magic {
+                        MAGIC_FREE => { total_idle += (*curr).size; "IDLE" },
+                        MAGIC_BUSY => { total_busy += (*curr).size; "BUSY" },
+                        MAGIC_LAST => "LAST",
+                        _ => "!!!!"
+                    };
+
+                    write!(f, "{} {:p} + {:#x} + {:#x}\n",
+                           desc, curr, HEADER_SIZE, (*curr).size)?;
+                    match (*curr).magic {
+                        MAGIC_FREE | MAGIC_BUSY => (),
+                        _ => break
+                    }
+
+                    curr = next_phys(curr);
+                }
+
+                range = (*range).next;
+            }
+
+            write!(f, " === busy: {:#x} idle: {:#x} meta: {:#x} total: {:#x}\n",
+                   total_busy, total_idle, total_meta,
+                   total_busy + total_idle + total_meta)
+        }
+    }
+}
@@ -53,28 +53,40 @@ impl fmt::Display for RoutingTable {
     }
 }
 
-pub fn config_routing_table(default_n_links: usize) -> RoutingTable {
-    let mut ret = RoutingTable::default_master(default_n_links);
-    let ok = config::read("routing_table", |result| {
+fn config_read_routing_table(key: &str, table: &mut RoutingTable) -> bool {
+    config::read(key, |result| {
         if let Ok(data) = result {
             if data.len() == DEST_COUNT*MAX_HOPS {
                 for i in 0..DEST_COUNT {
                     for j in 0..MAX_HOPS {
-                        ret.0[i][j] = data[i*MAX_HOPS+j];
+                        table.0[i][j] = data[i*MAX_HOPS+j];
                     }
                 }
                 return true;
             }
         }
         false
-    });
-    if !ok {
+    })
+}
+
+pub fn config_routing_table(default_n_links: usize) -> RoutingTable {
+    let mut ret = RoutingTable::default_master(default_n_links);
+    if !config_read_routing_table("routing_table", &mut ret) {
         warn!("could not read routing table from configuration, using default");
     }
     info!("routing table: {}", ret);
     ret
 }
 
+// the backup routes are optional, destinations without one have no entry
+pub fn config_backup_routing_table() -> RoutingTable {
+    let mut ret = RoutingTable::default_empty();
+    if config_read_routing_table("routing_table_backup", &mut ret) {
+        info!("backup routing table: {}", ret);
+    }
+    ret
+}
+
 #[cfg(has_drtio_routing)]
 pub fn interconnect_enable(routing_table: &RoutingTable, rank: u8, destination: u8) {
     let hop = routing_table.0[destination as usize][rank as usize];
@@ -1,11 +1,19 @@
-use core::slice;
+use core::{ptr, slice};
+use core::cmp::min;
 use crc;
 
 use io::{ProtoRead, ProtoWrite, Cursor, Error as IoError};
 use board_misoc::{csr::DRTIOAUX, mem::DRTIOAUX_MEM, clock};
 use proto_artiq::drtioaux_proto::Error as ProtocolError;
 
-pub use proto_artiq::drtioaux_proto::Packet;
+pub use proto_artiq::drtioaux_proto::{Packet, MONITOR_BATCH_SIZE,
+                                       IDENT_FIRMWARE, IDENT_GATEWARE, IDENT_SERIAL, IDENT_TOPOLOGY,
+                                       CLOCK_CHIP_SI5324, CLOCK_CHIP_SI549_MAIN,
+                                       CLOCK_CHIP_SI549_HELPER, CLOCK_REGISTERS_SIZE,
+                                       FIRMWARE_CHUNK_SIZE, LOG_CHUNK_SIZE,
+                                       SEGMENT_SIZE, MAX_SEGMENTED_SIZE, SEGMENTED_FIRMWARE_BLOCK,
+                                       SEGMENT_OK, SEGMENT_CORRUPTED, SEGMENT_OUT_OF_ORDER,
+                                       SEGMENT_TOO_LARGE, SEGMENT_REJECTED};
 
 // this is parametric over T because there's no impl Fail for !.
 #[derive(Fail, Debug)]
@@ -41,6 +49,27 @@ impl<T> From<IoError<T>> for Error<T> {
     }
 }
 
+// Size of each of the transmit and receive buffers of the gateware.
+const MAX_PACKET: usize = 1024;
+
+// Packets are assembled and checked in this buffer, in cached memory, and
+// copied to or from the buffers of the gateware a word at a time, instead of
+// accessing them a byte at a time over the bus. Sending and receiving never
+// yield, so one buffer serves all links.
+static mut BUFFER: [u32; MAX_PACKET / 4] = [0; MAX_PACKET / 4];
+
+unsafe fn copy_from_gateware(src: *const u32, dst: &mut [u32]) {
+    for (i, word) in dst.iter_mut().enumerate() {
+        *word = ptr::read_volatile(src.offset(i as isize));
+    }
+}
+
+unsafe fn copy_to_gateware(src: &[u32], dst: *mut u32) {
+    for (i, word) in src.iter().enumerate() {
+        ptr::write_volatile(dst.offset(i as isize), *word);
+    }
+}
+
 pub fn reset(linkno: u8) {
     let linkno = linkno as usize;
     unsafe {
@@ -70,8 +99,10 @@ fn receive<F, T>(linkno: u8, f: F) -> Result<Option<T>, Error<!>>
     unsafe {
         if (DRTIOAUX[linkidx].aux_rx_present_read)() == 1 {
             let ptr = DRTIOAUX_MEM[linkidx].base + DRTIOAUX_MEM[linkidx].size / 2;
-            let len = (DRTIOAUX[linkidx].aux_rx_length_read)();
-            let result = f(slice::from_raw_parts(ptr as *mut u8, len as usize));
+            let len = min((DRTIOAUX[linkidx].aux_rx_length_read)() as usize,
+                          min(DRTIOAUX_MEM[linkidx].size / 2, MAX_PACKET));
+            copy_from_gateware(ptr as *const u32, &mut BUFFER[..(len + 3) / 4]);
+            let result = f(slice::from_raw_parts(BUFFER.as_ptr() as *const u8, len));
             (DRTIOAUX[linkidx].aux_rx_present_write)(1);
             Ok(Some(result?))
         } else {
@@ -121,10 +152,12 @@ fn transmit<F>(linkno: u8, f: F) -> Result<(), Error<!>>
 {
     let linkno = linkno as usize;
     unsafe {
+        // assembled while the previous packet is still being transmitted
+        let len = min(DRTIOAUX_MEM[linkno].size / 2, MAX_PACKET);
+        let len = f(slice::from_raw_parts_mut(BUFFER.as_mut_ptr() as *mut u8, len))?;
         while (DRTIOAUX[linkno].aux_tx_read)() != 0 {}
         let ptr = DRTIOAUX_MEM[linkno].base;
-        let len = DRTIOAUX_MEM[linkno].size / 2;
-        let len = f(slice::from_raw_parts_mut(ptr as *mut u8, len))?;
+        copy_to_gateware(&BUFFER[..(len + 3) / 4], ptr as *mut u32);
         (DRTIOAUX[linkno].aux_tx_length_write)(len as u16);
         (DRTIOAUX[linkno].aux_tx_write)(1);
         Ok(())
@@ -150,3 +183,34 @@ pub fn send(linkno: u8, packet: &Packet) -> Result<(), Error<!>> {
         Ok(writer.position())
     })
 }
+
+pub fn segment_crc(data: &[u8]) -> u32 {
+    crc::crc32::checksum_ieee(data)
+}
+
+/// Number of segments `message` is split into; an empty message still
+/// takes one.
+pub fn segment_count(message: &[u8]) -> usize {
+    (message.len() + SEGMENT_SIZE - 1) / SEGMENT_SIZE + (message.is_empty() as usize)
+}
+
+/// Returns the `SegmentRequest` carrying segment `seq` of `message`.
+pub fn segment(destination: u8, kind: u8, transfer: u8, seq: u16, message: &[u8]) -> Packet {
+    let start = min(seq as usize * SEGMENT_SIZE, message.len());
+    let end = min(start + SEGMENT_SIZE, message.len());
+    let bytes = &message[start..end];
+    let mut data = [[0; 32]; 16];
+    for (row, bytes) in data.iter_mut().zip(bytes.chunks(32)) {
+        row[..bytes.len()].copy_from_slice(bytes);
+    }
+    Packet::SegmentRequest {
+        destination: destination,
+        kind: kind,
+        transfer: transfer,
+        seq: seq,
+        last: seq as usize + 1 >= segment_count(message),
+        crc: segment_crc(bytes),
+        length: bytes.len() as u16,
+        data: data
+    }
+}
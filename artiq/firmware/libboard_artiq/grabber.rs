@@ -1,6 +1,6 @@
-use board_misoc::csr;
+use board_misoc::{csr, clock};
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 enum State {
     Reset,
     ExitReset,
@@ -13,10 +13,59 @@ enum State {
 struct Info {
     state: State,
     frame_size: (u16, u16),
+    // Frame counter at the last tick, and when it last changed.
+    frame_count: u32,
+    last_frame_ms: u64,
+    timed_out: bool,
+    // Clock glitches counted while aligned, and the glitch counter at the
+    // last tick.
+    clock_glitches: u32,
+    glitch_count: u32,
 }
 
 static mut INFO: [Info; csr::GRABBER_LEN] =
-    [Info { state: State::Reset, frame_size: (0, 0) }; csr::GRABBER_LEN];
+    [Info {
+        state: State::Reset,
+        frame_size: (0, 0),
+        frame_count: 0,
+        last_frame_ms: 0,
+        timed_out: false,
+        clock_glitches: 0,
+        glitch_count: 0
+    }; csr::GRABBER_LEN];
+
+static mut FRAME_TIMEOUT_MS: Option<u64> = None;
+
+/// Frame statistics of a grabber since boot. Dropped frames have a size
+/// different from the previous frame, e.g. because of a loss of sync.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub aligned: bool,
+    pub frame_size: (u16, u16),
+    pub frames: u32,
+    pub dropped_frames: u32,
+    pub clock_glitches: u32
+}
+
+/// Logs a warning when a grabber receives no frame for `timeout_ms`.
+pub fn set_frame_timeout(timeout_ms: Option<u64>) {
+    unsafe { FRAME_TIMEOUT_MS = timeout_ms }
+}
+
+pub fn stats(g: usize) -> Stats {
+    let info = unsafe { INFO[g] };
+    Stats {
+        aligned: info.state == State::Watch,
+        frame_size: if info.frame_size == (0, 0) {
+            (0, 0)
+        } else {
+            (info.frame_size.0, info.frame_size.1 + 1)
+        },
+        frames: unsafe { (csr::GRABBER[g].frame_count_read)() },
+        dropped_frames: unsafe { (csr::GRABBER[g].dropped_frame_count_read)() },
+        clock_glitches: info.clock_glitches
+    }
+}
 
 fn get_pll_reset(g: usize) -> bool {
     unsafe { (csr::GRABBER[g].pll_reset_read)() != 0 }
@@ -92,8 +141,42 @@ fn get_video_clock(g: usize) -> u32 {
     2*freq_count*(csr::CONFIG_CLOCK_FREQUENCY/1000)/(511*1000)
 }
 
+fn update_stats(g: usize) {
+    let info = unsafe { &mut INFO[g] };
+
+    let glitch_count = unsafe { (csr::GRABBER[g].clk_glitch_count_read)() };
+    if info.state == State::Watch {
+        info.clock_glitches = info.clock_glitches.wrapping_add(
+            glitch_count.wrapping_sub(info.glitch_count));
+    }
+    info.glitch_count = glitch_count;
+
+    let now = clock::get_ms();
+    let frame_count = unsafe { (csr::GRABBER[g].frame_count_read)() };
+    if frame_count != info.frame_count {
+        info.frame_count = frame_count;
+        info.last_frame_ms = now;
+        if info.timed_out {
+            info!("grabber{} receiving frames again", g);
+            info.timed_out = false;
+        }
+    } else if let Some(timeout_ms) = unsafe { FRAME_TIMEOUT_MS } {
+        if !info.timed_out && now - info.last_frame_ms > timeout_ms {
+            let stats = stats(g);
+            warn!("grabber{} no frames: timeout_ms={} state={:?} pll_locked={} \
+                   video_clock_mhz={} frame_size={}x{} frames={} dropped_frames={} \
+                   clock_glitches={}",
+                  g, timeout_ms, info.state, pll_locked(g), get_video_clock(g),
+                  stats.frame_size.0, stats.frame_size.1, stats.frames,
+                  stats.dropped_frames, stats.clock_glitches);
+            info.timed_out = true;
+        }
+    }
+}
+
 pub fn tick() {
     for g in 0..csr::GRABBER.len() {
+        update_stats(g);
         let next = match unsafe { INFO[g].state } {
             State::Reset => {
                 set_pll_reset(g, true);
@@ -23,6 +23,8 @@ pub mod spi;
 pub mod mailbox;
 #[cfg(has_kernel_cpu)]
 pub mod rpc_queue;
+#[cfg(has_kernel_cpu)]
+pub mod stream_queue;
 
 #[cfg(has_si5324)]
 pub mod si5324;
@@ -1,14 +1,45 @@
 use core::ptr::{read_volatile, write_volatile};
 use core::slice;
+use byteorder::{ByteOrder, NetworkEndian};
 use board_misoc::{mem, cache};
+use io;
+use proto_artiq::rpc_proto::RpcWrite;
 
 const SEND_MAILBOX: *mut usize = (mem::MAILBOX_BASE + 4) as *mut usize;
 const RECV_MAILBOX: *mut usize = (mem::MAILBOX_BASE + 8) as *mut usize;
 
 const QUEUE_BEGIN: usize = 0x44000000;
-const QUEUE_END:   usize = 0x44ffff80;
+// The stream queue follows.
+const QUEUE_END:   usize = 0x44c00000;
 const QUEUE_CHUNK: usize = 0x1000;
 
+/// Each chunk starts with the length of its payload. This flag is set in
+/// the length when the message continues in the next chunk.
+pub const CONTINUED: u32 = 0x8000_0000;
+/// Set in the length when the payload is not data but the address and length
+/// of a buffer in kernel memory, to be sent from there.
+pub const REFERENCE: u32 = 0x4000_0000;
+/// Set in the length when the chunk holds a notification instead of an async RPC.
+/// A notification always fits in one chunk and is never continued. Like for
+/// any other chunk, the length does not count its own word: it covers the
+/// number of dropped notifications, the tag length, the tag, the payload
+/// length and the payload, i.e. `NOTIFICATION_FIELDS` plus the tag and payload.
+pub const NOTIFICATION: u32 = 0x2000_0000;
+/// Size of the fixed fields of a notification that follow the chunk length.
+pub const NOTIFICATION_FIELDS: usize = 12;
+
+/// Set in the length when the chunk holds a value to append to a dataset.
+/// Like a notification, it always fits in one chunk.
+pub const DATASET_APPEND: u32 = 0x1000_0000;
+
+/// Largest combined size of the tag and payload of a notification.
+pub const NOTIFICATION_MAX: usize = QUEUE_CHUNK - 4 - NOTIFICATION_FIELDS;
+/// Largest size of the key of a dataset append.
+pub const DATASET_KEY_MAX: usize = QUEUE_CHUNK - 16;
+
+// Buffers at least this large are passed by reference.
+const REFERENCE_THRESHOLD: usize = QUEUE_CHUNK / 4;
+
 pub unsafe fn init() {
     write_volatile(SEND_MAILBOX, QUEUE_BEGIN);
     write_volatile(RECV_MAILBOX, QUEUE_BEGIN);
@@ -57,3 +88,121 @@ pub fn dequeue<T, E, F>(f: F) -> Result<T, E>
         })
     }
 }
+
+/// Enqueues a notification, consisting of the number of notifications dropped
+/// before it, a tag and a payload, without waiting for space in the queue.
+/// Returns false if the queue is full.
+pub fn try_notify(dropped: u32, tag: &[u8], data: &[u8]) -> bool {
+    debug_assert!(tag.len() + data.len() <= NOTIFICATION_MAX);
+
+    if full() { return false }
+    let _ = enqueue(|chunk| -> Result<(), ()> {
+        let length = NOTIFICATION_FIELDS + tag.len() + data.len();
+        NetworkEndian::write_u32(&mut chunk[0..4], length as u32 | NOTIFICATION);
+        NetworkEndian::write_u32(&mut chunk[4..8], dropped);
+        NetworkEndian::write_u32(&mut chunk[8..12], tag.len() as u32);
+        chunk[12..12 + tag.len()].copy_from_slice(tag);
+        let data_off = 12 + tag.len();
+        NetworkEndian::write_u32(&mut chunk[data_off..data_off + 4], data.len() as u32);
+        chunk[data_off + 4..data_off + 4 + data.len()].copy_from_slice(data);
+        Ok(())
+    });
+    true
+}
+
+/// Enqueues a value to append to the dataset `key`, waiting for space in the
+/// queue like an async RPC, as results must not be lost.
+pub fn append_dataset(key: &[u8], value: f64) {
+    debug_assert!(key.len() <= DATASET_KEY_MAX);
+
+    while full() {}
+    let _ = enqueue(|chunk| -> Result<(), ()> {
+        let length = 12 + key.len();
+        NetworkEndian::write_u32(&mut chunk[0..4], length as u32 | DATASET_APPEND);
+        NetworkEndian::write_u32(&mut chunk[4..8], key.len() as u32);
+        chunk[8..8 + key.len()].copy_from_slice(key);
+        NetworkEndian::write_f64(&mut chunk[8 + key.len()..16 + key.len()], value);
+        Ok(())
+    });
+}
+
+/// Writes a message of arbitrary length into the queue, spreading it over as
+/// many chunks as needed. Each chunk is committed as soon as it is filled, so
+/// the consumer can stream the message while it is being written; when the
+/// queue is full, writing blocks until the consumer catches up.
+pub struct Writer {
+    position: usize,
+    referenced: bool
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer { position: 4, referenced: false }
+    }
+
+    fn chunk(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(read_volatile(SEND_MAILBOX) as *mut u8, QUEUE_CHUNK)
+        }
+    }
+
+    fn commit(&mut self, flags: u32) {
+        let length = (self.position - 4) as u32 | flags;
+        NetworkEndian::write_u32(&mut self.chunk()[..4], length);
+        while full() {}
+        unsafe {
+            write_volatile(SEND_MAILBOX, next(read_volatile(SEND_MAILBOX)));
+        }
+        self.position = 4;
+    }
+
+    /// Commits the last chunk of the message. If buffers were passed by
+    /// reference, waits until the consumer has sent them, as the caller is
+    /// free to modify them afterwards.
+    pub fn finish(mut self) {
+        self.commit(0);
+        if self.referenced {
+            while !empty() {}
+        }
+    }
+}
+
+impl io::Write for Writer {
+    type WriteError = !;
+    type FlushError = !;
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::WriteError> {
+        if self.position == QUEUE_CHUNK {
+            self.commit(CONTINUED)
+        }
+        let position = self.position;
+        let length = buf.len().min(QUEUE_CHUNK - position);
+        self.chunk()[position..position + length].copy_from_slice(&buf[..length]);
+        self.position += length;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushError> {
+        Ok(())
+    }
+}
+
+impl RpcWrite for Writer {
+    fn write_bulk(&mut self, data: &[u8]) -> Result<(), io::Error<!>> {
+        if data.len() < REFERENCE_THRESHOLD {
+            return io::Write::write_all(self, data)
+        }
+        if self.position > 4 {
+            self.commit(CONTINUED)
+        }
+        {
+            let chunk = self.chunk();
+            NetworkEndian::write_u32(&mut chunk[4..8], data.as_ptr() as u32);
+            NetworkEndian::write_u32(&mut chunk[8..12], data.len() as u32);
+        }
+        self.position = 12;
+        self.commit(REFERENCE | CONTINUED);
+        self.referenced = true;
+        Ok(())
+    }
+}
@@ -173,10 +173,7 @@ fn monitor_lock() -> Result<()> {
     Ok(())
 }
 
-fn init() -> Result<()> {
-    #[cfg(not(si5324_soft_reset))]
-    hard_reset();
-
+fn select_i2c_mux() -> Result<()> {
     #[cfg(soc_platform = "kasli")]
     {
         i2c::pca9548_select(BUSNO, 0x70, 0)?;
@@ -190,6 +187,14 @@ fn init() -> Result<()> {
     i2c::pca9548_select(BUSNO, 0x70, 1 << 4)?;
     #[cfg(soc_platform = "kc705")]
     i2c::pca9548_select(BUSNO, 0x74, 1 << 7)?;
+    Ok(())
+}
+
+fn init() -> Result<()> {
+    #[cfg(not(si5324_soft_reset))]
+    hard_reset();
+
+    select_i2c_mux()?;
 
     if ident()? != 0x0182 {
         return Err("Si5324 does not have expected product number");
@@ -200,6 +205,38 @@ fn init() -> Result<()> {
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    pub bypass: bool,
+    pub lol: bool,
+    pub losx: bool,
+    pub los1: bool,
+    pub los2: bool,
+    pub holdover: bool
+}
+
+pub fn status() -> Result<Status> {
+    // The I2C mux may have been switched by a kernel since initialization.
+    select_i2c_mux()?;
+    let bypass = read(0)? & 0x02 != 0;        // BYPASS_REG
+    let cksel = (read(3)? >> 6) & 0x03;       // CKSEL_REG
+    let los = read(129)?;
+    let lol_int = read(130)?;
+    let los1 = los & 0x02 != 0;
+    let los2 = los & 0x04 != 0;
+    let los_active = if cksel == 0b00 { los1 } else { los2 };
+    Ok(Status {
+        bypass: bypass,
+        lol: lol_int & 0x01 != 0,             // LOL_INT
+        losx: los & 0x01 != 0,                // LOSX_INT
+        los1: los1,                           // LOS1_INT
+        los2: los2,                           // LOS2_INT
+        // The PLL keeps its output from the digital hold history while the
+        // selected input is lost.
+        holdover: !bypass && los_active && lol_int & 0x40 != 0  // DIGHOLDVALID
+    })
+}
+
 pub fn bypass(input: Input) -> Result<()> {
     let cksel_reg = match input {
         Input::Ckin1 => 0b00,
@@ -260,6 +297,58 @@ pub fn setup(settings: &FrequencySettings, input: Input) -> Result<()> {
     Ok(())
 }
 
+// Registers that hold the configuration of the Si5324, in the order they are
+// restored. The others hold status flags, the identification of the device
+// and self-clearing controls.
+const PROFILE_REGISTERS: [u8; 40] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11,
+    19, 20, 21, 22, 23, 24, 25,
+    31, 32, 33, 34, 35, 36,
+    40, 41, 42, 43, 44, 45, 46, 47, 48,
+    55, 137, 138, 139, 142, 143
+];
+const STATUS_REGISTERS: [u8; 7] = [128, 129, 130, 131, 132, 134, 135];
+
+/// Calls `f` with the address and value of each configuration register, then
+/// of each status register, and whether it is a configuration register.
+pub fn snapshot<F: FnMut(u8, u8, bool)>(mut f: F) -> Result<()> {
+    // The I2C mux may have been switched by a kernel since initialization.
+    select_i2c_mux()?;
+    for &reg in PROFILE_REGISTERS.iter() {
+        f(reg, read(reg)?, true);
+    }
+    for &reg in STATUS_REGISTERS.iter() {
+        f(reg, read(reg)?, false);
+    }
+    Ok(())
+}
+
+/// Programs the configuration registers from `profile`, (address, value)
+/// pairs of bytes as in a snapshot, then calibrates and waits for lock
+/// unless the profile bypasses the PLL.
+pub fn restore(profile: &[u8]) -> Result<()> {
+    if profile.len() % 2 != 0 {
+        return Err("Si5324 profile has an odd length")
+    }
+    if profile.chunks(2).any(|pair| !PROFILE_REGISTERS.contains(&pair[0])) {
+        return Err("Si5324 profile writes a register that does not hold configuration")
+    }
+
+    init()?;
+    let mut bypass = false;
+    for pair in profile.chunks(2) {
+        write(pair[0], pair[1])?;
+        if pair[0] == 0 {
+            bypass = pair[1] & 0x02 != 0;                 // BYPASS_REG
+        }
+    }
+    if !bypass {
+        write(136, read(136)? | 0x40)?;                   // ICAL=1
+        monitor_lock()?;
+    }
+    Ok(())
+}
+
 pub fn select_input(input: Input) -> Result<()> {
     let cksel_reg = match input {
         Input::Ckin1 => 0b00,
@@ -51,6 +51,31 @@ mod imp {
             csr::converter_spi::data_read()
         })
     }
+
+    pub fn write_buffer(busno: u8, data: &[u32]) -> Result<(), ()> {
+        for &word in data {
+            write(busno, word)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_buffer(busno: u8, data: &mut [u32]) -> Result<(), ()> {
+        // Shifts out zeros while shifting the data in
+        for word in data.iter_mut() {
+            write(busno, 0)?;
+            *word = read(busno)?;
+        }
+        Ok(())
+    }
+
+    pub fn transfer(busno: u8, data: &mut [u32]) -> Result<(), ()> {
+        // Replaces each word written with the word shifted in at the same time
+        for word in data.iter_mut() {
+            write(busno, *word)?;
+            *word = read(busno)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(not(has_converter_spi))]
@@ -58,6 +83,9 @@ mod imp {
     pub fn set_config(_busno: u8, _flags: u8, _length: u8, _div: u8, _cs: u8) -> Result<(), ()> { Err(()) }
     pub fn write(_busno: u8,_data: u32) -> Result<(), ()> { Err(()) }
     pub fn read(_busno: u8,) -> Result<u32, ()> { Err(()) }
+    pub fn write_buffer(_busno: u8, _data: &[u32]) -> Result<(), ()> { Err(()) }
+    pub fn read_buffer(_busno: u8, _data: &mut [u32]) -> Result<(), ()> { Err(()) }
+    pub fn transfer(_busno: u8, _data: &mut [u32]) -> Result<(), ()> { Err(()) }
 }
 
 pub use self::imp::*;
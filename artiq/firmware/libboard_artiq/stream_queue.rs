@@ -0,0 +1,80 @@
+use core::ptr::{read_volatile, write_volatile};
+use core::slice;
+use byteorder::{ByteOrder, NetworkEndian};
+use board_misoc::{mem, cache};
+
+// Ring of fixed-size sample records that kernels push to the UDP stream of
+// the runtime. Unlike the RPC queue, the kernel CPU never waits for space:
+// records that do not fit are dropped and counted in the next record.
+
+const SEND_MAILBOX: *mut usize = (mem::MAILBOX_BASE + 12) as *mut usize;
+const RECV_MAILBOX: *mut usize = (mem::MAILBOX_BASE + 16) as *mut usize;
+
+const QUEUE_BEGIN: usize = 0x44c00000;
+const QUEUE_END:   usize = 0x45000000;
+
+/// Each record holds the stream number (u16), the number of samples (u16),
+/// the number of records dropped before it (u32) and the samples (i32).
+pub const RECORD_SIZE: usize = 64;
+/// Largest number of samples in a record.
+pub const MAX_SAMPLES: usize = (RECORD_SIZE - 8) / 4;
+
+pub unsafe fn init() {
+    write_volatile(SEND_MAILBOX, QUEUE_BEGIN);
+    write_volatile(RECV_MAILBOX, QUEUE_BEGIN);
+}
+
+fn next(mut addr: usize) -> usize {
+    debug_assert!(addr % RECORD_SIZE == 0);
+    debug_assert!(addr >= QUEUE_BEGIN && addr < QUEUE_END);
+
+    addr += RECORD_SIZE;
+    if addr >= QUEUE_END { addr = QUEUE_BEGIN }
+    addr
+}
+
+pub fn empty() -> bool {
+    unsafe { read_volatile(SEND_MAILBOX) == read_volatile(RECV_MAILBOX) }
+}
+
+pub fn full() -> bool {
+    unsafe { next(read_volatile(SEND_MAILBOX)) == read_volatile(RECV_MAILBOX) }
+}
+
+/// Enqueues a record without waiting for space in the queue. Returns false
+/// if the queue is full.
+pub fn try_push(stream: u16, dropped: u32, samples: &[i32]) -> bool {
+    debug_assert!(samples.len() <= MAX_SAMPLES);
+
+    if full() { return false }
+    unsafe {
+        let addr = read_volatile(SEND_MAILBOX);
+        let record = slice::from_raw_parts_mut(addr as *mut u8, RECORD_SIZE);
+        NetworkEndian::write_u16(&mut record[0..2], stream);
+        NetworkEndian::write_u16(&mut record[2..4], samples.len() as u16);
+        NetworkEndian::write_u32(&mut record[4..8], dropped);
+        for (i, &sample) in samples.iter().enumerate() {
+            NetworkEndian::write_i32(&mut record[8 + 4 * i..12 + 4 * i], sample);
+        }
+        write_volatile(SEND_MAILBOX, next(addr));
+    }
+    true
+}
+
+/// Dequeues up to `max` records, and calls `f` with the stream number, the
+/// number of records dropped before it and the samples of each, as big-endian
+/// 32-bit words.
+pub fn dequeue<F: FnMut(u16, u32, &[u8])>(max: usize, mut f: F) {
+    unsafe {
+        cache::flush_cpu_dcache();
+        for _ in 0..max {
+            if empty() { break }
+            let addr = read_volatile(RECV_MAILBOX);
+            let record = slice::from_raw_parts(addr as *const u8, RECORD_SIZE);
+            let count = (NetworkEndian::read_u16(&record[2..4]) as usize).min(MAX_SAMPLES);
+            f(NetworkEndian::read_u16(&record[0..2]), NetworkEndian::read_u32(&record[4..8]),
+              &record[8..8 + 4 * count]);
+            write_volatile(RECV_MAILBOX, next(addr));
+        }
+    }
+}
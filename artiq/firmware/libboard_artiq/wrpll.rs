@@ -283,6 +283,54 @@ mod si549 {
         let b3 = read(dcxo, 233)? as i8 as i32;
         Ok(b3 << 16 | b2 << 8 | b1)
     }
+
+    // Registers that set the output frequency (dividers and ADPLL), in the
+    // order they are restored; the rest of the setup is done by `program`.
+    pub const PROFILE_REGISTERS: [u8; 11] = [23, 24, 26, 27, 28, 29, 30, 31, 231, 232, 233];
+    // Control registers, for reference in snapshots.
+    pub const CONTROL_REGISTERS: [u8; 3] = [7, 17, 69];
+
+    pub fn restore(dcxo: i2c::Dcxo, profile: &[u8]) -> Result<(), &'static str> {
+        if profile.len() % 2 != 0 {
+            return Err("Si549 profile has an odd length")
+        }
+        if profile.chunks(2).any(|pair| !PROFILE_REGISTERS.contains(&pair[0])) {
+            return Err("Si549 profile writes a register that does not set the frequency")
+        }
+
+        write(dcxo, 255, 0x00)?;  // PAGE
+        write(dcxo, 17,  0x00)?;  // Synchronously disable output
+        for pair in profile.chunks(2) {
+            write(dcxo, pair[0], pair[1])?;
+        }
+        write(dcxo, 7,   0x08)?;  // Start FCAL
+        write(dcxo, 17,  0x01)?;  // Synchronously enable output
+        Ok(())
+    }
+}
+
+pub use self::i2c::Dcxo;
+
+/// Calls `f` with the address and value of each register of the Si549
+/// `dcxo` that sets its frequency, then of its control registers, and
+/// whether it sets the frequency.
+pub fn snapshot<F: FnMut(u8, u8, bool)>(dcxo: Dcxo, mut f: F) -> Result<(), &'static str> {
+    for &reg in si549::PROFILE_REGISTERS.iter() {
+        f(reg, si549::read(dcxo, reg)?, true);
+    }
+    for &reg in si549::CONTROL_REGISTERS.iter() {
+        f(reg, si549::read(dcxo, reg)?, false);
+    }
+    Ok(())
+}
+
+/// Programs the frequency of the Si549 `dcxo` from `profile`, (address,
+/// value) pairs of bytes as in a snapshot.
+pub fn restore(dcxo: Dcxo, profile: &[u8]) -> Result<(), &'static str> {
+    si549::restore(dcxo, profile)?;
+    // Si549 Settling Time for Large Frequency Change, as in `init`.
+    clock::spin_us(50_000);
+    Ok(())
 }
 
 // to do: load from gateware config
@@ -449,6 +497,158 @@ fn trim_dcxos(f_helper: u32, f_main: u32, f_cdr: u32) -> Result<(i32, i32), &'st
     Ok((helper_adpll as i32, main_adpll as i32))
 }
 
+// ADPLL value found by calibration against the recovered clock; runtime trims
+// are applied relative to it.
+static mut MAIN_ADPLL_BASE: i32 = 0;
+
+/// Offsets the main DCXO by `ppb` parts per billion from its calibrated
+/// frequency. The Si549 slews smoothly to new ADPLL values within its pull
+/// range, so this does not glitch the RTIO clock.
+pub fn set_main_trim(ppb: i32) -> Result<(), &'static str> {
+    const ADPLL_PPB: f64 = 0.1164;
+    const ADPLL_MAX: i64 = (950.0e3/ADPLL_PPB) as i64;
+
+    let adpll = unsafe { MAIN_ADPLL_BASE } as i64 + (ppb as f64 / ADPLL_PPB) as i64;
+    if adpll.abs() > ADPLL_MAX {
+        return Err("frequency trim outside of the Si549 pull range");
+    }
+    if unsafe { csr::wrpll::main_dcxo_gpio_enable_read() } != 0 {
+        si549::set_adpll(i2c::Dcxo::Main, adpll as i32)?;
+    } else {
+        // The gateware loop filter drives the DCXO; move its operating point instead.
+        unsafe { csr::wrpll::adpll_offset_main_write(adpll as u32); }
+    }
+    info!("main DCXO trimmed by {} ppb (ADPLL={})", ppb, adpll);
+    Ok(())
+}
+
+/// Synchronization performance of the recovered clock PLL, accumulated since
+/// the last time it locked (except for the unlock count).
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub locked: bool,
+    /// Time taken by the last clock transition to the recovered clock.
+    pub lock_time_ms: u32,
+    pub unlock_count: u32,
+    /// Number of DDMTD tag pairs included in the RMS values.
+    pub samples: u32,
+    /// RMS of the loop filter inputs, in DDMTD tags.
+    pub helper_rms: f32,
+    pub main_rms: f32,
+}
+
+struct StatsAccumulator {
+    locked: bool,
+    lock_time_ms: u32,
+    unlock_count: u32,
+    samples: u32,
+    helper_sq_sum: f64,
+    main_sq_sum: f64,
+    last_sample: u64,
+    last_summary: u64,
+}
+
+static mut STATS: StatsAccumulator = StatsAccumulator {
+    locked: false,
+    lock_time_ms: 0,
+    unlock_count: 0,
+    samples: 0,
+    helper_sq_sum: 0.0,
+    main_sq_sum: 0.0,
+    last_sample: 0,
+    last_summary: 0,
+};
+
+const STATS_SAMPLE_INTERVAL_MS: u64 = 1_000;
+const STATS_SUMMARY_INTERVAL_MS: u64 = 60_000;
+
+// core has no float sqrt; Newton's method is plenty for statistics.
+fn sqrt(x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0
+    }
+    let mut r = if x > 1.0 { x / 2.0 } else { 1.0 };
+    for _ in 0..64 {
+        r = (r + x / r) / 2.0;
+    }
+    r
+}
+
+pub fn stats() -> Stats {
+    let acc = unsafe { &STATS };
+    let rms = |sq_sum: f64| {
+        if acc.samples == 0 { 0.0 } else { sqrt(sq_sum / acc.samples as f64) as f32 }
+    };
+    Stats {
+        locked: acc.locked,
+        lock_time_ms: acc.lock_time_ms,
+        unlock_count: acc.unlock_count,
+        samples: acc.samples,
+        helper_rms: rms(acc.helper_sq_sum),
+        main_rms: rms(acc.main_sq_sum),
+    }
+}
+
+/// Phase of the main clock, i.e. the RTIO clock, after the recovered clock,
+/// in femtoseconds, or `None` if the PLL is not locked. A DDMTD tag stands
+/// for 1/(M f_main) of phase.
+pub fn main_phase_fs() -> Option<i32> {
+    if !unsafe { STATS.locked } {
+        return None
+    }
+    let (main_diff, _, _, _) = get_tags();
+    Some((main_diff as f64 * 1e15 / (DDMTD_COUNTER_M as f64 * F_MAIN)) as i32)
+}
+
+fn log_stats() {
+    let stats = stats();
+    info!("WRPLL: locked={} lock_time={}ms unlocks={} helper_rms={} main_rms={} tags ({} samples)",
+          stats.locked, stats.lock_time_ms, stats.unlock_count,
+          stats.helper_rms, stats.main_rms, stats.samples);
+}
+
+/// Samples the DDMTD tag errors while locked and periodically logs a summary.
+/// Must be called regularly from the main loop.
+pub fn tick() {
+    let acc = unsafe { &mut STATS };
+    if !acc.locked {
+        return
+    }
+    let now = clock::get_ms();
+    if now >= acc.last_sample + STATS_SAMPLE_INTERVAL_MS {
+        let (main_diff, helper_diff, _, _) = get_tags();
+        acc.helper_sq_sum += (helper_diff as f64) * (helper_diff as f64);
+        acc.main_sq_sum += (main_diff as f64) * (main_diff as f64);
+        acc.samples += 1;
+        acc.last_sample = now;
+    }
+    if now >= acc.last_summary + STATS_SUMMARY_INTERVAL_MS {
+        log_stats();
+        acc.last_summary = now;
+    }
+}
+
+fn stats_locked(lock_time_ms: u32) {
+    let acc = unsafe { &mut STATS };
+    let now = clock::get_ms();
+    acc.locked = true;
+    acc.lock_time_ms = lock_time_ms;
+    acc.samples = 0;
+    acc.helper_sq_sum = 0.0;
+    acc.main_sq_sum = 0.0;
+    acc.last_sample = now;
+    acc.last_summary = now;
+}
+
+fn stats_unlocked() {
+    let acc = unsafe { &mut STATS };
+    if acc.locked {
+        acc.locked = false;
+        acc.unlock_count += 1;
+        log_stats();
+    }
+}
+
 fn statistics(data: &[u16]) -> (f32, f32) {
     let sum = data.iter().fold(0 as u32, |acc, x| acc + *x as u32);
     let mean = sum as f32 / data.len() as f32;
@@ -477,6 +677,7 @@ fn select_recovered_clock_int(rc: bool) -> Result<(), &'static str> {
         print_tags();
 
         si549::set_adpll(i2c::Dcxo::Main, main_adpll).expect("ADPLL write failed");
+        unsafe { MAIN_ADPLL_BASE = main_adpll; }
 
         unsafe {
             csr::wrpll::adpll_offset_helper_write(helper_adpll as u32);
@@ -521,6 +722,7 @@ fn select_recovered_clock_int(rc: bool) -> Result<(), &'static str> {
     } else {
         si549::set_adpll(i2c::Dcxo::Helper, 0).expect("ADPLL write failed");
         si549::set_adpll(i2c::Dcxo::Main, 0).expect("ADPLL write failed");
+        unsafe { MAIN_ADPLL_BASE = 0; }
     }
     Ok(())
 }
@@ -531,8 +733,17 @@ pub fn select_recovered_clock(rc: bool) {
     } else {
         info!("switching to local XO clock");
     }
+    if !rc {
+        stats_unlocked();
+    }
+    let start = clock::get_ms();
     match select_recovered_clock_int(rc) {
-        Ok(()) => info!("clock transition completed"),
+        Ok(()) => {
+            info!("clock transition completed");
+            if rc {
+                stats_locked((clock::get_ms() - start) as u32);
+            }
+        }
         Err(e) => error!("clock transition failed: {}", e)
     }
 }
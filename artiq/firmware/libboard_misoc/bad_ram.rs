@@ -0,0 +1,43 @@
+use core::ptr;
+use mem;
+
+// The bootloader leaves a record of the main RAM region that failed its memory
+// test at the start of the integrated SRAM, which the firmware does not use
+// otherwise (see bootloader.ld). The SRAM keeps its contents across soft
+// resets but not across power cycles, so the record is protected by a check
+// word and rewritten by the bootloader at every start.
+const ADDR: *mut u32 = mem::SRAM_BASE as *mut u32;
+
+// Record: magic, first address, end address, check word.
+const MAGIC: u32 = 0x41424144; // "ABAD"
+const CHECK: u32 = 0x5a5a5a5a;
+
+/// Records the `[start, end)` main RAM address range as failing, or clears
+/// the record if `None`.
+pub fn set(range: Option<(usize, usize)>) {
+    let (magic, start, end) = match range {
+        Some((start, end)) => (MAGIC, start as u32, end as u32),
+        None => (0, 0, 0)
+    };
+    unsafe {
+        ptr::write_volatile(ADDR.offset(1), start);
+        ptr::write_volatile(ADDR.offset(2), end);
+        ptr::write_volatile(ADDR.offset(3), magic ^ start ^ end ^ CHECK);
+        ptr::write_volatile(ADDR, magic);
+    }
+}
+
+/// Returns the `[start, end)` main RAM address range that failed the memory
+/// test in the bootloader, if it was booted despite the failure.
+pub fn get() -> Option<(usize, usize)> {
+    let (magic, start, end, check) = unsafe {
+        (ptr::read_volatile(ADDR),
+         ptr::read_volatile(ADDR.offset(1)),
+         ptr::read_volatile(ADDR.offset(2)),
+         ptr::read_volatile(ADDR.offset(3)))
+    };
+    if magic != MAGIC || check != magic ^ start ^ end ^ CHECK || start >= end {
+        return None
+    }
+    Some((start as usize, end as usize))
+}
@@ -0,0 +1,64 @@
+use core::slice;
+use byteorder::{ByteOrder, BigEndian};
+use crc::crc32;
+use {config, ed25519, firmware, spiflash};
+
+// The sector after the panic log holds the public key that firmware images
+// are signed with. The firmware never writes it: it is written with
+// artiq_flash, so that it cannot be replaced over the network.
+const ADDR: usize = firmware::END + spiflash::SECTOR_SIZE;
+
+// Record: magic, public key, flags, CRC32 of all of the above.
+const MAGIC: &'static [u8] = b"AKEY";
+const RECORD_SIZE: usize = 4 + 32 + 4 + 4;
+const FLAG_REQUIRED: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Valid,
+    Unsigned,
+    Invalid,
+    NoKey
+}
+
+struct BootKey {
+    public_key: [u8; 32],
+    flags:      u32
+}
+
+fn read() -> Option<BootKey> {
+    let record = unsafe { slice::from_raw_parts(ADDR as *const u8, RECORD_SIZE) };
+    if &record[..4] != MAGIC ||
+            crc32::checksum_ieee(&record[..40]) != BigEndian::read_u32(&record[40..]) {
+        return None
+    }
+    let mut public_key = [0; 32];
+    public_key.copy_from_slice(&record[4..36]);
+    Some(BootKey {
+        public_key: public_key,
+        flags:      BigEndian::read_u32(&record[36..40])
+    })
+}
+
+/// Checks the signature of `firmware` against the key stored in flash.
+pub fn verify(firmware: &[u8], signature: Option<&[u8; 64]>) -> Status {
+    match (read(), signature) {
+        (None, _) => Status::NoKey,
+        (Some(_), None) => Status::Unsigned,
+        (Some(key), Some(signature)) => {
+            if ed25519::verify(&key.public_key, &[firmware], signature) {
+                Status::Valid
+            } else {
+                Status::Invalid
+            }
+        }
+    }
+}
+
+/// Whether firmware without a valid signature must not be booted. This is
+/// required by the key record, which cannot be changed over the network, or
+/// by setting the `boot_signature` config key to `required`.
+pub fn required() -> bool {
+    read().map_or(false, |key| key.flags & FLAG_REQUIRED != 0) ||
+        config::read_str("boot_signature", |r| r == Ok("required"))
+}
@@ -0,0 +1,151 @@
+use core::slice;
+use byteorder::{ByteOrder, BigEndian};
+use crc::crc32;
+use {cache, firmware, flash_health, spiflash};
+
+// The two sectors after the flash health spares hold the state of the
+// firmware banks and of the gateware update. It is kept out of the configuration, so that erasing,
+// backing up or restoring the configuration leaves it alone. Records are
+// appended to the sector in use; when it is full, the other sector is
+// erased and the log continues there, so that the last record survives an
+// interrupted erase.
+const ADDRS: [usize; 2] = [firmware::END + (7 + flash_health::SPARE_COUNT) * spiflash::SECTOR_SIZE,
+                           firmware::END + (8 + flash_health::SPARE_COUNT) * spiflash::SECTOR_SIZE];
+
+// Record: magic, sequence number, state, CRC32 of all of the above. The
+// record with the highest sequence number wins.
+const MAGIC: &'static [u8] = b"ABST";
+const RECORD_SIZE: usize = 16;
+
+/// Stage of the gateware update, see gateware.rs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatewareUpdate {
+    /// Written, to be booted on trial.
+    Trial,
+    /// Booted on trial, not checked in yet.
+    Started,
+    /// Booted at each power-up.
+    Active
+}
+
+impl GatewareUpdate {
+    fn from_byte(byte: u8) -> Option<GatewareUpdate> {
+        match byte {
+            1 => Some(GatewareUpdate::Trial),
+            2 => Some(GatewareUpdate::Started),
+            3 => Some(GatewareUpdate::Active),
+            _ => None
+        }
+    }
+
+    fn to_byte(update: Option<GatewareUpdate>) -> u8 {
+        match update {
+            None => 0xff,
+            Some(GatewareUpdate::Trial) => 1,
+            Some(GatewareUpdate::Started) => 2,
+            Some(GatewareUpdate::Active) => 3
+        }
+    }
+}
+
+/// State of the firmware banks and of the gateware update. Banks are
+/// numbered from 0 (A).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BootState {
+    /// The bank booted when no trial is pending.
+    pub fw_active:        u8,
+    /// The bank holding an update that has not checked in yet, if any.
+    pub fw_trial:         Option<u8>,
+    /// Whether the bank under trial has already been booted once.
+    pub fw_trial_started: bool,
+    /// The gateware update in use, if any.
+    pub gw_update:        Option<GatewareUpdate>
+}
+
+// Where to write the next record, and its sequence number.
+static mut NEXT: Option<(usize, u32)> = None;
+
+fn read_record(addr: usize) -> Option<(u32, BootState)> {
+    let record = unsafe { slice::from_raw_parts(addr as *const u8, RECORD_SIZE) };
+    if &record[..4] != MAGIC ||
+            crc32::checksum_ieee(&record[..12]) != BigEndian::read_u32(&record[12..]) {
+        return None
+    }
+    let state = BootState {
+        fw_active:        record[8],
+        fw_trial:         if record[9] == 0xff { None } else { Some(record[9]) },
+        fw_trial_started: record[10] != 0,
+        gw_update:        GatewareUpdate::from_byte(record[11])
+    };
+    Some((BigEndian::read_u32(&record[4..8]), state))
+}
+
+fn is_erased(addr: usize) -> bool {
+    let record = unsafe { slice::from_raw_parts(addr as *const u8, RECORD_SIZE) };
+    record.iter().all(|&b| b == 0xff)
+}
+
+// Last record of the log with its sequence number, and the address after it.
+fn find_last() -> Option<(u32, BootState, usize)> {
+    let mut last: Option<(u32, BootState, usize)> = None;
+    for &sector in ADDRS.iter() {
+        let mut addr = sector;
+        while addr < sector + spiflash::SECTOR_SIZE {
+            if is_erased(addr) {
+                break
+            }
+            if let Some((sequence, state)) = read_record(addr) {
+                if last.map_or(true, |(last_sequence, _, _)| sequence > last_sequence) {
+                    last = Some((sequence, state, addr + RECORD_SIZE))
+                }
+            }
+            addr += RECORD_SIZE;
+        }
+    }
+    last
+}
+
+/// Returns the state of the firmware banks and of the gateware update. A
+/// device that never recorded one boots bank A and the golden gateware.
+pub fn read() -> BootState {
+    find_last().map_or(BootState::default(), |(_, state, _)| state)
+}
+
+/// Records a new state of the firmware banks and of the gateware update.
+/// Returns false if the record could not be read back.
+pub fn write(state: BootState) -> bool {
+    let (mut addr, sequence) = unsafe {
+        match NEXT {
+            Some(next) => next,
+            None => find_last().map_or((0, 0), |(sequence, _, addr)| (addr, sequence + 1))
+        }
+    };
+
+    let mut record = [0xff; RECORD_SIZE];
+    record[..4].copy_from_slice(MAGIC);
+    BigEndian::write_u32(&mut record[4..8], sequence);
+    record[8] = state.fw_active;
+    record[9] = state.fw_trial.unwrap_or(0xff);
+    record[10] = state.fw_trial_started as u8;
+    record[11] = GatewareUpdate::to_byte(state.gw_update);
+    let crc = crc32::checksum_ieee(&record[..12]);
+    BigEndian::write_u32(&mut record[12..], crc);
+
+    unsafe {
+        if addr == 0 || addr % spiflash::SECTOR_SIZE == 0 || !is_erased(addr) {
+            // Start of the log, end of a sector, or a record interrupted by
+            // a reset.
+            addr = if addr > ADDRS[0] && addr <= ADDRS[0] + spiflash::SECTOR_SIZE {
+                ADDRS[1]
+            } else {
+                ADDRS[0]
+            };
+            spiflash::erase_sector(addr);
+        }
+        spiflash::write(addr, &record);
+        NEXT = Some((addr + RECORD_SIZE, sequence + 1));
+    }
+    cache::flush_l2_cache();
+    cache::flush_cpu_dcache();
+    read_record(addr) == Some((sequence, state))
+}
@@ -0,0 +1,141 @@
+/// Number of boots of the device, and its uptime over all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BootStats {
+    pub boot_count: u32,
+    pub uptime_s:   u32
+}
+
+#[cfg(has_spiflash)]
+mod imp {
+    use core::slice;
+    use byteorder::{ByteOrder, BigEndian};
+    use crc::crc32;
+    use {cache, clock, firmware, spiflash};
+    use super::BootStats;
+
+    // The two sectors after the boot key hold a log of records, appended to
+    // the sector in use. When it is full, the other sector is erased and the
+    // log continues there, so that the last record survives an interrupted
+    // erase.
+    const ADDRS: [usize; 2] = [firmware::END + 2 * spiflash::SECTOR_SIZE,
+                               firmware::END + 3 * spiflash::SECTOR_SIZE];
+
+    // Record: magic, boot count, cumulative uptime in seconds, CRC32 of all of
+    // the above.
+    const MAGIC: &'static [u8] = b"ABOT";
+    const RECORD_SIZE: usize = 16;
+
+    // Statistics after this boot was counted, and where to write the next
+    // record.
+    static mut AT_BOOT: Option<BootStats> = None;
+    static mut NEXT_ADDR: usize = 0;
+
+    fn read_record(addr: usize) -> Option<BootStats> {
+        let record = unsafe { slice::from_raw_parts(addr as *const u8, RECORD_SIZE) };
+        if &record[..4] != MAGIC ||
+                crc32::checksum_ieee(&record[..12]) != BigEndian::read_u32(&record[12..]) {
+            return None
+        }
+        Some(BootStats {
+            boot_count: BigEndian::read_u32(&record[4..8]),
+            uptime_s:   BigEndian::read_u32(&record[8..12])
+        })
+    }
+
+    fn is_erased(addr: usize) -> bool {
+        let record = unsafe { slice::from_raw_parts(addr as *const u8, RECORD_SIZE) };
+        record.iter().all(|&b| b == 0xff)
+    }
+
+    // Last record of the log, and the address after it.
+    fn find_last() -> Option<(BootStats, usize)> {
+        let mut last: Option<(BootStats, usize)> = None;
+        for &sector in ADDRS.iter() {
+            let mut addr = sector;
+            while addr < sector + spiflash::SECTOR_SIZE {
+                if is_erased(addr) {
+                    break
+                }
+                if let Some(stats) = read_record(addr) {
+                    if last.map_or(true, |(last_stats, _)| stats > last_stats) {
+                        last = Some((stats, addr + RECORD_SIZE))
+                    }
+                }
+                addr += RECORD_SIZE;
+            }
+        }
+        last
+    }
+
+    fn write(stats: BootStats) {
+        let mut record = [0; RECORD_SIZE];
+        record[..4].copy_from_slice(MAGIC);
+        BigEndian::write_u32(&mut record[4..8], stats.boot_count);
+        BigEndian::write_u32(&mut record[8..12], stats.uptime_s);
+        let crc = crc32::checksum_ieee(&record[..12]);
+        BigEndian::write_u32(&mut record[12..], crc);
+        unsafe {
+            let mut addr = NEXT_ADDR;
+            if addr == 0 || addr % spiflash::SECTOR_SIZE == 0 || !is_erased(addr) {
+                // Start of the log, end of a sector, or a record interrupted
+                // by a reset.
+                addr = if addr > ADDRS[0] && addr <= ADDRS[0] + spiflash::SECTOR_SIZE {
+                    ADDRS[1]
+                } else {
+                    ADDRS[0]
+                };
+                spiflash::erase_sector(addr);
+            }
+            spiflash::write(addr, &record);
+            NEXT_ADDR = addr + RECORD_SIZE;
+        }
+        cache::flush_l2_cache();
+        cache::flush_cpu_dcache();
+    }
+
+    /// Counts this boot, and returns the updated statistics. Must be called
+    /// once, at startup.
+    pub fn record_boot() -> Option<BootStats> {
+        let (last, next_addr) = match find_last() {
+            Some((stats, addr)) => (Some(stats), addr),
+            None => (None, 0)
+        };
+        let stats = BootStats {
+            boot_count: last.map_or(1, |stats| stats.boot_count + 1),
+            uptime_s:   last.map_or(0, |stats| stats.uptime_s)
+        };
+        unsafe {
+            NEXT_ADDR = next_addr;
+            AT_BOOT = Some(stats);
+        }
+        write(stats);
+        Some(stats)
+    }
+
+    /// Statistics including the uptime of this boot, if it was counted.
+    pub fn current() -> Option<BootStats> {
+        unsafe { AT_BOOT }.map(|stats| BootStats {
+            boot_count: stats.boot_count,
+            uptime_s:   stats.uptime_s.saturating_add((clock::get_ms() / 1000) as u32)
+        })
+    }
+
+    /// Saves the uptime of this boot, so that it is accounted for after a
+    /// reset.
+    pub fn save_uptime() {
+        if let Some(stats) = current() {
+            write(stats)
+        }
+    }
+}
+
+#[cfg(not(has_spiflash))]
+mod imp {
+    use super::BootStats;
+
+    pub fn record_boot() -> Option<BootStats> { None }
+    pub fn current() -> Option<BootStats> { None }
+    pub fn save_uptime() {}
+}
+
+pub use self::imp::*;
@@ -27,6 +27,21 @@ pub fn get_ms() -> u64 {
     }
 }
 
+// Unix time, in microseconds, at which the timer was started; known once
+// the wall clock has been set, e.g. by SNTP.
+static mut UNIX_EPOCH_OFFSET: Option<u64> = None;
+
+/// Sets the wall clock to `time`, in microseconds since the Unix epoch.
+pub fn set_unix_us(time: u64) {
+    unsafe { UNIX_EPOCH_OFFSET = Some(time - get_us()) }
+}
+
+/// Returns the wall clock time in microseconds since the Unix epoch, if it
+/// has been set.
+pub fn get_unix_us() -> Option<u64> {
+    unsafe { UNIX_EPOCH_OFFSET.map(|offset| offset + get_us()) }
+}
+
 pub fn spin_us(interval: u64) {
     unsafe {
         csr::timer0::update_value_write(1);
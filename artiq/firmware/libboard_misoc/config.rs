@@ -9,6 +9,10 @@ pub enum Error {
     MissingSeparator { offset: usize },
     Utf8Error(str::Utf8Error),
     NoFlash,
+    ValueTooLarge,
+    CorruptedValue,
+    InvalidBackup,
+    WriteFailed,
 }
 
 impl fmt::Display for Error {
@@ -28,6 +32,14 @@ impl fmt::Display for Error {
                 write!(f, "{}", err),
             &Error::NoFlash =>
                 write!(f, "flash memory is not present"),
+            &Error::ValueTooLarge =>
+                write!(f, "value too large"),
+            &Error::CorruptedValue =>
+                write!(f, "chunked value is incomplete or corrupted"),
+            &Error::InvalidBackup =>
+                write!(f, "invalid or corrupted configuration backup"),
+            &Error::WriteFailed =>
+                write!(f, "flash sector failed to erase or program"),
         }
     }
 }
@@ -36,8 +48,12 @@ impl fmt::Display for Error {
 mod imp {
     use core::str;
     use byteorder::{ByteOrder, BigEndian};
+    use crc::crc32;
+    #[cfg(feature = "alloc")]
+    use alloc::Vec;
     use cache;
     use spiflash;
+    use flash_health;
     use super::Error;
     use core::fmt;
     use core::fmt::Write;
@@ -71,13 +87,27 @@ mod imp {
         }
     }
 
-    // One flash sector immediately before the firmware.
-    const ADDR: usize = ::mem::FLASH_BOOT_ADDRESS - spiflash::SECTOR_SIZE;
+    // Two flash sectors immediately before the firmware. One of them holds
+    // the current records; when it fills up, the live records are compacted
+    // into the other one, which only becomes current once its header (written
+    // last) carries a higher sequence number. A power loss at any point leaves
+    // at least one valid sector behind. A record that fails to program is
+    // torn, and moves the records to the other sector; a sector that keeps
+    // failing during compactions is remapped, see flash_health.
+    const ADDR: usize = ::mem::FLASH_BOOT_ADDRESS - 2 * spiflash::SECTOR_SIZE;
     const SIZE: usize = spiflash::SECTOR_SIZE;
 
+    // Sector header: magic, sequence number, CRC32 of the preceding 8 bytes.
+    const MAGIC: &'static [u8] = b"ACF1";
+    const HEADER_SIZE: usize = 12;
+
+    // Record header: total record size, CRC32 of the size and the record body.
+    const RECORD_HEADER_SIZE: usize = 8;
+
     mod lock {
         use core::slice;
         use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+        use flash_health;
         use super::Error;
 
         static LOCKED: AtomicUsize = ATOMIC_USIZE_INIT;
@@ -93,8 +123,9 @@ mod imp {
                 }
             }
 
-            pub fn data(&self) -> &'static [u8] {
-                unsafe { slice::from_raw_parts(super::ADDR as *const u8, super::SIZE) }
+            pub fn data(&self, sector: usize) -> &'static [u8] {
+                let addr = flash_health::address(super::ADDR + sector * super::SIZE);
+                unsafe { slice::from_raw_parts(addr as *const u8, super::SIZE) }
             }
         }
 
@@ -107,15 +138,47 @@ mod imp {
 
     use self::lock::Lock;
 
+    fn sequence(data: &[u8]) -> Option<u32> {
+        let header = &data[..HEADER_SIZE];
+        if &header[0..4] != MAGIC ||
+                crc32::checksum_ieee(&header[0..8]) != BigEndian::read_u32(&header[8..12]) {
+            return None
+        }
+        Some(BigEndian::read_u32(&header[4..8]))
+    }
+
+    // Returns the current sector and its sequence number, if any sector is valid.
+    fn active(lock: &Lock) -> Option<(usize, u32)> {
+        match (sequence(lock.data(0)), sequence(lock.data(1))) {
+            (Some(seq0), Some(seq1)) =>
+                if (seq1.wrapping_sub(seq0) as i32) > 0 { Some((1, seq1)) } else { Some((0, seq0)) },
+            (Some(seq0), None) => Some((0, seq0)),
+            (None, Some(seq1)) => Some((1, seq1)),
+            (None, None) => None
+        }
+    }
+
+    fn active_data(lock: &Lock) -> &'static [u8] {
+        match active(lock) {
+            Some((sector, _)) => lock.data(sector),
+            None => &[]
+        }
+    }
+
+    fn record_crc(size: &[u8], body: &[u8]) -> u32 {
+        crc32::update(crc32::checksum_ieee(size), &crc32::IEEE_TABLE, body)
+    }
+
     #[derive(Clone)]
     struct Iter<'a> {
         data:   &'a [u8],
-        offset: usize
+        offset: usize,
+        torn:   bool
     }
 
     impl<'a> Iter<'a> {
         fn new(data: &'a [u8]) -> Iter<'a> {
-            Iter { data: data, offset: 0 }
+            Iter { data: data, offset: HEADER_SIZE, torn: false }
         }
     }
 
@@ -123,21 +186,24 @@ mod imp {
         type Item = Result<(&'a [u8], &'a [u8]), Error>;
 
         fn next(&mut self) -> Option<Self::Item> {
-            let data = &self.data[self.offset..];
-
-            if data.len() < 4 {
-                // error!("offset {}: truncated record", self.offset);
-                return Some(Err(Error::Truncated { offset: self.offset }))
+            if self.torn || self.offset + RECORD_HEADER_SIZE > self.data.len() {
+                return None
             }
+            let data = &self.data[self.offset..];
 
             let record_size = BigEndian::read_u32(data) as usize;
             if record_size == !0 /* all ones; erased flash */ {
                 return None
-            } else if record_size < 4 || record_size > data.len() {
-                return Some(Err(Error::InvalidSize { offset: self.offset, size: record_size }))
+            } else if record_size < RECORD_HEADER_SIZE || record_size > data.len() ||
+                    record_crc(&data[0..4], &data[RECORD_HEADER_SIZE..record_size]) !=
+                        BigEndian::read_u32(&data[4..8]) {
+                // A write interrupted by a power loss; nothing past this point can be
+                // trusted, and the next write has to go into a freshly erased sector.
+                self.torn = true;
+                return None
             }
 
-            let record_body = &data[4..record_size];
+            let record_body = &data[RECORD_HEADER_SIZE..record_size];
             match record_body.iter().position(|&x| x == 0) {
                 None => {
                     return Some(Err(Error::MissingSeparator { offset: self.offset }))
@@ -152,19 +218,154 @@ mod imp {
         }
     }
 
-    pub fn read<F: FnOnce(Result<&[u8], Error>) -> R, R>(key: &str, f: F) -> R {
-        f(Lock::take().and_then(|lock| {
-            let mut iter = Iter::new(lock.data());
-            let mut value = &[][..];
+    // Values larger than CHUNK_SIZE are split into chunk records keyed
+    // `key \xff <index>`, followed by a manifest record keyed `key \xff` that
+    // holds the total length and CRC32 of the value. 0xff never occurs in UTF-8,
+    // so these keys cannot collide with ordinary ones.
+    const CHUNK_SIZE: usize = 4096;
+    pub const MAX_VALUE_SIZE: usize = SIZE / 2;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Kind {
+        Plain,
+        Manifest,
+        Chunk(usize)
+    }
+
+    fn split_key(key: &[u8]) -> (&[u8], Kind) {
+        match key.iter().position(|&x| x == 0xff) {
+            None => (key, Kind::Plain),
+            Some(pos) => {
+                let suffix = &key[pos + 1..];
+                let kind =
+                    if suffix.is_empty() {
+                        Kind::Manifest
+                    } else {
+                        let index = str::from_utf8(suffix).ok().and_then(|s| s.parse().ok());
+                        Kind::Chunk(index.unwrap_or(!0))
+                    };
+                (&key[..pos], kind)
+            }
+        }
+    }
+
+    fn chunk_suffix(buf: &mut [u8; 8], index: usize) -> &[u8] {
+        buf[0] = 0xff;
+        let len = {
+            let mut wrapper = FmtWrapper::new(&mut buf[1..]);
+            write!(&mut wrapper, "{}", index).unwrap();
+            wrapper.contents().len()
+        };
+        &buf[..1 + len]
+    }
+
+    fn manifest_chunks(manifest: &[u8]) -> usize {
+        if manifest.len() < 8 { return 0 }
+        (BigEndian::read_u32(&manifest[0..4]) as usize + CHUNK_SIZE - 1) / CHUNK_SIZE
+    }
+
+    // Whether the record just returned by `iter` is still needed, judging by the
+    // records written after it.
+    fn is_live(iter: &Iter, key: &[u8], value: &[u8]) -> Result<bool, Error> {
+        let (base, kind) = split_key(key);
+        if kind == Kind::Plain && value.is_empty() {
+            // This is a removed entry.
+            return Ok(false)
+        }
+
+        let mut next_iter = iter.clone();
+        while let Some(next_result) = next_iter.next() {
+            let (next_key, next_value) = next_result?;
+            if key == next_key {
+                // There's another entry that overwrites this one.
+                return Ok(false)
+            }
+            let (next_base, next_kind) = split_key(next_key);
+            if base != next_base {
+                continue
+            }
+            match (kind, next_kind) {
+                (Kind::Plain, Kind::Manifest) |
+                (Kind::Manifest, Kind::Plain) |
+                (Kind::Chunk(_), Kind::Plain) => return Ok(false),
+                (Kind::Chunk(index), Kind::Manifest)
+                        if index >= manifest_chunks(next_value) => return Ok(false),
+                _ => ()
+            }
+        }
+        Ok(true)
+    }
+
+    // Returns the current plain value of `key`, or the manifest of its chunked value
+    // along with the offset right past the manifest record.
+    fn lookup(data: &'static [u8], key: &[u8])
+            -> Result<(&'static [u8], Option<(&'static [u8], usize)>), Error> {
+        let mut iter = Iter::new(data);
+        let mut value = &[][..];
+        let mut manifest = None;
+        while let Some(result) = iter.next() {
+            let (record_key, record_value) = result?;
+            if key == record_key {
+                // last write wins
+                value = record_value;
+                manifest = None;
+            } else if split_key(record_key) == (key, Kind::Manifest) {
+                manifest = Some((record_value, iter.offset));
+            }
+        }
+        Ok((value, manifest))
+    }
+
+    #[cfg(feature = "alloc")]
+    fn read_chunks(data: &[u8], key: &[u8], manifest: &[u8]) -> Result<Vec<u8>, Error> {
+        if manifest.len() < 8 {
+            return Err(Error::CorruptedValue)
+        }
+        let length = BigEndian::read_u32(&manifest[0..4]) as usize;
+        let crc = BigEndian::read_u32(&manifest[4..8]);
+
+        let mut buffer = Vec::with_capacity(length);
+        for index in 0..manifest_chunks(manifest) {
+            // Only consider chunks written before the manifest; later ones belong
+            // to a write that never completed.
+            let mut chunk = None;
+            let mut iter = Iter::new(data);
             while let Some(result) = iter.next() {
                 let (record_key, record_value) = result?;
-                if key.as_bytes() == record_key {
-                    // last write wins
-                    value = record_value
+                if split_key(record_key) == (key, Kind::Chunk(index)) {
+                    chunk = Some(record_value)
                 }
             }
-            Ok(value)
-        }))
+            match chunk {
+                Some(chunk) => buffer.extend_from_slice(chunk),
+                None => return Err(Error::CorruptedValue)
+            }
+        }
+        if buffer.len() != length || crc32::checksum_ieee(&buffer) != crc {
+            return Err(Error::CorruptedValue)
+        }
+        Ok(buffer)
+    }
+
+    pub fn read<F: FnOnce(Result<&[u8], Error>) -> R, R>(key: &str, f: F) -> R {
+        let found = Lock::take().and_then(|lock| lookup(active_data(&lock), key.as_bytes()));
+        match found {
+            Err(err) => f(Err(err)),
+            Ok((value, None)) => f(Ok(value)),
+            #[cfg(feature = "alloc")]
+            Ok((_, Some((manifest, end)))) => {
+                let chunks = Lock::take().and_then(|lock| {
+                    read_chunks(&active_data(&lock)[..end], key.as_bytes(), manifest)
+                });
+                match chunks {
+                    Ok(buffer) => f(Ok(&buffer[..])),
+                    Err(err) => f(Err(err))
+                }
+            }
+            // Reassembling a chunked value requires a heap.
+            #[cfg(not(feature = "alloc"))]
+            Ok((_, Some(_))) => f(Err(Error::ValueTooLarge))
+        }
     }
 
     pub fn read_str<F: FnOnce(Result<&str, Error>) -> R, R>(key: &str, f: F) -> R {
@@ -173,15 +374,41 @@ mod imp {
         })
     }
 
+    /// Calls `f` with the key and current value size of every record that has
+    /// not been removed.
+    pub fn iter<F: FnMut(&[u8], usize)>(mut f: F) -> Result<(), Error> {
+        let lock = Lock::take()?;
+        let mut iter = Iter::new(active_data(&lock));
+        while let Some(result) = iter.next() {
+            let (key, value) = result?;
+            if !is_live(&iter, key, value)? {
+                continue
+            }
+            match split_key(key) {
+                (key, Kind::Plain) => f(key, value.len()),
+                (key, Kind::Manifest) if value.len() >= 8 =>
+                    f(key, BigEndian::read_u32(&value[0..4]) as usize),
+                _ => ()
+            }
+        }
+        Ok(())
+    }
+
     unsafe fn append_at(data: &[u8], mut offset: usize,
-                        key: &[u8], value: &[u8]) -> Result<usize, Error> {
-        let record_size = 4 + key.len() + 1 + value.len();
+                        key: &[u8], suffix: &[u8], value: &[u8]) -> Result<usize, Error> {
+        let start = offset;
+        let record_size = RECORD_HEADER_SIZE + key.len() + suffix.len() + 1 + value.len();
         if offset + record_size > data.len() {
             return Err(Error::SpaceExhausted)
         }
 
-        let mut record_size_bytes = [0u8; 4];
-        BigEndian::write_u32(&mut record_size_bytes[..], record_size as u32);
+        let mut record_header = [0u8; RECORD_HEADER_SIZE];
+        BigEndian::write_u32(&mut record_header[0..4], record_size as u32);
+        let mut crc = crc32::checksum_ieee(&record_header[0..4]);
+        for part in [key, suffix, &[0], value].iter() {
+            crc = crc32::update(crc, &crc32::IEEE_TABLE, part);
+        }
+        BigEndian::write_u32(&mut record_header[4..8], crc);
 
         {
             let mut write = |payload| {
@@ -189,80 +416,294 @@ mod imp {
                 offset += payload.len();
             };
 
-            write(&record_size_bytes[..]);
+            write(&record_header[..]);
             write(key);
+            write(suffix);
             write(&[0]);
             write(value);
             cache::flush_l2_cache();
         }
 
+        let record = &data[start..offset];
+        if !flash_health::verify(record.as_ptr() as usize, &record_header) ||
+                record_crc(&record[0..4], &record[RECORD_HEADER_SIZE..]) != crc {
+            return Err(Error::WriteFailed)
+        }
         Ok(offset)
     }
 
-    fn compact() -> Result<(), Error> {
-        let lock = Lock::take()?;
-        let data = lock.data();
-
-        static mut OLD_DATA: [u8; SIZE] = [0; SIZE];
-        let old_data = unsafe {
-            OLD_DATA.copy_from_slice(data);
-            &OLD_DATA[..]
-        };
+    unsafe fn write_header(data: &[u8], sequence: u32) {
+        let mut header = [0u8; HEADER_SIZE];
+        header[0..4].copy_from_slice(MAGIC);
+        BigEndian::write_u32(&mut header[4..8], sequence);
+        let crc = crc32::checksum_ieee(&header[0..8]);
+        BigEndian::write_u32(&mut header[8..12], crc);
+        spiflash::write(data.as_ptr() as usize, &header);
+        cache::flush_l2_cache();
+    }
 
-        unsafe { spiflash::erase_sector(data.as_ptr() as usize) };
+    // Writes `value` as a plain record, or as chunks and their manifest, and
+    // returns the offset right past them.
+    unsafe fn append_value_at(data: &[u8], mut offset: usize,
+                              key: &[u8], value: &[u8]) -> Result<usize, Error> {
+        if value.len() <= CHUNK_SIZE {
+            return append_at(data, offset, key, &[], value)
+        }
+        for (index, chunk) in value.chunks(CHUNK_SIZE).enumerate() {
+            let mut buf = [0; 8];
+            offset = append_at(data, offset, key, chunk_suffix(&mut buf, index), chunk)?;
+        }
+        // The value only takes effect once its manifest is written.
+        let mut manifest = [0; 8];
+        BigEndian::write_u32(&mut manifest[0..4], value.len() as u32);
+        BigEndian::write_u32(&mut manifest[4..8], crc32::checksum_ieee(value));
+        append_at(data, offset, key, &[0xff], &manifest)
+    }
 
+    // Copies the live records of `old_data` whose key `keep` accepts into
+    // `data`, and returns the offset right past them.
+    fn copy_live<F: Fn(&[u8]) -> bool>(old_data: &[u8], data: &[u8], keep: F)
+            -> Result<usize, Error> {
         // This is worst-case quadratic, but we're limited by a small SPI flash sector size,
         // so it does not really matter.
-        let mut offset = 0;
+        let mut offset = HEADER_SIZE;
         let mut iter = Iter::new(old_data);
-        'iter: while let Some(result) = iter.next() {
-            let (key, mut value) = result?;
-            if value.is_empty() {
-                // This is a removed entry, ignore it.
-                continue
+        while let Some(result) = iter.next() {
+            let (key, value) = result?;
+            if keep(split_key(key).0) && is_live(&iter, key, value)? {
+                offset = unsafe { append_at(data, offset, key, &[], value)? };
             }
+        }
+        Ok(offset)
+    }
 
-            let mut next_iter = iter.clone();
-            while let Some(next_result) = next_iter.next() {
-                let (next_key, _) = next_result?;
-                if key == next_key {
-                    // There's another entry that overwrites this one, ignore this one.
-                    continue 'iter
+    // Writes the records of `fill`, given the current records and the other
+    // sector, into the other sector, which then becomes the current one.
+    fn rewrite<F>(fill: F) -> Result<(), Error>
+        where F: Fn(&[u8], &[u8]) -> Result<(), Error>
+    {
+        for _ in 0..flash_health::ATTEMPTS {
+            match rewrite_once(&fill) {
+                Err((Error::WriteFailed, sector)) => {
+                    flash_health::record_failure(ADDR + sector * SIZE);
                 }
+                result => return result.map_err(|(err, _)| err)
             }
-            offset = unsafe { append_at(data, offset, key, value)? };
+        }
+        Err(Error::WriteFailed)
+    }
+
+    // Returns the sector being written with the error.
+    fn rewrite_once<F>(fill: &F) -> Result<(), (Error, usize)>
+        where F: Fn(&[u8], &[u8]) -> Result<(), Error>
+    {
+        let lock = Lock::take().map_err(|err| (err, 0))?;
+        let (old_sector, old_sequence) = active(&lock).unwrap_or((1, 0));
+        let old_data = active_data(&lock);
+        let data = lock.data(1 - old_sector);
+        let failed = |err| (err, 1 - old_sector);
+
+        unsafe { spiflash::erase_sector(data.as_ptr() as usize) };
+        if !flash_health::verify_erased(data.as_ptr() as usize) {
+            return Err(failed(Error::WriteFailed))
+        }
+
+        fill(old_data, data).map_err(&failed)?;
+
+        // Until this point, the old sector is still the current one.
+        unsafe { write_header(data, old_sequence.wrapping_add(1)) };
+        cache::flush_l2_cache();
+        cache::flush_cpu_dcache();
+        if sequence(data) != Some(old_sequence.wrapping_add(1)) {
+            return Err(failed(Error::WriteFailed))
         }
 
         Ok(())
     }
 
-    fn append(key: &str, value: &[u8]) -> Result<(), Error> {
+    fn append(key: &[u8], value: &[u8]) -> Result<(), Error> {
         let lock = Lock::take()?;
-        let data = lock.data();
+        let data = active_data(&lock);
+        if data.is_empty() {
+            // No valid sector yet; compaction will initialize one.
+            return Err(Error::SpaceExhausted)
+        }
 
         let free_offset = {
             let mut iter = Iter::new(data);
             while let Some(result) = iter.next() {
                 let _ = result?;
             }
+            if iter.torn {
+                return Err(Error::SpaceExhausted)
+            }
             iter.offset
         };
 
-        unsafe { append_at(data, free_offset, key.as_bytes(), value)? };
-
-        Ok(())
+        // A torn record is left behind and the records are compacted into
+        // the other sector, so that no data is lost if this one is failing.
+        let result = unsafe { append_value_at(data, free_offset, key, value) };
+        if result == Err(Error::WriteFailed) {
+            flash_health::note_failure();
+        }
+        result.map(|_| ())
     }
 
     pub fn write(key: &str, value: &[u8]) -> Result<(), Error> {
+        if value.len() > MAX_VALUE_SIZE {
+            return Err(Error::ValueTooLarge)
+        }
+        let key = key.as_bytes();
         match append(key, value) {
-            Err(Error::SpaceExhausted) => {
-                compact()?;
-                append(key, value)
+            // The records are compacted along with the new value, rather than
+            // before it, so that the old value of a key does not need to fit
+            // along with the new one.
+            Err(Error::SpaceExhausted) | Err(Error::WriteFailed) => {
+                rewrite(|old_data, data| {
+                    let offset = copy_live(old_data, data, |other| other != key)?;
+                    if !value.is_empty() {
+                        unsafe { append_value_at(data, offset, key, value)? };
+                    }
+                    Ok(())
+                })
             }
             res => res
         }
     }
 
+    /// Verifies the records of the current sector and the CRCs of the chunked
+    /// values, and returns the number of values checked and of those found
+    /// corrupted. A torn record counts as a corrupted value.
+    #[cfg(feature = "alloc")]
+    pub fn check() -> Result<(usize, usize), Error> {
+        let lock = Lock::take()?;
+        let data = active_data(&lock);
+        let (mut checked, mut corrupted) = (0, 0);
+        let mut iter = Iter::new(data);
+        while let Some(result) = iter.next() {
+            let (key, value) = match result {
+                Ok(record) => record,
+                Err(_) => {
+                    iter.torn = true;
+                    break
+                }
+            };
+            if !is_live(&iter, key, value).unwrap_or(true) {
+                continue
+            }
+            match split_key(key) {
+                (_, Kind::Plain) => checked += 1,
+                (base, Kind::Manifest) => {
+                    checked += 1;
+                    if read_chunks(&data[..iter.offset], base, value).is_err() {
+                        corrupted += 1
+                    }
+                }
+                (_, Kind::Chunk(_)) => ()
+            }
+        }
+        if iter.torn {
+            checked += 1;
+            corrupted += 1;
+        }
+        Ok((checked, corrupted))
+    }
+
+    // Backup image: magic, format version, then for each key its length-prefixed
+    // name and value, all followed by a CRC32 of the preceding bytes.
+    const BACKUP_MAGIC: &'static [u8] = b"ACFB";
+    const BACKUP_VERSION: u32 = 1;
+
+    // Keys that hold the state of the device itself rather than its
+    // configuration. They are left out of backups, and kept by restores.
+    const DEVICE_STATE_KEYS: &'static [&'static str] = &[
+        "netboot_once", "audit_restart"
+    ];
+
+    fn is_device_state(key: &[u8]) -> bool {
+        DEVICE_STATE_KEYS.iter().any(|state_key| state_key.as_bytes() == key)
+    }
+
+    #[cfg(feature = "alloc")]
+    fn push_u32(buf: &mut Vec<u8>, value: u32) {
+        let mut bytes = [0; 4];
+        BigEndian::write_u32(&mut bytes, value);
+        buf.extend_from_slice(&bytes);
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn backup() -> Result<Vec<u8>, Error> {
+        let mut keys = Vec::new();
+        iter(|key, _| if !is_device_state(key) { keys.push(key.to_vec()) })?;
+
+        let mut image = Vec::new();
+        image.extend_from_slice(BACKUP_MAGIC);
+        push_u32(&mut image, BACKUP_VERSION);
+        for key in keys {
+            let key = str::from_utf8(&key).map_err(Error::Utf8Error)?;
+            read(key, |result| -> Result<(), Error> {
+                let value = result?;
+                push_u32(&mut image, key.len() as u32);
+                image.extend_from_slice(key.as_bytes());
+                push_u32(&mut image, value.len() as u32);
+                image.extend_from_slice(value);
+                Ok(())
+            })?;
+        }
+        let crc = crc32::checksum_ieee(&image);
+        push_u32(&mut image, crc);
+        Ok(image)
+    }
+
+    // Calls `f` for every key/value pair in a backup image, after validating it.
+    fn parse_backup<F: FnMut(&str, &[u8]) -> Result<(), Error>>(image: &[u8], mut f: F)
+            -> Result<(), Error> {
+        if image.len() < 12 || &image[0..4] != BACKUP_MAGIC ||
+                BigEndian::read_u32(&image[4..8]) != BACKUP_VERSION {
+            return Err(Error::InvalidBackup)
+        }
+        let (body, crc) = image.split_at(image.len() - 4);
+        if crc32::checksum_ieee(body) != BigEndian::read_u32(crc) {
+            return Err(Error::InvalidBackup)
+        }
+
+        let mut data = &body[8..];
+        while !data.is_empty() {
+            let mut fields = [&[][..]; 2];
+            for field in fields.iter_mut() {
+                if data.len() < 4 {
+                    return Err(Error::InvalidBackup)
+                }
+                let len = BigEndian::read_u32(data) as usize;
+                if data.len() - 4 < len {
+                    return Err(Error::InvalidBackup)
+                }
+                *field = &data[4..4 + len];
+                data = &data[4 + len..];
+            }
+            let key = str::from_utf8(fields[0]).map_err(Error::Utf8Error)?;
+            f(key, fields[1])?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the configuration with the contents of a backup image, except
+    /// for the state of the device. The image is fully validated first, and
+    /// then written into the other sector, which only becomes current once it
+    /// is complete.
+    pub fn restore(image: &[u8]) -> Result<(), Error> {
+        parse_backup(image, |_, _| Ok(()))?;
+        rewrite(|old_data, data| {
+            let mut offset = copy_live(old_data, data, is_device_state)?;
+            parse_backup(image, |key, value| {
+                if !is_device_state(key.as_bytes()) && !value.is_empty() {
+                    offset = unsafe { append_value_at(data, offset, key.as_bytes(), value)? };
+                }
+                Ok(())
+            })
+        })
+    }
+
     pub fn write_int(key: &str, value: u32) -> Result<(), Error> {
         let mut buf = [0; 16];
         let mut wrapper = FmtWrapper::new(&mut buf);
@@ -276,9 +717,10 @@ mod imp {
 
     pub fn erase() -> Result<(), Error> {
         let lock = Lock::take()?;
-        let data = lock.data();
 
-        unsafe { spiflash::erase_sector(data.as_ptr() as usize) };
+        for sector in 0..2 {
+            unsafe { spiflash::erase_sector(lock.data(sector).as_ptr() as usize) };
+        }
         cache::flush_l2_cache();
 
         Ok(())
@@ -305,9 +747,27 @@ mod imp {
         Err(Error::NoFlash)
     }
 
+    pub fn iter<F: FnMut(&[u8], usize)>(_f: F) -> Result<(), Error> {
+        Err(Error::NoFlash)
+    }
+
     pub fn erase() -> Result<(), Error> {
         Err(Error::NoFlash)
     }
+
+    #[cfg(feature = "alloc")]
+    pub fn check() -> Result<(usize, usize), Error> {
+        Err(Error::NoFlash)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn backup() -> Result<::alloc::Vec<u8>, Error> {
+        Err(Error::NoFlash)
+    }
+
+    pub fn restore(_image: &[u8]) -> Result<(), Error> {
+        Err(Error::NoFlash)
+    }
 }
 
 pub use self::imp::*;
@@ -0,0 +1,493 @@
+// Verification of Ed25519 signatures (RFC 8032), after TweetNaCl. This is
+// only used on public data, so no effort is made to run in constant time.
+
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+    0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+    0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+    0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+    0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+    0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+    0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+    0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+    0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+    0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+    0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
+
+const SHA512_H: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+];
+
+pub struct Sha512 {
+    state:  [u64; 8],
+    block:  [u8; 128],
+    used:   usize,
+    length: u64
+}
+
+impl Sha512 {
+    pub fn new() -> Sha512 {
+        Sha512 { state: SHA512_H, block: [0; 128], used: 0, length: 0 }
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u64; 80];
+        for i in 0..16 {
+            for j in 0..8 {
+                w[i] = (w[i] << 8) | self.block[8 * i + j] as u64;
+            }
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let mut v = self.state;
+        for i in 0..80 {
+            let s1 = v[4].rotate_right(14) ^ v[4].rotate_right(18) ^ v[4].rotate_right(41);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1).wrapping_add(ch)
+                         .wrapping_add(SHA512_K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(28) ^ v[0].rotate_right(34) ^ v[0].rotate_right(39);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+        }
+        for i in 0..8 {
+            self.state[i] = self.state[i].wrapping_add(v[i]);
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        while !data.is_empty() {
+            let length = if data.len() < 128 - self.used { data.len() } else { 128 - self.used };
+            self.block[self.used..self.used + length].copy_from_slice(&data[..length]);
+            self.used += length;
+            data = &data[length..];
+            if self.used == 128 {
+                self.compress();
+                self.used = 0;
+            }
+        }
+    }
+
+    pub fn finalize(mut self) -> [u8; 64] {
+        let bits = self.length * 8;
+        self.update(&[0x80]);
+        while self.used != 112 {
+            self.update(&[0]);
+        }
+        let mut trailer = [0; 16];
+        for i in 0..8 {
+            trailer[8 + i] = (bits >> (56 - 8 * i)) as u8;
+        }
+        self.update(&trailer);
+
+        let mut digest = [0; 64];
+        for i in 0..64 {
+            digest[i] = (self.state[i / 8] >> (56 - 8 * (i % 8))) as u8;
+        }
+        digest
+    }
+}
+
+// Elements of GF(2^255 - 19), in 16 limbs of 16 bits.
+type Gf = [i64; 16];
+
+const GF0: Gf = [0; 16];
+const GF1: Gf = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const D: Gf = [0x78a3, 0x1359, 0x4dca, 0x75eb, 0xd8ab, 0x4141, 0x0a4d, 0x0070,
+               0xe898, 0x7779, 0x4079, 0x8cc7, 0xfe73, 0x2b6f, 0x6cee, 0x5203];
+const D2: Gf = [0xf159, 0x26b2, 0x9b94, 0xebd6, 0xb156, 0x8283, 0x149a, 0x00e0,
+                0xd130, 0xeef3, 0x80f2, 0x198e, 0xfce7, 0x56df, 0xd9dc, 0x2406];
+const X: Gf = [0xd51a, 0x8f25, 0x2d60, 0xc956, 0xa7b2, 0x9525, 0xc760, 0x692c,
+               0xdc5c, 0xfdd6, 0xe231, 0xc0a4, 0x53fe, 0xcd6e, 0x36d3, 0x2169];
+const Y: Gf = [0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+               0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666];
+const I: Gf = [0xa0b0, 0x4a0e, 0x1b27, 0xc4ee, 0xe478, 0xad2f, 0x1806, 0x2f43,
+               0xd7a7, 0x3dfb, 0x0099, 0x2b4d, 0xdf0b, 0x4fc1, 0x2480, 0x2b83];
+
+// The order of the base point, little-endian.
+const L: [i64; 32] = [0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58,
+                      0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+                      0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10];
+
+fn car25519(o: &mut Gf) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        if i < 15 {
+            o[i + 1] += c - 1
+        } else {
+            o[0] += 38 * (c - 1)
+        }
+        o[i] -= c << 16;
+    }
+}
+
+fn sel25519(p: &mut Gf, q: &mut Gf, b: i64) {
+    let c = !(b - 1);
+    for i in 0..16 {
+        let t = c & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn pack25519(n: &Gf) -> [u8; 32] {
+    let mut t = *n;
+    car25519(&mut t);
+    car25519(&mut t);
+    car25519(&mut t);
+    let mut m = GF0;
+    for _ in 0..2 {
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let b = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        sel25519(&mut t, &mut m, 1 - b);
+    }
+    let mut o = [0; 32];
+    for i in 0..16 {
+        o[2 * i] = t[i] as u8;
+        o[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+    o
+}
+
+fn neq25519(a: &Gf, b: &Gf) -> bool {
+    pack25519(a) != pack25519(b)
+}
+
+fn par25519(a: &Gf) -> u8 {
+    pack25519(a)[0] & 1
+}
+
+fn unpack25519(n: &[u8]) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = n[2 * i] as i64 + ((n[2 * i + 1] as i64) << 8);
+    }
+    o[15] &= 0x7fff;
+    o
+}
+
+fn add(a: &Gf, b: &Gf) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = a[i] + b[i];
+    }
+    o
+}
+
+fn sub(a: &Gf, b: &Gf) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = a[i] - b[i];
+    }
+    o
+}
+
+fn mul(a: &Gf, b: &Gf) -> Gf {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+    let mut o = GF0;
+    o.copy_from_slice(&t[..16]);
+    car25519(&mut o);
+    car25519(&mut o);
+    o
+}
+
+fn square(a: &Gf) -> Gf {
+    mul(a, a)
+}
+
+fn inv25519(i: &Gf) -> Gf {
+    let mut c = *i;
+    for a in (0..254).rev() {
+        c = square(&c);
+        if a != 2 && a != 4 {
+            c = mul(&c, i)
+        }
+    }
+    c
+}
+
+fn pow2523(i: &Gf) -> Gf {
+    let mut c = *i;
+    for a in (0..251).rev() {
+        c = square(&c);
+        if a != 1 {
+            c = mul(&c, i)
+        }
+    }
+    c
+}
+
+// Points in extended coordinates (X, Y, Z, T).
+type Point = [Gf; 4];
+
+fn point_add(p: &mut Point, q: &Point) {
+    let a = mul(&sub(&p[1], &p[0]), &sub(&q[1], &q[0]));
+    let b = mul(&add(&p[0], &p[1]), &add(&q[0], &q[1]));
+    let c = mul(&mul(&p[3], &q[3]), &D2);
+    let d = mul(&p[2], &q[2]);
+    let d = add(&d, &d);
+    let e = sub(&b, &a);
+    let f = sub(&d, &c);
+    let g = add(&d, &c);
+    let h = add(&b, &a);
+    p[0] = mul(&e, &f);
+    p[1] = mul(&h, &g);
+    p[2] = mul(&g, &f);
+    p[3] = mul(&e, &h);
+}
+
+fn cswap(p: &mut Point, q: &mut Point, b: i64) {
+    for i in 0..4 {
+        sel25519(&mut p[i], &mut q[i], b);
+    }
+}
+
+fn pack(p: &Point) -> [u8; 32] {
+    let zi = inv25519(&p[2]);
+    let tx = mul(&p[0], &zi);
+    let ty = mul(&p[1], &zi);
+    let mut r = pack25519(&ty);
+    r[31] ^= par25519(&tx) << 7;
+    r
+}
+
+fn scalarmult(q: &Point, s: &[u8]) -> Point {
+    let mut p = [GF0, GF1, GF1, GF0];
+    let mut q = *q;
+    for i in (0..256).rev() {
+        let b = ((s[i / 8] >> (i & 7)) & 1) as i64;
+        cswap(&mut p, &mut q, b);
+        point_add(&mut q, &p);
+        let p2 = p;
+        point_add(&mut p, &p2);
+        cswap(&mut p, &mut q, b);
+    }
+    p
+}
+
+fn scalarbase(s: &[u8]) -> Point {
+    scalarmult(&[X, Y, GF1, mul(&X, &Y)], s)
+}
+
+// Reduces a 512-bit little-endian number modulo L.
+fn reduce(r: &[u8; 64]) -> [u8; 32] {
+    let mut x = [0i64; 64];
+    for i in 0..64 {
+        x[i] = r[i] as i64;
+    }
+    for i in (32..64).rev() {
+        let mut carry = 0;
+        let mut j = i - 32;
+        while j < i - 12 {
+            x[j] += carry - 16 * x[i] * L[j - (i - 32)];
+            carry = (x[j] + 128) >> 8;
+            x[j] -= carry << 8;
+            j += 1;
+        }
+        x[j] += carry;
+        x[i] = 0;
+    }
+    let mut carry = 0;
+    for j in 0..32 {
+        x[j] += carry - (x[31] >> 4) * L[j];
+        carry = x[j] >> 8;
+        x[j] &= 255;
+    }
+    for j in 0..32 {
+        x[j] -= carry * L[j];
+    }
+    let mut o = [0; 32];
+    for i in 0..32 {
+        x[i + 1] += x[i] >> 8;
+        o[i] = (x[i] & 255) as u8;
+    }
+    o
+}
+
+// Decodes the negation of the point encoded in p, if it is on the curve.
+fn unpackneg(p: &[u8]) -> Option<Point> {
+    let mut r = [GF0, unpack25519(p), GF1, GF0];
+    let num = square(&r[1]);
+    let den = mul(&num, &D);
+    let num = sub(&num, &r[2]);
+    let den = add(&r[2], &den);
+
+    let den2 = square(&den);
+    let den4 = square(&den2);
+    let den6 = mul(&den4, &den2);
+    let mut t = mul(&mul(&den6, &num), &den);
+    t = pow2523(&t);
+    t = mul(&mul(&mul(&t, &num), &den), &den);
+    r[0] = mul(&t, &den);
+
+    if neq25519(&mul(&square(&r[0]), &den), &num) {
+        r[0] = mul(&r[0], &I);
+    }
+    if neq25519(&mul(&square(&r[0]), &den), &num) {
+        return None
+    }
+    if par25519(&r[0]) == p[31] >> 7 {
+        r[0] = sub(&GF0, &r[0]);
+    }
+    r[3] = mul(&r[0], &r[1]);
+    Some(r)
+}
+
+// Whether the little-endian scalar s is below L.
+fn is_canonical(s: &[u8]) -> bool {
+    for i in (0..32).rev() {
+        if (s[i] as i64) < L[i] { return true }
+        if (s[i] as i64) > L[i] { return false }
+    }
+    false
+}
+
+/// Checks the signature of the concatenation of the parts of `message` made
+/// with the private key matching `public_key`.
+pub fn verify(public_key: &[u8; 32], message: &[&[u8]], signature: &[u8; 64]) -> bool {
+    if !is_canonical(&signature[32..]) {
+        return false
+    }
+    let a = match unpackneg(public_key) {
+        Some(a) => a,
+        None => return false
+    };
+
+    let mut hasher = Sha512::new();
+    hasher.update(&signature[..32]);
+    hasher.update(public_key);
+    for part in message {
+        hasher.update(part);
+    }
+    let h = reduce(&hasher.finalize());
+
+    // [S]B - [h]A must be R.
+    let mut p = scalarmult(&a, &h);
+    point_add(&mut p, &scalarbase(&signature[32..]));
+    pack(&p)[..] == signature[..32]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(string: &str, bytes: &mut [u8]) {
+        assert_eq!(string.len(), 2 * bytes.len());
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&string[2 * i..2 * i + 2], 16).unwrap();
+        }
+    }
+
+    fn digest(message: &[u8]) -> [u8; 64] {
+        let mut hasher = Sha512::new();
+        hasher.update(message);
+        hasher.finalize()
+    }
+
+    // FIPS 180-2, appendix C.
+    #[test]
+    fn sha512() {
+        let mut expected = [0; 64];
+        unhex("ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+               2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+              &mut expected);
+        assert_eq!(&digest(b"abc")[..], &expected[..]);
+
+        unhex("8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018\
+               501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909",
+              &mut expected);
+        assert_eq!(&digest(b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmn\
+                              hijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu")[..],
+                   &expected[..]);
+    }
+
+    // RFC 8032, section 7.1, tests 1 to 3.
+    const VECTORS: [(&str, &[u8], &str); 3] = [
+        ("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a", b"",
+         "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+          5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"),
+        ("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c", b"\x72",
+         "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+          085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"),
+        ("fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025", b"\xaf\x82",
+         "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac\
+          18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a"),
+    ];
+
+    fn vector(index: usize) -> ([u8; 32], &'static [u8], [u8; 64]) {
+        let (public_key_hex, message, signature_hex) = VECTORS[index];
+        let mut public_key = [0; 32];
+        unhex(public_key_hex, &mut public_key);
+        let mut signature = [0; 64];
+        unhex(signature_hex, &mut signature);
+        (public_key, message, signature)
+    }
+
+    #[test]
+    fn accepts_rfc8032_vectors() {
+        for index in 0..VECTORS.len() {
+            let (public_key, message, signature) = vector(index);
+            assert!(verify(&public_key, &[message], &signature));
+        }
+
+        // The message may come in parts.
+        let (public_key, message, signature) = vector(2);
+        assert!(verify(&public_key, &[&message[..1], &[], &message[1..]], &signature));
+    }
+
+    #[test]
+    fn rejects_altered_signatures() {
+        let (public_key, message, signature) = vector(2);
+        assert!(!verify(&public_key, &[b"\xaf\x83"], &signature));
+        assert!(!verify(&public_key, &[message, b"\x00"], &signature));
+
+        let (other_public_key, _, _) = vector(1);
+        assert!(!verify(&other_public_key, &[message], &signature));
+
+        for &bit in [0, 255, 256, 511].iter() {
+            let mut altered = signature;
+            altered[bit / 8] ^= 1 << (bit % 8);
+            assert!(!verify(&public_key, &[message], &altered));
+        }
+    }
+
+    #[test]
+    fn rejects_non_canonical_scalar() {
+        // Test 1 with S + L in place of S, which passes the group equation.
+        let (public_key, message, mut signature) = vector(0);
+        unhex("4c8c7872aa064e049dbb3013fbf29380d25bf5f0595bbe24655141438e7a101b",
+              &mut signature[32..]);
+        assert!(!verify(&public_key, &[message], &signature));
+    }
+}
@@ -0,0 +1,176 @@
+use core::cmp::min;
+use i2c;
+use clock;
+
+// Driver for the 24xx family of I2C EEPROMs (24C01 to 24C512 and their
+// compatibles), e.g. those of EEM cards for user calibration data. Devices
+// of up to 2 KiB take one address byte and the upper bits of the address in
+// the device address, larger ones take two address bytes. Writes are done
+// by page, and the device does not acknowledge its address until the write
+// cycle of the page is over.
+
+const MAX_SIZE: u32 = 0x10000;
+const SMALL_SIZE: u32 = 0x800;
+const WRITE_CYCLE_TIMEOUT_MS: u64 = 20;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Eeprom {
+    busno:     u8,
+    port:      Option<u8>,
+    address:   u8,
+    size:      u32,
+    page_size: u32
+}
+
+impl Eeprom {
+    /// `port` is the port of the I2C switches of Kasli to select, if any,
+    /// `address` is 7-bit, and `size` and `page_size` are in bytes.
+    pub fn new(busno: u8, port: Option<u8>, address: u8, size: u32, page_size: u32)
+              -> Result<Eeprom, &'static str> {
+        if address >= 0x80 {
+            return Err("invalid EEPROM address")
+        }
+        if size == 0 || size > MAX_SIZE || !size.is_power_of_two() {
+            return Err("invalid EEPROM size")
+        }
+        if page_size == 0 || page_size > size || !page_size.is_power_of_two() {
+            return Err("invalid EEPROM page size")
+        }
+        if port.map_or(false, |port| port >= 16) {
+            return Err("invalid I2C switch port")
+        }
+        Ok(Eeprom {
+            busno:     busno,
+            port:      port,
+            address:   address,
+            size:      size,
+            page_size: page_size
+        })
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    #[cfg(soc_platform = "kasli")]
+    fn select(&self) -> Result<(), &'static str> {
+        if let Some(port) = self.port {
+            let mask: u16 = 1 << port;
+            i2c::pca9548_select(self.busno, 0x70, mask as u8)?;
+            i2c::pca9548_select(self.busno, 0x71, (mask >> 8) as u8)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(soc_platform = "kasli"))]
+    fn select(&self) -> Result<(), &'static str> {
+        match self.port {
+            Some(_) => Err("no I2C switches on this board"),
+            None => Ok(())
+        }
+    }
+
+    fn check_range(&self, offset: u32, length: usize) -> Result<(), &'static str> {
+        if offset as u64 + length as u64 > self.size as u64 {
+            return Err("EEPROM access out of range")
+        }
+        Ok(())
+    }
+
+    // 8-bit device address for writing, with the block of `offset` for the
+    // devices with a single address byte.
+    fn device_address(&self, offset: u32) -> u8 {
+        if self.size <= SMALL_SIZE {
+            (self.address | (offset >> 8) as u8 & 0x7) << 1
+        } else {
+            self.address << 1
+        }
+    }
+
+    fn write_byte(&self, data: u8) -> Result<(), &'static str> {
+        if !i2c::write(self.busno, data)? {
+            return Err("EEPROM failed to ack")
+        }
+        Ok(())
+    }
+
+    fn start(&self, offset: u32) -> Result<(), &'static str> {
+        i2c::start(self.busno)?;
+        self.write_byte(self.device_address(offset))?;
+        if self.size > SMALL_SIZE {
+            self.write_byte((offset >> 8) as u8)?;
+        }
+        self.write_byte(offset as u8)
+    }
+
+    fn read_chunk(&self, offset: u32, data: &mut [u8]) -> Result<(), &'static str> {
+        self.start(offset)?;
+        i2c::restart(self.busno)?;
+        self.write_byte(self.device_address(offset) | 1)?;
+        let length = data.len();
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = i2c::read(self.busno, i < length - 1)?;
+        }
+        i2c::stop(self.busno)
+    }
+
+    fn write_page(&self, offset: u32, data: &[u8]) -> Result<(), &'static str> {
+        self.start(offset)?;
+        for &byte in data {
+            self.write_byte(byte)?;
+        }
+        i2c::stop(self.busno)
+    }
+
+    // Acknowledge polling: the device ignores its address during the write
+    // cycle.
+    fn wait_write_cycle(&self, offset: u32) -> Result<(), &'static str> {
+        let deadline = clock::get_ms() + WRITE_CYCLE_TIMEOUT_MS;
+        loop {
+            i2c::start(self.busno)?;
+            let ack = i2c::write(self.busno, self.device_address(offset))?;
+            i2c::stop(self.busno)?;
+            if ack {
+                return Ok(())
+            }
+            if clock::get_ms() > deadline {
+                return Err("EEPROM write cycle timed out")
+            }
+        }
+    }
+
+    pub fn read(&self, offset: u32, data: &mut [u8]) -> Result<(), &'static str> {
+        self.check_range(offset, data.len())?;
+        self.select()?;
+        let mut done = 0;
+        while done < data.len() {
+            let at = offset + done as u32;
+            // Not all devices with a single address byte roll over into the
+            // next block.
+            let length = if self.size <= SMALL_SIZE {
+                min(data.len() - done, 0x100 - (at & 0xff) as usize)
+            } else {
+                data.len() - done
+            };
+            self.read_chunk(at, &mut data[done..done + length])
+                .map_err(|e| { let _ = i2c::stop(self.busno); e })?;
+            done += length;
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, offset: u32, data: &[u8]) -> Result<(), &'static str> {
+        self.check_range(offset, data.len())?;
+        self.select()?;
+        let mut done = 0;
+        while done < data.len() {
+            let at = offset + done as u32;
+            let length = min(data.len() - done, (self.page_size - at % self.page_size) as usize);
+            self.write_page(at, &data[done..done + length])
+                .map_err(|e| { let _ = i2c::stop(self.busno); e })?;
+            self.wait_write_cycle(at)?;
+            done += length;
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,20 @@
-use core::{slice, fmt};
+use core::fmt;
+use byteorder::{ByteOrder, BigEndian};
 use smoltcp::Result;
 use smoltcp::time::Instant;
 use smoltcp::phy::{self, DeviceCapabilities, Device};
+use smoltcp::wire::{EthernetFrame, EthernetProtocol, Ipv4Packet, IpProtocol, TcpPacket,
+                    TcpSeqNumber};
 
 use csr;
 use mem::ETHMAC_BASE;
 
+// The MiSoC MAC exposes a ring of fixed-size SRAM slots for each direction.
+// Without a DMA engine, the tokens below hand the slots themselves to
+// smoltcp, which parses received frames in place and serializes outgoing
+// ones directly into them, one uncached bus access at a time. With the
+// ethmac_dma core, frames are built and parsed in cached main memory, and
+// copied to and from the slots by the DMA engine; see the dma module.
 const RX_SLOTS: usize = csr::ETHMAC_RX_SLOTS as usize;
 const TX_SLOTS: usize = csr::ETHMAC_TX_SLOTS as usize;
 const SLOT_SIZE: usize = csr::ETHMAC_SLOT_SIZE as usize;
@@ -40,10 +49,232 @@ fn tx_buffer(slot: usize) -> *mut u8 {
     (ETHMAC_BASE + SLOT_SIZE * (RX_SLOTS + slot)) as _
 }
 
+/// Frames and bytes passed between the MAC and the network stack since boot,
+/// and TCP segments sent again.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PacketCounts {
+    pub rx_packets:          u32,
+    pub rx_bytes:            u64,
+    pub tx_packets:          u32,
+    pub tx_bytes:            u64,
+    pub tcp_retransmissions: u32
+}
+
+static mut PACKET_COUNTS: PacketCounts = PacketCounts {
+    rx_packets:          0,
+    rx_bytes:            0,
+    tx_packets:          0,
+    tx_bytes:            0,
+    tcp_retransmissions: 0
+};
+
+pub fn packet_counts() -> PacketCounts {
+    unsafe { PACKET_COUNTS }
+}
+
+// smoltcp does not count retransmissions, so they are recognized in the
+// outgoing frames: for the connections that sent data most recently, the
+// end of the data sent so far is tracked, and a segment whose data starts
+// before it is a retransmission.
+const TRACKED_CONNECTIONS: usize = 8;
+
+#[derive(Clone, Copy)]
+struct SentSegments {
+    // Remote address, local and remote port.
+    connection: (u32, u16, u16),
+    end:        TcpSeqNumber
+}
+
+static mut SENT_SEGMENTS: [Option<SentSegments>; TRACKED_CONNECTIONS] =
+    [None; TRACKED_CONNECTIONS];
+static mut NEXT_TRACKED: usize = 0;
+
+fn count_retransmission(frame: &[u8]) {
+    let frame = match EthernetFrame::new_checked(frame) {
+        Ok(frame) if frame.ethertype() == EthernetProtocol::Ipv4 => frame,
+        _ => return
+    };
+    let packet = match Ipv4Packet::new_checked(frame.payload()) {
+        Ok(packet) if packet.protocol() == IpProtocol::Tcp => packet,
+        _ => return
+    };
+    let segment = match TcpPacket::new_checked(packet.payload()) {
+        Ok(segment) => segment,
+        _ => return
+    };
+    // SYN and FIN take a sequence number like a byte of data.
+    let length = segment.payload().len() + segment.syn() as usize + segment.fin() as usize;
+    if length == 0 {
+        return
+    }
+    let connection = (BigEndian::read_u32(packet.dst_addr().as_bytes()),
+                      segment.src_port(), segment.dst_port());
+    let start = segment.seq_number();
+    let end = start + length;
+
+    unsafe {
+        for sent in SENT_SEGMENTS.iter_mut() {
+            match *sent {
+                Some(ref mut sent) if sent.connection == connection => {
+                    if segment.syn() && start + 1 != sent.end {
+                        // A new connection between the same endpoints.
+                        sent.end = end
+                    } else {
+                        if start < sent.end {
+                            PACKET_COUNTS.tcp_retransmissions =
+                                PACKET_COUNTS.tcp_retransmissions.wrapping_add(1);
+                        }
+                        if end > sent.end {
+                            sent.end = end
+                        }
+                    }
+                    return
+                }
+                _ => ()
+            }
+        }
+        SENT_SEGMENTS[NEXT_TRACKED] = Some(SentSegments { connection: connection, end: end });
+        NEXT_TRACKED = (NEXT_TRACKED + 1) % TRACKED_CONNECTIONS;
+    }
+}
+
+// The runtime allocates one receive and one transmit buffer in main memory.
+// A received frame is copied out of its slot before smoltcp sees it, so the
+// MAC gets the slot back at once. An outgoing frame is copied while smoltcp
+// goes on with other work, and handed to the MAC at the next call into the
+// device, which waits for the copy first; so at most one frame is in flight
+// and the transmit buffer is free whenever a token is given out.
+#[cfg(all(has_ethmac_dma, feature = "alloc"))]
+mod dma {
+    use core::slice;
+    use alloc::Vec;
+    use {cache, csr};
+    use super::SLOT_SIZE;
+
+    struct Buffers {
+        rx: Vec<u32>,
+        tx: Vec<u32>,
+        // Slot and length of the frame being copied to the MAC, if any.
+        pending: Option<(usize, usize)>,
+        // Descriptors queued since boot, compared with those completed.
+        queued: u16
+    }
+
+    static mut BUFFERS: Option<Buffers> = None;
+
+    fn buffer() -> Vec<u32> {
+        let mut buffer = Vec::with_capacity(SLOT_SIZE / 4);
+        buffer.resize(SLOT_SIZE / 4, 0);
+        buffer
+    }
+
+    pub unsafe fn init() {
+        BUFFERS = Some(Buffers {
+            rx: buffer(),
+            tx: buffer(),
+            pending: None,
+            queued: csr::ethmac_dma::completed_read()
+        })
+    }
+
+    fn buffers() -> &'static mut Buffers {
+        unsafe { BUFFERS.as_mut().expect("ethmac DMA buffers not allocated") }
+    }
+
+    unsafe fn copy(source: *const u8, destination: *mut u8, length: usize) {
+        let buffers = buffers();
+        while csr::ethmac_dma::ready_read() == 0 {}
+        csr::ethmac_dma::source_write(source as u32);
+        csr::ethmac_dma::destination_write(destination as u32);
+        csr::ethmac_dma::length_write(length as u16);
+        csr::ethmac_dma::last_write(1);
+        csr::ethmac_dma::push_write(1);
+        buffers.queued = buffers.queued.wrapping_add(1);
+    }
+
+    unsafe fn wait() {
+        let queued = buffers().queued;
+        while csr::ethmac_dma::completed_read() != queued {}
+    }
+
+    /// Starts sending the frame copied by the last `send`, once the copy is
+    /// done.
+    pub unsafe fn flush() {
+        if let Some((slot, length)) = buffers().pending.take() {
+            wait();
+            super::start(slot, length)
+        }
+    }
+
+    pub unsafe fn receive(slot: usize, length: usize) -> &'static mut [u8] {
+        let buffer = buffers().rx.as_mut_ptr() as *mut u8;
+        copy(super::rx_buffer(slot), buffer, length);
+        wait();
+        // The DMA engine wrote behind the L1 cache.
+        cache::flush_cpu_dcache();
+        slice::from_raw_parts_mut(buffer, length)
+    }
+
+    pub unsafe fn transmit_buffer(length: usize) -> &'static mut [u8] {
+        slice::from_raw_parts_mut(buffers().tx.as_mut_ptr() as *mut u8, length)
+    }
+
+    pub unsafe fn send(slot: usize, length: usize) {
+        copy(buffers().tx.as_ptr() as *const u8, super::tx_buffer(slot), length);
+        buffers().pending = Some((slot, length))
+    }
+}
+
+#[cfg(all(has_ethmac_dma, feature = "alloc"))]
+unsafe fn flush() {
+    dma::flush()
+}
+
+#[cfg(not(all(has_ethmac_dma, feature = "alloc")))]
+unsafe fn flush() {}
+
+#[cfg(all(has_ethmac_dma, feature = "alloc"))]
+unsafe fn rx_frame(slot: usize, length: usize) -> &'static mut [u8] {
+    dma::receive(slot, length)
+}
+
+#[cfg(not(all(has_ethmac_dma, feature = "alloc")))]
+unsafe fn rx_frame(slot: usize, length: usize) -> &'static mut [u8] {
+    ::core::slice::from_raw_parts_mut(rx_buffer(slot), length)
+}
+
+#[cfg(all(has_ethmac_dma, feature = "alloc"))]
+unsafe fn tx_frame(_slot: usize, length: usize) -> &'static mut [u8] {
+    dma::transmit_buffer(length)
+}
+
+#[cfg(not(all(has_ethmac_dma, feature = "alloc")))]
+unsafe fn tx_frame(slot: usize, length: usize) -> &'static mut [u8] {
+    ::core::slice::from_raw_parts_mut(tx_buffer(slot), length)
+}
+
+#[cfg(all(has_ethmac_dma, feature = "alloc"))]
+unsafe fn send(slot: usize, length: usize) {
+    dma::send(slot, length)
+}
+
+#[cfg(not(all(has_ethmac_dma, feature = "alloc")))]
+unsafe fn send(slot: usize, length: usize) {
+    start(slot, length)
+}
+
+unsafe fn start(slot: usize, length: usize) {
+    csr::ethmac::sram_reader_slot_write(slot as u8);
+    csr::ethmac::sram_reader_length_write(length as u16);
+    csr::ethmac::sram_reader_start_write(1);
+}
+
 pub struct EthernetDevice(());
 
 impl EthernetDevice {
     pub unsafe fn new() -> EthernetDevice {
+        #[cfg(all(has_ethmac_dma, feature = "alloc"))]
+        dma::init();
         EthernetDevice(())
     }
 
@@ -77,6 +308,7 @@ impl<'a> Device<'a> for EthernetDevice {
     }
 
     fn receive(&mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        unsafe { flush() }
         if let (Some(rx_slot), Some(tx_slot)) = (next_rx_slot(), next_tx_slot()) {
             Some((EthernetRxSlot(rx_slot), EthernetTxSlot(tx_slot)))
         } else {
@@ -85,6 +317,7 @@ impl<'a> Device<'a> for EthernetDevice {
     }
 
     fn transmit(&mut self) -> Option<Self::TxToken> {
+        unsafe { flush() }
         if let Some(tx_slot) = next_tx_slot() {
             Some(EthernetTxSlot(tx_slot))
         } else {
@@ -101,8 +334,10 @@ impl phy::RxToken for EthernetRxSlot {
     {
         unsafe {
             let length = csr::ethmac::sram_writer_length_read() as usize;
-            let result = f(slice::from_raw_parts_mut(rx_buffer(self.0), length));
+            let result = f(rx_frame(self.0, length));
             csr::ethmac::sram_writer_ev_pending_write(1);
+            PACKET_COUNTS.rx_packets = PACKET_COUNTS.rx_packets.wrapping_add(1);
+            PACKET_COUNTS.rx_bytes += length as u64;
             result
         }
     }
@@ -117,10 +352,12 @@ impl phy::TxToken for EthernetTxSlot {
         debug_assert!(length < SLOT_SIZE);
 
         unsafe {
-            let result = f(slice::from_raw_parts_mut(tx_buffer(self.0), length))?;
-            csr::ethmac::sram_reader_slot_write(self.0 as u8);
-            csr::ethmac::sram_reader_length_write(length as u16);
-            csr::ethmac::sram_reader_start_write(1);
+            let frame = tx_frame(self.0, length);
+            let result = f(&mut *frame)?;
+            count_retransmission(frame);
+            send(self.0, length);
+            PACKET_COUNTS.tx_packets = PACKET_COUNTS.tx_packets.wrapping_add(1);
+            PACKET_COUNTS.tx_bytes += length as u64;
             Ok(result)
         }
     }
@@ -128,9 +365,9 @@ impl phy::TxToken for EthernetTxSlot {
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct EthernetStatistics {
-    rx_preamble_errors: u32,
-    rx_crc_errors:      u32,
-    rx_dropped:         u32,
+    pub rx_preamble_errors: u32,
+    pub rx_crc_errors:      u32,
+    pub rx_dropped:         u32,
 }
 
 impl EthernetStatistics {
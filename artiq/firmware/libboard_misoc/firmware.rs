@@ -0,0 +1,330 @@
+use core::{fmt, slice};
+use byteorder::{ByteOrder, BigEndian};
+use crc::crc32;
+use {mem, cache, boot_state, spiflash};
+
+// Firmware is stored in one of two banks following the configuration sectors.
+// An update is written into the bank that is not active and booted once on
+// trial; the bootloader goes back to the active bank if the new firmware
+// does not check in during that boot.
+//
+// The areas the firmware reserves for itself (panic log, boot key, boot
+// statistics, flash storage, flash health table and boot state) start at
+// `END`. This layout depends on the board, and artiq_flash.py has the same
+// table.
+#[cfg(not(any(soc_platform = "kc705", soc_platform = "sayma_amc", soc_platform = "metlino")))]
+mod layout {
+    pub const BANKS: usize = 2;
+    pub const BANK_SIZE: usize = 0x200000;
+    pub const END: usize = ::mem::FLASH_BOOT_ADDRESS + BANKS * BANK_SIZE;
+}
+
+// The gateware of the KC705 fills most of its flash; smaller banks leave
+// room for the reserved areas.
+#[cfg(soc_platform = "kc705")]
+mod layout {
+    pub const BANKS: usize = 2;
+    pub const BANK_SIZE: usize = 0x1c0000;
+    pub const END: usize = ::mem::FLASH_BOOT_ADDRESS + BANKS * BANK_SIZE;
+}
+
+// On Sayma and Metlino, the RTM gateware follows the firmware at 0x200000,
+// which leaves room for a single bank. The reserved areas follow the space
+// kept for the RTM gateware.
+#[cfg(any(soc_platform = "sayma_amc", soc_platform = "metlino"))]
+mod layout {
+    pub const BANKS: usize = 1;
+    pub const BANK_SIZE: usize = 0x200000 - ::mem::FLASH_BOOT_ADDRESS;
+    pub const END: usize = 0x600000;
+}
+
+/// Number of firmware banks, size of each, and first address of the areas
+/// reserved after them.
+pub use self::layout::{BANKS, BANK_SIZE, END};
+
+// Images carry the same header as the .fbi files written by artiq_flash:
+// length of the firmware, then its CRC32. Images signed with artiq_sign are
+// followed by a trailer: magic, then the Ed25519 signature of the firmware.
+const HEADER_SIZE: usize = 8;
+const SIGNATURE_MAGIC: &'static [u8] = b"ASIG";
+const SIGNATURE_SIZE: usize = 4 + 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bank {
+    A,
+    B
+}
+
+impl Bank {
+    fn from_index(index: u8) -> Bank {
+        if index == 1 && BANKS > 1 { Bank::B } else { Bank::A }
+    }
+
+    fn index(self) -> u8 {
+        match self {
+            Bank::A => 0,
+            Bank::B => 1
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Bank::A => "a",
+            Bank::B => "b"
+        }
+    }
+
+    pub fn address(self) -> usize {
+        debug_assert!(BANKS > 1 || self == Bank::A);
+        match self {
+            Bank::A => mem::FLASH_BOOT_ADDRESS,
+            Bank::B => mem::FLASH_BOOT_ADDRESS + BANK_SIZE
+        }
+    }
+
+    pub fn other(self) -> Bank {
+        match self {
+            Bank::A => Bank::B,
+            Bank::B => Bank::A
+        }
+    }
+}
+
+impl fmt::Display for Bank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    InvalidImage,
+    ImageTooLarge,
+    TrialInProgress,
+    SingleBank,
+    VerifyFailed,
+    StateWriteFailed
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::InvalidImage =>
+                write!(f, "invalid firmware image header or CRC"),
+            &Error::ImageTooLarge =>
+                write!(f, "firmware image larger than a bank ({} bytes)", BANK_SIZE),
+            &Error::TrialInProgress =>
+                write!(f, "the running firmware has not checked in yet"),
+            &Error::SingleBank =>
+                write!(f, "this board has a single firmware bank, which can only be \
+                           written by the bootloader or artiq_flash"),
+            &Error::VerifyFailed =>
+                write!(f, "firmware image read back from flash does not match"),
+            &Error::StateWriteFailed =>
+                write!(f, "failed to record the state of the firmware banks")
+        }
+    }
+}
+
+// Records the state of the firmware banks as changed by `f`, unless it is
+// unchanged.
+fn set_state<F: FnOnce(&mut boot_state::BootState)>(f: F) -> Result<(), Error> {
+    let old_state = boot_state::read();
+    let mut state = old_state;
+    f(&mut state);
+    if state != old_state && !boot_state::write(state) {
+        return Err(Error::StateWriteFailed)
+    }
+    Ok(())
+}
+
+/// The bank booted when no trial is pending.
+pub fn active() -> Bank {
+    Bank::from_index(boot_state::read().fw_active)
+}
+
+/// The bank holding an update that has not checked in yet, if any.
+pub fn trial() -> Option<Bank> {
+    if BANKS == 1 {
+        return None
+    }
+    boot_state::read().fw_trial.map(Bank::from_index)
+}
+
+/// Whether the bank under trial has already been booted once.
+pub fn trial_started() -> bool {
+    boot_state::read().fw_trial_started
+}
+
+pub fn start_trial() -> Result<(), Error> {
+    set_state(|state| state.fw_trial_started = true)
+}
+
+/// Forgets the bank under trial; the active bank is booted from now on.
+pub fn abort_trial() -> Result<(), Error> {
+    set_state(|state| {
+        state.fw_trial = None;
+        state.fw_trial_started = false
+    })
+}
+
+/// Called by the firmware once it is running correctly. If it was booted
+/// on trial, makes its bank the active one and returns it.
+pub fn check_in() -> Result<Option<Bank>, Error> {
+    match trial() {
+        Some(bank) if trial_started() => {
+            set_state(|state| {
+                state.fw_active = bank.index();
+                state.fw_trial = None;
+                state.fw_trial_started = false
+            })?;
+            Ok(Some(bank))
+        }
+        _ => Ok(None)
+    }
+}
+
+/// Returns the firmware stored in `bank` if its header and CRC are valid.
+pub fn read(bank: Bank) -> Option<&'static [u8]> {
+    let header = unsafe { slice::from_raw_parts(bank.address() as *const u8, HEADER_SIZE) };
+    let length = BigEndian::read_u32(&header[0..]) as usize;
+    let expected_crc = BigEndian::read_u32(&header[4..]);
+    if length == 0 || length > BANK_SIZE - HEADER_SIZE {
+        return None
+    }
+
+    let firmware = unsafe {
+        slice::from_raw_parts((bank.address() + HEADER_SIZE) as *const u8, length)
+    };
+    if crc32::checksum_ieee(firmware) == expected_crc {
+        Some(firmware)
+    } else {
+        None
+    }
+}
+
+/// Returns the signature following the firmware of `length` bytes stored
+/// in `bank`, if it is signed.
+pub fn signature(bank: Bank, length: usize) -> Option<[u8; 64]> {
+    if HEADER_SIZE + length + SIGNATURE_SIZE > BANK_SIZE {
+        return None
+    }
+    let trailer = unsafe {
+        slice::from_raw_parts((bank.address() + HEADER_SIZE + length) as *const u8,
+                              SIGNATURE_SIZE)
+    };
+    if &trailer[..4] != SIGNATURE_MAGIC {
+        return None
+    }
+    let mut signature = [0; 64];
+    signature.copy_from_slice(&trailer[4..]);
+    Some(signature)
+}
+
+// Whether an image of `image_length` bytes, header included, holds `length`
+// bytes of firmware, with or without a signature.
+fn image_length_valid(image_length: usize, length: usize) -> bool {
+    image_length == HEADER_SIZE + length ||
+        image_length == HEADER_SIZE + length + SIGNATURE_SIZE
+}
+
+/// Prepares writing an image of `length` bytes, header included, into the
+/// bank that is not active. The image is then written with `write_update`
+/// and committed with `finish_update`. Boards with a single bank refuse
+/// updates, as an update that fails to boot could not be rolled back.
+pub fn begin_update(length: usize) -> Result<Bank, Error> {
+    if BANKS == 1 {
+        return Err(Error::SingleBank)
+    }
+    if length <= HEADER_SIZE {
+        return Err(Error::InvalidImage)
+    }
+    if length > BANK_SIZE {
+        return Err(Error::ImageTooLarge)
+    }
+    if trial().is_some() && trial_started() {
+        return Err(Error::TrialInProgress)
+    }
+
+    abort_trial()?;
+    Ok(active().other())
+}
+
+/// Writes part of an image at `offset` within `bank`. Parts must be written
+/// in order; each flash sector is erased when the first part reaching into
+/// it is written.
+pub fn write_update(bank: Bank, offset: usize, data: &[u8]) -> Result<(), Error> {
+    if offset + data.len() > BANK_SIZE {
+        return Err(Error::ImageTooLarge)
+    }
+
+    let first_sector = (offset + spiflash::SECTOR_SIZE - 1) / spiflash::SECTOR_SIZE;
+    unsafe {
+        for sector in (first_sector * spiflash::SECTOR_SIZE..offset + data.len())
+                .step_by(spiflash::SECTOR_SIZE) {
+            spiflash::erase_sector(bank.address() + sector);
+        }
+        spiflash::write(bank.address() + offset, data);
+    }
+    Ok(())
+}
+
+/// Checks the image of `length` bytes written into `bank`, and schedules it
+/// to be booted on trial at the next boot.
+pub fn finish_update(bank: Bank, length: usize) -> Result<(), Error> {
+    cache::flush_l2_cache();
+    cache::flush_cpu_dcache();
+    match read(bank) {
+        Some(firmware) if image_length_valid(length, firmware.len()) => (),
+        _ => return Err(Error::VerifyFailed)
+    }
+
+    set_state(|state| {
+        state.fw_trial = Some(bank.index());
+        state.fw_trial_started = false
+    })
+}
+
+// Checks the header and CRC of a firmware image.
+fn check_image(image: &[u8]) -> Result<(), Error> {
+    if image.len() < HEADER_SIZE {
+        return Err(Error::InvalidImage)
+    }
+    let length = BigEndian::read_u32(&image[0..]) as usize;
+    let expected_crc = BigEndian::read_u32(&image[4..]);
+    if !image_length_valid(image.len(), length) ||
+            crc32::checksum_ieee(&image[HEADER_SIZE..HEADER_SIZE + length]) != expected_crc {
+        return Err(Error::InvalidImage)
+    }
+    Ok(())
+}
+
+/// Writes a firmware image, with its header, into the bank that is not
+/// active, and schedules it to be booted on trial at the next boot.
+pub fn update(image: &[u8]) -> Result<Bank, Error> {
+    check_image(image)?;
+
+    let bank = begin_update(image.len())?;
+    write_update(bank, 0, image)?;
+    finish_update(bank, image.len())?;
+    Ok(bank)
+}
+
+/// Writes a firmware image, with its header, over the firmware of a board
+/// with a single bank. Only used by the bootloader, which remains available
+/// to write another image if this one does not boot.
+pub fn overwrite(image: &[u8]) -> Result<(), Error> {
+    debug_assert!(BANKS == 1);
+    check_image(image)?;
+    if image.len() > BANK_SIZE {
+        return Err(Error::ImageTooLarge)
+    }
+
+    write_update(Bank::A, 0, image)?;
+    cache::flush_l2_cache();
+    cache::flush_cpu_dcache();
+    match read(Bank::A) {
+        Some(firmware) if image_length_valid(image.len(), firmware.len()) => Ok(()),
+        _ => Err(Error::VerifyFailed)
+    }
+}
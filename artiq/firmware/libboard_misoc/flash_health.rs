@@ -0,0 +1,166 @@
+use core::slice;
+use byteorder::{ByteOrder, BigEndian};
+use crc::crc32;
+use {cache, firmware, spiflash};
+
+// Sectors of the configuration and flash storage areas that repeatedly fail
+// to erase or program are remapped to spare sectors. The remap table is the
+// sector after the flash storage area, followed by the spares. Entries of
+// the table (address of the failing sector, address of its spare, CRC32 of
+// both) are appended to its erased space, so that the table is never
+// erased; the last entry of a sector wins. Failures are counted since boot.
+
+const TABLE_ADDR: usize = firmware::END + 6 * spiflash::SECTOR_SIZE;
+pub const SPARE_COUNT: usize = 4;
+const ENTRY_SIZE: usize = 12;
+
+/// Failures of a sector after which it is remapped.
+pub const FAILURE_LIMIT: u8 = 3;
+/// Attempts at an operation of a storage area, so that the last one is on
+/// a spare if the sector keeps failing.
+pub const ATTEMPTS: usize = FAILURE_LIMIT as usize + 1;
+
+// Sectors with failures tracked at once.
+const TRACKED: usize = 8;
+
+static mut FAILURES: [(usize, u8); TRACKED] = [(0, 0); TRACKED];
+static mut FAILURE_COUNT: u32 = 0;
+
+fn spare(index: usize) -> usize {
+    TABLE_ADDR + (1 + index) * spiflash::SECTOR_SIZE
+}
+
+fn flash(addr: usize, size: usize) -> &'static [u8] {
+    unsafe { slice::from_raw_parts(addr as *const u8, size) }
+}
+
+// Calls `f` with each valid entry of the table, and returns the offset of
+// its erased space.
+fn entries<F: FnMut(usize, usize)>(mut f: F) -> usize {
+    let table = flash(TABLE_ADDR, spiflash::SECTOR_SIZE);
+    let mut offset = 0;
+    while offset + ENTRY_SIZE <= table.len() {
+        let entry = &table[offset..offset + ENTRY_SIZE];
+        if entry.iter().all(|&b| b == 0xff) {
+            break
+        }
+        if crc32::checksum_ieee(&entry[..8]) == BigEndian::read_u32(&entry[8..]) {
+            f(BigEndian::read_u32(&entry[0..]) as usize, BigEndian::read_u32(&entry[4..]) as usize)
+        }
+        offset += ENTRY_SIZE;
+    }
+    offset
+}
+
+/// Address at which the data of `addr` in a storage area is kept, after
+/// remapping.
+pub fn address(addr: usize) -> usize {
+    let sector = addr & !(spiflash::SECTOR_SIZE - 1);
+    let mut target = sector;
+    entries(|from, to| if from == sector { target = to });
+    target + (addr - sector)
+}
+
+/// Calls `f` with each remapped sector and its spare.
+pub fn remapped<F: FnMut(usize, usize)>(mut f: F) {
+    let mut mappings = [(0, 0); SPARE_COUNT];
+    let mut count = 0;
+    entries(|from, to| {
+        match mappings[..count].iter().position(|&(sector, _)| sector == from) {
+            Some(index) => mappings[index].1 = to,
+            None if count < SPARE_COUNT => {
+                mappings[count] = (from, to);
+                count += 1
+            }
+            None => ()
+        }
+    });
+    for &(from, to) in mappings[..count].iter() {
+        f(from, to)
+    }
+}
+
+fn spare_used(index: usize) -> bool {
+    let mut used = false;
+    entries(|_, to| used |= to == spare(index));
+    used
+}
+
+/// Spare sectors not used yet.
+pub fn spares_free() -> usize {
+    (0..SPARE_COUNT).filter(|&index| !spare_used(index)).count()
+}
+
+/// Erase and program failures since boot.
+pub fn failures() -> u32 {
+    unsafe { FAILURE_COUNT }
+}
+
+/// Reads back `data` at `addr` after it was programmed.
+pub fn verify(addr: usize, data: &[u8]) -> bool {
+    cache::flush_l2_cache();
+    cache::flush_cpu_dcache();
+    flash(addr, data.len()) == data
+}
+
+/// Checks that the sector at `addr` is erased.
+pub fn verify_erased(addr: usize) -> bool {
+    cache::flush_l2_cache();
+    cache::flush_cpu_dcache();
+    flash(addr, spiflash::SECTOR_SIZE).iter().all(|&b| b == 0xff)
+}
+
+fn remap(sector: usize) -> bool {
+    let index = match (0..SPARE_COUNT).find(|&index| !spare_used(index)) {
+        Some(index) => index,
+        None => return false
+    };
+    unsafe { spiflash::erase_sector(spare(index)) }
+    if !verify_erased(spare(index)) {
+        return false
+    }
+
+    let mut entry = [0; ENTRY_SIZE];
+    BigEndian::write_u32(&mut entry[0..], sector as u32);
+    BigEndian::write_u32(&mut entry[4..], spare(index) as u32);
+    let crc = crc32::checksum_ieee(&entry[..8]);
+    BigEndian::write_u32(&mut entry[8..], crc);
+    let offset = entries(|_, _| ());
+    if offset + ENTRY_SIZE > spiflash::SECTOR_SIZE {
+        return false
+    }
+    unsafe { spiflash::write(TABLE_ADDR + offset, &entry) }
+    verify(TABLE_ADDR + offset, &entry)
+}
+
+/// Counts a failure to program a sector that still holds data, which the
+/// caller moves to another sector.
+pub fn note_failure() {
+    unsafe { FAILURE_COUNT = FAILURE_COUNT.saturating_add(1) }
+}
+
+/// Records a failure to erase or program the sector of a storage area at
+/// `addr`, before remapping, which must not hold data that is still needed,
+/// e.g. the target of a compaction. Once the sector has failed
+/// `FAILURE_LIMIT` times, it is remapped to a spare sector, if any is left,
+/// and true is returned.
+pub fn record_failure(addr: usize) -> bool {
+    note_failure();
+    let sector = addr & !(spiflash::SECTOR_SIZE - 1);
+    let failures = unsafe {
+        let slot = match FAILURES.iter().position(|&(s, n)| s == sector && n > 0) {
+            Some(slot) => slot,
+            None => FAILURES.iter().position(|&(_, n)| n == 0).unwrap_or(0)
+        };
+        if FAILURES[slot].0 != sector {
+            FAILURES[slot] = (sector, 0)
+        }
+        FAILURES[slot].1 += 1;
+        &mut FAILURES[slot]
+    };
+    if failures.1 < FAILURE_LIMIT {
+        return false
+    }
+    failures.1 = 0;
+    remap(sector)
+}
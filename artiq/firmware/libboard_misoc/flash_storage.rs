@@ -0,0 +1,333 @@
+use core::fmt;
+
+/// Largest key and value of a record, in bytes.
+pub const MAX_KEY_LENGTH: usize = 64;
+pub const MAX_VALUE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    NoFlash,
+    KeyTooLong,
+    ValueTooLarge,
+    SpaceExhausted,
+    WriteFailed
+}
+
+impl Error {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            &Error::NoFlash => "flash memory is not present",
+            &Error::KeyTooLong => "key too long",
+            &Error::ValueTooLarge => "value too large",
+            &Error::SpaceExhausted => "flash storage area is full",
+            &Error::WriteFailed => "flash sector failed to erase or program"
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(has_spiflash)]
+mod imp {
+    use core::{slice, str};
+    use byteorder::{ByteOrder, BigEndian};
+    use crc::crc32;
+    use {cache, firmware, flash_health, spiflash};
+    use super::{Error, MAX_KEY_LENGTH, MAX_VALUE_SIZE};
+
+    // Two sectors after the boot statistics hold a log of the records that
+    // kernels store, separate from the configuration so that kernels cannot
+    // exhaust it. Records are appended to the sector in use, and a record
+    // with an empty value removes its key. When the sector is full, the live
+    // records are copied to the other sector, whose header is written last
+    // so that an interrupted copy leaves the sector in use intact; each
+    // sector is thus erased at most once per sector's worth of writes.
+    // Addresses below are those of the area; flash_health maps them to the
+    // sectors holding the data, which differ once a sector is remapped.
+    const ADDRS: [usize; 2] = [firmware::END + 4 * spiflash::SECTOR_SIZE,
+                               firmware::END + 5 * spiflash::SECTOR_SIZE];
+
+    // Sector header: magic, generation.
+    const MAGIC: &'static [u8] = b"AKST";
+    const HEADER_SIZE: usize = 8;
+
+    // Record: size of the record (u16), length of the key (u8), key, value,
+    // CRC32 of all of the above.
+    const RECORD_HEADER_SIZE: usize = 3;
+    const MAX_RECORD_SIZE: usize = RECORD_HEADER_SIZE + MAX_KEY_LENGTH + MAX_VALUE_SIZE + 4;
+
+    fn sector(addr: usize) -> &'static [u8] {
+        let addr = flash_health::address(addr);
+        unsafe { slice::from_raw_parts(addr as *const u8, spiflash::SECTOR_SIZE) }
+    }
+
+    fn generation(addr: usize) -> Option<u32> {
+        let data = sector(addr);
+        if &data[..4] == MAGIC {
+            Some(BigEndian::read_u32(&data[4..HEADER_SIZE]))
+        } else {
+            None
+        }
+    }
+
+    // Sector in use, and its generation.
+    fn active() -> Option<(usize, u32)> {
+        match (generation(ADDRS[0]), generation(ADDRS[1])) {
+            (Some(g0), Some(g1)) if (g1.wrapping_sub(g0) as i32) > 0 => Some((ADDRS[1], g1)),
+            (Some(g0), _) => Some((ADDRS[0], g0)),
+            (None, Some(g1)) => Some((ADDRS[1], g1)),
+            (None, None) => None
+        }
+    }
+
+    struct Record {
+        offset: usize,
+        key:    &'static str,
+        value:  &'static [u8],
+        raw:    &'static [u8]
+    }
+
+    // Records of a sector, up to the erased space or a torn record.
+    struct Iter {
+        data:   &'static [u8],
+        offset: usize
+    }
+
+    impl Iter {
+        fn new(addr: usize) -> Iter {
+            Iter { data: sector(addr), offset: HEADER_SIZE }
+        }
+    }
+
+    impl Iterator for Iter {
+        type Item = Record;
+
+        fn next(&mut self) -> Option<Record> {
+            let data = &self.data[self.offset..];
+            if data.len() < RECORD_HEADER_SIZE + 4 {
+                return None
+            }
+            let size = BigEndian::read_u16(&data[0..2]) as usize;
+            let key_length = data[2] as usize;
+            if size < RECORD_HEADER_SIZE + key_length + 4 || size > data.len() {
+                return None
+            }
+            let raw = &data[..size];
+            if crc32::checksum_ieee(&raw[..size - 4]) != BigEndian::read_u32(&raw[size - 4..]) {
+                return None
+            }
+            let key = str::from_utf8(&raw[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + key_length]).ok()?;
+            let record = Record {
+                offset: self.offset,
+                key:    key,
+                value:  &raw[RECORD_HEADER_SIZE + key_length..size - 4],
+                raw:    raw
+            };
+            self.offset += size;
+            Some(record)
+        }
+    }
+
+    // Whether no later record of the sector has the key of `record`.
+    fn is_latest(addr: usize, record: &Record) -> bool {
+        let mut iter = Iter::new(addr);
+        iter.offset = record.offset + record.raw.len();
+        iter.all(|later| later.key != record.key)
+    }
+
+    fn is_erased(addr: usize, size: usize) -> bool {
+        let addr = flash_health::address(addr);
+        let data = unsafe { slice::from_raw_parts(addr as *const u8, size) };
+        data.iter().all(|&b| b == 0xff)
+    }
+
+    // Writes `data` and reads it back, past the caches, which are then
+    // up to date for the following reads.
+    fn program(addr: usize, data: &[u8]) -> bool {
+        let addr = flash_health::address(addr);
+        unsafe { spiflash::write(addr, data) }
+        flash_health::verify(addr, data)
+    }
+
+    /// Calls `f` with the value of `key`, or `None` if it is not stored.
+    pub fn read<F: FnOnce(Option<&[u8]>) -> R, R>(key: &str, f: F) -> R {
+        let value = active().and_then(|(addr, _)| {
+            Iter::new(addr).filter(|record| record.key == key).last()
+        }).and_then(|record| {
+            if record.value.is_empty() { None } else { Some(record.value) }
+        });
+        f(value)
+    }
+
+    /// Calls `f` with each stored key and its value.
+    pub fn for_each<F: FnMut(&str, &[u8])>(mut f: F) {
+        if let Some((addr, _)) = active() {
+            for record in Iter::new(addr) {
+                if !record.value.is_empty() && is_latest(addr, &record) {
+                    f(record.key, record.value)
+                }
+            }
+        }
+    }
+
+    /// Bytes used by the records, including the superseded ones, and the
+    /// size of the area.
+    pub fn usage() -> (usize, usize) {
+        let used = active().map_or(0, |(addr, _)| {
+            Iter::new(addr).last().map_or(HEADER_SIZE, |record| record.offset + record.raw.len())
+        });
+        (used, spiflash::SECTOR_SIZE)
+    }
+
+    fn write_header(addr: usize, generation: u32) -> bool {
+        let mut header = [0; HEADER_SIZE];
+        header[..4].copy_from_slice(MAGIC);
+        BigEndian::write_u32(&mut header[4..], generation);
+        program(addr, &header)
+    }
+
+    // Copies the live records to the other sector, and returns it. A sector
+    // that fails to erase or program is retried until flash_health remaps it.
+    fn compact() -> Result<usize, Error> {
+        for _ in 0..flash_health::ATTEMPTS {
+            match compact_once() {
+                Ok(to) => return Ok(to),
+                Err(to) => { flash_health::record_failure(to); }
+            }
+        }
+        Err(Error::WriteFailed)
+    }
+
+    // Returns the sector that failed as the error.
+    fn compact_once() -> Result<usize, usize> {
+        let (from, generation) = match active() {
+            Some((addr, generation)) => (Some(addr), generation),
+            None => (None, 0)
+        };
+        let to = if from == Some(ADDRS[0]) { ADDRS[1] } else { ADDRS[0] };
+        unsafe { spiflash::erase_sector(flash_health::address(to)) }
+        if !flash_health::verify_erased(flash_health::address(to)) {
+            return Err(to)
+        }
+        let mut offset = HEADER_SIZE;
+        if let Some(from) = from {
+            let mut buffer = [0; MAX_RECORD_SIZE];
+            for record in Iter::new(from) {
+                if !record.value.is_empty() && is_latest(from, &record) {
+                    let buffer = &mut buffer[..record.raw.len()];
+                    buffer.copy_from_slice(record.raw);
+                    if !program(to + offset, buffer) {
+                        return Err(to)
+                    }
+                    offset += buffer.len();
+                }
+            }
+        }
+        if !write_header(to, generation.wrapping_add(1)) {
+            return Err(to)
+        }
+        Ok(to)
+    }
+
+    /// Stores `value` under `key`, replacing the previous value. An empty
+    /// value removes the key.
+    pub fn write(key: &str, value: &[u8]) -> Result<(), Error> {
+        if key.len() > MAX_KEY_LENGTH {
+            return Err(Error::KeyTooLong)
+        }
+        if value.len() > MAX_VALUE_SIZE {
+            return Err(Error::ValueTooLarge)
+        }
+        // Rewriting the same value would only wear the flash.
+        if read(key, |current| current.unwrap_or(&[]) == value) {
+            return Ok(())
+        }
+
+        let size = RECORD_HEADER_SIZE + key.len() + value.len() + 4;
+        let mut record = [0; MAX_RECORD_SIZE];
+        let record = &mut record[..size];
+        BigEndian::write_u16(&mut record[0..2], size as u16);
+        record[2] = key.len() as u8;
+        record[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + key.len()].copy_from_slice(key.as_bytes());
+        record[RECORD_HEADER_SIZE + key.len()..size - 4].copy_from_slice(value);
+        let crc = crc32::checksum_ieee(&record[..size - 4]);
+        BigEndian::write_u32(&mut record[size - 4..], crc);
+
+        let free = |addr: usize| {
+            let end = Iter::new(addr).last().map_or(HEADER_SIZE, |r| r.offset + r.raw.len());
+            if end + size <= spiflash::SECTOR_SIZE && is_erased(addr + end, size) {
+                Some(addr + end)
+            } else {
+                None
+            }
+        };
+        let at = match active().and_then(|(addr, _)| free(addr)) {
+            Some(at) => at,
+            None => free(compact()?).ok_or(Error::SpaceExhausted)?
+        };
+        if program(at, record) {
+            return Ok(())
+        }
+        // The torn record ends the records of the sector, which are moved
+        // to the other one before trying again.
+        flash_health::note_failure();
+        let at = free(compact()?).ok_or(Error::SpaceExhausted)?;
+        if program(at, record) {
+            Ok(())
+        } else {
+            flash_health::note_failure();
+            Err(Error::WriteFailed)
+        }
+    }
+
+    /// Verifies the CRCs of the records in use, and returns the number of
+    /// live records and of torn ones, which end the records of the sector.
+    pub fn check() -> (usize, usize) {
+        let addr = match active() {
+            Some((addr, _)) => addr,
+            None => return (0, 0)
+        };
+        let mut iter = Iter::new(addr);
+        let mut live = 0;
+        while let Some(record) = iter.next() {
+            if !record.value.is_empty() && is_latest(addr, &record) {
+                live += 1
+            }
+        }
+        let end = iter.offset;
+        if is_erased(addr + end, spiflash::SECTOR_SIZE - end) {
+            (live, 0)
+        } else {
+            (live + 1, 1)
+        }
+    }
+
+    /// Removes all records.
+    pub fn erase() -> Result<(), Error> {
+        unsafe {
+            spiflash::erase_sector(flash_health::address(ADDRS[0]));
+            spiflash::erase_sector(flash_health::address(ADDRS[1]));
+        }
+        cache::flush_l2_cache();
+        cache::flush_cpu_dcache();
+        Ok(())
+    }
+}
+
+#[cfg(not(has_spiflash))]
+mod imp {
+    use super::Error;
+
+    pub fn read<F: FnOnce(Option<&[u8]>) -> R, R>(_key: &str, f: F) -> R { f(None) }
+    pub fn for_each<F: FnMut(&str, &[u8])>(_f: F) {}
+    pub fn usage() -> (usize, usize) { (0, 0) }
+    pub fn check() -> (usize, usize) { (0, 0) }
+    pub fn write(_key: &str, _value: &[u8]) -> Result<(), Error> { Err(Error::NoFlash) }
+    pub fn erase() -> Result<(), Error> { Err(Error::NoFlash) }
+}
+
+pub use self::imp::*;
@@ -0,0 +1,236 @@
+use core::{fmt, slice};
+use byteorder::{ByteOrder, BigEndian};
+use crc::crc32;
+use {cache, csr, spiflash, boot_state};
+use boot_state::GatewareUpdate;
+
+// The golden gateware, written by artiq_flash at the start of the flash, is
+// loaded at power-up. Updates are written into a slot in the upper quarter of
+// the flash, and the bootloader reconfigures the FPGA with them over the
+// ICAP. An update is booted once on trial, and the golden gateware is kept
+// if the firmware does not check in, e.g. because the FPGA could not be
+// configured and fell back to the golden gateware. The stage of the update
+// is kept with the state of the firmware banks, see boot_state.rs.
+pub const ADDRESS: usize = 0xc00000;
+pub const SIZE: usize = 0x400000;
+
+// Images have the same header as firmware images: length of the bitstream,
+// then its CRC32. The bitstream itself starts after the header.
+const HEADER_SIZE: usize = 8;
+const BITSTREAM_ADDRESS: usize = ADDRESS + HEADER_SIZE;
+
+// Status of the last configuration in BOOTSTS (UG470, table 5-36).
+const BOOTSTS_VALID:    u16 = 1 << 0;
+const BOOTSTS_FALLBACK: u16 = 1 << 1;
+const BOOTSTS_IPROG:    u16 = 1 << 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    InvalidImage,
+    ImageTooLarge,
+    TrialInProgress,
+    TrialFailed,
+    LoadFailed,
+    VerifyFailed,
+    StateWriteFailed
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::InvalidImage =>
+                write!(f, "invalid gateware image header or CRC"),
+            &Error::ImageTooLarge =>
+                write!(f, "gateware image larger than the update slot ({} bytes)", SIZE),
+            &Error::TrialInProgress =>
+                write!(f, "the running gateware has not checked in yet"),
+            &Error::TrialFailed =>
+                write!(f, "the gateware update did not check in"),
+            &Error::LoadFailed =>
+                write!(f, "the gateware update failed to configure the FPGA"),
+            &Error::VerifyFailed =>
+                write!(f, "gateware image read back from flash does not match"),
+            &Error::StateWriteFailed =>
+                write!(f, "failed to record the state of the gateware update")
+        }
+    }
+}
+
+/// Image the FPGA was configured with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Image {
+    Golden,
+    Update,
+    /// The golden gateware, after the update failed to configure the FPGA.
+    Fallback
+}
+
+impl fmt::Display for Image {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Image::Golden => write!(f, "golden gateware"),
+            Image::Update => write!(f, "gateware update"),
+            Image::Fallback => write!(f, "golden gateware, after the update failed to load")
+        }
+    }
+}
+
+pub fn running() -> Image {
+    let (status, start) = unsafe {
+        (csr::icap::boot_status_read(), csr::icap::warm_boot_address_read() as usize)
+    };
+    if status & BOOTSTS_VALID == 0 {
+        Image::Golden
+    } else if status & BOOTSTS_FALLBACK != 0 {
+        Image::Fallback
+    } else if status & BOOTSTS_IPROG != 0 && start == BITSTREAM_ADDRESS {
+        Image::Update
+    } else {
+        Image::Golden
+    }
+}
+
+fn state() -> Option<GatewareUpdate> {
+    boot_state::read().gw_update
+}
+
+fn set_state(update: Option<GatewareUpdate>) -> Result<(), Error> {
+    let mut state = boot_state::read();
+    if state.gw_update != update {
+        state.gw_update = update;
+        if !boot_state::write(state) {
+            return Err(Error::StateWriteFailed)
+        }
+    }
+    Ok(())
+}
+
+/// Whether the running update was booted on trial and has not checked in.
+pub fn trial_started() -> bool {
+    running() == Image::Update && state() == Some(GatewareUpdate::Started)
+}
+
+/// Stops booting the update; the golden gateware is kept from the next
+/// power-up.
+pub fn disable() -> Result<(), Error> {
+    set_state(None)
+}
+
+/// Called by the firmware once it is running correctly. If the update was
+/// booted on trial, makes it boot at each power-up and returns true.
+pub fn check_in() -> Result<bool, Error> {
+    if trial_started() {
+        set_state(Some(GatewareUpdate::Active))?;
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Returns the bitstream of the update if its header and CRC are valid.
+pub fn read() -> Option<&'static [u8]> {
+    let header = unsafe { slice::from_raw_parts(ADDRESS as *const u8, HEADER_SIZE) };
+    let length = BigEndian::read_u32(&header[0..]) as usize;
+    let expected_crc = BigEndian::read_u32(&header[4..]);
+    if length == 0 || length > SIZE - HEADER_SIZE {
+        return None
+    }
+
+    let bitstream = unsafe { slice::from_raw_parts(BITSTREAM_ADDRESS as *const u8, length) };
+    if crc32::checksum_ieee(bitstream) == expected_crc {
+        Some(bitstream)
+    } else {
+        None
+    }
+}
+
+/// Writes a gateware image, with its header, into the update slot and
+/// schedules it to be booted on trial, at the next boot of the golden
+/// gateware, or right away with `start_trial` and `reconfigure`.
+pub fn update(image: &[u8]) -> Result<(), Error> {
+    if image.len() <= HEADER_SIZE {
+        return Err(Error::InvalidImage)
+    }
+    if image.len() > SIZE {
+        return Err(Error::ImageTooLarge)
+    }
+    let length = BigEndian::read_u32(&image[0..]) as usize;
+    let expected_crc = BigEndian::read_u32(&image[4..]);
+    if image.len() != HEADER_SIZE + length ||
+            crc32::checksum_ieee(&image[HEADER_SIZE..]) != expected_crc {
+        return Err(Error::InvalidImage)
+    }
+    if trial_started() {
+        return Err(Error::TrialInProgress)
+    }
+    // Not booted while the slot is being written.
+    disable()?;
+
+    unsafe {
+        for sector in (ADDRESS..ADDRESS + image.len()).step_by(spiflash::SECTOR_SIZE) {
+            spiflash::erase_sector(sector);
+        }
+        spiflash::write(ADDRESS, image);
+    }
+    cache::flush_l2_cache();
+    cache::flush_cpu_dcache();
+    if read().map(|bitstream| bitstream.len()) != Some(length) {
+        return Err(Error::VerifyFailed)
+    }
+
+    set_state(Some(GatewareUpdate::Trial))
+}
+
+/// Called by the bootloader. Returns whether the FPGA is to be reconfigured
+/// with the update, recording its trial if it is booted on trial. An update
+/// that failed its trial or is corrupted is no longer booted, and the reason
+/// is returned.
+pub fn select() -> Result<bool, Error> {
+    match (running(), state()) {
+        (_, None) | (Image::Update, _) => Ok(false),
+        (Image::Fallback, _) => {
+            disable()?;
+            Err(Error::LoadFailed)
+        }
+        (Image::Golden, Some(GatewareUpdate::Started)) => {
+            disable()?;
+            Err(Error::TrialFailed)
+        }
+        (Image::Golden, Some(state)) => {
+            if read().is_none() {
+                disable()?;
+                return Err(Error::VerifyFailed)
+            }
+            if state == GatewareUpdate::Trial {
+                set_state(Some(GatewareUpdate::Started))?;
+            }
+            Ok(true)
+        }
+    }
+}
+
+unsafe fn reconfigure_from(address: usize) -> ! {
+    csr::icap::reconfigure_address_write(address as u32);
+    csr::icap::reconfigure_write(1);
+    loop {}
+}
+
+/// Reconfigures the FPGA with the update. Does not return.
+pub unsafe fn reconfigure() -> ! {
+    reconfigure_from(BITSTREAM_ADDRESS)
+}
+
+/// Reconfigures the FPGA with the golden gateware, as at power-up. Does not
+/// return.
+pub unsafe fn reconfigure_golden() -> ! {
+    reconfigure_from(0)
+}
+
+/// Boots the update right away, on trial, if it is waiting for its trial.
+pub fn start_trial() -> Result<bool, Error> {
+    if state() != Some(GatewareUpdate::Trial) || read().is_none() {
+        return Ok(false)
+    }
+    set_state(Some(GatewareUpdate::Started))?;
+    Ok(true)
+}
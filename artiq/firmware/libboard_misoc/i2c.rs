@@ -1,6 +1,24 @@
+/// Device address on the bus, excluding the R/W bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Address {
+    SevenBit(u8),
+    TenBit(u16)
+}
+
+/// SMBus packet error code: CRC-8 with polynomial x^8 + x^2 + x + 1.
+#[cfg(has_i2c)]
+fn pec_update(mut crc: u8, byte: u8) -> u8 {
+    crc ^= byte;
+    for _ in 0..8 {
+        crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+    }
+    crc
+}
+
 #[cfg(has_i2c)]
 mod imp {
     use super::super::{csr, clock};
+    use super::{Address, pec_update};
 
     const INVALID_BUS: &'static str = "Invalid I2C bus";
 
@@ -188,6 +206,142 @@ mod imp {
         Ok(data)
     }
 
+    pub fn write_buffer(busno: u8, data: &[u8]) -> Result<usize, &'static str> {
+        // Stops at the first NACK and returns the number of acknowledged bytes
+        for (i, &byte) in data.iter().enumerate() {
+            if !write(busno, byte)? {
+                return Ok(i)
+            }
+        }
+        Ok(data.len())
+    }
+
+    pub fn read_buffer(busno: u8, data: &mut [u8]) -> Result<(), &'static str> {
+        // ACK every byte except the last one
+        let len = data.len();
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = read(busno, i < len - 1)?;
+        }
+        Ok(())
+    }
+
+    fn write_pec(busno: u8, data: u8, pec: &mut u8) -> Result<bool, &'static str> {
+        *pec = pec_update(*pec, data);
+        write(busno, data)
+    }
+
+    fn read_pec(busno: u8, ack: bool, pec: &mut u8) -> Result<u8, &'static str> {
+        let data = read(busno, ack)?;
+        *pec = pec_update(*pec, data);
+        Ok(data)
+    }
+
+    // Must follow a start condition.
+    fn address_write(busno: u8, address: Address, pec: &mut u8) -> Result<bool, &'static str> {
+        match address {
+            Address::SevenBit(address) => {
+                if address > 0x7f {
+                    return Err("invalid 7-bit I2C address")
+                }
+                write_pec(busno, address << 1, pec)
+            }
+            Address::TenBit(address) => {
+                if address > 0x3ff {
+                    return Err("invalid 10-bit I2C address")
+                }
+                let header = 0xf0 | ((address >> 7) as u8 & 0x06);
+                Ok(write_pec(busno, header, pec)? && write_pec(busno, address as u8, pec)?)
+            }
+        }
+    }
+
+    // Must follow a repeated start condition after `address_write`; a 10-bit
+    // device that was just addressed only needs the header byte.
+    fn address_read(busno: u8, address: Address, pec: &mut u8) -> Result<bool, &'static str> {
+        match address {
+            Address::SevenBit(address) => write_pec(busno, (address << 1) | 1, pec),
+            Address::TenBit(address) => {
+                let header = 0xf0 | ((address >> 7) as u8 & 0x06);
+                write_pec(busno, header | 1, pec)
+            }
+        }
+    }
+
+    fn smbus_block_write_inner(busno: u8, address: Address, command: u8, data: &[u8],
+                               use_pec: bool) -> Result<(), &'static str> {
+        let mut pec = 0;
+        if !address_write(busno, address, &mut pec)? {
+            return Err("SMBus device failed to ack write address")
+        }
+        if !write_pec(busno, command, &mut pec)? {
+            return Err("SMBus device failed to ack command")
+        }
+        if !write_pec(busno, data.len() as u8, &mut pec)? {
+            return Err("SMBus device failed to ack byte count")
+        }
+        for &byte in data {
+            if !write_pec(busno, byte, &mut pec)? {
+                return Err("SMBus device failed to ack data")
+            }
+        }
+        if use_pec && !write(busno, pec)? {
+            return Err("SMBus device rejected PEC")
+        }
+        Ok(())
+    }
+
+    pub fn smbus_block_write(busno: u8, address: Address, command: u8, data: &[u8],
+                             use_pec: bool) -> Result<(), &'static str> {
+        if data.len() > 255 {
+            return Err("SMBus block too long")
+        }
+        start(busno)?;
+        let result = smbus_block_write_inner(busno, address, command, data, use_pec);
+        stop(busno)?;
+        result
+    }
+
+    fn smbus_block_read_inner(busno: u8, address: Address, command: u8, data: &mut [u8],
+                              use_pec: bool) -> Result<usize, &'static str> {
+        let mut pec = 0;
+        if !address_write(busno, address, &mut pec)? {
+            return Err("SMBus device failed to ack write address")
+        }
+        if !write_pec(busno, command, &mut pec)? {
+            return Err("SMBus device failed to ack command")
+        }
+        restart(busno)?;
+        if !address_read(busno, address, &mut pec)? {
+            return Err("SMBus device failed to ack read address")
+        }
+        let count = read_pec(busno, true, &mut pec)? as usize;
+        if count > data.len() {
+            // Still clock out the transfer so the device releases the bus
+            for i in 0..count {
+                read(busno, i < count - 1 || use_pec)?;
+            }
+            if use_pec {
+                read(busno, false)?;
+            }
+            return Err("SMBus block does not fit in buffer")
+        }
+        for i in 0..count {
+            data[i] = read_pec(busno, i < count - 1 || use_pec, &mut pec)?;
+        }
+        if use_pec && read(busno, false)? != pec {
+            return Err("SMBus PEC mismatch")
+        }
+        Ok(count)
+    }
+
+    pub fn smbus_block_read(busno: u8, address: Address, command: u8, data: &mut [u8],
+                            use_pec: bool) -> Result<usize, &'static str> {
+        start(busno)?;
+        let result = smbus_block_read_inner(busno, address, command, data, use_pec);
+        stop(busno)?;
+        result
+    }
+
     pub fn pca9548_select(busno: u8, address: u8, channels: u8) -> Result<(), &'static str> {
         start(busno)?;
         if !write(busno, address << 1)? {
@@ -203,6 +357,8 @@ mod imp {
 
 #[cfg(not(has_i2c))]
 mod imp {
+    use super::Address;
+
     const NO_I2C: &'static str = "No I2C support on this platform";
     pub fn init() -> Result<(), &'static str> { Err(NO_I2C) }
     pub fn start(_busno: u8) -> Result<(), &'static str> { Err(NO_I2C) }
@@ -210,7 +366,27 @@ mod imp {
     pub fn stop(_busno: u8) -> Result<(), &'static str> { Err(NO_I2C) }
     pub fn write(_busno: u8, _data: u8) -> Result<bool, &'static str> { Err(NO_I2C) }
     pub fn read(_busno: u8, _ack: bool) -> Result<u8, &'static str> { Err(NO_I2C) }
+    pub fn write_buffer(_busno: u8, _data: &[u8]) -> Result<usize, &'static str> { Err(NO_I2C) }
+    pub fn read_buffer(_busno: u8, _data: &mut [u8]) -> Result<(), &'static str> { Err(NO_I2C) }
+    pub fn smbus_block_write(_busno: u8, _address: Address, _command: u8, _data: &[u8],
+                             _use_pec: bool) -> Result<(), &'static str> { Err(NO_I2C) }
+    pub fn smbus_block_read(_busno: u8, _address: Address, _command: u8, _data: &mut [u8],
+                            _use_pec: bool) -> Result<usize, &'static str> { Err(NO_I2C) }
     pub fn pca9548_select(_busno: u8, _address: u8, _channels: u8) -> Result<(), &'static str> { Err(NO_I2C) }
 }
 
 pub use self::imp::*;
+
+// Ports of the I2C switches of Kasli, as in artiq.coredevice.kasli_i2c.
+const KASLI_PORTS: [(&'static str, u8); 16] = [
+    ("EEM0", 7), ("EEM1", 5), ("EEM2", 4), ("EEM3", 3),
+    ("EEM4", 2), ("EEM5", 1), ("EEM6", 0), ("EEM7", 6),
+    ("EEM8", 12), ("EEM9", 13), ("EEM10", 15), ("EEM11", 14),
+    ("SFP0", 8), ("SFP1", 9), ("SFP2", 10), ("LOC0", 11)
+];
+
+/// Port of the I2C switches of Kasli with the given name, e.g. `EEM0` or
+/// `LOC0`.
+pub fn kasli_port(name: &str) -> Option<u8> {
+    KASLI_PORTS.iter().find(|&&(n, _)| n == name).map(|&(_, port)| port)
+}
@@ -1,5 +1,28 @@
+use byteorder::{ByteOrder, BigEndian};
+use crc::crc32;
 use i2c;
 
+const SINARA_MAGIC: u16 = 0x391e;
+
+/// Identification of a Sinara board, from the first 128 bytes of its EEPROM.
+/// [Format](https://github.com/sinara-hw/sinara/wiki/EEPROM)
+#[derive(Debug, Clone, Copy)]
+pub struct SinaraIdentity {
+    name:         [u8; 10],
+    pub board_id: u16,
+    pub major:    u8,
+    pub minor:    u8,
+    pub variant:  u8,
+    pub vendor:   u8
+}
+
+impl SinaraIdentity {
+    pub fn name(&self) -> &str {
+        let length = self.name.iter().position(|&c| c == 0).unwrap_or(self.name.len());
+        ::core::str::from_utf8(&self.name[..length]).unwrap_or("")
+    }
+}
+
 /// [Hardware manual](http://ww1.microchip.com/downloads/en/DeviceDoc/24AA02E48-24AA025E48-24AA02E64-24AA025E64-Data-Sheet-20002124H.pdf)
 pub struct EEPROM {
     busno: u8,
@@ -28,6 +51,17 @@ impl EEPROM {
         }
     }
 
+    /// EEPROM of the EEM card connected to the I2C switch port `port`, for
+    /// the cards that have one.
+    #[cfg(soc_platform = "kasli")]
+    pub fn eem(port: u8) -> Self {
+        EEPROM {
+            busno: 0,
+            port: port,
+            address: 0xa0,
+        }
+    }
+
     #[cfg(soc_platform = "kasli")]
     fn select(&self) -> Result<(), &'static str> {
         let mask: u16 = 1 << self.port;
@@ -36,6 +70,17 @@ impl EEPROM {
         Ok(())
     }
 
+    /// Whether the EEPROM acknowledges its address.
+    pub fn probe(&self) -> Result<bool, &'static str> {
+        self.select()?;
+
+        i2c::start(self.busno)?;
+        let ack = i2c::write(self.busno, self.address)?;
+        i2c::stop(self.busno)?;
+
+        Ok(ack)
+    }
+
     pub fn read<'a>(&self, addr: u8, buf: &'a mut [u8]) -> Result<(), &'static str> {
         self.select()?;
 
@@ -55,6 +100,27 @@ impl EEPROM {
         Ok(())
     }
 
+    /// Returns `None` if the EEPROM does not contain valid Sinara data,
+    /// e.g. it was never programmed.
+    pub fn read_sinara(&self) -> Result<Option<SinaraIdentity>, &'static str> {
+        let mut data = [0u8; 128];
+        self.read(0, &mut data)?;
+        if BigEndian::read_u16(&data[4..6]) != SINARA_MAGIC ||
+                crc32::checksum_ieee(&data[4..]) != BigEndian::read_u32(&data[0..4]) {
+            return Ok(None)
+        }
+        let mut name = [0u8; 10];
+        name.copy_from_slice(&data[6..16]);
+        Ok(Some(SinaraIdentity {
+            name:     name,
+            board_id: BigEndian::read_u16(&data[16..18]),
+            major:    data[19],
+            minor:    data[20],
+            variant:  data[21],
+            vendor:   data[23]
+        }))
+    }
+
     /// > The 24AA02XEXX is programmed at the factory with a
     /// > globally unique node address stored in the upper half
     /// > of the array and permanently write-protected.
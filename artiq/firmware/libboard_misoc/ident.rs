@@ -13,3 +13,21 @@ pub fn read(buf: &mut [u8]) -> &str {
         str::from_utf8_unchecked(&buf[..len as usize])
     }
 }
+
+/// Compatibility descriptor of the gateware, stored after the identifier
+/// string, or 0 for gateware built without one.
+pub fn descriptor() -> u32 {
+    unsafe {
+        csr::identifier::address_write(0);
+        let len = csr::identifier::data_read();
+        if len > 251 {
+            return 0
+        }
+        let mut descriptor = 0;
+        for i in 0..4 {
+            csr::identifier::address_write(len + 1 + i);
+            descriptor = descriptor << 8 | csr::identifier::data_read() as u32;
+        }
+        descriptor
+    }
+}
@@ -0,0 +1,140 @@
+use i2c;
+
+const BUSNO: u8 = 0;
+
+const REG_CONFIG:       u8 = 0x00;
+const REG_SHUNT:        u8 = 0x01;
+const REG_BUS:          u8 = 0x02;
+const REG_MASK_ENABLE:  u8 = 0x06;
+const REG_ALERT_LIMIT:  u8 = 0x07;
+const REG_MANUFACTURER: u8 = 0xfe;
+
+const TI_ID: u16 = 0x5449;
+
+// INA219: 32V range, 320mV shunt range, 12-bit conversions, continuous
+// (the reset value).
+const INA219_CONFIG: u16 = 0x399f;
+// INA226: averages of 16 samples, 1.1ms conversions, continuous.
+const INA226_CONFIG: u16 = 0x4527;
+// INA226 alert on bus under-voltage, latched until Mask/Enable is read.
+const INA226_BUL: u16 = 1 << 12;
+const INA226_AFF: u16 = 1 << 4;
+const INA226_LEN: u16 = 1 << 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Ina219,
+    Ina226
+}
+
+/// Power monitor measuring the voltage of a rail and the current through a
+/// shunt resistor. `port` is the port of the I2C switches it is behind, if
+/// any, and `address` is its 7-bit I2C address.
+#[derive(Debug, Clone, Copy)]
+pub struct Ina2xx {
+    pub kind:       Kind,
+    pub port:       Option<u8>,
+    pub address:    u8,
+    pub shunt_mohm: u32
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub voltage_mv: u32,
+    pub current_ua: i32
+}
+
+#[cfg(soc_platform = "kasli")]
+fn select(port: Option<u8>) -> Result<(), &'static str> {
+    let mask: u16 = port.map_or(0, |port| 1 << port);
+    i2c::pca9548_select(BUSNO, 0x70, mask as u8)?;
+    i2c::pca9548_select(BUSNO, 0x71, (mask >> 8) as u8)?;
+    Ok(())
+}
+
+#[cfg(not(soc_platform = "kasli"))]
+fn select(port: Option<u8>) -> Result<(), &'static str> {
+    match port {
+        None => Ok(()),
+        Some(_) => Err("no I2C switches")
+    }
+}
+
+impl Ina2xx {
+    fn write_register(&self, register: u8, value: u16) -> Result<(), &'static str> {
+        i2c::start(BUSNO)?;
+        if !i2c::write(BUSNO, self.address << 1)? {
+            i2c::stop(BUSNO)?;
+            return Err("power monitor not present")
+        }
+        i2c::write(BUSNO, register)?;
+        i2c::write(BUSNO, (value >> 8) as u8)?;
+        i2c::write(BUSNO, value as u8)?;
+        i2c::stop(BUSNO)?;
+        Ok(())
+    }
+
+    fn read_register(&self, register: u8) -> Result<u16, &'static str> {
+        i2c::start(BUSNO)?;
+        if !i2c::write(BUSNO, self.address << 1)? {
+            i2c::stop(BUSNO)?;
+            return Err("power monitor not present")
+        }
+        i2c::write(BUSNO, register)?;
+        i2c::restart(BUSNO)?;
+        i2c::write(BUSNO, (self.address << 1) | 1)?;
+        let high = i2c::read(BUSNO, true)?;
+        let low = i2c::read(BUSNO, false)?;
+        i2c::stop(BUSNO)?;
+        Ok((high as u16) << 8 | low as u16)
+    }
+
+    /// Configures the monitor. On an INA226, a bus voltage lower than
+    /// `undervoltage_mv` is latched, see `undervoltage`.
+    pub fn init(&self, undervoltage_mv: Option<u32>) -> Result<(), &'static str> {
+        select(self.port)?;
+        match self.kind {
+            Kind::Ina219 => self.write_register(REG_CONFIG, INA219_CONFIG)?,
+            Kind::Ina226 => {
+                if self.read_register(REG_MANUFACTURER)? != TI_ID {
+                    return Err("not an INA226")
+                }
+                self.write_register(REG_CONFIG, INA226_CONFIG)?;
+                if let Some(undervoltage_mv) = undervoltage_mv {
+                    // 1.25mV per LSB
+                    self.write_register(REG_ALERT_LIMIT, (undervoltage_mv * 4 / 5) as u16)?;
+                    self.write_register(REG_MASK_ENABLE, INA226_BUL | INA226_LEN)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn measure(&self) -> Result<Measurement, &'static str> {
+        select(self.port)?;
+        let shunt = self.read_register(REG_SHUNT)? as i16 as i32;
+        let bus = self.read_register(REG_BUS)? as u32;
+        let (shunt_uv, voltage_mv) = match self.kind {
+            // 10uV and 4mV per LSB
+            Kind::Ina219 => (shunt * 10, (bus >> 3) * 4),
+            // 2.5uV and 1.25mV per LSB
+            Kind::Ina226 => (shunt * 5 / 2, bus * 5 / 4)
+        };
+        Ok(Measurement {
+            voltage_mv: voltage_mv,
+            current_ua: shunt_uv * 1000 / self.shunt_mohm as i32
+        })
+    }
+
+    /// Whether the bus voltage fell below the limit given to `init` since
+    /// the last call. Always false on an INA219.
+    pub fn undervoltage(&self) -> Result<bool, &'static str> {
+        match self.kind {
+            Kind::Ina219 => Ok(false),
+            Kind::Ina226 => {
+                select(self.port)?;
+                Ok(self.read_register(REG_MASK_ENABLE)? & INA226_AFF != 0)
+            }
+        }
+    }
+}
@@ -1,5 +1,12 @@
 use i2c;
+#[cfg(has_virtual_leds)]
 use csr;
+use config;
+
+// MCP23017 registers, with IOCON.BANK = 0 (the reset value).
+const IODIR: u8 = 0x00;
+const IPOL:  u8 = 0x02;
+const GPIO:  u8 = 0x12;
 
 pub struct IoExpander {
     busno: u8,
@@ -40,6 +47,20 @@ impl IoExpander {
         }
     }
 
+    /// Expander on `port` of the I2C switches, at the 7-bit `address`, with
+    /// all pins as inputs.
+    pub fn custom(port: u8, address: u8) -> Self {
+        IoExpander {
+            busno: 0,
+            port: port,
+            address: address << 1,
+            virtual_led_mapping: &[],
+            iodir: [0xff; 2],
+            out_current: [0; 2],
+            out_target: [0; 2],
+        }
+    }
+
     #[cfg(soc_platform = "kasli")]
     fn select(&self) -> Result<(), &'static str> {
         let mask: u16 = 1 << self.port;
@@ -57,9 +78,20 @@ impl IoExpander {
         Ok(())
     }
 
+    fn read(&self, addr: u8) -> Result<u8, &'static str> {
+        i2c::start(self.busno)?;
+        i2c::write(self.busno, self.address)?;
+        i2c::write(self.busno, addr)?;
+        i2c::restart(self.busno)?;
+        i2c::write(self.busno, self.address | 1)?;
+        let value = i2c::read(self.busno, false)?;
+        i2c::stop(self.busno)?;
+        Ok(value)
+    }
+
     fn update_iodir(&self) -> Result<(), &'static str> {
-        self.write(0x00, self.iodir[0])?;
-        self.write(0x01, self.iodir[1])?;
+        self.write(IODIR, self.iodir[0])?;
+        self.write(IODIR + 1, self.iodir[1])?;
         Ok(())
     }
 
@@ -72,9 +104,28 @@ impl IoExpander {
         self.update_iodir()?;
 
         self.out_current[0] = 0x00;
-        self.write(0x12, 0x00)?;
+        self.write(GPIO, 0x00)?;
         self.out_current[1] = 0x00;
-        self.write(0x13, 0x00)?;
+        self.write(GPIO + 1, 0x00)?;
+        Ok(())
+    }
+
+    /// Initializes the expander as described by `config`. The outputs are
+    /// set to their initial level before they are enabled, so that they do
+    /// not glitch.
+    pub fn init_with(&mut self, config: &Config) -> Result<(), &'static str> {
+        self.select()?;
+
+        self.out_target = [config.levels as u8, (config.levels >> 8) as u8];
+        self.write(GPIO, self.out_target[0])?;
+        self.write(GPIO + 1, self.out_target[1])?;
+        self.out_current = self.out_target;
+
+        self.write(IPOL, config.inverted as u8)?;
+        self.write(IPOL + 1, (config.inverted >> 8) as u8)?;
+
+        self.iodir = [!config.outputs as u8, !(config.outputs >> 8) as u8];
+        self.update_iodir()?;
         Ok(())
     }
 
@@ -92,26 +143,131 @@ impl IoExpander {
         }
     }
 
-    pub fn service(&mut self) -> Result<(), &'static str> {
-        for (led, port, bit) in self.virtual_led_mapping.iter() {
-            let level = unsafe {
-                (csr::virtual_leds::status_read() >> led) & 1
-            };
-            self.set(*port, *bit, level != 0);
-        }
+    /// Reads the level of pin `bit` of `port`, selecting the port of the
+    /// expander on the I2C switches.
+    pub fn get(&self, port: u8, bit: u8) -> Result<bool, &'static str> {
+        self.select()?;
+        Ok(self.read(GPIO + port)? & 1 << bit != 0)
+    }
 
-        if self.out_target != self.out_current {
-            self.select()?;
-            if self.out_target[0] != self.out_current[0] {
-                self.write(0x12, self.out_target[0])?;
-                self.out_current[0] = self.out_target[0];
-            }
-            if self.out_target[1] != self.out_current[1] {
-                self.write(0x13, self.out_target[1])?;
-                self.out_current[1] = self.out_target[1];
+    /// Writes the outputs that changed. Returns whether the expander was
+    /// written, selecting its port on the I2C switches.
+    pub fn service(&mut self) -> Result<bool, &'static str> {
+        #[cfg(has_virtual_leds)]
+        {
+            for (led, port, bit) in self.virtual_led_mapping.iter() {
+                let level = unsafe {
+                    (csr::virtual_leds::status_read() >> led) & 1
+                };
+                self.set(*port, *bit, level != 0);
             }
         }
 
-        Ok(())
+        if self.out_target == self.out_current {
+            return Ok(false)
+        }
+        self.select()?;
+        if self.out_target[0] != self.out_current[0] {
+            self.write(GPIO, self.out_target[0])?;
+            self.out_current[0] = self.out_target[0];
+        }
+        if self.out_target[1] != self.out_current[1] {
+            self.write(GPIO + 1, self.out_target[1])?;
+            self.out_current[1] = self.out_target[1];
+        }
+        Ok(true)
+    }
+}
+
+/// Expander declared in the `io_expanders` config entry. The masks have a
+/// bit per pin, port A in the low byte.
+#[derive(Debug, Clone, Copy)]
+pub struct Config<'a> {
+    pub name:     &'a str,
+    pub port:     u8,
+    pub address:  u8,
+    pub outputs:  u16,
+    pub levels:   u16,
+    pub inverted: u16
+}
+
+fn parse_mask(s: &str) -> Option<u16> {
+    if s.starts_with("0x") {
+        u16::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+// Parses "name:port:address:outputs:levels:inverted", where port is the name
+// of a port of the I2C switches, address is 7-bit, and the masks give the
+// pins that are outputs, the initial levels of the outputs, and the inputs
+// with inverted polarity.
+fn parse_config(s: &str) -> Option<Config> {
+    let mut fields = s.trim().split(':');
+    let config = Config {
+        name:     fields.next()?,
+        port:     i2c::kasli_port(fields.next()?)?,
+        address:  parse_mask(fields.next()?).and_then(|address| {
+            if address < 0x80 { Some(address as u8) } else { None }
+        })?,
+        outputs:  parse_mask(fields.next()?)?,
+        levels:   parse_mask(fields.next()?)?,
+        inverted: parse_mask(fields.next()?)?
+    };
+    if fields.next().is_some() {
+        return None
     }
+    Some(config)
+}
+
+// The expanders of Kasli v2.0 itself, driven by `IoExpander::new`.
+#[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
+fn is_builtin(config: &Config) -> bool {
+    config.port == 11 && (config.address == 0x20 || config.address == 0x21)
+}
+
+#[cfg(not(all(soc_platform = "kasli", hw_rev = "v2.0")))]
+fn is_builtin(_config: &Config) -> bool {
+    false
+}
+
+/// Expander declared in the `io_expanders` config entry with the given name,
+/// as `init_configured` left it, and the mask of its outputs.
+pub fn find_configured(name: &str) -> Option<(IoExpander, u16)> {
+    config::read_str("io_expanders", |r| {
+        let config = r.ok()?.split(',').filter_map(parse_config)
+                          .find(|config| config.name == name)?;
+        if is_builtin(&config) {
+            return None
+        }
+        let mut expander = IoExpander::custom(config.port, config.address);
+        expander.out_target = [config.levels as u8, (config.levels >> 8) as u8];
+        expander.out_current = expander.out_target;
+        expander.iodir = [!config.outputs as u8, !(config.outputs >> 8) as u8];
+        Some((expander, config.outputs))
+    })
+}
+
+/// Initializes the expanders declared in the `io_expanders` config entry, as
+/// comma-separated `name:port:address:outputs:levels:inverted` descriptions,
+/// and calls `f` with the description and the result of each. Invalid
+/// descriptions are passed as their name and an error.
+pub fn init_configured<F: FnMut(&str, Result<IoExpander, &'static str>)>(mut f: F) {
+    config::read_str("io_expanders", |r| {
+        if let Ok(s) = r {
+            for description in s.split(',') {
+                match parse_config(description) {
+                    None => f(description.trim(), Err("invalid I/O expander description")),
+                    Some(ref config) if is_builtin(config) =>
+                        f(config.name, Err("I/O expander already driven by the firmware")),
+                    Some(ref config) => {
+                        let mut expander = IoExpander::custom(config.port, config.address);
+                        let result = expander.init_with(config).map(|()| expander);
+                        f(config.name, result)
+                    }
+                }
+            }
+        }
+    })
 }
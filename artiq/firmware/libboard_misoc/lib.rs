@@ -1,7 +1,12 @@
 #![no_std]
 #![feature(asm, try_from)]
+#![cfg_attr(feature = "alloc", feature(alloc))]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
 extern crate byteorder;
+#[cfg(any(has_spiflash, soc_platform = "kasli"))]
+extern crate crc;
 #[cfg(feature = "log")]
 extern crate log;
 #[cfg(feature = "smoltcp")]
@@ -20,12 +25,28 @@ include!(concat!(env!("BUILDINC_DIRECTORY"), "/generated/sdram_phy.rs"));
 #[cfg(has_dfii)]
 pub mod sdram;
 pub mod ident;
+pub mod bad_ram;
 pub mod clock;
 #[cfg(has_uart)]
 pub mod uart;
 #[cfg(has_spiflash)]
 pub mod spiflash;
 pub mod config;
+#[cfg(has_spiflash)]
+pub mod firmware;
+#[cfg(all(has_spiflash, has_icap))]
+pub mod gateware;
+#[cfg(has_spiflash)]
+pub mod panic_log;
+pub mod ed25519;
+#[cfg(has_spiflash)]
+pub mod boot_key;
+pub mod boot_stats;
+pub mod flash_storage;
+#[cfg(has_spiflash)]
+pub mod flash_health;
+#[cfg(has_spiflash)]
+pub mod boot_state;
 #[cfg(feature = "uart_console")]
 #[macro_use]
 pub mod uart_console;
@@ -35,9 +56,13 @@ pub mod uart_logger;
 #[cfg(all(has_ethmac, feature = "smoltcp"))]
 pub mod ethmac;
 pub mod i2c;
+pub mod sfp;
+pub mod ina2xx;
+pub mod rtc;
+pub mod eeprom_24xx;
 #[cfg(soc_platform = "kasli")]
 pub mod i2c_eeprom;
-#[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
+#[cfg(soc_platform = "kasli")]
 pub mod io_expander;
 #[cfg(all(has_ethmac, feature = "smoltcp"))]
 pub mod net_settings;
@@ -1,6 +1,6 @@
 use core::fmt;
 
-use smoltcp::wire::{EthernetAddress, IpAddress};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv6Address};
 
 use config;
 #[cfg(soc_platform = "kasli")]
@@ -9,19 +9,31 @@ use i2c_eeprom;
 
 pub struct NetAddresses {
     pub hardware_addr: EthernetAddress,
+    /// Static IPv4 address, or the fallback address if `ipv4_dhcp` is set.
     pub ipv4_addr: IpAddress,
+    pub ipv4_dhcp: bool,
     pub ipv6_ll_addr: IpAddress,
-    pub ipv6_addr: Option<IpAddress>
+    /// Configured IPv6 address; a prefix length of 0 puts every address on-link.
+    pub ipv6_addr: Option<IpCidr>,
+    pub ipv6_gateway: Option<Ipv6Address>
 }
 
 impl fmt::Display for NetAddresses {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "MAC={} IPv4={} IPv6-LL={} IPv6=",
-            self.hardware_addr, self.ipv4_addr, self.ipv6_ll_addr)?;
+        write!(f, "MAC={} IPv4=", self.hardware_addr)?;
+        if self.ipv4_dhcp {
+            write!(f, "DHCP (fallback {})", self.ipv4_addr)?;
+        } else {
+            write!(f, "{}", self.ipv4_addr)?;
+        }
+        write!(f, " IPv6-LL={} IPv6=", self.ipv6_ll_addr)?;
         match self.ipv6_addr {
             Some(addr) => write!(f, "{}", addr)?,
             None => write!(f, "no configured address")?
         }
+        if let Some(gateway) = self.ipv6_gateway {
+            write!(f, " IPv6-GW={}", gateway)?;
+        }
         Ok(())
     }
 }
@@ -48,6 +60,7 @@ pub fn get_adresses() -> NetAddresses {
         }
     }
 
+    let ipv4_dhcp = config::read_str("ip", |r| r == Ok("dhcp"));
     let ipv4_addr;
     match config::read_str("ip", |r| r.map(|s| s.parse())) {
         Ok(Ok(addr)) => ipv4_addr = addr,
@@ -70,7 +83,16 @@ pub fn get_adresses() -> NetAddresses {
         0xfe00 | (hardware_addr.0[3] as u16),
         ((hardware_addr.0[4] as u16) << 8) | (hardware_addr.0[5] as u16));
 
-    let ipv6_addr = match config::read_str("ip6", |r| r.map(|s| s.parse())) {
+    // Either an address alone, as in earlier releases, or an address and
+    // prefix length such as 2001:db8::5/64.
+    let ipv6_addr = config::read_str("ip6", |r| {
+        r.ok().and_then(|s| {
+            s.parse::<IpCidr>().ok()
+             .or_else(|| s.parse::<IpAddress>().ok().map(|addr| IpCidr::new(addr, 0)))
+        })
+    });
+
+    let ipv6_gateway = match config::read_str("ip6_gateway", |r| r.map(|s| s.parse())) {
         Ok(Ok(addr)) => Some(addr),
         _ => None
     };
@@ -78,7 +100,9 @@ pub fn get_adresses() -> NetAddresses {
     NetAddresses {
         hardware_addr: hardware_addr,
         ipv4_addr: ipv4_addr,
+        ipv4_dhcp: ipv4_dhcp,
         ipv6_ll_addr: ipv6_ll_addr,
-        ipv6_addr: ipv6_addr
+        ipv6_addr: ipv6_addr,
+        ipv6_gateway: ipv6_gateway
     }
 }
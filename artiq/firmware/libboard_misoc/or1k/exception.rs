@@ -0,0 +1,107 @@
+use core::{fmt, convert::TryFrom};
+use super::irq::Exception;
+
+// Frame pushed by HANDLE_EXCEPTION in vectors.S: r2 to r31, then the
+// red zone.
+const FRAME_SIZE: u32 = 4 * 32 + 128;
+
+const CODE_WORDS: usize = 8;
+const DATA_WORDS: usize = 8;
+const STACK_WORDS: usize = 32;
+
+/// Words of memory copied at the time of an exception.
+#[derive(Clone, Copy)]
+struct Excerpt<A> {
+    addr:  u32,
+    words: Option<A>
+}
+
+macro_rules! excerpt {
+    ($len:expr, $addr:expr, $readable:expr) => ({
+        let addr = $addr & !3;
+        let mut words = [0; $len];
+        if $readable {
+            for (i, word) in words.iter_mut().enumerate() {
+                *word = ((addr as usize + 4 * i) as *const u32).read_volatile()
+            }
+        }
+        Excerpt { addr: addr, words: if $readable { Some(words) } else { None } }
+    })
+}
+
+fn write_excerpt(f: &mut fmt::Formatter, name: &str, addr: u32,
+                 words: Option<&[u32]>) -> fmt::Result {
+    match words {
+        None => writeln!(f, "{} at {:08x}: not read", name, addr),
+        Some(words) => {
+            writeln!(f, "{} at {:08x}:", name, addr)?;
+            for (i, row) in words.chunks(4).enumerate() {
+                write!(f, "  +{:03x}:", 16 * i)?;
+                for word in row {
+                    write!(f, " {:08x}", word)?;
+                }
+                writeln!(f, "")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Registers of the CPU and excerpts of the code, data and stack at an
+/// exception, copied by the CPU that took it.
+#[derive(Clone, Copy)]
+pub struct Dump {
+    pub vect: u32,
+    pub pc:   u32,
+    pub ea:   u32,
+    pub sr:   u32,
+    /// r0 to r31, with r1 the stack pointer before the exception.
+    pub gpr:  [u32; 32],
+    code:     Excerpt<[u32; CODE_WORDS]>,
+    data:     Excerpt<[u32; DATA_WORDS]>,
+    stack:    Excerpt<[u32; STACK_WORDS]>
+}
+
+impl Dump {
+    /// Copies the state saved by the exception vectors, given the arguments
+    /// of the `exception` handler. Memory around the effective address is
+    /// not read after a bus error, since that would fault again; neither is
+    /// the code when the bus error happened while fetching it.
+    pub unsafe fn capture(vect: u32, regs: *const u32, pc: u32, ea: u32, sr: u32) -> Dump {
+        let mut gpr = [0; 32];
+        gpr[1] = regs as u32 + FRAME_SIZE;
+        for i in 2..32 {
+            gpr[i] = *regs.offset(i as isize - 2)
+        }
+        let bus_error = vect == Exception::BusError as u32;
+        Dump {
+            vect: vect,
+            pc:   pc,
+            ea:   ea,
+            sr:   sr,
+            gpr:  gpr,
+            code:  excerpt!(CODE_WORDS, pc, !(bus_error && ea & !3 == pc & !3)),
+            data:  excerpt!(DATA_WORDS, ea, !bus_error),
+            stack: excerpt!(STACK_WORDS, gpr[1], true)
+        }
+    }
+}
+
+impl fmt::Display for Dump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match Exception::try_from(self.vect) {
+            Ok(vect) => write!(f, "{} exception", vect)?,
+            Err(_) => write!(f, "exception {:#x}", self.vect)?
+        }
+        writeln!(f, " at PC {:08x}, EA {:08x}, SR {:08x}", self.pc, self.ea, self.sr)?;
+        for (i, row) in self.gpr.chunks(4).enumerate() {
+            for (j, reg) in row.iter().enumerate() {
+                write!(f, "{}r{:<2} {:08x}", if j == 0 { "" } else { "  " }, 4 * i + j, reg)?;
+            }
+            writeln!(f, "")?;
+        }
+        write_excerpt(f, "code", self.code.addr, self.code.words.as_ref().map(|w| &w[..]))?;
+        write_excerpt(f, "data", self.data.addr, self.data.words.as_ref().map(|w| &w[..]))?;
+        write_excerpt(f, "stack", self.stack.addr, self.stack.words.as_ref().map(|w| &w[..]))
+    }
+}
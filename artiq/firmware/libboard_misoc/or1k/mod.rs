@@ -2,3 +2,4 @@ pub mod spr;
 pub mod irq;
 pub mod cache;
 pub mod boot;
+pub mod exception;
@@ -0,0 +1,88 @@
+use core::{cmp, fmt, slice, str};
+use byteorder::{ByteOrder, BigEndian};
+use crc::crc32;
+use {cache, firmware, spiflash};
+
+// One flash sector after the firmware banks holds the report of the last
+// panic, so that it survives the restart.
+const ADDR: usize = firmware::END;
+
+// Record header: magic, length of the text, CRC32 of the text.
+const MAGIC: &'static [u8] = b"APNC";
+const HEADER_SIZE: usize = 12;
+
+pub const MAX_LENGTH: usize = 8192;
+
+static mut BUFFER: [u8; MAX_LENGTH] = [0; MAX_LENGTH];
+
+/// Collects the text of a panic report in a static buffer, so that it can be
+/// used without the heap. Text that does not fit is dropped.
+pub struct Writer {
+    length: usize
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer { length: 0 }
+    }
+
+    /// Space left in the report.
+    pub fn remaining(&self) -> usize {
+        MAX_LENGTH - self.length
+    }
+
+    /// Writes the report to flash, replacing the previous one.
+    pub fn save(self) {
+        let text = unsafe { &BUFFER[..self.length] };
+        let mut header = [0; HEADER_SIZE];
+        header[0..4].copy_from_slice(MAGIC);
+        BigEndian::write_u32(&mut header[4..8], text.len() as u32);
+        BigEndian::write_u32(&mut header[8..12], crc32::checksum_ieee(text));
+        unsafe {
+            spiflash::erase_sector(ADDR);
+            spiflash::write(ADDR + HEADER_SIZE, text);
+            // Written last, so that an interrupted write leaves no record.
+            spiflash::write(ADDR, &header);
+        }
+        cache::flush_l2_cache();
+        cache::flush_cpu_dcache();
+    }
+}
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let length = cmp::min(s.len(), self.remaining());
+        unsafe {
+            BUFFER[self.length..self.length + length].copy_from_slice(&s.as_bytes()[..length]);
+        }
+        self.length += length;
+        Ok(())
+    }
+}
+
+/// Returns the report of the last panic, if there is one.
+pub fn read() -> Option<&'static str> {
+    let header = unsafe { slice::from_raw_parts(ADDR as *const u8, HEADER_SIZE) };
+    if &header[0..4] != MAGIC {
+        return None
+    }
+    let length = BigEndian::read_u32(&header[4..8]) as usize;
+    if length > MAX_LENGTH {
+        return None
+    }
+    let text = unsafe { slice::from_raw_parts((ADDR + HEADER_SIZE) as *const u8, length) };
+    if crc32::checksum_ieee(text) != BigEndian::read_u32(&header[8..12]) {
+        return None
+    }
+    // The text may have been cut in the middle of a character.
+    match str::from_utf8(text) {
+        Ok(text) => Some(text),
+        Err(err) => Some(unsafe { str::from_utf8_unchecked(&text[..err.valid_up_to()]) })
+    }
+}
+
+pub fn clear() {
+    unsafe { spiflash::erase_sector(ADDR) }
+    cache::flush_l2_cache();
+    cache::flush_cpu_dcache();
+}
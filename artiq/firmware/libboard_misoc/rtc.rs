@@ -0,0 +1,179 @@
+use i2c;
+
+// Driver for the DS1307 and DS3231 battery-backed real-time clocks, and
+// their compatibles, which keep the date and time in BCD in registers 0 to
+// 6; they are set here in 24-hour mode and for years 2000 to 2099. The time
+// read is only trusted if the oscillator has kept running since the clock
+// was set: the DS1307 halts it until it is first set, and the DS3231 flags
+// that it stopped, e.g. after its battery ran flat.
+
+const BUSNO: u8 = 0;
+
+const REG_SECONDS: u8 = 0x00;
+const DS1307_CH: u8 = 1 << 7;
+const DS3231_REG_STATUS: u8 = 0x0f;
+const DS3231_OSF: u8 = 1 << 7;
+const HOURS_12H: u8 = 1 << 6;
+const HOURS_PM: u8 = 1 << 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Ds1307,
+    Ds3231
+}
+
+/// Real-time clock. `port` is the port of the I2C switches it is behind, if
+/// any, and `address` is its 7-bit I2C address.
+#[derive(Debug, Clone, Copy)]
+pub struct Rtc {
+    pub kind:    Kind,
+    pub port:    Option<u8>,
+    pub address: u8
+}
+
+#[cfg(soc_platform = "kasli")]
+fn select(port: Option<u8>) -> Result<(), &'static str> {
+    let mask: u16 = port.map_or(0, |port| 1 << port);
+    i2c::pca9548_select(BUSNO, 0x70, mask as u8)?;
+    i2c::pca9548_select(BUSNO, 0x71, (mask >> 8) as u8)?;
+    Ok(())
+}
+
+#[cfg(not(soc_platform = "kasli"))]
+fn select(port: Option<u8>) -> Result<(), &'static str> {
+    match port {
+        None => Ok(()),
+        Some(_) => Err("no I2C switches")
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0xf)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    (value / 10) << 4 | value % 10
+}
+
+// Days since 1970-01-01 of a date of the proleptic Gregorian calendar,
+// following H. Hinnant's days_from_civil.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * mp + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Civil date of the number of days since 1970-01-01, as the year, month
+/// and day; the inverse of `days_from_civil`.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+                       - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl Rtc {
+    fn read_registers(&self, register: u8, data: &mut [u8]) -> Result<(), &'static str> {
+        select(self.port)?;
+        i2c::start(BUSNO)?;
+        if !i2c::write(BUSNO, self.address << 1)? {
+            i2c::stop(BUSNO)?;
+            return Err("RTC not found")
+        }
+        i2c::write(BUSNO, register)?;
+        i2c::restart(BUSNO)?;
+        i2c::write(BUSNO, self.address << 1 | 1)?;
+        let length = data.len();
+        for (index, byte) in data.iter_mut().enumerate() {
+            *byte = i2c::read(BUSNO, index < length - 1)?;
+        }
+        i2c::stop(BUSNO)
+    }
+
+    fn write_registers(&self, register: u8, data: &[u8]) -> Result<(), &'static str> {
+        select(self.port)?;
+        i2c::start(BUSNO)?;
+        if !i2c::write(BUSNO, self.address << 1)? {
+            i2c::stop(BUSNO)?;
+            return Err("RTC not found")
+        }
+        i2c::write(BUSNO, register)?;
+        for &byte in data {
+            i2c::write(BUSNO, byte)?;
+        }
+        i2c::stop(BUSNO)
+    }
+
+    /// Reads the time in seconds since the Unix epoch, or `None` if the
+    /// clock has not kept running since it was set.
+    pub fn read_unix_s(&self) -> Result<Option<u64>, &'static str> {
+        if self.kind == Kind::Ds3231 {
+            let mut status = [0; 1];
+            self.read_registers(DS3231_REG_STATUS, &mut status)?;
+            if status[0] & DS3231_OSF != 0 {
+                return Ok(None)
+            }
+        }
+        let mut registers = [0; 7];
+        self.read_registers(REG_SECONDS, &mut registers)?;
+        if self.kind == Kind::Ds1307 && registers[0] & DS1307_CH != 0 {
+            return Ok(None)
+        }
+
+        let seconds = from_bcd(registers[0] & 0x7f) as u64;
+        let minutes = from_bcd(registers[1] & 0x7f) as u64;
+        let hours = if registers[2] & HOURS_12H != 0 {
+            let hours = from_bcd(registers[2] & 0x1f) % 12;
+            if registers[2] & HOURS_PM != 0 { hours + 12 } else { hours }
+        } else {
+            from_bcd(registers[2] & 0x3f)
+        } as u64;
+        let day = from_bcd(registers[4] & 0x3f) as i64;
+        let month = from_bcd(registers[5] & 0x1f) as i64;
+        let year = 2000 + from_bcd(registers[6]) as i64;
+        if seconds > 59 || minutes > 59 || hours > 23 ||
+                day < 1 || day > 31 || month < 1 || month > 12 {
+            return Ok(None)
+        }
+        let days = days_from_civil(year, month, day) as u64;
+        Ok(Some(days * 86400 + hours * 3600 + minutes * 60 + seconds))
+    }
+
+    /// Sets the clock to `unix_s` seconds since the Unix epoch, and starts
+    /// its oscillator if it was halted.
+    pub fn write_unix_s(&self, unix_s: u64) -> Result<(), &'static str> {
+        let days = (unix_s / 86400) as i64;
+        let (year, month, day) = civil_from_days(days);
+        if year < 2000 || year > 2099 {
+            return Err("date out of the range of the RTC")
+        }
+        let registers = [
+            to_bcd((unix_s % 60) as u8),
+            to_bcd((unix_s / 60 % 60) as u8),
+            to_bcd((unix_s / 3600 % 24) as u8),
+            // Day of the week from 1 (Monday) to 7; 1970-01-01 was a Thursday.
+            ((days + 3) % 7 + 1) as u8,
+            to_bcd(day as u8),
+            to_bcd(month as u8),
+            to_bcd((year - 2000) as u8)
+        ];
+        self.write_registers(REG_SECONDS, &registers)?;
+        if self.kind == Kind::Ds3231 {
+            let mut status = [0; 1];
+            self.read_registers(DS3231_REG_STATUS, &mut status)?;
+            self.write_registers(DS3231_REG_STATUS, &[status[0] & !DS3231_OSF])?;
+        }
+        Ok(())
+    }
+}
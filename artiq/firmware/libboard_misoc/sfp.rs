@@ -0,0 +1,460 @@
+use core::fmt;
+use i2c;
+use config;
+#[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
+use io_expander::IoExpander;
+
+/// Digital diagnostics of an SFP transceiver, in the raw units of SFF-8472:
+/// 1/256 °C, 100 µV, 2 µA and 0.1 µW.
+#[derive(Debug, Clone, Copy)]
+pub struct Diagnostics {
+    pub temperature: i16,
+    pub voltage:     u16,
+    pub tx_bias:     u16,
+    pub tx_power:    u16,
+    pub rx_power:    u16
+}
+
+const BUSNO: u8 = 0;
+const ID_ADDRESS:   u8 = 0xa0;
+const DIAG_ADDRESS: u8 = 0xa2;
+
+// Ports of the I2C switches, as in artiq.coredevice.kasli_i2c.
+#[cfg(soc_platform = "kasli")]
+const PORTS: [u8; 3] = [8, 9, 10];
+#[cfg(not(soc_platform = "kasli"))]
+const PORTS: [u8; 0] = [];
+
+const MAX_PORTS: usize = 3;
+
+// Alarm flags added to those read from each port, to test monitoring
+// without degrading the hardware.
+#[cfg(has_fault_injection)]
+static mut INJECTED_ALARMS: [u16; MAX_PORTS] = [0; MAX_PORTS];
+
+/// Adds `alarms` (in the bit order of `ALARM_NAMES`) to the alarm flags
+/// read from SFP port `index` with diagnostics, until injected again.
+#[cfg(has_fault_injection)]
+pub fn inject_alarms(index: usize, alarms: u16) -> Result<(), &'static str> {
+    if index >= count() {
+        return Err("no such SFP port")
+    }
+    unsafe { INJECTED_ALARMS[index] = alarms & 0xffc0 }
+    Ok(())
+}
+
+#[cfg(has_fault_injection)]
+fn injected_alarms(index: usize) -> u16 {
+    unsafe { INJECTED_ALARMS[index] }
+}
+
+#[cfg(not(has_fault_injection))]
+fn injected_alarms(_index: usize) -> u16 {
+    0
+}
+
+/// Alarm flags of SFF-8472 (A2h bytes 112 and 113), from bit 15 down.
+pub const ALARM_NAMES: [&'static str; 10] = [
+    "temperature high", "temperature low",
+    "voltage high", "voltage low",
+    "TX bias high", "TX bias low",
+    "TX power high", "TX power low",
+    "RX power high", "RX power low"
+];
+
+/// State of an SFP port. The alarm flags are those of `ALARM_NAMES`, and are
+/// only read from transceivers that provide diagnostics.
+#[derive(Debug, Clone, Copy)]
+pub struct Status {
+    pub present:     bool,
+    pub diagnostics: Option<Diagnostics>,
+    pub alarms:      u16
+}
+
+pub fn count() -> usize {
+    PORTS.len()
+}
+
+/// SFP ports listed in the `sfp_unused` configuration key, e.g. `2,3`, as a
+/// bit mask. SFP0 carries Ethernet or the DRTIO uplink and is never unused.
+pub fn unused_ports() -> u8 {
+    config::read_str("sfp_unused", |r| r.map(|s| {
+        s.split(',').fold(0, |mask, port| match port.trim().parse::<u8>() {
+            Ok(port) if port >= 1 && port <= 3 => mask | 1 << port,
+            _ => mask
+        })
+    }).unwrap_or(0))
+}
+
+#[cfg(soc_platform = "kasli")]
+fn select(index: usize) -> Result<(), &'static str> {
+    let mask: u16 = 1 << PORTS[index];
+    i2c::pca9548_select(BUSNO, 0x70, mask as u8)?;
+    i2c::pca9548_select(BUSNO, 0x71, (mask >> 8) as u8)?;
+    Ok(())
+}
+
+#[cfg(not(soc_platform = "kasli"))]
+fn select(_index: usize) -> Result<(), &'static str> {
+    Err("no SFP ports")
+}
+
+fn probe(address: u8) -> Result<bool, &'static str> {
+    i2c::start(BUSNO)?;
+    let ack = i2c::write(BUSNO, address)?;
+    i2c::stop(BUSNO)?;
+    Ok(ack)
+}
+
+fn write(address: u8, offset: u8, data: u8) -> Result<(), &'static str> {
+    i2c::start(BUSNO)?;
+    if !i2c::write(BUSNO, address)? {
+        i2c::stop(BUSNO)?;
+        return Err("SFP transceiver not present")
+    }
+    i2c::write(BUSNO, offset)?;
+    i2c::write(BUSNO, data)?;
+    i2c::stop(BUSNO)?;
+    Ok(())
+}
+
+fn read(address: u8, offset: u8, buf: &mut [u8]) -> Result<(), &'static str> {
+    i2c::start(BUSNO)?;
+    if !i2c::write(BUSNO, address)? {
+        i2c::stop(BUSNO)?;
+        return Err("SFP transceiver not present")
+    }
+    i2c::write(BUSNO, offset)?;
+    i2c::restart(BUSNO)?;
+    i2c::write(BUSNO, address | 1)?;
+    let buf_len = buf.len();
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte = i2c::read(BUSNO, i < buf_len - 1)?;
+    }
+    i2c::stop(BUSNO)?;
+    Ok(())
+}
+
+/// Reads the diagnostics of the transceiver in SFP port `index`. Returns
+/// `Ok(None)` if the transceiver does not implement internally calibrated
+/// digital diagnostics.
+pub fn diagnostics(index: usize) -> Result<Option<Diagnostics>, &'static str> {
+    select(index)?;
+    read_diagnostics()
+}
+
+fn read_diagnostics() -> Result<Option<Diagnostics>, &'static str> {
+    // Diagnostic monitoring type: implemented, internally calibrated,
+    // and no address change sequence required.
+    let mut monitoring = [0; 1];
+    read(ID_ADDRESS, 92, &mut monitoring)?;
+    if monitoring[0] & 0x64 != 0x60 {
+        return Ok(None)
+    }
+
+    let mut values = [0; 10];
+    read(DIAG_ADDRESS, 96, &mut values)?;
+    let word = |i: usize| (values[i] as u16) << 8 | values[i + 1] as u16;
+    Ok(Some(Diagnostics {
+        temperature: word(0) as i16,
+        voltage:     word(2),
+        tx_bias:     word(4),
+        tx_power:    word(6),
+        rx_power:    word(8)
+    }))
+}
+
+/// Reads the presence, diagnostics and alarm flags of SFP port `index`.
+pub fn status(index: usize) -> Result<Status, &'static str> {
+    select(index)?;
+    if !probe(ID_ADDRESS)? {
+        return Ok(Status { present: false, diagnostics: None, alarms: 0 })
+    }
+    let diagnostics = read_diagnostics()?;
+    let mut alarms = [0; 2];
+    let mut injected = 0;
+    if diagnostics.is_some() {
+        read(DIAG_ADDRESS, 112, &mut alarms)?;
+        injected = injected_alarms(index);
+    }
+    Ok(Status {
+        present: true,
+        diagnostics: diagnostics,
+        alarms: ((alarms[0] as u16) << 8 | alarms[1] as u16) & 0xffc0 | injected
+    })
+}
+
+/// Reads the base ID fields of the transceiver in SFP port `index` (A0h bytes
+/// 0 to 62) and checks them against their check code (byte 63). Returns
+/// `Ok(false)` if the port is empty.
+pub fn check_id(index: usize) -> Result<bool, &'static str> {
+    select(index)?;
+    if !probe(ID_ADDRESS)? {
+        return Ok(false)
+    }
+    let mut id = [0; 64];
+    read(ID_ADDRESS, 0, &mut id)?;
+    let sum = id[..63].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    if sum != id[63] {
+        return Err("ID EEPROM check code mismatch")
+    }
+    Ok(true)
+}
+
+// Soft TX_DISABLE control of the transceiver (A2h byte 110, bit 6).
+fn set_soft_tx_disable(index: usize, disable: bool) -> Result<(), &'static str> {
+    select(index)?;
+    // Enhanced options: soft TX_DISABLE implemented.
+    let mut options = [0; 1];
+    read(ID_ADDRESS, 93, &mut options)?;
+    if options[0] & 0x40 == 0 {
+        return Err("soft TX_DISABLE not implemented")
+    }
+    let mut control = [0; 1];
+    read(DIAG_ADDRESS, 110, &mut control)?;
+    let control = if disable { control[0] | 0x40 } else { control[0] & !0x40 };
+    write(DIAG_ADDRESS, 110, control)
+}
+
+// RX_LOS state of the transceiver (A2h byte 110, bit 1).
+fn soft_los(index: usize) -> Result<bool, &'static str> {
+    select(index)?;
+    // Enhanced options: RX_LOS monitoring implemented.
+    let mut options = [0; 1];
+    read(ID_ADDRESS, 93, &mut options)?;
+    if options[0] & 0x10 == 0 {
+        return Err("soft RX_LOS not implemented")
+    }
+    let mut control = [0; 1];
+    read(DIAG_ADDRESS, 110, &mut control)?;
+    Ok(control[0] & 0x02 != 0)
+}
+
+// TX_DISABLE and LOS lines of the SFP ports, where the board exposes them:
+// through the FPGA on Kasli v1, and through the I/O expanders on Kasli v2.0.
+// The transmitters of the other ports are controlled through the transceiver
+// itself, over I2C.
+#[cfg(has_sfp_ctl)]
+mod lines {
+    use csr;
+
+    pub const COUNT: usize = 4;
+
+    pub fn present(index: usize) -> bool {
+        index < COUNT && csr::CONFIG_SFP_CTL_PORTS & 1 << index != 0
+    }
+
+    pub fn update(tx_disabled: u8) {
+        unsafe { csr::sfp_ctl::tx_disable_write(tx_disabled) }
+    }
+
+    pub fn los(index: usize) -> Result<bool, &'static str> {
+        Ok(unsafe { csr::sfp_ctl::los_read() } & 1 << index != 0)
+    }
+}
+
+#[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
+mod lines {
+    use io_expander::IoExpander;
+
+    // Expander and expander port of the pins of each SFP port.
+    pub const PINS: [(u8, u8); 4] = [(0, 0), (0, 1), (1, 0), (1, 1)];
+    pub const TX_DISABLE_BIT: u8 = 1;
+    pub const LOS_BIT: u8 = 2;
+
+    pub const COUNT: usize = 4;
+
+    pub fn present(index: usize) -> bool {
+        index < COUNT
+    }
+
+    // The expanders are written by their owner, with `drive_expanders`.
+    pub fn update(_tx_disabled: u8) {}
+
+    pub fn los(index: usize) -> Result<bool, &'static str> {
+        let (expander, port) = PINS[index];
+        IoExpander::new(expander).get(port, LOS_BIT)
+    }
+}
+
+#[cfg(not(any(has_sfp_ctl, all(soc_platform = "kasli", hw_rev = "v2.0"))))]
+mod lines {
+    pub const COUNT: usize = 0;
+
+    pub fn present(_index: usize) -> bool {
+        false
+    }
+
+    pub fn update(_tx_disabled: u8) {}
+
+    pub fn los(_index: usize) -> Result<bool, &'static str> {
+        Err("no LOS line")
+    }
+}
+
+// Ports whose transmitter is disabled by `set_tx_enable`, and whether those
+// other than SFP0 are inhibited by `set_tx_inhibited`.
+static mut TX_DISABLED: u8 = 0;
+static mut TX_INHIBITED: bool = false;
+
+// TX_DISABLE levels of the ports with lines.
+fn tx_disable_lines() -> u8 {
+    unsafe {
+        if TX_INHIBITED { TX_DISABLED | !1 } else { TX_DISABLED }
+    }
+}
+
+/// Number of SFP ports whose transmitter can be controlled, including those
+/// only reachable through their TX_DISABLE line.
+pub fn tx_count() -> usize {
+    if lines::COUNT > count() { lines::COUNT } else { count() }
+}
+
+/// Enables or disables the transmitter of SFP port `index`, with the
+/// TX_DISABLE line of the port where the board exposes it, and with the soft
+/// TX_DISABLE control of the transceiver otherwise, which uses the I2C bus.
+/// On Kasli v2.0, the lines are written by `drive_expanders`.
+pub fn set_tx_enable(index: usize, enable: bool) -> Result<(), &'static str> {
+    if lines::present(index) {
+        unsafe {
+            if enable { TX_DISABLED &= !(1 << index) } else { TX_DISABLED |= 1 << index }
+        }
+        lines::update(tx_disable_lines());
+        Ok(())
+    } else if index < count() {
+        set_soft_tx_disable(index, !enable)
+    } else {
+        Err("no such SFP port")
+    }
+}
+
+/// Whether the transceiver in SFP port `index` reports a loss of signal, from
+/// the LOS line of the port where the board exposes it, and from its RX_LOS
+/// state over I2C otherwise.
+pub fn los(index: usize) -> Result<bool, &'static str> {
+    if lines::present(index) {
+        lines::los(index)
+    } else if index < count() {
+        soft_los(index)
+    } else {
+        Err("no such SFP port")
+    }
+}
+
+/// While `inhibited`, disables the transmitters of the ports other than SFP0
+/// that have a TX_DISABLE line, whatever `set_tx_enable` set, e.g. while the
+/// FPGA is too hot.
+pub fn set_tx_inhibited(inhibited: bool) {
+    unsafe { TX_INHIBITED = inhibited }
+    lines::update(tx_disable_lines());
+}
+
+/// Makes the TX_DISABLE pins of the I/O expanders outputs, and sets them as
+/// `drive_expanders` does.
+#[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
+pub fn init_expanders(expander0: &mut IoExpander, expander1: &mut IoExpander)
+        -> Result<(), &'static str> {
+    for &(expander, port) in lines::PINS.iter() {
+        let expander = if expander == 0 { &mut *expander0 } else { &mut *expander1 };
+        expander.set_oe(port, 1 << lines::TX_DISABLE_BIT)?;
+    }
+    drive_expanders(expander0, expander1);
+    Ok(())
+}
+
+/// Sets the TX_DISABLE pins of the I/O expanders to the state of the
+/// transmitters; they are written by `IoExpander::service`.
+#[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
+pub fn drive_expanders(expander0: &mut IoExpander, expander1: &mut IoExpander) {
+    let tx_disabled = tx_disable_lines();
+    for (index, &(expander, port)) in lines::PINS.iter().enumerate() {
+        let expander = if expander == 0 { &mut *expander0 } else { &mut *expander1 };
+        expander.set(port, lines::TX_DISABLE_BIT, tx_disabled & 1 << index != 0);
+    }
+}
+
+/// Disables the transmitters of the ports listed in `sfp_unused`, and calls
+/// `f` with the index and result for each.
+pub fn disable_unused<F: FnMut(usize, Result<(), &'static str>)>(mut f: F) {
+    let unused = unused_ports();
+    for index in 1..tx_count() {
+        if unused & 1 << index != 0 {
+            f(index, set_tx_enable(index, false))
+        }
+    }
+}
+
+/// Change in the state of an SFP port seen by `Monitor::poll`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Inserted,
+    Removed,
+    AlarmRaised(&'static str),
+    AlarmCleared(&'static str)
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Event::Inserted => write!(f, "transceiver inserted"),
+            Event::Removed => write!(f, "transceiver removed"),
+            Event::AlarmRaised(name) => write!(f, "{} alarm", name),
+            Event::AlarmCleared(name) => write!(f, "{} alarm cleared", name)
+        }
+    }
+}
+
+/// Keeps the last state of the SFP ports, to report their changes.
+pub struct Monitor {
+    ports:  [Option<Status>; MAX_PORTS],
+    unused: u8
+}
+
+impl Monitor {
+    pub fn new() -> Monitor {
+        Monitor { ports: [None; MAX_PORTS], unused: unused_ports() }
+    }
+
+    /// Reads all SFP ports and calls `f` with the index of the port and the
+    /// event for each change since the previous poll. The alarms already
+    /// active at the first poll are reported, the transceivers already
+    /// present are not. Ports that cannot be read keep their last state, and
+    /// the ports listed in `sfp_unused` are not read.
+    pub fn poll<F: FnMut(usize, Event)>(&mut self, mut f: F) {
+        for index in 0..count() {
+            if self.unused & 1 << index != 0 {
+                continue
+            }
+            let status = match status(index) {
+                Ok(status) => status,
+                Err(_) => continue
+            };
+            let (was_present, old_alarms) = match self.ports[index] {
+                Some(old) => (old.present, old.alarms),
+                None => (status.present, 0)
+            };
+            if status.present != was_present {
+                f(index, if status.present { Event::Inserted } else { Event::Removed })
+            }
+            for (i, &name) in ALARM_NAMES.iter().enumerate() {
+                let bit = 1 << (15 - i);
+                match (old_alarms & bit != 0, status.alarms & bit != 0) {
+                    (false, true) => f(index, Event::AlarmRaised(name)),
+                    (true, false) => f(index, Event::AlarmCleared(name)),
+                    _ => ()
+                }
+            }
+            self.ports[index] = Some(status);
+        }
+    }
+
+    /// Last state of SFP port `index`, if it has been read.
+    pub fn status(&self, index: usize) -> Option<Status> {
+        self.ports.get(index).and_then(|port| *port)
+    }
+
+    /// Whether any transceiver has an active alarm.
+    pub fn alarm(&self) -> bool {
+        self.ports.iter().any(|port| port.map_or(false, |port| port.alarms != 0))
+    }
+}
@@ -1,8 +1,33 @@
 use csr;
 
+// Event bits of the MiSoC UART.
+const EV_RX: u8 = 0x2;
+
 pub fn set_speed(rate: u32) {
     unsafe {
         let tuning_word = (rate as u64) * (1 << 32) / (csr::CONFIG_CLOCK_FREQUENCY as u64);
         csr::uart_phy::tuning_word_write(tuning_word as u32);
     }
 }
+
+/// Returns the next received byte, if any.
+pub fn read() -> Option<u8> {
+    unsafe {
+        if csr::uart::rxempty_read() != 0 {
+            None
+        } else {
+            let c = csr::uart::rxtx_read();
+            // Acknowledging the event pops the byte from the FIFO.
+            csr::uart::ev_pending_write(EV_RX);
+            Some(c)
+        }
+    }
+}
+
+/// Sends a byte, bypassing the console.
+pub fn write(c: u8) {
+    unsafe {
+        while csr::uart::txfull_read() != 0 {}
+        csr::uart::rxtx_write(c)
+    }
+}
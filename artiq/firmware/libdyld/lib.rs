@@ -1,6 +1,6 @@
 #![no_std]
 
-use core::{mem, ptr, fmt, slice, str, convert};
+use core::{mem, ptr, fmt, slice, str, cmp, convert};
 use elf::*;
 
 pub mod elf;
@@ -78,8 +78,10 @@ impl<'a> fmt::Display for Error<'a> {
 pub struct Library<'a> {
     image_off:   Elf32_Addr,
     image_sz:    usize,
+    image_len:   usize,
     strtab:      &'a [u8],
     symtab:      &'a [Elf32_Sym],
+    rela:        &'a [Elf32_Rela],
     pltrel:      &'a [Elf32_Rela],
     hash_bucket: &'a [Elf32_Word],
     hash_chain:  &'a [Elf32_Word],
@@ -195,10 +197,10 @@ impl<'a> Library<'a> {
         self.update_rela(rela, value)
     }
 
-    pub fn load(data: &[u8], image: &'a mut [u8], resolve: &Fn(&[u8]) -> Option<Elf32_Word>)
-            -> Result<Library<'a>, Error<'a>> {
-        #![allow(unused_assignments)]
-
+    // Checks the ELF header of `data`, then returns the offset of the dynamic section
+    // and the extent of the loaded segments in the image, copying them there if `copy`.
+    fn load_segments(data: &[u8], image: &mut [u8], copy: bool)
+            -> Result<(usize, usize), &'static str> {
         let ehdr = read_unaligned::<Elf32_Ehdr>(data, 0)
                                   .map_err(|()| "cannot read ELF header")?;
 
@@ -214,10 +216,11 @@ impl<'a> Library<'a> {
         const ARCH: u16 = EM_NONE;
 
         if ehdr.e_ident != IDENT || ehdr.e_type != ET_DYN || ehdr.e_machine != ARCH {
-            return Err("not a shared library for current architecture")?
+            return Err("not a shared library for current architecture")
         }
 
         let mut dyn_off = None;
+        let mut image_len = 0;
         for i in 0..ehdr.e_phnum {
             let phdr_off = ehdr.e_phoff as usize + mem::size_of::<Elf32_Phdr>() * i as usize;
             let phdr = read_unaligned::<Elf32_Phdr>(data, phdr_off)
@@ -227,8 +230,11 @@ impl<'a> Library<'a> {
                 PT_LOAD => {
                     if (phdr.p_vaddr + phdr.p_filesz) as usize > image.len() ||
                             (phdr.p_offset + phdr.p_filesz) as usize > data.len() {
-                        return Err("program header requests an out of bounds load")?
+                        return Err("program header requests an out of bounds load")
                     }
+                    image_len = cmp::max(image_len, (phdr.p_vaddr + phdr.p_filesz) as usize);
+                    if !copy { continue }
+
                     let dst = image.get_mut(phdr.p_vaddr as usize..
                                             (phdr.p_vaddr + phdr.p_filesz) as usize)
                                    .ok_or("cannot write to program header destination")?;
@@ -239,12 +245,19 @@ impl<'a> Library<'a> {
                 }
 
                 PT_DYNAMIC =>
-                    dyn_off = Some(phdr.p_vaddr),
+                    dyn_off = Some(phdr.p_vaddr as usize),
 
                 _ => ()
             }
         }
 
+        Ok((dyn_off.ok_or("cannot find a dynamic header")?, image_len))
+    }
+
+    fn parse(image: &'a mut [u8], dyn_off: usize, image_len: usize)
+            -> Result<Library<'a>, Error<'a>> {
+        #![allow(unused_assignments)]
+
         let (mut strtab_off, mut strtab_sz) = (0, 0);
         let (mut symtab_off, mut symtab_sz) = (0, 0);
         let (mut rela_off,   mut rela_sz)   = (0, 0);
@@ -255,9 +268,8 @@ impl<'a> Library<'a> {
         let mut nbucket  = 0;
         let mut nchain   = 0;
 
-        let dyn_off = dyn_off.ok_or("cannot find a dynamic header")?;
         for i in 0.. {
-            let dyn_off = dyn_off as usize + i * mem::size_of::<Elf32_Dyn>();
+            let dyn_off = dyn_off + i * mem::size_of::<Elf32_Dyn>();
             let dyn = get_ref::<Elf32_Dyn>(image, dyn_off)
                               .map_err(|()| "cannot read dynamic header")?;
 
@@ -297,7 +309,14 @@ impl<'a> Library<'a> {
         // the symbols that overflowed the bucket.
         symtab_sz = nchain;
 
-        // Drop the mutability. See also the comment below.
+        // If a borrow exists anywhere, the borrowed memory cannot be mutated except
+        // through that pointer or it's UB. However, we need to retain pointers
+        // to the symbol tables and relocations, and at the same time mutate the code
+        // to resolve the relocations.
+        //
+        // To avoid invoking UB, we drop the mutability of the only pointer to the entire
+        // area (which is unique since it's a &mut) and do not return it; we retain pointers
+        // to the various tables, but we never write to the memory they refer to, so it's safe.
         let image = &*image;
 
         let strtab = get_ref_slice::<u8>(image, strtab_off, strtab_sz)
@@ -311,29 +330,89 @@ impl<'a> Library<'a> {
         let hash   = get_ref_slice::<Elf32_Word>(image, hash_off, hash_sz)
                                    .map_err(|()| "cannot read hash entries")?;
 
-        let library = Library {
+        Ok(Library {
             image_off:   image.as_ptr() as Elf32_Word,
             image_sz:    image.len(),
+            image_len:   image_len,
             strtab:      strtab,
             symtab:      symtab,
+            rela:        rela,
             pltrel:      pltrel,
             hash_bucket: &hash[..nbucket],
             hash_chain:  &hash[nbucket..nbucket + nchain],
-        };
+        })
+    }
 
-        // If a borrow exists anywhere, the borrowed memory cannot be mutated except
-        // through that pointer or it's UB. However, we need to retain pointers
-        // to the symbol tables and relocations, and at the same time mutate the code
-        // to resolve the relocations.
-        //
-        // To avoid invoking UB, we drop the only pointer to the entire area (which is
-        // unique since it's a &mut); we retain pointers to the various tables, but
-        // we never write to the memory they refer to, so it's safe.
-        mem::drop(image);
+    pub fn load(data: &[u8], image: &'a mut [u8], resolve: &Fn(&[u8]) -> Option<Elf32_Word>)
+            -> Result<Library<'a>, Error<'a>> {
+        let (dyn_off, image_len) = Library::load_segments(data, image, true)?;
+        let library = Library::parse(image, dyn_off, image_len)?;
 
-        for r in rela   { library.resolve_rela(r, resolve)? }
-        for r in pltrel { library.resolve_rela(r, resolve)? }
+        for r in library.rela   { library.resolve_rela(r, resolve)? }
+        for r in library.pltrel { library.resolve_rela(r, resolve)? }
 
         Ok(library)
     }
+
+    /// Loads `data` from `relocated`, a copy of the first `image_len()` bytes of
+    /// an image where the same library was loaded before, skipping relocation.
+    /// The copy is only valid at the same address and with the same `resolve`.
+    pub fn load_relocated(data: &[u8], image: &'a mut [u8], relocated: &[u8])
+            -> Result<Library<'a>, Error<'a>> {
+        let (dyn_off, image_len) = Library::load_segments(data, image, false)?;
+        if relocated.len() != image_len {
+            return Err("relocated image does not match the library")?
+        }
+        image[..image_len].copy_from_slice(relocated);
+
+        Library::parse(image, dyn_off, image_len)
+    }
+
+    /// Returns the length of the part of the image written by `load`.
+    pub fn image_len(&self) -> usize {
+        self.image_len
+    }
+}
+
+/// Calls `f` with the address, size and name of every function in the static
+/// symbol table (`.symtab`) of `data`. Unlike the dynamic symbol table, it also
+/// covers functions with internal linkage, but it is not part of the loaded image.
+pub fn for_each_function<F>(data: &[u8], mut f: F) -> Result<(), Error<'static>>
+        where F: FnMut(Elf32_Addr, Elf32_Word, &[u8]) {
+    let ehdr = read_unaligned::<Elf32_Ehdr>(data, 0)
+                              .map_err(|()| "cannot read ELF header")?;
+
+    let read_shdr = |index: usize| {
+        let shdr_off = ehdr.e_shoff as usize + mem::size_of::<Elf32_Shdr>() * index;
+        read_unaligned::<Elf32_Shdr>(data, shdr_off)
+                       .map_err(|()| "cannot read section header")
+    };
+
+    for i in 0..ehdr.e_shnum as usize {
+        let symtab_hdr = read_shdr(i)?;
+        if symtab_hdr.sh_type as usize != SHT_SYMTAB { continue }
+
+        let strtab_hdr = read_shdr(symtab_hdr.sh_link as usize)?;
+        let strtab = data.get(strtab_hdr.sh_offset as usize..
+                              (strtab_hdr.sh_offset + strtab_hdr.sh_size) as usize)
+                         .ok_or("cannot read string table")?;
+
+        let sym_count = symtab_hdr.sh_size as usize / mem::size_of::<Elf32_Sym>();
+        for j in 0..sym_count {
+            let sym_off = symtab_hdr.sh_offset as usize + mem::size_of::<Elf32_Sym>() * j;
+            let sym = read_unaligned::<Elf32_Sym>(data, sym_off)
+                                     .map_err(|()| "cannot read symbol")?;
+            if ELF32_ST_TYPE(sym.st_info) != STT_FUNC || sym.st_shndx == SHN_UNDEF {
+                continue
+            }
+
+            let name = strtab.get(sym.st_name as usize..)
+                             .ok_or("cannot read symbol name")?;
+            let size = name.iter().position(|&x| x == 0)
+                           .ok_or("symbol in symbol table not null-terminated")?;
+            f(sym.st_value, sym.st_size, &name[..size])
+        }
+    }
+
+    Ok(())
 }
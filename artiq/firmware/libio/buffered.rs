@@ -0,0 +1,67 @@
+use {Write, Error};
+
+/// Writer that gathers small writes in a borrowed buffer, e.g. one on the
+/// stack, and passes them to the inner writer in one piece.
+///
+/// Writes that do not fit in the buffer go directly to the inner writer, so
+/// that large payloads are not copied. The buffered bytes are only written
+/// by `flush`, which must be called before the `BufWriter` is dropped; it
+/// does not flush the inner writer.
+pub struct BufWriter<'a, W: Write + ?Sized + 'a> {
+    inner:  &'a mut W,
+    buffer: &'a mut [u8],
+    len:    usize
+}
+
+impl<'a, W: Write + ?Sized> BufWriter<'a, W> {
+    #[inline]
+    pub fn new(inner: &'a mut W, buffer: &'a mut [u8]) -> BufWriter<'a, W> {
+        BufWriter { inner: inner, buffer: buffer, len: 0 }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner
+    }
+
+    fn drain(&mut self) -> Result<(), Error<W::WriteError>> {
+        if self.len > 0 {
+            let len = self.len;
+            self.len = 0;
+            self.inner.write_all(&self.buffer[..len])?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W: Write + ?Sized> Write for BufWriter<'a, W> {
+    type WriteError = W::WriteError;
+    type FlushError = Error<W::WriteError>;
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::WriteError> {
+        if self.len + buf.len() > self.buffer.len() {
+            match self.drain() {
+                Ok(()) => (),
+                Err(Error::Other(err)) => return Err(err),
+                // The inner writer cannot take more bytes.
+                Err(Error::UnexpectedEnd) => return Ok(0)
+            }
+        }
+        if buf.len() >= self.buffer.len() {
+            self.inner.write(buf)
+        } else {
+            self.buffer[self.len..self.len + buf.len()].copy_from_slice(buf);
+            self.len += buf.len();
+            Ok(buf.len())
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<(), Self::FlushError> {
+        self.drain()
+    }
+
+    fn size_hint(&mut self, min: usize, max: Option<usize>) {
+        self.inner.size_hint(min, max)
+    }
+}
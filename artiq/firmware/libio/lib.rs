@@ -12,10 +12,12 @@ extern crate alloc;
 extern crate byteorder;
 
 mod cursor;
+mod buffered;
 #[cfg(feature = "byteorder")]
 mod proto;
 
 pub use cursor::Cursor;
+pub use buffered::BufWriter;
 #[cfg(feature = "byteorder")]
 pub use proto::{ProtoRead, ProtoWrite};
 #[cfg(all(feature = "byteorder", feature = "alloc"))]
@@ -90,6 +92,15 @@ pub trait Write {
         Ok(())
     }
 
+    /// Attempts to write several buffers into `self`, in order, without
+    /// concatenating them first.
+    fn write_all_vectored(&mut self, bufs: &[&[u8]]) -> Result<(), Error<Self::WriteError>> {
+        for buf in bufs {
+            self.write_all(buf)?
+        }
+        Ok(())
+    }
+
     /// Hints the writer how much bytes will be written after call to this function.
     ///
     /// At least `min` bytes should be written after the call to this function and
@@ -109,6 +120,10 @@ impl<'a, T: Write> Write for &'a mut T {
         T::flush(self)
     }
 
+    fn write_all_vectored(&mut self, bufs: &[&[u8]]) -> Result<(), Error<Self::WriteError>> {
+        T::write_all_vectored(self, bufs)
+    }
+
     fn size_hint(&mut self, min: usize, max: Option<usize>) {
         T::size_hint(self, min, max)
     }
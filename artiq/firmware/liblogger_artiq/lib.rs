@@ -5,30 +5,29 @@ extern crate log_buffer;
 #[macro_use]
 extern crate board_misoc;
 
+use core::{cmp, fmt};
 use core::cell::{Cell, RefCell, RefMut};
 use core::fmt::Write;
 use log::{Log, LevelFilter};
 use log_buffer::LogBuffer;
 use board_misoc::clock;
+use board_misoc::rtc::civil_from_days;
 
+/// Exclusive access to the log buffer. Records logged meanwhile cannot be
+/// added to it, and are counted as lost.
 pub struct LogBufferRef<'a> {
-    buffer:        RefMut<'a, LogBuffer<&'static mut [u8]>>,
-    old_log_level: LevelFilter
+    buffer: RefMut<'a, LogBuffer<&'static mut [u8]>>,
+    unread: &'a Cell<usize>
 }
 
 impl<'a> LogBufferRef<'a> {
-    fn new(buffer: RefMut<'a, LogBuffer<&'static mut [u8]>>) -> LogBufferRef<'a> {
-        let old_log_level = log::max_level();
-        log::set_max_level(LevelFilter::Off);
-        LogBufferRef { buffer, old_log_level }
-    }
-
     pub fn is_empty(&self) -> bool {
         self.buffer.is_empty()
     }
 
     pub fn clear(&mut self) {
-        self.buffer.clear()
+        self.buffer.clear();
+        self.unread.set(0)
     }
 
     pub fn extract(&mut self) -> &str {
@@ -36,15 +35,218 @@ impl<'a> LogBufferRef<'a> {
     }
 }
 
-impl<'a> Drop for LogBufferRef<'a> {
-    fn drop(&mut self) {
-        log::set_max_level(self.old_log_level)
+// Counts the bytes of a formatted record.
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+pub const MAX_MODULE_FILTERS: usize = 16;
+const MAX_MODULE_LEN: usize = 64;
+
+// Log level for a module path and everything below it, overriding the
+// global log level.
+#[derive(Clone, Copy)]
+struct ModuleFilter {
+    module: [u8; MAX_MODULE_LEN],
+    len:    usize,
+    level:  LevelFilter
+}
+
+impl ModuleFilter {
+    fn module(&self) -> &str {
+        // Only ever filled from a &str, cut at a character boundary.
+        unsafe { core::str::from_utf8_unchecked(&self.module[..self.len]) }
+    }
+
+    fn matches(&self, target: &str) -> bool {
+        let module = self.module();
+        target.starts_with(module) &&
+            (target.len() == module.len() || target[module.len()..].starts_with("::"))
+    }
+}
+
+// Identical messages (same level, module and text) logged again within the
+// repeat window after the first are only counted. At the end of the window,
+// a summary with the count is logged instead, so that a fault reported at
+// each poll does not flood the UART and the log buffer.
+pub const DEFAULT_REPEAT_WINDOW_MS: u64 = 30_000;
+const MAX_REPEATS: usize = 8;
+const MAX_MESSAGE_LEN: usize = 96;
+
+// FNV-1a hash of a formatted record.
+struct Hasher(u32);
+
+impl Write for Hasher {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0 = (self.0 ^ byte as u32).wrapping_mul(0x01000193);
+        }
+        Ok(())
+    }
+}
+
+// Copies a string into a fixed buffer, cut at a character boundary.
+struct Truncator<'a> {
+    buf:       &'a mut [u8],
+    len:       usize,
+    truncated: bool
+}
+
+impl<'a> Write for Truncator<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let mut bytes = [0; 4];
+            let bytes = c.encode_utf8(&mut bytes).as_bytes();
+            if self.truncated || self.len + bytes.len() > self.buf.len() {
+                self.truncated = true;
+                break
+            }
+            self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+        Ok(())
+    }
+}
+
+// Message logged within the repeat window, and the number of times it was
+// logged again since.
+#[derive(Clone, Copy)]
+struct Repeat {
+    hash:        u32,
+    level:       log::Level,
+    target:      [u8; MAX_MODULE_LEN],
+    target_len:  usize,
+    message:     [u8; MAX_MESSAGE_LEN],
+    message_len: usize,
+    truncated:   bool,
+    since_ms:    u64,
+    count:       u32
+}
+
+impl Repeat {
+    fn new(hash: u32, record: &log::Record, now_ms: u64) -> Repeat {
+        let mut repeat = Repeat {
+            hash:        hash,
+            level:       record.level(),
+            target:      [0; MAX_MODULE_LEN],
+            target_len:  0,
+            message:     [0; MAX_MESSAGE_LEN],
+            message_len: 0,
+            truncated:   false,
+            since_ms:    now_ms,
+            count:       0
+        };
+        {
+            let mut target = Truncator { buf: &mut repeat.target, len: 0, truncated: false };
+            target.write_str(record.target()).unwrap();
+            repeat.target_len = target.len;
+        }
+        {
+            let mut message = Truncator { buf: &mut repeat.message, len: 0, truncated: false };
+            write!(message, "{}", record.args()).unwrap();
+            repeat.message_len = message.len;
+            repeat.truncated = message.truncated;
+        }
+        repeat
+    }
+
+    fn target(&self) -> &str {
+        // Only ever filled by a Truncator.
+        unsafe { core::str::from_utf8_unchecked(&self.target[..self.target_len]) }
+    }
+
+    fn message(&self) -> &str {
+        unsafe { core::str::from_utf8_unchecked(&self.message[..self.message_len]) }
+    }
+}
+
+/// Format of the records in the buffer, and so of the records read over
+/// mgmt. The UART always uses text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `[timestamp] LEVEL(module): message`
+    Text,
+    /// One JSON object per line, with the `level`, `module`, `timestamp`,
+    /// `uptime_us` and `message` fields.
+    Json
+}
+
+impl LogFormat {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LogFormat> {
+        match name {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None
+        }
+    }
+}
+
+/// Destination of the log records. Each has its own level, applied to the
+/// records admitted by the global and per-module levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    Uart,
+    /// The buffer read over mgmt and forwarded to the host.
+    Buffer,
+    /// A remote syslog server (RFC 5424 over UDP).
+    Syslog
+}
+
+impl Sink {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Sink::Uart => "uart",
+            Sink::Buffer => "buffer",
+            Sink::Syslog => "syslog"
+        }
+    }
+
+    pub fn from_index(index: u8) -> Option<Sink> {
+        match index {
+            0 => Some(Sink::Uart),
+            1 => Some(Sink::Buffer),
+            2 => Some(Sink::Syslog),
+            _ => None
+        }
     }
 }
 
 pub struct BufferLogger {
-    buffer:      RefCell<LogBuffer<&'static mut [u8]>>,
-    uart_filter: Cell<LevelFilter>
+    buffer:         RefCell<LogBuffer<&'static mut [u8]>>,
+    capacity:       usize,
+    // Bytes written since the buffer was last cleared.
+    unread:         Cell<usize>,
+    // Number of hosts pulling the log. While there are any, records that do
+    // not fit are dropped instead of overwriting the oldest ones.
+    forwarding:     Cell<usize>,
+    lost:           Cell<u32>,
+    lost_unreported: Cell<u32>,
+    buffer_format:  Cell<LogFormat>,
+    uart_filter:    Cell<LevelFilter>,
+    buffer_filter:  Cell<LevelFilter>,
+    syslog_filter:  Cell<LevelFilter>,
+    // Records formatted for syslog, until they are sent, as in the buffer
+    // while it is pulled.
+    syslog:         RefCell<Option<LogBuffer<&'static mut [u8]>>>,
+    syslog_capacity: Cell<usize>,
+    syslog_unsent:  Cell<usize>,
+    syslog_lost:    Cell<u32>,
+    default_filter: Cell<LevelFilter>,
+    module_filters: RefCell<[Option<ModuleFilter>; MAX_MODULE_FILTERS]>,
+    repeat_window_ms: Cell<u64>,
+    repeats:        RefCell<[Option<Repeat>; MAX_REPEATS]>
 }
 
 static mut LOGGER: *const BufferLogger = 0 as *const _;
@@ -52,8 +254,24 @@ static mut LOGGER: *const BufferLogger = 0 as *const _;
 impl BufferLogger {
     pub fn new(buffer: &'static mut [u8]) -> BufferLogger {
         BufferLogger {
+            capacity: buffer.len(),
             buffer: RefCell::new(LogBuffer::new(buffer)),
+            unread: Cell::new(0),
+            forwarding: Cell::new(0),
+            lost: Cell::new(0),
+            lost_unreported: Cell::new(0),
+            buffer_format: Cell::new(LogFormat::Text),
             uart_filter: Cell::new(LevelFilter::Info),
+            buffer_filter: Cell::new(LevelFilter::Trace),
+            syslog_filter: Cell::new(LevelFilter::Off),
+            syslog: RefCell::new(None),
+            syslog_capacity: Cell::new(0),
+            syslog_unsent: Cell::new(0),
+            syslog_lost: Cell::new(0),
+            default_filter: Cell::new(LevelFilter::Info),
+            module_filters: RefCell::new([None; MAX_MODULE_FILTERS]),
+            repeat_window_ms: Cell::new(DEFAULT_REPEAT_WINDOW_MS),
+            repeats: RefCell::new([None; MAX_REPEATS]),
         }
     }
 
@@ -72,12 +290,44 @@ impl BufferLogger {
     }
 
     pub fn buffer<'a>(&'a self) -> Result<LogBufferRef<'a>, ()> {
+        let unread = &self.unread;
         self.buffer
             .try_borrow_mut()
-            .map(LogBufferRef::new)
+            .map(|buffer| LogBufferRef { buffer, unread })
             .map_err(|_| ())
     }
 
+    /// Marks the start or the end of the pulling of the log by a host, which
+    /// clears the buffer as it sends it.
+    pub fn set_forwarding(&self, forwarding: bool) {
+        let count = self.forwarding.get();
+        self.forwarding.set(if forwarding { count + 1 } else { count.saturating_sub(1) })
+    }
+
+    /// Number of records lost since boot, because the buffer was in use or,
+    /// while the log is pulled, full.
+    pub fn lost_records(&self) -> u32 {
+        self.lost.get()
+    }
+
+    /// Number of records lost since the last call.
+    pub fn take_unreported_lost_records(&self) -> u32 {
+        self.lost_unreported.replace(0)
+    }
+
+    fn record_lost(&self) {
+        self.lost.set(self.lost.get().wrapping_add(1));
+        self.lost_unreported.set(self.lost_unreported.get().saturating_add(1));
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        self.buffer_format.get()
+    }
+
+    pub fn set_log_format(&self, format: LogFormat) {
+        self.buffer_format.set(format)
+    }
+
     pub fn uart_log_level(&self) -> LevelFilter {
         self.uart_filter.get()
     }
@@ -85,34 +335,380 @@ impl BufferLogger {
     pub fn set_uart_log_level(&self, max_level: LevelFilter) {
         self.uart_filter.set(max_level)
     }
-}
 
-// required for impl Log
-unsafe impl Sync for BufferLogger {}
+    fn sink_filter(&self, sink: Sink) -> &Cell<LevelFilter> {
+        match sink {
+            Sink::Uart => &self.uart_filter,
+            Sink::Buffer => &self.buffer_filter,
+            Sink::Syslog => &self.syslog_filter
+        }
+    }
 
-impl Log for BufferLogger {
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
-        true
+    pub fn sink_log_level(&self, sink: Sink) -> LevelFilter {
+        self.sink_filter(sink).get()
     }
 
-    fn log(&self, record: &log::Record) {
-        if self.enabled(record.metadata()) {
-            let timestamp = clock::get_us();
-            let seconds   = timestamp / 1_000_000;
-            let micros    = timestamp % 1_000_000;
+    pub fn set_sink_log_level(&self, sink: Sink, max_level: LevelFilter) {
+        self.sink_filter(sink).set(max_level)
+    }
 
+    /// Enables the syslog sink, with `buffer` holding the records until they
+    /// are sent.
+    pub fn set_syslog_buffer(&self, buffer: &'static mut [u8]) {
+        self.syslog_capacity.set(buffer.len());
+        *self.syslog.borrow_mut() = Some(LogBuffer::new(buffer))
+    }
+
+    /// Records to send to the syslog server, one RFC 5424 message per line
+    /// with `-` in place of the host name. Records that do not fit are
+    /// dropped until the buffer is cleared.
+    pub fn syslog_buffer<'a>(&'a self) -> Result<LogBufferRef<'a>, ()> {
+        let unread = &self.syslog_unsent;
+        let buffer = self.syslog.try_borrow_mut().map_err(|_| ())?;
+        if buffer.is_none() {
+            return Err(())
+        }
+        Ok(LogBufferRef { buffer: RefMut::map(buffer, |buffer| buffer.as_mut().unwrap()), unread })
+    }
+
+    /// Number of records dropped from the syslog sink since boot.
+    pub fn lost_syslog_records(&self) -> u32 {
+        self.syslog_lost.get()
+    }
+
+    /// Log level of modules without a level of their own.
+    pub fn log_level(&self) -> LevelFilter {
+        self.default_filter.get()
+    }
+
+    pub fn set_log_level(&self, max_level: LevelFilter) {
+        self.default_filter.set(max_level);
+        self.update_max_level()
+    }
+
+    /// Sets the log level of `module` and its submodules, e.g. `runtime::session`,
+    /// independently of the global log level. Fails if the module path is too
+    /// long or too many modules have their own level.
+    pub fn set_module_log_level(&self, module: &str, max_level: LevelFilter) -> Result<(), ()> {
+        if module.len() > MAX_MODULE_LEN {
+            return Err(())
+        }
+        {
+            let mut filters = self.module_filters.borrow_mut();
+            let slot = filters.iter()
+                .position(|filter| filter.map_or(false, |f| f.module() == module))
+                .or_else(|| filters.iter().position(|filter| filter.is_none()))
+                .ok_or(())?;
+            let mut filter = ModuleFilter {
+                module: [0; MAX_MODULE_LEN],
+                len:    module.len(),
+                level:  max_level
+            };
+            filter.module[..module.len()].copy_from_slice(module.as_bytes());
+            filters[slot] = Some(filter);
+        }
+        self.update_max_level();
+        Ok(())
+    }
+
+    /// Makes `module` follow the global log level again. Returns false if it
+    /// had no level of its own.
+    pub fn remove_module_log_level(&self, module: &str) -> bool {
+        let mut removed = false;
+        for filter in self.module_filters.borrow_mut().iter_mut() {
+            if filter.map_or(false, |f| f.module() == module) {
+                *filter = None;
+                removed = true;
+            }
+        }
+        self.update_max_level();
+        removed
+    }
+
+    pub fn module_log_levels<F: FnMut(&str, LevelFilter)>(&self, mut f: F) {
+        for filter in self.module_filters.borrow().iter() {
+            if let Some(ref filter) = *filter {
+                f(filter.module(), filter.level)
+            }
+        }
+    }
+
+    fn filter_for(&self, target: &str) -> LevelFilter {
+        let mut best: Option<ModuleFilter> = None;
+        for filter in self.module_filters.borrow().iter() {
+            match *filter {
+                Some(filter) if filter.matches(target) &&
+                        best.map_or(true, |best| filter.len > best.len) =>
+                    best = Some(filter),
+                _ => ()
+            }
+        }
+        best.map_or(self.default_filter.get(), |filter| filter.level)
+    }
+
+    pub fn repeat_window_ms(&self) -> u64 {
+        self.repeat_window_ms.get()
+    }
+
+    /// Sets the window within which identical messages are counted instead
+    /// of logged again; 0 logs them all.
+    pub fn set_repeat_window_ms(&self, window_ms: u64) {
+        self.repeat_window_ms.set(window_ms);
+        self.flush_repeats(window_ms == 0)
+    }
+
+    // Whether `record` repeats a message logged within the repeat window, in
+    // which case it is only counted.
+    fn repeated(&self, record: &log::Record) -> bool {
+        let window_ms = self.repeat_window_ms.get();
+        if window_ms == 0 {
+            return false
+        }
+        let mut repeats = match self.repeats.try_borrow_mut() {
+            Ok(repeats) => repeats,
+            Err(_) => return false
+        };
+        let mut hasher = Hasher(0x811c9dc5);
+        write!(hasher, "{}\0{}", record.target(), record.args()).unwrap();
+        let now_ms = clock::get_ms();
+
+        if let Some(repeat) = repeats.iter_mut().filter_map(|repeat| repeat.as_mut())
+                                     .find(|repeat| repeat.hash == hasher.0 &&
+                                                    repeat.level == record.level()) {
+            if now_ms < repeat.since_ms + window_ms {
+                repeat.count = repeat.count.saturating_add(1);
+                return true
+            }
+            self.write_summary(repeat);
+            repeat.since_ms = now_ms;
+            repeat.count = 0;
+            return false
+        }
+
+        // Replaces the oldest message if there is no room.
+        let slot = match repeats.iter().position(|repeat| repeat.is_none()) {
+            Some(slot) => slot,
+            None => (0..MAX_REPEATS)
+                .min_by_key(|&i| repeats[i].map_or(0, |repeat| repeat.since_ms))
+                .unwrap()
+        };
+        if let Some(ref repeat) = repeats[slot] {
+            self.write_summary(repeat);
+        }
+        repeats[slot] = Some(Repeat::new(hasher.0, record, now_ms));
+        false
+    }
+
+    // Logs the summaries of the repeat windows that ended, or of all of them.
+    fn flush_repeats(&self, all: bool) {
+        let window_ms = self.repeat_window_ms.get();
+        let mut repeats = match self.repeats.try_borrow_mut() {
+            Ok(repeats) => repeats,
+            Err(_) => return
+        };
+        let now_ms = clock::get_ms();
+        for slot in repeats.iter_mut() {
+            let ended = slot.map_or(false, |repeat| all || now_ms >= repeat.since_ms + window_ms);
+            if ended {
+                self.write_summary(slot.as_ref().unwrap());
+                *slot = None;
+            }
+        }
+    }
+
+    fn write_summary(&self, repeat: &Repeat) {
+        if repeat.count == 0 {
+            return
+        }
+        self.write(&log::Record::builder()
+            .args(format_args!("last message repeated {} times: {}{}", repeat.count,
+                               repeat.message(), if repeat.truncated { "..." } else { "" }))
+            .level(repeat.level)
+            .target(repeat.target())
+            .build())
+    }
+
+    fn write(&self, record: &log::Record) {
+        let timestamp = Timestamp::now();
+
+        let format = self.buffer_format.get();
+        if record.level() <= self.buffer_filter.get() {
             if let Ok(mut buffer) = self.buffer.try_borrow_mut() {
-                writeln!(buffer, "[{:6}.{:06}s] {:>5}({}): {}", seconds, micros,
-                         record.level(), record.target(), record.args()).unwrap();
+                let mut length = ByteCounter(0);
+                write_record(&mut length, format, &timestamp, record).unwrap();
+                let unread = self.unread.get() + length.0;
+                if self.forwarding.get() > 0 && unread > self.capacity {
+                    self.record_lost()
+                } else {
+                    write_record(&mut *buffer, format, &timestamp, record).unwrap();
+                    self.unread.set(unread)
+                }
+            } else {
+                self.record_lost()
             }
+        }
+
+        // The network stack logs the sending of the records, which would
+        // then never stop.
+        if record.level() <= self.syslog_filter.get() &&
+                !record.target().starts_with("smoltcp") {
+            if let Ok(mut syslog) = self.syslog.try_borrow_mut() {
+                if let Some(ref mut buffer) = *syslog {
+                    let mut length = ByteCounter(0);
+                    write_syslog(&mut length, &timestamp, record).unwrap();
+                    let unsent = self.syslog_unsent.get() + length.0;
+                    if unsent > self.syslog_capacity.get() {
+                        self.syslog_lost.set(self.syslog_lost.get().wrapping_add(1))
+                    } else {
+                        write_syslog(buffer, &timestamp, record).unwrap();
+                        self.syslog_unsent.set(unsent)
+                    }
+                }
+            } else {
+                self.syslog_lost.set(self.syslog_lost.get().wrapping_add(1))
+            }
+        }
 
-            if record.level() <= self.uart_filter.get() {
-                println!("[{:6}.{:06}s] {:>5}({}): {}", seconds, micros,
-                         record.level(), record.target(), record.args());
+        if record.level() <= self.uart_filter.get() {
+            println!("[{}] {:>5}({}): {}", timestamp,
+                     record.level(), record.target(), record.args());
+        }
+    }
+
+    // The `log` macros skip records above the global maximum before they
+    // reach the logger, so it has to admit the most verbose module.
+    fn update_max_level(&self) {
+        let mut max_level = self.default_filter.get();
+        self.module_log_levels(|_, level| max_level = cmp::max(max_level, level));
+        log::set_max_level(max_level)
+    }
+}
+
+/// Time since boot, or UTC date and time once the wall clock is set.
+struct Timestamp {
+    uptime_us: u64,
+    unix_us:   Option<u64>
+}
+
+impl Timestamp {
+    fn now() -> Timestamp {
+        Timestamp { uptime_us: clock::get_us(), unix_us: clock::get_unix_us() }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.unix_us {
+            None => write!(f, "{:6}.{:06}s", self.uptime_us / 1_000_000,
+                           self.uptime_us % 1_000_000),
+            Some(unix_us) => {
+                let seconds = unix_us / 1_000_000;
+                let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+                write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06}",
+                       year, month, day, seconds / 3600 % 24, seconds / 60 % 60,
+                       seconds % 60, unix_us % 1_000_000)
             }
         }
     }
+}
+
+// Writes a string into a JSON string literal, escaping it.
+struct JsonEscaper<'a, W: Write + 'a>(&'a mut W);
+
+impl<'a, W: Write> Write for JsonEscaper<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"'  => self.0.write_str("\\\""),
+                '\\' => self.0.write_str("\\\\"),
+                '\n' => self.0.write_str("\\n"),
+                '\r' => self.0.write_str("\\r"),
+                '\t' => self.0.write_str("\\t"),
+                c if (c as u32) < 0x20 => write!(self.0, "\\u{:04x}", c as u32),
+                c => self.0.write_char(c)
+            }?
+        }
+        Ok(())
+    }
+}
+
+fn write_record<W: Write>(w: &mut W, format: LogFormat, timestamp: &Timestamp,
+                          record: &log::Record) -> fmt::Result {
+    match format {
+        LogFormat::Text =>
+            writeln!(w, "[{}] {:>5}({}): {}", timestamp,
+                     record.level(), record.target(), record.args()),
+        LogFormat::Json =>
+            write_json(w, timestamp, record)
+    }
+}
+
+fn write_json<W: Write>(w: &mut W, timestamp: &Timestamp, record: &log::Record) -> fmt::Result {
+    write!(w, "{{\"level\":\"{}\",\"module\":\"", record.level())?;
+    JsonEscaper(&mut *w).write_str(record.target())?;
+    write!(w, "\",\"timestamp\":\"{}\",\"uptime_us\":{},\"message\":\"",
+           timestamp, timestamp.uptime_us)?;
+    write!(JsonEscaper(&mut *w), "{}", record.args())?;
+    w.write_str("\"}\n")
+}
+
+// Writes a string, replacing the line breaks that would split a syslog
+// message.
+struct LineJoiner<'a, W: Write + 'a>(&'a mut W);
+
+impl<'a, W: Write> Write for LineJoiner<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '\n' | '\r' => self.0.write_char(' '),
+                c => self.0.write_char(c)
+            }?
+        }
+        Ok(())
+    }
+}
+
+// RFC 5424 message with the user-level facility, and the host name left
+// for the sender to fill in.
+fn write_syslog<W: Write>(w: &mut W, timestamp: &Timestamp, record: &log::Record) -> fmt::Result {
+    let severity = match record.level() {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7
+    };
+    write!(w, "<{}>1 ", 8 + severity)?;
+    match timestamp.unix_us {
+        None => w.write_str("-")?,
+        Some(unix_us) => {
+            let (year, month, day) = civil_from_days((unix_us / 86_400_000_000) as i64);
+            let seconds = unix_us / 1_000_000;
+            write!(w, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+                   year, month, day, seconds / 3600 % 24, seconds / 60 % 60,
+                   seconds % 60, unix_us % 1_000_000)?
+        }
+    }
+    write!(w, " - artiq - - - {}: ", record.target())?;
+    write!(LineJoiner(&mut *w), "{}", record.args())?;
+    w.write_str("\n")
+}
+
+// required for impl Log
+unsafe impl Sync for BufferLogger {}
+
+impl Log for BufferLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.filter_for(metadata.target())
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) && !self.repeated(record) {
+            self.write(record)
+        }
+    }
 
+    /// Logs the summaries of the repeated messages whose window ended.
     fn flush(&self) {
+        self.flush_repeats(false)
     }
 }
@@ -1,4 +1,40 @@
-use io::{Write, ProtoWrite, Error as IoError};
+use io::{Read, ProtoRead, Write, ProtoWrite, Error as IoError};
+
+#[derive(Fail, Debug)]
+pub enum Error<T> {
+    #[fail(display = "unknown packet {:#02x}", _0)]
+    UnknownPacket(u8),
+    #[fail(display = "{}", _0)]
+    Io(#[cause] IoError<T>)
+}
+
+impl<T> From<IoError<T>> for Error<T> {
+    fn from(value: IoError<T>) -> Error<T> {
+        Error::Io(value)
+    }
+}
+
+// Set in the request byte by hosts that can decompress dumps.
+const FLAG_COMPRESSED: u8 = 0x80;
+
+#[derive(Debug)]
+pub enum Request {
+    Dump { compressed: bool },
+    Stream
+}
+
+impl Request {
+    pub fn read_from<R>(reader: &mut R) -> Result<Self, Error<R::ReadError>>
+        where R: Read + ?Sized
+    {
+        let ty = reader.read_u8()?;
+        Ok(match (ty & !FLAG_COMPRESSED, ty & FLAG_COMPRESSED != 0) {
+            (0, compressed) => Request::Dump { compressed: compressed },
+            (1, false) => Request::Stream,
+            _ => return Err(Error::UnknownPacket(ty))
+        })
+    }
+}
 
 #[derive(Debug)]
 pub struct Header {
@@ -21,3 +57,41 @@ impl Header {
         Ok(())
     }
 }
+
+#[derive(Debug)]
+pub struct StreamHeader {
+    pub log_channel: u8,
+    pub dds_onehot_sel: bool
+}
+
+impl StreamHeader {
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        writer.write_u8(self.log_channel)?;
+        writer.write_u8(self.dds_onehot_sel as u8)?;
+        Ok(())
+    }
+}
+
+/// Precedes `sent_bytes` of messages in a stream. `lost_bytes` is the total
+/// size of the messages dropped so far because the host could not keep up.
+/// The messages are followed by a byte that is 0 if they were overwritten
+/// by the analyzer while being sent, in which case the host discards them.
+#[derive(Debug)]
+pub struct StreamChunk {
+    pub sent_bytes: u32,
+    pub lost_bytes: u64,
+    pub overflow_occurred: bool
+}
+
+impl StreamChunk {
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        writer.write_u32(self.sent_bytes)?;
+        writer.write_u64(self.lost_bytes)?;
+        writer.write_u8(self.overflow_occurred as u8)?;
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use core::cmp::min;
 use io::{Read, ProtoRead, Write, ProtoWrite, Error as IoError};
 
 #[derive(Fail, Debug)]
@@ -14,6 +15,98 @@ impl<T> From<IoError<T>> for Error<T> {
     }
 }
 
+/// Maximum number of values read by a batched monitor request.
+pub const MONITOR_BATCH_SIZE: usize = 32;
+
+/// Maximum number of probes a satellite samples for the master. The master
+/// subscribes to them `MONITOR_BATCH_SIZE` at a time with
+/// `MonitorSubscribeRequest`, each request setting the entries from `offset`
+/// on and ending the list after them. The satellite answers the destination
+/// survey with `DestinationOkChangesReply` while some of their values
+/// changed since the master fetched them with `MonitorChangesRequest`; a
+/// newly subscribed entry counts as changed. `entries` in the reply is the
+/// length of the list, for the master to notice a satellite that lost it.
+pub const MONITOR_SUBSCRIPTION_SIZE: usize = 256;
+
+/// Identification items of a satellite, read 32 bytes at a time with
+/// `IdentRequest`. The serial number is the EUI-48 of the board, if any,
+/// and the topology the RTIO topology of its gateware, if known.
+pub const IDENT_FIRMWARE: u8 = 0;
+pub const IDENT_GATEWARE: u8 = 1;
+pub const IDENT_SERIAL: u8 = 2;
+pub const IDENT_TOPOLOGY: u8 = 3;
+
+/// Clocking chips of a satellite, whose registers are read
+/// `CLOCK_REGISTERS_SIZE` at a time with `ClockRegistersRequest`.
+pub const CLOCK_CHIP_SI5324: u8 = 0;
+pub const CLOCK_CHIP_SI549_MAIN: u8 = 1;
+pub const CLOCK_CHIP_SI549_HELPER: u8 = 2;
+pub const CLOCK_REGISTERS_SIZE: usize = 16;
+
+/// Maximum number of image bytes carried by a `FirmwareUpdateDataRequest`,
+/// as sixteen rows of 32 bytes. Only the first `length` bytes are sent.
+pub const FIRMWARE_CHUNK_SIZE: usize = 512;
+/// Maximum number of log bytes carried by a `LogReply`, laid out the same way.
+/// Chunks are numbered, and the satellite answers with the same chunk until
+/// the master acknowledges its sequence number in a `LogRequest`, so that a
+/// lost reply does not lose log messages.
+pub const LOG_CHUNK_SIZE: usize = 512;
+
+/// Messages larger than a packet are split into segments of up to
+/// `SEGMENT_SIZE` bytes, laid out the same way, which are sent in order with
+/// `SegmentRequest` and each acknowledged with `SegmentReply`. Each segment
+/// carries its sequence number in the message and the CRC-32 of its data,
+/// which the destination checks after the packet went through any
+/// repeaters. The destination reassembles up to `MAX_SEGMENTED_SIZE` bytes
+/// before handling the message according to its kind. `transfer` identifies
+/// the message, so that a segment sent again after a lost reply is
+/// recognized.
+pub const SEGMENT_SIZE: usize = 512;
+pub const MAX_SEGMENTED_SIZE: usize = 8192;
+
+/// Kinds of segmented messages. A firmware block holds the offset of its
+/// first byte in the image (u32) followed by the image data.
+pub const SEGMENTED_FIRMWARE_BLOCK: u8 = 0;
+
+/// Status of a segment in `SegmentReply`. The status of the last segment
+/// is that of the handling of the whole message.
+pub const SEGMENT_OK: u8 = 0;
+pub const SEGMENT_CORRUPTED: u8 = 1;
+pub const SEGMENT_OUT_OF_ORDER: u8 = 2;
+pub const SEGMENT_TOO_LARGE: u8 = 3;
+pub const SEGMENT_REJECTED: u8 = 4;
+
+fn read_chunk<R>(reader: &mut R, length: u16) -> Result<[[u8; 32]; 16], IoError<R::ReadError>>
+    where R: Read + ?Sized
+{
+    let mut data = [[0; 32]; 16];
+    let mut remaining = length as usize;
+    for row in data.iter_mut() {
+        if remaining == 0 {
+            break
+        }
+        let size = min(remaining, row.len());
+        reader.read_exact(&mut row[..size])?;
+        remaining -= size;
+    }
+    Ok(data)
+}
+
+fn write_chunk<W>(writer: &mut W, length: u16, data: &[[u8; 32]; 16]) -> Result<(), IoError<W::WriteError>>
+    where W: Write + ?Sized
+{
+    let mut remaining = length as usize;
+    for row in data.iter() {
+        if remaining == 0 {
+            break
+        }
+        let size = min(remaining, row.len());
+        writer.write_all(&row[..size])?;
+        remaining -= size;
+    }
+    Ok(())
+}
+
 #[derive(PartialEq, Debug)]
 pub enum Packet {
     EchoRequest,
@@ -28,6 +121,7 @@ pub enum Packet {
     DestinationSequenceErrorReply { channel: u16 },
     DestinationCollisionReply { channel: u16 },
     DestinationBusyReply { channel: u16 },
+    DestinationOkChangesReply,
 
     RoutingSetPath { destination: u8, hops: [u8; 32] },
     RoutingSetRank { rank: u8 },
@@ -38,6 +132,13 @@ pub enum Packet {
     InjectionRequest { destination: u8, channel: u16, overrd: u8, value: u8 },
     InjectionStatusRequest { destination: u8, channel: u16, overrd: u8 },
     InjectionStatusReply { value: u8 },
+    MonitorBatchRequest { destination: u8, count: u8, channels: [u16; 32], probes: [u8; 32] },
+    InjectionStatusBatchRequest { destination: u8, count: u8, channels: [u16; 32], overrds: [u8; 32] },
+    MonitorBatchReply { count: u8, values: [u32; 32] },
+    MonitorSubscribeRequest { destination: u8, offset: u16, count: u8, channels: [u16; 32], probes: [u8; 32] },
+    MonitorSubscribeReply { succeeded: bool },
+    MonitorChangesRequest { destination: u8 },
+    MonitorChangesReply { entries: u16, more: bool, count: u8, indices: [u16; 32], values: [u32; 32] },
 
     I2cStartRequest { destination: u8, busno: u8 },
     I2cRestartRequest { destination: u8, busno: u8 },
@@ -56,6 +157,55 @@ pub enum Packet {
 
     JdacBasicRequest { destination: u8, dacno: u8, reqno: u8, param: u8 },
     JdacBasicReply { succeeded: bool, retval: u8 },
+
+    Si549TrimRequest { destination: u8, ppb: i32 },
+    Si549TrimReply { succeeded: bool },
+    WrpllStatsRequest { destination: u8 },
+    WrpllStatsReply {
+        available: bool, locked: bool, lock_time_ms: u32, unlock_count: u32,
+        samples: u32, helper_rms_mtags: u32, main_rms_mtags: u32
+    },
+
+    IdentRequest { destination: u8, item: u8, offset: u8 },
+    IdentReply { length: u8, data: [u8; 32] },
+
+    FirmwareUpdateStartRequest { destination: u8, length: u32 },
+    FirmwareUpdateDataRequest { destination: u8, offset: u32, length: u16, data: [[u8; 32]; 16] },
+    FirmwareUpdateFinishRequest { destination: u8 },
+    FirmwareUpdateReply { succeeded: bool },
+    RebootRequest { destination: u8 },
+
+    LogRequest { destination: u8, acknowledged: bool, sequence: u16 },
+    LogReply { sequence: u16, more: bool, length: u16, data: [[u8; 32]; 16] },
+
+    TscStatsRequest { destination: u8 },
+    TscStatsReply { syncs: u32, offset: i32, max_offset: u32, interval_ms: u32, phase_fs: Option<i32> },
+
+    SfpStatusRequest { destination: u8, port: u8 },
+    SfpStatusReply {
+        count: u8, present: bool, diagnostics: bool,
+        temperature: i16, voltage: u16, tx_bias: u16, tx_power: u16, rx_power: u16,
+        alarms: u16
+    },
+
+    RtioCountersRequest { destination: u8, clear: bool },
+    RtioCountersReply {
+        underflows: u32, overflows: u32, buffer_space_timeouts: u32,
+        protocol_errors: u32, tsc_resyncs: u32
+    },
+
+    ClockRegistersRequest { destination: u8, chip: u8, offset: u8 },
+    // Bit i of `configuration` is set if register i is part of the profile.
+    ClockRegistersReply {
+        available: bool, count: u8, configuration: u16,
+        registers: [u8; CLOCK_REGISTERS_SIZE], values: [u8; CLOCK_REGISTERS_SIZE]
+    },
+
+    SegmentRequest {
+        destination: u8, kind: u8, transfer: u8, seq: u16, last: bool, crc: u32,
+        length: u16, data: [[u8; 32]; 16]
+    },
+    SegmentReply { seq: u16, status: u8 },
 }
 
 impl Packet {
@@ -83,6 +233,7 @@ impl Packet {
             0x25 => Packet::DestinationBusyReply {
                 channel: reader.read_u16()?
             },
+            0x26 => Packet::DestinationOkChangesReply,
 
             0x30 => {
                 let destination = reader.read_u8()?;
@@ -120,6 +271,86 @@ impl Packet {
             0x52 => Packet::InjectionStatusReply {
                 value: reader.read_u8()?
             },
+            ty @ 0x42 | ty @ 0x53 => {
+                let destination = reader.read_u8()?;
+                let count = reader.read_u8()?;
+                let mut channels = [0; 32];
+                for channel in channels.iter_mut() {
+                    *channel = reader.read_u16()?;
+                }
+                let mut selectors = [0; 32];
+                reader.read_exact(&mut selectors)?;
+                if ty == 0x42 {
+                    Packet::MonitorBatchRequest {
+                        destination: destination,
+                        count: count,
+                        channels: channels,
+                        probes: selectors
+                    }
+                } else {
+                    Packet::InjectionStatusBatchRequest {
+                        destination: destination,
+                        count: count,
+                        channels: channels,
+                        overrds: selectors
+                    }
+                }
+            },
+            0x43 => {
+                let count = reader.read_u8()?;
+                let mut values = [0; 32];
+                for value in values.iter_mut() {
+                    *value = reader.read_u32()?;
+                }
+                Packet::MonitorBatchReply {
+                    count: count,
+                    values: values
+                }
+            },
+            0x44 => {
+                let destination = reader.read_u8()?;
+                let offset = reader.read_u16()?;
+                let count = reader.read_u8()?;
+                let mut channels = [0; 32];
+                for channel in channels.iter_mut() {
+                    *channel = reader.read_u16()?;
+                }
+                let mut probes = [0; 32];
+                reader.read_exact(&mut probes)?;
+                Packet::MonitorSubscribeRequest {
+                    destination: destination,
+                    offset: offset,
+                    count: count,
+                    channels: channels,
+                    probes: probes
+                }
+            },
+            0x45 => Packet::MonitorSubscribeReply {
+                succeeded: reader.read_bool()?
+            },
+            0x46 => Packet::MonitorChangesRequest {
+                destination: reader.read_u8()?
+            },
+            0x47 => {
+                let entries = reader.read_u16()?;
+                let more = reader.read_bool()?;
+                let count = reader.read_u8()?;
+                let mut indices = [0; 32];
+                for index in indices.iter_mut() {
+                    *index = reader.read_u16()?;
+                }
+                let mut values = [0; 32];
+                for value in values.iter_mut() {
+                    *value = reader.read_u32()?;
+                }
+                Packet::MonitorChangesReply {
+                    entries: entries,
+                    more: more,
+                    count: count,
+                    indices: indices,
+                    values: values
+                }
+            },
 
             0x80 => Packet::I2cStartRequest {
                 destination: reader.read_u8()?,
@@ -192,6 +423,175 @@ impl Packet {
                 retval: reader.read_u8()?
             },
 
+            0xb0 => Packet::Si549TrimRequest {
+                destination: reader.read_u8()?,
+                ppb: reader.read_u32()? as i32
+            },
+            0xb1 => Packet::Si549TrimReply {
+                succeeded: reader.read_bool()?
+            },
+            0xb2 => Packet::WrpllStatsRequest {
+                destination: reader.read_u8()?
+            },
+            0xb3 => Packet::WrpllStatsReply {
+                available: reader.read_bool()?,
+                locked: reader.read_bool()?,
+                lock_time_ms: reader.read_u32()?,
+                unlock_count: reader.read_u32()?,
+                samples: reader.read_u32()?,
+                helper_rms_mtags: reader.read_u32()?,
+                main_rms_mtags: reader.read_u32()?
+            },
+
+            0xc0 => Packet::IdentRequest {
+                destination: reader.read_u8()?,
+                item: reader.read_u8()?,
+                offset: reader.read_u8()?
+            },
+            0xc1 => {
+                let length = reader.read_u8()?;
+                let mut data = [0; 32];
+                reader.read_exact(&mut data)?;
+                Packet::IdentReply {
+                    length: length,
+                    data: data
+                }
+            },
+
+            0xd0 => Packet::FirmwareUpdateStartRequest {
+                destination: reader.read_u8()?,
+                length: reader.read_u32()?
+            },
+            0xd1 => {
+                let destination = reader.read_u8()?;
+                let offset = reader.read_u32()?;
+                let length = reader.read_u16()?;
+                let data = read_chunk(reader, length)?;
+                Packet::FirmwareUpdateDataRequest {
+                    destination: destination,
+                    offset: offset,
+                    length: length,
+                    data: data
+                }
+            },
+            0xd2 => Packet::FirmwareUpdateFinishRequest {
+                destination: reader.read_u8()?
+            },
+            0xd3 => Packet::FirmwareUpdateReply {
+                succeeded: reader.read_bool()?
+            },
+            0xd4 => Packet::RebootRequest {
+                destination: reader.read_u8()?
+            },
+
+            0xd8 => Packet::LogRequest {
+                destination: reader.read_u8()?,
+                acknowledged: reader.read_bool()?,
+                sequence: reader.read_u16()?
+            },
+            0xd9 => {
+                let sequence = reader.read_u16()?;
+                let more = reader.read_bool()?;
+                let length = reader.read_u16()?;
+                let data = read_chunk(reader, length)?;
+                Packet::LogReply {
+                    sequence: sequence,
+                    more: more,
+                    length: length,
+                    data: data
+                }
+            },
+
+            0xda => Packet::TscStatsRequest {
+                destination: reader.read_u8()?
+            },
+            0xdb => Packet::TscStatsReply {
+                syncs: reader.read_u32()?,
+                offset: reader.read_u32()? as i32,
+                max_offset: reader.read_u32()?,
+                interval_ms: reader.read_u32()?,
+                phase_fs: {
+                    let available = reader.read_bool()?;
+                    let phase_fs = reader.read_u32()? as i32;
+                    if available { Some(phase_fs) } else { None }
+                }
+            },
+
+            0xdc => Packet::SfpStatusRequest {
+                destination: reader.read_u8()?,
+                port: reader.read_u8()?
+            },
+            0xdd => Packet::SfpStatusReply {
+                count: reader.read_u8()?,
+                present: reader.read_bool()?,
+                diagnostics: reader.read_bool()?,
+                temperature: reader.read_u16()? as i16,
+                voltage: reader.read_u16()?,
+                tx_bias: reader.read_u16()?,
+                tx_power: reader.read_u16()?,
+                rx_power: reader.read_u16()?,
+                alarms: reader.read_u16()?
+            },
+
+            0xde => Packet::RtioCountersRequest {
+                destination: reader.read_u8()?,
+                clear: reader.read_bool()?
+            },
+            0xdf => Packet::RtioCountersReply {
+                underflows: reader.read_u32()?,
+                overflows: reader.read_u32()?,
+                buffer_space_timeouts: reader.read_u32()?,
+                protocol_errors: reader.read_u32()?,
+                tsc_resyncs: reader.read_u32()?
+            },
+
+            0xe0 => Packet::ClockRegistersRequest {
+                destination: reader.read_u8()?,
+                chip: reader.read_u8()?,
+                offset: reader.read_u8()?
+            },
+            0xe1 => {
+                let available = reader.read_bool()?;
+                let count = reader.read_u8()?;
+                let configuration = reader.read_u16()?;
+                let mut registers = [0; CLOCK_REGISTERS_SIZE];
+                reader.read_exact(&mut registers)?;
+                let mut values = [0; CLOCK_REGISTERS_SIZE];
+                reader.read_exact(&mut values)?;
+                Packet::ClockRegistersReply {
+                    available: available,
+                    count: count,
+                    configuration: configuration,
+                    registers: registers,
+                    values: values
+                }
+            },
+
+            0xe2 => {
+                let destination = reader.read_u8()?;
+                let kind = reader.read_u8()?;
+                let transfer = reader.read_u8()?;
+                let seq = reader.read_u16()?;
+                let last = reader.read_bool()?;
+                let crc = reader.read_u32()?;
+                let length = reader.read_u16()?;
+                let data = read_chunk(reader, length)?;
+                Packet::SegmentRequest {
+                    destination: destination,
+                    kind: kind,
+                    transfer: transfer,
+                    seq: seq,
+                    last: last,
+                    crc: crc,
+                    length: length,
+                    data: data
+                }
+            },
+            0xe3 => Packet::SegmentReply {
+                seq: reader.read_u16()?,
+                status: reader.read_u8()?
+            },
+
             ty => return Err(Error::UnknownPacket(ty))
         })
     }
@@ -231,6 +631,8 @@ impl Packet {
                 writer.write_u8(0x25)?;
                 writer.write_u16(channel)?;
             },
+            Packet::DestinationOkChangesReply =>
+                writer.write_u8(0x26)?,
 
             Packet::RoutingSetPath { destination, hops } => {
                 writer.write_u8(0x30)?;
@@ -271,6 +673,61 @@ impl Packet {
                 writer.write_u8(0x52)?;
                 writer.write_u8(value)?;
             },
+            Packet::MonitorBatchRequest { destination, count, channels, probes } => {
+                writer.write_u8(0x42)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(count)?;
+                for &channel in channels.iter() {
+                    writer.write_u16(channel)?;
+                }
+                writer.write_all(&probes)?;
+            },
+            Packet::InjectionStatusBatchRequest { destination, count, channels, overrds } => {
+                writer.write_u8(0x53)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(count)?;
+                for &channel in channels.iter() {
+                    writer.write_u16(channel)?;
+                }
+                writer.write_all(&overrds)?;
+            },
+            Packet::MonitorBatchReply { count, values } => {
+                writer.write_u8(0x43)?;
+                writer.write_u8(count)?;
+                for &value in values.iter() {
+                    writer.write_u32(value)?;
+                }
+            },
+            Packet::MonitorSubscribeRequest { destination, offset, count, channels, probes } => {
+                writer.write_u8(0x44)?;
+                writer.write_u8(destination)?;
+                writer.write_u16(offset)?;
+                writer.write_u8(count)?;
+                for &channel in channels.iter() {
+                    writer.write_u16(channel)?;
+                }
+                writer.write_all(&probes)?;
+            },
+            Packet::MonitorSubscribeReply { succeeded } => {
+                writer.write_u8(0x45)?;
+                writer.write_bool(succeeded)?;
+            },
+            Packet::MonitorChangesRequest { destination } => {
+                writer.write_u8(0x46)?;
+                writer.write_u8(destination)?;
+            },
+            Packet::MonitorChangesReply { entries, more, count, indices, values } => {
+                writer.write_u8(0x47)?;
+                writer.write_u16(entries)?;
+                writer.write_bool(more)?;
+                writer.write_u8(count)?;
+                for &index in indices.iter() {
+                    writer.write_u16(index)?;
+                }
+                for &value in values.iter() {
+                    writer.write_u32(value)?;
+                }
+            },
 
             Packet::I2cStartRequest { destination, busno } => {
                 writer.write_u8(0x80)?;
@@ -356,6 +813,162 @@ impl Packet {
                 writer.write_bool(succeeded)?;
                 writer.write_u8(retval)?;
             },
+
+            Packet::Si549TrimRequest { destination, ppb } => {
+                writer.write_u8(0xb0)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(ppb as u32)?;
+            },
+            Packet::Si549TrimReply { succeeded } => {
+                writer.write_u8(0xb1)?;
+                writer.write_bool(succeeded)?;
+            },
+            Packet::WrpllStatsRequest { destination } => {
+                writer.write_u8(0xb2)?;
+                writer.write_u8(destination)?;
+            },
+            Packet::WrpllStatsReply { available, locked, lock_time_ms, unlock_count,
+                                      samples, helper_rms_mtags, main_rms_mtags } => {
+                writer.write_u8(0xb3)?;
+                writer.write_bool(available)?;
+                writer.write_bool(locked)?;
+                writer.write_u32(lock_time_ms)?;
+                writer.write_u32(unlock_count)?;
+                writer.write_u32(samples)?;
+                writer.write_u32(helper_rms_mtags)?;
+                writer.write_u32(main_rms_mtags)?;
+            },
+
+            Packet::IdentRequest { destination, item, offset } => {
+                writer.write_u8(0xc0)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(item)?;
+                writer.write_u8(offset)?;
+            },
+            Packet::IdentReply { length, data } => {
+                writer.write_u8(0xc1)?;
+                writer.write_u8(length)?;
+                writer.write_all(&data)?;
+            },
+
+            Packet::FirmwareUpdateStartRequest { destination, length } => {
+                writer.write_u8(0xd0)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(length)?;
+            },
+            Packet::FirmwareUpdateDataRequest { destination, offset, length, data } => {
+                writer.write_u8(0xd1)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(offset)?;
+                writer.write_u16(length)?;
+                write_chunk(writer, length, &data)?;
+            },
+            Packet::FirmwareUpdateFinishRequest { destination } => {
+                writer.write_u8(0xd2)?;
+                writer.write_u8(destination)?;
+            },
+            Packet::FirmwareUpdateReply { succeeded } => {
+                writer.write_u8(0xd3)?;
+                writer.write_bool(succeeded)?;
+            },
+            Packet::RebootRequest { destination } => {
+                writer.write_u8(0xd4)?;
+                writer.write_u8(destination)?;
+            },
+
+            Packet::LogRequest { destination, acknowledged, sequence } => {
+                writer.write_u8(0xd8)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(acknowledged)?;
+                writer.write_u16(sequence)?;
+            },
+            Packet::LogReply { sequence, more, length, data } => {
+                writer.write_u8(0xd9)?;
+                writer.write_u16(sequence)?;
+                writer.write_bool(more)?;
+                writer.write_u16(length)?;
+                write_chunk(writer, length, &data)?;
+            },
+
+            Packet::TscStatsRequest { destination } => {
+                writer.write_u8(0xda)?;
+                writer.write_u8(destination)?;
+            },
+            Packet::TscStatsReply { syncs, offset, max_offset, interval_ms, phase_fs } => {
+                writer.write_u8(0xdb)?;
+                writer.write_u32(syncs)?;
+                writer.write_u32(offset as u32)?;
+                writer.write_u32(max_offset)?;
+                writer.write_u32(interval_ms)?;
+                writer.write_bool(phase_fs.is_some())?;
+                writer.write_u32(phase_fs.unwrap_or(0) as u32)?;
+            },
+
+            Packet::SfpStatusRequest { destination, port } => {
+                writer.write_u8(0xdc)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(port)?;
+            },
+            Packet::SfpStatusReply { count, present, diagnostics, temperature, voltage,
+                                     tx_bias, tx_power, rx_power, alarms } => {
+                writer.write_u8(0xdd)?;
+                writer.write_u8(count)?;
+                writer.write_bool(present)?;
+                writer.write_bool(diagnostics)?;
+                writer.write_u16(temperature as u16)?;
+                writer.write_u16(voltage)?;
+                writer.write_u16(tx_bias)?;
+                writer.write_u16(tx_power)?;
+                writer.write_u16(rx_power)?;
+                writer.write_u16(alarms)?;
+            },
+
+            Packet::RtioCountersRequest { destination, clear } => {
+                writer.write_u8(0xde)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(clear)?;
+            },
+            Packet::RtioCountersReply { underflows, overflows, buffer_space_timeouts,
+                                        protocol_errors, tsc_resyncs } => {
+                writer.write_u8(0xdf)?;
+                writer.write_u32(underflows)?;
+                writer.write_u32(overflows)?;
+                writer.write_u32(buffer_space_timeouts)?;
+                writer.write_u32(protocol_errors)?;
+                writer.write_u32(tsc_resyncs)?;
+            },
+
+            Packet::ClockRegistersRequest { destination, chip, offset } => {
+                writer.write_u8(0xe0)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(chip)?;
+                writer.write_u8(offset)?;
+            },
+            Packet::ClockRegistersReply { available, count, configuration, registers, values } => {
+                writer.write_u8(0xe1)?;
+                writer.write_bool(available)?;
+                writer.write_u8(count)?;
+                writer.write_u16(configuration)?;
+                writer.write_all(&registers)?;
+                writer.write_all(&values)?;
+            },
+
+            Packet::SegmentRequest { destination, kind, transfer, seq, last, crc, length, data } => {
+                writer.write_u8(0xe2)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(kind)?;
+                writer.write_u8(transfer)?;
+                writer.write_u16(seq)?;
+                writer.write_bool(last)?;
+                writer.write_u32(crc)?;
+                writer.write_u16(length)?;
+                write_chunk(writer, length, &data)?;
+            },
+            Packet::SegmentReply { seq, status } => {
+                writer.write_u8(0xe3)?;
+                writer.write_u16(seq)?;
+                writer.write_u8(status)?;
+            },
         }
         Ok(())
     }
@@ -0,0 +1,216 @@
+use alloc::Vec;
+use byteorder::{ByteOrder, NetworkEndian};
+use mgmt_proto::{HealthSnapshot, FpgaSensors, SfpDiagnostics, DrtioLinkStats, PowerRail,
+                 SatelliteSfp};
+
+// Health and diagnostic records in a self-describing binary format, sent
+// over mgmt and along with alerts. Hosts decode the records and fields they
+// do not know from their type, so that records and fields can be added
+// without breaking older hosts.
+//
+// A record set is the version of the format, then records up to its end.
+// A record is its kind, the index of the instance (e.g. the SFP port), the
+// length of its fields (u16), then the fields. A field is its tag within
+// the kind, its type, a decimal exponent and the length of its value, then
+// the value in network byte order. Numbers are integers in SI units
+// (degrees Celsius for temperatures), to be multiplied by 10**exponent.
+// Fields whose value is not known are left out. The version only changes
+// with the framing; new kinds and tags do not change it.
+
+pub const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    System       = 1,
+    Heap         = 2,
+    Fpga         = 3,
+    Sfp          = 4,
+    DrtioLink    = 5,
+    PowerRail    = 6,
+    SatelliteSfp = 7
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Bool     = 0,
+    Unsigned = 1,
+    Signed   = 2,
+    String   = 3
+}
+
+struct Record<'a> {
+    data: &'a mut Vec<u8>
+}
+
+impl<'a> Record<'a> {
+    fn field(&mut self, tag: u8, ty: Type, exponent: i8, value: &[u8]) {
+        self.data.extend_from_slice(&[tag, ty as u8, exponent as u8, value.len() as u8]);
+        self.data.extend_from_slice(value);
+    }
+
+    fn boolean(&mut self, tag: u8, value: bool) {
+        self.field(tag, Type::Bool, 0, &[value as u8])
+    }
+
+    fn unsigned(&mut self, tag: u8, exponent: i8, value: u64) {
+        let mut bytes = [0; 8];
+        NetworkEndian::write_u64(&mut bytes, value);
+        let start = if value >> 32 == 0 { 4 } else { 0 };
+        self.field(tag, Type::Unsigned, exponent, &bytes[start..])
+    }
+
+    fn signed(&mut self, tag: u8, exponent: i8, value: i64) {
+        let mut bytes = [0; 8];
+        NetworkEndian::write_i64(&mut bytes, value);
+        let start = if value as i32 as i64 == value { 4 } else { 0 };
+        self.field(tag, Type::Signed, exponent, &bytes[start..])
+    }
+
+    fn string(&mut self, tag: u8, value: &str) {
+        let mut length = value.len().min(255);
+        while !value.is_char_boundary(length) {
+            length -= 1
+        }
+        self.field(tag, Type::String, 0, &value.as_bytes()[..length])
+    }
+
+    // Fields 1 to 6 of SFP and satellite SFP records.
+    fn sfp(&mut self, diagnostics: &SfpDiagnostics) {
+        // SFF-8472 units: 1/256 degC, 100 uV, 2 uA and 0.1 uW.
+        self.signed(1, -3, diagnostics.temperature as i64 * 1000 / 256);
+        self.unsigned(2, -4, diagnostics.voltage as u64);
+        self.unsigned(3, -6, diagnostics.tx_bias as u64 * 2);
+        self.unsigned(4, -7, diagnostics.tx_power as u64);
+        self.unsigned(5, -7, diagnostics.rx_power as u64);
+        self.unsigned(6, 0, diagnostics.alarms as u64);
+    }
+}
+
+pub struct RecordSet {
+    data: Vec<u8>
+}
+
+impl RecordSet {
+    pub fn new() -> RecordSet {
+        let mut data = Vec::new();
+        data.push(VERSION);
+        RecordSet { data: data }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    fn record<F: FnOnce(&mut Record)>(&mut self, kind: Kind, index: u8, f: F) {
+        let start = self.data.len();
+        self.data.extend_from_slice(&[kind as u8, index, 0, 0]);
+        f(&mut Record { data: &mut self.data });
+        let length = self.data.len() - start - 4;
+        NetworkEndian::write_u16(&mut self.data[start + 2..start + 4], length as u16);
+    }
+
+    /// Uptime, wall clock time, boots, versions, RTIO clock.
+    pub fn system(&mut self, health: &HealthSnapshot) {
+        self.record(Kind::System, 0, |record| {
+            record.unsigned(1, -3, health.uptime_ms);
+            if let Some(unix_time_us) = health.unix_time_us {
+                record.unsigned(2, -6, unix_time_us);
+            }
+            if let Some((boot_count, uptime_s)) = health.boot_stats {
+                record.unsigned(3, 0, boot_count as u64);
+                record.unsigned(4, 0, uptime_s as u64);
+            }
+            record.string(5, health.firmware_version);
+            record.string(6, health.gateware_version);
+            record.boolean(7, health.rtio_clock_ok);
+            record.unsigned(8, 0, health.rtio_frequency as u64);
+        })
+    }
+
+    /// Total, used and largest free bytes of the heap.
+    pub fn heap(&mut self, total: u32, used: u32, largest_free: u32) {
+        self.record(Kind::Heap, 0, |record| {
+            record.unsigned(1, 0, total as u64);
+            record.unsigned(2, 0, used as u64);
+            record.unsigned(3, 0, largest_free as u64);
+        })
+    }
+
+    /// Die temperature and VCCINT, VCCAUX and VCCBRAM supplies.
+    pub fn fpga(&mut self, sensors: &FpgaSensors) {
+        // XADC codes are 12-bit; supplies are measured on a 3V scale.
+        self.record(Kind::Fpga, 0, |record| {
+            record.signed(1, -3, sensors.temperature as i64 * 503_975 / 4096 - 273_150);
+            record.unsigned(2, -3, sensors.vccint as u64 * 3000 / 4096);
+            record.unsigned(3, -3, sensors.vccaux as u64 * 3000 / 4096);
+            record.unsigned(4, -3, sensors.vccbram as u64 * 3000 / 4096);
+        })
+    }
+
+    /// Temperature, supply voltage, TX bias, TX and RX power and alarms.
+    pub fn sfp(&mut self, port: u8, diagnostics: &SfpDiagnostics) {
+        self.record(Kind::Sfp, port, |record| record.sfp(diagnostics))
+    }
+
+    /// Whether the link is up, and its error counts.
+    pub fn drtio_link(&mut self, linkno: u8, link: &DrtioLinkStats) {
+        self.record(Kind::DrtioLink, linkno, |record| {
+            record.boolean(1, link.up);
+            record.unsigned(2, 0, link.crc_errors as u64);
+            record.unsigned(3, 0, link.aux_timeouts as u64);
+            record.unsigned(4, 0, link.link_drops as u64);
+            record.unsigned(5, 0, link.protocol_errors as u64);
+            record.unsigned(6, 0, link.remote_rtio_errors as u64);
+        })
+    }
+
+    /// Name, voltage and current, lowest voltage and brownouts.
+    pub fn power_rail(&mut self, index: u8, rail: &PowerRail) {
+        self.record(Kind::PowerRail, index, |record| {
+            record.string(1, &rail.name);
+            if let Some((voltage_mv, current_ua)) = rail.reading {
+                record.unsigned(2, -3, voltage_mv as u64);
+                record.signed(3, -6, current_ua as i64);
+            }
+            record.unsigned(4, -3, rail.min_voltage_mv as u64);
+            record.unsigned(5, 0, rail.brownouts as u64);
+        })
+    }
+
+    /// The fields of SFP records, and the port, indexed by destination.
+    pub fn satellite_sfp(&mut self, sfp: &SatelliteSfp) {
+        if let Some(ref diagnostics) = sfp.diagnostics {
+            self.record(Kind::SatelliteSfp, sfp.destination, |record| {
+                record.sfp(diagnostics);
+                record.unsigned(7, 0, sfp.port as u64);
+            })
+        }
+    }
+
+    /// All the records of a health snapshot.
+    pub fn snapshot(&mut self, health: &HealthSnapshot) {
+        self.system(health);
+        self.heap(health.heap_total, health.heap_used, health.heap_largest_free);
+        if let Some(ref fpga) = health.fpga {
+            self.fpga(fpga);
+        }
+        for (port, sfp) in health.sfp.iter().enumerate() {
+            if let Some(ref diagnostics) = *sfp {
+                self.sfp(port as u8, diagnostics);
+            }
+        }
+        for (linkno, link) in health.drtio_links.iter().enumerate() {
+            self.drtio_link(linkno as u8, link);
+        }
+        for (index, rail) in health.power.iter().enumerate() {
+            self.power_rail(index as u8, rail);
+        }
+        for sfp in health.satellite_sfp {
+            self.satellite_sfp(sfp);
+        }
+    }
+}
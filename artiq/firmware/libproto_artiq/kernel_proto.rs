@@ -6,7 +6,7 @@ pub const KERNELCPU_PAYLOAD_ADDRESS: usize = 0x45060000;
 pub const KERNELCPU_LAST_ADDRESS:    usize = 0x4fffffff;
 pub const KSUPPORT_HEADER_SIZE:      usize = 0x80;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct Exception<'a> {
     pub name:     &'a str,
     pub file:     &'a str,
@@ -17,18 +17,36 @@ pub struct Exception<'a> {
     pub param:    [i64; 3]
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum DmaSegmentError {
+    TraceNotFound,
+    MarkerNotFound,
+    // The offset is not at the start of a record of the trace.
+    InvalidOffset,
+    // The segment extends past the end of the trace.
+    InvalidDuration
+}
+
 #[derive(Debug)]
 pub enum Message<'a> {
-    LoadRequest(&'a [u8]),
-    LoadReply(Result<(), dyld::Error<'a>>),
+    // `relocated` is the start of an image where `library` was loaded before,
+    // see dyld::Library::load_relocated.
+    LoadRequest { library: &'a [u8], relocated: Option<&'a [u8]> },
+    // The length of the part of the image written by the load.
+    LoadReply(Result<usize, dyld::Error<'a>>),
 
     RtioInitRequest,
 
     RtioDestinationStatusRequest { destination: u8 },
-    RtioDestinationStatusReply { up: bool },
+    /// `age_ms`: time since the destination was last reported up.
+    RtioDestinationStatusReply { up: bool, age_ms: u64 },
 
     DmaRecordStart(&'a str),
     DmaRecordAppend(&'a [u8]),
+    DmaRecordMark {
+        name:      &'a str,
+        timestamp: u64
+    },
     DmaRecordStop {
         duration:  u64
     },
@@ -45,12 +63,41 @@ pub enum Message<'a> {
         duration: u64
     },
 
+    DmaSegmentRequest {
+        name:     &'a str,
+        marker:   &'a str,
+        offset:   usize,
+        duration: i64
+    },
+    DmaSegmentReply(Result<(&'a [u8], u64), DmaSegmentError>),
+
+    DmaChainRequest {
+        name:  &'a str,
+        trace: &'a str,
+        first: bool
+    },
+    DmaChainReply {
+        found: bool
+    },
+
     RunFinished,
     RunException {
         exception: Exception<'a>,
+        // Exceptions being handled when `exception` was raised, oldest first.
+        context:   &'a [Exception<'a>],
         backtrace: &'a [usize]
     },
     RunAborted,
+    // Registers and memory at an unexpected exception, sent before the
+    // panic that aborts the kernel.
+    ExceptionDump(fmt::Arguments<'a>),
+
+    ProfilerRequest,
+    ProfilerReply { interval_cycles: u32 },
+    ProfileSamples {
+        samples: &'a [(usize, usize)],
+        dropped: u32
+    },
 
     RpcSend {
         async: bool,
@@ -65,7 +112,27 @@ pub enum Message<'a> {
     CacheGetRequest { key: &'a str },
     CacheGetReply   { value: &'static [i32] },
     CachePutRequest { key: &'a str, value: &'a [i32] },
-    CachePutReply   { succeeded: bool },
+    CachePutReply   { succeeded: bool, quota_exceeded: bool },
+
+    FlashStorageGetRequest { key: &'a str },
+    FlashStorageGetReply   { value: Option<&'a [u8]> },
+    FlashStoragePutRequest { key: &'a str, value: &'a [i32] },
+    FlashStoragePutReply   { error: Option<&'a str> },
+
+    WatchdogSetRequest { ms: u64, scope: &'a str },
+    WatchdogSetReply   { id: usize },
+    WatchdogClear      { id: usize },
+
+    // Window of the bounded-latency mode of the comms CPU, closed if 0.
+    LowLatencyRequest { duration_ms: u32 },
+
+    DdsSyncReport {
+        channel:         u32,
+        chip_select:     u8,
+        in_delay:        u8,
+        window:          u8,
+        io_update_delay: u8
+    },
 
     I2cStartRequest { busno: u32 },
     I2cRestartRequest { busno: u32 },
@@ -74,16 +141,47 @@ pub enum Message<'a> {
     I2cWriteReply { succeeded: bool, ack: bool },
     I2cReadRequest { busno: u32, ack: bool },
     I2cReadReply { succeeded: bool, data: u8 },
+    I2cWriteBufferRequest { busno: u32, data: &'a [i32] },
+    I2cWriteBufferReply { succeeded: bool, acked: usize },
+    I2cReadBufferRequest { busno: u32, length: usize },
+    I2cReadBufferReply { succeeded: bool, data: &'a [u8] },
     I2cBasicReply { succeeded: bool },
 
+    // `port` is the port of the I2C switches of Kasli, or -1.
+    EepromReadRequest {
+        busno: u32, port: i32, address: u8, size: u32, page_size: u32,
+        offset: u32, length: usize
+    },
+    EepromReadReply(Result<&'a [u8], &'a str>),
+    EepromWriteRequest {
+        busno: u32, port: i32, address: u8, size: u32, page_size: u32,
+        offset: u32, data: &'a [i32]
+    },
+    EepromWriteReply(Result<(), &'a str>),
+
+    // `address` is a 10-bit address if `ten_bit`, or a 7-bit one.
+    SmbusBlockWriteRequest {
+        busno: u32, address: u16, ten_bit: bool, command: u8, data: &'a [i32], pec: bool
+    },
+    SmbusBlockWriteReply(Result<(), &'a str>),
+    SmbusBlockReadRequest {
+        busno: u32, address: u16, ten_bit: bool, command: u8, length: usize, pec: bool
+    },
+    SmbusBlockReadReply(Result<&'a [u8], &'a str>),
+
     SpiSetConfigRequest { busno: u32, flags: u8, length: u8, div: u8, cs: u8 },
     SpiWriteRequest { busno: u32, data: u32 },
     SpiReadRequest { busno: u32 },
     SpiReadReply { succeeded: bool, data: u32 },
+    SpiWriteBufferRequest { busno: u32, data: &'a [i32] },
+    SpiReadBufferRequest { busno: u32, length: usize },
+    SpiTransferRequest { busno: u32, data: &'a [i32] },
+    SpiReadBufferReply { succeeded: bool, data: &'a [u32] },
     SpiBasicReply { succeeded: bool },
 
     Log(fmt::Arguments<'a>),
-    LogSlice(&'a str)
+    LogSlice(&'a str),
+    LogRecord { level: u8, tag: &'a str, text: &'a str }
 }
 
 pub use self::Message::*;
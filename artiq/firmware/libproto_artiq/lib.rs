@@ -1,5 +1,5 @@
 #![no_std]
-#![cfg_attr(feature = "alloc", feature(alloc))]
+#![cfg_attr(feature = "alloc", feature(alloc, never_type))]
 
 extern crate failure;
 #[macro_use]
@@ -12,6 +12,8 @@ extern crate cslice;
 extern crate log;
 
 extern crate byteorder;
+#[cfg(feature = "alloc")]
+extern crate crc;
 extern crate io;
 extern crate dyld;
 
@@ -23,9 +25,13 @@ pub mod drtioaux_proto;
 #[cfg(feature = "alloc")]
 pub mod mgmt_proto;
 #[cfg(feature = "alloc")]
+pub mod health_record;
+#[cfg(feature = "alloc")]
 pub mod analyzer_proto;
 #[cfg(feature = "alloc")]
 pub mod moninj_proto;
 #[cfg(feature = "alloc")]
 pub mod session_proto;
+#[cfg(feature = "alloc")]
+pub mod lz4;
 pub mod rpc_proto;
@@ -0,0 +1,80 @@
+use core::cmp::min;
+use alloc::Vec;
+
+// Compressor for the LZ4 block format, without the frame format. It finds
+// matches with a single hash table lookup per position, which is fast and
+// works well on the repetitive numeric data of RPCs and on logs.
+
+const MIN_MATCH: usize = 4;
+// The last match must start at least 12 bytes before the end of the block,
+// and the last 5 bytes must be literals.
+const MF_LIMIT: usize = 12;
+const LAST_LITERALS: usize = 5;
+const MAX_OFFSET: usize = 65535;
+const HASH_LOG: usize = 12;
+
+fn read_u32(input: &[u8], pos: usize) -> u32 {
+    (input[pos] as u32) | (input[pos + 1] as u32) << 8 |
+        (input[pos + 2] as u32) << 16 | (input[pos + 3] as u32) << 24
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+fn write_length(output: &mut Vec<u8>, mut length: usize) {
+    while length >= 255 {
+        output.push(255);
+        length -= 255;
+    }
+    output.push(length as u8)
+}
+
+fn write_literals(output: &mut Vec<u8>, token: usize, literals: &[u8]) {
+    output.push((min(literals.len(), 15) << 4 | token) as u8);
+    if literals.len() >= 15 {
+        write_length(output, literals.len() - 15)
+    }
+    output.extend_from_slice(literals)
+}
+
+/// Compresses `input` into an LZ4 block.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2 + 16);
+    let mut table = Vec::new();
+    table.resize(1 << HASH_LOG, 0u32);
+
+    let mut anchor = 0;
+    if input.len() > MF_LIMIT {
+        let last_match_start = input.len() - MF_LIMIT;
+        let match_end_limit = input.len() - LAST_LITERALS;
+        let mut pos = 0;
+        while pos <= last_match_start {
+            let sequence = read_u32(input, pos);
+            let slot = hash(sequence);
+            let candidate = table[slot] as usize;
+            table[slot] = pos as u32;
+            if candidate >= pos || pos - candidate > MAX_OFFSET ||
+                    read_u32(input, candidate) != sequence {
+                pos += 1;
+                continue
+            }
+
+            let mut length = MIN_MATCH;
+            while pos + length < match_end_limit && input[candidate + length] == input[pos + length] {
+                length += 1
+            }
+            let offset = pos - candidate;
+            write_literals(&mut output, min(length - MIN_MATCH, 15), &input[anchor..pos]);
+            output.push(offset as u8);
+            output.push((offset >> 8) as u8);
+            if length - MIN_MATCH >= 15 {
+                write_length(&mut output, length - MIN_MATCH - 15)
+            }
+            pos += length;
+            anchor = pos;
+        }
+    }
+    write_literals(&mut output, 0, &input[anchor..]);
+    output
+}
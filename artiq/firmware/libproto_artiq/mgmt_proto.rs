@@ -1,9 +1,11 @@
 use core::str::Utf8Error;
 use alloc::{Vec, String};
+use alloc::boxed::Box;
+use crc::crc32::{self, Hasher32};
 #[cfg(feature = "log")]
 use log;
 
-use io::{Read, ProtoRead, Write, ProtoWrite, Error as IoError, ReadStringError};
+use io::{Read, ProtoRead, Write, ProtoWrite, BufWriter, Error as IoError, ReadStringError};
 
 #[derive(Fail, Debug)]
 pub enum Error<T> {
@@ -13,6 +15,10 @@ pub enum Error<T> {
     UnknownPacket(u8),
     #[fail(display = "unknown log level {}", _0)]
     UnknownLogLevel(u8),
+    #[fail(display = "unknown analyzer trigger mode {}", _0)]
+    UnknownTriggerMode(u8),
+    #[fail(display = "unknown fault {}", _0)]
+    UnknownFault(u8),
     #[fail(display = "invalid UTF-8: {}", _0)]
     Utf8(Utf8Error),
     #[fail(display = "{}", _0)]
@@ -53,15 +59,119 @@ pub enum Request {
     GetLog,
     ClearLog,
     PullLog,
+    PullLogCounted,
+    GetLostLogRecords,
+    GetThreadStats { clear: bool },
+    // Offsets are swept in steps of the given sizes over the whole eye, and
+    // each point sampled 2**(1 + prescale) times longer.
+    DrtioEyeScan { linkno: u8, horizontal_step: u8, vertical_step: u8, prescale: u8 },
+    GetClockRegisters { destination: u8 },
+    GetRtioTopology { destination: u8 },
     #[cfg(feature = "log")]
     SetLogFilter(log::LevelFilter),
     #[cfg(feature = "log")]
     SetUartLogFilter(log::LevelFilter),
+    #[cfg(feature = "log")]
+    SetModuleLogFilter { module: String, level: log::LevelFilter },
+    RemoveModuleLogFilter { module: String },
+    SetLogFormat { json: bool },
+    GetPanicLog,
+    ClearPanicLog,
+    GetHealthSnapshot,
+    GetNetStats,
+    SetAnalyzerTrigger { start: AnalyzerTrigger, stop: AnalyzerTrigger },
+    GetAnalyzerTrigger,
+    SetAnalyzerFilter { exclude: bool, channels: Vec<u32> },
+    GetAnalyzerFilter,
+    GetDrtioLinkStats,
+    GetSatelliteIdents,
+    ReloadRoutingTable,
+    GetTscStats,
+    GetSedStats { clear: bool },
+    GetCacheEntries,
+    RemoveCacheEntry { key: String },
+    ClearCache,
+    GetBoardIdentity,
+    GetGrabberStats,
+    /// With `records`, each alert also carries the health records of its
+    /// condition, if any, see `health_record`.
+    SubscribeAlerts { records: bool },
+    AcknowledgeAlert { sequence: u32 },
+    RunSelfTest,
+    GetSelfTestReport,
+    RunEemSelfTest,
+    GetDdsSyncReports,
+
+    // 24xx I2C EEPROM, with the port of the I2C switches of Kasli, if any,
+    // and the 7-bit address.
+    EepromRead {
+        busno: u8, port: Option<u8>, address: u8, size: u32, page_size: u32,
+        offset: u32, length: u32
+    },
+    EepromWrite {
+        busno: u8, port: Option<u8>, address: u8, size: u32, page_size: u32,
+        offset: u32, data: Vec<u8>
+    },
+
+    FlashStorageRead,
+    FlashStorageErase,
+    GetSensorHistory,
+    InjectFault(Fault),
+    GetRtioErrorCounts { clear: bool },
+
+    KernelImageWrite  { name: String, image: Vec<u8> },
+    KernelImageRemove { name: String },
+    KernelImageList,
+    KernelImageRun    { name: String },
+    TerminateKernel   { reason: String },
+    GetEemPresence,
+
+    // Raw transactions on a local bus, with the port of the I2C switches of
+    // Kasli, if any, and the 7-bit address for I2C.
+    I2cTransfer {
+        busno: u8, port: Option<u8>, address: u8, write: Vec<u8>, read_length: u16
+    },
+    SpiTransfer { busno: u8, flags: u8, length: u8, div: u8, cs: u8, data: Vec<u32> },
+
+    GetRtc,
+    SetRtc { unix_s: u64 },
+
+    GetMoninjLimits,
+    GetAuditLog,
+    GetKernelRunStats,
+    GetFeatures,
+    /// Scrubs the flash areas first if `scrub` is set.
+    GetFlashHealth { scrub: bool },
+    GetChannelEvents { clear: bool },
+    /// Bytes of the reply to `request` from `offset` on, up to `max_length`
+    /// (capped at `MAX_CHUNK_LENGTH`), as a `Reply::Chunk`. Only requests
+    /// whose reply can be large are answered in chunks. The reply is
+    /// snapshotted when a read starts, and later chunks are read from the
+    /// snapshot as long as its CRC32 is `crc`, even over another connection;
+    /// see `ChunkedReply`.
+    ReadChunk { offset: u32, max_length: u32, crc: u32, request: Box<Request> },
+    GetHealthRecords,
+
+    GetDrtioLatency,
+    /// Stores `latency_ps`, or the latency measured if `None`, as the
+    /// calibration of `destination`.
+    SetDrtioLatency { destination: u8, latency_ps: Option<u32> },
+
+    /// Sets the level of a log sink: 0 for the UART, 1 for the buffer and
+    /// 2 for syslog.
+    #[cfg(feature = "log")]
+    SetLogSinkFilter { sink: u8, level: log::LevelFilter },
+    GetLogSinks,
+
+    GetCompatibility,
 
     ConfigRead   { key: String },
     ConfigWrite  { key: String, value: Vec<u8> },
     ConfigRemove { key: String },
     ConfigErase,
+    ConfigList,
+    ConfigBackup,
+    ConfigRestore(Vec<u8>),
 
     StartProfiler {
         interval_us: u32,
@@ -71,10 +181,684 @@ pub enum Request {
     StopProfiler,
     GetProfile,
 
+    StartKernelProfiler {
+        interval_us: u32,
+        hits_size: u32,
+        edges_size: u32,
+    },
+    StopKernelProfiler,
+    GetKernelProfile,
+
     Hotswap(Vec<u8>),
+    FlashFirmware(Vec<u8>),
+    FlashSatelliteFirmware { destination: u8, image: Vec<u8> },
+    FlashGateware { image: Vec<u8>, reconfigure: bool },
     Reboot,
+    RebootToBootloader,
+    PrepareForPowerOff,
+
+    Si549Trim { destination: u8, ppb: i32 },
+    WrpllStats { destination: u8 },
+    GetRtioFrequency,
+    GetRtioCounter,
 
     DebugAllocator,
+    AllocatorStats,
+}
+
+/// FPGA die temperature and supply voltages, as raw 12-bit XADC codes.
+#[derive(Debug, Clone, Copy)]
+pub struct FpgaSensors {
+    pub temperature: u16,
+    pub vccint:      u16,
+    pub vccaux:      u16,
+    pub vccbram:     u16
+}
+
+impl FpgaSensors {
+    fn write_to<W>(sensors: &Option<FpgaSensors>, writer: &mut W)
+            -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        match *sensors {
+            None => writer.write_bool(false)?,
+            Some(fpga) => {
+                writer.write_bool(true)?;
+                writer.write_u16(fpga.temperature)?;
+                writer.write_u16(fpga.vccint)?;
+                writer.write_u16(fpga.vccaux)?;
+                writer.write_u16(fpga.vccbram)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// SFP digital diagnostics, in the raw units of SFF-8472, and alarm flags
+/// (A2h bytes 112 and 113).
+#[derive(Debug, Clone, Copy)]
+pub struct SfpDiagnostics {
+    pub temperature: i16,
+    pub voltage:     u16,
+    pub tx_bias:     u16,
+    pub tx_power:    u16,
+    pub rx_power:    u16,
+    pub alarms:      u16
+}
+
+impl SfpDiagnostics {
+    fn write_to<W>(diagnostics: &Option<SfpDiagnostics>, writer: &mut W)
+            -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        match *diagnostics {
+            None => writer.write_bool(false)?,
+            Some(sfp) => {
+                writer.write_bool(true)?;
+                writer.write_u16(sfp.temperature as u16)?;
+                writer.write_u16(sfp.voltage)?;
+                writer.write_u16(sfp.tx_bias)?;
+                writer.write_u16(sfp.tx_power)?;
+                writer.write_u16(sfp.rx_power)?;
+                writer.write_u16(sfp.alarms)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Features of the core device, reported by `GetFeatures` and in the `Hello`
+/// reply of kernel sessions as a single word, so that hosts can adapt to the
+/// device without sending requests that its firmware may not support. Bits
+/// 0 to 11 are flags; bits 12 to 15 hold the number of SFP ports and bits 16
+/// to 31 the size of the RTIO analyzer trace buffer in KiB, 0 without an
+/// analyzer.
+pub mod feature {
+    /// The gateware has DRTIO links to satellites.
+    pub const DRTIO_MASTER:    u32 = 1 << 0;
+    /// Satellites may be reached through repeaters.
+    pub const DRTIO_ROUTING:   u32 = 1 << 1;
+    /// The RTIO clock is recovered with the WRPLL.
+    pub const WRPLL:           u32 = 1 << 2;
+    /// Kernels may run subkernels on satellites. Not supported by this
+    /// firmware, and never set.
+    pub const SUBKERNELS:      u32 = 1 << 3;
+    pub const ANALYZER:        u32 = 1 << 4;
+    /// Alerts, see `SubscribeAlerts`.
+    pub const ALERTS:          u32 = 1 << 5;
+    /// Compressed messages in kernel sessions and analyzer dumps.
+    pub const COMPRESSION:     u32 = 1 << 6;
+    /// Firmware updates with `FlashFirmware`.
+    pub const FIRMWARE_UPDATE: u32 = 1 << 7;
+    /// Gateware updates with `FlashGateware`.
+    pub const GATEWARE_UPDATE: u32 = 1 << 8;
+    /// Health records, see `GetHealthRecords`.
+    pub const HEALTH_RECORDS:  u32 = 1 << 9;
+
+    pub const SFP_COUNT_SHIFT:     u32 = 12;
+    pub const SFP_COUNT_MASK:      u32 = 0xf;
+    pub const ANALYZER_SIZE_SHIFT: u32 = 16;
+    pub const ANALYZER_SIZE_MASK:  u32 = 0xffff;
+}
+
+/// Largest number of SFP ports in a sample of the sensor history.
+pub const SENSOR_SAMPLE_SFP_PORTS: usize = 3;
+
+/// Sample of the sensor history: uptime at which it was taken, FPGA sensors,
+/// and TX and RX power of each SFP port in the raw units of SFF-8472, if
+/// they could be read.
+#[derive(Debug, Clone, Copy)]
+pub struct SensorSample {
+    pub timestamp_ms: u64,
+    pub fpga:         Option<FpgaSensors>,
+    pub sfp_power:    [Option<(u16, u16)>; SENSOR_SAMPLE_SFP_PORTS]
+}
+
+/// SFP port of a DRTIO satellite, as last read by the satellite. The
+/// diagnostics are `None` if the satellite did not answer or if there is no
+/// transceiver providing them.
+#[derive(Debug, Clone, Copy)]
+pub struct SatelliteSfp {
+    pub destination: u8,
+    pub port:        u8,
+    pub diagnostics: Option<SfpDiagnostics>
+}
+
+/// Power rail monitored with an INA2xx: voltage in mV and current in µA, or
+/// `None` if the monitor could not be read, lowest voltage seen since boot,
+/// and number of brownouts caught between readings.
+#[derive(Debug)]
+pub struct PowerRail {
+    pub name:           String,
+    pub reading:        Option<(u32, i32)>,
+    pub min_voltage_mv: u32,
+    pub brownouts:      u32
+}
+
+/// Identification of the board, from its EEPROM: unique serial number (the
+/// EUI-48 programmed at the factory), and the contents of the Sinara EEPROM
+/// format if present: board name, board ID, hardware revision (major, minor),
+/// variant and vendor.
+#[derive(Debug, Clone, Copy)]
+pub struct BoardIdentity<'a> {
+    pub serial: [u8; 6],
+    pub sinara: Option<(&'a str, u16, u8, u8, u8, u8)>
+}
+
+impl<'a> BoardIdentity<'a> {
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        writer.write_all(&self.serial)?;
+        match self.sinara {
+            None => writer.write_bool(false)?,
+            Some((name, board_id, major, minor, variant, vendor)) => {
+                writer.write_bool(true)?;
+                writer.write_string(name)?;
+                writer.write_u16(board_id)?;
+                writer.write_u8(major)?;
+                writer.write_u8(minor)?;
+                writer.write_u8(variant)?;
+                writer.write_u8(vendor)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Frame statistics of a grabber since boot: whether it is aligned to the
+/// Camera Link clock, size of the last frame, frames received, frames dropped
+/// because their size differs from the previous one, and glitches of the
+/// Camera Link clock while aligned.
+#[derive(Debug, Clone, Copy)]
+pub struct GrabberStats {
+    pub aligned:        bool,
+    pub frame_size:     (u16, u16),
+    pub frames:         u32,
+    pub dropped_frames: u32,
+    pub clock_glitches: u32
+}
+
+/// Check of a subsystem by the self test, with its outcome (0: passed,
+/// 1: failed, 2: skipped) and a detail such as the reason of a failure.
+#[derive(Debug, Clone, Copy)]
+pub struct SelfTestCheck<'a> {
+    pub subsystem: &'a str,
+    pub outcome:   u8,
+    pub detail:    &'a str
+}
+
+/// EEM connector of Kasli as probed at boot: type of the peripheral of the
+/// gateware on it (empty if none), whether a card answered (`None` if the
+/// connector was not probed or the probe failed), and board name in the
+/// EEPROM of the card (empty if unknown).
+#[derive(Debug, Clone, Copy)]
+pub struct EemPresence<'a> {
+    pub eem:      u8,
+    pub expected: &'a str,
+    pub detected: Option<bool>,
+    pub name:     &'a str
+}
+
+/// Entry of the audit log of connections and commands of note.
+#[derive(Debug, Clone)]
+pub struct AuditEntry<'a> {
+    pub sequence:     u32,
+    pub timestamp_ms: u64,
+    pub unix_us:      Option<u64>,
+    /// 0: session, 1: mgmt, 2: moninj.
+    pub service:      u8,
+    /// 0: connected, 1: disconnected, 2: command.
+    pub event:        u8,
+    pub peer:         &'a str,
+    pub duration_ms:  u64,
+    pub detail:       &'a str
+}
+
+/// One-way latency of the RT link to a satellite in picoseconds: measured
+/// when its link came up, for the satellites on a link of the master, and
+/// the calibration stored in the config, if any.
+#[derive(Debug, Clone, Copy)]
+pub struct DrtioLatency {
+    pub destination:   u8,
+    pub measured_ps:   Option<u32>,
+    pub calibrated_ps: Option<u32>
+}
+
+/// Statistics of a kernel run, also sent to the host at the end of the run.
+#[derive(Debug, Clone)]
+pub struct KernelRunStats<'a> {
+    pub sequence:      u32,
+    /// Uptime when the kernel started.
+    pub started_ms:    u64,
+    pub unix_us:       Option<u64>,
+    /// `host`, or the config key of a kernel run from flash.
+    pub source:        &'a str,
+    pub duration_us:   u64,
+    /// 0: finished, 1: uncaught exception, 2: stopped, e.g. by a watchdog.
+    pub outcome:       u8,
+    /// Output events written by the kernel, not counting DMA.
+    pub output_events: u32,
+    /// Minimum slack of the output events in machine units, if any was
+    /// written and the gateware measures it.
+    pub min_slack_mu:  Option<i64>,
+    /// RTIO errors counted during the run, see `RtioErrorCounts`.
+    pub rtio_errors:   u32
+}
+
+impl<'a> KernelRunStats<'a> {
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        writer.write_u32(self.sequence)?;
+        writer.write_u64(self.started_ms)?;
+        match self.unix_us {
+            Some(unix_us) => {
+                writer.write_bool(true)?;
+                writer.write_u64(unix_us)?;
+            }
+            None => writer.write_bool(false)?
+        }
+        writer.write_string(self.source)?;
+        writer.write_u64(self.duration_us)?;
+        writer.write_u8(self.outcome)?;
+        writer.write_u32(self.output_events)?;
+        match self.min_slack_mu {
+            Some(min_slack_mu) => {
+                writer.write_bool(true)?;
+                writer.write_i64(min_slack_mu)?;
+            }
+            None => writer.write_bool(false)?
+        }
+        writer.write_u32(self.rtio_errors)
+    }
+}
+
+/// Result of the last scrub of a flash area: the values it holds that were
+/// checked, and those found corrupted.
+#[derive(Debug, Clone, Copy)]
+pub struct FlashAreaHealth {
+    pub name:      &'static str,
+    pub checked:   u32,
+    pub corrupted: u32
+}
+
+/// Sector of a flash area remapped to a spare sector, by flash address.
+#[derive(Debug, Clone, Copy)]
+pub struct RemappedSector {
+    pub sector: u32,
+    pub spare:  u32
+}
+
+/// Result of the last SYNC_IN and IO_UPDATE alignment of an AD9910 channel by
+/// a kernel, identified by the RTIO channel of its SPI bus and its chip select.
+#[derive(Debug, Clone, Copy)]
+pub struct DdsSyncReport {
+    pub channel:         u32,
+    pub chip_select:     u8,
+    pub in_delay:        u8,
+    pub window:          u8,
+    pub io_update_delay: u8,
+    pub timestamp_ms:    u64
+}
+
+/// State of a DRTIO link, and counters of the events that indicate a marginal
+/// link since boot.
+#[derive(Debug, Clone, Copy)]
+pub struct DrtioLinkStats {
+    pub up:                 bool,
+    pub crc_errors:         u32,
+    pub aux_timeouts:       u32,
+    pub link_drops:         u32,
+    pub protocol_errors:    u32,
+    pub remote_rtio_errors: u32
+}
+
+impl DrtioLinkStats {
+    fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        writer.write_bool(self.up)?;
+        writer.write_u32(self.crc_errors)?;
+        writer.write_u32(self.aux_timeouts)?;
+        writer.write_u32(self.link_drops)?;
+        writer.write_u32(self.protocol_errors)?;
+        writer.write_u32(self.remote_rtio_errors)?;
+        Ok(())
+    }
+}
+
+/// Identification of a DRTIO satellite, reached through `route` (the hops
+/// of the routing table). The identification is `None` if the satellite did
+/// not answer.
+#[derive(Debug)]
+pub struct SatelliteIdent {
+    pub destination: u8,
+    pub route:       Vec<u8>,
+    pub ident:       Option<(String, String, Option<[u8; 6]>)>
+}
+
+/// Offsets of the TSC of a DRTIO satellite from the time of the master,
+/// measured at its synchronizations, in coarse RTIO cycles: number of
+/// synchronizations since the satellite booted, last offset, largest offset
+/// in magnitude, time between the last load of the TSC and the last
+/// synchronization, and phase of the RTIO clock after the recovered clock in
+/// femtoseconds at the last synchronization, where the satellite measures
+/// it. The statistics are `None` if the satellite did not answer.
+#[derive(Debug)]
+pub struct SatelliteTscStats {
+    pub destination: u8,
+    pub stats:       Option<(u32, i32, u32, u32, Option<i32>)>
+}
+
+/// RTIO errors involving the channels of a destination, since boot or the
+/// last clear: underflows, input overflows and unreachable destinations
+/// that ended a kernel, and the asynchronous errors of the SED (collisions,
+/// busy and sequence errors), with the last channel involved.
+#[derive(Debug, Clone, Copy)]
+pub struct RtioErrorCounts {
+    pub destination:     u8,
+    pub underflows:      u32,
+    pub overflows:       u32,
+    pub unreachable:     u32,
+    pub collisions:      u32,
+    pub busy_errors:     u32,
+    pub sequence_errors: u32,
+    pub last_channel:    u32,
+    /// Counted by the satellite of the destination, if it is one.
+    pub satellite:       SatelliteRtioCounters
+}
+
+/// Errors seen by a satellite itself: writes that arrived too late
+/// (underflows) or found the FIFOs full (overflows), timeouts waiting for
+/// buffer space at a destination downstream, malformed DRTIO packets, and
+/// synchronizations that found its TSC off.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SatelliteRtioCounters {
+    pub underflows:            u32,
+    pub overflows:             u32,
+    pub buffer_space_timeouts: u32,
+    pub protocol_errors:       u32,
+    pub tsc_resyncs:           u32
+}
+
+/// Register of a clocking chip; `profile` is set for the registers that hold
+/// its configuration, which can be programmed back from a saved profile.
+#[derive(Debug, Clone, Copy)]
+pub struct ClockRegister {
+    pub address: u8,
+    pub value:   u8,
+    pub profile: bool
+}
+
+/// Registers of a clocking chip, one of the `drtioaux_proto::CLOCK_CHIP_*`.
+#[derive(Debug, Clone)]
+pub struct ClockChipRegisters {
+    pub chip:      u8,
+    pub registers: Vec<ClockRegister>
+}
+
+/// Scheduling of the comms CPU threads with the same name: the number of
+/// such threads running, and since boot or the last clear, the number of
+/// times they were resumed, the time they ran in total and the longest time
+/// one of them ran before suspending itself.
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadStats {
+    pub name:           &'static str,
+    pub threads:        u32,
+    pub wakeups:        u64,
+    pub run_time_us:    u64,
+    pub longest_run_us: u32
+}
+
+/// Point of a transceiver eye scan: the horizontal (phase) and vertical
+/// (voltage) offsets of the sampler, and the errors counted over the
+/// samples taken there.
+#[derive(Debug, Clone, Copy)]
+pub struct EyeScanPoint {
+    pub horizontal: i16,
+    pub vertical:   i16,
+    pub errors:     u16,
+    pub samples:    u16
+}
+
+/// Entry of the kernel cache, with the memory it takes in bytes. Borrowed
+/// entries are in use by the running kernel and cannot be removed.
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub key:      String,
+    pub size:     u32,
+    pub borrowed: bool
+}
+
+/// Counters of the SED (the output event dispatcher of the RTIO core) since
+/// boot or the last clear: events written into each lane, times each lane
+/// became full, and asynchronous errors.
+#[derive(Debug)]
+pub struct SedStats {
+    pub lane_writes:     Vec<u32>,
+    pub lane_full:       Vec<u32>,
+    pub collisions:      u32,
+    pub busy_errors:     u32,
+    pub sequence_errors: u32
+}
+
+#[derive(Debug)]
+pub struct HealthSnapshot<'a> {
+    pub uptime_ms:         u64,
+    /// Wall clock time in microseconds since the Unix epoch, if synchronized.
+    pub unix_time_us:      Option<u64>,
+    /// Number of boots and uptime in seconds over all of them, if kept.
+    pub boot_stats:        Option<(u32, u32)>,
+    pub firmware_version:  &'a str,
+    pub gateware_version:  &'a str,
+    pub rtio_clock_ok:     bool,
+    pub rtio_frequency:    u32,
+    pub heap_total:        u32,
+    pub heap_used:         u32,
+    pub heap_largest_free: u32,
+    pub fpga:              Option<FpgaSensors>,
+    pub drtio_links:       &'a [DrtioLinkStats],
+    /// Diagnostics of each SFP port, if a transceiver providing them is present.
+    pub sfp:               &'a [Option<SfpDiagnostics>],
+    pub power:             &'a [PowerRail],
+    pub satellite_sfp:     &'a [SatelliteSfp]
+}
+
+/// Condition starting or stopping an RTIO analyzer capture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnalyzerTrigger {
+    None,
+    /// Any input or output event on the given RTIO channel.
+    Channel(u32),
+    /// Any RTIO exception, e.g. an underflow or an input overflow.
+    Exception
+}
+
+impl AnalyzerTrigger {
+    fn read_from<R>(reader: &mut R) -> Result<Self, Error<R::ReadError>>
+        where R: Read + ?Sized
+    {
+        let mode = reader.read_u8()?;
+        let channel = reader.read_u32()?;
+        Ok(match mode {
+            0 => AnalyzerTrigger::None,
+            1 => AnalyzerTrigger::Channel(channel),
+            2 => AnalyzerTrigger::Exception,
+            mode => return Err(Error::UnknownTriggerMode(mode))
+        })
+    }
+
+    fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        match *self {
+            AnalyzerTrigger::None => {
+                writer.write_u8(0)?;
+                writer.write_u32(0)
+            }
+            AnalyzerTrigger::Channel(channel) => {
+                writer.write_u8(1)?;
+                writer.write_u32(channel)
+            }
+            AnalyzerTrigger::Exception => {
+                writer.write_u8(2)?;
+                writer.write_u32(0)
+            }
+        }
+    }
+}
+
+/// Synthetic fault to inject into the monitoring of the core device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// Alarm flags of an SFP port, in the order of SFF-8472 A2h bytes 112
+    /// and 113; 0 stops injecting them.
+    SfpAlarms { port: u8, alarms: u16 },
+    DrtioLinkDrop { linkno: u8 },
+    /// FPGA die temperature in m°C, for a number of seconds.
+    FpgaTemperature { temperature_mc: i32, duration_s: u32 },
+    /// Stops injecting all faults.
+    Clear
+}
+
+impl Fault {
+    fn read_from<R>(reader: &mut R) -> Result<Self, Error<R::ReadError>>
+        where R: Read + ?Sized
+    {
+        Ok(match reader.read_u8()? {
+            0 => Fault::Clear,
+            1 => Fault::SfpAlarms {
+                port:   reader.read_u8()?,
+                alarms: reader.read_u16()?
+            },
+            2 => Fault::DrtioLinkDrop {
+                linkno: reader.read_u8()?
+            },
+            3 => Fault::FpgaTemperature {
+                temperature_mc: reader.read_u32()? as i32,
+                duration_s:     reader.read_u32()?
+            },
+            fault => return Err(Error::UnknownFault(fault))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct SocketStats {
+    pub state:      String,
+    pub local:      String,
+    pub remote:     String,
+    pub recv_queue: u32,
+    pub send_queue: u32
+}
+
+/// Largest chunk of a reply sent at once, see `Request::ReadChunk`.
+pub const MAX_CHUNK_LENGTH: u32 = 16384;
+
+/// Largest reply kept between chunks, see `ChunkedReply`; large enough for
+/// the log.
+pub const MAX_SNAPSHOT_LENGTH: usize = 1 << 18;
+
+// Keeps the bytes of a reply between `start` and `end`, and computes the
+// CRC32 of all of them.
+struct ChunkWriter {
+    start:    usize,
+    end:      usize,
+    position: usize,
+    data:     Vec<u8>,
+    digest:   crc32::Digest
+}
+
+impl ChunkWriter {
+    fn new(start: usize, end: usize) -> ChunkWriter {
+        ChunkWriter {
+            start:    start,
+            end:      end,
+            position: 0,
+            data:     Vec::new(),
+            digest:   crc32::Digest::new(crc32::IEEE)
+        }
+    }
+}
+
+impl Write for ChunkWriter {
+    type WriteError = !;
+    type FlushError = !;
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::WriteError> {
+        self.digest.write(buf);
+        let from = self.start.max(self.position).min(self.position + buf.len());
+        let to = self.end.min(self.position + buf.len()).max(from);
+        self.data.extend_from_slice(&buf[from - self.position..to - self.position]);
+        self.position += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushError> {
+        Ok(())
+    }
+}
+
+fn chunk_end(offset: u32, max_length: u32) -> usize {
+    (offset as usize).saturating_add(max_length.min(MAX_CHUNK_LENGTH) as usize)
+}
+
+/// Snapshot of a reply read in chunks with `Request::ReadChunk`, so that
+/// the chunks are taken from the same reply even if it changes meanwhile.
+pub struct ChunkedReply {
+    pub crc: u32,
+    data:    Vec<u8>
+}
+
+impl ChunkedReply {
+    /// Returns None if the reply is longer than `MAX_SNAPSHOT_LENGTH`; its
+    /// chunks are then written with `Reply::write_chunk_to`.
+    pub fn new(reply: &Reply) -> Option<ChunkedReply> {
+        let mut writer = ChunkWriter::new(0, MAX_SNAPSHOT_LENGTH);
+        reply.write_to(&mut writer).unwrap();
+        if writer.position > MAX_SNAPSHOT_LENGTH {
+            return None
+        }
+        Some(ChunkedReply { crc: writer.digest.sum32(), data: writer.data })
+    }
+
+    /// Whether the chunk from `offset` on, up to `max_length`, is the last one.
+    pub fn is_last(&self, offset: u32, max_length: u32) -> bool {
+        chunk_end(offset, max_length) >= self.data.len()
+    }
+
+    /// Writes the bytes of the reply from `offset` on, up to `max_length`, as
+    /// a `Reply::Chunk`.
+    pub fn write_chunk_to<W>(&self, offset: u32, max_length: u32, writer: &mut W)
+            -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        let start = (offset as usize).min(self.data.len());
+        let end = chunk_end(offset, max_length).min(self.data.len());
+        Reply::Chunk {
+            total:  self.data.len() as u32,
+            crc:    self.crc,
+            offset: offset,
+            data:   &self.data[start..end]
+        }.write_to(writer)
+    }
+}
+
+#[derive(Debug)]
+pub struct NetStats<'a> {
+    pub rx_packets:          u32,
+    pub rx_bytes:            u64,
+    pub tx_packets:          u32,
+    pub tx_bytes:            u64,
+    pub rx_preamble_errors:  u32,
+    pub rx_crc_errors:       u32,
+    pub rx_dropped:          u32,
+    /// Packets rejected by the network stack, e.g. malformed or not for us.
+    pub network_errors:      u32,
+    /// TCP segments sent again, e.g. after a frame was lost.
+    pub tcp_retransmissions: u32,
+    pub sockets:             &'a [SocketStats]
 }
 
 pub enum Reply<'a> {
@@ -85,15 +869,192 @@ pub enum Reply<'a> {
     LogContent(&'a str),
 
     ConfigData(&'a [u8]),
+    ConfigKeys(&'a [(String, u32)]),
 
     Profile,
 
     RebootImminent,
+
+    WrpllStats {
+        locked: bool,
+        lock_time_ms: u32,
+        unlock_count: u32,
+        samples: u32,
+        helper_rms_mtags: u32,
+        main_rms_mtags: u32,
+    },
+    RtioFrequency(u32),
+    RtioCounter {
+        counter:   u64,
+        /// Uptime and wall clock time, if set, at which `counter` was latched.
+        uptime_us: u64,
+        unix_us:   Option<u64>
+    },
+    AllocatorStats {
+        total: u32,
+        used: u32,
+        largest_free: u32,
+        free_blocks: u32,
+    },
+    HealthSnapshot(&'a HealthSnapshot<'a>),
+    NetStats(&'a NetStats<'a>),
+    AnalyzerTrigger {
+        start: AnalyzerTrigger,
+        stop: AnalyzerTrigger,
+        started: bool,
+        stopped: bool,
+    },
+    AnalyzerFilter {
+        exclude: bool,
+        channels: &'a [u32],
+    },
+    DrtioLinkStats(&'a [DrtioLinkStats]),
+    SatelliteIdents(&'a [SatelliteIdent]),
+    TscStats(&'a [SatelliteTscStats]),
+    SedStats(&'a SedStats),
+    CacheEntries(&'a [CacheEntry]),
+    BoardIdentity(&'a BoardIdentity<'a>),
+    GrabberStats(&'a [GrabberStats]),
+    Alert {
+        sequence:     u32,
+        timestamp_ms: u64,
+        severity:     u8,
+        source:       u8,
+        /// Number of alerts dropped since boot because the queue was full.
+        dropped:      u32,
+        message:      &'a str,
+        /// Health record set, for subscriptions with records.
+        records:      Option<&'a [u8]>
+    },
+    SelfTestReport {
+        /// Uptime at which the self test started.
+        timestamp_ms: u64,
+        checks:       &'a [SelfTestCheck<'a>]
+    },
+    DdsSyncReports(&'a [DdsSyncReport]),
+    FlashStorageRecords {
+        /// Bytes used in the area, including superseded records.
+        used:    u32,
+        size:    u32,
+        records: &'a [(String, Vec<u8>)]
+    },
+    RtioErrorCounts(&'a [RtioErrorCounts]),
+    LostLogRecords(u32),
+    ThreadStats {
+        /// Time over which the statistics were accumulated.
+        elapsed_us: u64,
+        threads:    &'a [ThreadStats]
+    },
+    EyeScan {
+        /// Horizontal offsets in one unit interval.
+        ui_steps:        u16,
+        /// Bits compared per counted sample; the bit error ratio at a point
+        /// is `errors / (samples * bits_per_sample)`.
+        bits_per_sample: u32,
+        points:          &'a [EyeScanPoint]
+    },
+    Compatibility {
+        /// Descriptor the firmware was built for.
+        firmware: u32,
+        /// Descriptor read from the gateware, 0 if it has none.
+        gateware: u32
+    },
+    SensorHistory {
+        interval_ms: u32,
+        sfp_ports:   u8,
+        /// Oldest first.
+        samples:     &'a [SensorSample]
+    },
+    ClockRegisters(&'a [ClockChipRegisters]),
+    /// See `build_soc.get_topology` for the format.
+    RtioTopology(&'a str),
+    EemPresence(&'a [EemPresence<'a>]),
+    SpiData(&'a [u32]),
+    /// Time of the RTC in seconds since the Unix epoch, or `None` if the
+    /// RTC has not kept it.
+    Rtc(Option<u64>),
+    MoninjLimits {
+        /// Requests per second per connection, 0 if not limited.
+        request_rate:       u32,
+        /// Reads per second per channel and connection, 0 if not limited.
+        probe_rate:         u32,
+        /// Requests held back, and reads postponed, since boot.
+        requests_throttled: u32,
+        reads_postponed:    u32
+    },
+    AuditLog {
+        /// Entries dropped since boot to make room for newer ones.
+        dropped: u32,
+        /// Oldest first.
+        entries: &'a [AuditEntry<'a>]
+    },
+    DrtioLatency(&'a [DrtioLatency]),
+    LogSinks {
+        /// Levels of the UART, buffer and syslog sinks, encoded as in the
+        /// requests.
+        levels:      [u8; 3],
+        /// Address of the syslog server, empty if none.
+        syslog:      &'a str,
+        /// Records dropped from the syslog sink since boot.
+        syslog_lost: u32
+    },
+    PowerOffReady {
+        /// Whether a kernel had to be stopped.
+        kernel_stopped:     bool,
+        /// Number of outputs driven to their safe state.
+        safe_state_outputs: u32,
+        /// SFP ports whose transmitter is disabled, as a bit mask.
+        sfp_tx_disabled:    u8
+    },
+    KernelRunStats {
+        /// Runs dropped since boot to make room for newer ones.
+        dropped: u32,
+        /// Oldest first.
+        runs:    &'a [KernelRunStats<'a>]
+    },
+    /// See `feature`.
+    Features(u32),
+    FlashHealth {
+        /// Uptime at the end of the last scrub, if any.
+        scrubbed_ms: Option<u64>,
+        scrubs:      u32,
+        areas:       &'a [FlashAreaHealth],
+        /// Erase and program failures since boot.
+        failures:    u32,
+        spares_free: u8,
+        remapped:    &'a [RemappedSector]
+    },
+    /// Output events accepted for each group of `group_size` consecutive
+    /// local RTIO channels, since boot or the last clear.
+    ChannelEvents {
+        group_size: u16,
+        events:     &'a [u32]
+    },
+    /// Health record set, see `health_record`.
+    HealthRecords(&'a [u8]),
+    /// Part of a reply, see `Request::ReadChunk`. The host checks that
+    /// `crc` is the same for all chunks, i.e. that the reply did not change
+    /// in between, and that it matches the reassembled reply.
+    Chunk {
+        /// Length and CRC32 of the whole reply.
+        total:  u32,
+        crc:    u32,
+        offset: u32,
+        data:   &'a [u8]
+    },
 }
 
 impl Request {
     pub fn read_from<R>(reader: &mut R) -> Result<Self, Error<R::ReadError>>
         where R: Read + ?Sized
+    {
+        Request::read_request(reader, false)
+    }
+
+    // The request of a `ReadChunk` is not itself a `ReadChunk`, so that
+    // parsing cannot recurse.
+    fn read_request<R>(reader: &mut R, in_chunk: bool) -> Result<Self, Error<R::ReadError>>
+        where R: Read + ?Sized
     {
         #[cfg(feature = "log")]
         fn read_log_level_filter<T: Read + ?Sized>(reader: &mut T) ->
@@ -109,6 +1070,15 @@ impl Request {
             })
         }
 
+        // 0xff if no I2C switch port is selected.
+        fn read_port<T: Read + ?Sized>(reader: &mut T) ->
+                Result<Option<u8>, Error<T::ReadError>> {
+            Ok(match reader.read_u8()? {
+                0xff => None,
+                port => Some(port)
+            })
+        }
+
         Ok(match reader.read_u8()? {
             1  => Request::GetLog,
             2  => Request::ClearLog,
@@ -117,6 +1087,171 @@ impl Request {
             3 => Request::SetLogFilter(read_log_level_filter(reader)?),
             #[cfg(feature = "log")]
             6 => Request::SetUartLogFilter(read_log_level_filter(reader)?),
+            #[cfg(feature = "log")]
+            28 => Request::SetModuleLogFilter {
+                module: reader.read_string()?,
+                level:  read_log_level_filter(reader)?
+            },
+            29 => Request::RemoveModuleLogFilter {
+                module: reader.read_string()?
+            },
+            49 => Request::SetLogFormat {
+                json: reader.read_bool()?
+            },
+            30 => Request::GetPanicLog,
+            31 => Request::ClearPanicLog,
+            32 => Request::GetHealthSnapshot,
+            33 => Request::GetNetStats,
+            34 => Request::SetAnalyzerTrigger {
+                start: AnalyzerTrigger::read_from(reader)?,
+                stop:  AnalyzerTrigger::read_from(reader)?
+            },
+            35 => Request::GetAnalyzerTrigger,
+            36 => {
+                let exclude = reader.read_bool()?;
+                let count = reader.read_u8()?;
+                let mut channels = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    channels.push(reader.read_u32()?);
+                }
+                Request::SetAnalyzerFilter { exclude: exclude, channels: channels }
+            }
+            37 => Request::GetAnalyzerFilter,
+            38 => Request::GetDrtioLinkStats,
+            39 => Request::GetSatelliteIdents,
+            41 => Request::ReloadRoutingTable,
+            42 => Request::GetTscStats,
+            43 => Request::GetSedStats {
+                clear: reader.read_bool()?
+            },
+            44 => Request::GetCacheEntries,
+            45 => Request::RemoveCacheEntry {
+                key: reader.read_string()?
+            },
+            46 => Request::ClearCache,
+            47 => Request::GetBoardIdentity,
+            48 => Request::GetGrabberStats,
+            50 => Request::SubscribeAlerts { records: false },
+            95 => Request::SubscribeAlerts { records: true },
+            51 => Request::AcknowledgeAlert {
+                sequence: reader.read_u32()?
+            },
+            52 => Request::RunSelfTest,
+            53 => Request::GetSelfTestReport,
+            86 => Request::RunEemSelfTest,
+            54 => Request::GetDdsSyncReports,
+
+            56 => Request::EepromRead {
+                busno:     reader.read_u8()?,
+                port:      read_port(reader)?,
+                address:   reader.read_u8()?,
+                size:      reader.read_u32()?,
+                page_size: reader.read_u32()?,
+                offset:    reader.read_u32()?,
+                length:    reader.read_u32()?
+            },
+            57 => Request::EepromWrite {
+                busno:     reader.read_u8()?,
+                port:      read_port(reader)?,
+                address:   reader.read_u8()?,
+                size:      reader.read_u32()?,
+                page_size: reader.read_u32()?,
+                offset:    reader.read_u32()?,
+                data:      reader.read_bytes()?
+            },
+            58 => Request::FlashStorageRead,
+            59 => Request::FlashStorageErase,
+            60 => Request::GetSensorHistory,
+            61 => Request::InjectFault(Fault::read_from(reader)?),
+            62 => Request::GetRtioErrorCounts {
+                clear: reader.read_bool()?
+            },
+            63 => Request::KernelImageWrite {
+                name:  reader.read_string()?,
+                image: reader.read_bytes()?
+            },
+            64 => Request::KernelImageRemove {
+                name: reader.read_string()?
+            },
+            65 => Request::KernelImageList,
+            66 => Request::KernelImageRun {
+                name: reader.read_string()?
+            },
+            67 => Request::GetCompatibility,
+            68 => Request::PullLogCounted,
+            69 => Request::GetLostLogRecords,
+            70 => Request::GetThreadStats {
+                clear: reader.read_bool()?
+            },
+            71 => Request::DrtioEyeScan {
+                linkno:          reader.read_u8()?,
+                horizontal_step: reader.read_u8()?,
+                vertical_step:   reader.read_u8()?,
+                prescale:        reader.read_u8()?
+            },
+            72 => Request::GetClockRegisters {
+                destination: reader.read_u8()?
+            },
+            73 => Request::GetRtioTopology {
+                destination: reader.read_u8()?
+            },
+            74 => Request::TerminateKernel {
+                reason: reader.read_string()?
+            },
+            75 => Request::GetEemPresence,
+            76 => Request::I2cTransfer {
+                busno:       reader.read_u8()?,
+                port:        read_port(reader)?,
+                address:     reader.read_u8()?,
+                write:       reader.read_bytes()?,
+                read_length: reader.read_u16()?
+            },
+            77 => {
+                let busno = reader.read_u8()?;
+                let flags = reader.read_u8()?;
+                let length = reader.read_u8()?;
+                let div = reader.read_u8()?;
+                let cs = reader.read_u8()?;
+                let mut data = Vec::new();
+                for _ in 0..reader.read_u16()? {
+                    data.push(reader.read_u32()?);
+                }
+                Request::SpiTransfer {
+                    busno: busno, flags: flags, length: length, div: div, cs: cs, data: data
+                }
+            }
+            78 => Request::GetRtc,
+            79 => Request::SetRtc {
+                unix_s: reader.read_u64()?
+            },
+            80 => Request::GetMoninjLimits,
+            81 => Request::GetAuditLog,
+            82 => Request::GetDrtioLatency,
+            83 => Request::SetDrtioLatency {
+                destination: reader.read_u8()?,
+                latency_ps:  if reader.read_bool()? { Some(reader.read_u32()?) } else { None }
+            },
+            #[cfg(feature = "log")]
+            84 => Request::SetLogSinkFilter {
+                sink:  reader.read_u8()?,
+                level: read_log_level_filter(reader)?
+            },
+            85 => Request::GetLogSinks,
+            88 => Request::GetKernelRunStats,
+            90 => Request::GetFeatures,
+            91 => Request::GetFlashHealth {
+                scrub: reader.read_bool()?
+            },
+            92 => Request::GetChannelEvents {
+                clear: reader.read_bool()?
+            },
+            93 if !in_chunk => Request::ReadChunk {
+                offset:     reader.read_u32()?,
+                max_length: reader.read_u32()?,
+                crc:        reader.read_u32()?,
+                request:    Box::new(Request::read_request(reader, true)?)
+            },
+            94 => Request::GetHealthRecords,
 
             12 => Request::ConfigRead {
                 key: reader.read_string()?
@@ -129,6 +1264,9 @@ impl Request {
                 key: reader.read_string()?
             },
             15 => Request::ConfigErase,
+            16 => Request::ConfigList,
+            17 => Request::ConfigBackup,
+            18 => Request::ConfigRestore(reader.read_bytes()?),
 
             9 => Request::StartProfiler {
                 interval_us: reader.read_u32()?,
@@ -138,10 +1276,40 @@ impl Request {
             10 => Request::StopProfiler,
             11 => Request::GetProfile,
 
+            22 => Request::StartKernelProfiler {
+                interval_us: reader.read_u32()?,
+                hits_size: reader.read_u32()?,
+                edges_size: reader.read_u32()?,
+            },
+            23 => Request::StopKernelProfiler,
+            24 => Request::GetKernelProfile,
+
             4 => Request::Hotswap(reader.read_bytes()?),
+            27 => Request::FlashFirmware(reader.read_bytes()?),
+            40 => Request::FlashSatelliteFirmware {
+                destination: reader.read_u8()?,
+                image: reader.read_bytes()?
+            },
+            89 => Request::FlashGateware {
+                image: reader.read_bytes()?,
+                reconfigure: reader.read_bool()?
+            },
             5 => Request::Reboot,
+            26 => Request::RebootToBootloader,
+            87 => Request::PrepareForPowerOff,
+
+            19 => Request::Si549Trim {
+                destination: reader.read_u8()?,
+                ppb: reader.read_u32()? as i32
+            },
+            20 => Request::WrpllStats {
+                destination: reader.read_u8()?
+            },
+            21 => Request::GetRtioFrequency,
+            55 => Request::GetRtioCounter,
 
             8 => Request::DebugAllocator,
+            25 => Request::AllocatorStats,
 
             ty => return Err(Error::UnknownPacket(ty))
         })
@@ -149,8 +1317,35 @@ impl Request {
 }
 
 impl<'a> Reply<'a> {
+    /// Writes the bytes of the reply from `offset` on, up to `max_length`, as
+    /// a `Reply::Chunk`. The reply is serialized again for each chunk, and
+    /// only the bytes of the chunk are kept.
+    pub fn write_chunk_to<W>(&self, offset: u32, max_length: u32, writer: &mut W)
+            -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        let mut chunk = ChunkWriter::new(offset as usize, chunk_end(offset, max_length));
+        self.write_to(&mut chunk).unwrap();
+        Reply::Chunk {
+            total:  chunk.position as u32,
+            crc:    chunk.digest.sum32(),
+            offset: offset,
+            data:   &chunk.data
+        }.write_to(writer)
+    }
+
     pub fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
         where W: Write + ?Sized
+    {
+        // See session_proto::Reply::write_to.
+        let mut buffer = [0; 256];
+        let mut writer = BufWriter::new(writer, &mut buffer);
+        self.write_fields(&mut writer)?;
+        writer.flush()
+    }
+
+    fn write_fields<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
     {
         match *self {
             Reply::Success => {
@@ -173,6 +1368,14 @@ impl<'a> Reply<'a> {
                 writer.write_u8(7)?;
                 writer.write_bytes(bytes)?;
             },
+            Reply::ConfigKeys(ref keys) => {
+                writer.write_u8(8)?;
+                writer.write_u32(keys.len() as u32)?;
+                for &(ref key, size) in keys.iter() {
+                    writer.write_string(key)?;
+                    writer.write_u32(size)?;
+                }
+            },
 
             Reply::Profile => {
                 writer.write_u8(5)?;
@@ -182,6 +1385,498 @@ impl<'a> Reply<'a> {
             Reply::RebootImminent => {
                 writer.write_u8(3)?;
             }
+
+            Reply::WrpllStats { locked, lock_time_ms, unlock_count, samples,
+                                helper_rms_mtags, main_rms_mtags } => {
+                writer.write_u8(9)?;
+                writer.write_bool(locked)?;
+                writer.write_u32(lock_time_ms)?;
+                writer.write_u32(unlock_count)?;
+                writer.write_u32(samples)?;
+                writer.write_u32(helper_rms_mtags)?;
+                writer.write_u32(main_rms_mtags)?;
+            }
+            Reply::RtioFrequency(frequency) => {
+                writer.write_u8(10)?;
+                writer.write_u32(frequency)?;
+            }
+            Reply::RtioCounter { counter, uptime_us, unix_us } => {
+                writer.write_u8(26)?;
+                writer.write_u64(counter)?;
+                writer.write_u64(uptime_us)?;
+                match unix_us {
+                    None => writer.write_bool(false)?,
+                    Some(time) => {
+                        writer.write_bool(true)?;
+                        writer.write_u64(time)?;
+                    }
+                }
+            }
+            Reply::AllocatorStats { total, used, largest_free, free_blocks } => {
+                writer.write_u8(11)?;
+                writer.write_u32(total)?;
+                writer.write_u32(used)?;
+                writer.write_u32(largest_free)?;
+                writer.write_u32(free_blocks)?;
+            }
+            Reply::HealthSnapshot(health) => {
+                writer.write_u8(12)?;
+                writer.write_u64(health.uptime_ms)?;
+                match health.unix_time_us {
+                    None => writer.write_bool(false)?,
+                    Some(time) => {
+                        writer.write_bool(true)?;
+                        writer.write_u64(time)?;
+                    }
+                }
+                match health.boot_stats {
+                    None => writer.write_bool(false)?,
+                    Some((boot_count, uptime_s)) => {
+                        writer.write_bool(true)?;
+                        writer.write_u32(boot_count)?;
+                        writer.write_u32(uptime_s)?;
+                    }
+                }
+                writer.write_string(health.firmware_version)?;
+                writer.write_string(health.gateware_version)?;
+                writer.write_bool(health.rtio_clock_ok)?;
+                writer.write_u32(health.rtio_frequency)?;
+                writer.write_u32(health.heap_total)?;
+                writer.write_u32(health.heap_used)?;
+                writer.write_u32(health.heap_largest_free)?;
+                FpgaSensors::write_to(&health.fpga, writer)?;
+                writer.write_u8(health.drtio_links.len() as u8)?;
+                for link in health.drtio_links {
+                    link.write_to(writer)?;
+                }
+                writer.write_u8(health.sfp.len() as u8)?;
+                for sfp in health.sfp {
+                    SfpDiagnostics::write_to(sfp, writer)?;
+                }
+                writer.write_u8(health.power.len() as u8)?;
+                for rail in health.power {
+                    writer.write_string(&rail.name)?;
+                    match rail.reading {
+                        None => writer.write_bool(false)?,
+                        Some((voltage_mv, current_ua)) => {
+                            writer.write_bool(true)?;
+                            writer.write_u32(voltage_mv)?;
+                            writer.write_i32(current_ua)?;
+                        }
+                    }
+                    writer.write_u32(rail.min_voltage_mv)?;
+                    writer.write_u32(rail.brownouts)?;
+                }
+                writer.write_u8(health.satellite_sfp.len() as u8)?;
+                for sfp in health.satellite_sfp {
+                    writer.write_u8(sfp.destination)?;
+                    writer.write_u8(sfp.port)?;
+                    SfpDiagnostics::write_to(&sfp.diagnostics, writer)?;
+                }
+            }
+            Reply::NetStats(stats) => {
+                writer.write_u8(13)?;
+                writer.write_u32(stats.rx_packets)?;
+                writer.write_u64(stats.rx_bytes)?;
+                writer.write_u32(stats.tx_packets)?;
+                writer.write_u64(stats.tx_bytes)?;
+                writer.write_u32(stats.rx_preamble_errors)?;
+                writer.write_u32(stats.rx_crc_errors)?;
+                writer.write_u32(stats.rx_dropped)?;
+                writer.write_u32(stats.network_errors)?;
+                writer.write_u32(stats.tcp_retransmissions)?;
+                writer.write_u8(stats.sockets.len() as u8)?;
+                for socket in stats.sockets {
+                    writer.write_string(&socket.state)?;
+                    writer.write_string(&socket.local)?;
+                    writer.write_string(&socket.remote)?;
+                    writer.write_u32(socket.recv_queue)?;
+                    writer.write_u32(socket.send_queue)?;
+                }
+            }
+            Reply::AnalyzerTrigger { start, stop, started, stopped } => {
+                writer.write_u8(14)?;
+                start.write_to(writer)?;
+                stop.write_to(writer)?;
+                writer.write_bool(started)?;
+                writer.write_bool(stopped)?;
+            }
+            Reply::AnalyzerFilter { exclude, channels } => {
+                writer.write_u8(15)?;
+                writer.write_bool(exclude)?;
+                writer.write_u8(channels.len() as u8)?;
+                for &channel in channels {
+                    writer.write_u32(channel)?;
+                }
+            }
+            Reply::DrtioLinkStats(links) => {
+                writer.write_u8(16)?;
+                writer.write_u8(links.len() as u8)?;
+                for link in links {
+                    link.write_to(writer)?;
+                }
+            }
+            Reply::SatelliteIdents(satellites) => {
+                writer.write_u8(17)?;
+                writer.write_u8(satellites.len() as u8)?;
+                for satellite in satellites {
+                    writer.write_u8(satellite.destination)?;
+                    writer.write_bytes(&satellite.route)?;
+                    match satellite.ident {
+                        None => writer.write_bool(false)?,
+                        Some((ref firmware, ref gateware, serial)) => {
+                            writer.write_bool(true)?;
+                            writer.write_string(firmware)?;
+                            writer.write_string(gateware)?;
+                            match serial {
+                                None => writer.write_bool(false)?,
+                                Some(serial) => {
+                                    writer.write_bool(true)?;
+                                    writer.write_all(&serial)?;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Reply::TscStats(satellites) => {
+                writer.write_u8(18)?;
+                writer.write_u8(satellites.len() as u8)?;
+                for satellite in satellites {
+                    writer.write_u8(satellite.destination)?;
+                    match satellite.stats {
+                        None => writer.write_bool(false)?,
+                        Some((syncs, offset, max_offset, interval_ms, phase_fs)) => {
+                            writer.write_bool(true)?;
+                            writer.write_u32(syncs)?;
+                            writer.write_u32(offset as u32)?;
+                            writer.write_u32(max_offset)?;
+                            writer.write_u32(interval_ms)?;
+                            match phase_fs {
+                                None => writer.write_bool(false)?,
+                                Some(phase_fs) => {
+                                    writer.write_bool(true)?;
+                                    writer.write_u32(phase_fs as u32)?;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Reply::SedStats(stats) => {
+                writer.write_u8(19)?;
+                writer.write_u8(stats.lane_writes.len() as u8)?;
+                for (&writes, &full) in stats.lane_writes.iter().zip(stats.lane_full.iter()) {
+                    writer.write_u32(writes)?;
+                    writer.write_u32(full)?;
+                }
+                writer.write_u32(stats.collisions)?;
+                writer.write_u32(stats.busy_errors)?;
+                writer.write_u32(stats.sequence_errors)?;
+            }
+            Reply::CacheEntries(entries) => {
+                writer.write_u8(20)?;
+                writer.write_u32(entries.len() as u32)?;
+                for entry in entries {
+                    writer.write_string(&entry.key)?;
+                    writer.write_u32(entry.size)?;
+                    writer.write_bool(entry.borrowed)?;
+                }
+            }
+            Reply::BoardIdentity(identity) => {
+                writer.write_u8(21)?;
+                identity.write_to(writer)?;
+            }
+            Reply::GrabberStats(grabbers) => {
+                writer.write_u8(22)?;
+                writer.write_u8(grabbers.len() as u8)?;
+                for grabber in grabbers {
+                    writer.write_bool(grabber.aligned)?;
+                    writer.write_u16(grabber.frame_size.0)?;
+                    writer.write_u16(grabber.frame_size.1)?;
+                    writer.write_u32(grabber.frames)?;
+                    writer.write_u32(grabber.dropped_frames)?;
+                    writer.write_u32(grabber.clock_glitches)?;
+                }
+            }
+            Reply::Alert { sequence, timestamp_ms, severity, source, dropped, message, records } => {
+                writer.write_u8(23)?;
+                writer.write_u32(sequence)?;
+                writer.write_u64(timestamp_ms)?;
+                writer.write_u8(severity)?;
+                writer.write_u8(source)?;
+                writer.write_u32(dropped)?;
+                writer.write_string(message)?;
+                if let Some(records) = records {
+                    writer.write_bytes(records)?;
+                }
+            }
+            Reply::SelfTestReport { timestamp_ms, checks } => {
+                writer.write_u8(24)?;
+                writer.write_u64(timestamp_ms)?;
+                writer.write_u8(checks.len() as u8)?;
+                for check in checks {
+                    writer.write_string(check.subsystem)?;
+                    writer.write_u8(check.outcome)?;
+                    writer.write_string(check.detail)?;
+                }
+            }
+            Reply::DdsSyncReports(reports) => {
+                writer.write_u8(25)?;
+                writer.write_u8(reports.len() as u8)?;
+                for report in reports {
+                    writer.write_u32(report.channel)?;
+                    writer.write_u8(report.chip_select)?;
+                    writer.write_u8(report.in_delay)?;
+                    writer.write_u8(report.window)?;
+                    writer.write_u8(report.io_update_delay)?;
+                    writer.write_u64(report.timestamp_ms)?;
+                }
+            }
+            Reply::FlashStorageRecords { used, size, records } => {
+                writer.write_u8(27)?;
+                writer.write_u32(used)?;
+                writer.write_u32(size)?;
+                writer.write_u32(records.len() as u32)?;
+                for &(ref key, ref value) in records {
+                    writer.write_string(key)?;
+                    writer.write_bytes(value)?;
+                }
+            }
+            Reply::RtioErrorCounts(counts) => {
+                writer.write_u8(29)?;
+                writer.write_u32(counts.len() as u32)?;
+                for counts in counts {
+                    writer.write_u8(counts.destination)?;
+                    writer.write_u32(counts.underflows)?;
+                    writer.write_u32(counts.overflows)?;
+                    writer.write_u32(counts.unreachable)?;
+                    writer.write_u32(counts.collisions)?;
+                    writer.write_u32(counts.busy_errors)?;
+                    writer.write_u32(counts.sequence_errors)?;
+                    writer.write_u32(counts.last_channel)?;
+                    writer.write_u32(counts.satellite.underflows)?;
+                    writer.write_u32(counts.satellite.overflows)?;
+                    writer.write_u32(counts.satellite.buffer_space_timeouts)?;
+                    writer.write_u32(counts.satellite.protocol_errors)?;
+                    writer.write_u32(counts.satellite.tsc_resyncs)?;
+                }
+            }
+            Reply::ThreadStats { elapsed_us, threads } => {
+                writer.write_u8(32)?;
+                writer.write_u64(elapsed_us)?;
+                writer.write_u32(threads.len() as u32)?;
+                for thread in threads {
+                    writer.write_string(thread.name)?;
+                    writer.write_u32(thread.threads)?;
+                    writer.write_u64(thread.wakeups)?;
+                    writer.write_u64(thread.run_time_us)?;
+                    writer.write_u32(thread.longest_run_us)?;
+                }
+            }
+            Reply::EyeScan { ui_steps, bits_per_sample, points } => {
+                writer.write_u8(33)?;
+                writer.write_u16(ui_steps)?;
+                writer.write_u32(bits_per_sample)?;
+                writer.write_u32(points.len() as u32)?;
+                for point in points {
+                    writer.write_u16(point.horizontal as u16)?;
+                    writer.write_u16(point.vertical as u16)?;
+                    writer.write_u16(point.errors)?;
+                    writer.write_u16(point.samples)?;
+                }
+            }
+            Reply::ClockRegisters(chips) => {
+                writer.write_u8(34)?;
+                writer.write_u8(chips.len() as u8)?;
+                for chip in chips {
+                    writer.write_u8(chip.chip)?;
+                    writer.write_u8(chip.registers.len() as u8)?;
+                    for register in chip.registers.iter() {
+                        writer.write_u8(register.address)?;
+                        writer.write_u8(register.value)?;
+                        writer.write_bool(register.profile)?;
+                    }
+                }
+            }
+            Reply::RtioTopology(topology) => {
+                writer.write_u8(35)?;
+                writer.write_string(topology)?;
+            }
+            Reply::EemPresence(eems) => {
+                writer.write_u8(36)?;
+                writer.write_u8(eems.len() as u8)?;
+                for eem in eems {
+                    writer.write_u8(eem.eem)?;
+                    writer.write_string(eem.expected)?;
+                    // 0: not detected, 1: detected, 2: not probed.
+                    writer.write_u8(match eem.detected {
+                        Some(false) => 0,
+                        Some(true) => 1,
+                        None => 2
+                    })?;
+                    writer.write_string(eem.name)?;
+                }
+            }
+            Reply::SpiData(data) => {
+                writer.write_u8(37)?;
+                writer.write_u16(data.len() as u16)?;
+                for &word in data {
+                    writer.write_u32(word)?;
+                }
+            }
+            Reply::Rtc(unix_s) => {
+                writer.write_u8(38)?;
+                match unix_s {
+                    Some(unix_s) => {
+                        writer.write_bool(true)?;
+                        writer.write_u64(unix_s)?;
+                    }
+                    None => writer.write_bool(false)?
+                }
+            }
+            Reply::MoninjLimits { request_rate, probe_rate, requests_throttled, reads_postponed } => {
+                writer.write_u8(39)?;
+                writer.write_u32(request_rate)?;
+                writer.write_u32(probe_rate)?;
+                writer.write_u32(requests_throttled)?;
+                writer.write_u32(reads_postponed)?;
+            }
+            Reply::AuditLog { dropped, entries } => {
+                writer.write_u8(40)?;
+                writer.write_u32(dropped)?;
+                writer.write_u32(entries.len() as u32)?;
+                for entry in entries {
+                    writer.write_u32(entry.sequence)?;
+                    writer.write_u64(entry.timestamp_ms)?;
+                    match entry.unix_us {
+                        Some(unix_us) => {
+                            writer.write_bool(true)?;
+                            writer.write_u64(unix_us)?;
+                        }
+                        None => writer.write_bool(false)?
+                    }
+                    writer.write_u8(entry.service)?;
+                    writer.write_u8(entry.event)?;
+                    writer.write_string(entry.peer)?;
+                    writer.write_u64(entry.duration_ms)?;
+                    writer.write_string(entry.detail)?;
+                }
+            }
+            Reply::DrtioLatency(satellites) => {
+                writer.write_u8(41)?;
+                writer.write_u8(satellites.len() as u8)?;
+                for satellite in satellites {
+                    writer.write_u8(satellite.destination)?;
+                    for &latency_ps in &[satellite.measured_ps, satellite.calibrated_ps] {
+                        match latency_ps {
+                            Some(latency_ps) => {
+                                writer.write_bool(true)?;
+                                writer.write_u32(latency_ps)?;
+                            }
+                            None => writer.write_bool(false)?
+                        }
+                    }
+                }
+            }
+            Reply::LogSinks { levels, syslog, syslog_lost } => {
+                writer.write_u8(42)?;
+                for &level in &levels {
+                    writer.write_u8(level)?;
+                }
+                writer.write_string(syslog)?;
+                writer.write_u32(syslog_lost)?;
+            }
+            Reply::PowerOffReady { kernel_stopped, safe_state_outputs, sfp_tx_disabled } => {
+                writer.write_u8(43)?;
+                writer.write_bool(kernel_stopped)?;
+                writer.write_u32(safe_state_outputs)?;
+                writer.write_u8(sfp_tx_disabled)?;
+            }
+            Reply::KernelRunStats { dropped, runs } => {
+                writer.write_u8(44)?;
+                writer.write_u32(dropped)?;
+                writer.write_u32(runs.len() as u32)?;
+                for run in runs {
+                    run.write_to(writer)?;
+                }
+            }
+            Reply::Features(features) => {
+                writer.write_u8(45)?;
+                writer.write_u32(features)?;
+            }
+            Reply::FlashHealth { scrubbed_ms, scrubs, areas, failures, spares_free, remapped } => {
+                writer.write_u8(46)?;
+                match scrubbed_ms {
+                    Some(scrubbed_ms) => {
+                        writer.write_bool(true)?;
+                        writer.write_u64(scrubbed_ms)?;
+                    }
+                    None => writer.write_bool(false)?
+                }
+                writer.write_u32(scrubs)?;
+                writer.write_u32(areas.len() as u32)?;
+                for area in areas {
+                    writer.write_string(area.name)?;
+                    writer.write_u32(area.checked)?;
+                    writer.write_u32(area.corrupted)?;
+                }
+                writer.write_u32(failures)?;
+                writer.write_u8(spares_free)?;
+                writer.write_u32(remapped.len() as u32)?;
+                for sector in remapped {
+                    writer.write_u32(sector.sector)?;
+                    writer.write_u32(sector.spare)?;
+                }
+            }
+            Reply::ChannelEvents { group_size, events } => {
+                writer.write_u8(47)?;
+                writer.write_u16(group_size)?;
+                writer.write_u32(events.len() as u32)?;
+                for &count in events {
+                    writer.write_u32(count)?;
+                }
+            }
+            Reply::HealthRecords(records) => {
+                writer.write_u8(49)?;
+                writer.write_bytes(records)?;
+            }
+            Reply::Chunk { total, crc, offset, data } => {
+                writer.write_u8(48)?;
+                writer.write_u32(total)?;
+                writer.write_u32(crc)?;
+                writer.write_u32(offset)?;
+                writer.write_bytes(data)?;
+            }
+            Reply::LostLogRecords(lost) => {
+                writer.write_u8(31)?;
+                writer.write_u32(lost)?;
+            }
+            Reply::Compatibility { firmware, gateware } => {
+                writer.write_u8(30)?;
+                writer.write_u32(firmware)?;
+                writer.write_u32(gateware)?;
+            }
+            Reply::SensorHistory { interval_ms, sfp_ports, samples } => {
+                writer.write_u8(28)?;
+                writer.write_u32(interval_ms)?;
+                writer.write_u8(sfp_ports)?;
+                writer.write_u32(samples.len() as u32)?;
+                for sample in samples {
+                    writer.write_u64(sample.timestamp_ms)?;
+                    FpgaSensors::write_to(&sample.fpga, writer)?;
+                    for power in sample.sfp_power[..sfp_ports as usize].iter() {
+                        match *power {
+                            None => writer.write_bool(false)?,
+                            Some((tx_power, rx_power)) => {
+                                writer.write_bool(true)?;
+                                writer.write_u16(tx_power)?;
+                                writer.write_u16(rx_power)?;
+                            }
+                        }
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -35,7 +35,11 @@ pub enum HostMessage {
     MonitorProbe { enable: bool, channel: u32, probe: u8 },
     MonitorInjection { enable: bool, channel: u32, overrd: u8 },
     Inject { channel: u32, overrd: u8, value: u8 },
-    GetInjectionStatus { channel: u32, overrd: u8 }
+    GetInjectionStatus { channel: u32, overrd: u8 },
+    SetProbeInterval { interval_ms: u32 },
+    InjectPattern { channel: u32, overrd: u8, high_ms: u32, low_ms: u32, count: u32 },
+    // Sends all the monitored values again, e.g. after lost datagrams.
+    Resync
 }
 
 #[derive(Debug)]
@@ -68,6 +72,17 @@ impl HostMessage {
                 channel: reader.read_u32()?,
                 overrd: reader.read_u8()?
             },
+            4 => HostMessage::SetProbeInterval {
+                interval_ms: reader.read_u32()?
+            },
+            5 => HostMessage::InjectPattern {
+                channel: reader.read_u32()?,
+                overrd: reader.read_u8()?,
+                high_ms: reader.read_u32()?,
+                low_ms: reader.read_u32()?,
+                count: reader.read_u32()?
+            },
+            6 => HostMessage::Resync,
             ty => return Err(Error::UnknownPacket(ty))
         })
     }
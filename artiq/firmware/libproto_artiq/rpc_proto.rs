@@ -1,10 +1,51 @@
 use core::str;
 use core::slice;
+use core::mem;
 use cslice::{CSlice, CMutSlice};
 use byteorder::{NetworkEndian, ByteOrder};
-use io::{ProtoRead, Read, Write, ProtoWrite, Error};
+use io::{ProtoRead, Read, Write, ProtoWrite, BufWriter, Error};
 use self::tag::{Tag, TagIterator, split_tag};
 
+/// Sink for serialized RPC arguments.
+///
+/// Contiguous buffers of primitive values (bytes, and lists or arrays of
+/// booleans, integers, floats and complex numbers) are passed to `write_bulk`, which
+/// implementors may override to transmit the buffer by reference instead of
+/// copying it.
+pub trait RpcWrite: Write {
+    fn write_bulk(&mut self, data: &[u8]) -> Result<(), Error<Self::WriteError>> {
+        self.write_all(data)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl RpcWrite for ::alloc::Vec<u8> {}
+
+impl<'a, W: RpcWrite + ?Sized> RpcWrite for BufWriter<'a, W> {
+    fn write_bulk(&mut self, data: &[u8]) -> Result<(), Error<Self::WriteError>> {
+        self.flush()?;
+        self.get_mut().write_bulk(data)
+    }
+}
+
+// Values are laid out as the kernel compiler lays out the corresponding
+// LLVM types, i.e. each one aligned to its natural alignment, and tuples
+// padded to the alignment of their most aligned element.
+#[inline]
+fn round_up(value: usize, alignment: usize) -> usize {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+#[inline]
+fn align_ptr<T>(ptr: *const ()) -> *const T {
+    round_up(ptr as usize, mem::align_of::<T>()) as *const T
+}
+
+#[inline]
+fn align_ptr_mut<T>(ptr: *mut ()) -> *mut T {
+    round_up(ptr as usize, mem::align_of::<T>()) as *mut T
+}
+
 unsafe fn recv_value<R, E>(reader: &mut R, tag: Tag, data: &mut *mut (),
                            alloc: &Fn(usize) -> Result<*mut (), E>)
                           -> Result<(), E>
@@ -13,7 +54,7 @@ unsafe fn recv_value<R, E>(reader: &mut R, tag: Tag, data: &mut *mut (),
 {
     macro_rules! consume_value {
         ($ty:ty, |$ptr:ident| $map:expr) => ({
-            let $ptr = (*data) as *mut $ty;
+            let $ptr = align_ptr_mut::<$ty>(*data);
             *data = $ptr.offset(1) as *mut ();
             $map
         })
@@ -33,6 +74,22 @@ unsafe fn recv_value<R, E>(reader: &mut R, tag: Tag, data: &mut *mut (),
             consume_value!(u64, |ptr| {
                 *ptr = reader.read_u64()?; Ok(())
             }),
+        Tag::Float16 =>
+            consume_value!(u16, |ptr| {
+                *ptr = reader.read_u16()?; Ok(())
+            }),
+        Tag::Complex64 =>
+            consume_value!([u32; 2], |ptr| {
+                (*ptr)[0] = reader.read_u32()?;
+                (*ptr)[1] = reader.read_u32()?;
+                Ok(())
+            }),
+        Tag::Complex128 =>
+            consume_value!([u64; 2], |ptr| {
+                (*ptr)[0] = reader.read_u64()?;
+                (*ptr)[1] = reader.read_u64()?;
+                Ok(())
+            }),
         Tag::String | Tag::Bytes | Tag::ByteArray => {
             consume_value!(CMutSlice<u8>, |ptr| {
                 let length = reader.read_u32()? as usize;
@@ -47,6 +104,7 @@ unsafe fn recv_value<R, E>(reader: &mut R, tag: Tag, data: &mut *mut (),
                 let tag = it.next().expect("truncated tag");
                 recv_value(reader, tag, data, alloc)?
             }
+            *data = round_up(*data as usize, tag.alignment()) as *mut ();
             Ok(())
         }
         Tag::List(it) => {
@@ -77,6 +135,24 @@ unsafe fn recv_value<R, E>(reader: &mut R, tag: Tag, data: &mut *mut (),
                         let dest = slice::from_raw_parts_mut(data as *mut i64, length);
                         NetworkEndian::from_slice_i64(dest);
                     },
+                    Tag::Float16 => {
+                        let dest = slice::from_raw_parts_mut(data as *mut u8, length * 2);
+                        reader.read_exact(dest)?;
+                        let dest = slice::from_raw_parts_mut(data as *mut u16, length);
+                        NetworkEndian::from_slice_u16(dest);
+                    },
+                    Tag::Complex64 => {
+                        let dest = slice::from_raw_parts_mut(data as *mut u8, length * 8);
+                        reader.read_exact(dest)?;
+                        let dest = slice::from_raw_parts_mut(data as *mut u32, length * 2);
+                        NetworkEndian::from_slice_u32(dest);
+                    },
+                    Tag::Complex128 => {
+                        let dest = slice::from_raw_parts_mut(data as *mut u8, length * 16);
+                        reader.read_exact(dest)?;
+                        let dest = slice::from_raw_parts_mut(data as *mut u64, length * 2);
+                        NetworkEndian::from_slice_u64(dest);
+                    },
                     _ => {
                         for _ in 0..length {
                             recv_value(reader, tag, &mut data, alloc)?
@@ -117,6 +193,24 @@ unsafe fn recv_value<R, E>(reader: &mut R, tag: Tag, data: &mut *mut (),
                         let dest = slice::from_raw_parts_mut(data as *mut i64, length);
                         NetworkEndian::from_slice_i64(dest);
                     },
+                    Tag::Float16 => {
+                        let dest = slice::from_raw_parts_mut(data as *mut u8, length * 2);
+                        reader.read_exact(dest)?;
+                        let dest = slice::from_raw_parts_mut(data as *mut u16, length);
+                        NetworkEndian::from_slice_u16(dest);
+                    },
+                    Tag::Complex64 => {
+                        let dest = slice::from_raw_parts_mut(data as *mut u8, length * 8);
+                        reader.read_exact(dest)?;
+                        let dest = slice::from_raw_parts_mut(data as *mut u32, length * 2);
+                        NetworkEndian::from_slice_u32(dest);
+                    },
+                    Tag::Complex128 => {
+                        let dest = slice::from_raw_parts_mut(data as *mut u8, length * 16);
+                        reader.read_exact(dest)?;
+                        let dest = slice::from_raw_parts_mut(data as *mut u64, length * 2);
+                        NetworkEndian::from_slice_u64(dest);
+                    },
                     _ => {
                         for _ in 0..length {
                             recv_value(reader, elt_tag, &mut data, alloc)?
@@ -157,11 +251,11 @@ pub fn recv_return<R, E>(reader: &mut R, tag_bytes: &[u8], data: *mut (),
 
 unsafe fn send_value<W>(writer: &mut W, tag: Tag, data: &mut *const ())
                        -> Result<(), Error<W::WriteError>>
-    where W: Write + ?Sized
+    where W: RpcWrite + ?Sized
 {
     macro_rules! consume_value {
         ($ty:ty, |$ptr:ident| $map:expr) => ({
-            let $ptr = (*data) as *const $ty;
+            let $ptr = align_ptr::<$ty>(*data);
             *data = $ptr.offset(1) as *const ();
             $map
         })
@@ -179,12 +273,27 @@ unsafe fn send_value<W>(writer: &mut W, tag: Tag, data: &mut *const ())
         Tag::Int64 | Tag::Float64 =>
             consume_value!(u64, |ptr|
                 writer.write_u64(*ptr)),
+        Tag::Float16 =>
+            consume_value!(u16, |ptr|
+                writer.write_u16(*ptr)),
+        Tag::Complex64 =>
+            consume_value!([u32; 2], |ptr| {
+                writer.write_u32((*ptr)[0])?;
+                writer.write_u32((*ptr)[1])
+            }),
+        Tag::Complex128 =>
+            consume_value!([u64; 2], |ptr| {
+                writer.write_u64((*ptr)[0])?;
+                writer.write_u64((*ptr)[1])
+            }),
         Tag::String =>
             consume_value!(CSlice<u8>, |ptr|
                 writer.write_string(str::from_utf8((*ptr).as_ref()).unwrap())),
         Tag::Bytes | Tag::ByteArray =>
-            consume_value!(CSlice<u8>, |ptr|
-                writer.write_bytes((*ptr).as_ref())),
+            consume_value!(CSlice<u8>, |ptr| {
+                writer.write_u32((*ptr).as_ref().len() as u32)?;
+                writer.write_bulk((*ptr).as_ref())
+            }),
         Tag::Tuple(it, arity) => {
             let mut it = it.clone();
             writer.write_u8(arity)?;
@@ -192,6 +301,7 @@ unsafe fn send_value<W>(writer: &mut W, tag: Tag, data: &mut *const ())
                 let tag = it.next().expect("truncated tag");
                 send_value(writer, tag, data)?
             }
+            *data = round_up(*data as usize, tag.alignment()) as *const ();
             Ok(())
         }
         Tag::List(it) => {
@@ -208,15 +318,27 @@ unsafe fn send_value<W>(writer: &mut W, tag: Tag, data: &mut *const ())
                     // and that is not needed as the data is already in network endian
                     Tag::Bool => {
                         let slice = slice::from_raw_parts(data as *const u8, length);
-                        writer.write_all(slice)?;
+                        writer.write_bulk(slice)?;
                     },
                     Tag::Int32 => {
                         let slice = slice::from_raw_parts(data as *const u8, length * 4);
-                        writer.write_all(slice)?;
+                        writer.write_bulk(slice)?;
                     },
                     Tag::Int64 | Tag::Float64 => {
                         let slice = slice::from_raw_parts(data as *const u8, length * 8);
-                        writer.write_all(slice)?;
+                        writer.write_bulk(slice)?;
+                    },
+                    Tag::Float16 => {
+                        let slice = slice::from_raw_parts(data as *const u8, length * 2);
+                        writer.write_bulk(slice)?;
+                    },
+                    Tag::Complex64 => {
+                        let slice = slice::from_raw_parts(data as *const u8, length * 8);
+                        writer.write_bulk(slice)?;
+                    },
+                    Tag::Complex128 => {
+                        let slice = slice::from_raw_parts(data as *const u8, length * 16);
+                        writer.write_bulk(slice)?;
                     },
                     _ => {
                         for _ in 0..length {
@@ -241,21 +363,36 @@ unsafe fn send_value<W>(writer: &mut W, tag: Tag, data: &mut *const ())
                 }
                 let length = total_len as usize;
                 let mut data = *buffer;
+                if elt_tag.is_record() {
+                    return send_records(writer, elt_tag, length, &mut data)
+                }
                 writer.write_u8(elt_tag.as_u8())?;
                 match elt_tag {
                     // we cannot use NetworkEndian::from_slice_i32 as the data is not mutable,
                     // and that is not needed as the data is already in network endian
                     Tag::Bool => {
                         let slice = slice::from_raw_parts(data as *const u8, length);
-                        writer.write_all(slice)?;
+                        writer.write_bulk(slice)?;
                     },
                     Tag::Int32 => {
                         let slice = slice::from_raw_parts(data as *const u8, length * 4);
-                        writer.write_all(slice)?;
+                        writer.write_bulk(slice)?;
                     },
                     Tag::Int64 | Tag::Float64 => {
                         let slice = slice::from_raw_parts(data as *const u8, length * 8);
-                        writer.write_all(slice)?;
+                        writer.write_bulk(slice)?;
+                    },
+                    Tag::Float16 => {
+                        let slice = slice::from_raw_parts(data as *const u8, length * 2);
+                        writer.write_bulk(slice)?;
+                    },
+                    Tag::Complex64 => {
+                        let slice = slice::from_raw_parts(data as *const u8, length * 8);
+                        writer.write_bulk(slice)?;
+                    },
+                    Tag::Complex128 => {
+                        let slice = slice::from_raw_parts(data as *const u8, length * 16);
+                        writer.write_bulk(slice)?;
                     },
                     _ => {
                         for _ in 0..length {
@@ -294,9 +431,69 @@ unsafe fn send_value<W>(writer: &mut W, tag: Tag, data: &mut *const ())
     }
 }
 
+// Arrays of records, i.e. of tuples of booleans, integers, floats and
+// complex numbers, are
+// sent as the tag of the record followed by the fields of every element,
+// without the per-element tags, so that the host can read them at once into
+// a structured array.
+unsafe fn send_records<W>(writer: &mut W, tag: Tag, length: usize, data: &mut *const ())
+                         -> Result<(), Error<W::WriteError>>
+    where W: RpcWrite + ?Sized
+{
+    let (it, arity) = match tag {
+        Tag::Tuple(it, arity) => (it, arity),
+        _ => unreachable!()
+    };
+    writer.write_u8(tag.as_u8())?;
+    writer.write_u8(arity)?;
+    writer.write_all(it.as_bytes())?;
+    for _ in 0..length {
+        let mut it = it.clone();
+        for _ in 0..arity {
+            match it.next().expect("truncated tag") {
+                Tag::Bool => {
+                    let ptr = align_ptr::<u8>(*data);
+                    writer.write_u8(*ptr)?;
+                    *data = ptr.offset(1) as *const ();
+                }
+                Tag::Int32 => {
+                    let ptr = align_ptr::<u32>(*data);
+                    writer.write_u32(*ptr)?;
+                    *data = ptr.offset(1) as *const ();
+                }
+                Tag::Int64 | Tag::Float64 => {
+                    let ptr = align_ptr::<u64>(*data);
+                    writer.write_u64(*ptr)?;
+                    *data = ptr.offset(1) as *const ();
+                }
+                Tag::Float16 => {
+                    let ptr = align_ptr::<u16>(*data);
+                    writer.write_u16(*ptr)?;
+                    *data = ptr.offset(1) as *const ();
+                }
+                Tag::Complex64 => {
+                    let ptr = align_ptr::<[u32; 2]>(*data);
+                    writer.write_u32((*ptr)[0])?;
+                    writer.write_u32((*ptr)[1])?;
+                    *data = ptr.offset(1) as *const ();
+                }
+                Tag::Complex128 => {
+                    let ptr = align_ptr::<[u64; 2]>(*data);
+                    writer.write_u64((*ptr)[0])?;
+                    writer.write_u64((*ptr)[1])?;
+                    *data = ptr.offset(1) as *const ();
+                }
+                _ => unreachable!()
+            }
+        }
+        *data = round_up(*data as usize, tag.alignment()) as *const ();
+    }
+    Ok(())
+}
+
 pub fn send_args<W>(writer: &mut W, service: u32, tag_bytes: &[u8], data: *const *const ())
                    -> Result<(), Error<W::WriteError>>
-    where W: Write + ?Sized
+    where W: RpcWrite + ?Sized
 {
     let (arg_tags_bytes, return_tag_bytes) = split_tag(tag_bytes);
 
@@ -323,7 +520,7 @@ pub fn send_args<W>(writer: &mut W, service: u32, tag_bytes: &[u8], data: *const
 }
 
 mod tag {
-    use core::fmt;
+    use core::{fmt, cmp, mem};
 
     pub fn split_tag(tag_bytes: &[u8]) -> (&[u8], &[u8]) {
         let tag_separator =
@@ -342,6 +539,9 @@ mod tag {
         Int32,
         Int64,
         Float64,
+        Float16,
+        Complex64,
+        Complex128,
         String,
         Bytes,
         ByteArray,
@@ -361,6 +561,9 @@ mod tag {
                 Tag::Int32 => b'i',
                 Tag::Int64 => b'I',
                 Tag::Float64 => b'f',
+                Tag::Float16 => b'h',
+                Tag::Complex64 => b'c',
+                Tag::Complex128 => b'C',
                 Tag::String => b's',
                 Tag::Bytes => b'B',
                 Tag::ByteArray => b'A',
@@ -380,6 +583,9 @@ mod tag {
                 Tag::Int32 => 4,
                 Tag::Int64 => 8,
                 Tag::Float64 => 8,
+                Tag::Float16 => 2,
+                Tag::Complex64 => 8,
+                Tag::Complex128 => 16,
                 Tag::String => 8,
                 Tag::Bytes => 8,
                 Tag::ByteArray => 8,
@@ -388,9 +594,9 @@ mod tag {
                     let mut it = it.clone();
                     for _ in 0..arity {
                         let tag = it.next().expect("truncated tag");
-                        size += tag.size();
+                        size = super::round_up(size, tag.alignment()) + tag.size();
                     }
-                    size
+                    super::round_up(size, self.alignment())
                 }
                 Tag::List(_) => 8,
                 Tag::Array(_, num_dims) => 4 * (1 + num_dims as usize),
@@ -402,6 +608,47 @@ mod tag {
                 Tag::Object => unreachable!(),
             }
         }
+
+        pub fn alignment(self) -> usize {
+            match self {
+                Tag::None => 1,
+                Tag::Bool => mem::align_of::<u8>(),
+                Tag::Int32 => mem::align_of::<u32>(),
+                Tag::Int64 | Tag::Float64 | Tag::Complex128 => mem::align_of::<u64>(),
+                Tag::Float16 => mem::align_of::<u16>(),
+                Tag::Complex64 => mem::align_of::<u32>(),
+                Tag::String | Tag::Bytes | Tag::ByteArray |
+                Tag::List(_) | Tag::Array(_, _) => mem::align_of::<*const ()>(),
+                Tag::Tuple(it, arity) => {
+                    let mut alignment = 1;
+                    let mut it = it.clone();
+                    for _ in 0..arity {
+                        let tag = it.next().expect("truncated tag");
+                        alignment = cmp::max(alignment, tag.alignment());
+                    }
+                    alignment
+                }
+                Tag::Range(it) => it.clone().next().expect("truncated tag").alignment(),
+                Tag::Keyword(_) => unreachable!(),
+                Tag::Object => unreachable!(),
+            }
+        }
+
+        /// Whether this is a tuple of booleans, integers, floats and complex
+        /// numbers only.
+        pub fn is_record(self) -> bool {
+            match self {
+                Tag::Tuple(it, arity) => {
+                    let mut it = it.clone();
+                    (0..arity).all(|_| match it.next().expect("truncated tag") {
+                        Tag::Bool | Tag::Int32 | Tag::Int64 | Tag::Float64 |
+                        Tag::Float16 | Tag::Complex64 | Tag::Complex128 => true,
+                        _ => false
+                    })
+                }
+                _ => false
+            }
+        }
     }
 
     #[derive(Debug, Clone, Copy)]
@@ -414,6 +661,10 @@ mod tag {
             TagIterator { data: data }
         }
 
+        pub fn as_bytes(&self) -> &'a [u8] {
+            self.data
+        }
+
         pub fn next(&mut self) -> Option<Tag<'a>> {
             if self.data.len() == 0 {
                 return None
@@ -427,6 +678,9 @@ mod tag {
                 b'i' => Tag::Int32,
                 b'I' => Tag::Int64,
                 b'f' => Tag::Float64,
+                b'h' => Tag::Float16,
+                b'c' => Tag::Complex64,
+                b'C' => Tag::Complex128,
                 b's' => Tag::String,
                 b'B' => Tag::Bytes,
                 b'A' => Tag::ByteArray,
@@ -479,6 +733,12 @@ mod tag {
                         write!(f, "Int64")?,
                     Tag::Float64 =>
                         write!(f, "Float64")?,
+                    Tag::Float16 =>
+                        write!(f, "Float16")?,
+                    Tag::Complex64 =>
+                        write!(f, "Complex64")?,
+                    Tag::Complex128 =>
+                        write!(f, "Complex128")?,
                     Tag::String =>
                         write!(f, "String")?,
                     Tag::Bytes =>
@@ -1,7 +1,7 @@
 use core::str::Utf8Error;
 use alloc::{Vec, String};
 
-use io::{Read, ProtoRead, Write, ProtoWrite, Error as IoError, ReadStringError};
+use io::{Read, ProtoRead, Write, ProtoWrite, BufWriter, Error as IoError, ReadStringError};
 
 #[derive(Fail, Debug)]
 pub enum Error<T> {
@@ -30,17 +30,83 @@ impl<T> From<ReadStringError<IoError<T>>> for Error<T> {
     }
 }
 
-pub fn read_magic<R>(reader: &mut R) -> Result<(), Error<R::ReadError>>
+/// Version of the session protocol, sent in `Hello`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Optional parts of the session protocol. A host that does not send
+/// `Hello` is assumed to support all of those that predate it.
+pub mod capability {
+    /// Board identity and boot statistics in `SystemInfo`.
+    pub const SYSTEM_INFO_EXTENDED: u32 = 1 << 0;
+    /// Symbol names and context in `KernelException`.
+    pub const EXCEPTION_DETAILS:    u32 = 1 << 1;
+    /// `Notification` messages.
+    pub const NOTIFICATIONS:        u32 = 1 << 2;
+    /// `Compressed` messages.
+    pub const COMPRESSION:          u32 = 1 << 3;
+    /// `LoadKernelDelta` requests.
+    pub const DELTA_LOAD:           u32 = 1 << 4;
+    /// `DatasetAppend` messages.
+    pub const DATASET_APPEND:       u32 = 1 << 5;
+    /// `KernelRunStats` messages.
+    pub const RUN_STATS:            u32 = 1 << 6;
+    /// Device features in the `Hello` reply, see `mgmt_proto::feature`.
+    pub const FEATURES:             u32 = 1 << 7;
+    /// Session token in the `Hello` reply, see `SessionKind::Resume`.
+    pub const RESUME_TOKEN:         u32 = 1 << 8;
+
+    pub const ALL: u32 = SYSTEM_INFO_EXTENDED | EXCEPTION_DETAILS | NOTIFICATIONS | COMPRESSION |
+                         DELTA_LOAD | DATASET_APPEND | RUN_STATS | FEATURES | RESUME_TOKEN;
+    /// Capabilities of a host that does not send `Hello`.
+    pub const DEFAULT: u32 = SYSTEM_INFO_EXTENDED | EXCEPTION_DETAILS | NOTIFICATIONS;
+}
+
+/// Operations of the patch of a `LoadKernelDelta` request, applied in order
+/// to rebuild the kernel.
+pub mod delta {
+    /// Followed by an offset and a length (u32): copies that range of the
+    /// base kernel.
+    pub const COPY:   u8 = 0;
+    /// Followed by bytes (u32 length and data): inserts them.
+    pub const INSERT: u8 = 1;
+}
+
+/// Kind of session requested by the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionKind {
+    /// Full session that loads and runs kernels; only one exists at a time.
+    Kernel,
+    /// Diagnostic session that may run concurrently with the kernel session.
+    ReadOnly,
+    /// Reconnection of a host to a kernel session whose connection was lost.
+    /// The magic is followed by the token of the session from its `Hello`
+    /// reply, once the host is authenticated.
+    Resume
+}
+
+/// Size of the token that identifies a kernel session when it is resumed.
+pub const TOKEN_SIZE: usize = 16;
+
+/// Size of the magic sent by the host at the start of a connection.
+pub const MAGIC_SIZE: usize = 14;
+
+pub fn read_magic<R>(reader: &mut R) -> Result<SessionKind, Error<R::ReadError>>
     where R: Read + ?Sized
 {
-    const MAGIC: &'static [u8] = b"ARTIQ coredev\n";
+    const KERNEL_MAGIC: &'static [u8] = b"ARTIQ coredev\n";
+    const READ_ONLY_MAGIC: &'static [u8] = b"ARTIQ coremon\n";
+    const RESUME_MAGIC: &'static [u8] = b"ARTIQ coreres\n";
 
-    let mut magic: [u8; 14] = [0; 14];
+    let mut magic: [u8; MAGIC_SIZE] = [0; MAGIC_SIZE];
     reader.read_exact(&mut magic)?;
-    if magic != MAGIC {
-        Err(Error::WrongMagic)
+    if magic == KERNEL_MAGIC {
+        Ok(SessionKind::Kernel)
+    } else if magic == READ_ONLY_MAGIC {
+        Ok(SessionKind::ReadOnly)
+    } else if magic == RESUME_MAGIC {
+        Ok(SessionKind::Resume)
     } else {
-        Ok(())
+        Err(Error::WrongMagic)
     }
 }
 
@@ -64,8 +130,22 @@ fn write_sync<W>(writer: &mut W) -> Result<(), IoError<W::WriteError>>
 #[derive(Debug)]
 pub enum Request {
     SystemInfo,
+    Hello { version: u32, capabilities: u32 },
 
     LoadKernel(Vec<u8>),
+    /// `LoadKernel` or `LoadKernelDelta` request whose kernel or patch of
+    /// `length` bytes was skipped, as it was larger than the limit given to
+    /// `read_from`.
+    LoadKernelTooLarge { length: u32 },
+    /// Kernel rebuilt from a previously loaded kernel, whose FNV-1a hash is
+    /// `base`, and a patch against it; see `delta`. The rebuilt kernel is
+    /// `length` bytes long and its hash is `hash`.
+    LoadKernelDelta {
+        base:   u32,
+        hash:   u32,
+        length: u32,
+        patch:  Vec<u8>
+    },
     RunKernel,
 
     RpcReply { tag: Vec<u8> },
@@ -78,20 +158,39 @@ pub enum Request {
         column:   u32,
         function: String,
     },
+
+    GetLog,
+    GetHealth,
+    GetAnalyzerDump,
 }
 
 #[derive(Debug)]
 pub enum Reply<'a> {
     SystemInfo {
         ident: &'a str,
-        finished_cleanly: bool
+        finished_cleanly: bool,
+        // The following are only sent with capability::SYSTEM_INFO_EXTENDED.
+        extended: bool,
+        board: Option<::mgmt_proto::BoardIdentity<'a>>,
+        // Number of boots and uptime in seconds over all of them.
+        boot_stats: Option<(u32, u32)>
     },
+    /// `features` is only sent with capability::FEATURES, and `token` with
+    /// capability::RESUME_TOKEN.
+    Hello { version: u32, capabilities: u32, features: Option<u32>,
+            token: Option<[u8; TOKEN_SIZE]> },
 
     LoadCompleted,
     LoadFailed(&'a str),
+    /// The base of a `LoadKernelDelta` is not available, or the kernel
+    /// rebuilt from it does not match; the host should send the whole kernel.
+    LoadBaseMissing,
 
     KernelFinished,
     KernelStartupFailed,
+    /// Statistics of the kernel run, sent before `KernelFinished` or
+    /// `KernelException`.
+    KernelRunStats(::mgmt_proto::KernelRunStats<'a>),
     KernelException {
         name:      &'a str,
         message:   &'a str,
@@ -100,23 +199,112 @@ pub enum Reply<'a> {
         line:      u32,
         column:    u32,
         function:  &'a str,
-        backtrace: &'a [usize]
+        backtrace: &'a [usize],
+        // Whether to send `symbols` and `context`, see
+        // capability::EXCEPTION_DETAILS.
+        details:   bool,
+        // Names of the functions the backtrace entries point into, or empty.
+        symbols:   &'a [&'a str],
+        // Exceptions being handled when this one was raised, oldest first.
+        context:   &'a [::kernel_proto::Exception<'a>]
     },
 
     RpcRequest { async: bool },
+    Notification {
+        // Notifications dropped by the kernel before this one.
+        dropped: u32,
+        tag:     &'a str,
+        data:    &'a [u8]
+    },
+    /// Batch of `count` values appended to datasets by the kernel, each
+    /// serialized in `data` as its key (string) and value (f64).
+    DatasetAppend {
+        count: u32,
+        data:  &'a [u8]
+    },
 
+    WatchdogExpired {
+        scope:      &'a str,
+        timeout_ms: u64,
+        elapsed_ms: u64
+    },
+    /// The kernel was stopped after its termination was requested over mgmt.
+    KernelTerminated { reason: &'a str },
     ClockFailure,
+
+    Log(&'a str),
+    Health {
+        rtio_clock_ok: bool,
+        rtio_frequency: u32,
+        clock_fallback: bool,
+        uptime_ms: u64
+    },
+    /// Followed by an analyzer dump in the format used on the analyzer port.
+    AnalyzerDump,
+    Unavailable,
+    SessionResumed,
+    /// LZ4 block holding one or more complete messages, which are `length`
+    /// bytes long once decompressed.
+    Compressed {
+        length: u32,
+        data:   &'a [u8]
+    },
+}
+
+// Reads bytes of at most `max_length`, or skips them and returns their length.
+fn read_limited<R>(reader: &mut R, max_length: usize) -> Result<Result<Vec<u8>, u32>, Error<R::ReadError>>
+    where R: Read + ?Sized
+{
+    let length = reader.read_u32()?;
+    if length as usize <= max_length {
+        let mut data = Vec::new();
+        data.resize(length as usize, 0);
+        reader.read_exact(&mut data)?;
+        return Ok(Ok(data))
+    }
+    let mut buffer = [0; 64];
+    let mut remaining = length as usize;
+    while remaining > 0 {
+        let chunk = remaining.min(buffer.len());
+        reader.read_exact(&mut buffer[..chunk])?;
+        remaining -= chunk;
+    }
+    Ok(Err(length))
 }
 
 impl Request {
-    pub fn read_from<R>(reader: &mut R) -> Result<Self, Error<R::ReadError>>
+    /// Reads a request. Kernels and kernel patches longer than
+    /// `max_kernel_length` are skipped instead of being allocated.
+    pub fn read_from<R>(reader: &mut R, max_kernel_length: usize) -> Result<Self, Error<R::ReadError>>
         where R: Read + ?Sized
     {
         read_sync(reader)?;
         Ok(match reader.read_u8()? {
             3  => Request::SystemInfo,
+            12 => Request::Hello {
+                version:      reader.read_u32()?,
+                capabilities: reader.read_u32()?
+            },
 
-            5  => Request::LoadKernel(reader.read_bytes()?),
+            5  => match read_limited(reader, max_kernel_length)? {
+                Ok(kernel) => Request::LoadKernel(kernel),
+                Err(length) => Request::LoadKernelTooLarge { length: length }
+            },
+            13 => {
+                let base = reader.read_u32()?;
+                let hash = reader.read_u32()?;
+                let length = reader.read_u32()?;
+                match read_limited(reader, max_kernel_length)? {
+                    Ok(patch) if length as usize <= max_kernel_length =>
+                        Request::LoadKernelDelta {
+                            base:   base,
+                            hash:   hash,
+                            length: length,
+                            patch:  patch
+                        },
+                    _ => Request::LoadKernelTooLarge { length: length }
+                }
+            },
             6  => Request::RunKernel,
 
             7  => Request::RpcReply {
@@ -134,6 +322,10 @@ impl Request {
                 function: reader.read_string()?
             },
 
+            9  => Request::GetLog,
+            10 => Request::GetHealth,
+            11 => Request::GetAnalyzerDump,
+
             ty  => return Err(Error::UnknownPacket(ty))
         })
     }
@@ -142,14 +334,55 @@ impl Request {
 impl<'a> Reply<'a> {
     pub fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
         where W: Write + ?Sized
+    {
+        // Most fields are a few bytes long; gather them so that the stream
+        // is not written to for each of them.
+        let mut buffer = [0; 256];
+        let mut writer = BufWriter::new(writer, &mut buffer);
+        self.write_fields(&mut writer)?;
+        writer.flush()
+    }
+
+    fn write_fields<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
     {
         write_sync(writer)?;
         match *self {
-            Reply::SystemInfo { ident, finished_cleanly } => {
+            Reply::SystemInfo { ident, finished_cleanly, extended, board, boot_stats } => {
                 writer.write_u8(2)?;
                 writer.write(b"AROR")?;
                 writer.write_string(ident)?;
                 writer.write_u8(finished_cleanly as u8)?;
+                if !extended {
+                    return Ok(())
+                }
+                match board {
+                    None => writer.write_u8(0)?,
+                    Some(board) => {
+                        writer.write_u8(1)?;
+                        board.write_to(writer)?;
+                    }
+                }
+                match boot_stats {
+                    None => writer.write_u8(0)?,
+                    Some((boot_count, uptime_s)) => {
+                        writer.write_u8(1)?;
+                        writer.write_u32(boot_count)?;
+                        writer.write_u32(uptime_s)?;
+                    }
+                }
+            },
+
+            Reply::Hello { version, capabilities, features, token } => {
+                writer.write_u8(22)?;
+                writer.write_u32(version)?;
+                writer.write_u32(capabilities)?;
+                if let Some(features) = features {
+                    writer.write_u32(features)?;
+                }
+                if let Some(token) = token {
+                    writer.write_all(&token)?;
+                }
             },
 
             Reply::LoadCompleted => {
@@ -159,6 +392,9 @@ impl<'a> Reply<'a> {
                 writer.write_u8(6)?;
                 writer.write_string(reason)?;
             },
+            Reply::LoadBaseMissing => {
+                writer.write_u8(24)?;
+            },
 
             Reply::KernelFinished => {
                 writer.write_u8(7)?;
@@ -167,7 +403,8 @@ impl<'a> Reply<'a> {
                 writer.write_u8(8)?;
             },
             Reply::KernelException {
-                name, message, param, file, line, column, function, backtrace
+                name, message, param, file, line, column, function, backtrace, details,
+                symbols, context
             } => {
                 writer.write_u8(9)?;
                 writer.write_string(name)?;
@@ -183,6 +420,24 @@ impl<'a> Reply<'a> {
                 for &addr in backtrace {
                     writer.write_u32(addr as u32)?
                 }
+                if !details {
+                    return Ok(())
+                }
+                for &symbol in symbols {
+                    writer.write_string(symbol)?
+                }
+                writer.write_u8(context.len() as u8)?;
+                for exception in context {
+                    writer.write_string(exception.name)?;
+                    writer.write_string(exception.message)?;
+                    writer.write_u64(exception.param[0] as u64)?;
+                    writer.write_u64(exception.param[1] as u64)?;
+                    writer.write_u64(exception.param[2] as u64)?;
+                    writer.write_string(exception.file)?;
+                    writer.write_u32(exception.line)?;
+                    writer.write_u32(exception.column)?;
+                    writer.write_string(exception.function)?;
+                }
             },
 
             Reply::RpcRequest { async } => {
@@ -190,9 +445,60 @@ impl<'a> Reply<'a> {
                 writer.write_u8(async as u8)?;
             },
 
+            Reply::WatchdogExpired { scope, timeout_ms, elapsed_ms } => {
+                writer.write_u8(14)?;
+                writer.write_string(scope)?;
+                writer.write_u64(timeout_ms)?;
+                writer.write_u64(elapsed_ms)?;
+            },
+            Reply::KernelTerminated { reason } => {
+                writer.write_u8(25)?;
+                writer.write_string(reason)?;
+            },
+            Reply::KernelRunStats(ref run) => {
+                writer.write_u8(27)?;
+                run.write_to(writer)?;
+            },
             Reply::ClockFailure => {
                 writer.write_u8(15)?;
             },
+
+            Reply::Log(log) => {
+                writer.write_u8(16)?;
+                writer.write_string(log)?;
+            },
+            Reply::Health { rtio_clock_ok, rtio_frequency, clock_fallback, uptime_ms } => {
+                writer.write_u8(17)?;
+                writer.write_bool(rtio_clock_ok)?;
+                writer.write_u32(rtio_frequency)?;
+                writer.write_bool(clock_fallback)?;
+                writer.write_u64(uptime_ms)?;
+            },
+            Reply::AnalyzerDump => {
+                writer.write_u8(18)?;
+            },
+            Reply::Unavailable => {
+                writer.write_u8(19)?;
+            },
+            Reply::SessionResumed => {
+                writer.write_u8(20)?;
+            },
+            Reply::Notification { dropped, tag, data } => {
+                writer.write_u8(21)?;
+                writer.write_u32(dropped)?;
+                writer.write_string(tag)?;
+                writer.write_bytes(data)?;
+            },
+            Reply::DatasetAppend { count, data } => {
+                writer.write_u8(26)?;
+                writer.write_u32(count)?;
+                writer.write_bytes(data)?;
+            },
+            Reply::Compressed { length, data } => {
+                writer.write_u8(23)?;
+                writer.write_u32(length)?;
+                writer.write_bytes(data)?;
+            },
         }
         Ok(())
     }
@@ -0,0 +1,119 @@
+use alloc::{Vec, String};
+use board_misoc::clock;
+use health_record::RecordSet;
+
+// Alerts are conditions a host monitoring the core device should be told
+// about even if it is not reading the log. They are kept until a host
+// subscribed with `SubscribeAlerts` acknowledges them, and are sent most
+// severe first. When the queue is full, the oldest of the least severe
+// alerts is dropped to make room for an alert at least as severe. Alerts
+// may carry the health records of their condition, e.g. the diagnostics of
+// an SFP port, for hosts that subscribe with records.
+
+const CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info     = 0,
+    Warning  = 1,
+    Critical = 2
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Sfp         = 0,
+    Clock       = 1,
+    Drtio       = 2,
+    Temperature = 3,
+    Watchdog    = 4,
+    SelfTest    = 5,
+    Eem         = 6,
+    Interlock   = 7
+}
+
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub sequence:     u32,
+    pub timestamp_ms: u64,
+    pub severity:     Severity,
+    pub source:       Source,
+    pub message:      String,
+    /// Encoded record set, empty if the alert has none.
+    pub records:      Vec<u8>
+}
+
+struct Queue {
+    alerts:        Vec<Alert>,
+    next_sequence: u32,
+    dropped:       u32
+}
+
+static mut QUEUE: Option<Queue> = None;
+
+fn queue() -> &'static mut Queue {
+    unsafe {
+        if QUEUE.is_none() {
+            QUEUE = Some(Queue {
+                alerts: Vec::with_capacity(CAPACITY),
+                next_sequence: 1,
+                dropped: 0
+            })
+        }
+        QUEUE.as_mut().unwrap()
+    }
+}
+
+pub fn push(severity: Severity, source: Source, message: String) {
+    enqueue(severity, source, message, Vec::new())
+}
+
+pub fn push_with_records(severity: Severity, source: Source, message: String,
+                         records: RecordSet) {
+    enqueue(severity, source, message, records.into_bytes())
+}
+
+fn enqueue(severity: Severity, source: Source, message: String, records: Vec<u8>) {
+    let queue = queue();
+    let sequence = queue.next_sequence;
+    queue.next_sequence = queue.next_sequence.wrapping_add(1);
+
+    if queue.alerts.len() == CAPACITY {
+        let victim = queue.alerts.iter().enumerate()
+            .min_by_key(|&(_, alert)| (alert.severity, alert.sequence))
+            .map(|(index, alert)| (index, alert.severity));
+        match victim {
+            Some((index, victim_severity)) if victim_severity <= severity => {
+                queue.alerts.remove(index);
+            }
+            _ => {
+                queue.dropped = queue.dropped.wrapping_add(1);
+                return
+            }
+        }
+        queue.dropped = queue.dropped.wrapping_add(1);
+    }
+    queue.alerts.push(Alert {
+        sequence: sequence,
+        timestamp_ms: clock::get_ms(),
+        severity: severity,
+        source: source,
+        message: message,
+        records: records
+    })
+}
+
+/// Most severe of the oldest unacknowledged alerts.
+pub fn next() -> Option<Alert> {
+    queue().alerts.iter()
+        .max_by_key(|alert| (alert.severity, !alert.sequence))
+        .cloned()
+}
+
+pub fn acknowledge(sequence: u32) {
+    queue().alerts.retain(|alert| alert.sequence != sequence)
+}
+
+/// Number of alerts dropped because the queue was full.
+pub fn dropped() -> u32 {
+    queue().dropped
+}
@@ -1,23 +1,111 @@
-use io::{Write, Error as IoError};
-use board_misoc::{csr, cache};
+use core::{mem, slice};
+use core::cmp::min;
+use alloc::Vec;
+use byteorder::{ByteOrder, NetworkEndian};
+use io::{Write, BufWriter, Error as IoError};
+use board_misoc::{csr, cache, clock, config};
 use sched::{Io, TcpListener, TcpStream, Error as SchedError};
 use analyzer_proto::*;
+use mgmt_proto::AnalyzerTrigger;
 
-const BUFFER_SIZE: usize = 512 * 1024;
+const DEFAULT_BUFFER_SIZE: usize = 512 * 1024;
+const MIN_BUFFER_SIZE: usize = 64 * 1024;
+// Buffer sizes are rounded down to a multiple of this, which is also the
+// alignment of the buffer, so that it holds whole bus words and cache lines.
+const BUFFER_ALIGNMENT: usize = 1024;
 
-#[repr(align(64))]
-struct Buffer {
-    data: [u8; BUFFER_SIZE],
+// Hosts that do not send a request within this time after connecting get a
+// one-shot dump, as with earlier versions.
+const REQUEST_TIMEOUT_MS: u64 = 200;
+
+// Largest amount of messages sent at once while streaming, and the time to
+// wait for new messages when there are none.
+const CHUNK_SIZE: usize = 16 * 1024;
+const STREAM_INTERVAL_MS: u64 = 10;
+
+static mut STREAMING: bool = false;
+
+// The trace is sent with recording stopped, by the analyzer port or by a
+// read-only session of the kernel port, and recording restarts afterwards.
+// One of them holds the trace from stopping to restarting recording, so that
+// the other does not restart it while the trace is being sent.
+static mut TRACE_HELD: bool = false;
+
+struct TraceGuard;
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        unsafe { TRACE_HELD = false }
+    }
+}
+
+fn hold_trace(io: &Io) -> Result<TraceGuard, SchedError> {
+    io.until(|| unsafe { !TRACE_HELD })?;
+    unsafe { TRACE_HELD = true }
+    Ok(TraceGuard)
+}
+
+const MESSAGE_SIZE: usize = 32;
+// Offsets of the RTIO counter and of the timestamp in a message.
+const DELTA_FIELDS: [usize; 2] = [12, 20];
+const COMPRESSED_CHUNK_SIZE: usize = 4096;
+
+// Number of entries of the channel filter of the gateware.
+const FILTER_SIZE: usize = 8;
+
+// The filter entries cannot be read back from the gateware.
+static mut FILTER_CHANNELS: [u32; FILTER_SIZE] = [0; FILTER_SIZE];
+static mut FILTER_LENGTH: usize = 0;
+
+static mut BUFFER_BASE: *mut u8 = 0 as *mut u8;
+static mut BUFFER_SIZE: usize = 0;
+
+/// Allocates the trace buffer from the heap, with the size in bytes given by
+/// the `analyzer_trace_size` config entry. Sizes that would leave less than
+/// half of the free heap are refused.
+pub fn startup() {
+    let size = match config::read_str("analyzer_trace_size", |r| r.map(|s| s.parse::<usize>())) {
+        Ok(Ok(size)) => {
+            let size = size - size % BUFFER_ALIGNMENT;
+            let available = ::heap_stats().largest_free / 2;
+            if size < MIN_BUFFER_SIZE {
+                warn!("analyzer_trace_size is below the minimum of {} bytes, using {} bytes",
+                      MIN_BUFFER_SIZE, DEFAULT_BUFFER_SIZE);
+                DEFAULT_BUFFER_SIZE
+            } else if size > available {
+                warn!("analyzer_trace_size of {} bytes does not fit in the {} bytes of free \
+                       memory that can be used, using {} bytes", size, available, DEFAULT_BUFFER_SIZE);
+                DEFAULT_BUFFER_SIZE
+            } else {
+                info!("analyzer trace buffer: {} bytes", size);
+                size
+            }
+        }
+        Ok(Err(_)) => {
+            warn!("invalid value for analyzer_trace_size, using {} bytes", DEFAULT_BUFFER_SIZE);
+            DEFAULT_BUFFER_SIZE
+        }
+        Err(_) => DEFAULT_BUFFER_SIZE
+    };
+
+    // The buffer is never freed.
+    let mut memory: Vec<u8> = Vec::with_capacity(size + BUFFER_ALIGNMENT - 1);
+    let address = memory.as_mut_ptr() as usize;
+    mem::forget(memory);
+    unsafe {
+        BUFFER_BASE = ((address + BUFFER_ALIGNMENT - 1) & !(BUFFER_ALIGNMENT - 1)) as *mut u8;
+        BUFFER_SIZE = size;
+    }
 }
 
-static mut BUFFER: Buffer = Buffer {
-    data: [0; BUFFER_SIZE]
-};
+fn buffer() -> &'static [u8] {
+    unsafe { slice::from_raw_parts(BUFFER_BASE, BUFFER_SIZE) }
+}
 
 fn arm() {
     unsafe {
-        let base_addr = &mut BUFFER.data[0] as *mut _ as usize;
-        let last_addr = &mut BUFFER.data[BUFFER_SIZE - 1] as *mut _ as usize;
+        let base_addr = BUFFER_BASE as usize;
+        let last_addr = base_addr + BUFFER_SIZE - 1;
         csr::rtio_analyzer::message_encoder_overflow_reset_write(1);
         csr::rtio_analyzer::dma_base_address_write(base_addr as u64);
         csr::rtio_analyzer::dma_last_address_write(last_addr as u64);
@@ -36,46 +124,362 @@ fn disarm() {
 }
 
 fn worker(stream: &mut TcpStream) -> Result<(), IoError<SchedError>> {
-    let data = unsafe { &BUFFER.data[..] };
+    let data = buffer();
     let overflow_occurred = unsafe { csr::rtio_analyzer::message_encoder_overflow_read() != 0 };
     let total_byte_count = unsafe { csr::rtio_analyzer::dma_byte_count_read() };
-    let pointer = (total_byte_count % BUFFER_SIZE as u64) as usize;
-    let wraparound = total_byte_count >= BUFFER_SIZE as u64;
+    let pointer = (total_byte_count % data.len() as u64) as usize;
+    let wraparound = total_byte_count >= data.len() as u64;
 
     let header = Header {
         total_byte_count: total_byte_count,
-        sent_bytes: if wraparound { BUFFER_SIZE as u32 } else { total_byte_count as u32 },
+        sent_bytes: if wraparound { data.len() as u32 } else { total_byte_count as u32 },
         overflow_occurred: overflow_occurred,
         log_channel: csr::CONFIG_RTIO_LOG_CHANNEL as u8,
         dds_onehot_sel: true  // kept for backward compatibility of analyzer dumps
     };
     debug!("{:?}", header);
 
-    stream.write_all("E".as_bytes())?;
-    header.write_to(stream)?;
+    let mut buffer = [0; 32];
+    {
+        let mut writer = BufWriter::new(stream, &mut buffer);
+        writer.write_all("E".as_bytes())?;
+        header.write_to(&mut writer)?;
+        writer.flush()?;
+    }
+    // The caches were flushed when disarming, so the trace is read from
+    // memory in bursts straight into the send buffer of the socket.
     if wraparound {
-        stream.write_all(&data[pointer..])?;
-        stream.write_all(&data[..pointer])?;
+        stream.write_all_in_bursts(&data[pointer..])?;
+        stream.write_all_in_bursts(&data[..pointer])?;
     } else {
-        stream.write_all(&data[..pointer])?;
+        stream.write_all_in_bursts(&data[..pointer])?;
     }
 
     Ok(())
 }
 
+// Compressed dumps encode each message against the previous one: the RTIO
+// counter and timestamp are replaced by their differences with those of the
+// previous message, and only the bytes that differ from the previous encoded
+// message are sent, after a 32-bit mask of their positions.
+struct Compressor {
+    last_message: [u8; MESSAGE_SIZE],
+    last_encoded: [u8; MESSAGE_SIZE],
+    output: Vec<u8>
+}
+
+impl Compressor {
+    fn new() -> Compressor {
+        Compressor {
+            last_message: [0; MESSAGE_SIZE],
+            last_encoded: [0; MESSAGE_SIZE],
+            output: Vec::with_capacity(COMPRESSED_CHUNK_SIZE + 4 + MESSAGE_SIZE)
+        }
+    }
+
+    fn write_all(&mut self, stream: &mut TcpStream, data: &[u8]) -> Result<(), IoError<SchedError>> {
+        for message in data.chunks(MESSAGE_SIZE) {
+            let mut encoded = [0; MESSAGE_SIZE];
+            encoded.copy_from_slice(message);
+            for &offset in DELTA_FIELDS.iter() {
+                let value = NetworkEndian::read_u64(&message[offset..]);
+                let last = NetworkEndian::read_u64(&self.last_message[offset..]);
+                NetworkEndian::write_u64(&mut encoded[offset..], value.wrapping_sub(last));
+            }
+
+            let mask_offset = self.output.len();
+            self.output.extend_from_slice(&[0; 4]);
+            let mut mask = 0u32;
+            for i in 0..MESSAGE_SIZE {
+                if encoded[i] != self.last_encoded[i] {
+                    mask |= 1 << i;
+                    self.output.push(encoded[i]);
+                }
+            }
+            NetworkEndian::write_u32(&mut self.output[mask_offset..], mask);
+
+            self.last_message.copy_from_slice(message);
+            self.last_encoded = encoded;
+            if self.output.len() >= COMPRESSED_CHUNK_SIZE {
+                self.flush(stream)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, stream: &mut TcpStream) -> Result<(), IoError<SchedError>> {
+        stream.write_all(&self.output)?;
+        self.output.clear();
+        Ok(())
+    }
+}
+
+fn compressed_worker(stream: &mut TcpStream) -> Result<(), IoError<SchedError>> {
+    let data = buffer();
+    let overflow_occurred = unsafe { csr::rtio_analyzer::message_encoder_overflow_read() != 0 };
+    let total_byte_count = unsafe { csr::rtio_analyzer::dma_byte_count_read() };
+    let pointer = (total_byte_count % data.len() as u64) as usize;
+    let wraparound = total_byte_count >= data.len() as u64;
+
+    let header = Header {
+        total_byte_count: total_byte_count,
+        sent_bytes: if wraparound { data.len() as u32 } else { total_byte_count as u32 },
+        overflow_occurred: overflow_occurred,
+        log_channel: csr::CONFIG_RTIO_LOG_CHANNEL as u8,
+        dds_onehot_sel: true
+    };
+    debug!("{:?}, compressed", header);
+
+    let mut buffer = [0; 32];
+    {
+        let mut writer = BufWriter::new(stream, &mut buffer);
+        writer.write_all("C".as_bytes())?;
+        header.write_to(&mut writer)?;
+        writer.flush()?;
+    }
+    let mut compressor = Compressor::new();
+    if wraparound {
+        compressor.write_all(stream, &data[pointer..])?;
+        compressor.write_all(stream, &data[..pointer])?;
+    } else {
+        compressor.write_all(stream, &data[..pointer])?;
+    }
+    compressor.flush(stream)
+}
+
+// The byte count can be read while the analyzer is running, but the words
+// of the CSR are not read atomically.
+fn byte_count() -> u64 {
+    loop {
+        let count = unsafe { csr::rtio_analyzer::dma_byte_count_read() };
+        if count == unsafe { csr::rtio_analyzer::dma_byte_count_read() } {
+            return count
+        }
+    }
+}
+
+fn stream_worker(io: &Io, stream: &mut TcpStream) -> Result<(), IoError<SchedError>> {
+    let data = buffer();
+
+    let mut buffer = [0; 32];
+    {
+        let mut writer = BufWriter::new(stream, &mut buffer);
+        writer.write_all("E".as_bytes())?;
+        StreamHeader {
+            log_channel: csr::CONFIG_RTIO_LOG_CHANNEL as u8,
+            dds_onehot_sel: true
+        }.write_to(&mut writer)?;
+        writer.flush()?;
+    }
+
+    // Position in the trace up to which messages were sent or dropped.
+    let mut position = 0;
+    let mut lost = 0;
+    while stream.may_recv() {
+        let count = byte_count();
+        if count == position {
+            io.sleep(STREAM_INTERVAL_MS).map_err(IoError::Other)?;
+            continue
+        }
+        if count - position > (data.len() - CHUNK_SIZE) as u64 {
+            // Skip ahead, leaving room for the analyzer to keep writing
+            // while the next messages are sent.
+            let resume = count - (data.len() / 2) as u64;
+            lost += resume - position;
+            position = resume;
+        }
+
+        unsafe {
+            cache::flush_cpu_dcache();
+            cache::flush_l2_cache();
+        }
+        let start = (position % data.len() as u64) as usize;
+        let length = min(min(count - position, CHUNK_SIZE as u64) as usize, data.len() - start);
+        let overflow_occurred = unsafe {
+            let overflow = csr::rtio_analyzer::message_encoder_overflow_read() != 0;
+            csr::rtio_analyzer::message_encoder_overflow_reset_write(1);
+            overflow
+        };
+        {
+            // The chunk header goes out with the start of the data.
+            let mut writer = BufWriter::new(stream, &mut buffer);
+            StreamChunk {
+                sent_bytes: length as u32,
+                lost_bytes: lost,
+                overflow_occurred: overflow_occurred
+            }.write_to(&mut writer)?;
+            writer.write_all(&data[start..start + length])?;
+            writer.flush()?;
+        }
+        let valid = byte_count() - position <= data.len() as u64;
+        stream.write_all(&[valid as u8])?;
+
+        if !valid {
+            lost += length as u64;
+        }
+        position += length as u64;
+    }
+
+    Ok(())
+}
+
+fn trigger_to_csr(trigger: AnalyzerTrigger) -> (u8, u32) {
+    match trigger {
+        AnalyzerTrigger::None => (0, 0),
+        AnalyzerTrigger::Channel(channel) => (1, channel),
+        AnalyzerTrigger::Exception => (2, 0)
+    }
+}
+
+fn trigger_from_csr(mode: u8, channel: u32) -> AnalyzerTrigger {
+    match mode {
+        1 => AnalyzerTrigger::Channel(channel),
+        2 => AnalyzerTrigger::Exception,
+        _ => AnalyzerTrigger::None
+    }
+}
+
+/// Size of the trace buffer in bytes, 0 before `startup`.
+pub fn buffer_size() -> usize {
+    unsafe { BUFFER_SIZE }
+}
+
+/// Sets the conditions that start and stop recording. They apply to the
+/// current capture at once, and are rearmed each time a new capture begins.
+pub fn set_trigger(start: AnalyzerTrigger, stop: AnalyzerTrigger) {
+    let (start_mode, start_channel) = trigger_to_csr(start);
+    let (stop_mode, stop_channel) = trigger_to_csr(stop);
+    unsafe {
+        // Disable the triggers while the channels are changed, so that no
+        // event is matched against a partial configuration.
+        csr::rtio_analyzer::message_encoder_trigger_start_mode_write(0);
+        csr::rtio_analyzer::message_encoder_trigger_stop_mode_write(0);
+        csr::rtio_analyzer::message_encoder_trigger_start_channel_write(start_channel);
+        csr::rtio_analyzer::message_encoder_trigger_stop_channel_write(stop_channel);
+        csr::rtio_analyzer::message_encoder_trigger_start_mode_write(start_mode);
+        csr::rtio_analyzer::message_encoder_trigger_stop_mode_write(stop_mode);
+    }
+}
+
+/// Returns the start and stop triggers, and whether they have fired
+/// during the current capture.
+pub fn trigger() -> (AnalyzerTrigger, AnalyzerTrigger, bool, bool) {
+    unsafe {
+        let state = csr::rtio_analyzer::message_encoder_trigger_state_read();
+        (trigger_from_csr(csr::rtio_analyzer::message_encoder_trigger_start_mode_read(),
+                          csr::rtio_analyzer::message_encoder_trigger_start_channel_read()),
+         trigger_from_csr(csr::rtio_analyzer::message_encoder_trigger_stop_mode_read(),
+                          csr::rtio_analyzer::message_encoder_trigger_stop_channel_read()),
+         state & 1 != 0, state & 2 != 0)
+    }
+}
+
+fn write_filter_entry(index: usize, channel: u32, valid: bool) {
+    unsafe {
+        csr::rtio_analyzer::message_encoder_filter_index_write(index as u8);
+        csr::rtio_analyzer::message_encoder_filter_channel_write(channel);
+        csr::rtio_analyzer::message_encoder_filter_valid_write(valid as u8);
+        csr::rtio_analyzer::message_encoder_filter_write_write(1);
+    }
+}
+
+/// Records the input and output messages of the given channels only, or
+/// with `exclude`, of all but them. An empty exclusion list records all
+/// channels.
+pub fn set_filter(exclude: bool, channels: &[u32]) -> Result<(), ()> {
+    if channels.len() > FILTER_SIZE {
+        return Err(())
+    }
+    unsafe {
+        // Record all channels while the entries are replaced.
+        csr::rtio_analyzer::message_encoder_filter_exclude_write(1);
+        for index in 0..FILTER_SIZE {
+            write_filter_entry(index, 0, false);
+        }
+        for (index, &channel) in channels.iter().enumerate() {
+            write_filter_entry(index, channel, true);
+            FILTER_CHANNELS[index] = channel;
+        }
+        FILTER_LENGTH = channels.len();
+        csr::rtio_analyzer::message_encoder_filter_exclude_write(exclude as u8);
+    }
+    Ok(())
+}
+
+pub fn filter() -> (bool, &'static [u32]) {
+    unsafe {
+        (csr::rtio_analyzer::message_encoder_filter_exclude_read() != 0,
+         &FILTER_CHANNELS[..FILTER_LENGTH])
+    }
+}
+
+/// Sends the trace recorded so far and restarts recording, as the analyzer
+/// port does for each connection.
+pub fn dump(io: &Io, stream: &mut TcpStream) -> Result<(), IoError<SchedError>> {
+    if unsafe { STREAMING } {
+        warn!("cannot dump the analyzer trace while it is being streamed");
+        stream.write_all("E".as_bytes())?;
+        return Header {
+            total_byte_count: 0,
+            sent_bytes: 0,
+            overflow_occurred: false,
+            log_channel: csr::CONFIG_RTIO_LOG_CHANNEL as u8,
+            dds_onehot_sel: true
+        }.write_to(stream)
+    }
+
+    let _trace = hold_trace(io).map_err(IoError::Other)?;
+    disarm();
+    let result = worker(stream);
+    arm();
+    result
+}
+
 pub fn thread(io: Io) {
-    let listener = TcpListener::new(&io, 65535);
+    let listener = TcpListener::new(&io, ::socket_buffer_size("analyzer", 65535));
     listener.listen(1382).expect("analyzer: cannot listen");
 
+    arm();
     loop {
-        arm();
-
         let mut stream = listener.accept().expect("analyzer: cannot accept");
         info!("connection from {}", stream.remote_endpoint());
 
-        disarm();
+        let deadline = clock::get_ms() + REQUEST_TIMEOUT_MS;
+        let _ = io.until(|| stream.can_recv() || !stream.may_recv() ||
+                            clock::get_ms() > deadline);
+        let request = if stream.can_recv() {
+            Request::read_from(&mut stream)
+        } else {
+            Ok(Request::Dump { compressed: false })
+        };
 
-        match worker(&mut stream) {
+        let _trace = hold_trace(&io).expect("analyzer: cannot hold the trace");
+        let result = match request {
+            Ok(Request::Dump { compressed: false }) => {
+                disarm();
+                worker(&mut stream)
+            }
+            Ok(Request::Dump { compressed: true }) => {
+                disarm();
+                compressed_worker(&mut stream)
+            }
+            Ok(Request::Stream) => {
+                info!("streaming trace");
+                unsafe { STREAMING = true }
+                let result = stream_worker(&io, &mut stream);
+                unsafe { STREAMING = false }
+                disarm();
+                result
+            }
+            Err(err) => {
+                error!("analyzer aborted: {}", err);
+                disarm();
+                arm();
+                continue
+            }
+        };
+        arm();
+        match result {
             Ok(())   => (),
             Err(err) => error!("analyzer aborted: {}", err)
         }
@@ -0,0 +1,191 @@
+use core::mem;
+use alloc::{Vec, String};
+use smoltcp::wire::IpEndpoint;
+use board_misoc::{clock, config};
+use hw_watchdog;
+
+// Audit log of the connections to the session, mgmt and moninj ports, and of
+// the mgmt commands that change the state of the core device (reboots,
+// flashing, config writes, ...), so that the users of a shared core device
+// can find out who did what, e.g. rebooted it during a run. Unlike the log,
+// entries are not drowned by other messages; the oldest are dropped once
+// `CAPACITY` are kept. The command that restarts the core device is saved in
+// the `RESTART_KEY` config key, and recorded again after the restart.
+
+const CAPACITY: usize = 128;
+const RESTART_KEY: &'static str = "audit_restart";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    Session = 0,
+    Mgmt    = 1,
+    Moninj  = 2
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Connected    = 0,
+    Disconnected = 1,
+    Command      = 2
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub sequence:     u32,
+    pub timestamp_ms: u64,
+    /// Wall clock time, if set by SNTP or the RTC. For the command saved
+    /// before a restart, `timestamp_ms` is 0 and this is its own time.
+    pub unix_us:      Option<u64>,
+    pub service:      Service,
+    pub event:        Event,
+    pub peer:         String,
+    /// For `Disconnected`, how long the connection lasted.
+    pub duration_ms:  u64,
+    /// What was done, or e.g. how the connection ended.
+    pub detail:       String
+}
+
+struct Log {
+    entries:       Vec<Entry>,
+    next_sequence: u32,
+    dropped:       u32
+}
+
+static mut LOG: Option<Log> = None;
+
+fn log() -> &'static mut Log {
+    unsafe {
+        if LOG.is_none() {
+            LOG = Some(Log {
+                entries: Vec::with_capacity(CAPACITY),
+                next_sequence: 1,
+                dropped: 0
+            })
+        }
+        LOG.as_mut().unwrap()
+    }
+}
+
+fn push_entry(mut entry: Entry) {
+    let log = log();
+    if log.entries.len() == CAPACITY {
+        log.entries.remove(0);
+        log.dropped = log.dropped.wrapping_add(1);
+    }
+    entry.sequence = log.next_sequence;
+    log.next_sequence = log.next_sequence.wrapping_add(1);
+    log.entries.push(entry)
+}
+
+fn push(service: Service, event: Event, peer: IpEndpoint, duration_ms: u64, detail: String) {
+    push_entry(Entry {
+        sequence:     0,
+        timestamp_ms: clock::get_ms(),
+        unix_us:      clock::get_unix_us(),
+        service:      service,
+        event:        event,
+        peer:         format!("{}", peer),
+        duration_ms:  duration_ms,
+        detail:       detail
+    })
+}
+
+/// Records the command that restarted the core device, if any. Must be
+/// called once at boot.
+pub fn init() {
+    let saved = config::read_str(RESTART_KEY, |r| r.ok().map(String::from));
+    let saved = match saved {
+        Some(saved) => saved,
+        None => return
+    };
+    // Saved as the peer, the wall clock time or `-`, and the command,
+    // separated by tabs.
+    let mut fields = saved.splitn(3, '\t');
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(peer), Some(unix_us), Some(detail)) => push_entry(Entry {
+            sequence:     0,
+            timestamp_ms: 0,
+            unix_us:      unix_us.parse().ok(),
+            service:      Service::Mgmt,
+            event:        Event::Command,
+            peer:         String::from(peer),
+            duration_ms:  0,
+            detail:       String::from(detail)
+        }),
+        _ => warn!("invalid command saved before the restart in {}", RESTART_KEY)
+    }
+    if let Err(e) = hw_watchdog::suspend(|| config::remove(RESTART_KEY)) {
+        warn!("cannot remove {}: {}", RESTART_KEY, e)
+    }
+}
+
+/// Saves the last entry, the command about to restart the core device, to
+/// record it again after the restart.
+pub fn save_restart() {
+    let saved = match log().entries.last() {
+        Some(entry) => format!("{}\t{}\t{}", entry.peer,
+                               entry.unix_us.map(|us| format!("{}", us))
+                                            .unwrap_or(String::from("-")),
+                               entry.detail),
+        None => return
+    };
+    if let Err(e) = hw_watchdog::suspend(|| config::write(RESTART_KEY, saved.as_bytes())) {
+        warn!("cannot save the command restarting the core device: {}", e)
+    }
+}
+
+/// Records an event that is not tied to a `Connection`, e.g. a connection
+/// refused before it was established.
+pub fn record(service: Service, event: Event, peer: IpEndpoint, detail: &str) {
+    push(service, event, peer, 0, String::from(detail))
+}
+
+/// A connection, whose closing is recorded when it is dropped.
+pub struct Connection {
+    service: Service,
+    peer:    IpEndpoint,
+    opened:  u64,
+    ending:  String
+}
+
+impl Connection {
+    /// Records that `peer` connected, with e.g. the kind of connection in
+    /// `detail`.
+    pub fn open(service: Service, peer: IpEndpoint, detail: &str) -> Connection {
+        push(service, Event::Connected, peer, 0, String::from(detail));
+        Connection {
+            service: service,
+            peer:    peer,
+            opened:  clock::get_ms(),
+            ending:  String::from("closed")
+        }
+    }
+
+    /// Records a command of note from the peer.
+    pub fn command(&self, detail: String) {
+        push(self.service, Event::Command, self.peer, 0, detail)
+    }
+
+    /// Sets how the connection ended, recorded when it is dropped.
+    pub fn end(&mut self, detail: String) {
+        self.ending = detail
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let duration_ms = clock::get_ms() - self.opened;
+        let ending = mem::replace(&mut self.ending, String::new());
+        push(self.service, Event::Disconnected, self.peer, duration_ms, ending)
+    }
+}
+
+/// Entries kept, oldest first.
+pub fn entries() -> &'static [Entry] {
+    &log().entries
+}
+
+/// Number of entries dropped since boot to make room for newer ones.
+pub fn dropped() -> u32 {
+    log().dropped
+}
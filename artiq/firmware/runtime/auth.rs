@@ -0,0 +1,191 @@
+use alloc::Vec;
+use byteorder::{ByteOrder, BigEndian};
+use io::{Write, Error as IoError};
+use board_misoc::{boot_stats, clock, config};
+use sched::{TcpStream, Error as SchedError};
+use session_proto::TOKEN_SIZE;
+
+// Pre-shared key authentication of session and management connections.
+//
+// After the endian byte, the core device sends 0 if no key is configured,
+// or 1 followed by a 32-byte challenge. The host replies with
+// HMAC-SHA256(key, challenge), and the core device answers 1 if the
+// response matches and 0 before closing the connection otherwise.
+
+const KEY: &'static str = "auth_key";
+const MAC_SIZE: usize = 32;
+// Time the host has to send its response.
+const RESPONSE_TIMEOUT_MS: u64 = 10_000;
+
+struct Sha256 {
+    state:  [u32; 8],
+    block:  [u8; 64],
+    filled: usize,
+    length: u64
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+];
+
+impl Sha256 {
+    fn new() -> Sha256 {
+        Sha256 {
+            state:  [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+                     0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19],
+            block:  [0; 64],
+            filled: 0,
+            length: 0
+        }
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = BigEndian::read_u32(&self.block[i * 4..]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let mut v = self.state;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+        }
+        for i in 0..8 {
+            self.state[i] = self.state[i].wrapping_add(v[i]);
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.length += data.len() as u64;
+        for &byte in data {
+            self.block[self.filled] = byte;
+            self.filled += 1;
+            if self.filled == 64 {
+                self.compress();
+                self.filled = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; MAC_SIZE] {
+        let bit_length = self.length * 8;
+        self.update(&[0x80]);
+        while self.filled != 56 {
+            self.update(&[0]);
+        }
+        let mut length = [0; 8];
+        BigEndian::write_u64(&mut length, bit_length);
+        self.update(&length);
+
+        let mut digest = [0; MAC_SIZE];
+        for i in 0..8 {
+            BigEndian::write_u32(&mut digest[i * 4..], self.state[i]);
+        }
+        digest
+    }
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; MAC_SIZE] {
+    let mut block = [0; 64];
+    if key.len() > block.len() {
+        let mut hash = Sha256::new();
+        hash.update(key);
+        block[..MAC_SIZE].copy_from_slice(&hash.finish());
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut pad = [0; 64];
+    for (p, b) in pad.iter_mut().zip(block.iter()) { *p = b ^ 0x36 }
+    let mut inner = Sha256::new();
+    inner.update(&pad);
+    inner.update(message);
+    for (p, b) in pad.iter_mut().zip(block.iter()) { *p = b ^ 0x5c }
+    let mut outer = Sha256::new();
+    outer.update(&pad);
+    outer.update(&inner.finish());
+    outer.finish()
+}
+
+// There is no hardware random number generator; the challenge only has to
+// be unpredictable to someone who does not know the key, and must never
+// repeat, so that a recorded response cannot be replayed. It is derived from
+// the key, the boot count kept in flash, a connection counter and the time
+// since boot.
+fn challenge(key: &[u8]) -> [u8; MAC_SIZE] {
+    static mut COUNTER: u64 = 0;
+
+    let mut seed = [0; 20];
+    BigEndian::write_u32(&mut seed[0..], boot_stats::current().map_or(0, |s| s.boot_count));
+    unsafe {
+        COUNTER += 1;
+        BigEndian::write_u64(&mut seed[4..], COUNTER);
+    }
+    BigEndian::write_u64(&mut seed[12..], clock::get_us());
+    hmac_sha256(key, &seed)
+}
+
+/// Returns a token identifying a kernel session, which the host sends back
+/// to resume it. Without a key, anyone may open a session anyway, and the
+/// token only keeps another host from resuming it by mistake.
+pub fn session_token() -> [u8; TOKEN_SIZE] {
+    let key = config::read(KEY, |result| result.ok().map(|key| key.to_vec()))
+        .unwrap_or(Vec::new());
+    let mut token = [0; TOKEN_SIZE];
+    token.copy_from_slice(&challenge(&key)[..TOKEN_SIZE]);
+    token
+}
+
+/// Compares `a` and `b` in constant time, so that timing does not reveal
+/// how much of a secret was guessed.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() &&
+        a.iter().zip(b.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Authenticates the host connected to `stream` if a key is set in the
+/// `auth_key` config entry. Returns whether the host may proceed, and fails
+/// if the host does not answer the challenge in time.
+pub fn handshake(stream: &mut TcpStream) -> Result<bool, IoError<SchedError>> {
+    let key = config::read(KEY, |result| result.ok().map(|key| key.to_vec()));
+    let key = match key {
+        Some(ref key) if !key.is_empty() => key,
+        _ => {
+            stream.write_all(&[0])?;
+            return Ok(true)
+        }
+    };
+
+    let challenge = challenge(key);
+    stream.write_all(&[1])?;
+    stream.write_all(&challenge)?;
+
+    let mut response = [0; MAC_SIZE];
+    stream.read_exact_until(&mut response, clock::get_ms() + RESPONSE_TIMEOUT_MS)?;
+    let expected = hmac_sha256(key, &challenge);
+    if constant_time_eq(&expected, &response) {
+        stream.write_all(&[1])?;
+        Ok(true)
+    } else {
+        warn!("authentication failed for {}", stream.remote_endpoint());
+        stream.write_all(&[0])?;
+        Ok(false)
+    }
+}
@@ -0,0 +1,55 @@
+#[cfg(soc_platform = "kasli")]
+use board_misoc::i2c_eeprom::{EEPROM, SinaraIdentity};
+use mgmt_proto::BoardIdentity;
+
+#[cfg(soc_platform = "kasli")]
+static mut IDENTITY: Option<([u8; 6], Option<SinaraIdentity>)> = None;
+
+/// Reads the identification EEPROM of the board. Must be called once, after
+/// the I2C bus is initialized.
+#[cfg(soc_platform = "kasli")]
+pub fn init() {
+    let eeprom = EEPROM::new();
+    let serial = match eeprom.read_eui48() {
+        Ok(serial) => serial,
+        Err(e) => {
+            error!("cannot read the board EEPROM: {}", e);
+            return
+        }
+    };
+    let sinara = match eeprom.read_sinara() {
+        Ok(sinara) => sinara,
+        Err(e) => {
+            error!("cannot read the board EEPROM: {}", e);
+            None
+        }
+    };
+    match sinara {
+        Some(ref sinara) =>
+            info!("board: {} v{}.{}, variant {}, vendor {}, serial {:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+                  sinara.name(), sinara.major, sinara.minor, sinara.variant, sinara.vendor,
+                  serial[0], serial[1], serial[2], serial[3], serial[4], serial[5]),
+        None =>
+            info!("board: serial {:02x}{:02x}{:02x}{:02x}{:02x}{:02x}, no identification data",
+                  serial[0], serial[1], serial[2], serial[3], serial[4], serial[5])
+    }
+    unsafe { IDENTITY = Some((serial, sinara)) }
+}
+
+#[cfg(not(soc_platform = "kasli"))]
+pub fn init() {}
+
+#[cfg(soc_platform = "kasli")]
+pub fn get() -> Option<BoardIdentity<'static>> {
+    unsafe { IDENTITY.as_ref() }.map(|&(serial, ref sinara)| BoardIdentity {
+        serial: serial,
+        sinara: sinara.as_ref().map(|sinara|
+            (sinara.name(), sinara.board_id, sinara.major, sinara.minor,
+             sinara.variant, sinara.vendor))
+    })
+}
+
+#[cfg(not(soc_platform = "kasli"))]
+pub fn get() -> Option<BoardIdentity<'static>> {
+    None
+}
@@ -0,0 +1,111 @@
+use alloc::Vec;
+use board_misoc::{config, i2c};
+use board_artiq::spi;
+use sched::Io;
+use kernel;
+use nrt_bus;
+
+// Raw transactions on the local I2C buses and the non-realtime SPI bus,
+// requested over mgmt for hardware bring-up and tests scripted from the
+// host. A raw transaction can put a device in any state, so they are
+// refused unless the `bus_passthrough` config key is `1`, and while a
+// kernel is running, as it may be using the same devices. I2C transactions
+// are also arbitrated with the background tasks of the runtime; see
+// `nrt_bus`.
+
+fn check_allowed() -> Result<(), &'static str> {
+    if !config::read_str("bus_passthrough", |r| r == Ok("1")) {
+        return Err("bus passthrough is disabled (bus_passthrough config key)")
+    }
+    if kernel::running() {
+        return Err("a kernel is running")
+    }
+    Ok(())
+}
+
+#[cfg(soc_platform = "kasli")]
+fn select(busno: u8, port: Option<u8>) -> Result<(), &'static str> {
+    if let Some(port) = port {
+        if port >= 16 {
+            return Err("invalid I2C switch port")
+        }
+        let mask: u16 = 1 << port;
+        i2c::pca9548_select(busno, 0x70, mask as u8)?;
+        i2c::pca9548_select(busno, 0x71, (mask >> 8) as u8)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(soc_platform = "kasli"))]
+fn select(_busno: u8, port: Option<u8>) -> Result<(), &'static str> {
+    match port {
+        Some(_) => Err("no I2C switches on this board"),
+        None => Ok(())
+    }
+}
+
+fn i2c_address(busno: u8, address: u8) -> Result<(), &'static str> {
+    if !i2c::write(busno, address)? {
+        return Err("I2C address not acknowledged")
+    }
+    Ok(())
+}
+
+fn i2c_transfer(busno: u8, address: u8, write: &[u8], read_length: usize)
+               -> Result<Vec<u8>, &'static str> {
+    let mut data = Vec::new();
+    i2c::start(busno)?;
+    if !write.is_empty() || read_length == 0 {
+        i2c_address(busno, address << 1)?;
+        for &byte in write {
+            if !i2c::write(busno, byte)? {
+                return Err("I2C write not acknowledged")
+            }
+        }
+        if read_length > 0 {
+            i2c::restart(busno)?;
+        }
+    }
+    if read_length > 0 {
+        i2c_address(busno, address << 1 | 1)?;
+        for index in 0..read_length {
+            data.push(i2c::read(busno, index < read_length - 1)?);
+        }
+    }
+    Ok(data)
+}
+
+/// Writes the bytes `write` to the device at the 7-bit `address` on I2C bus
+/// `busno`, behind the I2C switch `port` of Kasli if any, then reads
+/// `read_length` bytes from it after a repeated start condition, and
+/// returns them.
+pub fn i2c(io: &Io, busno: u8, port: Option<u8>, address: u8, write: &[u8],
+           read_length: usize) -> Result<Vec<u8>, &'static str> {
+    check_allowed()?;
+    if address >= 0x80 {
+        return Err("invalid I2C address")
+    }
+    nrt_bus::with_i2c(io, busno, || {
+        select(busno, port)?;
+        let result = i2c_transfer(busno, address, write, read_length);
+        // Release the bus even if the device did not acknowledge.
+        i2c::stop(busno)?;
+        result
+    })?
+}
+
+/// Configures SPI bus `busno` as `SPIMaster.set_config_mu` does, then
+/// shifts out the words `data`, and returns the words shifted in at the
+/// same time.
+pub fn spi(busno: u8, flags: u8, length: u8, div: u8, cs: u8, data: &[u32])
+           -> Result<Vec<u32>, &'static str> {
+    check_allowed()?;
+    if length == 0 || length > 32 || div < 2 {
+        return Err("invalid SPI configuration")
+    }
+    let mut data = Vec::from(data);
+    spi::set_config(busno, flags, length, div, cs)
+        .and_then(|()| spi::transfer(busno, &mut data))
+        .map_err(|()| "no such SPI bus")?;
+    Ok(data)
+}
@@ -1,48 +1,162 @@
 use alloc::{Vec, String, BTreeMap};
+use board_misoc::config;
+use proto_artiq::mgmt_proto::CacheEntry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    // The entry was borrowed by the running kernel.
+    Busy,
+    QuotaExceeded,
+    NotFound
+}
 
 #[derive(Debug)]
 struct Entry {
     data: Vec<i32>,
-    borrowed: bool
+    borrowed: bool,
+    // Value of `Cache::clock` when the entry was last accessed.
+    last_used: u64
+}
+
+// Memory accounted to an entry, in bytes.
+fn entry_size(key: &str, length: usize) -> usize {
+    key.len() + length * 4
+}
+
+fn read_size(key: &str) -> Option<usize> {
+    config::read_str(key, |r| r.ok().and_then(|s| s.parse().ok()))
 }
 
 #[derive(Debug)]
 pub struct Cache {
-    entries: BTreeMap<String, Entry>
+    entries: BTreeMap<String, Entry>,
+    size: usize,
+    clock: u64,
+    max_entry_size: Option<usize>,
+    max_size: Option<usize>,
+    // Make room for new entries by evicting the least recently used ones,
+    // rather than refusing them, when `max_size` is reached.
+    evict_lru: bool
 }
 
 impl Cache {
     pub fn new() -> Cache {
-        Cache { entries: BTreeMap::new() }
+        let max_entry_size = read_size("cache_max_entry_size");
+        let max_size = read_size("cache_max_size");
+        let evict_lru = config::read_str("cache_eviction", |r| r == Ok("lru"));
+        if max_entry_size.is_some() || max_size.is_some() {
+            info!("cache limited to {:?} bytes per entry and {:?} bytes in total{}",
+                  max_entry_size, max_size, if evict_lru { ", evicting LRU entries" } else { "" });
+        }
+        Cache {
+            entries: BTreeMap::new(),
+            size: 0,
+            clock: 0,
+            max_entry_size: max_entry_size,
+            max_size: max_size,
+            evict_lru: evict_lru
+        }
     }
 
     pub fn get(&mut self, key: &str) -> *const [i32] {
+        self.clock += 1;
         match self.entries.get_mut(key) {
             None => &[],
             Some(ref mut entry) => {
                 entry.borrowed = true;
+                entry.last_used = self.clock;
                 &entry.data[..]
             }
         }
     }
 
-    pub fn put(&mut self, key: &str, data: &[i32]) -> Result<(), ()> {
-        match self.entries.get_mut(key) {
-            None => (),
-            Some(ref mut entry) => {
-                if entry.borrowed { return Err(()) }
-                entry.data = Vec::from(data);
-                return Ok(())
+    pub fn put(&mut self, key: &str, data: &[i32]) -> Result<(), Error> {
+        let previous_size = match self.entries.get(key) {
+            None => 0,
+            Some(entry) => {
+                if entry.borrowed { return Err(Error::Busy) }
+                entry_size(key, entry.data.len())
+            }
+        };
+
+        if data.is_empty() {
+            if self.entries.remove(key).is_some() {
+                self.size -= previous_size;
+            }
+            return Ok(())
+        }
+
+        let size = entry_size(key, data.len());
+        if self.max_entry_size.map_or(false, |max| size > max) {
+            return Err(Error::QuotaExceeded)
+        }
+        if let Some(max_size) = self.max_size {
+            while self.size - previous_size + size > max_size {
+                if !(self.evict_lru && self.evict_one(key)) {
+                    return Err(Error::QuotaExceeded)
+                }
             }
         }
 
+        self.clock += 1;
+        self.size = self.size - previous_size + size;
         self.entries.insert(String::from(key), Entry {
             data: Vec::from(data),
-            borrowed: false
+            borrowed: false,
+            last_used: self.clock
         });
         Ok(())
     }
 
+    // Removes the least recently used entry that is not borrowed, other than
+    // `except`. Returns false if there is none.
+    fn evict_one(&mut self, except: &str) -> bool {
+        let key = self.entries.iter()
+            .filter(|&(key, entry)| !entry.borrowed && key != except)
+            .min_by_key(|&(_key, entry)| entry.last_used)
+            .map(|(key, _entry)| key.clone());
+        match key {
+            Some(key) => {
+                debug!("evicting cache entry {}", key);
+                self.remove(&key).is_ok()
+            }
+            None => false
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<(), Error> {
+        match self.entries.get(key) {
+            None => return Err(Error::NotFound),
+            Some(entry) if entry.borrowed => return Err(Error::Busy),
+            Some(_) => ()
+        }
+        if let Some(entry) = self.entries.remove(key) {
+            self.size -= entry_size(key, entry.data.len());
+        }
+        Ok(())
+    }
+
+    /// Removes all entries that are not borrowed, and returns the number of
+    /// entries left.
+    pub fn clear(&mut self) -> usize {
+        let keys: Vec<String> = self.entries.iter()
+            .filter(|&(_key, entry)| !entry.borrowed)
+            .map(|(key, _entry)| key.clone())
+            .collect();
+        for key in keys {
+            let _ = self.remove(&key);
+        }
+        self.entries.len()
+    }
+
+    pub fn list(&self) -> Vec<CacheEntry> {
+        self.entries.iter().map(|(key, entry)| CacheEntry {
+            key: key.clone(),
+            size: entry_size(key, entry.data.len()) as u32,
+            borrowed: entry.borrowed
+        }).collect()
+    }
+
     pub unsafe fn unborrow(&mut self) {
         for (_key, entry) in self.entries.iter_mut() {
             entry.borrowed = false;
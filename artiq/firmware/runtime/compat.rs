@@ -0,0 +1,30 @@
+use board_misoc::{csr, ident};
+
+// The gateware embeds a hash of its CSR map and of its feature flags after
+// the identifier string, and the firmware built along with it the same hash
+// as a constant. Firmware run on other gateware would drive the wrong CSRs,
+// so the runtime then only brings up networking and management, to report
+// the mismatch and let a matching firmware or gateware be flashed.
+
+/// Descriptors of the firmware and of the gateware, 0 for gateware built
+/// without one.
+pub fn descriptors() -> (u32, u32) {
+    (csr::CONFIG_COMPAT_DESCRIPTOR, ident::descriptor())
+}
+
+/// Compares the descriptors and returns whether the runtime may drive the
+/// gateware. Gateware without a descriptor is assumed to be compatible.
+pub fn check() -> bool {
+    let (firmware, gateware) = descriptors();
+    if gateware == 0 {
+        warn!("gateware has no compatibility descriptor, cannot check that it matches the firmware");
+        true
+    } else if gateware != firmware {
+        error!("firmware was built for gateware 0x{:08x}, but the gateware is 0x{:08x}; \
+                only networking and management are started, flash matching firmware and gateware",
+               firmware, gateware);
+        false
+    } else {
+        true
+    }
+}
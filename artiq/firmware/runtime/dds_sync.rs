@@ -0,0 +1,41 @@
+use alloc::Vec;
+use board_misoc::clock;
+use mgmt_proto::DdsSyncReport;
+
+// Results of the SYNC_IN and IO_UPDATE alignment of AD9910 channels that
+// kernels report after `AD9910.resync`, kept since boot so that the
+// alignment of phase-coherent setups can be checked over mgmt.
+
+static mut REPORTS: Option<Vec<DdsSyncReport>> = None;
+
+fn reports_mut() -> &'static mut Vec<DdsSyncReport> {
+    unsafe {
+        if REPORTS.is_none() {
+            REPORTS = Some(Vec::new())
+        }
+        REPORTS.as_mut().unwrap()
+    }
+}
+
+/// Records the alignment of a channel, replacing the previous one.
+pub fn record(channel: u32, chip_select: u8, in_delay: u8, window: u8, io_update_delay: u8) {
+    info!("DDS on SPI channel 0x{:06x} CS {} aligned: SYNC_IN delay {}, window {}, \
+           IO_UPDATE delay {}", channel, chip_select, in_delay, window, io_update_delay);
+    let report = DdsSyncReport {
+        channel:         channel,
+        chip_select:     chip_select,
+        in_delay:        in_delay,
+        window:          window,
+        io_update_delay: io_update_delay,
+        timestamp_ms:    clock::get_ms()
+    };
+    let reports = reports_mut();
+    match reports.iter().position(|r| r.channel == channel && r.chip_select == chip_select) {
+        Some(index) => reports[index] = report,
+        None => reports.push(report)
+    }
+}
+
+pub fn reports() -> Vec<DdsSyncReport> {
+    reports_mut().clone()
+}
@@ -0,0 +1,76 @@
+use smoltcp::dhcp::Dhcpv4Client;
+use smoltcp::iface::EthernetInterface;
+use smoltcp::phy::Device;
+use smoltcp::socket::{SocketSet, RawSocketBuffer, RawPacketMetadata};
+use smoltcp::time::Instant;
+use smoltcp::wire::{IpCidr, IpAddress};
+use board_misoc::clock;
+
+// Time after which the lack of a DHCP offer is reported; the fallback address
+// stays in use until a lease is obtained.
+const FALLBACK_MS: u64 = 10_000;
+
+/// Obtains the IPv4 address, netmask and gateway of the interface by DHCP.
+/// The interface keeps its fallback address, which must be its first IP
+/// address, until a lease is obtained.
+pub struct Dhcp {
+    client:      Dhcpv4Client,
+    fallback:    IpAddress,
+    fallback_at: Option<u64>,
+    bound:       Option<IpCidr>
+}
+
+impl Dhcp {
+    pub fn new(sockets: &mut SocketSet<'static, 'static, 'static>, fallback: IpAddress) -> Dhcp {
+        let rx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 600]);
+        let tx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 600]);
+        let now = clock::get_ms();
+        info!("requesting an IPv4 address by DHCP");
+        Dhcp {
+            client:      Dhcpv4Client::new(sockets, rx_buffer, tx_buffer,
+                                           Instant::from_millis(now as i64)),
+            fallback:    fallback,
+            fallback_at: Some(now + FALLBACK_MS),
+            bound:       None
+        }
+    }
+
+    pub fn poll<D>(&mut self, interface: &mut EthernetInterface<D>,
+                   sockets: &mut SocketSet<'static, 'static, 'static>)
+            where D: for<'d> Device<'d> {
+        let now = clock::get_ms();
+        if self.fallback_at.map_or(false, |at| now > at) {
+            warn!("no DHCP offer received, using fallback address {}", self.fallback);
+            self.fallback_at = None;
+        }
+
+        let config = match self.client.poll(interface, sockets, Instant::from_millis(now as i64)) {
+            Ok(Some(config)) => config,
+            Ok(None) => return,
+            Err(err) => {
+                debug!("DHCP error: {}", err);
+                return
+            }
+        };
+        if let Some(cidr) = config.address {
+            let cidr = IpCidr::Ipv4(cidr);
+            if self.bound != Some(cidr) {
+                match config.router {
+                    Some(router) => info!("DHCP lease obtained: address {}, gateway {}",
+                                          cidr, router),
+                    None => info!("DHCP lease obtained: address {}", cidr)
+                }
+                interface.update_ip_addrs(|addrs| addrs[0] = cidr);
+                self.bound = Some(cidr);
+                self.fallback_at = None;
+            } else {
+                debug!("DHCP lease renewed");
+            }
+        }
+        if let Some(router) = config.router {
+            if let Err(err) = interface.routes_mut().add_default_ipv4_route(router) {
+                warn!("cannot set default gateway {}: {}", router, err);
+            }
+        }
+    }
+}
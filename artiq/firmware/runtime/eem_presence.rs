@@ -0,0 +1,158 @@
+#[cfg(soc_platform = "kasli")]
+use core::ops::Range;
+use alloc::{Vec, String};
+#[cfg(soc_platform = "kasli")]
+use board_misoc::{i2c, i2c_eeprom::EEPROM};
+#[cfg(soc_platform = "kasli")]
+use alerts::{self, Severity, Source};
+
+// Detection of the cards on the EEM connectors of Kasli. At boot, each
+// connector is probed through the EEPROM that Sinara EEM cards have on the
+// I2C bus of the connector, and the cards detected are compared with the
+// peripherals of the gateware (see `build_soc.get_topology`), so that a card
+// that is not seated or not powered is reported instead of just looking like
+// dead channels. Peripherals that span several connectors, e.g. Urukul with
+// two EEMs, have their EEPROM on the first one only, so the others are not
+// probed.
+
+#[cfg(soc_platform = "kasli")]
+const EEM_COUNT: u8 = 12;
+
+#[derive(Debug, Clone)]
+pub struct Eem {
+    pub eem:      u8,
+    /// Type of the peripheral of the gateware on the connector, if any.
+    pub expected: Option<String>,
+    /// Whether a card answered, or `None` if the connector was not probed or
+    /// the probe failed.
+    pub detected: Option<bool>,
+    /// Board name in the EEPROM of the card, if it holds Sinara data.
+    pub name:     Option<String>
+}
+
+static mut EEMS: Option<Vec<Eem>> = None;
+
+#[cfg(all(soc_platform = "kasli", has_rtio_topology))]
+fn topology() -> &'static str {
+    board_misoc::csr::CONFIG_RTIO_TOPOLOGY
+}
+
+#[cfg(all(soc_platform = "kasli", not(has_rtio_topology)))]
+fn topology() -> &'static str {
+    ""
+}
+
+/// Peripheral of the gateware on EEM connectors, with its type (e.g.
+/// `urukul`), its connectors, the first being the one of its EEPROM, and
+/// the range of its RTIO channels.
+#[cfg(soc_platform = "kasli")]
+#[derive(Debug, Clone)]
+pub struct Peripheral {
+    pub ty:       &'static str,
+    pub eems:     Vec<u8>,
+    pub channels: Range<u32>
+}
+
+/// Peripherals of the gateware, from the `type@eem,eem=first-last` fields
+/// of the topology; empty if the gateware does not describe them.
+#[cfg(soc_platform = "kasli")]
+pub fn peripherals() -> Vec<Peripheral> {
+    let mut peripherals = Vec::new();
+    for field in topology().split(';') {
+        let mut parts = field.splitn(2, '=');
+        let (key, value) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let mut parts = key.splitn(2, '@');
+        let (ty, eems) = match (parts.next(), parts.next()) {
+            (Some(ty), Some(eems)) => (ty, eems),
+            _ => continue
+        };
+        let eems: Vec<u8> = eems.split(',').filter_map(|eem| eem.parse().ok()).collect();
+        if eems.is_empty() {
+            continue
+        }
+        let mut bounds = value.splitn(2, '-').map(|bound| bound.parse::<u32>());
+        let channels = match (bounds.next(), bounds.next()) {
+            (Some(Ok(first)), Some(Ok(last))) => first..last,
+            _ => 0..0
+        };
+        peripherals.push(Peripheral {
+            ty:       ty,
+            eems:     eems,
+            channels: channels
+        })
+    }
+    peripherals
+}
+
+#[cfg(soc_platform = "kasli")]
+fn probe(eem: u8) -> (Option<bool>, Option<String>) {
+    let port = match i2c::kasli_port(&format!("EEM{}", eem)) {
+        Some(port) => port,
+        None => return (None, None)
+    };
+    let eeprom = EEPROM::eem(port);
+    match eeprom.probe() {
+        Ok(true) => {
+            let name = match eeprom.read_sinara() {
+                Ok(sinara) => sinara.map(|sinara| String::from(sinara.name())),
+                Err(e) => {
+                    warn!("EEM{}: cannot read the EEPROM of the card: {}", eem, e);
+                    None
+                }
+            };
+            (Some(true), name)
+        }
+        Ok(false) => (Some(false), None),
+        Err(e) => {
+            warn!("EEM{}: cannot probe the card: {}", eem, e);
+            (None, None)
+        }
+    }
+}
+
+/// Probes the EEM connectors and reports the cards of the gateware that are
+/// missing. Must be called once, after the I2C bus is initialized.
+#[cfg(soc_platform = "kasli")]
+pub fn init() {
+    let peripherals = peripherals();
+    if peripherals.is_empty() {
+        info!("the gateware does not describe its EEM peripherals, \
+               only detecting the cards");
+    }
+
+    let mut eems = Vec::new();
+    let mut missing = 0;
+    for eem in 0..EEM_COUNT {
+        let peripheral = peripherals.iter().find(|peripheral| peripheral.eems.contains(&eem));
+        let (detected, name) = match peripheral {
+            Some(peripheral) if peripheral.eems[0] != eem => (None, None),
+            _ => probe(eem)
+        };
+        let expected = peripheral.map(|peripheral| String::from(peripheral.ty));
+        let card = name.as_ref().map(|name| name.as_str()).unwrap_or("unidentified card");
+        match (&expected, detected) {
+            (&Some(ref ty), Some(false)) => {
+                error!("EEM{}: {} expected by the gateware, but no card detected", eem, ty);
+                missing += 1
+            }
+            (&Some(ref ty), Some(true)) => info!("EEM{}: {} ({})", eem, ty, card),
+            (&None, Some(true)) => info!("EEM{}: {} not used by the gateware", eem, card),
+            _ => ()
+        }
+        eems.push(Eem { eem: eem, expected: expected, detected: detected, name: name })
+    }
+
+    if missing > 0 {
+        alerts::push(Severity::Warning, Source::Eem,
+                     format!("{} EEM cards of the gateware not detected", missing));
+    }
+    unsafe { EEMS = Some(eems) }
+}
+
+#[cfg(not(soc_platform = "kasli"))]
+pub fn init() {}
+
+/// EEM connectors as probed at boot, or `None` if they were not probed.
+pub fn get() -> Option<&'static [Eem]> {
+    unsafe { EEMS.as_ref().map(|eems| eems.as_slice()) }
+}
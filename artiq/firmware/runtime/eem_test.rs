@@ -0,0 +1,518 @@
+use post::Report;
+#[cfg(all(has_rtio_nrt, soc_platform = "kasli"))]
+use kernel;
+
+// Self-test of the Urukul, Sampler and Zotino cards on the EEM connectors,
+// to qualify them after installation without writing experiment code. The
+// SPI buses of the cards are driven by the comms CPU through the RTIO
+// initiator of the gateware for non-realtime transactions, so the test is
+// refused while a kernel is running, and it does not yield, so that no
+// kernel starts in the middle of it. It checks:
+//
+//  * Urukul: the protocol revision of the CPLD, and the shift register of
+//    the attenuator of each channel, which is restored afterwards;
+//  * Sampler: the shift register of the PGIA of each channel, whose gain is
+//    left at 1;
+//  * Zotino: that the DAC answers, and the readback of the X1A register of
+//    each channel, which is left at 0 V;
+//  * the Zotino channels wired to Sampler channels, listed in the
+//    `eem_loopback` config key as `zotino.channel=sampler.channel` pairs
+//    separated by commas, where `zotino` and `sampler` are the first EEM
+//    connectors of the cards, e.g. `3.0=5.0,3.1=5.1`: the voltage measured
+//    by Sampler must match the one set by Zotino.
+//
+// The cards are left as after a reset of their CPLD and DAC, and must be
+// initialized again by experiments.
+
+#[cfg(all(has_rtio_nrt, soc_platform = "kasli"))]
+mod nrt {
+    use core::ptr::write_volatile;
+    use board_misoc::csr;
+
+    const O_STATUS_WAIT: u8 = 1;
+    const O_STATUS_UNDERFLOW: u8 = 2;
+    const O_STATUS_DESTINATION_UNREACHABLE: u8 = 4;
+    const I_STATUS_WAIT_EVENT: u8 = 1;
+    const I_STATUS_OVERFLOW: u8 = 2;
+    const I_STATUS_WAIT_STATUS: u8 = 4;
+    const I_STATUS_DESTINATION_UNREACHABLE: u8 = 8;
+
+    // Events are scheduled this far after the RTIO counter; the comms CPU
+    // has no hard timing.
+    const SLACK_MU: u64 = 100_000;
+    const INPUT_TIMEOUT_MU: u64 = 1_000_000;
+    // Period of the coarse RTIO clock of Kasli.
+    const COARSE_PERIOD_MU: u64 = 8;
+
+    pub const SPI_END: u32 = 0x02;
+    pub const SPI_INPUT: u32 = 0x04;
+    pub const SPI_CS_POLARITY: u32 = 0x08;
+    pub const SPI_CLK_PHASE: u32 = 0x20;
+
+    const SPI_DATA_ADDR: u8 = 0;
+    const SPI_CONFIG_ADDR: u8 = 1;
+
+    fn counter() -> u64 {
+        unsafe {
+            csr::rtio_nrt::counter_update_write(1);
+            csr::rtio_nrt::counter_read()
+        }
+    }
+
+    /// Timeline of the events of the comms CPU. Creating it connects the
+    /// initiator of the comms CPU to the RTIO core in place of the kernel
+    /// CPU, until it is dropped.
+    pub struct Timeline {
+        now: u64
+    }
+
+    impl Timeline {
+        pub fn new() -> Timeline {
+            unsafe { csr::rtio_nrt::enable_write(1) }
+            Timeline { now: counter() + SLACK_MU }
+        }
+
+        pub fn delay(&mut self, duration_mu: u64) {
+            self.now += duration_mu
+        }
+
+        pub fn output(&mut self, channel: u32, address: u8, data: u32)
+                     -> Result<(), &'static str> {
+            self.now = self.now.max(counter() + SLACK_MU);
+            unsafe {
+                csr::rtio_nrt::now_hi_write((self.now >> 32) as u32);
+                csr::rtio_nrt::now_lo_write(self.now as u32);
+                csr::rtio_nrt::target_write(channel << 8 | address as u32);
+                // Writing the LSB of o_data triggers the write.
+                write_volatile(csr::rtio_nrt::O_DATA_ADDR.offset(
+                    (csr::rtio_nrt::O_DATA_SIZE - 1) as isize), data);
+                let mut status = csr::rtio_nrt::o_status_read();
+                while status & O_STATUS_WAIT != 0 {
+                    status = csr::rtio_nrt::o_status_read();
+                }
+                if status & O_STATUS_UNDERFLOW != 0 {
+                    return Err("RTIO underflow")
+                }
+                if status & O_STATUS_DESTINATION_UNREACHABLE != 0 {
+                    return Err("RTIO destination unreachable")
+                }
+            }
+            Ok(())
+        }
+
+        pub fn input_data(&mut self, channel: u32) -> Result<u32, &'static str> {
+            let timeout = self.now.max(counter()) + INPUT_TIMEOUT_MU;
+            unsafe {
+                csr::rtio_nrt::target_write(channel << 8);
+                csr::rtio_nrt::i_timeout_write(timeout);
+                let mut status = I_STATUS_WAIT_STATUS;
+                while status & I_STATUS_WAIT_STATUS != 0 {
+                    status = csr::rtio_nrt::i_status_read();
+                }
+                if status & I_STATUS_OVERFLOW != 0 {
+                    return Err("RTIO input overflow")
+                }
+                if status & I_STATUS_WAIT_EVENT != 0 {
+                    return Err("no data read from SPI")
+                }
+                if status & I_STATUS_DESTINATION_UNREACHABLE != 0 {
+                    return Err("RTIO destination unreachable")
+                }
+                Ok(csr::rtio_nrt::i_data_read())
+            }
+        }
+
+        pub fn ttl(&mut self, channel: u32, level: bool) -> Result<(), &'static str> {
+            self.output(channel, 0, level as u32)
+        }
+    }
+
+    impl Drop for Timeline {
+        fn drop(&mut self) {
+            // Let the events be issued before giving the RTIO core back.
+            while counter() < self.now {}
+            unsafe { csr::rtio_nrt::enable_write(0) }
+        }
+    }
+
+    /// SPI bus of a `spi2.SPIMaster` RTIO channel, as in the drivers of
+    /// `artiq.coredevice`.
+    #[derive(Debug, Clone, Copy)]
+    pub struct Spi {
+        channel:     u32,
+        duration_mu: u64
+    }
+
+    impl Spi {
+        pub fn new(channel: u32) -> Spi {
+            Spi { channel: channel, duration_mu: 0 }
+        }
+
+        pub fn set_config(&mut self, timeline: &mut Timeline, flags: u32, length: u32,
+                          div: u32, cs: u32) -> Result<(), &'static str> {
+            timeline.output(self.channel, SPI_CONFIG_ADDR,
+                            flags | (length - 1) << 8 | (div - 2) << 16 | cs << 24)?;
+            self.duration_mu = (length as u64 + 1) * div as u64 * COARSE_PERIOD_MU;
+            timeline.delay(COARSE_PERIOD_MU);
+            Ok(())
+        }
+
+        pub fn write(&self, timeline: &mut Timeline, data: u32) -> Result<(), &'static str> {
+            timeline.output(self.channel, SPI_DATA_ADDR, data)?;
+            timeline.delay(self.duration_mu);
+            Ok(())
+        }
+
+        pub fn read(&self, timeline: &mut Timeline) -> Result<u32, &'static str> {
+            timeline.input_data(self.channel)
+        }
+    }
+}
+
+#[cfg(all(has_rtio_nrt, soc_platform = "kasli"))]
+mod imp {
+    use alloc::{Vec, String};
+    use board_misoc::config;
+    use eem_presence::{self, Peripheral};
+    use post::{Report, Outcome};
+    use super::nrt::*;
+
+    const URUKUL_CONFIG: u32 = SPI_CS_POLARITY;
+    const URUKUL_DIV: u32 = 16;
+    const URUKUL_CS_CFG: u32 = 1;
+    const URUKUL_CS_ATT: u32 = 2;
+    const URUKUL_CFG_IO_RST: u32 = 1 << 20;
+    const URUKUL_PROTO_REV: u32 = 0x08;
+    // A different value for each attenuator.
+    const URUKUL_ATT_PATTERN: u32 = 0xc33ca55a;
+
+    const SAMPLER_DIV: u32 = 8;
+    const SAMPLER_CS_ADC: u32 = 0;
+    const SAMPLER_CS_PGIA: u32 = 1;
+    const SAMPLER_PGIA_PATTERNS: [u32; 2] = [0x5555, 0xaaaa];
+    const SAMPLER_CNV_MU: u64 = 30;
+    const SAMPLER_CONVERSION_MU: u64 = 450;
+
+    const ZOTINO_CONFIG: u32 = SPI_END | SPI_CLK_PHASE;
+    const ZOTINO_DIV_WRITE: u32 = 4;
+    const ZOTINO_DIV_READ: u32 = 8;
+    const ZOTINO_CS_DAC: u32 = 1;
+    const ZOTINO_CHANNELS: u32 = 32;
+    const ZOTINO_OFFSET_DACS: u32 = 0x2000;
+    const ZOTINO_READBACK_SETUP_MU: u64 = 270;
+    const ZOTINO_LDAC_MU: u64 = 16;
+
+    const AD53XX_CMD_DATA: u32 = 3 << 22;
+    const AD53XX_SPECIAL_NOP: u32 = 0 << 16;
+    const AD53XX_SPECIAL_CONTROL: u32 = 1 << 16;
+    const AD53XX_SPECIAL_OFS0: u32 = 2 << 16;
+    const AD53XX_SPECIAL_OFS1: u32 = 3 << 16;
+    const AD53XX_SPECIAL_READ: u32 = 5 << 16;
+    const AD53XX_READ_X1A: u32 = 0x008 << 7;
+    const AD53XX_READ_CONTROL: u32 = 0x101 << 7;
+
+    // Both cards span -10 V to +10 V, with the PGIA gain of Sampler at 1.
+    const LOOPBACK_VOLTAGES_MV: [i32; 2] = [4_000, -4_000];
+    const LOOPBACK_TOLERANCE_MV: i32 = 200;
+    const LOOPBACK_SETTLING_MU: u64 = 1_000_000;
+
+    fn urukul_cpld(timeline: &mut Timeline, spi: &mut Spi) -> Result<String, String> {
+        // Reading the status register writes the configuration register;
+        // reset the I/O of the DDS with it, as `CPLD.init` does.
+        spi.set_config(timeline, URUKUL_CONFIG | SPI_END | SPI_INPUT, 24,
+                       URUKUL_DIV, URUKUL_CS_CFG)?;
+        spi.write(timeline, URUKUL_CFG_IO_RST << 8)?;
+        spi.set_config(timeline, URUKUL_CONFIG | SPI_END, 24, URUKUL_DIV, URUKUL_CS_CFG)?;
+        spi.write(timeline, 0)?;
+        let status = spi.read(timeline)?;
+        let proto_rev = status >> 16 & 0x7f;
+        if proto_rev != URUKUL_PROTO_REV {
+            return Err(format!("CPLD protocol revision 0x{:02x}, expected 0x{:02x}",
+                               proto_rev, URUKUL_PROTO_REV))
+        }
+        Ok(format!("PLL lock 0b{:04b}", status >> 8 & 0xf))
+    }
+
+    // Shifts a pattern through the chain of attenuators, then their settings
+    // back in, which shifts the pattern out, before they are latched.
+    fn urukul_attenuators(timeline: &mut Timeline, spi: &mut Spi) -> Result<u32, &'static str> {
+        spi.set_config(timeline, URUKUL_CONFIG | SPI_INPUT, 32, URUKUL_DIV, URUKUL_CS_ATT)?;
+        spi.write(timeline, URUKUL_ATT_PATTERN)?;
+        spi.set_config(timeline, URUKUL_CONFIG | SPI_END | SPI_INPUT, 32,
+                       URUKUL_DIV, URUKUL_CS_ATT)?;
+        let settings = spi.read(timeline)?;
+        spi.write(timeline, settings)?;
+        spi.read(timeline)
+    }
+
+    fn urukul(report: &mut Report, timeline: &mut Timeline, name: &str, peripheral: &Peripheral) {
+        let mut spi = Spi::new(peripheral.channels.start);
+        report.result(format!("{}:cpld", name), urukul_cpld(timeline, &mut spi));
+
+        let readback = urukul_attenuators(timeline, &mut spi);
+        for channel in 0..4 {
+            let subsystem = format!("{}:att{}", name, channel);
+            let expected = URUKUL_ATT_PATTERN >> (channel * 8) & 0xff;
+            match readback {
+                Ok(readback) if readback >> (channel * 8) & 0xff == expected =>
+                    report.record(subsystem, Outcome::Pass, String::new()),
+                Ok(readback) =>
+                    report.record(subsystem, Outcome::Fail,
+                        format!("shifted 0x{:02x} through, read back 0x{:02x}",
+                                expected, readback >> (channel * 8) & 0xff)),
+                Err(e) => report.record(subsystem, Outcome::Fail, String::from(e))
+            }
+        }
+    }
+
+    // Shifts the patterns through the PGIA register, leaving the gains at 1.
+    fn sampler_pgia(timeline: &mut Timeline, spi: &mut Spi) -> Result<[u32; 2], &'static str> {
+        spi.set_config(timeline, SPI_END | SPI_INPUT, 16, SAMPLER_DIV, SAMPLER_CS_PGIA)?;
+        for &pattern in SAMPLER_PGIA_PATTERNS.iter() {
+            spi.write(timeline, pattern << 16)?;
+        }
+        spi.write(timeline, 0)?;
+        spi.read(timeline)?;
+        Ok([spi.read(timeline)? & 0xffff, spi.read(timeline)? & 0xffff])
+    }
+
+    fn sampler(report: &mut Report, timeline: &mut Timeline, name: &str, peripheral: &Peripheral) {
+        let mut spi = Spi::new(peripheral.channels.start + 1);
+        let readback = sampler_pgia(timeline, &mut spi);
+        for channel in 0..8 {
+            let subsystem = format!("{}:pgia{}", name, channel);
+            let field = |value: u32| value >> (channel * 2) & 0b11;
+            match readback {
+                Ok(readback) if field(readback[0]) == field(SAMPLER_PGIA_PATTERNS[0]) &&
+                                field(readback[1]) == field(SAMPLER_PGIA_PATTERNS[1]) =>
+                    report.record(subsystem, Outcome::Pass, String::new()),
+                Ok(readback) =>
+                    report.record(subsystem, Outcome::Fail,
+                        format!("shifted 0b{:02b} and 0b{:02b} through, read back 0b{:02b} \
+                                 and 0b{:02b}", field(SAMPLER_PGIA_PATTERNS[0]),
+                                field(SAMPLER_PGIA_PATTERNS[1]), field(readback[0]),
+                                field(readback[1]))),
+                Err(e) => report.record(subsystem, Outcome::Fail, String::from(e))
+            }
+        }
+    }
+
+    // Samples all channels of Sampler and returns the one of `channel`, in
+    // millivolts.
+    fn sampler_sample_mv(timeline: &mut Timeline, peripheral: &Peripheral, channel: u32)
+                        -> Result<i32, &'static str> {
+        let mut adc = Spi::new(peripheral.channels.start);
+        let cnv = peripheral.channels.start + 2;
+        adc.set_config(timeline, SPI_END | SPI_INPUT, 32, SAMPLER_DIV, SAMPLER_CS_ADC)?;
+        timeline.ttl(cnv, true)?;
+        timeline.delay(SAMPLER_CNV_MU);
+        timeline.ttl(cnv, false)?;
+        timeline.delay(SAMPLER_CONVERSION_MU);
+        for _ in 0..4 {
+            adc.write(timeline, 0)?;
+        }
+        // Each word holds two channels, from channels 7 and 6 down.
+        let mut code = 0;
+        for word in 0..4 {
+            let data = adc.read(timeline)?;
+            if channel == 7 - 2 * word {
+                code = data >> 16;
+            } else if channel == 6 - 2 * word {
+                code = data & 0xffff;
+            }
+        }
+        Ok((code as u16 as i16) as i32 * 20_000 / (1 << 16))
+    }
+
+    fn zotino_write(timeline: &mut Timeline, spi: &Spi, command: u32) -> Result<(), &'static str> {
+        spi.write(timeline, command << 8)
+    }
+
+    fn zotino_read(timeline: &mut Timeline, spi: &mut Spi, channel: u32, register: u32)
+                  -> Result<u32, &'static str> {
+        zotino_write(timeline, spi, AD53XX_SPECIAL_READ | (register + (channel << 7)))?;
+        spi.set_config(timeline, ZOTINO_CONFIG | SPI_INPUT, 24, ZOTINO_DIV_READ, ZOTINO_CS_DAC)?;
+        timeline.delay(ZOTINO_READBACK_SETUP_MU);
+        zotino_write(timeline, spi, AD53XX_SPECIAL_NOP)?;
+        spi.set_config(timeline, ZOTINO_CONFIG, 24, ZOTINO_DIV_WRITE, ZOTINO_CS_DAC)?;
+        Ok(spi.read(timeline)? & 0xffff)
+    }
+
+    fn zotino_set_mu(timeline: &mut Timeline, spi: &Spi, channel: u32, value: u32)
+                    -> Result<(), &'static str> {
+        zotino_write(timeline, spi, AD53XX_CMD_DATA | (channel + 8) << 16 | value)
+    }
+
+    fn zotino_load(timeline: &mut Timeline, peripheral: &Peripheral) -> Result<(), &'static str> {
+        let ldac = peripheral.channels.start + 1;
+        timeline.ttl(ldac, false)?;
+        timeline.delay(ZOTINO_LDAC_MU);
+        timeline.ttl(ldac, true)
+    }
+
+    fn zotino_mv_to_mu(voltage_mv: i32) -> u32 {
+        ((voltage_mv * (1 << 16) / 20_000) + (ZOTINO_OFFSET_DACS * 4) as i32) as u32 & 0xffff
+    }
+
+    // Initializes the DAC as `AD53xx.init` does.
+    fn zotino_init(timeline: &mut Timeline, spi: &mut Spi, peripheral: &Peripheral)
+                  -> Result<String, String> {
+        timeline.ttl(peripheral.channels.start + 1, true)?;
+        timeline.ttl(peripheral.channels.start + 2, true)?;
+        spi.set_config(timeline, ZOTINO_CONFIG, 24, ZOTINO_DIV_WRITE, ZOTINO_CS_DAC)?;
+        zotino_write(timeline, spi, AD53XX_SPECIAL_OFS0 | ZOTINO_OFFSET_DACS)?;
+        zotino_write(timeline, spi, AD53XX_SPECIAL_OFS1 | ZOTINO_OFFSET_DACS)?;
+        let control = zotino_read(timeline, spi, 0, AD53XX_READ_CONTROL)?;
+        if control == 0xffff {
+            return Err(String::from("DAC not found"))
+        }
+        if control & 0b10000 != 0 {
+            return Err(String::from("DAC over temperature"))
+        }
+        // Enable power and the overtemperature shutdown.
+        zotino_write(timeline, spi, AD53XX_SPECIAL_CONTROL | 0b0010)?;
+        let control = zotino_read(timeline, spi, 0, AD53XX_READ_CONTROL)?;
+        if control & 0b10111 != 0b00010 {
+            return Err(format!("control register read back as 0x{:04x}", control))
+        }
+        Ok(String::new())
+    }
+
+    fn zotino_readback(timeline: &mut Timeline, spi: &mut Spi, channel: u32)
+                      -> Result<String, String> {
+        // A different value for each channel.
+        let pattern = 0xa500 | channel;
+        zotino_set_mu(timeline, spi, channel, pattern)?;
+        let readback = zotino_read(timeline, spi, channel, AD53XX_READ_X1A)?;
+        if readback != pattern {
+            return Err(format!("wrote 0x{:04x}, read back 0x{:04x}", pattern, readback))
+        }
+        Ok(String::new())
+    }
+
+    fn zotino(report: &mut Report, timeline: &mut Timeline, name: &str, peripheral: &Peripheral) {
+        let mut spi = Spi::new(peripheral.channels.start);
+        let init = zotino_init(timeline, &mut spi, peripheral);
+        let found = init.is_ok();
+        report.result(format!("{}:dac", name), init);
+        if !found {
+            return
+        }
+
+        for channel in 0..ZOTINO_CHANNELS {
+            let subsystem = format!("{}:ch{}", name, channel);
+            let result = zotino_readback(timeline, &mut spi, channel);
+            report.result(subsystem, result);
+            if let Err(e) = zotino_set_mu(timeline, &spi, channel, zotino_mv_to_mu(0)) {
+                warn!("{}: cannot set channel {} to 0 V: {}", name, channel, e)
+            }
+        }
+        if let Err(e) = zotino_load(timeline, peripheral) {
+            warn!("{}: cannot load the DAC: {}", name, e)
+        }
+    }
+
+    fn loopback(timeline: &mut Timeline, zotino: &Peripheral, zotino_channel: u32,
+                sampler: &Peripheral, sampler_channel: u32) -> Result<String, String> {
+        let mut spi = Spi::new(zotino.channels.start);
+        spi.set_config(timeline, ZOTINO_CONFIG, 24, ZOTINO_DIV_WRITE, ZOTINO_CS_DAC)?;
+        let mut measured = Vec::new();
+        for &voltage_mv in LOOPBACK_VOLTAGES_MV.iter() {
+            zotino_set_mu(timeline, &spi, zotino_channel, zotino_mv_to_mu(voltage_mv))?;
+            zotino_load(timeline, zotino)?;
+            timeline.delay(LOOPBACK_SETTLING_MU);
+            measured.push(sampler_sample_mv(timeline, sampler, sampler_channel)?);
+        }
+        zotino_set_mu(timeline, &spi, zotino_channel, zotino_mv_to_mu(0))?;
+        zotino_load(timeline, zotino)?;
+
+        let mut detail = String::new();
+        let mut pass = true;
+        for (&set, &read) in LOOPBACK_VOLTAGES_MV.iter().zip(measured.iter()) {
+            if !detail.is_empty() {
+                detail.push_str(", ");
+            }
+            detail.push_str(&format!("{:+} mV read as {:+} mV", set, read));
+            pass &= (read - set).abs() <= LOOPBACK_TOLERANCE_MV;
+        }
+        if pass { Ok(detail) } else { Err(detail) }
+    }
+
+    // Parses `eem.channel`.
+    fn parse_endpoint(s: &str) -> Option<(u8, u32)> {
+        let mut parts = s.trim().splitn(2, '.');
+        Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+    }
+
+    fn loopbacks(report: &mut Report, timeline: &mut Timeline, peripherals: &[Peripheral]) {
+        let pairs = config::read_str("eem_loopback", |r| r.map(String::from).unwrap_or_default());
+        let find = |ty: &str, eem: u8| peripherals.iter().find(|peripheral|
+            peripheral.ty == ty && peripheral.eems[0] == eem);
+        for pair in pairs.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let mut endpoints = pair.splitn(2, '=').map(parse_endpoint);
+            let (zotino, sampler) = match (endpoints.next(), endpoints.next()) {
+                (Some(Some(zotino)), Some(Some(sampler))) => (zotino, sampler),
+                _ => {
+                    report.record(format!("loopback {}", pair.trim()), Outcome::Fail,
+                                  String::from("invalid entry in the eem_loopback config key"));
+                    continue
+                }
+            };
+            let subsystem = format!("zotino{}:ch{}>sampler{}:ch{}",
+                                    zotino.0, zotino.1, sampler.0, sampler.1);
+            let result = match (find("zotino", zotino.0), find("sampler", sampler.0)) {
+                (None, _) => Err(format!("no Zotino on EEM{}", zotino.0)),
+                (_, None) => Err(format!("no Sampler on EEM{}", sampler.0)),
+                _ if zotino.1 >= ZOTINO_CHANNELS || sampler.1 >= 8 =>
+                    Err(String::from("no such channel")),
+                (Some(zotino_peripheral), Some(sampler_peripheral)) =>
+                    loopback(timeline, zotino_peripheral, zotino.1, sampler_peripheral, sampler.1)
+            };
+            report.result(subsystem, result);
+        }
+    }
+
+    pub fn run(report: &mut Report) {
+        let peripherals = eem_presence::peripherals();
+        let mut timeline = Timeline::new();
+        let mut tested = 0;
+        for peripheral in peripherals.iter() {
+            let name = format!("{}{}", peripheral.ty, peripheral.eems[0]);
+            match peripheral.ty {
+                "urukul" => urukul(report, &mut timeline, &name, peripheral),
+                "sampler" => sampler(report, &mut timeline, &name, peripheral),
+                "zotino" => zotino(report, &mut timeline, &name, peripheral),
+                _ => continue
+            }
+            tested += 1;
+        }
+        if tested == 0 {
+            report.record(String::from("eem"), Outcome::Skipped,
+                          String::from("no Urukul, Sampler or Zotino in the gateware"));
+        }
+        loopbacks(report, &mut timeline, &peripherals);
+    }
+}
+
+/// Runs the self-test of the EEM cards and returns its report.
+#[cfg(all(has_rtio_nrt, soc_platform = "kasli"))]
+pub fn run() -> Result<Report, &'static str> {
+    if kernel::running() {
+        return Err("a kernel is running")
+    }
+    info!("running the self test of the EEM cards");
+    let mut report = Report::new();
+    imp::run(&mut report);
+    let failures = report.failures();
+    if failures == 0 {
+        info!("EEM self test passed");
+    } else {
+        error!("EEM self test failed {} of {} checks", failures, report.checks.len());
+    }
+    Ok(report)
+}
+
+#[cfg(not(all(has_rtio_nrt, soc_platform = "kasli")))]
+pub fn run() -> Result<Report, &'static str> {
+    Err("the gateware cannot drive RTIO from the comms CPU")
+}
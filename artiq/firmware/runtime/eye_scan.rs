@@ -0,0 +1,134 @@
+// Statistical eye scans of the DRTIO transceivers, through the DRP of their
+// channels (ug482). The offset sampler is moved over the eye while the data
+// sampler keeps receiving, so the link and the traffic on it are not
+// disturbed. Only built into gateware with GTP transceivers; the scan needs
+// the RX of the link to be initialized, i.e. the link up.
+
+use alloc::Vec;
+use board_misoc::{csr, clock};
+use mgmt_proto::EyeScanPoint;
+use sched::Io;
+use rtio_mgt;
+
+const ES_VERT_OFFSET: u16 = 0x03b;
+const ES_HORZ_OFFSET: u16 = 0x03c;
+const ES_CONTROL: u16 = 0x03d;
+const ES_ERROR_COUNT: u16 = 0x14f;
+const ES_SAMPLE_COUNT: u16 = 0x150;
+const ES_CONTROL_STATUS: u16 = 0x151;
+
+const DATA_WIDTH: u32 = 20;
+// With RXOUT_DIV = 2, the horizontal offset covers -64..64 over one UI.
+const HORZ_RANGE: i16 = 64;
+const VERT_RANGE: i16 = 127;
+const MAX_PRESCALE: u8 = 10;
+
+const DRP_TIMEOUT_MS: u64 = 10;
+// At 2.5 Gb/s, a point takes about 1 ms with a prescale of 0.
+const POINT_TIMEOUT_MS: u64 = 10;
+
+static mut SCANNING: bool = false;
+
+fn drp_access(channel: u8, addr: u16, write: Option<u16>) -> Result<u16, &'static str> {
+    unsafe {
+        csr::drtio_transceiver::drp_channel_write(channel);
+        csr::drtio_transceiver::drp_addr_write(addr);
+        csr::drtio_transceiver::drp_di_write(write.unwrap_or(0));
+        csr::drtio_transceiver::drp_we_write(write.is_some() as u8);
+        csr::drtio_transceiver::drp_start_write(1);
+        let deadline = clock::get_ms() + DRP_TIMEOUT_MS;
+        while csr::drtio_transceiver::drp_done_read() == 0 {
+            if clock::get_ms() > deadline {
+                return Err("transceiver DRP access timed out")
+            }
+        }
+        Ok(csr::drtio_transceiver::drp_do_read())
+    }
+}
+
+fn drp_read(channel: u8, addr: u16) -> Result<u16, &'static str> {
+    drp_access(channel, addr, None)
+}
+
+fn drp_modify(channel: u8, addr: u16, mask: u16, value: u16) -> Result<(), &'static str> {
+    let old = drp_read(channel, addr)?;
+    drp_access(channel, addr, Some(old & !mask | value & mask))?;
+    Ok(())
+}
+
+fn measure(io: &Io, channel: u8, horizontal: i16, vertical: i16, prescale: u8)
+          -> Result<EyeScanPoint, &'static str> {
+    drp_modify(channel, ES_HORZ_OFFSET, 0x0fff, horizontal as u16 & 0x0fff)?;
+    // Sign and magnitude.
+    let vert_offset = if vertical < 0 { 0x80 | -vertical as u16 } else { vertical as u16 };
+    drp_modify(channel, ES_VERT_OFFSET, 0xf8ff, (prescale as u16) << 11 | vert_offset)?;
+
+    // ES_CONTROL[0] starts the measurement, which is done when the
+    // error or the sample counter saturates.
+    drp_modify(channel, ES_CONTROL, 1 << 10, 1 << 10)?;
+    let deadline = clock::get_ms() + (POINT_TIMEOUT_MS << prescale);
+    while drp_read(channel, ES_CONTROL_STATUS)? & 1 == 0 {
+        if clock::get_ms() > deadline {
+            drp_modify(channel, ES_CONTROL, 1 << 10, 0)?;
+            return Err("eye scan measurement timed out")
+        }
+        io.relinquish().unwrap();
+    }
+    let point = EyeScanPoint {
+        horizontal: horizontal,
+        vertical:   vertical,
+        errors:     drp_read(channel, ES_ERROR_COUNT)?,
+        samples:    drp_read(channel, ES_SAMPLE_COUNT)?
+    };
+    drp_modify(channel, ES_CONTROL, 1 << 10, 0)?;
+    Ok(point)
+}
+
+fn scan_link(io: &Io, linkno: u8, horizontal_step: u8, vertical_step: u8, prescale: u8)
+            -> Result<Vec<EyeScanPoint>, &'static str> {
+    let mut points = Vec::new();
+    let mut vertical = -VERT_RANGE;
+    while vertical <= VERT_RANGE {
+        let mut horizontal = -HORZ_RANGE;
+        while horizontal <= HORZ_RANGE {
+            if !rtio_mgt::drtio::link_rx_up(linkno) {
+                return Err("link went down during the eye scan")
+            }
+            points.push(measure(io, linkno, horizontal, vertical, prescale)?);
+            horizontal += horizontal_step as i16;
+        }
+        vertical += vertical_step as i16;
+    }
+    Ok(points)
+}
+
+/// Scans the eye of DRTIO link `linkno`, and returns the horizontal
+/// offsets in one UI, the bits compared per sample, and the points.
+pub fn scan(io: &Io, linkno: u8, horizontal_step: u8, vertical_step: u8, prescale: u8)
+           -> Result<(u16, u32, Vec<EyeScanPoint>), &'static str> {
+    if linkno as usize >= rtio_mgt::drtio::link_count() {
+        return Err("no such DRTIO link")
+    }
+    if !rtio_mgt::drtio::link_rx_up(linkno) {
+        return Err("link is down")
+    }
+    if horizontal_step == 0 || vertical_step == 0 {
+        return Err("offset steps must not be zero")
+    }
+    if prescale > MAX_PRESCALE {
+        return Err("prescale too large")
+    }
+    if unsafe { SCANNING } {
+        return Err("an eye scan is already running")
+    }
+
+    info!("scanning the eye of DRTIO link {}", linkno);
+    unsafe { SCANNING = true }
+    let start = clock::get_ms();
+    let result = scan_link(io, linkno, horizontal_step, vertical_step, prescale);
+    unsafe { SCANNING = false }
+    let points = result?;
+    info!("scanned {} points of DRTIO link {} in {} ms",
+          points.len(), linkno, clock::get_ms() - start);
+    Ok((2 * HORZ_RANGE as u16, DATA_WIDTH << (1 + prescale), points))
+}
@@ -0,0 +1,104 @@
+use alloc::Vec;
+use board_misoc::{csr, config, clock};
+use sched::Priority;
+use {sysmon, timers};
+use status_led::{self, Alarm};
+
+// Fan duty cycle in percent as a function of the FPGA die temperature in °C,
+// interpolated linearly between points and constant outside of them.
+struct Curve {
+    points: Vec<(i32, i32)>
+}
+
+impl Curve {
+    fn default() -> Curve {
+        Curve { points: vec![(40, 30), (70, 100)] }
+    }
+
+    // Parses "temperature:duty,temperature:duty,...".
+    fn parse(s: &str) -> Option<Curve> {
+        let mut points = Vec::new();
+        for point in s.split(',') {
+            let mut fields = point.trim().splitn(2, ':');
+            let temperature = fields.next()?.parse().ok()?;
+            let duty: i32 = fields.next()?.parse().ok()?;
+            if duty < 0 || duty > 100 {
+                return None
+            }
+            points.push((temperature, duty));
+        }
+        points.sort();
+        Some(Curve { points: points })
+    }
+
+    // Duty cycle in percent at `temperature`, in m°C.
+    fn duty(&self, temperature: i32) -> i32 {
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        if temperature <= first.0 * 1000 {
+            return first.1
+        }
+        for window in self.points.windows(2) {
+            let ((t0, d0), (t1, d1)) = (window[0], window[1]);
+            if temperature <= t1 * 1000 {
+                return d0 + (d1 - d0) * (temperature - t0 * 1000) / ((t1 - t0) * 1000)
+            }
+        }
+        last.1
+    }
+}
+
+// Below this duty cycle, fans may legitimately stop.
+const MIN_CHECKED_DUTY: i32 = 20;
+// Time the fan may take to reach the expected speed before it is considered
+// failed.
+const FAIL_TIME_MS: u64 = 5_000;
+const PULSES_PER_REVOLUTION: u64 = 2;
+const INTERVAL_MS: u64 = 1000;
+
+fn set_duty(percent: i32) {
+    unsafe { csr::fan::duty_write((percent * 255 / 100) as u8) }
+}
+
+pub fn init() {
+    let curve = config::read_str("fan_curve", |r| match r {
+        Ok(s) => Curve::parse(s).or_else(|| {
+            error!("invalid fan_curve, using the default");
+            None
+        }),
+        Err(_) => None
+    }).unwrap_or_else(Curve::default);
+    let min_rpm = sysmon::config_int("fan_min_rpm", 300) as u64;
+    info!("fan curve (°C:%): {:?}", curve.points);
+
+    let mut last_count = unsafe { csr::fan::tach_count_read() };
+    let mut last_time = clock::get_ms();
+    let mut slow_since = None;
+    let mut failed = false;
+    timers::register("fan", INTERVAL_MS * 1000, Priority::Low, move |_| {
+        let duty = if failed { 100 } else { curve.duty(sysmon::fpga_temperature()) };
+        set_duty(duty);
+
+        let count = unsafe { csr::fan::tach_count_read() };
+        let time = clock::get_ms();
+        let rpm = count.wrapping_sub(last_count) as u64 * 60_000 /
+                  PULSES_PER_REVOLUTION / (time - last_time);
+        last_count = count;
+        last_time = time;
+
+        if duty < MIN_CHECKED_DUTY || rpm >= min_rpm {
+            slow_since = None;
+            if failed && rpm >= min_rpm {
+                info!("fan running again at {} RPM", rpm);
+                failed = false;
+                status_led::set_alarm(Alarm::Fan, false);
+            }
+        } else {
+            let since = *slow_since.get_or_insert(time);
+            if !failed && time - since > FAIL_TIME_MS {
+                error!("!!! fan failed: {} RPM at {}% duty cycle !!!", rpm, duty);
+                failed = true;
+                status_led::set_alarm(Alarm::Fan, true);
+            }
+        }
+    })
+}
@@ -0,0 +1,80 @@
+// Synthetic faults injected over mgmt into the monitoring of the core
+// device, so that host-side dashboards and automation can be tested without
+// degrading the hardware. They go through the same paths as real faults:
+// injected SFP alarms are read with the alarm flags of the transceiver, an
+// injected DRTIO link drop is seen by the link monitor as the link going
+// down, and an injected FPGA temperature replaces the XADC reading, and
+// thus acts on `fpga_overtemp_action`. Only built into gateware built with
+// `--with-fault-injection`.
+
+#[cfg(has_fault_injection)]
+mod imp {
+    use board_misoc::{clock, sfp};
+    use mgmt_proto::Fault;
+    use rtio_mgt;
+
+    // Injected temperature in m°C, and uptime until which it is injected.
+    static mut TEMPERATURE: Option<(i32, u64)> = None;
+    // DRTIO links to report down once, as a bit mask.
+    static mut LINK_DROPS: u32 = 0;
+
+    pub fn inject(fault: Fault) -> Result<(), &'static str> {
+        match fault {
+            Fault::SfpAlarms { port, alarms } => {
+                sfp::inject_alarms(port as usize, alarms)?;
+                warn!("injecting alarm flags 0x{:04x} on SFP{}", alarms, port);
+            }
+            Fault::DrtioLinkDrop { linkno } => {
+                if linkno as usize >= rtio_mgt::drtio::link_count() || linkno >= 32 {
+                    return Err("no such DRTIO link")
+                }
+                warn!("injecting a drop of DRTIO link {}", linkno);
+                unsafe { LINK_DROPS |= 1 << linkno }
+            }
+            Fault::FpgaTemperature { temperature_mc, duration_s } => {
+                warn!("injecting an FPGA die temperature of {} °C for {} s",
+                      temperature_mc / 1000, duration_s);
+                let until = clock::get_ms() + duration_s as u64 * 1000;
+                unsafe { TEMPERATURE = Some((temperature_mc, until)) }
+            }
+            Fault::Clear => {
+                info!("clearing injected faults");
+                for index in 0..sfp::count() {
+                    sfp::inject_alarms(index, 0)?;
+                }
+                unsafe {
+                    LINK_DROPS = 0;
+                    TEMPERATURE = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Injected FPGA die temperature in m°C, if any.
+    pub fn fpga_temperature() -> Option<i32> {
+        match unsafe { TEMPERATURE } {
+            Some((temperature, until)) if clock::get_ms() < until => Some(temperature),
+            _ => None
+        }
+    }
+
+    /// Whether a drop of DRTIO link `linkno` was injected since the last
+    /// call.
+    pub fn take_link_drop(linkno: u8) -> bool {
+        unsafe {
+            let mask = 1u32.checked_shl(linkno as u32).unwrap_or(0);
+            let dropped = LINK_DROPS & mask != 0;
+            LINK_DROPS &= !mask;
+            dropped
+        }
+    }
+}
+
+#[cfg(not(has_fault_injection))]
+mod imp {
+    pub fn fpga_temperature() -> Option<i32> { None }
+    pub fn take_link_drop(_linkno: u8) -> bool { false }
+}
+
+pub use self::imp::*;
@@ -0,0 +1,44 @@
+use board_misoc::sfp;
+use mgmt_proto::feature::*;
+
+// Features of the core device as reported to hosts, see
+// mgmt_proto::feature.
+
+fn flags() -> u32 {
+    let mut flags = ALERTS | COMPRESSION | HEALTH_RECORDS;
+    if cfg!(has_drtio) {
+        flags |= DRTIO_MASTER
+    }
+    if cfg!(has_drtio_routing) {
+        flags |= DRTIO_ROUTING
+    }
+    if cfg!(has_wrpll) {
+        flags |= WRPLL
+    }
+    if cfg!(has_rtio_analyzer) {
+        flags |= ANALYZER
+    }
+    if cfg!(has_spiflash) {
+        flags |= FIRMWARE_UPDATE
+    }
+    if cfg!(all(has_spiflash, has_icap)) {
+        flags |= GATEWARE_UPDATE
+    }
+    flags
+}
+
+#[cfg(has_rtio_analyzer)]
+fn analyzer_size_kib() -> u32 {
+    (::analyzer::buffer_size() / 1024) as u32
+}
+
+#[cfg(not(has_rtio_analyzer))]
+fn analyzer_size_kib() -> u32 {
+    0
+}
+
+pub fn word() -> u32 {
+    let sfp_count = (sfp::count() as u32).min(SFP_COUNT_MASK);
+    let analyzer_size = analyzer_size_kib().min(ANALYZER_SIZE_MASK);
+    flags() | sfp_count << SFP_COUNT_SHIFT | analyzer_size << ANALYZER_SIZE_SHIFT
+}
@@ -0,0 +1,79 @@
+use board_misoc::{clock, config, flash_storage, flash_health};
+use sched::Io;
+use hw_watchdog;
+use mgmt_proto::FlashAreaHealth;
+
+// Periodic verification of the flash areas that the firmware writes at run
+// time: the configuration, which also holds the idle, startup and named
+// kernels, and the flash storage of kernels. Corruption is logged when it
+// is first found, so that a failing flash is noticed before values are
+// lost. The interval between scrubs is set in seconds by
+// `flash_scrub_interval` (0 disables the periodic scrub). Sectors that
+// repeatedly fail to erase or program are remapped by board_misoc as they
+// are written, see `flash_health`.
+
+const DEFAULT_INTERVAL_S: u32 = 3600;
+
+pub struct Report {
+    /// Uptime at the end of the last scrub, if any.
+    pub scrubbed_ms: Option<u64>,
+    pub scrubs:      u32,
+    pub areas:       [FlashAreaHealth; 2]
+}
+
+static mut REPORT: Report = Report {
+    scrubbed_ms: None,
+    scrubs:      0,
+    areas:       [FlashAreaHealth { name: "config",  checked: 0, corrupted: 0 },
+                  FlashAreaHealth { name: "storage", checked: 0, corrupted: 0 }]
+};
+
+pub fn report() -> &'static Report {
+    unsafe { &REPORT }
+}
+
+fn update(index: usize, checked: usize, corrupted: usize) {
+    let area = unsafe { &mut REPORT.areas[index] };
+    if corrupted as u32 > area.corrupted {
+        warn!("flash scrub: {} of {} values of the {} area are corrupted",
+              corrupted, checked, area.name)
+    } else if corrupted == 0 && area.corrupted != 0 {
+        info!("flash scrub: the {} area is no longer corrupted", area.name)
+    }
+    area.checked = checked as u32;
+    area.corrupted = corrupted as u32;
+}
+
+/// Verifies all areas, relinquishing between them if `io` is given.
+pub fn scrub(io: Option<&Io>) {
+    match hw_watchdog::suspend(|| config::check()) {
+        Ok((checked, corrupted)) => update(0, checked, corrupted),
+        Err(err) => warn!("flash scrub: cannot check the config: {}", err)
+    }
+    if let Some(io) = io {
+        io.relinquish().unwrap();
+    }
+    let (checked, corrupted) = hw_watchdog::suspend(|| flash_storage::check());
+    update(1, checked, corrupted);
+
+    if flash_health::spares_free() == 0 {
+        warn!("flash scrub: no spare sector is left to remap failing sectors")
+    }
+    let report = unsafe { &mut REPORT };
+    report.scrubbed_ms = Some(clock::get_ms());
+    report.scrubs += 1;
+}
+
+pub fn thread(io: Io) {
+    let interval_s = config::read_str("flash_scrub_interval",
+                                      |r| r.ok().and_then(|s| s.parse().ok()))
+        .unwrap_or(DEFAULT_INTERVAL_S);
+    if interval_s == 0 {
+        info!("periodic flash scrub disabled");
+        return
+    }
+    loop {
+        io.sleep(interval_s as u64 * 1000).unwrap();
+        scrub(Some(&io));
+    }
+}
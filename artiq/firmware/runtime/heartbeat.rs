@@ -0,0 +1,141 @@
+use alloc::String;
+use core::fmt::{self, Write};
+use smoltcp::socket::{SocketSet, SocketHandle, UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+use board_misoc::{csr, clock, ident, config, net_settings::NetAddresses};
+use {kernel, mdns, session, status_led};
+
+// Periodic UDP datagram announcing the presence and basic health of the
+// core device to monitoring tools, as a JSON object, e.g.:
+// {"hostname":"kasli-6a0f3c","serial":"80:1f:12:6a:0f:3c","firmware":"7.0",
+//  "gateware":"7.0;master","uptime_s":120,"session":"idle","kernel":false,
+//  "alarms":["fan"]}
+
+const DEFAULT_PORT: u16 = 1384;
+
+pub struct Heartbeat {
+    handle:      SocketHandle,
+    destination: IpEndpoint,
+    interval_ms: u64,
+    hostname:    String,
+    serial:      [u8; 6],
+    next_at:     u64
+}
+
+// Writes a JSON string literal.
+fn write_string(buffer: &mut String, s: &str) -> fmt::Result {
+    buffer.push('"');
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => { buffer.push('\\'); buffer.push(c) }
+            c if (c as u32) < 0x20 => write!(buffer, "\\u{:04x}", c as u32)?,
+            c => buffer.push(c)
+        }
+    }
+    buffer.push('"');
+    Ok(())
+}
+
+impl Heartbeat {
+    /// Returns a sender if the `heartbeat_interval_ms` config entry is set.
+    /// The datagrams go to `heartbeat_port` (1384 by default) of
+    /// `heartbeat_addr` (the IPv4 broadcast address by default).
+    pub fn new(sockets: &mut SocketSet<'static, 'static, 'static>,
+               addresses: &NetAddresses) -> Option<Heartbeat> {
+        let interval_ms = match config::read_str("heartbeat_interval_ms",
+                                                 |r| r.map(|s| s.parse::<u64>())) {
+            Ok(Ok(interval_ms)) if interval_ms > 0 => interval_ms,
+            Ok(_) => {
+                error!("invalid heartbeat_interval_ms");
+                return None
+            }
+            Err(_) => return None
+        };
+        let port = match config::read_str("heartbeat_port", |r| r.map(|s| s.parse::<u16>())) {
+            Ok(Ok(port)) => port,
+            Ok(Err(_)) => {
+                error!("invalid heartbeat_port");
+                return None
+            }
+            Err(_) => DEFAULT_PORT
+        };
+        let address = match config::read_str("heartbeat_addr", |r| r.map(|s| s.parse::<IpAddress>())) {
+            Ok(Ok(address)) => address,
+            Ok(Err(())) => {
+                error!("invalid heartbeat_addr");
+                return None
+            }
+            Err(_) => IpAddress::Ipv4(Ipv4Address::BROADCAST)
+        };
+
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 1], vec![0; 16]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 1], vec![0; 512]);
+        let handle = sockets.add(UdpSocket::new(rx_buffer, tx_buffer));
+        sockets.get::<UdpSocket>(handle).bind(port).expect("heartbeat: cannot bind");
+
+        info!("sending heartbeats to {}:{} every {} ms", address, port, interval_ms);
+        Some(Heartbeat {
+            handle:      handle,
+            destination: IpEndpoint::new(address, port),
+            interval_ms: interval_ms,
+            hostname:    mdns::hostname(addresses),
+            serial:      addresses.hardware_addr.0,
+            next_at:     0
+        })
+    }
+
+    fn message(&self) -> Result<String, fmt::Error> {
+        let mut gateware = [0; 64];
+        let gateware = ident::read(&mut gateware);
+        let state =
+            if session::host_connected() {
+                "host"
+            } else if kernel::running() {
+                "idle_kernel"
+            } else {
+                "idle"
+            };
+
+        let mut message = String::new();
+        message.push_str("{\"hostname\":");
+        write_string(&mut message, &self.hostname)?;
+        let s = &self.serial;
+        write!(message, ",\"serial\":\"{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}\"",
+               s[0], s[1], s[2], s[3], s[4], s[5])?;
+        message.push_str(",\"firmware\":");
+        write_string(&mut message, csr::CONFIG_IDENTIFIER_STR)?;
+        message.push_str(",\"gateware\":");
+        write_string(&mut message, gateware)?;
+        write!(message, ",\"uptime_s\":{},\"session\":\"{}\",\"kernel\":{},\"alarms\":[",
+               clock::get_ms() / 1000, state, kernel::running())?;
+        let mut first = true;
+        status_led::active_alarms(|name| {
+            if !first { message.push(',') }
+            message.push('"');
+            message.push_str(name);
+            message.push('"');
+            first = false;
+        });
+        message.push_str("]}");
+        Ok(message)
+    }
+
+    pub fn poll(&mut self, sockets: &mut SocketSet<'static, 'static, 'static>) {
+        let now = clock::get_ms();
+        if now < self.next_at {
+            return
+        }
+        let mut socket = sockets.get::<UdpSocket>(self.handle);
+        if !socket.can_send() {
+            return
+        }
+        let message = match self.message() {
+            Ok(message) => message,
+            Err(_) => return
+        };
+        if let Err(err) = socket.send_slice(message.as_bytes(), self.destination) {
+            debug!("cannot send heartbeat: {}", err);
+        }
+        self.next_at = now + self.interval_ms;
+    }
+}
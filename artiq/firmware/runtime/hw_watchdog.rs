@@ -0,0 +1,96 @@
+// Hardware watchdog of the comms CPU, fed by a high priority timer four
+// times per timeout, so that the device restarts when the scheduler stops
+// running. Distinct from the software watchdogs that kernels set, see
+// watchdog.rs.
+
+/// Message of the panic raised when the watchdog expires, which is saved
+/// with the panic log.
+pub const EXPIRED: &'static str = "hardware watchdog expired";
+
+#[cfg(has_watchdog)]
+mod imp {
+    use board_misoc::{csr, irq, config};
+    use sched::Priority;
+    use timers;
+
+    static mut TIMEOUT_MS: u16 = 0;
+
+    fn set_timeout(timeout_ms: u16) {
+        unsafe {
+            csr::watchdog::timeout_ms_write(timeout_ms);
+            if timeout_ms != 0 {
+                csr::watchdog::ev_pending_write(1);
+                csr::watchdog::ev_enable_write(1);
+                irq::enable(csr::WATCHDOG_INTERRUPT);
+            } else {
+                irq::disable(csr::WATCHDOG_INTERRUPT);
+            }
+        }
+    }
+
+    /// Starts the watchdog with the timeout in the `watchdog_timeout_ms`
+    /// config entry, or stops it if there is none.
+    pub fn init() {
+        let timeout_ms = match config::read_str("watchdog_timeout_ms", |r| r.map(|s| s.parse())) {
+            Ok(Ok(timeout_ms)) => timeout_ms,
+            Ok(Err(_)) => {
+                warn!("invalid watchdog_timeout_ms, hardware watchdog disabled");
+                0
+            }
+            Err(_) => 0
+        };
+        if timeout_ms != 0 {
+            info!("hardware watchdog enabled with a timeout of {} ms", timeout_ms);
+            feed();
+            timers::register("hw_watchdog", timeout_ms as u64 * 1000 / 4, Priority::High,
+                             |_| feed());
+        }
+        unsafe { TIMEOUT_MS = timeout_ms }
+        set_timeout(timeout_ms)
+    }
+
+    pub fn feed() {
+        unsafe { csr::watchdog::feed_write(1) }
+    }
+
+    /// Runs `f`, e.g. a long flash operation, with the watchdog stopped.
+    pub fn suspend<F: FnOnce() -> R, R>(f: F) -> R {
+        set_timeout(0);
+        let result = f();
+        set_timeout(unsafe { TIMEOUT_MS });
+        result
+    }
+
+    /// Stops the watchdog for good, before a restart that skips the
+    /// bootloader or in the panic handler.
+    pub fn disable() {
+        unsafe { TIMEOUT_MS = 0 }
+        set_timeout(0)
+    }
+
+    /// Whether the interrupt being handled is the expiry of the watchdog.
+    pub fn expired() -> bool {
+        irq::is_pending(csr::WATCHDOG_INTERRUPT)
+    }
+}
+
+#[cfg(not(has_watchdog))]
+mod imp {
+    use board_misoc::config;
+
+    pub fn init() {
+        if config::read_str("watchdog_timeout_ms", |r| r.is_ok()) {
+            warn!("watchdog_timeout_ms is set, but the gateware has no hardware watchdog");
+        }
+    }
+
+    pub fn feed() {}
+
+    pub fn suspend<F: FnOnce() -> R, R>(f: F) -> R { f() }
+
+    pub fn disable() {}
+
+    pub fn expired() -> bool { false }
+}
+
+pub use self::imp::*;
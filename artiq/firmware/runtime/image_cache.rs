@@ -0,0 +1,140 @@
+use alloc::Vec;
+use byteorder::{ByteOrder, NetworkEndian};
+use board_misoc::config;
+use session_proto::delta;
+
+const DEFAULT_CAPACITY: usize = 4;
+
+// FNV-1a, which is cheap enough to run over every kernel that is loaded.
+pub fn hash(data: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for &byte in data {
+        hash = (hash ^ byte as u32).wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+// Rebuilds a kernel from `base` and a patch of `session_proto::delta`
+// operations, checking that it is `length` bytes long.
+pub fn apply_delta(base: &[u8], patch: &[u8], length: usize) -> Result<Vec<u8>, &'static str> {
+    fn take<'a>(patch: &mut &'a [u8], len: usize) -> Result<&'a [u8], &'static str> {
+        if patch.len() < len {
+            return Err("truncated patch")
+        }
+        let (head, tail) = patch.split_at(len);
+        *patch = tail;
+        Ok(head)
+    }
+    fn take_u32(patch: &mut &[u8]) -> Result<usize, &'static str> {
+        take(patch, 4).map(|bytes| NetworkEndian::read_u32(bytes) as usize)
+    }
+
+    let mut library = Vec::with_capacity(length);
+    let mut patch = patch;
+    while !patch.is_empty() {
+        let op = take(&mut patch, 1)?[0];
+        match op {
+            delta::COPY => {
+                let offset = take_u32(&mut patch)?;
+                let len = take_u32(&mut patch)?;
+                match offset.checked_add(len) {
+                    Some(end) if end <= base.len() =>
+                        library.extend_from_slice(&base[offset..end]),
+                    _ => return Err("patch copies beyond the base kernel")
+                }
+            }
+            delta::INSERT => {
+                let len = take_u32(&mut patch)?;
+                library.extend_from_slice(take(&mut patch, len)?);
+            }
+            _ => return Err("unknown patch operation")
+        }
+        if library.len() > length {
+            return Err("patched kernel too long")
+        }
+    }
+    if library.len() != length {
+        return Err("patched kernel too short")
+    }
+    Ok(library)
+}
+
+#[derive(Debug)]
+struct Entry {
+    hash: u32,
+    // The library is compared in full on lookup, so that a hash collision
+    // cannot run the wrong kernel.
+    library: Vec<u8>,
+    image: Vec<u8>,
+    // Value of `ImageCache::clock` when the entry was last used.
+    last_used: u64
+}
+
+// Relocated images of recently loaded kernels. Loading the same kernel again,
+// e.g. once per point of a scan, copies the image instead of relocating it.
+#[derive(Debug)]
+pub struct ImageCache {
+    entries: Vec<Entry>,
+    capacity: usize,
+    clock: u64
+}
+
+impl ImageCache {
+    pub fn new() -> ImageCache {
+        let capacity = config::read_str("kernel_image_cache",
+                                        |r| r.ok().and_then(|s| s.parse().ok()))
+                             .unwrap_or(DEFAULT_CAPACITY);
+        if capacity != DEFAULT_CAPACITY {
+            info!("caching the relocated images of up to {} kernels", capacity);
+        }
+        ImageCache {
+            entries: Vec::new(),
+            capacity: capacity,
+            clock: 0
+        }
+    }
+
+    pub fn get(&mut self, hash: u32, library: &[u8]) -> Option<&[u8]> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.iter_mut()
+            .find(|entry| entry.hash == hash && &entry.library[..] == library)
+            .map(|entry| {
+                entry.last_used = clock;
+                &entry.image[..]
+            })
+    }
+
+    // Library of a cached kernel, as the base of a `LoadKernelDelta` request.
+    pub fn library(&self, hash: u32) -> Option<&[u8]> {
+        self.entries.iter()
+            .find(|entry| entry.hash == hash)
+            .map(|entry| &entry.library[..])
+    }
+
+    pub fn insert(&mut self, hash: u32, library: &[u8], image: &[u8]) {
+        if self.capacity == 0 { return }
+
+        while self.entries.len() >= self.capacity {
+            let lru = self.entries.iter().enumerate()
+                                  .min_by_key(|&(_, entry)| entry.last_used)
+                                  .map(|(index, _)| index)
+                                  .unwrap();
+            self.entries.remove(lru);
+        }
+
+        // Leave at least as much heap to the kernel as the entry would take.
+        let size = library.len() + image.len();
+        if ::heap_stats().largest_free < size * 2 {
+            debug!("not caching kernel image ({} bytes): not enough memory", size);
+            return
+        }
+
+        self.entries.push(Entry {
+            hash: hash,
+            library: Vec::from(library),
+            image: Vec::from(image),
+            last_used: self.clock
+        })
+    }
+}
@@ -0,0 +1,143 @@
+use alloc::Vec;
+use board_misoc::{csr, config};
+use sched::Priority;
+use {timers, session, safe_state};
+use alerts::{self, Severity, Source};
+
+// Interlock inputs, e.g. a door switch or a laser safety interlock, are TTL
+// inputs that the firmware polls continuously, independently of the kernels.
+// The `interlock` config entry lists them as `channel=level[:action]` entries
+// separated by commas, where `level` is the input level that trips the
+// interlock, e.g. `3=0,4=1:alert`. Actions are:
+//   * `stop` (default): stops the running kernel without a grace period,
+//     refuses to load kernels while the input is tripped, and applies the
+//     safe state (see `safe_state`),
+//   * `safe_state`: applies the safe state, and lets the kernel run,
+//   * `alert`: only raises the alert.
+// Every trip raises a critical alert. The safe state is held while the input
+// is tripped, even across new kernel sessions, and released as usual by the
+// next session once the input is cleared. Only local channels can be
+// monitored, since their level is read with the moninj probes. The inputs are
+// polled every `interlock_poll_us` (1000 by default).
+
+const DEFAULT_POLL_US: u64 = 1000;
+
+// moninj probe of the level of TTL inputs.
+const PROBE_LEVEL: u8 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Alert,
+    SafeState,
+    Stop
+}
+
+struct Input {
+    channel: u16,
+    level:   u8,
+    action:  Action,
+    tripped: bool
+}
+
+static mut STOPPED_BY: Option<u16> = None;
+
+/// The channel of a tripped interlock that stops kernels, if any.
+pub fn tripped() -> Option<u16> {
+    unsafe { STOPPED_BY }
+}
+
+fn inputs() -> Vec<Input> {
+    config::read_str("interlock", |r| {
+        let mut inputs = Vec::new();
+        for entry in r.unwrap_or("").split(',').filter(|entry| !entry.is_empty()) {
+            let mut parts = entry.splitn(2, '=');
+            let channel = parts.next().and_then(|s| s.trim().parse::<u32>().ok());
+            let mut parts = parts.next().unwrap_or("").splitn(2, ':');
+            let level = parts.next().and_then(|s| s.trim().parse::<u8>().ok());
+            let action = match parts.next().map(|s| s.trim()) {
+                None | Some("stop") => Some(Action::Stop),
+                Some("safe_state") => Some(Action::SafeState),
+                Some("alert") => Some(Action::Alert),
+                Some(_) => None
+            };
+            match (channel, level, action) {
+                (Some(channel), _, _) if channel >= 0x10000 =>
+                    warn!("interlock on channel {}: only local channels can be monitored", channel),
+                (Some(channel), Some(level), Some(action)) if level <= 1 =>
+                    inputs.push(Input {
+                        channel: channel as u16,
+                        level:   level,
+                        action:  action,
+                        tripped: false
+                    }),
+                _ => warn!("invalid entry {:?} in `interlock` config key", entry)
+            }
+        }
+        inputs
+    })
+}
+
+fn read_level(channel: u16) -> u8 {
+    unsafe {
+        csr::rtio_moninj::mon_chan_sel_write(channel as _);
+        csr::rtio_moninj::mon_probe_sel_write(PROBE_LEVEL);
+        csr::rtio_moninj::mon_value_update_write(1);
+        csr::rtio_moninj::mon_value_read() as u8
+    }
+}
+
+fn trip(input: &Input) {
+    error!("interlock on channel {} tripped", input.channel);
+    alerts::push(Severity::Critical, Source::Interlock,
+                 format!("interlock on channel {} tripped", input.channel));
+    if input.action == Action::Stop {
+        unsafe { STOPPED_BY = Some(input.channel) }
+        session::request_stop(&format!("interlock on channel {} tripped", input.channel));
+    }
+}
+
+fn rearm(input: &Input, inputs: &[Input]) {
+    info!("interlock on channel {} cleared", input.channel);
+    alerts::push(Severity::Info, Source::Interlock,
+                 format!("interlock on channel {} cleared", input.channel));
+    if tripped() == Some(input.channel) {
+        let other = inputs.iter()
+            .find(|other| other.tripped && other.action == Action::Stop)
+            .map(|other| other.channel);
+        unsafe { STOPPED_BY = other }
+    }
+}
+
+pub fn init() {
+    let mut inputs = inputs();
+    if inputs.is_empty() {
+        return
+    }
+    let poll_us = config::read_str("interlock_poll_us", |r| match r.map(|s| s.parse()) {
+        Ok(Ok(0)) | Ok(Err(_)) => {
+            warn!("invalid interlock_poll_us, using {} us", DEFAULT_POLL_US);
+            DEFAULT_POLL_US
+        }
+        Ok(Ok(poll_us)) => poll_us,
+        Err(_) => DEFAULT_POLL_US
+    });
+    info!("monitoring {} interlock inputs every {} us", inputs.len(), poll_us);
+
+    timers::register("interlock", poll_us, Priority::High, move |_io| {
+        for index in 0..inputs.len() {
+            let tripped = read_level(inputs[index].channel) == inputs[index].level;
+            if tripped != inputs[index].tripped {
+                inputs[index].tripped = tripped;
+                if tripped {
+                    trip(&inputs[index])
+                } else {
+                    rearm(&inputs[index], &inputs)
+                }
+            }
+        }
+        // Also brings the safe state back after a new session released it.
+        if inputs.iter().any(|input| input.tripped && input.action != Action::Alert) {
+            safe_state::trigger(safe_state::Cause::Interlock)
+        }
+    })
+}
@@ -1,10 +1,13 @@
+use alloc::Vec;
 use core::cell::RefCell;
 use kernel_proto as kern;
 use sched::{Io, Mutex, Error as SchedError};
 use session::{kern_acknowledge, kern_send, Error};
 use rtio_mgt;
+use nrt_bus;
 use urc::Urc;
 use board_misoc::i2c as local_i2c;
+use board_misoc::eeprom_24xx::Eeprom;
 use board_artiq::drtio_routing;
 use board_artiq::spi as local_spi;
 
@@ -115,6 +118,23 @@ mod remote_i2c {
             }
         }
     }
+
+    pub fn write_buffer(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8, busno: u8, data: &[u8]) -> Result<usize, &'static str> {
+        for (i, &byte) in data.iter().enumerate() {
+            if !write(io, aux_mutex, linkno, destination, busno, byte)? {
+                return Ok(i)
+            }
+        }
+        Ok(data.len())
+    }
+
+    pub fn read_buffer(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8, busno: u8, data: &mut [u8]) -> Result<(), &'static str> {
+        let len = data.len();
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = read(io, aux_mutex, linkno, destination, busno, i < len - 1)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(has_drtio)]
@@ -187,15 +207,81 @@ mod remote_spi {
             }
         }
     }
+
+    pub fn write_buffer(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8, busno: u8, data: &[u32]) -> Result<(), ()> {
+        for &word in data {
+            write(io, aux_mutex, linkno, destination, busno, word)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_buffer(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8, busno: u8, data: &mut [u32]) -> Result<(), ()> {
+        for word in data.iter_mut() {
+            write(io, aux_mutex, linkno, destination, busno, 0)?;
+            *word = read(io, aux_mutex, linkno, destination, busno)?;
+        }
+        Ok(())
+    }
+
+    pub fn transfer(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8, busno: u8, data: &mut [u32]) -> Result<(), ()> {
+        for word in data.iter_mut() {
+            write(io, aux_mutex, linkno, destination, busno, *word)?;
+            *word = read(io, aux_mutex, linkno, destination, busno)?;
+        }
+        Ok(())
+    }
 }
 
+// Buses of the master, which are shared with the background tasks of the
+// runtime; see `nrt_bus`.
+fn local_bus(busno: u32) -> Option<u8> {
+    if busno >> 16 == 0 { Some(busno as u8) } else { None }
+}
+
+// The EEPROM driver only drives the I2C buses of the master.
+fn local_eeprom(busno: u32, port: i32, address: u8, size: u32, page_size: u32)
+               -> Result<Eeprom, &'static str> {
+    if busno >> 16 != 0 {
+        return Err("EEPROMs of DRTIO satellites are not supported")
+    }
+    let port = if port < 0 { None } else { Some(port as u8) };
+    Eeprom::new(busno as u8, port, address, size, page_size)
+}
+
+// SMBus transfers are driven by board_misoc, on the I2C buses of the master.
+fn local_smbus(busno: u32, address: u16, ten_bit: bool)
+              -> Result<(u8, local_i2c::Address), &'static str> {
+    if busno >> 16 != 0 {
+        return Err("SMBus devices of DRTIO satellites are not supported")
+    }
+    if ten_bit {
+        Ok((busno as u8, local_i2c::Address::TenBit(address)))
+    } else if address > 0x7f {
+        Err("invalid 7-bit I2C address")
+    } else {
+        Ok((busno as u8, local_i2c::Address::SevenBit(address as u8)))
+    }
+}
+
+// An SMBus transfer is a whole transaction of the kernel on the bus.
+fn smbus_transaction<F, T>(io: &Io, busno: u8, f: F) -> Result<T, &'static str>
+    where F: FnOnce() -> Result<T, &'static str>
+{
+    if nrt_bus::kernel_holds(busno) {
+        return Err("SMBus transfer within an I2C transaction")
+    }
+    nrt_bus::kernel_start(io, busno);
+    let result = f();
+    nrt_bus::kernel_stop(busno);
+    result
+}
 
 #[cfg(has_drtio)]
 macro_rules! dispatch {
     ($io:ident, $aux_mutex:ident, $mod_local:ident, $mod_remote:ident, $routing_table:ident, $busno:expr, $func:ident $(, $param:expr)*) => {{
         let destination = ($busno >> 16) as u8;
         let busno = $busno as u8;
-        let hop = $routing_table.0[destination as usize][0];
+        let hop = $routing_table.borrow().0[destination as usize][0];
         if hop == 0 {
             $mod_local::$func(busno, $($param, )*)
         } else {
@@ -214,7 +300,7 @@ macro_rules! dispatch {
 }
 
 pub fn process_kern_hwreq(io: &Io, aux_mutex: &Mutex,
-        _routing_table: &drtio_routing::RoutingTable,
+        _routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
         _up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
         request: &kern::Message) -> Result<bool, Error<SchedError>> {
     match request {
@@ -224,30 +310,40 @@ pub fn process_kern_hwreq(io: &Io, aux_mutex: &Mutex,
             kern_acknowledge()
         }
 
-        &kern::RtioDestinationStatusRequest { destination: _destination } => {
-            #[cfg(has_drtio)]
-            let up = {
-                let up_destinations = _up_destinations.borrow();
-                up_destinations[_destination as usize]
-            };
-            #[cfg(not(has_drtio))]
-            let up = true;
-            kern_send(io, &kern::RtioDestinationStatusReply { up: up })
+        &kern::RtioDestinationStatusRequest { destination } => {
+            let age_ms = rtio_mgt::drtio::destination_age_ms(&_routing_table.borrow(),
+                                                              _up_destinations, destination);
+            kern_send(io, &kern::RtioDestinationStatusReply {
+                up:     age_ms.is_some(),
+                age_ms: age_ms.unwrap_or(0)
+            })
         }
 
         &kern::I2cStartRequest { busno } => {
+            if let Some(busno) = local_bus(busno) {
+                nrt_bus::kernel_start(io, busno)
+            }
             let succeeded = dispatch!(io, aux_mutex, local_i2c, remote_i2c, _routing_table, busno, start).is_ok();
             kern_send(io, &kern::I2cBasicReply { succeeded: succeeded })
         }
         &kern::I2cRestartRequest { busno } => {
+            if let Some(busno) = local_bus(busno) {
+                nrt_bus::kernel_restart(busno)
+            }
             let succeeded = dispatch!(io, aux_mutex, local_i2c, remote_i2c, _routing_table, busno, restart).is_ok();
             kern_send(io, &kern::I2cBasicReply { succeeded: succeeded })
         }
         &kern::I2cStopRequest { busno } => {
             let succeeded = dispatch!(io, aux_mutex, local_i2c, remote_i2c, _routing_table, busno, stop).is_ok();
+            if let Some(busno) = local_bus(busno) {
+                nrt_bus::kernel_stop(busno)
+            }
             kern_send(io, &kern::I2cBasicReply { succeeded: succeeded })
         }
         &kern::I2cWriteRequest { busno, data } => {
+            if let Some(busno) = local_bus(busno) {
+                nrt_bus::kernel_write(busno, data)
+            }
             match dispatch!(io, aux_mutex, local_i2c, remote_i2c, _routing_table, busno, write, data) {
                 Ok(ack) => kern_send(io, &kern::I2cWriteReply { succeeded: true, ack: ack }),
                 Err(_) => kern_send(io, &kern::I2cWriteReply { succeeded: false, ack: false })
@@ -259,6 +355,66 @@ pub fn process_kern_hwreq(io: &Io, aux_mutex: &Mutex,
                 Err(_) => kern_send(io, &kern::I2cReadReply { succeeded: false, data: 0xff })
             }
         }
+        &kern::I2cWriteBufferRequest { busno, data } => {
+            let data: Vec<u8> = data.iter().map(|&byte| byte as u8).collect();
+            if let Some(busno) = local_bus(busno) {
+                for &byte in data.iter() {
+                    nrt_bus::kernel_write(busno, byte)
+                }
+            }
+            match dispatch!(io, aux_mutex, local_i2c, remote_i2c, _routing_table, busno, write_buffer, &data) {
+                Ok(acked) => kern_send(io, &kern::I2cWriteBufferReply { succeeded: true, acked: acked }),
+                Err(_) => kern_send(io, &kern::I2cWriteBufferReply { succeeded: false, acked: 0 })
+            }
+        }
+        &kern::I2cReadBufferRequest { busno, length } => {
+            let mut data = vec![0; length];
+            match dispatch!(io, aux_mutex, local_i2c, remote_i2c, _routing_table, busno, read_buffer, &mut data) {
+                Ok(()) => kern_send(io, &kern::I2cReadBufferReply { succeeded: true, data: &data }),
+                Err(_) => kern_send(io, &kern::I2cReadBufferReply { succeeded: false, data: &[] })
+            }
+        }
+
+        &kern::EepromReadRequest { busno, port, address, size, page_size, offset, length } => {
+            let result = local_eeprom(busno, port, address, size, page_size)
+                .and_then(|eeprom| {
+                    if length > eeprom.size() as usize {
+                        return Err("EEPROM access out of range")
+                    }
+                    let mut data = vec![0; length];
+                    eeprom.read(offset, &mut data).map(|()| data)
+                });
+            match result {
+                Ok(data) => kern_send(io, &kern::EepromReadReply(Ok(&data))),
+                Err(e) => kern_send(io, &kern::EepromReadReply(Err(e)))
+            }
+        }
+        &kern::EepromWriteRequest { busno, port, address, size, page_size, offset, data } => {
+            let data: Vec<u8> = data.iter().map(|&byte| byte as u8).collect();
+            let result = local_eeprom(busno, port, address, size, page_size)
+                .and_then(|eeprom| eeprom.write(offset, &data));
+            kern_send(io, &kern::EepromWriteReply(result))
+        }
+
+        &kern::SmbusBlockWriteRequest { busno, address, ten_bit, command, data, pec } => {
+            let data: Vec<u8> = data.iter().map(|&byte| byte as u8).collect();
+            let result = local_smbus(busno, address, ten_bit)
+                .and_then(|(busno, address)| smbus_transaction(io, busno, || {
+                    local_i2c::smbus_block_write(busno, address, command, &data, pec)
+                }));
+            kern_send(io, &kern::SmbusBlockWriteReply(result))
+        }
+        &kern::SmbusBlockReadRequest { busno, address, ten_bit, command, length, pec } => {
+            let mut data = vec![0; length];
+            let result = local_smbus(busno, address, ten_bit)
+                .and_then(|(busno, address)| smbus_transaction(io, busno, || {
+                    local_i2c::smbus_block_read(busno, address, command, &mut data, pec)
+                }));
+            match result {
+                Ok(count) => kern_send(io, &kern::SmbusBlockReadReply(Ok(&data[..count]))),
+                Err(e) => kern_send(io, &kern::SmbusBlockReadReply(Err(e)))
+            }
+        }
 
         &kern::SpiSetConfigRequest { busno, flags, length, div, cs } => {
             let succeeded = dispatch!(io, aux_mutex, local_spi, remote_spi, _routing_table, busno,
@@ -276,6 +432,26 @@ pub fn process_kern_hwreq(io: &Io, aux_mutex: &Mutex,
                 Err(_) => kern_send(io, &kern::SpiReadReply { succeeded: false, data: 0 })
             }
         }
+        &kern::SpiWriteBufferRequest { busno, data } => {
+            let data: Vec<u32> = data.iter().map(|&word| word as u32).collect();
+            let succeeded = dispatch!(io, aux_mutex, local_spi, remote_spi, _routing_table, busno,
+                write_buffer, &data).is_ok();
+            kern_send(io, &kern::SpiBasicReply { succeeded: succeeded })
+        }
+        &kern::SpiReadBufferRequest { busno, length } => {
+            let mut data = vec![0; length];
+            match dispatch!(io, aux_mutex, local_spi, remote_spi, _routing_table, busno, read_buffer, &mut data) {
+                Ok(()) => kern_send(io, &kern::SpiReadBufferReply { succeeded: true, data: &data }),
+                Err(_) => kern_send(io, &kern::SpiReadBufferReply { succeeded: false, data: &[] })
+            }
+        }
+        &kern::SpiTransferRequest { busno, data } => {
+            let mut data: Vec<u32> = data.iter().map(|&word| word as u32).collect();
+            match dispatch!(io, aux_mutex, local_spi, remote_spi, _routing_table, busno, transfer, &mut data) {
+                Ok(()) => kern_send(io, &kern::SpiReadBufferReply { succeeded: true, data: &data }),
+                Err(_) => kern_send(io, &kern::SpiReadBufferReply { succeeded: false, data: &[] })
+            }
+        }
 
         _ => return Ok(false)
     }.and(Ok(true))
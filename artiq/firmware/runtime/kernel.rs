@@ -2,6 +2,9 @@ use core::ptr;
 use board_misoc::csr;
 use mailbox;
 use rpc_queue;
+use stream_queue;
+use nrt_bus;
+use low_latency;
 
 use kernel_proto::{KERNELCPU_EXEC_ADDRESS, KERNELCPU_LAST_ADDRESS, KSUPPORT_HEADER_SIZE};
 
@@ -26,6 +29,7 @@ pub unsafe fn start() {
     csr::kernel_cpu::reset_write(0);
 
     rpc_queue::init();
+    stream_queue::init();
 }
 
 #[cfg(not(has_kernel_cpu))]
@@ -36,9 +40,24 @@ pub unsafe fn start() {
 pub unsafe fn stop() {
     #[cfg(has_kernel_cpu)]
     csr::kernel_cpu::reset_write(1);
+    nrt_bus::kernel_stopped();
+    low_latency::kernel_stopped();
 
     mailbox::acknowledge();
     rpc_queue::init();
+    stream_queue::init();
+}
+
+/// Whether the kernel CPU is running. See `nrt_bus` for the I2C buses it
+/// shares with the runtime.
+#[cfg(has_kernel_cpu)]
+pub fn running() -> bool {
+    unsafe { csr::kernel_cpu::reset_read() == 0 }
+}
+
+#[cfg(not(has_kernel_cpu))]
+pub fn running() -> bool {
+    false
 }
 
 pub fn validate(ptr: usize) -> bool {
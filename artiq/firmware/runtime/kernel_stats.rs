@@ -0,0 +1,165 @@
+use alloc::{Vec, String};
+use board_misoc::clock;
+use proto_artiq::mgmt_proto::KernelRunStats;
+use rtio_mgt;
+
+// Statistics of the kernel runs: their duration, the minimum slack of the
+// output events they wrote, as measured by the gateware, and the RTIO errors
+// counted meanwhile, so that users get quantitative feedback on the timing
+// margins of their experiments. The statistics of each run are sent to the
+// host at its end, and the last `CAPACITY` runs are kept for mgmt.
+
+const CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Finished  = 0,
+    Exception = 1,
+    Stopped   = 2
+}
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub sequence:      u32,
+    pub started_ms:    u64,
+    pub unix_us:       Option<u64>,
+    pub source:        String,
+    pub duration_us:   u64,
+    pub outcome:       Outcome,
+    pub output_events: u32,
+    pub min_slack_mu:  Option<i64>,
+    pub rtio_errors:   u32
+}
+
+impl Record {
+    pub fn as_proto(&self) -> KernelRunStats {
+        KernelRunStats {
+            sequence:      self.sequence,
+            started_ms:    self.started_ms,
+            unix_us:       self.unix_us,
+            source:        &self.source,
+            duration_us:   self.duration_us,
+            outcome:       self.outcome as u8,
+            output_events: self.output_events,
+            min_slack_mu:  self.min_slack_mu,
+            rtio_errors:   self.rtio_errors
+        }
+    }
+}
+
+struct History {
+    records:       Vec<Record>,
+    next_sequence: u32,
+    dropped:       u32
+}
+
+static mut HISTORY: Option<History> = None;
+
+fn history() -> &'static mut History {
+    unsafe {
+        if HISTORY.is_none() {
+            HISTORY = Some(History {
+                records: Vec::with_capacity(CAPACITY),
+                next_sequence: 1,
+                dropped: 0
+            })
+        }
+        HISTORY.as_mut().unwrap()
+    }
+}
+
+#[cfg(has_rtio_slack)]
+mod slack {
+    use board_misoc::csr;
+
+    pub fn reset() {
+        unsafe { csr::rtio_slack::reset_write(1) }
+    }
+
+    // Output events written since the reset, and their minimum slack.
+    pub fn read() -> (u32, Option<i64>) {
+        unsafe {
+            csr::rtio_slack::update_write(1);
+            let events = csr::rtio_slack::events_read();
+            let min_slack_mu = csr::rtio_slack::min_slack_read() as i64;
+            (events, if events == 0 { None } else { Some(min_slack_mu) })
+        }
+    }
+}
+
+#[cfg(not(has_rtio_slack))]
+mod slack {
+    pub fn reset() {}
+
+    pub fn read() -> (u32, Option<i64>) {
+        (0, None)
+    }
+}
+
+/// A kernel run, from its start.
+#[derive(Debug)]
+pub struct Run {
+    source:      String,
+    started_us:  u64,
+    unix_us:     Option<u64>,
+    rtio_errors: u32
+}
+
+impl Run {
+    /// Starts measuring a run of the kernel from `source`, just before it
+    /// starts.
+    pub fn start(source: &str) -> Run {
+        slack::reset();
+        Run {
+            source:      String::from(source),
+            started_us:  clock::get_us(),
+            unix_us:     clock::get_unix_us(),
+            rtio_errors: rtio_mgt::errors_since_boot()
+        }
+    }
+
+    /// Ends the run and keeps its statistics, which are returned. Must be
+    /// called after the RTIO errors that ended the kernel, if any, are
+    /// counted.
+    pub fn finish(self, outcome: Outcome) -> &'static Record {
+        let (output_events, min_slack_mu) = slack::read();
+        let history = history();
+        if history.records.len() == CAPACITY {
+            history.records.remove(0);
+            history.dropped = history.dropped.wrapping_add(1);
+        }
+        let record = Record {
+            sequence:      history.next_sequence,
+            started_ms:    self.started_us / 1000,
+            unix_us:       self.unix_us,
+            source:        self.source,
+            duration_us:   clock::get_us() - self.started_us,
+            outcome:       outcome,
+            output_events: output_events,
+            min_slack_mu:  min_slack_mu,
+            rtio_errors:   rtio_mgt::errors_since_boot().wrapping_sub(self.rtio_errors)
+        };
+        match record.min_slack_mu {
+            Some(min_slack_mu) =>
+                debug!("kernel run #{} ({}): {} us, {} output events, minimum slack {} mu, \
+                        {} RTIO errors", record.sequence, record.source, record.duration_us,
+                       record.output_events, min_slack_mu, record.rtio_errors),
+            None =>
+                debug!("kernel run #{} ({}): {} us, {} RTIO errors", record.sequence,
+                       record.source, record.duration_us, record.rtio_errors)
+        }
+        history.next_sequence = history.next_sequence.wrapping_add(1);
+        history.records.push(record);
+        history.records.last().unwrap()
+    }
+}
+
+/// Statistics of the runs kept, oldest first.
+pub fn records() -> &'static [Record] {
+    &history().records
+}
+
+/// Number of runs dropped since boot to make room for newer ones.
+pub fn dropped() -> u32 {
+    history().dropped
+}
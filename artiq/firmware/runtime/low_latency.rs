@@ -0,0 +1,125 @@
+use board_misoc::{clock, config};
+
+// Bounded-latency mode of the comms CPU, for the phases of a kernel that
+// make many RPCs in a row. While the mode is active, the low priority
+// threads (mgmt, analyzer streaming, monitoring) are no longer resumed to
+// avoid their starvation, the main loop processes a bounded number of
+// network frames per iteration, and the background network services and
+// the log flush only run every `BACKGROUND_INTERVAL_MS`, so that the session
+// thread is serviced as soon as a reply of the host arrives.
+//
+// Kernels open a window of the mode with the `rpc_low_latency` syscall, and
+// close it by requesting a window of 0 ms or by ending. Windows are capped
+// at `low_latency_max_ms` (0 disables the mode), so that the background
+// tasks are never held back for long. With `low_latency_auto_rpcs` set, the
+// mode is also entered for `RATE_WINDOW_MS` whenever the kernel makes that
+// many synchronous RPCs within `RATE_WINDOW_MS`.
+
+const DEFAULT_MAX_MS: u64 = 1_000;
+const RATE_WINDOW_MS: u64 = 100;
+const BACKGROUND_INTERVAL_MS: u64 = 50;
+
+/// Number of times the network interface is polled at each iteration of the
+/// main loop while the mode is active.
+pub const POLL_BUDGET: usize = 4;
+
+struct State {
+    max_ms:        u64,
+    // 0 if the mode is not entered automatically.
+    auto_rpcs:     u32,
+    until_ms:      u64,
+    rate_start_ms: u64,
+    rpcs:          u32,
+    background_ms: u64
+}
+
+static mut STATE: State = State {
+    max_ms:        DEFAULT_MAX_MS,
+    auto_rpcs:     0,
+    until_ms:      0,
+    rate_start_ms: 0,
+    rpcs:          0,
+    background_ms: 0
+};
+
+fn read_config(key: &str, default: u64) -> u64 {
+    config::read_str(key, |r| match r.map(|s| s.parse()) {
+        Ok(Ok(value)) => value,
+        Ok(Err(_)) => {
+            warn!("invalid {}, using {}", key, default);
+            default
+        }
+        Err(_) => default
+    })
+}
+
+/// Reads the configuration of the mode.
+pub fn init() {
+    let state = unsafe { &mut STATE };
+    state.max_ms = read_config("low_latency_max_ms", DEFAULT_MAX_MS);
+    state.auto_rpcs = read_config("low_latency_auto_rpcs", 0) as u32;
+    if state.max_ms == 0 {
+        info!("bounded-latency mode disabled")
+    } else if state.auto_rpcs != 0 {
+        info!("bounded-latency mode entered at {} RPCs per {} ms", state.auto_rpcs, RATE_WINDOW_MS)
+    }
+}
+
+fn open(duration_ms: u64) {
+    let state = unsafe { &mut STATE };
+    let until_ms = clock::get_ms() + duration_ms.min(state.max_ms);
+    if until_ms > state.until_ms {
+        state.until_ms = until_ms
+    }
+}
+
+/// Opens a window of the mode for `duration_ms`, or closes the current one
+/// if it is 0. Requested by the kernel.
+pub fn request(duration_ms: u32) {
+    if duration_ms == 0 {
+        unsafe { STATE.until_ms = 0 }
+    } else {
+        open(duration_ms as u64)
+    }
+}
+
+/// Counts a synchronous RPC of the kernel, which enters the mode if the
+/// kernel makes them often enough.
+pub fn rpc() {
+    let state = unsafe { &mut STATE };
+    if state.auto_rpcs == 0 {
+        return
+    }
+    let now = clock::get_ms();
+    if now >= state.rate_start_ms + RATE_WINDOW_MS {
+        state.rate_start_ms = now;
+        state.rpcs = 0;
+    }
+    state.rpcs += 1;
+    if state.rpcs >= state.auto_rpcs {
+        open(RATE_WINDOW_MS)
+    }
+}
+
+/// Ends the mode when the kernel stops.
+pub fn kernel_stopped() {
+    let state = unsafe { &mut STATE };
+    state.until_ms = 0;
+    state.rpcs = 0;
+}
+
+pub fn active() -> bool {
+    clock::get_ms() < unsafe { STATE.until_ms }
+}
+
+/// Whether the background network services and the log flush are to run at
+/// this iteration of the main loop.
+pub fn background_due() -> bool {
+    let state = unsafe { &mut STATE };
+    let now = clock::get_ms();
+    if now < state.until_ms && now < state.background_ms + BACKGROUND_INTERVAL_MS {
+        return false
+    }
+    state.background_ms = now;
+    true
+}
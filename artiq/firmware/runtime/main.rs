@@ -17,7 +17,8 @@ extern crate fringe;
 extern crate managed;
 extern crate smoltcp;
 
-extern crate alloc_list;
+extern crate alloc_tlsf;
+extern crate dyld;
 extern crate unwind_backtrace;
 extern crate io;
 #[macro_use]
@@ -26,18 +27,19 @@ extern crate board_artiq;
 extern crate logger_artiq;
 extern crate proto_artiq;
 
+use core::cmp::min;
 use core::cell::RefCell;
 use core::convert::TryFrom;
 use smoltcp::wire::IpCidr;
 
-use board_misoc::{csr, irq, ident, clock, boot, config, net_settings};
+use board_misoc::{csr, irq, exception, ident, clock, boot, config, net_settings};
 #[cfg(has_ethmac)]
 use board_misoc::ethmac;
 #[cfg(has_drtio)]
 use board_artiq::drtioaux;
 use board_artiq::drtio_routing;
-use board_artiq::{mailbox, rpc_queue};
-use proto_artiq::{mgmt_proto, moninj_proto, rpc_proto, session_proto, kernel_proto};
+use board_artiq::{mailbox, rpc_queue, stream_queue};
+use proto_artiq::{mgmt_proto, health_record, moninj_proto, rpc_proto, session_proto, kernel_proto};
 #[cfg(has_rtio_analyzer)]
 use proto_artiq::analyzer_proto;
 
@@ -47,11 +49,55 @@ mod rtio_mgt;
 mod urc;
 mod sched;
 mod cache;
+mod image_cache;
 mod rtio_dma;
+mod watchdog;
+mod hw_watchdog;
+mod symbols;
+mod auth;
+mod dhcp;
+mod mdns;
+mod sntp;
+mod heartbeat;
+mod syslog;
+mod stream;
+#[cfg(has_uart)]
+mod uart_shell;
+mod board_identity;
+mod eem_presence;
+mod eem_test;
+mod compat;
+mod features;
+mod status_led;
+mod alerts;
+mod audit;
+mod post;
+mod dds_sync;
+mod sysmon;
+mod sensor_history;
+#[cfg(has_spiflash)]
+mod flash_scrub;
+mod fault_injection;
+mod safe_state;
+#[cfg(has_rtio_moninj)]
+mod interlock;
+mod shutdown;
+mod nrt_bus;
+mod low_latency;
+mod timers;
+mod bus_passthrough;
+#[cfg(has_drtio_eye_scan)]
+mod eye_scan;
+mod power;
+mod rtc;
+mod sfp_monitor;
+#[cfg(all(has_fan, has_xadc))]
+mod fan;
 
 mod mgmt;
 mod profiler;
 mod kernel;
+mod kernel_stats;
 mod kern_hwreq;
 mod session;
 #[cfg(any(has_rtio_moninj, has_drtio))]
@@ -61,18 +107,34 @@ mod analyzer;
 
 #[cfg(has_grabber)]
 fn grabber_thread(io: sched::Io) {
+    let frame_timeout_ms = config::read_str("grabber_frame_timeout_ms",
+        |r| r.ok().and_then(|s| s.parse().ok())).filter(|&timeout_ms| timeout_ms != 0);
+    board_artiq::grabber::set_frame_timeout(frame_timeout_ms);
     loop {
         board_artiq::grabber::tick();
         io.sleep(200).unwrap();
     }
 }
 
+// The uptime of the last boot is lost up to this interval.
+#[cfg(has_spiflash)]
+const BOOT_STATS_SAVE_INTERVAL_MS: u64 = 600_000;
+
+#[cfg(has_spiflash)]
+fn boot_stats_thread(io: sched::Io) {
+    loop {
+        io.sleep(BOOT_STATS_SAVE_INTERVAL_MS).unwrap();
+        board_misoc::boot_stats::save_uptime();
+    }
+}
+
 fn setup_log_levels() {
     match config::read_str("log_level", |r| r.map(|s| s.parse())) {
         Ok(Ok(log_level_filter)) => {
             info!("log level set to {} by `log_level` config key",
                   log_level_filter);
-            log::set_max_level(log_level_filter);
+            logger_artiq::BufferLogger::with(|logger|
+                logger.set_log_level(log_level_filter));
         }
         _ => info!("log level set to INFO by default")
     }
@@ -85,6 +147,124 @@ fn setup_log_levels() {
         }
         _ => info!("UART log level set to INFO by default")
     }
+    for &(sink, key) in &[(logger_artiq::Sink::Buffer, "buffer_log_level"),
+                          (logger_artiq::Sink::Syslog, "syslog_log_level")] {
+        let level = match config::read_str(key, |r| r.map(|s| s.parse())) {
+            Ok(Ok(level)) => {
+                info!("{} log level set to {} by `{}` config key", sink.name(), level, key);
+                level
+            }
+            // The buffer takes all the records by default, as before sinks
+            // had their own levels.
+            _ if sink == logger_artiq::Sink::Buffer => log::LevelFilter::Trace,
+            _ => log::LevelFilter::Info
+        };
+        logger_artiq::BufferLogger::with(|logger| logger.set_sink_log_level(sink, level));
+    }
+    config::read_str("log_format", |r| {
+        match r.map(logger_artiq::LogFormat::from_name) {
+            Ok(Some(format)) => {
+                info!("log format set to {} by `log_format` config key", format.name());
+                logger_artiq::BufferLogger::with(|logger| logger.set_log_format(format));
+            }
+            Ok(None) => warn!("invalid `log_format` config key, using text"),
+            Err(_) => ()
+        }
+    });
+    match config::read_str("log_repeat_window_ms", |r| r.map(|s| s.parse::<u64>())) {
+        Ok(Ok(window_ms)) => {
+            info!("log repeat window set to {} ms by `log_repeat_window_ms` config key",
+                  window_ms);
+            logger_artiq::BufferLogger::with(|logger| logger.set_repeat_window_ms(window_ms));
+        }
+        Ok(Err(_)) => warn!("invalid `log_repeat_window_ms` config key"),
+        Err(_) => ()
+    }
+    config::read_str("log_module_levels", |r| {
+        for entry in r.unwrap_or("").split(',').filter(|entry| !entry.is_empty()) {
+            let mut parts = entry.splitn(2, '=');
+            let module = parts.next().unwrap_or("");
+            match parts.next().map(|level| level.parse::<log::LevelFilter>()) {
+                Some(Ok(level)) => {
+                    match logger_artiq::BufferLogger::with(|logger|
+                            logger.set_module_log_level(module, level)) {
+                        Ok(()) => info!("log level of {} set to {} by `log_module_levels` config key",
+                                        module, level),
+                        Err(()) => warn!("cannot set log level of {}", module)
+                    }
+                }
+                _ => warn!("invalid entry {:?} in `log_module_levels` config key", entry)
+            }
+        }
+    });
+}
+
+// Returns the time at which firmware or gateware booted on trial after an
+// update should check in, if it was.
+#[cfg(has_spiflash)]
+fn setup_firmware_trial() -> Option<u64> {
+    use board_misoc::firmware;
+
+    let trial_ms = config::read_str("fw_trial_s", |r| r.ok().and_then(|s| s.parse().ok()))
+                          .unwrap_or(10) * 1000;
+    let firmware_on_trial = match firmware::trial() {
+        Some(bank) if firmware::trial_started() => {
+            warn!("running firmware bank {} on trial, checking in after {} ms", bank, trial_ms);
+            true
+        }
+        _ => {
+            info!("running firmware bank {}", firmware::active());
+            false
+        }
+    };
+    if setup_gateware_trial(trial_ms) || firmware_on_trial {
+        Some(clock::get_ms() + trial_ms)
+    } else {
+        None
+    }
+}
+
+#[cfg(all(has_spiflash, has_icap))]
+fn setup_gateware_trial(trial_ms: u64) -> bool {
+    use board_misoc::gateware;
+
+    if gateware::trial_started() {
+        warn!("running the gateware update on trial, checking in after {} ms", trial_ms);
+        true
+    } else {
+        info!("running the {}", gateware::running());
+        false
+    }
+}
+
+#[cfg(all(has_spiflash, not(has_icap)))]
+fn setup_gateware_trial(_trial_ms: u64) -> bool {
+    false
+}
+
+#[cfg(has_spiflash)]
+fn firmware_check_in() {
+    match board_misoc::firmware::check_in() {
+        Ok(Some(bank)) => info!("firmware bank {} checked in and is now active", bank),
+        Ok(None) => (),
+        Err(err) => error!("firmware check-in failed: {}", err)
+    }
+    #[cfg(has_icap)]
+    match board_misoc::gateware::check_in() {
+        Ok(true) => info!("gateware update checked in and is now booted at power-up"),
+        Ok(false) => (),
+        Err(err) => error!("gateware check-in failed: {}", err)
+    }
+}
+
+#[cfg(has_spiflash)]
+fn firmware_on_trial() -> bool {
+    board_misoc::firmware::trial().is_some() && board_misoc::firmware::trial_started()
+}
+
+#[cfg(not(has_spiflash))]
+fn firmware_on_trial() -> bool {
+    false
 }
 
 fn startup() {
@@ -96,30 +276,88 @@ fn startup() {
     info!("gateware ident {}", ident::read(&mut [0; 64]));
 
     setup_log_levels();
-    #[cfg(has_i2c)]
-    board_misoc::i2c::init().expect("I2C initialization failed");
-    #[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
-    let (mut io_expander0, mut io_expander1);
-    #[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
+    #[cfg(has_spiflash)]
+    let mut check_in_at = setup_firmware_trial();
+    if let Some((start, end)) = board_misoc::bad_ram::get() {
+        extern {
+            static _fheap: u8;
+            static _eheap: u8;
+        }
+        let (fheap, eheap) = unsafe {
+            (&_fheap as *const u8 as usize, &_eheap as *const u8 as usize)
+        };
+        if start < fheap {
+            error!("memory test failed in the bootloader at 0x{:08x}-0x{:08x}, \
+                    which holds the firmware and cannot be excluded", start, end - 1);
+        } else if start < eheap {
+            warn!("memory test failed in the bootloader, 0x{:08x}-0x{:08x} excluded from the heap",
+                  start, min(end, eheap) - 1);
+        } else {
+            warn!("memory test failed in the bootloader at 0x{:08x}-0x{:08x}, \
+                   outside the heap", start, end - 1);
+        }
+    }
+    #[cfg(has_spiflash)]
     {
-        io_expander0 = board_misoc::io_expander::IoExpander::new(0);
-        io_expander1 = board_misoc::io_expander::IoExpander::new(1);
+        match board_misoc::panic_log::read() {
+            Some(log) if log.contains(hw_watchdog::EXPIRED) => {
+                warn!("the previous run was restarted by the hardware watchdog; \
+                       see `artiq_coremgmt log panic`");
+                alerts::push(alerts::Severity::Critical, alerts::Source::Watchdog,
+                    alloc::String::from("the previous run was restarted by the hardware watchdog"));
+            }
+            Some(_) =>
+                warn!("a panic or kernel CPU exception was saved to flash; \
+                       see `artiq_coremgmt log panic`"),
+            None => ()
+        }
+    }
+    if let Some(stats) = board_misoc::boot_stats::record_boot() {
+        info!("boot #{}, {} h of uptime over all boots", stats.boot_count, stats.uptime_s / 3600);
+    }
+    let compatible = compat::check();
+    if compatible {
+        #[cfg(has_i2c)]
+        board_misoc::i2c::init().expect("I2C initialization failed");
+        #[cfg(has_i2c)]
+        board_misoc::sfp::disable_unused(|index, result| match result {
+            Ok(()) => info!("SFP{} unused, TX disabled", index),
+            Err(e) => debug!("cannot disable the transmitter of SFP{}: {}", index, e)
+        });
+    }
+    #[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
+    let mut io_expanders = if compatible {
+        let mut io_expander0 = board_misoc::io_expander::IoExpander::new(0);
+        let mut io_expander1 = board_misoc::io_expander::IoExpander::new(1);
         io_expander0.init().expect("I2C I/O expander #0 initialization failed");
         io_expander1.init().expect("I2C I/O expander #1 initialization failed");
 
-        // Actively drive TX_DISABLE to false on SFP0..3
-        io_expander0.set_oe(0, 1 << 1).unwrap();
-        io_expander0.set_oe(1, 1 << 1).unwrap();
-        io_expander1.set_oe(0, 1 << 1).unwrap();
-        io_expander1.set_oe(1, 1 << 1).unwrap();
-        io_expander0.set(0, 1, false);
-        io_expander0.set(1, 1, false);
-        io_expander1.set(0, 1, false);
-        io_expander1.set(1, 1, false);
+        // Actively drive TX_DISABLE on SFP0..3, to true on the unused ports
+        board_misoc::sfp::init_expanders(&mut io_expander0, &mut io_expander1).unwrap();
         io_expander0.service().unwrap();
         io_expander1.service().unwrap();
+        Some((io_expander0, io_expander1))
+    } else {
+        None
+    };
+    if compatible {
+        #[cfg(soc_platform = "kasli")]
+        board_misoc::io_expander::init_configured(|name, result| match result {
+            Ok(_) => info!("initialized I/O expander {}", name),
+            Err(e) => error!("cannot initialize I/O expander {}: {}", name, e)
+        });
+        status_led::init();
+        board_identity::init();
+        eem_presence::init();
+        #[cfg(has_i2c)]
+        rtc::init();
+        rtio_clocking::init();
+        if post::enabled_at_boot() {
+            post::run(None);
+        }
     }
-    rtio_clocking::init();
+
+    audit::init();
 
     let mut net_device = unsafe { ethmac::EthernetDevice::new() };
     net_device.reset_phy_if_any();
@@ -146,6 +384,7 @@ fn startup() {
 
     let neighbor_cache =
         smoltcp::iface::NeighborCache::new(alloc::btree_map::BTreeMap::new());
+    let routes = smoltcp::iface::Routes::new(alloc::btree_map::BTreeMap::new());
     let net_addresses = net_settings::get_adresses();
     info!("network addresses: {}", net_addresses);
     let mut interface = match net_addresses.ipv6_addr {
@@ -153,12 +392,14 @@ fn startup() {
             let ip_addrs = [
                 IpCidr::new(net_addresses.ipv4_addr, 0),
                 IpCidr::new(net_addresses.ipv6_ll_addr, 0),
-                IpCidr::new(addr, 0)
+                addr
             ];
             smoltcp::iface::EthernetInterfaceBuilder::new(net_device)
                        .ethernet_addr(net_addresses.hardware_addr)
                        .ip_addrs(ip_addrs)
                        .neighbor_cache(neighbor_cache)
+                       .routes(routes)
+                       .ipv4_multicast_groups(alloc::btree_map::BTreeMap::new())
                        .finalize()
         }
         None => {
@@ -170,9 +411,16 @@ fn startup() {
                        .ethernet_addr(net_addresses.hardware_addr)
                        .ip_addrs(ip_addrs)
                        .neighbor_cache(neighbor_cache)
+                       .routes(routes)
+                       .ipv4_multicast_groups(alloc::btree_map::BTreeMap::new())
                        .finalize()
         }
     };
+    if let Some(gateway) = net_addresses.ipv6_gateway {
+        if let Err(err) = interface.routes_mut().add_default_ipv6_route(gateway) {
+            warn!("cannot set IPv6 gateway {}: {}", gateway, err);
+        }
+    }
 
     #[cfg(has_drtio)]
     let drtio_routing_table = urc::Urc::new(RefCell::new(
@@ -185,62 +433,204 @@ fn startup() {
     #[cfg(has_drtio_routing)]
     drtio_routing::interconnect_disable_all();
     let aux_mutex = sched::Mutex::new();
+    let cache = urc::Urc::new(RefCell::new(cache::Cache::new()));
 
     let mut scheduler = sched::Scheduler::new();
     let io = scheduler.io();
 
-    rtio_mgt::startup(&io, &aux_mutex, &drtio_routing_table, &up_destinations);
+    if compatible {
+        rtio_mgt::startup(&io, &aux_mutex, &drtio_routing_table, &up_destinations);
+    }
 
-    io.spawn(4096, mgmt::thread);
     {
         let aux_mutex = aux_mutex.clone();
         let drtio_routing_table = drtio_routing_table.clone();
-        let up_destinations = up_destinations.clone();
-        io.spawn(16384, move |io| { session::thread(io, &aux_mutex, &drtio_routing_table, &up_destinations) });
+        let cache = cache.clone();
+        io.spawn_named("mgmt", sched::Priority::Low, 4096,
+                       move |io| { mgmt::thread(io, &aux_mutex, &drtio_routing_table, &cache) });
     }
-    #[cfg(any(has_rtio_moninj, has_drtio))]
-    {
-        let aux_mutex = aux_mutex.clone();
-        let drtio_routing_table = drtio_routing_table.clone();
-        io.spawn(4096, move |io| { moninj::thread(io, &aux_mutex, &drtio_routing_table) });
+    if compatible {
+        {
+            let aux_mutex = aux_mutex.clone();
+            let drtio_routing_table = drtio_routing_table.clone();
+            let up_destinations = up_destinations.clone();
+            let cache = cache.clone();
+            io.spawn_named("session", sched::Priority::High, 16384,
+                           move |io| { session::thread(io, &aux_mutex, &drtio_routing_table,
+                                                       &up_destinations, &cache) });
+        }
+        #[cfg(any(has_rtio_moninj, has_drtio))]
+        {
+            let aux_mutex = aux_mutex.clone();
+            let drtio_routing_table = drtio_routing_table.clone();
+            io.spawn_named("moninj", sched::Priority::Normal, 4096,
+                           move |io| { moninj::thread(io, &aux_mutex, &drtio_routing_table) });
+        }
+        #[cfg(has_rtio_analyzer)]
+        {
+            analyzer::startup();
+            io.spawn_named("analyzer", sched::Priority::Low, 4096, analyzer::thread);
+        }
+
+        #[cfg(has_grabber)]
+        io.spawn_named("grabber", sched::Priority::Normal, 4096, grabber_thread);
+
+        #[cfg(si5324_as_synthesizer)]
+        io.spawn_named("si5324", sched::Priority::Low, 4096,
+                       rtio_clocking::si5324_monitor_thread);
+
+        #[cfg(has_xadc)]
+        sysmon::init();
+        #[cfg(has_i2c)]
+        io.spawn_named("power", sched::Priority::Low, 4096, power::thread);
+        #[cfg(has_i2c)]
+        io.spawn_named("rtc", sched::Priority::Low, 4096, rtc::thread);
+        #[cfg(has_i2c)]
+        sfp_monitor::init();
+        #[cfg(all(has_fan, has_xadc))]
+        fan::init();
+        #[cfg(has_rtio_moninj)]
+        interlock::init();
+        io.spawn_named("status_led", sched::Priority::Low, 4096, status_led::thread);
+        io.spawn_named("sensor_history", sched::Priority::Low, 4096, sensor_history::thread);
     }
-    #[cfg(has_rtio_analyzer)]
-    io.spawn(4096, analyzer::thread);
+    #[cfg(has_spiflash)]
+    io.spawn_named("boot_stats", sched::Priority::Low, 4096, boot_stats_thread);
+    #[cfg(has_spiflash)]
+    io.spawn_named("flash_scrub", sched::Priority::Low, 4096, flash_scrub::thread);
+    #[cfg(has_uart)]
+    io.spawn_named("uart_shell", sched::Priority::Low, 4096, uart_shell::thread);
+
+    let mut dhcp = if net_addresses.ipv4_dhcp {
+        let sockets = &mut *scheduler.sockets().borrow_mut();
+        Some(dhcp::Dhcp::new(sockets, net_addresses.ipv4_addr))
+    } else {
+        None
+    };
+
+    let mut mdns = if config::read_str("mdns", |r| r != Ok("0")) {
+        let timestamp = smoltcp::time::Instant::from_millis(clock::get_ms() as i64);
+        if let Err(err) = interface.join_multicast_group(mdns::GROUP, timestamp) {
+            warn!("cannot join the mDNS multicast group: {}", err);
+        }
+        let sockets = &mut *scheduler.sockets().borrow_mut();
+        Some(mdns::Mdns::new(sockets, &net_addresses))
+    } else {
+        None
+    };
 
-    #[cfg(has_grabber)]
-    io.spawn(4096, grabber_thread);
+    let mut sntp = {
+        let sockets = &mut *scheduler.sockets().borrow_mut();
+        sntp::Sntp::new(sockets)
+    };
+
+    let mut heartbeat = {
+        let sockets = &mut *scheduler.sockets().borrow_mut();
+        heartbeat::Heartbeat::new(sockets, &net_addresses)
+    };
+
+    let mut syslog = {
+        let sockets = &mut *scheduler.sockets().borrow_mut();
+        syslog::Syslog::new(sockets, &net_addresses)
+    };
+
+    let mut stream = {
+        let sockets = &mut *scheduler.sockets().borrow_mut();
+        stream::Stream::new(sockets)
+    };
+
+    hw_watchdog::init();
+    low_latency::init();
+    timers::start(&io);
 
     let mut net_stats = ethmac::EthernetStatistics::new();
     loop {
         scheduler.run();
 
+        #[cfg(has_spiflash)]
+        {
+            if check_in_at.map_or(false, |at| clock::get_ms() > at) {
+                firmware_check_in();
+                check_in_at = None;
+            }
+        }
+
+        let low_latency = low_latency::active();
+        let background = low_latency::background_due();
         {
             let sockets = &mut *scheduler.sockets().borrow_mut();
+            let mut polls = 0;
             loop {
+                // The frames left are processed at the next iteration, after
+                // the session thread had its turn.
+                if low_latency && polls == low_latency::POLL_BUDGET {
+                    break
+                }
+                polls += 1;
                 let timestamp = smoltcp::time::Instant::from_millis(clock::get_ms() as i64);
                 match interface.poll(sockets, timestamp) {
                     Ok(true) => (),
                     Ok(false) => break,
                     Err(smoltcp::Error::Unrecognized) => (),
-                    Err(err) => debug!("network error: {}", err)
+                    Err(err) => {
+                        unsafe { NETWORK_ERRORS = NETWORK_ERRORS.wrapping_add(1) }
+                        debug!("network error: {}", err)
+                    }
                 }
             }
         }
+        if background {
+            let sockets = &mut *scheduler.sockets().borrow_mut();
+            if let Some(ref mut dhcp) = dhcp {
+                dhcp.poll(&mut interface, sockets);
+            }
+            if let Some(ref mut mdns) = mdns {
+                mdns.poll(sockets, interface.ip_addrs());
+            }
+            if let Some(ref mut sntp) = sntp {
+                sntp.poll(sockets);
+            }
+            if let Some(ref mut heartbeat) = heartbeat {
+                heartbeat.poll(sockets);
+            }
+            if let Some(ref mut syslog) = syslog {
+                syslog.poll(sockets);
+            }
+            stream.poll(sockets);
+        }
+
+        // Summaries of the repeated log messages.
+        if background {
+            log::logger().flush();
+        }
 
         if let Some(_net_stats_diff) = net_stats.update() {
             debug!("ethernet mac:{}", ethmac::EthernetStatistics::new());
         }
 
+        // SFP0 carries Ethernet and is left on, so that the device can still
+        // be reached.
+        board_misoc::sfp::set_tx_inhibited(sysmon::throttled());
         #[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
         {
-            io_expander0.service().expect("I2C I/O expander #0 service failed");
-            io_expander1.service().expect("I2C I/O expander #1 service failed");
+            if let Some((ref mut io_expander0, ref mut io_expander1)) = io_expanders {
+                board_misoc::sfp::drive_expanders(io_expander0, io_expander1);
+                // Not in the middle of a kernel I2C transaction; the outputs
+                // are written at a later iteration.
+                if !nrt_bus::kernel_holds(nrt_bus::MAIN_BUS) {
+                    let written0 = io_expander0.service().expect("I2C I/O expander #0 service failed");
+                    let written1 = io_expander1.service().expect("I2C I/O expander #1 service failed");
+                    if written0 || written1 {
+                        nrt_bus::restore_switches(nrt_bus::MAIN_BUS)
+                    }
+                }
+            }
         }
     }
 }
 
 #[global_allocator]
-static mut ALLOC: alloc_list::ListAlloc = alloc_list::EMPTY;
+static mut ALLOC: alloc_tlsf::TlsfAlloc = alloc_tlsf::EMPTY;
 static mut LOG_BUFFER: [u8; 1<<17] = [0; 1<<17];
 
 #[no_mangle]
@@ -250,7 +640,19 @@ pub extern fn main() -> i32 {
             static mut _fheap: u8;
             static mut _eheap: u8;
         }
-        ALLOC.add_range(&mut _fheap, &mut _eheap);
+        let (fheap, eheap) = (&mut _fheap as *mut u8, &mut _eheap as *mut u8);
+        match board_misoc::bad_ram::get() {
+            // Leave out the memory that failed the test in the bootloader.
+            Some((start, end)) if start < eheap as usize && end > fheap as usize => {
+                if start > fheap as usize {
+                    ALLOC.add_range(fheap, start as *mut u8);
+                }
+                if end < eheap as usize {
+                    ALLOC.add_range(end as *mut u8, eheap);
+                }
+            }
+            _ => ALLOC.add_range(fheap, eheap)
+        }
 
         logger_artiq::BufferLogger::new(&mut LOG_BUFFER[..]).register(startup);
 
@@ -258,36 +660,29 @@ pub extern fn main() -> i32 {
     }
 }
 
+// State at the last unexpected exception, for the panic log.
+static mut EXCEPTION_DUMP: Option<exception::Dump> = None;
+
 #[no_mangle]
-pub extern fn exception(vect: u32, _regs: *const u32, pc: u32, ea: u32) {
-    let vect = irq::Exception::try_from(vect).expect("unknown exception");
-    match vect {
+pub extern fn exception(vect: u32, regs: *const u32, pc: u32, ea: u32, sr: u32) {
+    let vector = irq::Exception::try_from(vect).expect("unknown exception");
+    match vector {
         irq::Exception::Interrupt =>
             while irq::pending_mask() != 0 {
                 match () {
                     #[cfg(has_timer1)]
                     () if irq::is_pending(csr::TIMER1_INTERRUPT) =>
                         profiler::sample(pc as usize),
+                    () if hw_watchdog::expired() =>
+                        panic!("{} at PC 0x{:x}", hw_watchdog::EXPIRED, pc),
                     _ => panic!("spurious irq {}", irq::pending_mask().trailing_zeros())
                 }
             },
         _ => {
-            fn hexdump(addr: u32) {
-                let addr = (addr - addr % 4) as *const u32;
-                let mut ptr  = addr;
-                println!("@ {:08p}", ptr);
-                for _ in 0..4 {
-                    print!("+{:04x}: ", ptr as usize - addr as usize);
-                    print!("{:08x} ",   unsafe { *ptr }); ptr = ptr.wrapping_offset(1);
-                    print!("{:08x} ",   unsafe { *ptr }); ptr = ptr.wrapping_offset(1);
-                    print!("{:08x} ",   unsafe { *ptr }); ptr = ptr.wrapping_offset(1);
-                    print!("{:08x}\n",  unsafe { *ptr }); ptr = ptr.wrapping_offset(1);
-                }
-            }
-
-            hexdump(pc);
-            hexdump(ea);
-            panic!("exception {:?} at PC 0x{:x}, EA 0x{:x}", vect, pc, ea)
+            let dump = unsafe { exception::Dump::capture(vect, regs, pc, ea, sr) };
+            print!("{}", dump);
+            unsafe { EXCEPTION_DUMP = Some(dump) }
+            panic!("exception {:?} at PC 0x{:x}, EA 0x{:x}", vector, pc, ea)
         }
     }
 }
@@ -301,13 +696,141 @@ pub extern fn abort() {
 #[no_mangle] // https://github.com/rust-lang/rust/issues/{38281,51647}
 #[lang = "oom"] // https://github.com/rust-lang/rust/issues/51540
 pub fn oom(layout: core::alloc::Layout) -> ! {
-    panic!("heap view: {}\ncannot allocate layout: {:?}", unsafe { &ALLOC }, layout)
+    panic!("heap view: {}\nheap {}\ncannot allocate layout: {:?}",
+           unsafe { &ALLOC }, heap_stats(), layout)
 }
 
+static mut NETWORK_ERRORS: u32 = 0;
+
+/// Number of errors reported by the network stack since boot.
+pub fn network_errors() -> u32 {
+    unsafe { NETWORK_ERRORS }
+}
+
+pub fn heap_stats() -> alloc_tlsf::Stats {
+    unsafe { ALLOC.stats() }
+}
+
+/// Size of each of the receive and transmit buffers of the TCP sockets of
+/// `service`, from the `<service>_buffer_size` config entry. Sizes for which
+/// the buffers of the listening socket and of one connection would take more
+/// than half of the free heap are refused.
+pub fn socket_buffer_size(service: &str, default: usize) -> usize {
+    const MIN_SIZE: usize = 1024;
+    // Receive and transmit buffers of the listening socket and of a connection.
+    const BUFFERS: usize = 4;
+
+    let key = format!("{}_buffer_size", service);
+    let size = match config::read_str(&key, |r| r.map(|s| s.parse::<usize>())) {
+        Ok(Ok(size)) => size,
+        Ok(Err(_)) => {
+            warn!("invalid value for {}, using {} bytes", key, default);
+            return default
+        }
+        Err(_) => return default
+    };
+    let stats = heap_stats();
+    let available = stats.free / 2;
+    if size < MIN_SIZE {
+        warn!("{} of {} bytes is below the minimum of {} bytes, using {} bytes",
+              key, size, MIN_SIZE, default);
+        default
+    } else if BUFFERS * size > available || size > stats.largest_free {
+        warn!("{} of {} bytes does not fit in the {} bytes of free memory that can be \
+               used for buffers, using {} bytes", key, size, available, default);
+        default
+    } else {
+        info!("{} socket buffers: 2x{} bytes", service, size);
+        size
+    }
+}
+
+// Keeps the panic message, backtrace and the end of the log in flash, to be
+// retrieved with `artiq_coremgmt log panic` after the restart.
+#[cfg(has_spiflash)]
+fn save_panic_log(info: &core::panic::PanicInfo) {
+    use core::fmt::Write;
+    use board_misoc::panic_log;
+
+    const LOG_TAIL: usize = 4096;
+
+    let mut record = panic_log::Writer::new();
+    if let Some(location) = info.location() {
+        let _ = write!(record, "panic at {}:{}:{}", location.file(), location.line(), location.column());
+    } else {
+        let _ = write!(record, "panic at unknown location");
+    }
+    if let Some(message) = info.message() {
+        let _ = writeln!(record, ": {}", message);
+    } else {
+        let _ = writeln!(record, "");
+    }
+    if let Some(unix_us) = clock::get_unix_us() {
+        let _ = writeln!(record, "at {} s since the Unix epoch", unix_us / 1_000_000);
+    }
+    if session::startup_kernel_running() {
+        let _ = writeln!(record, "{}", session::STARTUP_KERNEL_PANIC);
+    }
+
+    if let Some(ref dump) = unsafe { EXCEPTION_DUMP } {
+        let _ = write!(record, "{}", dump);
+    }
+
+    let _ = writeln!(record, "backtrace for software version {}:", csr::CONFIG_IDENTIFIER_STR);
+    let _ = unwind_backtrace::backtrace(|ip| {
+        let _ = writeln!(record, "{:#08x}", ip - 2 * 4);
+    });
+
+    // The log buffer is unavailable if the panic happened while it was in use.
+    let _ = logger_artiq::BufferLogger::with(|logger| logger.buffer().map(|mut buffer| {
+        let log = buffer.extract();
+        let mut start = log.len().saturating_sub(LOG_TAIL.min(record.remaining()));
+        while !log.is_char_boundary(start) { start += 1 }
+        let _ = write!(record, "log:\n{}", &log[start..]);
+    }));
+
+    record.save();
+    println!("panic log saved to flash");
+}
+
+/// Keeps the report of an unexpected exception on the kernel CPU in flash,
+/// in place of the panic log, since it does not restart the core device.
+#[cfg(has_spiflash)]
+pub fn save_kernel_exception(dump: &str) {
+    use core::fmt::Write;
+    use board_misoc::panic_log;
+
+    let mut record = panic_log::Writer::new();
+    let _ = write!(record, "kernel CPU {}", dump);
+    record.save();
+    warn!("kernel CPU exception saved to flash; see `artiq_coremgmt log panic`");
+}
+
+#[cfg(not(has_spiflash))]
+pub fn save_kernel_exception(_dump: &str) {}
+
+/// Keeps the report of the failure of the startup kernel in flash, in place
+/// of the panic log.
+#[cfg(has_spiflash)]
+pub fn save_startup_kernel_failure(report: &str) {
+    use core::fmt::Write;
+    use board_misoc::panic_log;
+
+    let mut record = panic_log::Writer::new();
+    let _ = write!(record, "startup kernel failed: {}", report);
+    record.save();
+    warn!("startup kernel failure saved to flash; see `artiq_coremgmt log panic`");
+}
+
+#[cfg(not(has_spiflash))]
+pub fn save_startup_kernel_failure(_report: &str) {}
+
 #[no_mangle] // https://github.com/rust-lang/rust/issues/{38281,51647}
 #[panic_implementation]
 pub fn panic_impl(info: &core::panic::PanicInfo) -> ! {
     irq::set_ie(false);
+    // Neither saving the panic log nor halting may be cut short by a restart.
+    hw_watchdog::disable();
 
     #[cfg(has_error_led)]
     unsafe {
@@ -332,7 +855,25 @@ pub fn panic_impl(info: &core::panic::PanicInfo) -> ! {
         println!("{:#08x}", ip - 2 * 4);
     });
 
-    if config::read_str("panic_reset", |r| r == Ok("1")) {
+    #[cfg(has_spiflash)]
+    save_panic_log(info);
+
+    // A gateware update on trial is rolled back by reconfiguring the FPGA
+    // with the golden gateware, as at power-up.
+    #[cfg(all(has_spiflash, has_icap))]
+    {
+        if board_misoc::gateware::trial_started() {
+            println!("reconfiguring the FPGA with the golden gateware...");
+            unsafe {
+                kernel::stop();
+                board_misoc::gateware::reconfigure_golden();
+            }
+        }
+    }
+
+    // A panic before firmware on trial checks in makes the bootloader roll
+    // it back, but only if the device restarts.
+    if config::read_str("panic_reset", |r| r == Ok("1")) || firmware_on_trial() {
         println!("restarting...");
         unsafe {
             kernel::stop();
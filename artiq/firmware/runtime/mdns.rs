@@ -0,0 +1,297 @@
+use alloc::{Vec, String};
+use byteorder::{ByteOrder, NetworkEndian};
+use smoltcp::socket::{SocketSet, SocketHandle, UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+use smoltcp::wire::{IpAddress, IpCidr, IpEndpoint, Ipv4Address};
+use board_misoc::{csr, clock, ident, config, net_settings::NetAddresses};
+
+// Multicast DNS responder (RFC 6762) advertising the core device as an
+// _artiq._tcp service (RFC 6763). Only IPv4 queries are answered, but the
+// IPv6 addresses of the device are advertised as well.
+
+pub const GROUP: Ipv4Address = Ipv4Address([224, 0, 0, 251]);
+const PORT: u16 = 5353;
+const SESSION_PORT: u16 = 1381;
+
+const TYPE_A:    u16 = 1;
+const TYPE_PTR:  u16 = 12;
+const TYPE_TXT:  u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV:  u16 = 33;
+const TYPE_ANY:  u16 = 255;
+
+const CLASS_IN:    u16 = 1;
+const CACHE_FLUSH: u16 = 0x8000;
+
+const HOST_TTL:    u32 = 120;
+const SERVICE_TTL: u32 = 4500;
+
+// Sets of records that can be put in a response.
+const ADDRESSES:   u8 = 1 << 0;
+const SERVICE:     u8 = 1 << 1;
+const INSTANCE:    u8 = 1 << 2;
+const ENUMERATION: u8 = 1 << 3;
+
+const SERVICE_NAME:     [&'static str; 3] = ["_artiq", "_tcp", "local"];
+const ENUMERATION_NAME: [&'static str; 4] = ["_services", "_dns-sd", "_udp", "local"];
+
+// Number of unsolicited announcements, and the interval between them.
+const ANNOUNCEMENTS: u8 = 2;
+const ANNOUNCE_INTERVAL_MS: u64 = 1000;
+
+pub struct Mdns {
+    handle:         SocketHandle,
+    hostname:       String,
+    txt:            Vec<String>,
+    ip_addrs:       Vec<IpCidr>,
+    announcements:  u8,
+    announce_at:    u64
+}
+
+fn dotted(labels: &[&str]) -> String {
+    let mut name = String::new();
+    for label in labels {
+        if !name.is_empty() { name.push('.') }
+        name.push_str(&label.to_ascii_lowercase());
+    }
+    name
+}
+
+// Reads a possibly compressed name, lowercased with dots between labels.
+// Returns it with the offset following the name.
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    for _ in 0..64 {
+        let length = *packet.get(offset)? as usize;
+        if length & 0xc0 == 0xc0 {
+            let pointer = (length & 0x3f) << 8 | *packet.get(offset + 1)? as usize;
+            if end.is_none() { end = Some(offset + 2) }
+            offset = pointer;
+        } else if length == 0 {
+            return Some((name, end.unwrap_or(offset + 1)))
+        } else {
+            let label = packet.get(offset + 1..offset + 1 + length)?;
+            if !name.is_empty() { name.push('.') }
+            name.extend(label.iter().map(|&b| (b as char).to_ascii_lowercase()));
+            offset += 1 + length;
+        }
+    }
+    None
+}
+
+fn write_name(buffer: &mut Vec<u8>, labels: &[&str]) {
+    for label in labels {
+        buffer.push(label.len() as u8);
+        buffer.extend_from_slice(label.as_bytes());
+    }
+    buffer.push(0);
+}
+
+fn write_record(buffer: &mut Vec<u8>, name: &[&str], ty: u16, class: u16, ttl: u32,
+                rdata: &[u8]) {
+    let mut fields = [0; 10];
+    NetworkEndian::write_u16(&mut fields[0..], ty);
+    NetworkEndian::write_u16(&mut fields[2..], class);
+    NetworkEndian::write_u32(&mut fields[4..], ttl);
+    NetworkEndian::write_u16(&mut fields[8..], rdata.len() as u16);
+    write_name(buffer, name);
+    buffer.extend_from_slice(&fields);
+    buffer.extend_from_slice(rdata);
+}
+
+/// Host name from the `hostname` config entry, or made of the platform
+/// name and the end of the MAC address.
+pub fn hostname(addresses: &NetAddresses) -> String {
+    let mac = addresses.hardware_addr.0;
+    config::read_str("hostname", |r| r.map(String::from))
+        .unwrap_or_else(|_| format!("{}-{:02x}{:02x}{:02x}",
+                                    csr::CONFIG_SOC_PLATFORM, mac[3], mac[4], mac[5]))
+}
+
+impl Mdns {
+    pub fn new(sockets: &mut SocketSet<'static, 'static, 'static>,
+               addresses: &NetAddresses) -> Mdns {
+        let mac = addresses.hardware_addr.0;
+        let hostname = hostname(addresses);
+
+        let mut gateware = [0; 64];
+        let gateware = ident::read(&mut gateware);
+        let variant = gateware.splitn(2, ';').nth(1).unwrap_or("");
+        let txt = vec![
+            format!("variant={}", variant),
+            format!("serial={:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+                    mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]),
+            format!("version={}", csr::CONFIG_IDENTIFIER_STR)
+        ];
+
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 2048]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 2048]);
+        let handle = sockets.add(UdpSocket::new(rx_buffer, tx_buffer));
+        sockets.get::<UdpSocket>(handle).bind(PORT).expect("mdns: cannot bind");
+
+        info!("advertising {}.local by mDNS", hostname);
+        Mdns {
+            handle:        handle,
+            hostname:      hostname,
+            txt:           txt,
+            ip_addrs:      Vec::new(),
+            announcements: 0,
+            announce_at:   0
+        }
+    }
+
+    fn host_name(&self) -> [&str; 2] {
+        [&self.hostname, "local"]
+    }
+
+    fn instance_name(&self) -> [&str; 4] {
+        [&self.hostname, SERVICE_NAME[0], SERVICE_NAME[1], SERVICE_NAME[2]]
+    }
+
+    // Returns the record sets answering the questions of `query`, and the
+    // offset following the questions.
+    fn answer(&self, query: &[u8]) -> Option<(u8, usize)> {
+        if query.len() < 12 || NetworkEndian::read_u16(&query[2..]) & 0xf800 != 0 {
+            // Truncated, not a query, or not a standard query.
+            return None
+        }
+        let host_name = dotted(&self.host_name());
+        let instance_name = dotted(&self.instance_name());
+
+        let mut records = 0;
+        let mut offset = 12;
+        for _ in 0..NetworkEndian::read_u16(&query[4..]) {
+            let (name, next) = read_name(query, offset)?;
+            let ty = NetworkEndian::read_u16(query.get(next..next + 2)?);
+            offset = next + 4;
+            if offset > query.len() { return None }
+
+            let any = ty == TYPE_ANY;
+            if name == host_name && (any || ty == TYPE_A || ty == TYPE_AAAA) {
+                records |= ADDRESSES
+            } else if name == dotted(&SERVICE_NAME) && (any || ty == TYPE_PTR) {
+                records |= SERVICE
+            } else if name == instance_name && (any || ty == TYPE_SRV || ty == TYPE_TXT) {
+                records |= INSTANCE
+            } else if name == dotted(&ENUMERATION_NAME) && (any || ty == TYPE_PTR) {
+                records |= ENUMERATION
+            }
+        }
+        Some((records, offset))
+    }
+
+    fn write_records(&self, buffer: &mut Vec<u8>, records: u8) -> u16 {
+        let mut count = 0;
+        if records & ENUMERATION != 0 {
+            let mut rdata = Vec::new();
+            write_name(&mut rdata, &SERVICE_NAME);
+            write_record(buffer, &ENUMERATION_NAME, TYPE_PTR, CLASS_IN, SERVICE_TTL, &rdata);
+            count += 1;
+        }
+        if records & SERVICE != 0 {
+            let mut rdata = Vec::new();
+            write_name(&mut rdata, &self.instance_name());
+            write_record(buffer, &SERVICE_NAME, TYPE_PTR, CLASS_IN, SERVICE_TTL, &rdata);
+            count += 1;
+        }
+        if records & INSTANCE != 0 {
+            let mut rdata = vec![0; 6];
+            NetworkEndian::write_u16(&mut rdata[4..], SESSION_PORT);
+            write_name(&mut rdata, &self.host_name());
+            write_record(buffer, &self.instance_name(), TYPE_SRV, CLASS_IN | CACHE_FLUSH,
+                         HOST_TTL, &rdata);
+
+            let mut rdata = Vec::new();
+            for entry in self.txt.iter() {
+                rdata.push(entry.len() as u8);
+                rdata.extend_from_slice(entry.as_bytes());
+            }
+            write_record(buffer, &self.instance_name(), TYPE_TXT, CLASS_IN | CACHE_FLUSH,
+                         SERVICE_TTL, &rdata);
+            count += 2;
+        }
+        if records & ADDRESSES != 0 {
+            for cidr in self.ip_addrs.iter() {
+                if cidr.address().is_unspecified() { continue }
+                match cidr.address() {
+                    IpAddress::Ipv4(addr) =>
+                        write_record(buffer, &self.host_name(), TYPE_A, CLASS_IN | CACHE_FLUSH,
+                                     HOST_TTL, addr.as_bytes()),
+                    IpAddress::Ipv6(addr) =>
+                        write_record(buffer, &self.host_name(), TYPE_AAAA, CLASS_IN | CACHE_FLUSH,
+                                     HOST_TTL, addr.as_bytes()),
+                    _ => continue
+                }
+                count += 1;
+            }
+        }
+        count
+    }
+
+    // Builds a response; `query` is the header and questions of a legacy
+    // unicast query, which are repeated in the response.
+    fn response(&self, query: Option<&[u8]>, answers: u8, additionals: u8) -> Vec<u8> {
+        let mut buffer = vec![0; 12];
+        if let Some(query) = query {
+            buffer[0..2].copy_from_slice(&query[0..2]);
+            buffer[4..6].copy_from_slice(&query[4..6]);
+            buffer.extend_from_slice(&query[12..]);
+        }
+        // Response, authoritative answer.
+        NetworkEndian::write_u16(&mut buffer[2..], 0x8400);
+        let answer_count = self.write_records(&mut buffer, answers);
+        let additional_count = self.write_records(&mut buffer, additionals & !answers);
+        NetworkEndian::write_u16(&mut buffer[6..], answer_count);
+        NetworkEndian::write_u16(&mut buffer[10..], additional_count);
+        buffer
+    }
+
+    pub fn poll(&mut self, sockets: &mut SocketSet<'static, 'static, 'static>,
+                ip_addrs: &[IpCidr]) {
+        let mut socket = sockets.get::<UdpSocket>(self.handle);
+        let now = clock::get_ms();
+
+        if self.ip_addrs[..] != ip_addrs[..] {
+            // The DHCP lease may have changed the IPv4 address.
+            self.ip_addrs = ip_addrs.to_vec();
+            self.announcements = 0;
+            self.announce_at = now;
+        }
+        if self.announcements < ANNOUNCEMENTS && now >= self.announce_at && socket.can_send() {
+            let response = self.response(None, SERVICE | INSTANCE | ADDRESSES, 0);
+            if let Err(err) = socket.send_slice(&response, IpEndpoint::new(GROUP.into(), PORT)) {
+                debug!("cannot send mDNS announcement: {}", err);
+            }
+            self.announcements += 1;
+            self.announce_at = now + ANNOUNCE_INTERVAL_MS;
+        }
+
+        let mut query = [0; 1500];
+        while socket.can_recv() && socket.can_send() {
+            let (length, endpoint) = match socket.recv_slice(&mut query) {
+                Ok(received) => received,
+                Err(_) => break
+            };
+            let (answers, questions_end) = match self.answer(&query[..length]) {
+                Some((0, _)) | None => continue,
+                Some(answer) => answer
+            };
+            let mut additionals = 0;
+            if answers & SERVICE != 0 { additionals |= INSTANCE | ADDRESSES }
+            if answers & INSTANCE != 0 { additionals |= ADDRESSES }
+
+            // Queries not sent from the mDNS port come from simple resolvers,
+            // which expect a conventional unicast DNS response.
+            let result = if endpoint.port == PORT {
+                let response = self.response(None, answers, additionals);
+                socket.send_slice(&response, IpEndpoint::new(GROUP.into(), PORT))
+            } else {
+                let response = self.response(Some(&query[..questions_end]), answers, additionals);
+                socket.send_slice(&response, endpoint)
+            };
+            if let Err(err) = result {
+                debug!("cannot send mDNS response: {}", err);
+            }
+        }
+    }
+}
@@ -1,11 +1,51 @@
+use alloc::{Vec, String};
+use core::cell::RefCell;
 use log::{self, LevelFilter};
 
 use io::{Write, ProtoWrite, Error as IoError};
-use board_misoc::{config, boot};
-use logger_artiq::BufferLogger;
+use board_misoc::{csr, clock, ident, config, boot, sfp, ethmac, boot_stats, flash_storage};
+use smoltcp::socket::Socket;
+use logger_artiq::{BufferLogger, LogFormat, Sink};
 use mgmt_proto::*;
-use sched::{Io, TcpListener, TcpStream, Error as SchedError};
+use health_record::RecordSet;
+use sched::{self, Io, Mutex, TcpListener, TcpStream, Error as SchedError};
+use urc::Urc;
+use board_artiq::drtio_routing;
+#[cfg(has_grabber)]
+use board_artiq::grabber;
 use profiler;
+use hw_watchdog;
+use board_identity;
+use eem_presence;
+use eem_test;
+use compat;
+use features;
+use sysmon;
+use session;
+use sensor_history;
+#[cfg(has_spiflash)]
+use flash_scrub;
+use fault_injection;
+#[cfg(has_drtio_eye_scan)]
+use eye_scan;
+use power;
+use rtio_clocking;
+use rtio_mgt;
+use cache::{self, Cache};
+use auth;
+use alerts;
+use audit::{self, Service, Event};
+use post;
+use dds_sync;
+use nrt_bus;
+use bus_passthrough;
+use rtc;
+use syslog;
+use shutdown;
+use kernel_stats;
+#[cfg(any(has_rtio_moninj, has_drtio))]
+use moninj;
+use board_misoc::eeprom_24xx::Eeprom;
 
 impl From<SchedError> for Error<SchedError> {
     fn from(value: SchedError) -> Error<SchedError> {
@@ -13,18 +53,916 @@ impl From<SchedError> for Error<SchedError> {
     }
 }
 
-fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
+#[cfg(has_drtio)]
+mod remote_clock {
+    use drtioaux;
+    use rtio_mgt::drtio;
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+    use mgmt_proto::Reply;
+
+    pub fn transact(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                    destination: u8, request: &drtioaux::Packet) -> Result<drtioaux::Packet, &'static str> {
+        let hop = routing_table.0[destination as usize][0];
+        if hop == 0 {
+            return Err("destination is not a satellite")
+        }
+        let linkno = hop - 1;
+        drtio::aux_transact(io, aux_mutex, linkno, request)
+    }
+
+    pub fn si549_trim(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                      destination: u8, ppb: i32) -> Result<bool, &'static str> {
+        let request = drtioaux::Packet::Si549TrimRequest { destination: destination, ppb: ppb };
+        match transact(io, aux_mutex, routing_table, destination, &request)? {
+            drtioaux::Packet::Si549TrimReply { succeeded } => Ok(succeeded),
+            packet => {
+                error!("received unexpected aux packet: {:?}", packet);
+                Err("unexpected aux packet")
+            }
+        }
+    }
+
+    pub fn wrpll_stats(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                       destination: u8) -> Result<Reply<'static>, &'static str> {
+        let request = drtioaux::Packet::WrpllStatsRequest { destination: destination };
+        match transact(io, aux_mutex, routing_table, destination, &request)? {
+            drtioaux::Packet::WrpllStatsReply { available: false, .. } =>
+                Err("destination has no WRPLL"),
+            drtioaux::Packet::WrpllStatsReply {
+                    available: true, locked, lock_time_ms, unlock_count,
+                    samples, helper_rms_mtags, main_rms_mtags } =>
+                Ok(Reply::WrpllStats {
+                    locked: locked,
+                    lock_time_ms: lock_time_ms,
+                    unlock_count: unlock_count,
+                    samples: samples,
+                    helper_rms_mtags: helper_rms_mtags,
+                    main_rms_mtags: main_rms_mtags
+                }),
+            packet => {
+                error!("received unexpected aux packet: {:?}", packet);
+                Err("unexpected aux packet")
+            }
+        }
+    }
+}
+
+#[cfg(not(has_drtio))]
+mod remote_clock {
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+    use mgmt_proto::Reply;
+
+    pub fn si549_trim(_io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
+                      _destination: u8, _ppb: i32) -> Result<bool, &'static str> {
+        Err("DRTIO is not supported")
+    }
+
+    pub fn wrpll_stats(_io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
+                       _destination: u8) -> Result<Reply<'static>, &'static str> {
+        Err("DRTIO is not supported")
+    }
+}
+
+#[cfg(has_drtio)]
+mod remote_ident {
+    use alloc::{Vec, String};
+    use core::cmp::min;
+    use drtioaux;
+    use rtio_mgt::drtio;
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+    use mgmt_proto::SatelliteIdent;
+    use super::remote_clock::transact;
+
+    fn read_item(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                 destination: u8, item: u8) -> Result<Vec<u8>, &'static str> {
+        let mut value = Vec::new();
+        loop {
+            let request = drtioaux::Packet::IdentRequest {
+                destination: destination,
+                item: item,
+                offset: value.len() as u8
+            };
+            match transact(io, aux_mutex, routing_table, destination, &request)? {
+                drtioaux::Packet::IdentReply { length, data } => {
+                    let length = length as usize;
+                    if length < value.len() {
+                        return Err("inconsistent identification length")
+                    }
+                    let remaining = length - value.len();
+                    value.extend_from_slice(&data[..min(remaining, data.len())]);
+                    if value.len() == length {
+                        return Ok(value)
+                    }
+                }
+                packet => {
+                    error!("received unexpected aux packet: {:?}", packet);
+                    return Err("unexpected aux packet")
+                }
+            }
+        }
+    }
+
+    fn read_ident(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                  destination: u8) -> Result<(String, String, Option<[u8; 6]>), &'static str> {
+        let firmware = read_item(io, aux_mutex, routing_table, destination, drtioaux::IDENT_FIRMWARE)?;
+        let gateware = read_item(io, aux_mutex, routing_table, destination, drtioaux::IDENT_GATEWARE)?;
+        let serial = read_item(io, aux_mutex, routing_table, destination, drtioaux::IDENT_SERIAL)?;
+        let serial = if serial.len() == 6 {
+            let mut eui48 = [0; 6];
+            eui48.copy_from_slice(&serial);
+            Some(eui48)
+        } else {
+            None
+        };
+        Ok((String::from_utf8_lossy(&firmware).into_owned(),
+            String::from_utf8_lossy(&gateware).into_owned(),
+            serial))
+    }
+
+    /// Identifies the satellites of the routing table, in the order of
+    /// their destination numbers.
+    pub fn satellites(io: &Io, aux_mutex: &Mutex,
+                      routing_table: &drtio_routing::RoutingTable) -> Vec<SatelliteIdent> {
+        let mut satellites = Vec::new();
+        for destination in 0..drtio_routing::DEST_COUNT {
+            let hops = &routing_table.0[destination];
+            if hops[0] == 0 || hops[0] as usize > drtio::link_count() {
+                continue
+            }
+            let destination = destination as u8;
+            let ident = if drtio::link_rx_up(hops[0] - 1) {
+                match read_ident(io, aux_mutex, routing_table, destination) {
+                    Ok(ident) => Some(ident),
+                    Err(e) => {
+                        warn!("[DEST#{}] cannot read identification ({})", destination, e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            satellites.push(SatelliteIdent {
+                destination: destination,
+                route: hops.iter().take_while(|&&hop| hop != drtio_routing::INVALID_HOP)
+                           .cloned().collect(),
+                ident: ident
+            });
+        }
+        satellites
+    }
+
+    /// RTIO topology of the gateware of the satellite of `destination`,
+    /// empty if its gateware does not describe it.
+    pub fn topology(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                    destination: u8) -> Result<String, &'static str> {
+        let topology = read_item(io, aux_mutex, routing_table, destination, drtioaux::IDENT_TOPOLOGY)?;
+        Ok(String::from_utf8_lossy(&topology).into_owned())
+    }
+}
+
+#[cfg(not(has_drtio))]
+mod remote_ident {
+    use alloc::{Vec, String};
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+    use mgmt_proto::SatelliteIdent;
+
+    pub fn satellites(_io: &Io, _aux_mutex: &Mutex,
+                      _routing_table: &drtio_routing::RoutingTable) -> Vec<SatelliteIdent> {
+        Vec::new()
+    }
+
+    pub fn topology(_io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
+                    _destination: u8) -> Result<String, &'static str> {
+        Err("DRTIO is not supported")
+    }
+}
+
+#[cfg(has_rtio_topology)]
+fn local_topology() -> &'static str {
+    csr::CONFIG_RTIO_TOPOLOGY
+}
+
+#[cfg(not(has_rtio_topology))]
+fn local_topology() -> &'static str {
+    ""
+}
+
+#[cfg(has_drtio)]
+mod remote_tsc {
+    use alloc::Vec;
+    use drtioaux;
+    use rtio_mgt::drtio;
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+    use mgmt_proto::SatelliteTscStats;
+    use super::remote_clock::transact;
+
+    /// Reads the TSC statistics of the satellites of the routing table, in
+    /// the order of their destination numbers.
+    pub fn stats(io: &Io, aux_mutex: &Mutex,
+                 routing_table: &drtio_routing::RoutingTable) -> Vec<SatelliteTscStats> {
+        let mut satellites = Vec::new();
+        for destination in 0..drtio_routing::DEST_COUNT {
+            let hop = routing_table.0[destination][0];
+            if hop == 0 || hop as usize > drtio::link_count() {
+                continue
+            }
+            let destination = destination as u8;
+            let stats = if drtio::link_rx_up(hop - 1) {
+                let request = drtioaux::Packet::TscStatsRequest { destination: destination };
+                match transact(io, aux_mutex, routing_table, destination, &request) {
+                    Ok(drtioaux::Packet::TscStatsReply { syncs, offset, max_offset, interval_ms, phase_fs }) =>
+                        Some((syncs, offset, max_offset, interval_ms, phase_fs)),
+                    Ok(packet) => {
+                        error!("[DEST#{}] received unexpected aux packet: {:?}", destination, packet);
+                        None
+                    }
+                    Err(e) => {
+                        warn!("[DEST#{}] cannot read TSC statistics ({})", destination, e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            satellites.push(SatelliteTscStats { destination: destination, stats: stats });
+        }
+        satellites
+    }
+}
+
+#[cfg(not(has_drtio))]
+mod remote_tsc {
+    use alloc::Vec;
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+    use mgmt_proto::SatelliteTscStats;
+
+    pub fn stats(_io: &Io, _aux_mutex: &Mutex,
+                 _routing_table: &drtio_routing::RoutingTable) -> Vec<SatelliteTscStats> {
+        Vec::new()
+    }
+}
+
+#[cfg(has_drtio)]
+mod remote_rtio_counters {
+    use drtioaux;
+    use rtio_mgt::{self, drtio};
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+    use mgmt_proto::SatelliteRtioCounters;
+    use super::remote_clock::transact;
+
+    /// Reads the RTIO error counters of the satellites of the routing table
+    /// that are up into the counters of their destinations, and clears them
+    /// on the satellites if `clear` is set.
+    pub fn collect(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                   clear: bool) {
+        for destination in 0..drtio_routing::DEST_COUNT {
+            let hop = routing_table.0[destination][0];
+            if hop == 0 || hop as usize > drtio::link_count() || !drtio::link_rx_up(hop - 1) {
+                continue
+            }
+            let destination = destination as u8;
+            let request = drtioaux::Packet::RtioCountersRequest {
+                destination: destination,
+                clear: clear
+            };
+            match transact(io, aux_mutex, routing_table, destination, &request) {
+                Ok(drtioaux::Packet::RtioCountersReply {
+                        underflows, overflows, buffer_space_timeouts, protocol_errors, tsc_resyncs }) =>
+                    rtio_mgt::set_satellite_counters(destination, SatelliteRtioCounters {
+                        underflows:            underflows,
+                        overflows:             overflows,
+                        buffer_space_timeouts: buffer_space_timeouts,
+                        protocol_errors:       protocol_errors,
+                        tsc_resyncs:           tsc_resyncs
+                    }),
+                Ok(packet) =>
+                    error!("[DEST#{}] received unexpected aux packet: {:?}", destination, packet),
+                Err(e) =>
+                    warn!("[DEST#{}] cannot read RTIO error counters ({})", destination, e)
+            }
+        }
+    }
+}
+
+#[cfg(not(has_drtio))]
+mod remote_rtio_counters {
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+
+    pub fn collect(_io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
+                   _clear: bool) {}
+}
+
+mod clock_registers {
+    use alloc::Vec;
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+    use mgmt_proto::{ClockChipRegisters, ClockRegister};
+    use proto_artiq::drtioaux_proto::{CLOCK_CHIP_SI5324, CLOCK_CHIP_SI549_MAIN,
+                                      CLOCK_CHIP_SI549_HELPER};
+
+    const CHIPS: [u8; 3] = [CLOCK_CHIP_SI5324, CLOCK_CHIP_SI549_MAIN, CLOCK_CHIP_SI549_HELPER];
+
+    #[cfg(has_si5324)]
+    fn read_local(io: &Io, chip: u8) -> Result<Option<Vec<ClockRegister>>, &'static str> {
+        use board_artiq::si5324;
+        use nrt_bus;
+
+        if chip != CLOCK_CHIP_SI5324 {
+            return Ok(None)
+        }
+        let mut registers = Vec::new();
+        nrt_bus::with_i2c(io, nrt_bus::MAIN_BUS, || {
+            si5324::snapshot(|address, value, profile| registers.push(ClockRegister {
+                address: address,
+                value:   value,
+                profile: profile
+            }))
+        })??;
+        Ok(Some(registers))
+    }
+
+    #[cfg(not(has_si5324))]
+    fn read_local(_io: &Io, _chip: u8) -> Result<Option<Vec<ClockRegister>>, &'static str> {
+        Ok(None)
+    }
+
+    #[cfg(has_drtio)]
+    fn read_remote(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                   destination: u8, chip: u8) -> Result<Option<Vec<ClockRegister>>, &'static str> {
+        use drtioaux;
+        use super::remote_clock::transact;
+
+        let mut registers = Vec::new();
+        loop {
+            let request = drtioaux::Packet::ClockRegistersRequest {
+                destination: destination,
+                chip: chip,
+                offset: registers.len() as u8
+            };
+            match transact(io, aux_mutex, routing_table, destination, &request)? {
+                drtioaux::Packet::ClockRegistersReply { available: false, .. } =>
+                    return Ok(None),
+                drtioaux::Packet::ClockRegistersReply {
+                        available: true, count, configuration, registers: addresses, values } => {
+                    for i in 0..count as usize {
+                        registers.push(ClockRegister {
+                            address: addresses[i],
+                            value:   values[i],
+                            profile: configuration & 1 << i != 0
+                        })
+                    }
+                    if (count as usize) < drtioaux::CLOCK_REGISTERS_SIZE {
+                        return Ok(Some(registers))
+                    }
+                }
+                packet => {
+                    error!("received unexpected aux packet: {:?}", packet);
+                    return Err("unexpected aux packet")
+                }
+            }
+        }
+    }
+
+    #[cfg(not(has_drtio))]
+    fn read_remote(_io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
+                   _destination: u8, _chip: u8) -> Result<Option<Vec<ClockRegister>>, &'static str> {
+        Err("DRTIO is not supported")
+    }
+
+    /// Reads the registers of the clocking chips of `destination`.
+    pub fn read(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                destination: u8) -> Result<Vec<ClockChipRegisters>, &'static str> {
+        let mut chips = Vec::new();
+        for &chip in CHIPS.iter() {
+            let registers = if destination == 0 {
+                read_local(io, chip)?
+            } else {
+                read_remote(io, aux_mutex, routing_table, destination, chip)?
+            };
+            if let Some(registers) = registers {
+                chips.push(ClockChipRegisters { chip: chip, registers: registers })
+            }
+        }
+        Ok(chips)
+    }
+}
+
+#[cfg(has_drtio)]
+mod remote_sfp {
+    use alloc::Vec;
+    use drtioaux;
+    use rtio_mgt::drtio;
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+    use mgmt_proto::{SatelliteSfp, SfpDiagnostics};
+    use super::remote_clock::transact;
+
+    /// Reads the SFP ports of the satellites of the routing table that are
+    /// up, in the order of their destination numbers.
+    pub fn ports(io: &Io, aux_mutex: &Mutex,
+                 routing_table: &drtio_routing::RoutingTable) -> Vec<SatelliteSfp> {
+        let mut ports = Vec::new();
+        for destination in 0..drtio_routing::DEST_COUNT {
+            let hop = routing_table.0[destination][0];
+            if hop == 0 || hop as usize > drtio::link_count() || !drtio::link_rx_up(hop - 1) {
+                continue
+            }
+            let destination = destination as u8;
+            let mut port = 0;
+            loop {
+                let request = drtioaux::Packet::SfpStatusRequest {
+                    destination: destination,
+                    port: port
+                };
+                match transact(io, aux_mutex, routing_table, destination, &request) {
+                    Ok(drtioaux::Packet::SfpStatusReply {
+                            count, present: _, diagnostics, temperature, voltage,
+                            tx_bias, tx_power, rx_power, alarms }) => {
+                        if port >= count {
+                            break
+                        }
+                        let diagnostics = if diagnostics {
+                            Some(SfpDiagnostics {
+                                temperature: temperature,
+                                voltage: voltage,
+                                tx_bias: tx_bias,
+                                tx_power: tx_power,
+                                rx_power: rx_power,
+                                alarms: alarms
+                            })
+                        } else {
+                            None
+                        };
+                        ports.push(SatelliteSfp {
+                            destination: destination,
+                            port: port,
+                            diagnostics: diagnostics
+                        });
+                        port += 1;
+                    }
+                    Ok(packet) => {
+                        error!("[DEST#{}] received unexpected aux packet: {:?}", destination, packet);
+                        break
+                    }
+                    Err(e) => {
+                        warn!("[DEST#{}] cannot read SFP status ({})", destination, e);
+                        break
+                    }
+                }
+            }
+        }
+        ports
+    }
+}
+
+#[cfg(not(has_drtio))]
+mod remote_sfp {
+    use alloc::Vec;
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+    use mgmt_proto::SatelliteSfp;
+
+    pub fn ports(_io: &Io, _aux_mutex: &Mutex,
+                 _routing_table: &drtio_routing::RoutingTable) -> Vec<SatelliteSfp> {
+        Vec::new()
+    }
+}
+
+#[cfg(has_drtio)]
+mod remote_firmware {
+    use byteorder::{ByteOrder, NetworkEndian};
+    use drtioaux;
+    use rtio_mgt::drtio;
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+
+    // Erasing a flash sector or verifying the image takes much longer than
+    // the usual aux transaction.
+    const TIMEOUT_MS: u32 = 10_000;
+    const ATTEMPTS: usize = 3;
+    // Image bytes in each segmented message, one flash sector.
+    const BLOCK_SIZE: usize = 4096;
+
+    fn transact(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                destination: u8, request: &drtioaux::Packet) -> Result<bool, &'static str> {
+        let hop = routing_table.0[destination as usize][0];
+        if hop == 0 {
+            return Err("destination is not a satellite")
+        }
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match drtio::aux_transact_timeout(io, aux_mutex, hop - 1, request, TIMEOUT_MS) {
+                Ok(drtioaux::Packet::FirmwareUpdateReply { succeeded }) => return Ok(succeeded),
+                Ok(packet) => {
+                    error!("received unexpected aux packet: {:?}", packet);
+                    return Err("unexpected aux packet")
+                }
+                // The satellite ignores a chunk it has already written, so
+                // requests can be sent again if the reply is lost.
+                Err(e) if attempt < ATTEMPTS =>
+                    warn!("[DEST#{}] firmware update request failed ({}), retrying", destination, e),
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    /// Writes a firmware image into the inactive flash bank of a satellite
+    /// and reboots the satellite to try it.
+    pub fn flash(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                 destination: u8, image: &[u8]) -> Result<(), &'static str> {
+        info!("[DEST#{}] writing {} byte firmware image", destination, image.len());
+        let request = drtioaux::Packet::FirmwareUpdateStartRequest {
+            destination: destination,
+            length: image.len() as u32
+        };
+        if !transact(io, aux_mutex, routing_table, destination, &request)? {
+            return Err("satellite refused the firmware update")
+        }
+
+        if !write_blocks(io, aux_mutex, routing_table, destination, image)? {
+            warn!("[DEST#{}] satellite does not support segmented messages, \
+                   sending the image in chunks", destination);
+            write_chunks(io, aux_mutex, routing_table, destination, image)?;
+        }
+
+        let request = drtioaux::Packet::FirmwareUpdateFinishRequest { destination: destination };
+        if !transact(io, aux_mutex, routing_table, destination, &request)? {
+            return Err("satellite failed to verify firmware")
+        }
+
+        warn!("[DEST#{}] firmware written, restarting the satellite to try it", destination);
+        let request = drtioaux::Packet::RebootRequest { destination: destination };
+        if let Err(e) = transact(io, aux_mutex, routing_table, destination, &request) {
+            warn!("[DEST#{}] no answer to reboot request ({})", destination, e);
+        }
+        Ok(())
+    }
+
+    // Sends the image in blocks of `BLOCK_SIZE` bytes, each a segmented
+    // message. Returns false, having written nothing, if the satellite does
+    // not answer segmented messages, as firmware that predates them.
+    fn write_blocks(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                    destination: u8, image: &[u8]) -> Result<bool, &'static str> {
+        let hop = routing_table.0[destination as usize][0];
+        if hop == 0 {
+            return Err("destination is not a satellite")
+        }
+        for (i, block) in image.chunks(BLOCK_SIZE).enumerate() {
+            let mut message = [0; 4 + BLOCK_SIZE];
+            NetworkEndian::write_u32(&mut message[..4], (i * BLOCK_SIZE) as u32);
+            message[4..4 + block.len()].copy_from_slice(block);
+            match drtio::aux_send_segmented(io, aux_mutex, hop - 1, destination,
+                                            drtioaux::SEGMENTED_FIRMWARE_BLOCK,
+                                            &message[..4 + block.len()], TIMEOUT_MS) {
+                Ok(()) => (),
+                Err("timeout") if i == 0 => return Ok(false),
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(true)
+    }
+
+    fn write_chunks(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+                    destination: u8, image: &[u8]) -> Result<(), &'static str> {
+        for (i, chunk) in image.chunks(drtioaux::FIRMWARE_CHUNK_SIZE).enumerate() {
+            let mut data = [[0; 32]; 16];
+            for (row, bytes) in data.iter_mut().zip(chunk.chunks(32)) {
+                row[..bytes.len()].copy_from_slice(bytes);
+            }
+            let request = drtioaux::Packet::FirmwareUpdateDataRequest {
+                destination: destination,
+                offset: (i * drtioaux::FIRMWARE_CHUNK_SIZE) as u32,
+                length: chunk.len() as u16,
+                data: data
+            };
+            if !transact(io, aux_mutex, routing_table, destination, &request)? {
+                return Err("satellite failed to write firmware")
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(has_drtio))]
+mod remote_firmware {
+    use sched::{Io, Mutex};
+    use board_artiq::drtio_routing;
+
+    pub fn flash(_io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
+                 _destination: u8, _image: &[u8]) -> Result<(), &'static str> {
+        Err("DRTIO is not supported")
+    }
+}
+
+// Stores the per-module log levels in the format read at startup,
+// e.g. `board_misoc::sfp=OFF,runtime::session=DEBUG`.
+fn save_module_log_levels() {
+    let mut levels = String::new();
+    BufferLogger::with(|logger| logger.module_log_levels(|module, level| {
+        if !levels.is_empty() { levels.push(',') }
+        levels.push_str(&format!("{}={}", module, level));
+    }));
+    let result = if levels.is_empty() {
+        hw_watchdog::suspend(|| config::remove("log_module_levels"))
+    } else {
+        hw_watchdog::suspend(|| config::write("log_module_levels", levels.as_bytes()))
+    };
+    if let Err(err) = result {
+        warn!("failed to save module log levels: {}", err);
+    }
+}
+
+// Config key holding the level of a log sink, read at startup.
+fn sink_log_level_key(sink: Sink) -> &'static str {
+    match sink {
+        Sink::Uart => "uart_log_level",
+        Sink::Buffer => "buffer_log_level",
+        Sink::Syslog => "syslog_log_level"
+    }
+}
+
+// Encoding of the log levels in the protocol.
+fn log_level_index(level: LevelFilter) -> u8 {
+    match level {
+        LevelFilter::Off => 0,
+        LevelFilter::Error => 1,
+        LevelFilter::Warn => 2,
+        LevelFilter::Info => 3,
+        LevelFilter::Debug => 4,
+        LevelFilter::Trace => 5
+    }
+}
+
+// Sends the log as it is written, clearing the buffer. While the log is
+// pulled, records that do not fit in the buffer are dropped rather than
+// overwriting those not sent yet; with `counted`, each chunk is preceded by
+// the number of records lost since the previous one.
+fn pull_log(io: &Io, stream: &mut TcpStream, counted: bool) -> Result<(), Error<SchedError>> {
+    BufferLogger::with(|logger| {
+        logger.set_forwarding(true);
+        let result = (|| loop {
+            let log_level = log::max_level();
+
+            let mut buffer = io.until_ok(|| logger.buffer())?;
+            let lost = if counted { logger.take_unreported_lost_records() } else { 0 };
+            if buffer.is_empty() && lost == 0 { continue }
+
+            if counted {
+                stream.write_u32(lost)?;
+            }
+            stream.write_string(buffer.extract())?;
+
+            if log_level == LevelFilter::Trace {
+                // Hold exclusive access over the logger until we get positive
+                // acknowledgement; otherwise we get an infinite loop of network
+                // trace messages being transmitted and causing more network
+                // trace messages to be emitted.
+                //
+                // Any messages unrelated to this management socket that arrive
+                // while it is flushed are lost, and counted as such.
+                stream.flush()?;
+            }
+
+            // Clear the log *after* flushing the network buffers, or we're just
+            // going to resend all the trace messages on the next iteration.
+            buffer.clear();
+        })();
+        logger.set_forwarding(false);
+        result
+    })
+}
+
+// Description of the requests that change the state of the core device, for
+// the audit log.
+fn audited(request: &Request) -> Option<String> {
+    Some(match request {
+        &Request::ConfigWrite { ref key, .. } => format!("config write {}", key),
+        &Request::ConfigRemove { ref key } => format!("config remove {}", key),
+        &Request::ConfigErase => String::from("config erase"),
+        &Request::ConfigRestore(_) => String::from("config restore"),
+        &Request::Hotswap(_) => String::from("hotswap"),
+        &Request::FlashFirmware(_) => String::from("flash firmware"),
+        &Request::FlashSatelliteFirmware { destination, .. } =>
+            format!("flash firmware of destination {}", destination),
+        &Request::FlashGateware { reconfigure, .. } =>
+            String::from(if reconfigure { "flash gateware and reconfigure" } else { "flash gateware" }),
+        &Request::Reboot => String::from("reboot"),
+        &Request::RebootToBootloader => String::from("reboot to bootloader"),
+        &Request::PrepareForPowerOff => String::from("prepare for power-off"),
+        &Request::EepromWrite { busno, address, .. } =>
+            format!("EEPROM write at 0x{:02x} on bus {}", address, busno),
+        &Request::FlashStorageErase => String::from("flash storage erase"),
+        &Request::InjectFault(ref fault) => format!("inject fault {:?}", fault),
+        &Request::KernelImageWrite { ref name, .. } => format!("kernel image write {}", name),
+        &Request::KernelImageRemove { ref name } => format!("kernel image remove {}", name),
+        &Request::KernelImageRun { ref name } => format!("kernel image run {}", name),
+        &Request::TerminateKernel { ref reason } => format!("terminate kernel: {}", reason),
+        &Request::I2cTransfer { busno, address, .. } =>
+            format!("raw I2C transaction with 0x{:02x} on bus {}", address, busno),
+        &Request::SpiTransfer { busno, .. } => format!("raw SPI transaction on bus {}", busno),
+        &Request::SetRtc { unix_s } => format!("set RTC to {}", unix_s),
+        &Request::ReloadRoutingTable => String::from("reload routing table"),
+        &Request::Si549Trim { destination, ppb } =>
+            format!("trim Si549 of destination {} by {} ppb", destination, ppb),
+        &Request::SetDrtioLatency { destination, .. } =>
+            format!("set latency calibration of destination {}", destination),
+        &Request::RunEemSelfTest => String::from("EEM self test"),
+        _ => return None
+    })
+}
+
+// Requests whose reply can be large, which can also be read in chunks with
+// `Request::ReadChunk`. Calls `f` with the reply, or returns false for the
+// other requests.
+fn with_large_reply<F>(io: &Io, request: &Request, f: F) -> Result<bool, IoError<SchedError>>
+    where F: FnOnce(Reply) -> Result<(), IoError<SchedError>>
+{
+    match *request {
+        Request::GetLog => {
+            BufferLogger::with(|logger| {
+                let mut buffer = io.until_ok(|| logger.buffer())?;
+                f(Reply::LogContent(buffer.extract()))
+            })?
+        }
+        #[cfg(has_spiflash)]
+        Request::GetPanicLog => {
+            match board_misoc::panic_log::read() {
+                Some(log) => f(Reply::LogContent(log)),
+                None => f(Reply::Unavailable)
+            }?
+        }
+        #[cfg(not(has_spiflash))]
+        Request::GetPanicLog => f(Reply::Unavailable)?,
+        Request::ConfigBackup => {
+            match config::backup() {
+                Ok(image) => f(Reply::ConfigData(&image)),
+                Err(err) => {
+                    error!("failed to back up configuration: {}", err);
+                    f(Reply::Error)
+                }
+            }?
+        }
+        Request::FlashStorageRead => {
+            let mut records = Vec::new();
+            flash_storage::for_each(|key, value| {
+                records.push((String::from(key), value.to_vec()))
+            });
+            let (used, size) = flash_storage::usage();
+            f(Reply::FlashStorageRecords {
+                used:    used as u32,
+                size:    size as u32,
+                records: &records
+            })?
+        }
+        Request::GetSensorHistory => {
+            let (interval_ms, samples) = sensor_history::samples();
+            f(Reply::SensorHistory {
+                interval_ms: interval_ms,
+                sfp_ports:   sensor_history::sfp_ports(),
+                samples:     &samples
+            })?
+        }
+        Request::GetAuditLog => {
+            let entries: Vec<AuditEntry> = audit::entries().iter().map(|entry| AuditEntry {
+                sequence:     entry.sequence,
+                timestamp_ms: entry.timestamp_ms,
+                unix_us:      entry.unix_us,
+                service:      entry.service as u8,
+                event:        entry.event as u8,
+                peer:         &entry.peer,
+                duration_ms:  entry.duration_ms,
+                detail:       &entry.detail
+            }).collect();
+            f(Reply::AuditLog { dropped: audit::dropped(), entries: &entries })?
+        }
+        Request::GetKernelRunStats => {
+            let runs: Vec<KernelRunStats> = kernel_stats::records().iter()
+                .map(|record| record.as_proto())
+                .collect();
+            f(Reply::KernelRunStats { dropped: kernel_stats::dropped(), runs: &runs })?
+        }
+        _ => return Ok(false)
+    }
+    Ok(true)
+}
+
+// Snapshot of the reply being read in chunks, see `Request::ReadChunk`. It
+// is kept across connections, so that a read interrupted by a lost
+// connection resumes with the same reply, e.g. a log without the lines
+// about the new connection. It is dropped once it has been read, when
+// another read starts, or after CHUNKED_REPLY_TIMEOUT_MS without a chunk
+// being read, e.g. if the host gave up.
+struct Snapshot {
+    reply:      Urc<ChunkedReply>,
+    expires_ms: u64
+}
+
+const CHUNKED_REPLY_TIMEOUT_MS: u64 = 30_000;
+
+static mut CHUNKED_REPLY: Option<Snapshot> = None;
+
+fn chunked_reply_expired() -> bool {
+    unsafe { CHUNKED_REPLY.as_ref() }
+        .map_or(false, |snapshot| clock::get_ms() >= snapshot.expires_ms)
+}
+
+fn drop_chunked_reply(crc: u32) {
+    unsafe {
+        if CHUNKED_REPLY.as_ref().map_or(false, |snapshot| snapshot.reply.crc == crc) {
+            CHUNKED_REPLY = None
+        }
+    }
+}
+
+// Writes a chunk of the snapshot of the reply with the CRC32 `crc`, or of
+// the reply to `request`, snapshotting it if it is small enough. Returns
+// false for the requests that are not answered in chunks.
+fn write_chunk(io: &Io, request: &Request, offset: u32, max_length: u32, crc: u32,
+               stream: &mut TcpStream) -> Result<bool, IoError<SchedError>> {
+    let snapshot = match unsafe { CHUNKED_REPLY.as_mut() } {
+        Some(ref mut snapshot) if snapshot.reply.crc == crc => {
+            snapshot.expires_ms = clock::get_ms() + CHUNKED_REPLY_TIMEOUT_MS;
+            Some(snapshot.reply.clone())
+        }
+        _ => None
+    };
+    let reply = match snapshot {
+        Some(reply) => reply,
+        None => {
+            // Freed before the new reply is serialized.
+            unsafe { CHUNKED_REPLY = None }
+
+            let mut snapshot = None;
+            if !with_large_reply(io, request, |reply| {
+                match ChunkedReply::new(&reply) {
+                    Some(chunked) => {
+                        snapshot = Some(Urc::new(chunked));
+                        Ok(())
+                    }
+                    None => reply.write_chunk_to(offset, max_length, stream)
+                }
+            })? {
+                return Ok(false)
+            }
+            match snapshot {
+                Some(reply) => {
+                    unsafe {
+                        CHUNKED_REPLY = Some(Snapshot {
+                            reply:      reply.clone(),
+                            expires_ms: clock::get_ms() + CHUNKED_REPLY_TIMEOUT_MS
+                        })
+                    }
+                    reply
+                }
+                // Too large, and written directly.
+                None => return Ok(true)
+            }
+        }
+    };
+    reply.write_chunk_to(offset, max_length, stream)?;
+    if reply.is_last(offset, max_length) {
+        drop_chunked_reply(reply.crc)
+    }
+    Ok(true)
+}
+
+fn worker(io: &Io, aux_mutex: &Mutex, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+          cache: &Urc<RefCell<Cache>>, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
     read_magic(stream)?;
     Write::write_all(stream, "E".as_bytes())?;
+    if !auth::handshake(stream)? {
+        audit::record(Service::Mgmt, Event::Disconnected, stream.remote_endpoint(),
+                      "authentication failed");
+        return Ok(())
+    }
     info!("new connection from {}", stream.remote_endpoint());
+    let connection = audit::Connection::open(Service::Mgmt, stream.remote_endpoint(), "");
 
     loop {
-        match Request::read_from(stream)? {
-            Request::GetLog => {
-                BufferLogger::with(|logger| {
-                    let mut buffer = io.until_ok(|| logger.buffer())?;
-                    Reply::LogContent(buffer.extract()).write_to(stream)
-                })?;
+        let request = Request::read_from(stream)?;
+        if let Some(detail) = audited(&request) {
+            connection.command(detail);
+        }
+        match request {
+            Request::GetLog | Request::GetPanicLog | Request::ConfigBackup |
+            Request::FlashStorageRead | Request::GetSensorHistory | Request::GetAuditLog |
+            Request::GetKernelRunStats => {
+                with_large_reply(io, &request, |reply| reply.write_to(stream))?;
+            }
+            Request::ReadChunk { offset, max_length, crc, ref request } => {
+                if !write_chunk(io, request, offset, max_length, crc, stream)? {
+                    warn!("the reply to this request cannot be read in chunks");
+                    Reply::Error.write_to(stream)?;
+                }
             }
             Request::ClearLog => {
                 BufferLogger::with(|logger| -> Result<(), Error<SchedError>> {
@@ -34,38 +972,61 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
 
                 Reply::Success.write_to(stream)?;
             }
-            Request::PullLog => {
-                BufferLogger::with(|logger| -> Result<(), Error<SchedError>> {
-                    loop {
-                        // Do this *before* acquiring the buffer, since that sets the log level
-                        // to OFF.
-                        let log_level = log::max_level();
-
-                        let mut buffer = io.until_ok(|| logger.buffer())?;
-                        if buffer.is_empty() { continue }
-
-                        stream.write_string(buffer.extract())?;
-
-                        if log_level == LevelFilter::Trace {
-                            // Hold exclusive access over the logger until we get positive
-                            // acknowledgement; otherwise we get an infinite loop of network
-                            // trace messages being transmitted and causing more network
-                            // trace messages to be emitted.
-                            //
-                            // Any messages unrelated to this management socket that arrive
-                            // while it is flushed are lost, but such is life.
-                            stream.flush()?;
-                        }
-
-                        // Clear the log *after* flushing the network buffers, or we're just
-                        // going to resend all the trace messages on the next iteration.
-                        buffer.clear();
-                    }
-                })?;
+            Request::PullLog => pull_log(io, stream, false)?,
+            Request::PullLogCounted => pull_log(io, stream, true)?,
+            Request::GetThreadStats { clear } => {
+                let (elapsed_us, threads) = sched::thread_stats(clear);
+                Reply::ThreadStats { elapsed_us: elapsed_us, threads: &threads }.write_to(stream)?;
+            }
+            Request::GetLostLogRecords => {
+                let lost = BufferLogger::with(|logger| logger.lost_records());
+                Reply::LostLogRecords(lost).write_to(stream)?;
             }
             Request::SetLogFilter(level) => {
                 info!("changing log level to {}", level);
-                log::set_max_level(level);
+                BufferLogger::with(|logger|
+                    logger.set_log_level(level));
+                Reply::Success.write_to(stream)?;
+            }
+            #[cfg(has_spiflash)]
+            Request::ClearPanicLog => {
+                board_misoc::panic_log::clear();
+                Reply::Success.write_to(stream)?;
+            }
+            #[cfg(not(has_spiflash))]
+            Request::ClearPanicLog => {
+                Reply::Unavailable.write_to(stream)?;
+            }
+            Request::SetModuleLogFilter { ref module, level } => {
+                info!("changing log level of {} to {}", module, level);
+                match BufferLogger::with(|logger| logger.set_module_log_level(module, level)) {
+                    Ok(()) => {
+                        save_module_log_levels();
+                        Reply::Success.write_to(stream)
+                    }
+                    Err(()) => {
+                        warn!("too many module log levels, or module path too long");
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::RemoveModuleLogFilter { ref module } => {
+                info!("removing log level of {}", module);
+                if BufferLogger::with(|logger| logger.remove_module_log_level(module)) {
+                    save_module_log_levels();
+                    Reply::Success.write_to(stream)
+                } else {
+                    Reply::Error.write_to(stream)
+                }?;
+            }
+            Request::SetLogFormat { json } => {
+                let format = if json { LogFormat::Json } else { LogFormat::Text };
+                info!("changing log format to {}", format.name());
+                BufferLogger::with(|logger| logger.set_log_format(format));
+                if let Err(err) = hw_watchdog::suspend(||
+                        config::write("log_format", format.name().as_bytes())) {
+                    warn!("failed to save log format: {}", err);
+                }
                 Reply::Success.write_to(stream)?;
             }
             Request::SetUartLogFilter(level) => {
@@ -74,6 +1035,42 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
                     logger.set_uart_log_level(level));
                 Reply::Success.write_to(stream)?;
             }
+            Request::SetLogSinkFilter { sink, level } => {
+                match Sink::from_index(sink) {
+                    Some(sink) => {
+                        info!("changing {} log level to {}", sink.name(), level);
+                        BufferLogger::with(|logger| logger.set_sink_log_level(sink, level));
+                        let key = sink_log_level_key(sink);
+                        if let Err(err) = hw_watchdog::suspend(||
+                                config::write(key, format!("{}", level).as_bytes())) {
+                            warn!("failed to save {} log level: {}", sink.name(), err);
+                        }
+                        Reply::Success.write_to(stream)
+                    }
+                    None => {
+                        warn!("unknown log sink {}", sink);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::GetLogSinks => {
+                let (levels, syslog_lost) = BufferLogger::with(|logger| {
+                    let mut levels = [0; 3];
+                    for (index, &sink) in [Sink::Uart, Sink::Buffer, Sink::Syslog].iter().enumerate() {
+                        levels[index] = log_level_index(logger.sink_log_level(sink));
+                    }
+                    (levels, logger.lost_syslog_records())
+                });
+                let syslog = match syslog::destination() {
+                    Some(Ok(destination)) => format!("{}", destination),
+                    _ => String::new()
+                };
+                Reply::LogSinks {
+                    levels:      levels,
+                    syslog:      &syslog,
+                    syslog_lost: syslog_lost
+                }.write_to(stream)?;
+            }
 
             Request::ConfigRead { ref key } => {
                 config::read(key, |result| {
@@ -84,20 +1081,38 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
                 })?;
             }
             Request::ConfigWrite { ref key, ref value } => {
-                match config::write(key, value) {
+                match hw_watchdog::suspend(|| config::write(key, value)) {
                     Ok(_)  => Reply::Success.write_to(stream),
                     Err(_) => Reply::Error.write_to(stream)
                 }?;
             }
             Request::ConfigRemove { ref key } => {
-                match config::remove(key) {
+                match hw_watchdog::suspend(|| config::remove(key)) {
                     Ok(()) => Reply::Success.write_to(stream),
                     Err(_) => Reply::Error.write_to(stream)
                 }?;
 
             }
+            Request::ConfigList => {
+                let mut keys = Vec::new();
+                match config::iter(|key, size| {
+                    keys.push((String::from_utf8_lossy(key).into_owned(), size as u32))
+                }) {
+                    Ok(()) => Reply::ConfigKeys(&keys).write_to(stream),
+                    Err(_) => Reply::Error.write_to(stream)
+                }?;
+            }
+            Request::ConfigRestore(ref image) => {
+                match hw_watchdog::suspend(|| config::restore(image)) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(err) => {
+                        error!("failed to restore configuration: {}", err);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
             Request::ConfigErase => {
-                match config::erase() {
+                match hw_watchdog::suspend(|| config::erase()) {
                     Ok(()) => Reply::Success.write_to(stream),
                     Err(_) => Reply::Error.write_to(stream)
                 }?;
@@ -115,70 +1130,873 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
                 Reply::Success.write_to(stream)?;
             }
             Request::GetProfile => {
-                profiler::pause(|profile| {
-                    let profile = match profile {
-                        None => return Reply::Unavailable.write_to(stream),
-                        Some(profile) => profile
-                    };
-
-                    Reply::Profile.write_to(stream)?;
-                    {
-                        let hits = profile.hits();
-                        stream.write_u32(hits.len() as u32)?;
-                        for (&addr, &count) in hits.iter() {
-                            stream.write_u32(addr.as_raw() as u32)?;
-                            stream.write_u32(count)?;
-                        }
-                    }
-                    {
-                        let edges = profile.edges();
-                        stream.write_u32(edges.len() as u32)?;
-                        for (&(caller, callee), &count) in edges.iter() {
-                            stream.write_u32(caller.as_raw() as u32)?;
-                            stream.write_u32(callee.as_raw() as u32)?;
-                            stream.write_u32(count)?;
-                        }
-                    }
+                profiler::pause(|profile| write_profile(stream, profile))?;
+            }
 
-                    Ok(())
-                })?;
+            Request::StartKernelProfiler { interval_us, hits_size, edges_size } => {
+                match profiler::start_kernel(interval_us as u64,
+                                             hits_size as usize, edges_size as usize) {
+                    Ok(()) => Reply::Success.write_to(stream)?,
+                    Err(()) => Reply::Unavailable.write_to(stream)?
+                }
+            }
+            Request::StopKernelProfiler => {
+                profiler::stop_kernel();
+                Reply::Success.write_to(stream)?;
+            }
+            Request::GetKernelProfile => {
+                profiler::with_kernel_profile(|profile| write_profile(stream, profile))?;
             }
 
             Request::Hotswap(firmware) => {
+                // Hotswapped images bypass the signature check of the bootloader.
+                #[cfg(has_spiflash)]
+                {
+                    if board_misoc::boot_key::required() {
+                        error!("hotswap refused, signed firmware is required");
+                        Reply::Error.write_to(stream)?;
+                        continue
+                    }
+                }
                 Reply::RebootImminent.write_to(stream)?;
                 stream.close()?;
                 stream.flush()?;
 
+                audit::save_restart();
                 profiler::stop();
+                hw_watchdog::disable();
                 warn!("hotswapping firmware");
                 unsafe { boot::hotswap(&firmware) }
             }
+            #[cfg(has_spiflash)]
+            Request::FlashFirmware(image) => {
+                match hw_watchdog::suspend(|| board_misoc::firmware::update(&image)) {
+                    Ok(bank) => {
+                        Reply::RebootImminent.write_to(stream)?;
+                        stream.close()?;
+                        stream.flush()?;
+
+                        audit::save_restart();
+                        profiler::stop();
+                        warn!("firmware written to bank {}, restarting to try it", bank);
+                        unsafe { boot::reset() }
+                    }
+                    Err(err) => {
+                        error!("failed to write firmware: {}", err);
+                        Reply::Error.write_to(stream)?;
+                    }
+                }
+            }
+            #[cfg(not(has_spiflash))]
+            Request::FlashFirmware(_) => {
+                error!("no flash to write firmware to");
+                Reply::Error.write_to(stream)?;
+            }
+            #[cfg(all(has_spiflash, has_icap))]
+            Request::FlashGateware { ref image, .. } if image.is_empty() => {
+                match hw_watchdog::suspend(|| board_misoc::gateware::disable()) {
+                    Ok(()) => {
+                        info!("gateware update disabled, golden gateware from the next power-up");
+                        Reply::Success.write_to(stream)?;
+                    }
+                    Err(err) => {
+                        error!("failed to disable the gateware update: {}", err);
+                        Reply::Error.write_to(stream)?;
+                    }
+                }
+            }
+            // Gateware images are not signed, so they would bypass the
+            // signature check of the bootloader.
+            #[cfg(all(has_spiflash, has_icap))]
+            Request::FlashGateware { .. } if board_misoc::boot_key::required() => {
+                error!("gateware update refused, signed firmware is required");
+                Reply::Error.write_to(stream)?;
+            }
+            #[cfg(all(has_spiflash, has_icap))]
+            Request::FlashGateware { image, reconfigure } => {
+                use board_misoc::gateware;
+
+                match hw_watchdog::suspend(|| gateware::update(&image)) {
+                    Ok(()) if reconfigure => match gateware::start_trial() {
+                        Ok(true) => {
+                            Reply::RebootImminent.write_to(stream)?;
+                            stream.close()?;
+                            stream.flush()?;
+
+                            audit::save_restart();
+                            profiler::stop();
+                            hw_watchdog::disable();
+                            warn!("gateware written, reconfiguring the FPGA to try it");
+                            unsafe { gateware::reconfigure() }
+                        }
+                        Ok(false) => {
+                            error!("gateware written, but not found again for its trial");
+                            Reply::Error.write_to(stream)?;
+                        }
+                        Err(err) => {
+                            error!("failed to start gateware trial: {}", err);
+                            Reply::Error.write_to(stream)?;
+                        }
+                    },
+                    Ok(()) => {
+                        info!("gateware written, to be booted on trial at the next power-up");
+                        Reply::Success.write_to(stream)?;
+                    }
+                    Err(err) => {
+                        error!("failed to write gateware: {}", err);
+                        Reply::Error.write_to(stream)?;
+                    }
+                }
+            }
+            #[cfg(not(all(has_spiflash, has_icap)))]
+            Request::FlashGateware { .. } => {
+                error!("no gateware update slot");
+                Reply::Error.write_to(stream)?;
+            }
+            Request::FlashSatelliteFirmware { destination, image } => {
+                match remote_firmware::flash(io, aux_mutex, &routing_table.borrow(), destination, &image) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("failed to write satellite firmware: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
             Request::Reboot => {
                 Reply::RebootImminent.write_to(stream)?;
                 stream.close()?;
                 stream.flush()?;
 
+                audit::save_restart();
                 profiler::stop();
                 warn!("restarting");
                 unsafe { boot::reset() }
             }
+            Request::RebootToBootloader => {
+                // The bootloader clears this key as soon as it sees it, so only
+                // the next boot skips the firmware in flash.
+                if let Err(err) = hw_watchdog::suspend(|| config::write("netboot_once", b"1")) {
+                    error!("failed to request network boot: {}", err);
+                    Reply::Error.write_to(stream)?;
+                    continue
+                }
+                Reply::RebootImminent.write_to(stream)?;
+                stream.close()?;
+                stream.flush()?;
+
+                audit::save_restart();
+                profiler::stop();
+                warn!("restarting into the bootloader");
+                unsafe { boot::reset() }
+            }
+            Request::PrepareForPowerOff => {
+                let report = shutdown::prepare(io)?;
+                Reply::PowerOffReady {
+                    kernel_stopped:     report.kernel_stopped,
+                    safe_state_outputs: report.safe_state_outputs,
+                    sfp_tx_disabled:    report.sfp_tx_disabled
+                }.write_to(stream)?;
+            }
+
+            Request::Si549Trim { destination, ppb } => {
+                match remote_clock::si549_trim(io, aux_mutex, &routing_table.borrow(), destination, ppb) {
+                    Ok(true) => Reply::Success.write_to(stream),
+                    Ok(false) => Reply::Error.write_to(stream),
+                    Err(e) => {
+                        warn!("Si549 trim unavailable: {}", e);
+                        Reply::Unavailable.write_to(stream)
+                    }
+                }?;
+            }
+            Request::WrpllStats { destination } => {
+                match remote_clock::wrpll_stats(io, aux_mutex, &routing_table.borrow(), destination) {
+                    Ok(reply) => reply.write_to(stream),
+                    Err(e) => {
+                        warn!("WRPLL statistics unavailable: {}", e);
+                        Reply::Unavailable.write_to(stream)
+                    }
+                }?;
+            }
+
+            Request::GetClockRegisters { destination } => {
+                match clock_registers::read(io, aux_mutex, &routing_table.borrow(), destination) {
+                    Ok(chips) => Reply::ClockRegisters(&chips).write_to(stream),
+                    Err(e) => {
+                        error!("cannot read the clocking chip registers of destination {}: {}",
+                               destination, e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+
+            Request::GetRtioFrequency => {
+                Reply::RtioFrequency(rtio_clocking::measured_frequency()).write_to(stream)?;
+            }
+            Request::GetRtioCounter => {
+                let (counter, uptime_us, unix_us) = rtio_clocking::counter_correlation();
+                Reply::RtioCounter {
+                    counter:   counter,
+                    uptime_us: uptime_us,
+                    unix_us:   unix_us
+                }.write_to(stream)?;
+            }
 
             Request::DebugAllocator =>
                 unsafe { println!("{}", ::ALLOC) },
+            Request::AllocatorStats => {
+                let stats = ::heap_stats();
+                Reply::AllocatorStats {
+                    total: stats.total as u32,
+                    used: stats.used as u32,
+                    largest_free: stats.largest_free as u32,
+                    free_blocks: stats.free_blocks as u32
+                }.write_to(stream)?;
+            }
+            Request::GetHealthSnapshot => {
+                with_health_snapshot(io, aux_mutex, routing_table,
+                                     |health| Reply::HealthSnapshot(health).write_to(stream))?;
+            }
+            Request::GetHealthRecords => {
+                let records = with_health_snapshot(io, aux_mutex, routing_table, |health| {
+                    let mut records = RecordSet::new();
+                    records.snapshot(health);
+                    records
+                });
+                Reply::HealthRecords(records.as_bytes()).write_to(stream)?;
+            }
+            Request::GetNetStats => {
+                let sockets: Vec<SocketStats> = io.sockets().borrow().iter()
+                    .filter_map(|socket| match *socket {
+                        Socket::Tcp(ref socket) => Some(SocketStats {
+                            state: format!("{}", socket.state()),
+                            local: format!("{}", socket.local_endpoint()),
+                            remote: format!("{}", socket.remote_endpoint()),
+                            recv_queue: socket.recv_queue() as u32,
+                            send_queue: socket.send_queue() as u32
+                        }),
+                        _ => None
+                    })
+                    .collect();
+                let packets = ethmac::packet_counts();
+                let errors = ethmac::EthernetStatistics::new();
+                Reply::NetStats(&NetStats {
+                    rx_packets: packets.rx_packets,
+                    rx_bytes: packets.rx_bytes,
+                    tx_packets: packets.tx_packets,
+                    tx_bytes: packets.tx_bytes,
+                    rx_preamble_errors: errors.rx_preamble_errors,
+                    rx_crc_errors: errors.rx_crc_errors,
+                    rx_dropped: errors.rx_dropped,
+                    network_errors: ::network_errors(),
+                    tcp_retransmissions: packets.tcp_retransmissions,
+                    sockets: &sockets
+                }).write_to(stream)?;
+            }
+            Request::GetSatelliteIdents => {
+                let satellites = remote_ident::satellites(io, aux_mutex, &routing_table.borrow());
+                Reply::SatelliteIdents(&satellites).write_to(stream)?;
+            }
+            Request::GetRtioTopology { destination } => {
+                let topology = if destination == 0 {
+                    Ok(String::from(local_topology()))
+                } else {
+                    remote_ident::topology(io, aux_mutex, &routing_table.borrow(), destination)
+                };
+                match topology {
+                    Ok(ref topology) if topology.is_empty() => {
+                        warn!("the gateware of destination {} does not describe its topology",
+                              destination);
+                        Reply::Unavailable.write_to(stream)
+                    }
+                    Ok(ref topology) => Reply::RtioTopology(topology).write_to(stream),
+                    Err(e) => {
+                        error!("cannot read the RTIO topology of destination {}: {}", destination, e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::GetTscStats => {
+                let satellites = remote_tsc::stats(io, aux_mutex, &routing_table.borrow());
+                Reply::TscStats(&satellites).write_to(stream)?;
+            }
+            Request::GetSedStats { clear } => {
+                Reply::SedStats(&rtio_mgt::sed_stats(clear)).write_to(stream)?;
+            }
+            Request::GetChannelEvents { clear } => {
+                let (group_size, events) = rtio_mgt::channel_events(clear);
+                Reply::ChannelEvents { group_size: group_size, events: &events }.write_to(stream)?;
+            }
+            Request::GetCacheEntries => {
+                Reply::CacheEntries(&cache.borrow().list()).write_to(stream)?;
+            }
+            Request::RemoveCacheEntry { ref key } => {
+                let result = cache.borrow_mut().remove(key);
+                match result {
+                    Ok(()) => {
+                        info!("removed cache entry {}", key);
+                        Reply::Success.write_to(stream)
+                    }
+                    Err(cache::Error::Busy) => {
+                        warn!("cannot remove cache entry {}: in use by the running kernel", key);
+                        Reply::Error.write_to(stream)
+                    }
+                    Err(_) => {
+                        warn!("cannot remove cache entry {}: not found", key);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::ClearCache => {
+                let left = cache.borrow_mut().clear();
+                if left == 0 {
+                    info!("cleared cache");
+                    Reply::Success.write_to(stream)?;
+                } else {
+                    warn!("cannot clear {} cache entries in use by the running kernel", left);
+                    Reply::Error.write_to(stream)?;
+                }
+            }
+            Request::GetGrabberStats => {
+                match grabber_stats() {
+                    Some(grabbers) => Reply::GrabberStats(&grabbers).write_to(stream),
+                    None => Reply::Unavailable.write_to(stream)
+                }?;
+            }
+            Request::SubscribeAlerts { records } => {
+                // Each alert is sent until the host acknowledges it, so that
+                // none is lost if the connection drops in between.
+                loop {
+                    let alert = io.until_ok(|| alerts::next().ok_or(()))?;
+                    Reply::Alert {
+                        sequence: alert.sequence,
+                        timestamp_ms: alert.timestamp_ms,
+                        severity: alert.severity as u8,
+                        source: alert.source as u8,
+                        dropped: alerts::dropped(),
+                        message: &alert.message,
+                        records: if records { Some(&alert.records[..]) } else { None }
+                    }.write_to(stream)?;
+                    stream.flush()?;
+                    match Request::read_from(stream)? {
+                        Request::AcknowledgeAlert { sequence } => alerts::acknowledge(sequence),
+                        request => {
+                            warn!("expected an alert acknowledgement, received {:?}", request);
+                            return Ok(())
+                        }
+                    }
+                }
+            }
+            Request::AcknowledgeAlert { .. } => {
+                warn!("alert acknowledged without a subscription");
+                Reply::Error.write_to(stream)?;
+            }
+            Request::RunSelfTest => {
+                let report = {
+                    let _lock = aux_mutex.lock(io)?;
+                    post::run(Some(io))
+                };
+                write_self_test_report(stream, &report)?;
+            }
+            Request::GetSelfTestReport => {
+                match post::last_report() {
+                    Some(report) => write_self_test_report(stream, &report),
+                    None => Reply::Unavailable.write_to(stream)
+                }?;
+            }
+            Request::RunEemSelfTest => {
+                match eem_test::run() {
+                    Ok(report) => write_self_test_report(stream, &report),
+                    Err(e) => {
+                        warn!("cannot run the EEM self test: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::EepromRead { busno, port, address, size, page_size, offset, length } => {
+                let result = eeprom_access(io, busno, port, address, size, page_size, |eeprom| {
+                    if length > eeprom.size() {
+                        return Err("EEPROM access out of range")
+                    }
+                    let mut data = vec![0; length as usize];
+                    eeprom.read(offset, &mut data).map(|()| data)
+                });
+                match result {
+                    Ok(data) => Reply::ConfigData(&data).write_to(stream),
+                    Err(e) => {
+                        warn!("cannot read EEPROM: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::EepromWrite { busno, port, address, size, page_size, offset, ref data } => {
+                match eeprom_access(io, busno, port, address, size, page_size,
+                                    |eeprom| eeprom.write(offset, data)) {
+                    Ok(()) => {
+                        info!("wrote {} bytes at 0x{:x} of EEPROM 0x{:02x} on I2C bus {}",
+                              data.len(), offset, address, busno);
+                        Reply::Success.write_to(stream)
+                    }
+                    Err(e) => {
+                        warn!("cannot write EEPROM: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::FlashStorageErase => {
+                match hw_watchdog::suspend(|| flash_storage::erase()) {
+                    Ok(()) => {
+                        info!("erased the flash storage area");
+                        Reply::Success.write_to(stream)
+                    }
+                    Err(e) => {
+                        error!("cannot erase the flash storage area: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_fault_injection)]
+            Request::InjectFault(fault) => {
+                match fault_injection::inject(fault) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("cannot inject fault: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(not(has_fault_injection))]
+            Request::InjectFault(_) => {
+                warn!("fault injection is not built into this gateware");
+                Reply::Unavailable.write_to(stream)?;
+            }
+            #[cfg(has_drtio_eye_scan)]
+            Request::DrtioEyeScan { linkno, horizontal_step, vertical_step, prescale } => {
+                match eye_scan::scan(io, linkno, horizontal_step, vertical_step, prescale) {
+                    Ok((ui_steps, bits_per_sample, points)) => Reply::EyeScan {
+                        ui_steps:        ui_steps,
+                        bits_per_sample: bits_per_sample,
+                        points:          &points
+                    }.write_to(stream),
+                    Err(e) => {
+                        error!("cannot scan the eye of DRTIO link {}: {}", linkno, e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(not(has_drtio_eye_scan))]
+            Request::DrtioEyeScan { .. } => {
+                warn!("eye scans are not supported by this gateware");
+                Reply::Unavailable.write_to(stream)?;
+            }
+            Request::GetRtioErrorCounts { clear } => {
+                remote_rtio_counters::collect(io, aux_mutex, &routing_table.borrow(), clear);
+                Reply::RtioErrorCounts(&rtio_mgt::error_counts(clear)).write_to(stream)?;
+            }
+            Request::KernelImageWrite { ref name, ref image } => {
+                match session::kernel_image_key(name) {
+                    None => {
+                        error!("invalid kernel image name: {}", name);
+                        Reply::Error.write_to(stream)
+                    }
+                    Some(key) => match hw_watchdog::suspend(|| config::write(&key, image)) {
+                        Ok(()) => {
+                            info!("stored kernel image {} ({} bytes)", name, image.len());
+                            Reply::Success.write_to(stream)
+                        }
+                        Err(err) => {
+                            error!("cannot store kernel image {}: {}", name, err);
+                            Reply::Error.write_to(stream)
+                        }
+                    }
+                }?;
+            }
+            Request::KernelImageRemove { ref name } => {
+                match session::kernel_image_key(name)
+                        .map(|key| hw_watchdog::suspend(|| config::remove(&key))) {
+                    Some(Ok(())) => {
+                        info!("removed kernel image {}", name);
+                        Reply::Success.write_to(stream)
+                    }
+                    _ => Reply::Error.write_to(stream)
+                }?;
+            }
+            Request::KernelImageList => {
+                match session::kernel_images() {
+                    Ok(images) => Reply::ConfigKeys(&images).write_to(stream),
+                    Err(_) => Reply::Error.write_to(stream)
+                }?;
+            }
+            Request::KernelImageRun { ref name } => {
+                match session::run_stored_kernel(name) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        warn!("cannot run kernel image {}: {}", name, e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::TerminateKernel { ref reason } => {
+                if session::request_termination(reason) {
+                    Reply::Success.write_to(stream)
+                } else {
+                    Reply::Unavailable.write_to(stream)
+                }?;
+            }
+            Request::GetEemPresence => {
+                match eem_presence::get() {
+                    Some(eems) => {
+                        let eems: Vec<EemPresence> = eems.iter().map(|eem| EemPresence {
+                            eem:      eem.eem,
+                            expected: eem.expected.as_ref().map(|ty| ty.as_str()).unwrap_or(""),
+                            detected: eem.detected,
+                            name:     eem.name.as_ref().map(|name| name.as_str()).unwrap_or("")
+                        }).collect();
+                        Reply::EemPresence(&eems).write_to(stream)
+                    }
+                    None => Reply::Unavailable.write_to(stream)
+                }?;
+            }
+            Request::I2cTransfer { busno, port, address, ref write, read_length } => {
+                info!("raw I2C transaction with 0x{:02x} on bus {}: {} bytes written, {} read",
+                      address, busno, write.len(), read_length);
+                match bus_passthrough::i2c(io, busno, port, address, write, read_length as usize) {
+                    Ok(data) => Reply::ConfigData(&data).write_to(stream),
+                    Err(e) => {
+                        warn!("raw I2C transaction failed: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::SpiTransfer { busno, flags, length, div, cs, ref data } => {
+                info!("raw SPI transaction on bus {}, chip select 0x{:02x}: {} words",
+                      busno, cs, data.len());
+                match bus_passthrough::spi(busno, flags, length, div, cs, data) {
+                    Ok(data) => Reply::SpiData(&data).write_to(stream),
+                    Err(e) => {
+                        warn!("raw SPI transaction failed: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::GetRtc | Request::SetRtc { .. } if !rtc::configured() => {
+                Reply::Unavailable.write_to(stream)?;
+            }
+            Request::GetRtc => {
+                match rtc::read(io) {
+                    Ok(unix_s) => Reply::Rtc(unix_s).write_to(stream),
+                    Err(e) => {
+                        warn!("cannot read the RTC: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::SetRtc { unix_s } => {
+                match rtc::set(io, unix_s) {
+                    Ok(()) => {
+                        info!("RTC and wall clock set to {} s since the Unix epoch", unix_s);
+                        Reply::Success.write_to(stream)
+                    }
+                    Err(e) => {
+                        warn!("cannot set the RTC: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::GetMoninjLimits => {
+                match moninj_limits() {
+                    Some(reply) => reply.write_to(stream),
+                    None => Reply::Unavailable.write_to(stream)
+                }?;
+            }
+            Request::GetFeatures => {
+                Reply::Features(features::word()).write_to(stream)?;
+            }
+            #[cfg(has_spiflash)]
+            Request::GetFlashHealth { scrub } => {
+                use board_misoc::flash_health;
+
+                if scrub {
+                    flash_scrub::scrub(Some(io));
+                }
+                let mut remapped = Vec::new();
+                flash_health::remapped(|sector, spare| remapped.push(RemappedSector {
+                    sector: sector as u32,
+                    spare:  spare as u32
+                }));
+                let report = flash_scrub::report();
+                Reply::FlashHealth {
+                    scrubbed_ms: report.scrubbed_ms,
+                    scrubs:      report.scrubs,
+                    areas:       &report.areas,
+                    failures:    flash_health::failures(),
+                    spares_free: flash_health::spares_free() as u8,
+                    remapped:    &remapped
+                }.write_to(stream)?;
+            }
+            #[cfg(not(has_spiflash))]
+            Request::GetFlashHealth { .. } => {
+                error!("no flash to check");
+                Reply::Error.write_to(stream)?;
+            }
+            Request::GetDrtioLatency => {
+                Reply::DrtioLatency(&rtio_mgt::latencies(&routing_table.borrow())).write_to(stream)?;
+            }
+            Request::SetDrtioLatency { destination, latency_ps } => {
+                match rtio_mgt::calibrate_latency(&routing_table.borrow(), destination, latency_ps) {
+                    Ok(latency_ps) => {
+                        info!("[DEST#{}] latency calibration set to {} ps", destination, latency_ps);
+                        Reply::Success.write_to(stream)
+                    }
+                    Err(e) => {
+                        warn!("[DEST#{}] cannot set the latency calibration: {}", destination, e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::GetCompatibility => {
+                let (firmware, gateware) = compat::descriptors();
+                Reply::Compatibility { firmware: firmware, gateware: gateware }.write_to(stream)?;
+            }
+            Request::GetDdsSyncReports => {
+                Reply::DdsSyncReports(&dds_sync::reports()).write_to(stream)?;
+            }
+            Request::GetBoardIdentity => {
+                match board_identity::get() {
+                    Some(identity) => Reply::BoardIdentity(&identity).write_to(stream),
+                    None => Reply::Unavailable.write_to(stream)
+                }?;
+            }
+            Request::ReloadRoutingTable => {
+                match rtio_mgt::drtio::reload_routing_table(io, routing_table) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        warn!("cannot reload routing table: {}", e);
+                        Reply::Unavailable.write_to(stream)
+                    }
+                }?;
+            }
+            Request::GetDrtioLinkStats => {
+                Reply::DrtioLinkStats(&drtio_link_stats()).write_to(stream)?;
+            }
+            #[cfg(has_rtio_analyzer)]
+            Request::SetAnalyzerTrigger { start, stop } => {
+                info!("setting analyzer triggers to start on {:?}, stop on {:?}", start, stop);
+                ::analyzer::set_trigger(start, stop);
+                Reply::Success.write_to(stream)?;
+            }
+            #[cfg(has_rtio_analyzer)]
+            Request::GetAnalyzerTrigger => {
+                let (start, stop, started, stopped) = ::analyzer::trigger();
+                Reply::AnalyzerTrigger {
+                    start: start,
+                    stop: stop,
+                    started: started,
+                    stopped: stopped
+                }.write_to(stream)?;
+            }
+            #[cfg(has_rtio_analyzer)]
+            Request::SetAnalyzerFilter { exclude, ref channels } => {
+                info!("setting analyzer filter to {} channels {:?}",
+                      if exclude { "exclude" } else { "include" }, channels);
+                match ::analyzer::set_filter(exclude, channels) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(()) => {
+                        warn!("too many channels for the analyzer filter");
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_rtio_analyzer)]
+            Request::GetAnalyzerFilter => {
+                let (exclude, channels) = ::analyzer::filter();
+                Reply::AnalyzerFilter {
+                    exclude: exclude,
+                    channels: channels
+                }.write_to(stream)?;
+            }
+            #[cfg(not(has_rtio_analyzer))]
+            Request::SetAnalyzerTrigger { .. } | Request::GetAnalyzerTrigger |
+            Request::SetAnalyzerFilter { .. } | Request::GetAnalyzerFilter => {
+                Reply::Unavailable.write_to(stream)?;
+            }
         };
     }
 }
 
-pub fn thread(io: Io) {
-    let listener = TcpListener::new(&io, 8192);
+// Calls `f` with the current state of the core device.
+fn with_health_snapshot<F, R>(io: &Io, aux_mutex: &Mutex,
+                              routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+                              f: F) -> R
+    where F: FnOnce(&HealthSnapshot) -> R
+{
+    let mut ident = [0; 64];
+    let drtio_links = drtio_link_stats();
+    let sfp: Vec<Option<SfpDiagnostics>> = (0..sfp::count())
+        .map(|index| match sfp::status(index) {
+            Ok(status) => status.diagnostics.map(|d| SfpDiagnostics {
+                temperature: d.temperature,
+                voltage: d.voltage,
+                tx_bias: d.tx_bias,
+                tx_power: d.tx_power,
+                rx_power: d.rx_power,
+                alarms: status.alarms
+            }),
+            Err(e) => {
+                debug!("cannot read diagnostics of SFP{}: {}", index, e);
+                None
+            }
+        })
+        .collect();
+    let heap = ::heap_stats();
+    f(&HealthSnapshot {
+        uptime_ms: clock::get_ms(),
+        unix_time_us: clock::get_unix_us(),
+        boot_stats: boot_stats::current()
+            .map(|stats| (stats.boot_count, stats.uptime_s)),
+        firmware_version: csr::CONFIG_IDENTIFIER_STR,
+        gateware_version: ident::read(&mut ident),
+        rtio_clock_ok: rtio_clocking::crg::check(),
+        rtio_frequency: rtio_clocking::measured_frequency(),
+        heap_total: heap.total as u32,
+        heap_used: heap.used as u32,
+        heap_largest_free: heap.largest_free as u32,
+        fpga: sysmon::fpga_sensors(),
+        drtio_links: &drtio_links,
+        sfp: &sfp,
+        power: &power::rails(),
+        satellite_sfp: &remote_sfp::ports(io, aux_mutex, &routing_table.borrow())
+    })
+}
+
+fn drtio_link_stats() -> Vec<DrtioLinkStats> {
+    (0..rtio_mgt::drtio::link_count())
+        .map(|linkno| {
+            let stats = rtio_mgt::drtio::link_stats(linkno as u8);
+            DrtioLinkStats {
+                up: rtio_mgt::drtio::link_rx_up(linkno as u8),
+                crc_errors: stats.crc_errors,
+                aux_timeouts: stats.aux_timeouts,
+                link_drops: stats.link_drops,
+                protocol_errors: stats.protocol_errors,
+                remote_rtio_errors: stats.remote_rtio_errors
+            }
+        })
+        .collect()
+}
+
+#[cfg(any(has_rtio_moninj, has_drtio))]
+fn moninj_limits() -> Option<Reply<'static>> {
+    let (request_rate, probe_rate) = moninj::rate_limits();
+    let (requests_throttled, reads_postponed) = moninj::rate_limit_stats();
+    Some(Reply::MoninjLimits {
+        request_rate:       request_rate,
+        probe_rate:         probe_rate,
+        requests_throttled: requests_throttled,
+        reads_postponed:    reads_postponed
+    })
+}
+
+#[cfg(not(any(has_rtio_moninj, has_drtio)))]
+fn moninj_limits() -> Option<Reply<'static>> {
+    None
+}
+
+#[cfg(has_grabber)]
+fn grabber_stats() -> Option<Vec<GrabberStats>> {
+    Some((0..csr::GRABBER_LEN).map(|g| {
+        let stats = grabber::stats(g);
+        GrabberStats {
+            aligned: stats.aligned,
+            frame_size: stats.frame_size,
+            frames: stats.frames,
+            dropped_frames: stats.dropped_frames,
+            clock_glitches: stats.clock_glitches
+        }
+    }).collect())
+}
+
+#[cfg(not(has_grabber))]
+fn grabber_stats() -> Option<Vec<GrabberStats>> {
+    None
+}
+
+// Runs `f` on an EEPROM once no kernel I2C transaction is in progress on
+// its bus.
+fn eeprom_access<F, T>(io: &Io, busno: u8, port: Option<u8>, address: u8, size: u32,
+                       page_size: u32, f: F) -> Result<T, &'static str>
+    where F: FnOnce(Eeprom) -> Result<T, &'static str>
+{
+    let eeprom = Eeprom::new(busno, port, address, size, page_size)?;
+    nrt_bus::with_i2c(io, busno, || f(eeprom))?
+}
+
+fn write_self_test_report(stream: &mut TcpStream, report: &post::Report)
+                          -> Result<(), IoError<SchedError>> {
+    // The number of checks is sent on 8 bits.
+    let checks: Vec<SelfTestCheck> = report.checks.iter().take(255).map(|check| SelfTestCheck {
+        subsystem: &check.subsystem,
+        outcome: check.outcome as u8,
+        detail: &check.detail
+    }).collect();
+    Reply::SelfTestReport {
+        timestamp_ms: report.timestamp_ms,
+        checks: &checks
+    }.write_to(stream)
+}
+
+fn write_profile(stream: &mut TcpStream, profile: Option<&mut profiler::Profile>)
+                 -> Result<(), IoError<SchedError>> {
+    let profile = match profile {
+        None => return Reply::Unavailable.write_to(stream),
+        Some(profile) => profile
+    };
+
+    Reply::Profile.write_to(stream)?;
+    {
+        let hits = profile.hits();
+        stream.write_u32(hits.len() as u32)?;
+        for (&addr, &count) in hits.iter() {
+            stream.write_u32(addr.as_raw() as u32)?;
+            stream.write_u32(count)?;
+        }
+    }
+    {
+        let edges = profile.edges();
+        stream.write_u32(edges.len() as u32)?;
+        for (&(caller, callee), &count) in edges.iter() {
+            stream.write_u32(caller.as_raw() as u32)?;
+            stream.write_u32(callee.as_raw() as u32)?;
+            stream.write_u32(count)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn thread(io: Io, aux_mutex: &Mutex, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+              cache: &Urc<RefCell<Cache>>) {
+    let listener = TcpListener::new(&io, ::socket_buffer_size("mgmt", 8192));
     listener.listen(1380).expect("mgmt: cannot listen");
     info!("management interface active");
 
     loop {
+        let aux_mutex = aux_mutex.clone();
+        let routing_table = routing_table.clone();
+        let cache = cache.clone();
+        io.until(|| listener.can_accept() || chunked_reply_expired())
+            .expect("mgmt: cannot accept");
+        if chunked_reply_expired() {
+            warn!("chunked read abandoned by the host");
+            unsafe { CHUNKED_REPLY = None }
+        }
+        if !listener.can_accept() {
+            continue
+        }
         let stream = listener.accept().expect("mgmt: cannot accept").into_handle();
-        io.spawn(4096, move |io| {
+        io.spawn(8192, move |io| {
             let mut stream = TcpStream::from_handle(&io, stream);
-            match worker(&io, &mut stream) {
+            stream.set_liveness_from_config("mgmt", 1000, 10000);
+            match worker(&io, &aux_mutex, &routing_table, &cache, &mut stream) {
                 Ok(()) => (),
                 Err(Error::Io(IoError::UnexpectedEnd)) => (),
                 Err(err) => error!("aborted: {}", err)
@@ -1,13 +1,19 @@
+use alloc::{Vec, String};
 use alloc::btree_map::BTreeMap;
+use alloc::btree_set::BTreeSet;
 use core::cell::RefCell;
 
-use io::Error as IoError;
+use byteorder::{ByteOrder, NetworkEndian};
+use smoltcp::wire::IpEndpoint;
+
+use io::{Cursor, Error as IoError};
 use io::Write;
 use moninj_proto::*;
-use sched::{Io, Mutex, TcpListener, TcpStream, Error as SchedError};
+use sched::{Io, Mutex, TcpListener, TcpStream, UdpSocket, Error as SchedError};
 use urc::Urc;
-use board_misoc::clock;
+use board_misoc::{clock, config};
 use board_artiq::drtio_routing;
+use audit::{self, Service};
 
 #[cfg(has_rtio_moninj)]
 mod local_moninj {
@@ -54,20 +60,6 @@ mod remote_moninj {
     use rtio_mgt::drtio;
     use sched::{Io, Mutex};
 
-    pub fn read_probe(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8, channel: u16, probe: u8) -> u32 {
-        let reply = drtio::aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::MonitorRequest { 
-            destination: destination,
-            channel: channel,
-            probe: probe
-        });
-        match reply {
-            Ok(drtioaux::Packet::MonitorReply { value }) => return value,
-            Ok(packet) => error!("received unexpected aux packet: {:?}", packet),
-            Err(e) => error!("aux packet error ({})", e)
-        }
-        0
-    }
-
     pub fn inject(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8, channel: u16, overrd: u8, value: u8) {
         let _lock = aux_mutex.lock(io).unwrap();
         drtioaux::send(linkno, &drtioaux::Packet::InjectionRequest {
@@ -91,6 +83,239 @@ mod remote_moninj {
         }
         0
     }
+
+    /// Reads up to `MONITOR_BATCH_SIZE` probes, or injection states if
+    /// `injection` is set, of a satellite in a single aux transaction.
+    pub fn read_batch(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8,
+                      entries: &[(u16, u8)], injection: bool)
+                      -> Option<[u32; drtioaux::MONITOR_BATCH_SIZE]> {
+        let mut channels = [0; drtioaux::MONITOR_BATCH_SIZE];
+        let mut selectors = [0; drtioaux::MONITOR_BATCH_SIZE];
+        for (i, &(channel, selector)) in entries.iter().enumerate() {
+            channels[i] = channel;
+            selectors[i] = selector;
+        }
+        let count = entries.len() as u8;
+        let request = if injection {
+            drtioaux::Packet::InjectionStatusBatchRequest {
+                destination: destination,
+                count: count,
+                channels: channels,
+                overrds: selectors
+            }
+        } else {
+            drtioaux::Packet::MonitorBatchRequest {
+                destination: destination,
+                count: count,
+                channels: channels,
+                probes: selectors
+            }
+        };
+        match drtio::aux_transact(io, aux_mutex, linkno, &request) {
+            Ok(drtioaux::Packet::MonitorBatchReply { count: reply_count, values })
+                    if reply_count == count => return Some(values),
+            Ok(packet) => error!("received unexpected aux packet: {:?}", packet),
+            Err(e) => error!("aux packet error ({})", e)
+        }
+        None
+    }
+}
+
+// Probes of satellites are not polled. Each satellite is subscribed to those
+// that the clients monitor, samples them, and flags the changes in its reply
+// to the destination survey of rtio_mgt; only then are the changed values
+// fetched. While nothing changes, monitoring adds no aux traffic. The probes
+// beyond MONITOR_SUBSCRIPTION_SIZE, and those of satellites that could not
+// be subscribed, are polled in batches.
+#[cfg(has_drtio)]
+mod subscriptions {
+    use alloc::Vec;
+    use alloc::btree_map::BTreeMap;
+    use board_misoc::clock;
+    use drtioaux;
+    use rtio_mgt::drtio;
+    use sched::{Io, Mutex};
+
+    const RETRY_INTERVAL_MS: u64 = 10_000;
+
+    struct Subscription {
+        // Number of clients monitoring each probe.
+        watchers:   BTreeMap<(u16, u8), u32>,
+        // Bumped whenever the watchers change.
+        generation: u32,
+        // The list of the satellite, and the generation and destination
+        // epoch it was made at.
+        entries:    Vec<(u16, u8)>,
+        subscribed: Option<(u32, u32)>,
+        retry_at:   u64,
+        values:     BTreeMap<(u16, u8), u32>
+    }
+
+    static mut SUBSCRIPTIONS: Option<BTreeMap<u8, Subscription>> = None;
+
+    // Not held across aux transactions, during which other clients run.
+    fn subscriptions() -> &'static mut BTreeMap<u8, Subscription> {
+        unsafe {
+            if SUBSCRIPTIONS.is_none() {
+                SUBSCRIPTIONS = Some(BTreeMap::new());
+            }
+            SUBSCRIPTIONS.as_mut().unwrap()
+        }
+    }
+
+    pub fn watch(channel: u32, probe: u8) {
+        let subscription = subscriptions().entry((channel >> 16) as u8).or_insert_with(|| Subscription {
+            watchers:   BTreeMap::new(),
+            generation: 0,
+            entries:    Vec::new(),
+            subscribed: None,
+            retry_at:   0,
+            values:     BTreeMap::new()
+        });
+        *subscription.watchers.entry((channel as u16, probe)).or_insert(0) += 1;
+        subscription.generation = subscription.generation.wrapping_add(1);
+    }
+
+    pub fn unwatch(channel: u32, probe: u8) {
+        let key = (channel as u16, probe);
+        if let Some(subscription) = subscriptions().get_mut(&((channel >> 16) as u8)) {
+            let unwatched = match subscription.watchers.get_mut(&key) {
+                Some(count) => { *count -= 1; *count == 0 }
+                None => return
+            };
+            if unwatched {
+                subscription.watchers.remove(&key);
+                subscription.values.remove(&key);
+            }
+            subscription.generation = subscription.generation.wrapping_add(1);
+        }
+    }
+
+    /// Last value of a probe of `destination`, if the satellite is
+    /// subscribed to it and reported it.
+    pub fn value(destination: u8, channel: u16, probe: u8) -> Option<u32> {
+        subscriptions().get(&destination)
+            .and_then(|subscription| subscription.values.get(&(channel, probe)).cloned())
+    }
+
+    fn subscribe(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8,
+                 entries: &[(u16, u8)]) -> Result<(), &'static str> {
+        let mut offset = 0;
+        loop {
+            let chunk = &entries[offset..(offset + drtioaux::MONITOR_BATCH_SIZE).min(entries.len())];
+            let mut channels = [0; drtioaux::MONITOR_BATCH_SIZE];
+            let mut probes = [0; drtioaux::MONITOR_BATCH_SIZE];
+            for (i, &(channel, probe)) in chunk.iter().enumerate() {
+                channels[i] = channel;
+                probes[i] = probe;
+            }
+            let request = drtioaux::Packet::MonitorSubscribeRequest {
+                destination: destination,
+                offset: offset as u16,
+                count: chunk.len() as u8,
+                channels: channels,
+                probes: probes
+            };
+            match drtio::aux_transact(io, aux_mutex, linkno, &request)? {
+                drtioaux::Packet::MonitorSubscribeReply { succeeded: true } => (),
+                drtioaux::Packet::MonitorSubscribeReply { succeeded: false } =>
+                    return Err("subscription rejected"),
+                _ => return Err("unexpected aux packet")
+            }
+            offset += chunk.len();
+            if offset == entries.len() {
+                return Ok(())
+            }
+        }
+    }
+
+    fn fetch_changes(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8)
+                     -> Result<(), &'static str> {
+        loop {
+            let reply = drtio::aux_transact(io, aux_mutex, linkno,
+                &drtioaux::Packet::MonitorChangesRequest { destination: destination })?;
+            let subscription = match subscriptions().get_mut(&destination) {
+                Some(subscription) => subscription,
+                None => return Ok(())
+            };
+            match reply {
+                drtioaux::Packet::MonitorChangesReply { entries, more, count, indices, values } => {
+                    if entries as usize != subscription.entries.len() {
+                        return Err("satellite lost the subscription")
+                    }
+                    for (&index, &value) in indices.iter().zip(values.iter()).take(count as usize) {
+                        if let Some(&key) = subscription.entries.get(index as usize) {
+                            subscription.values.insert(key, value);
+                        }
+                    }
+                    if !more {
+                        return Ok(())
+                    }
+                }
+                _ => return Err("unexpected aux packet")
+            }
+        }
+    }
+
+    /// Subscribes the satellite of `destination` again if needed, and
+    /// fetches the values that changed.
+    pub fn update(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8) {
+        let epoch = drtio::destination_epoch(destination);
+        let resubscribe = match subscriptions().get_mut(&destination) {
+            Some(subscription) => {
+                let current = Some((subscription.generation, epoch));
+                if subscription.subscribed == current || clock::get_ms() < subscription.retry_at {
+                    None
+                } else {
+                    let entries: Vec<(u16, u8)> = subscription.watchers.keys().cloned()
+                        .take(drtioaux::MONITOR_SUBSCRIPTION_SIZE).collect();
+                    Some((subscription.generation, entries))
+                }
+            }
+            None => return
+        };
+
+        let mut changes = drtio::take_monitor_changes(destination);
+        if let Some((generation, entries)) = resubscribe {
+            let result = subscribe(io, aux_mutex, linkno, destination, &entries);
+            let subscription = match subscriptions().get_mut(&destination) {
+                Some(subscription) => subscription,
+                None => return
+            };
+            match result {
+                Ok(()) => {
+                    subscription.entries = entries;
+                    subscription.subscribed = Some((generation, epoch));
+                    subscription.values.clear();
+                    // The new entries are reported as changed.
+                    changes = true;
+                }
+                Err(e) => {
+                    error!("[DEST#{}] cannot subscribe to probes ({}), polling them", destination, e);
+                    subscription.entries.clear();
+                    subscription.subscribed = None;
+                    subscription.values.clear();
+                    subscription.retry_at = clock::get_ms() + RETRY_INTERVAL_MS;
+                    return
+                }
+            }
+        }
+        if changes {
+            if let Err(e) = fetch_changes(io, aux_mutex, linkno, destination) {
+                error!("[DEST#{}] cannot fetch probe changes ({})", destination, e);
+                if let Some(subscription) = subscriptions().get_mut(&destination) {
+                    subscription.subscribed = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(has_drtio))]
+mod subscriptions {
+    pub fn watch(_channel: u32, _probe: u8) {}
+
+    pub fn unwatch(_channel: u32, _probe: u8) {}
 }
 
 #[cfg(has_drtio)]
@@ -98,9 +323,12 @@ macro_rules! dispatch {
     ($io:ident, $aux_mutex:ident, $routing_table:ident, $channel:expr, $func:ident $(, $param:expr)*) => {{
         let destination = ($channel >> 16) as u8;
         let channel = $channel as u16;
-        let hop = $routing_table.0[destination as usize][0];
+        let hop = $routing_table.borrow().0[destination as usize][0];
         if hop == 0 {
             local_moninj::$func(channel, $($param, )*)
+        } else if hop as usize > ::rtio_mgt::drtio::link_count() {
+            // Not routed.
+            Default::default()
         } else {
             let linkno = hop - 1;
             remote_moninj::$func($io, $aux_mutex, linkno, destination, channel, $($param, )*)
@@ -116,101 +344,607 @@ macro_rules! dispatch {
     }}
 }
 
-fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
-        mut stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
-    let mut probe_watch_list = BTreeMap::new();
-    let mut inject_watch_list = BTreeMap::new();
-    let mut next_check = 0;
+fn read_local(channel: u32, selector: u8, injection: bool) -> u32 {
+    if injection {
+        local_moninj::read_injection_status(channel as u16, selector) as u32
+    } else {
+        local_moninj::read_probe(channel as u16, selector)
+    }
+}
 
-    read_magic(&mut stream)?;
-    stream.write_all("E".as_bytes())?;
-    info!("new connection from {}", stream.remote_endpoint());
+// Reads the probes, or injection states if `injection` is set, of the given
+// (channel, probe or override) pairs. The values of each satellite are
+// fetched in batches, and those of satellites behind a link that is down
+// are not read (None) instead of waiting for the aux transactions to time
+// out.
+#[cfg(has_drtio)]
+fn read_values(io: &Io, aux_mutex: &Mutex, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+               keys: &[(u32, u8)], injection: bool) -> Vec<Option<u32>> {
+    use board_artiq::drtioaux::MONITOR_BATCH_SIZE;
+    use rtio_mgt::drtio;
 
-    loop {
-        if stream.can_recv() {
-            let request = HostMessage::read_from(stream)?;
-            trace!("moninj<-host {:?}", request);
-
-            match request {
-                HostMessage::MonitorProbe { enable, channel, probe } => {
-                    if enable {
-                        let _ = probe_watch_list.entry((channel, probe)).or_insert(None);
-                    } else {
-                        let _ = probe_watch_list.remove(&(channel, probe));
-                    }
-                },
-                HostMessage::MonitorInjection { enable, channel, overrd } => {
-                    if enable {
-                        let _ = inject_watch_list.entry((channel, overrd)).or_insert(None);
-                    } else {
-                        let _ = inject_watch_list.remove(&(channel, overrd));
+    let mut values = vec![None; keys.len()];
+    let mut remote: BTreeMap<u8, Vec<usize>> = BTreeMap::new();
+    let mut subscribed: BTreeMap<u8, u8> = BTreeMap::new();
+    for (i, &(channel, selector)) in keys.iter().enumerate() {
+        let destination = (channel >> 16) as u8;
+        let hop = routing_table.borrow().0[destination as usize][0];
+        if hop == 0 {
+            values[i] = Some(read_local(channel, selector, injection));
+        } else if hop as usize > drtio::link_count() {
+            // Not routed.
+        } else if drtio::link_rx_up(hop - 1) {
+            if injection {
+                remote.entry(destination).or_insert_with(Vec::new).push(i);
+            } else {
+                subscribed.insert(destination, hop - 1);
+            }
+        }
+    }
+    for (&destination, &linkno) in subscribed.iter() {
+        subscriptions::update(io, aux_mutex, linkno, destination);
+    }
+    for (i, &(channel, selector)) in keys.iter().enumerate() {
+        let destination = (channel >> 16) as u8;
+        if !injection && subscribed.contains_key(&destination) {
+            values[i] = subscriptions::value(destination, channel as u16, selector);
+            if values[i].is_none() {
+                remote.entry(destination).or_insert_with(Vec::new).push(i);
+            }
+        }
+    }
+    for (&destination, indices) in remote.iter() {
+        let linkno = routing_table.borrow().0[destination as usize][0] - 1;
+        for chunk in indices.chunks(MONITOR_BATCH_SIZE) {
+            let entries: Vec<(u16, u8)> = chunk.iter()
+                .map(|&i| (keys[i].0 as u16, keys[i].1))
+                .collect();
+            if let Some(batch) = remote_moninj::read_batch(io, aux_mutex, linkno, destination,
+                                                           &entries, injection) {
+                for (&i, &value) in chunk.iter().zip(batch.iter()) {
+                    values[i] = Some(value);
+                }
+            }
+        }
+    }
+    values
+}
+
+#[cfg(not(has_drtio))]
+fn read_values(_io: &Io, _aux_mutex: &Mutex, _routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+               keys: &[(u32, u8)], injection: bool) -> Vec<Option<u32>> {
+    keys.iter()
+        .map(|&(channel, selector)| Some(read_local(channel, selector, injection)))
+        .collect()
+}
+
+const DEFAULT_PROBE_INTERVAL_MS: u64 = 200;
+const MIN_PROBE_INTERVAL_MS: u64 = 10;
+
+// Rate limits, so that a misconfigured host cannot starve the comms CPU and
+// slow down the RPCs of the kernel session. Each connection may send
+// `moninj_request_rate` requests per second; the requests beyond wait in the
+// TCP buffers, or are left unacknowledged over UDP so that the host resends
+// them. Each connection may also read the probes and injection states of
+// each channel `moninj_probe_rate` times per second; the reads beyond are
+// postponed to the next check. A rate of 0 disables the limit.
+const DEFAULT_REQUEST_RATE: u32 = 1000;
+const DEFAULT_PROBE_RATE: u32 = 100;
+
+static mut REQUESTS_THROTTLED: u32 = 0;
+static mut READS_POSTPONED: u32 = 0;
+
+fn read_rate(key: &str, default: u32) -> u32 {
+    config::read_str(key, |r| r.ok().and_then(|s| s.parse().ok())).unwrap_or(default)
+}
+
+/// Requests per second per connection, and reads per second per channel and
+/// connection, allowed to new connections.
+pub fn rate_limits() -> (u32, u32) {
+    (read_rate("moninj_request_rate", DEFAULT_REQUEST_RATE),
+     read_rate("moninj_probe_rate", DEFAULT_PROBE_RATE))
+}
+
+/// Number of requests held back, and of reads postponed, by the rate limits
+/// since boot.
+pub fn rate_limit_stats() -> (u32, u32) {
+    unsafe { (REQUESTS_THROTTLED, READS_POSTPONED) }
+}
+
+// Token bucket allowing `rate` operations per second, in bursts of up to one
+// second's worth. Tokens are counted in thousandths.
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    rate:   u32,
+    tokens: u64,
+    last:   u64
+}
+
+impl TokenBucket {
+    fn new(rate: u32, now: u64) -> TokenBucket {
+        TokenBucket { rate: rate, tokens: rate as u64 * 1000, last: now }
+    }
+
+    fn refill(&mut self, now: u64) {
+        let full = self.rate as u64 * 1000;
+        let elapsed = now.saturating_sub(self.last);
+        self.tokens = (self.tokens + elapsed * self.rate as u64).min(full);
+        self.last = self.last.max(now);
+    }
+
+    // Takes a token if there is one.
+    fn take(&mut self, now: u64) -> bool {
+        if self.rate == 0 {
+            return true
+        }
+        self.refill(now);
+        if self.tokens < 1000 {
+            return false
+        }
+        self.tokens -= 1000;
+        true
+    }
+
+    // Takes a token even if there is none left, for operations that cannot
+    // be held back.
+    fn spend(&mut self, now: u64) {
+        if self.rate != 0 {
+            self.refill(now);
+            self.tokens = self.tokens.saturating_sub(1000);
+        }
+    }
+}
+
+// Injection pattern generated by the firmware: the override is set to 1 for
+// `high_ms`, then to 0 for `low_ms`, `count` times (forever if 0). Timing has
+// the resolution of the scheduler, i.e. about a millisecond.
+struct Pattern {
+    high_ms:   u64,
+    low_ms:    u64,
+    remaining: u32,
+    high:      bool,
+    next_edge: u64
+}
+
+// What a host monitors and injects, independently of the transport.
+struct Client {
+    probe_watch_list:  BTreeMap<(u32, u8), Option<u32>>,
+    inject_watch_list: BTreeMap<(u32, u8), Option<u8>>,
+    probe_interval:    u64,
+    next_check:        u64,
+    patterns:          BTreeMap<(u32, u8), Pattern>,
+    // Overrides injected, released when the client goes away.
+    injected:          BTreeSet<(u32, u8)>,
+    requests:          TokenBucket,
+    // Whether the request waiting was already counted as throttled.
+    throttled:         bool,
+    probe_rate:        u32,
+    reads:             BTreeMap<u32, TokenBucket>
+}
+
+impl Client {
+    fn new() -> Client {
+        let (request_rate, probe_rate) = rate_limits();
+        Client {
+            probe_watch_list:  BTreeMap::new(),
+            inject_watch_list: BTreeMap::new(),
+            probe_interval:    DEFAULT_PROBE_INTERVAL_MS,
+            next_check:        0,
+            patterns:          BTreeMap::new(),
+            injected:          BTreeSet::new(),
+            requests:          TokenBucket::new(request_rate, clock::get_ms()),
+            throttled:         false,
+            probe_rate:        probe_rate,
+            reads:             BTreeMap::new()
+        }
+    }
+
+    // Whether the next request may be handled now.
+    fn take_request(&mut self) -> bool {
+        if self.requests.take(clock::get_ms()) {
+            self.throttled = false;
+            return true
+        }
+        if !self.throttled {
+            unsafe { REQUESTS_THROTTLED = REQUESTS_THROTTLED.saturating_add(1) }
+            self.throttled = true;
+        }
+        false
+    }
+
+    // The monitored values of `keys` that may be read now; the others are
+    // postponed.
+    fn allowed_reads(&mut self, keys: Vec<(u32, u8)>) -> Vec<(u32, u8)> {
+        let now = clock::get_ms();
+        let probe_rate = self.probe_rate;
+        let reads = &mut self.reads;
+        keys.into_iter().filter(|&(channel, _)| {
+            let allowed = reads.entry(channel)
+                .or_insert_with(|| TokenBucket::new(probe_rate, now))
+                .take(now);
+            if !allowed {
+                unsafe { READS_POSTPONED = READS_POSTPONED.saturating_add(1) }
+            }
+            allowed
+        }).collect()
+    }
+
+    fn handle(&mut self, io: &Io, _aux_mutex: &Mutex,
+              _routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+              request: HostMessage, replies: &mut Vec<DeviceMessage>) {
+        trace!("moninj<-host {:?}", request);
+        match request {
+            HostMessage::MonitorProbe { enable, channel, probe } => {
+                if enable {
+                    if !self.probe_watch_list.contains_key(&(channel, probe)) {
+                        self.probe_watch_list.insert((channel, probe), None);
+                        subscriptions::watch(channel, probe);
                     }
-                },
-                HostMessage::Inject { channel, overrd, value } => dispatch!(io, _aux_mutex, _routing_table, channel, inject, overrd, value),
-                HostMessage::GetInjectionStatus { channel, overrd } => {
-                    let value = dispatch!(io, _aux_mutex, _routing_table, channel, read_injection_status, overrd);
-                    let reply = DeviceMessage::InjectionStatus {
-                        channel: channel,
-                        overrd: overrd,
-                        value: value
-                    };
+                } else if self.probe_watch_list.remove(&(channel, probe)).is_some() {
+                    subscriptions::unwatch(channel, probe);
+                }
+            },
+            HostMessage::MonitorInjection { enable, channel, overrd } => {
+                if enable {
+                    let _ = self.inject_watch_list.entry((channel, overrd)).or_insert(None);
+                } else {
+                    let _ = self.inject_watch_list.remove(&(channel, overrd));
+                }
+            },
+            HostMessage::Inject { channel, overrd, value } => {
+                let _ = self.patterns.remove(&(channel, overrd));
+                self.injected.insert((channel, overrd));
+                dispatch!(io, _aux_mutex, _routing_table, channel, inject, overrd, value)
+            },
+            HostMessage::InjectPattern { channel, overrd, high_ms, low_ms, count } => {
+                self.injected.insert((channel, overrd));
+                dispatch!(io, _aux_mutex, _routing_table, channel, inject, overrd, 1);
+                self.patterns.insert((channel, overrd), Pattern {
+                    high_ms:   high_ms as u64,
+                    low_ms:    low_ms as u64,
+                    remaining: count,
+                    high:      true,
+                    next_edge: clock::get_ms() + high_ms as u64
+                });
+            },
+            HostMessage::GetInjectionStatus { channel, overrd } => {
+                let value = dispatch!(io, _aux_mutex, _routing_table, channel, read_injection_status, overrd);
+                replies.push(DeviceMessage::InjectionStatus {
+                    channel: channel,
+                    overrd: overrd,
+                    value: value
+                });
+            },
+            HostMessage::SetProbeInterval { interval_ms } => {
+                self.probe_interval = (interval_ms as u64).max(MIN_PROBE_INTERVAL_MS);
+                self.next_check = clock::get_ms();
+            },
+            HostMessage::Resync => {
+                for previous in self.probe_watch_list.values_mut() {
+                    *previous = None;
+                }
+                for previous in self.inject_watch_list.values_mut() {
+                    *previous = None;
+                }
+                self.next_check = clock::get_ms();
+            }
+        }
+    }
+
+    // Stops the injection patterns and clears the overrides injected, so that
+    // the channels are driven by kernels again. Overrides are cleared in
+    // order, so that the enable override of a TTL (0) goes first.
+    fn release(&mut self, io: &Io, _aux_mutex: &Mutex,
+               _routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>) {
+        self.patterns.clear();
+        for &(channel, overrd) in self.injected.iter() {
+            dispatch!(io, _aux_mutex, _routing_table, channel, inject, overrd, 0);
+        }
+        self.injected.clear();
+    }
 
-                    trace!("moninj->host {:?}", reply);
-                    reply.write_to(stream)?;
+    // Advances the injection patterns and, when due, checks the monitored
+    // values.
+    fn poll(&mut self, io: &Io, _aux_mutex: &Mutex,
+            _routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+            updates: &mut Vec<DeviceMessage>) {
+        let now = clock::get_ms();
+        let mut finished = None;
+        for (&(channel, overrd), pattern) in self.patterns.iter_mut() {
+            if now < pattern.next_edge {
+                continue
+            }
+            if pattern.high {
+                dispatch!(io, _aux_mutex, _routing_table, channel, inject, overrd, 0);
+                if pattern.remaining == 1 {
+                    finished = Some((channel, overrd));
+                    continue
                 }
+                pattern.remaining = pattern.remaining.saturating_sub(1);
+                pattern.next_edge += pattern.low_ms;
+            } else {
+                dispatch!(io, _aux_mutex, _routing_table, channel, inject, overrd, 1);
+                pattern.next_edge += pattern.high_ms;
             }
-        } else if !stream.may_recv() {
-            return Ok(())
+            pattern.high = !pattern.high;
+            // Do not try to catch up with edges missed while the scheduler
+            // was busy elsewhere.
+            if pattern.next_edge < now {
+                pattern.next_edge = now;
+            }
+        }
+        if let Some(key) = finished {
+            let _ = self.patterns.remove(&key);
         }
 
-        if clock::get_ms() > next_check {
-            for (&(channel, probe), previous) in probe_watch_list.iter_mut() {
-                let current = dispatch!(io, _aux_mutex, _routing_table, channel, read_probe, probe);
+        // Only the values that changed since the last check are sent; a
+        // value that changed several times in between is reported once.
+        if clock::get_ms() > self.next_check {
+            let keys = self.probe_watch_list.keys().cloned().collect();
+            let keys = self.allowed_reads(keys);
+            let values = read_values(io, _aux_mutex, _routing_table, &keys, false);
+            for (&(channel, probe), value) in keys.iter().zip(values.into_iter()) {
+                let current = match value {
+                    Some(value) => value,
+                    None => continue
+                };
+                let previous = match self.probe_watch_list.get_mut(&(channel, probe)) {
+                    Some(previous) => previous,
+                    None => continue
+                };
                 if previous.is_none() || previous.unwrap() != current {
-                    let message = DeviceMessage::MonitorStatus {
+                    updates.push(DeviceMessage::MonitorStatus {
                         channel: channel,
                         probe: probe,
                         value: current
-                    };
-
-                    trace!("moninj->host {:?}", message);
-                    message.write_to(stream)?;
-
+                    });
                     *previous = Some(current);
                 }
             }
-            for (&(channel, overrd), previous) in inject_watch_list.iter_mut() {
-                let current = dispatch!(io, _aux_mutex, _routing_table, channel, read_injection_status, overrd);
+            let keys = self.inject_watch_list.keys().cloned().collect();
+            let keys = self.allowed_reads(keys);
+            let values = read_values(io, _aux_mutex, _routing_table, &keys, true);
+            for (&(channel, overrd), value) in keys.iter().zip(values.into_iter()) {
+                let current = match value {
+                    Some(value) => value as u8,
+                    None => continue
+                };
+                let previous = match self.inject_watch_list.get_mut(&(channel, overrd)) {
+                    Some(previous) => previous,
+                    None => continue
+                };
                 if previous.is_none() || previous.unwrap() != current {
-                    let message = DeviceMessage::InjectionStatus {
+                    updates.push(DeviceMessage::InjectionStatus {
                         channel: channel,
                         overrd: overrd,
                         value: current
-                    };
-
-                    trace!("moninj->host {:?}", message);
-                    message.write_to(stream)?;
-
+                    });
                     *previous = Some(current);
                 }
             }
-            next_check = clock::get_ms() + 200;
+            self.next_check = clock::get_ms() + self.probe_interval;
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        for &(channel, probe) in self.probe_watch_list.keys() {
+            subscriptions::unwatch(channel, probe);
+        }
+    }
+}
+
+fn connection_worker(io: &Io, aux_mutex: &Mutex, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+        mut stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
+    let mut client = Client::new();
+
+    read_magic(&mut stream)?;
+    stream.write_all("E".as_bytes())?;
+    info!("new connection from {}", stream.remote_endpoint());
+    let _connection = audit::Connection::open(Service::Moninj, stream.remote_endpoint(), "");
+
+    let result = serve(io, aux_mutex, routing_table, stream, &mut client);
+    client.release(io, aux_mutex, routing_table);
+    result
+}
+
+fn serve(io: &Io, aux_mutex: &Mutex, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+         stream: &mut TcpStream, client: &mut Client) -> Result<(), Error<SchedError>> {
+    let mut messages = Vec::new();
+    let mut buffer = Vec::new();
+
+    loop {
+        if stream.can_recv() {
+            if client.take_request() {
+                let request = HostMessage::read_from(stream)?;
+                client.handle(io, aux_mutex, routing_table, request, &mut messages);
+            }
+        } else if !stream.may_recv() {
+            return Ok(())
+        }
+
+        client.poll(io, aux_mutex, routing_table, &mut messages);
+
+        // The messages are sent together in a single write.
+        if !messages.is_empty() {
+            for message in messages.drain(..) {
+                trace!("moninj->host {:?}", message);
+                message.write_to(&mut buffer).unwrap();
+            }
+            stream.write_all(&buffer)?;
+            buffer.clear();
         }
 
         io.relinquish().map_err(|err| Error::Io(IoError::Other(err)))?;
     }
 }
 
+// Moninj over UDP, enabled by setting the `moninj_udp` config key to `1`,
+// for monitoring many channels at high rates without the head-of-line
+// blocking of TCP on a congested network.
+//
+// Each datagram starts with the magic `AMNJ` and a sequence number (u32),
+// followed by the same messages as over TCP, all big-endian. The datagrams
+// of the device also carry, after the sequence number, the sequence number
+// of the last datagram received from the host (u32), as an acknowledgement.
+// The host numbers its datagrams from 1 and resends those not acknowledged;
+// they are executed in order, and the others ignored. A host that sees a gap
+// in the sequence numbers of the device sends `Resync` to get all the
+// monitored values again. Hosts are forgotten when they send nothing for
+// 10 s, and must send their last datagram again to keep alive.
+
+const UDP_PORT: u16 = 1383;
+const UDP_MAGIC: &'static [u8] = b"AMNJ";
+const UDP_MAX_CLIENTS: usize = 4;
+const UDP_CLIENT_TIMEOUT_MS: u64 = 10_000;
+const UDP_MAX_DATAGRAM_SIZE: usize = 1400;
+
+struct UdpClient {
+    endpoint:  IpEndpoint,
+    last_seen: u64,
+    // Sequence number of the next datagram sent.
+    sequence:  u32,
+    // Sequence number of the last datagram received.
+    received:  u32,
+    client:    Client,
+    _connection: audit::Connection
+}
+
+fn udp_header(datagram: &mut Vec<u8>, client: &mut UdpClient) {
+    datagram.clear();
+    datagram.extend_from_slice(UDP_MAGIC);
+    datagram.resize(12, 0);
+    NetworkEndian::write_u32(&mut datagram[4..8], client.sequence);
+    NetworkEndian::write_u32(&mut datagram[8..12], client.received);
+    client.sequence = client.sequence.wrapping_add(1);
+}
+
+fn udp_send_datagram(socket: &UdpSocket, datagram: &[u8], endpoint: IpEndpoint) {
+    if let Err(err) = socket.send_slice(datagram, endpoint) {
+        debug!("cannot send to {}: {}", endpoint, err)
+    }
+}
+
+// Sends the messages to the client, in as few datagrams as possible, and an
+// empty datagram if there are none but `ack` is set.
+fn udp_send(socket: &UdpSocket, client: &mut UdpClient, messages: &mut Vec<DeviceMessage>,
+            datagram: &mut Vec<u8>, ack: bool) {
+    if messages.is_empty() && !ack {
+        return
+    }
+    udp_header(datagram, client);
+    for message in messages.drain(..) {
+        trace!("moninj->host {:?}", message);
+        let length = datagram.len();
+        message.write_to(datagram).unwrap();
+        if datagram.len() > UDP_MAX_DATAGRAM_SIZE {
+            let mut rest = datagram.split_off(length);
+            udp_send_datagram(socket, datagram, client.endpoint);
+            udp_header(datagram, client);
+            datagram.append(&mut rest);
+        }
+    }
+    udp_send_datagram(socket, datagram, client.endpoint);
+}
+
+fn udp_worker(io: &Io, aux_mutex: &Mutex, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>) {
+    let socket = UdpSocket::new(io, 16, 16 * UDP_MAX_DATAGRAM_SIZE);
+    socket.bind(UDP_PORT).expect("moninj: cannot bind");
+    info!("accepting moninj datagrams on port {}", UDP_PORT);
+
+    let mut clients: Vec<UdpClient> = Vec::new();
+    let mut received = vec![0; UDP_MAX_DATAGRAM_SIZE];
+    let mut datagram = Vec::with_capacity(UDP_MAX_DATAGRAM_SIZE);
+    let mut messages = Vec::new();
+    loop {
+        let now = clock::get_ms();
+        while let Some((length, endpoint)) = socket.recv_slice(&mut received) {
+            if length < 8 || &received[..4] != UDP_MAGIC {
+                continue
+            }
+            let sequence = NetworkEndian::read_u32(&received[4..8]);
+            let position = clients.iter().position(|c| c.endpoint == endpoint);
+            let index = match position {
+                Some(index) => index,
+                None if clients.len() < UDP_MAX_CLIENTS => {
+                    info!("new UDP client {}", endpoint);
+                    // UDP clients are only forgotten when they time out.
+                    let mut connection = audit::Connection::open(Service::Moninj, endpoint, "UDP");
+                    connection.end(String::from("timed out"));
+                    clients.push(UdpClient {
+                        endpoint:  endpoint,
+                        last_seen: now,
+                        sequence:  0,
+                        received:  0,
+                        client:    Client::new(),
+                        _connection: connection
+                    });
+                    clients.len() - 1
+                }
+                None => {
+                    warn!("too many UDP clients, ignoring {}", endpoint);
+                    continue
+                }
+            };
+            let client = &mut clients[index];
+            client.last_seen = now;
+            // A datagram over the rate limit is not acknowledged, and the
+            // host resends it; its requests beyond the first are charged too.
+            if sequence == client.received.wrapping_add(1) && client.client.take_request() {
+                client.received = sequence;
+                let mut reader = Cursor::new(&received[8..length]);
+                let mut first = true;
+                while reader.position() < length - 8 {
+                    if !first {
+                        client.client.requests.spend(clock::get_ms());
+                    }
+                    first = false;
+                    match HostMessage::read_from(&mut reader) {
+                        Ok(request) =>
+                            client.client.handle(io, aux_mutex, routing_table, request, &mut messages),
+                        Err(err) => {
+                            warn!("malformed datagram from {}: {}", endpoint, err);
+                            break
+                        }
+                    }
+                }
+            }
+            udp_send(&socket, client, &mut messages, &mut datagram, true);
+        }
+
+        for c in clients.iter_mut() {
+            if now > c.last_seen + UDP_CLIENT_TIMEOUT_MS {
+                info!("UDP client {} timed out", c.endpoint);
+                c.client.release(io, aux_mutex, routing_table);
+            }
+        }
+        clients.retain(|c| now <= c.last_seen + UDP_CLIENT_TIMEOUT_MS);
+
+        for client in clients.iter_mut() {
+            client.client.poll(io, aux_mutex, routing_table, &mut messages);
+            udp_send(&socket, client, &mut messages, &mut datagram, false);
+        }
+
+        io.relinquish().unwrap();
+    }
+}
+
 pub fn thread(io: Io, aux_mutex: &Mutex, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>) {
-    let listener = TcpListener::new(&io, 2047);
+    let listener = TcpListener::new(&io, ::socket_buffer_size("moninj", 2047));
     listener.listen(1383).expect("moninj: cannot listen");
 
+    if config::read_str("moninj_udp", |r| r == Ok("1")) {
+        let aux_mutex = aux_mutex.clone();
+        let routing_table = routing_table.clone();
+        io.spawn(16384, move |io| udp_worker(&io, &aux_mutex, &routing_table));
+    }
+
     loop {
         let aux_mutex = aux_mutex.clone();
         let routing_table = routing_table.clone();
         let stream = listener.accept().expect("moninj: cannot accept").into_handle();
         io.spawn(16384, move |io| {
-            let routing_table = routing_table.borrow();
             let mut stream = TcpStream::from_handle(&io, stream);
             match connection_worker(&io, &aux_mutex, &routing_table, &mut stream) {
                 Ok(()) => {},
@@ -0,0 +1,185 @@
+use board_misoc::{clock, i2c};
+use sched::Io;
+
+// Arbitration of the local I2C buses between kernels and the background
+// tasks of the runtime (SFP and power monitoring, sensor history, ...).
+//
+// A kernel drives an I2C transaction with one request per start condition,
+// byte and stop condition, and the runtime runs its other threads between
+// them. A kernel therefore holds the bus from its start to its stop
+// condition, and background tasks wait until the bus is free; their own
+// transactions never yield, so kernels do not wait for them. To keep a
+// kernel that keeps the bus busy from starving the background tasks, once
+// one has waited for `STARVATION_MS` the next start of the kernel is held
+// back until the task has had its turn. Background tasks select channels of
+// the I2C switches of Kasli, so the channels last selected by the kernel
+// are restored after them.
+//
+// The SPI buses are not used by background tasks and need no arbitration.
+
+/// Bus of the I2C switches, the SFP modules and the power monitors.
+pub const MAIN_BUS: u8 = 0;
+
+// Buses tracked; requests on others are passed through.
+const BUS_COUNT: usize = 4;
+// Addresses of the I2C switches of Kasli.
+const SWITCHES: [u8; 2] = [0x70, 0x71];
+
+const STARVATION_MS: u64 = 1_000;
+// A kernel that never ends its transaction does not block background tasks
+// or the kernel forever.
+const WAIT_TIMEOUT_MS: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Snoop {
+    Idle,
+    // The next byte is the address.
+    Address,
+    // The next byte is the control word of switch `n`.
+    Switch(usize),
+    Other
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bus {
+    kernel_held: bool,
+    waiters: u32,
+    // When the background task that has waited the longest started waiting.
+    waiting_since: u64,
+    snoop: Snoop,
+    // Channels last selected by the kernel on each switch.
+    switches: [Option<u8>; 2]
+}
+
+const IDLE_BUS: Bus = Bus {
+    kernel_held: false, waiters: 0, waiting_since: 0,
+    snoop: Snoop::Idle, switches: [None; 2]
+};
+
+static mut BUSES: [Bus; BUS_COUNT] = [IDLE_BUS; BUS_COUNT];
+
+fn bus(busno: u8) -> Option<&'static mut Bus> {
+    unsafe { BUSES.get_mut(busno as usize) }
+}
+
+fn busy(busno: u8) -> bool {
+    bus(busno).map(|bus| bus.kernel_held).unwrap_or(false)
+}
+
+fn starving(busno: u8) -> bool {
+    match bus(busno) {
+        Some(bus) => bus.waiters > 0 && clock::get_ms() >= bus.waiting_since + STARVATION_MS,
+        None => false
+    }
+}
+
+/// Called before the start condition of a kernel on a local bus.
+pub fn kernel_start(io: &Io, busno: u8) {
+    if busy(busno) {
+        // Repeated start condition within the transaction.
+        if let Some(bus) = bus(busno) { bus.snoop = Snoop::Address }
+        return
+    }
+    if starving(busno) {
+        debug!("I2C bus {}: holding the kernel back for background tasks", busno);
+        let deadline = clock::get_ms() + WAIT_TIMEOUT_MS;
+        let _ = io.until(|| !starving(busno) || clock::get_ms() >= deadline);
+    }
+    if let Some(bus) = bus(busno) {
+        bus.kernel_held = true;
+        bus.snoop = Snoop::Address;
+    }
+}
+
+/// Called before the repeated start condition of a kernel.
+pub fn kernel_restart(busno: u8) {
+    if let Some(bus) = bus(busno) {
+        bus.snoop = Snoop::Address
+    }
+}
+
+/// Called before each byte written by a kernel, to track the channels it
+/// selects on the I2C switches.
+pub fn kernel_write(busno: u8, data: u8) {
+    if let Some(bus) = bus(busno) {
+        bus.snoop = match bus.snoop {
+            Snoop::Address =>
+                match SWITCHES.iter().position(|&address| address << 1 == data) {
+                    Some(n) => Snoop::Switch(n),
+                    None => Snoop::Other
+                },
+            Snoop::Switch(n) => {
+                bus.switches[n] = Some(data);
+                Snoop::Other
+            }
+            snoop => snoop
+        }
+    }
+}
+
+/// Called after the stop condition of a kernel.
+pub fn kernel_stop(busno: u8) {
+    if let Some(bus) = bus(busno) {
+        bus.kernel_held = false;
+        bus.snoop = Snoop::Idle;
+    }
+}
+
+/// Releases the buses held by a kernel that has stopped, possibly in the
+/// middle of a transaction.
+pub fn kernel_stopped() {
+    for bus in unsafe { BUSES.iter_mut() } {
+        bus.kernel_held = false;
+        bus.snoop = Snoop::Idle;
+        bus.switches = [None; 2];
+    }
+}
+
+/// Whether a kernel transaction is in progress on `busno`, for callers
+/// that cannot wait and try again later.
+pub fn kernel_holds(busno: u8) -> bool {
+    busy(busno)
+}
+
+/// Selects again the channels of the I2C switches last selected by the
+/// kernel, after a background task used the bus.
+pub fn restore_switches(busno: u8) {
+    let switches = match bus(busno) {
+        Some(bus) => bus.switches,
+        None => return
+    };
+    for (&address, &channels) in SWITCHES.iter().zip(switches.iter()) {
+        if let Some(channels) = channels {
+            if let Err(e) = i2c::pca9548_select(busno, address, channels) {
+                warn!("I2C bus {}: cannot restore switch 0x{:02x}: {}", busno, address, e);
+            }
+        }
+    }
+}
+
+/// Runs `f`, which uses `busno` without yielding, once no kernel
+/// transaction is in progress on it. Fails if a kernel keeps its
+/// transaction open for too long.
+pub fn with_i2c<F, T>(io: &Io, busno: u8, f: F) -> Result<T, &'static str>
+    where F: FnOnce() -> T
+{
+    if busy(busno) {
+        if let Some(bus) = bus(busno) {
+            if bus.waiters == 0 {
+                bus.waiting_since = clock::get_ms();
+            }
+            bus.waiters += 1;
+        }
+        let deadline = clock::get_ms() + WAIT_TIMEOUT_MS;
+        let _ = io.until(|| !busy(busno) || clock::get_ms() >= deadline);
+        if let Some(bus) = bus(busno) {
+            bus.waiters -= 1;
+        }
+        if busy(busno) {
+            return Err("I2C bus held by the kernel")
+        }
+    }
+    let result = f();
+    restore_switches(busno);
+    Ok(result)
+}
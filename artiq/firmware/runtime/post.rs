@@ -0,0 +1,304 @@
+use alloc::{Vec, String};
+use core::ptr;
+use board_misoc::{clock, config, cache, bad_ram};
+#[cfg(any(has_i2c, has_drtio))]
+use board_misoc::csr;
+#[cfg(has_i2c)]
+use board_misoc::{i2c, sfp};
+#[cfg(has_spiflash)]
+use board_misoc::firmware;
+#[cfg(si5324_as_synthesizer)]
+use board_artiq::si5324;
+#[cfg(has_drtio)]
+use board_artiq::drtioaux;
+use sched::Io;
+use nrt_bus;
+#[cfg(has_drtio)]
+use rtio_mgt;
+use alerts::{self, Severity, Source};
+
+// The power-on self test exercises the I2C buses, SFP transceivers, Si5324,
+// SDRAM, firmware in flash and DRTIO transceivers, and reports the outcome
+// of each check for the acceptance testing of crates. It runs at boot if the
+// `post` configuration key is `1`, before the DRTIO links are started, and
+// at the request of a management client.
+
+const MEMORY_TEST_WORDS: usize = 64 * 1024;
+#[cfg(has_drtio)]
+const LOOPBACK_UP_TIMEOUT_MS: u64 = 1000;
+#[cfg(has_drtio)]
+const LOOPBACK_ECHO_TIMEOUT_MS: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Pass    = 0,
+    Fail    = 1,
+    Skipped = 2
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub subsystem: String,
+    pub outcome:   Outcome,
+    pub detail:    String
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub timestamp_ms: u64,
+    pub checks:       Vec<Check>
+}
+
+impl Report {
+    pub fn new() -> Report {
+        Report { timestamp_ms: clock::get_ms(), checks: Vec::new() }
+    }
+
+    pub fn failures(&self) -> usize {
+        self.checks.iter().filter(|check| check.outcome == Outcome::Fail).count()
+    }
+
+    pub fn record(&mut self, subsystem: String, outcome: Outcome, detail: String) {
+        match outcome {
+            Outcome::Pass => info!("self test: {} passed {}", subsystem, detail),
+            Outcome::Fail => error!("self test: {} failed: {}", subsystem, detail),
+            Outcome::Skipped => info!("self test: {} skipped: {}", subsystem, detail)
+        }
+        self.checks.push(Check { subsystem: subsystem, outcome: outcome, detail: detail })
+    }
+
+    pub fn result(&mut self, subsystem: String, result: Result<String, String>) {
+        match result {
+            Ok(detail) => self.record(subsystem, Outcome::Pass, detail),
+            Err(detail) => self.record(subsystem, Outcome::Fail, detail)
+        }
+    }
+}
+
+static mut LAST_REPORT: Option<Report> = None;
+
+/// Report of the last self test since boot.
+pub fn last_report() -> Option<Report> {
+    unsafe { LAST_REPORT.clone() }
+}
+
+pub fn enabled_at_boot() -> bool {
+    config::read_str("post", |r| r == Ok("1"))
+}
+
+/// Runs the self test. At boot, `io` is `None` and every check runs. On
+/// request, `io` is the thread to relinquish while waiting; the I2C checks
+/// are skipped while a kernel I2C transaction is in progress, and only the DRTIO links that are
+/// down are put in loopback. The caller must then hold the aux mutex.
+pub fn run(io: Option<&Io>) -> Report {
+    info!("running self test");
+    let mut report = Report::new();
+    // The checks do not yield, so they cannot run in the middle of a kernel
+    // I2C transaction that is not already in progress.
+    let i2c_free = io.is_none() || !nrt_bus::kernel_holds(nrt_bus::MAIN_BUS);
+
+    #[cfg(has_i2c)]
+    check_i2c(&mut report, i2c_free);
+    #[cfg(has_i2c)]
+    check_sfp(&mut report, i2c_free);
+    #[cfg(si5324_as_synthesizer)]
+    check_si5324(&mut report, i2c_free);
+    #[cfg(not(any(has_i2c, si5324_as_synthesizer)))]
+    let _ = i2c_free;
+    #[cfg(has_i2c)]
+    {
+        if io.is_some() && i2c_free {
+            nrt_bus::restore_switches(nrt_bus::MAIN_BUS)
+        }
+    }
+    check_sdram(&mut report);
+    #[cfg(has_spiflash)]
+    check_flash(&mut report);
+    #[cfg(has_drtio)]
+    check_drtio(&mut report, io);
+
+    let failures = report.failures();
+    if failures == 0 {
+        info!("self test passed");
+    } else {
+        error!("self test failed {} of {} checks", failures, report.checks.len());
+        alerts::push(Severity::Critical, Source::SelfTest,
+                     format!("self test failed {} of {} checks", failures, report.checks.len()));
+    }
+    unsafe { LAST_REPORT = Some(report.clone()) }
+    report
+}
+
+#[cfg(has_i2c)]
+fn check_i2c(report: &mut Report, i2c_free: bool) {
+    for busno in 0..csr::CONFIG_I2C_BUS_COUNT {
+        let busno = busno as u8;
+        let subsystem = format!("i2c{}", busno);
+        if !i2c_free {
+            report.record(subsystem, Outcome::Skipped, String::from("I2C bus held by the kernel"));
+            continue
+        }
+        // Fails if SCL or SDA is stuck low.
+        let result = i2c::start(busno).and_then(|()| i2c::stop(busno));
+        report.result(subsystem, result.map(|()| String::new()).map_err(String::from));
+    }
+}
+
+#[cfg(has_i2c)]
+fn check_sfp(report: &mut Report, i2c_free: bool) {
+    let unused = sfp::unused_ports();
+    for index in 0..sfp::count() {
+        let subsystem = format!("sfp{}", index);
+        if unused & 1 << index != 0 {
+            report.record(subsystem, Outcome::Skipped, String::from("unused"));
+        } else if !i2c_free {
+            report.record(subsystem, Outcome::Skipped, String::from("I2C bus held by the kernel"));
+        } else {
+            match sfp::check_id(index) {
+                Ok(true) => report.record(subsystem, Outcome::Pass, String::new()),
+                Ok(false) => report.record(subsystem, Outcome::Skipped, String::from("empty")),
+                Err(e) => report.record(subsystem, Outcome::Fail, String::from(e))
+            }
+        }
+    }
+}
+
+#[cfg(si5324_as_synthesizer)]
+fn check_si5324(report: &mut Report, i2c_free: bool) {
+    let subsystem = String::from("si5324");
+    if !i2c_free {
+        report.record(subsystem, Outcome::Skipped, String::from("I2C bus held by the kernel"));
+        return
+    }
+    let result = match si5324::status() {
+        Ok(ref status) if status.bypass => Ok(String::from("bypassed")),
+        Ok(ref status) if status.lol => Err(String::from("not locked")),
+        Ok(_) => Ok(String::from("locked")),
+        Err(e) => Err(String::from(e))
+    };
+    report.result(subsystem, result)
+}
+
+fn check_sdram(report: &mut Report) {
+    let subsystem = String::from("sdram");
+    if let Some((start, end)) = bad_ram::get() {
+        report.record(subsystem, Outcome::Fail,
+            format!("bootloader memory test failed at 0x{:08x}-0x{:08x}", start, end - 1));
+        return
+    }
+
+    let mut buffer = vec![0u32; MEMORY_TEST_WORDS];
+    let errors = memory_test(&mut buffer);
+    let detail = format!("{} KiB of heap", MEMORY_TEST_WORDS * 4 / 1024);
+    if errors == 0 {
+        report.record(subsystem, Outcome::Pass, detail)
+    } else {
+        report.record(subsystem, Outcome::Fail, format!("{} errors in {}", errors, detail))
+    }
+}
+
+// Data bus patterns, then pseudo-random data, as in the bootloader, which
+// tests all of the SDRAM but only at power-up.
+fn memory_test(buffer: &mut [u32]) -> usize {
+    fn prng32(seed: &mut u32) -> u32 {
+        *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+        *seed
+    }
+
+    let mut errors = 0;
+    for pattern in 0..3 {
+        let data = |seed: &mut u32| match pattern {
+            0 => 0xaaaaaaaa,
+            1 => 0x55555555,
+            _ => prng32(seed)
+        };
+
+        let mut seed = 0;
+        for word in buffer.iter_mut() {
+            unsafe { ptr::write_volatile(word, data(&mut seed)) }
+        }
+
+        cache::flush_cpu_dcache();
+        cache::flush_l2_cache();
+
+        let mut seed = 0;
+        for word in buffer.iter() {
+            if unsafe { ptr::read_volatile(word) } != data(&mut seed) {
+                errors += 1;
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(has_spiflash)]
+fn check_flash(report: &mut Report) {
+    let bank = firmware::active();
+    let result = match firmware::read(bank) {
+        Some(image) => Ok(format!("firmware bank {}, {} bytes", bank, image.len())),
+        None => Err(format!("firmware bank {} fails its CRC check", bank))
+    };
+    report.result(String::from("flash"), result)
+}
+
+#[cfg(has_drtio)]
+fn check_drtio(report: &mut Report, io: Option<&Io>) {
+    for linkno in 0..csr::DRTIO.len() {
+        let linkno = linkno as u8;
+        let subsystem = format!("drtio{}", linkno);
+        if io.is_some() && rtio_mgt::drtio::link_rx_up(linkno) {
+            report.record(subsystem, Outcome::Skipped, String::from("link up"));
+            continue
+        }
+
+        set_loopback(linkno, true);
+        let result = loopback_echo(linkno, io);
+        set_loopback(linkno, false);
+        drtioaux::reset(linkno);
+        report.result(subsystem, result.map(|()| String::new()).map_err(String::from));
+    }
+}
+
+#[cfg(has_drtio)]
+fn set_loopback(linkno: u8, enable: bool) {
+    unsafe {
+        let loopback = csr::drtio_transceiver::loopback_read();
+        if enable {
+            csr::drtio_transceiver::loopback_write(loopback | 1 << linkno);
+        } else {
+            csr::drtio_transceiver::loopback_write(loopback & !(1 << linkno));
+        }
+    }
+}
+
+// With near-end PMA loopback, the link receives what it transmits: it comes
+// up and receives its own aux packets.
+#[cfg(has_drtio)]
+fn loopback_echo(linkno: u8, io: Option<&Io>) -> Result<(), &'static str> {
+    let wait = || if let Some(io) = io { io.relinquish().unwrap() };
+
+    let deadline = clock::get_ms() + LOOPBACK_UP_TIMEOUT_MS;
+    while !rtio_mgt::drtio::link_rx_up(linkno) {
+        if clock::get_ms() > deadline {
+            return Err("link does not come up in loopback")
+        }
+        wait();
+    }
+
+    drtioaux::reset(linkno);
+    drtioaux::send(linkno, &drtioaux::Packet::EchoRequest)
+        .map_err(|_| "cannot send aux packet")?;
+    let deadline = clock::get_ms() + LOOPBACK_ECHO_TIMEOUT_MS;
+    loop {
+        match drtioaux::recv(linkno) {
+            Ok(Some(drtioaux::Packet::EchoRequest)) => return Ok(()),
+            Ok(Some(_)) => return Err("unexpected aux packet in loopback"),
+            Ok(None) => (),
+            Err(_) => return Err("corrupted aux packet in loopback")
+        }
+        if clock::get_ms() > deadline {
+            return Err("aux packet not received in loopback")
+        }
+        wait();
+    }
+}
@@ -0,0 +1,148 @@
+use alloc::{Vec, String};
+use board_misoc::{config, i2c};
+use board_misoc::ina2xx::{Ina2xx, Kind};
+use sched::Io;
+use mgmt_proto::PowerRail;
+use nrt_bus;
+use status_led::{self, Alarm};
+
+struct Rail {
+    name:           String,
+    monitor:        Ina2xx,
+    nominal_mv:     u32,
+    reading:        Option<(u32, i32)>,
+    min_voltage_mv: u32,
+    brownouts:      u32,
+    in_range:       bool
+}
+
+static mut RAILS: Option<Vec<Rail>> = None;
+
+// Parses "name:port:address:kind:shunt_mohm:nominal_mv", where port is the
+// name of a port of the I2C switches or "-", and kind is "ina219" or "ina226".
+fn parse_rail(s: &str) -> Option<Rail> {
+    let fields: Vec<&str> = s.trim().split(':').collect();
+    if fields.len() != 6 {
+        return None
+    }
+    let port = match fields[1] {
+        "-" => None,
+        name => Some(i2c::kasli_port(name)?)
+    };
+    let address = if fields[2].starts_with("0x") {
+        u8::from_str_radix(&fields[2][2..], 16).ok()?
+    } else {
+        fields[2].parse().ok()?
+    };
+    let kind = match fields[3] {
+        "ina219" => Kind::Ina219,
+        "ina226" => Kind::Ina226,
+        _ => return None
+    };
+    let shunt_mohm = fields[4].parse().ok()?;
+    if shunt_mohm == 0 {
+        return None
+    }
+    Some(Rail {
+        name: String::from(fields[0]),
+        monitor: Ina2xx { kind: kind, port: port, address: address, shunt_mohm: shunt_mohm },
+        nominal_mv: fields[5].parse().ok()?,
+        reading: None,
+        min_voltage_mv: u32::max_value(),
+        brownouts: 0,
+        in_range: true
+    })
+}
+
+/// Last readings of the monitored power rails, for the health snapshot.
+pub fn rails() -> Vec<PowerRail> {
+    let rails = unsafe { RAILS.as_ref() };
+    rails.map_or(Vec::new(), |rails| rails.iter().map(|rail| PowerRail {
+        name: rail.name.clone(),
+        reading: rail.reading,
+        min_voltage_mv: if rail.min_voltage_mv == u32::max_value() {
+            0
+        } else {
+            rail.min_voltage_mv
+        },
+        brownouts: rail.brownouts
+    }).collect())
+}
+
+pub fn thread(io: Io) {
+    let rails: Vec<Rail> = config::read_str("power_monitors", |r| match r {
+        Ok(s) => s.split(',').filter_map(|rail| {
+            let parsed = parse_rail(rail);
+            if parsed.is_none() {
+                error!("invalid power monitor description: {}", rail);
+            }
+            parsed
+        }).collect(),
+        Err(_) => Vec::new()
+    });
+    if rails.is_empty() {
+        return
+    }
+    let tolerance = config::read_str("power_rail_tolerance_pct",
+        |r| r.ok().and_then(|s| s.parse().ok())).unwrap_or(5);
+
+    for rail in rails.iter() {
+        let undervoltage_mv = rail.nominal_mv * (100 - tolerance) / 100;
+        match rail.monitor.init(Some(undervoltage_mv)) {
+            Ok(()) => info!("monitoring power rail {} ({:?} at 0x{:02x})",
+                            rail.name, rail.monitor.kind, rail.monitor.address),
+            Err(e) => error!("cannot initialize the monitor of power rail {}: {}", rail.name, e)
+        }
+    }
+    unsafe { RAILS = Some(rails) }
+
+    loop {
+        io.sleep(1000).unwrap();
+        let rails = unsafe { RAILS.as_mut().unwrap() };
+        // INA226 monitors latch the brownouts that happen while a kernel
+        // holds the bus.
+        let result = nrt_bus::with_i2c(&io, nrt_bus::MAIN_BUS, || {
+            for rail in rails.iter_mut() {
+                match rail.monitor.measure() {
+                    Ok(measurement) => {
+                        let voltage_mv = measurement.voltage_mv;
+                        rail.reading = Some((voltage_mv, measurement.current_ua));
+                        if voltage_mv < rail.min_voltage_mv {
+                            rail.min_voltage_mv = voltage_mv;
+                        }
+                        let deviation = (voltage_mv as i32 - rail.nominal_mv as i32).abs() as u32;
+                        let in_range = deviation * 100 <= rail.nominal_mv * tolerance;
+                        if in_range != rail.in_range {
+                            if in_range {
+                                info!("power rail {} back in range: {} mV", rail.name, voltage_mv)
+                            } else {
+                                warn!("power rail {} out of range: {} mV, {} mA (nominal {} mV)",
+                                      rail.name, voltage_mv, measurement.current_ua / 1000,
+                                      rail.nominal_mv)
+                            }
+                            rail.in_range = in_range;
+                        }
+                    }
+                    Err(e) => {
+                        if rail.reading.is_some() {
+                            error!("cannot read the monitor of power rail {}: {}", rail.name, e);
+                        }
+                        rail.reading = None;
+                    }
+                }
+                match rail.monitor.undervoltage() {
+                    Ok(true) => {
+                        rail.brownouts += 1;
+                        warn!("brownout on power rail {}: below {}% of {} mV since the last reading",
+                              rail.name, 100 - tolerance, rail.nominal_mv);
+                    }
+                    Ok(false) | Err(_) => ()
+                }
+            }
+        });
+        if result.is_err() {
+            continue
+        }
+        status_led::set_alarm(Alarm::PowerRail, rails.iter().any(|rail| !rail.in_range));
+    }
+}
@@ -86,6 +86,72 @@ impl Profile {
     }
 }
 
+// Profile of the kernel CPU, built from program counter samples that
+// ksupport takes with the OR1K tick timer and sends over the mailbox.
+static mut KERNEL_PROFILE: Option<(u32, Profile)> = None;
+
+pub fn start_kernel(interval_us: u64, hits_size: usize, edges_size: usize) -> Result<(), ()> {
+    use board_misoc::csr;
+
+    let interval_cycles = csr::CONFIG_CLOCK_FREQUENCY as u64 * interval_us / 1_000_000;
+    if interval_cycles == 0 || interval_cycles > 0x0fff_ffff {
+        error!("kernel sampling interval out of range");
+        return Err(())
+    }
+
+    let profile = Profile::new(hits_size, edges_size);
+    info!("sampling kernels at {}us interval using {} heap bytes",
+          interval_us, profile.overhead());
+    unsafe { KERNEL_PROFILE = Some((interval_cycles as u32, profile)) }
+    Ok(())
+}
+
+pub fn stop_kernel() {
+    unsafe {
+        if KERNEL_PROFILE.take().is_some() {
+            info!("stopped sampling kernels");
+        }
+    }
+}
+
+/// Tick timer period for newly started kernels, or 0 if kernels are not sampled.
+pub fn kernel_interval_cycles() -> u32 {
+    unsafe { KERNEL_PROFILE.as_ref().map_or(0, |&(interval_cycles, _)| interval_cycles) }
+}
+
+pub fn record_kernel(samples: &[(usize, usize)], dropped: u32) {
+    let result = unsafe {
+        match KERNEL_PROFILE {
+            None => return,
+            Some((_, ref mut profile)) => {
+                samples.iter().fold(Ok(()), |result, &(pc, caller)| {
+                    result
+                        .and_then(|()| profile.record_hit(Address::new(pc)))
+                        .and_then(|()| {
+                            if caller != 0 && profile.edges.len() > 0 {
+                                profile.record_edge(Address::new(caller), Address::new(pc))
+                            } else {
+                                Ok(())
+                            }
+                        })
+                })
+            }
+        }
+    };
+
+    if dropped > 0 {
+        debug!("{} kernel samples dropped", dropped);
+    }
+    if result.is_err() {
+        warn!("out of space for kernel samples");
+        stop_kernel();
+    }
+}
+
+pub fn with_kernel_profile<F: FnOnce(Option<&mut Profile>) -> R, R>(f: F) -> R {
+    unsafe { f(KERNEL_PROFILE.as_mut().map(|&mut (_, ref mut profile)| profile)) }
+}
+
 #[cfg(has_timer1)]
 mod imp {
     use unwind_backtrace::backtrace;
@@ -0,0 +1,111 @@
+use board_misoc::{clock, config, i2c};
+use board_misoc::rtc::{Rtc, Kind};
+use sched::Io;
+use nrt_bus;
+
+// Battery-backed real-time clock of the hardware variants that carry one,
+// described by the `rtc` config key as "port:address:kind", where port is
+// the name of a port of the I2C switches or "-", and kind is "ds1307" or
+// "ds3231". At boot, it sets the wall clock used for the timestamps of the
+// log, and thus of the log saved with a panic, so that they are dated even
+// without a network or an SNTP server. Once SNTP synchronizes the wall
+// clock, the RTC is set from it, at most every `UPDATE_INTERVAL_MS`.
+
+const UPDATE_INTERVAL_MS: u64 = 3_600_000;
+
+static mut RTC: Option<Rtc> = None;
+static mut UPDATE_PENDING: bool = false;
+
+fn parse(s: &str) -> Option<Rtc> {
+    let mut fields = s.trim().split(':');
+    let port = match fields.next()? {
+        "-" => None,
+        name => Some(i2c::kasli_port(name)?)
+    };
+    let address = fields.next()?;
+    let address = if address.starts_with("0x") {
+        u8::from_str_radix(&address[2..], 16).ok()?
+    } else {
+        address.parse().ok()?
+    };
+    let kind = match fields.next()? {
+        "ds1307" => Kind::Ds1307,
+        "ds3231" => Kind::Ds3231,
+        _ => return None
+    };
+    if fields.next().is_some() || address >= 0x80 {
+        return None
+    }
+    Some(Rtc { kind: kind, port: port, address: address })
+}
+
+/// Sets the wall clock from the RTC described by the `rtc` config key, if
+/// any. Must be called once, after the I2C bus is initialized.
+pub fn init() {
+    let rtc = match config::read_str("rtc", |r| r.map(parse)) {
+        Ok(Some(rtc)) => rtc,
+        Ok(None) => {
+            error!("invalid RTC description in the `rtc` config key");
+            return
+        }
+        Err(_) => return
+    };
+    unsafe { RTC = Some(rtc) }
+    match rtc.read_unix_s() {
+        Ok(Some(unix_s)) => {
+            clock::set_unix_us(unix_s * 1_000_000);
+            info!("wall clock set from the RTC");
+        }
+        Ok(None) =>
+            warn!("the RTC has not kept the time, e.g. its battery is flat; \
+                   set it with `artiq_coremgmt rtc set`"),
+        Err(e) => error!("cannot read the RTC: {}", e)
+    }
+}
+
+/// Called when SNTP synchronizes the wall clock, to set the RTC from it.
+pub fn synchronized() {
+    unsafe { UPDATE_PENDING = true }
+}
+
+/// Whether the `rtc` config key describes an RTC.
+pub fn configured() -> bool {
+    unsafe { RTC.is_some() }
+}
+
+fn rtc() -> Result<Rtc, &'static str> {
+    unsafe { RTC }.ok_or("no RTC configured")
+}
+
+/// Reads the RTC, in seconds since the Unix epoch; see `Rtc::read_unix_s`.
+pub fn read(io: &Io) -> Result<Option<u64>, &'static str> {
+    let rtc = rtc()?;
+    nrt_bus::with_i2c(io, nrt_bus::MAIN_BUS, || rtc.read_unix_s())?
+}
+
+/// Sets the RTC, and the wall clock with it.
+pub fn set(io: &Io, unix_s: u64) -> Result<(), &'static str> {
+    let rtc = rtc()?;
+    nrt_bus::with_i2c(io, nrt_bus::MAIN_BUS, || rtc.write_unix_s(unix_s))??;
+    clock::set_unix_us(unix_s * 1_000_000);
+    Ok(())
+}
+
+pub fn thread(io: Io) {
+    let rtc = match unsafe { RTC } {
+        Some(rtc) => rtc,
+        None => return
+    };
+    loop {
+        io.until(|| unsafe { UPDATE_PENDING }).unwrap();
+        unsafe { UPDATE_PENDING = false }
+        if let Some(unix_us) = clock::get_unix_us() {
+            let unix_s = unix_us / 1_000_000;
+            match nrt_bus::with_i2c(&io, nrt_bus::MAIN_BUS, || rtc.write_unix_s(unix_s)) {
+                Ok(Ok(())) => debug!("RTC set from the wall clock"),
+                Ok(Err(e)) | Err(e) => warn!("cannot set the RTC: {}", e)
+            }
+        }
+        io.sleep(UPDATE_INTERVAL_MS).unwrap();
+    }
+}
@@ -1,8 +1,15 @@
+use alloc::String;
 use board_misoc::config;
 #[cfg(si5324_as_synthesizer)]
 use board_artiq::si5324;
-#[cfg(has_drtio)]
 use board_misoc::{csr, clock};
+#[cfg(si5324_as_synthesizer)]
+use sched::Io;
+#[cfg(si5324_as_synthesizer)]
+use kernel;
+#[cfg(si5324_as_synthesizer)]
+use nrt_bus;
+use alerts::{self, Severity, Source};
 
 #[derive(Debug)]
 pub enum RtioClock {
@@ -66,7 +73,7 @@ pub mod crg {
 }
 
 #[cfg(si5324_as_synthesizer)]
-fn setup_si5324_as_synthesizer() {
+fn setup_si5324_as_synthesizer() -> Result<(), &'static str> {
     // 125 MHz output from 10 MHz CLKINx reference, 504 Hz BW
     #[cfg(all(rtio_frequency = "125.0", si5324_ext_ref, ext_ref_frequency = "10.0"))]
     const SI5324_SETTINGS: si5324::FrequencySettings
@@ -153,22 +160,48 @@ fn setup_si5324_as_synthesizer() {
     let si5324_ref_input = si5324::Input::Ckin2;
     #[cfg(soc_platform = "metlino")]
     let si5324_ref_input = si5324::Input::Ckin2;
-    si5324::setup(&SI5324_SETTINGS, si5324_ref_input).expect("cannot initialize Si5324");
+    si5324::setup(&SI5324_SETTINGS, si5324_ref_input)
+}
+
+#[cfg(si5324_as_synthesizer)]
+fn si5324_ext_input() -> si5324::Input {
+    #[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
+    let si5324_ext_input = si5324::Input::Ckin1;
+    #[cfg(all(soc_platform = "kasli", not(hw_rev = "v2.0")))]
+    let si5324_ext_input = si5324::Input::Ckin2;
+    #[cfg(soc_platform = "metlino")]
+    let si5324_ext_input = si5324::Input::Ckin2;
+    si5324_ext_input
+}
+
+// Programs the Si5324 from the register profile saved in the
+// `si5324_profile` config key, if any, e.g. to reproduce a configuration
+// tuned in the lab. See `artiq_coremgmt clockregs`.
+#[cfg(si5324_as_synthesizer)]
+fn restore_si5324_profile() {
+    config::read("si5324_profile", |result| match result {
+        Ok(profile) if profile.len() > 0 => match si5324::restore(profile) {
+            Ok(()) => info!("Si5324 programmed from the si5324_profile config key"),
+            Err(e) => {
+                error!("cannot apply the si5324_profile config key: {}", e);
+                alerts::push(Severity::Warning, Source::Clock,
+                    format!("cannot apply the si5324_profile config key: {}", e));
+            }
+        },
+        _ => ()
+    })
 }
 
 pub fn init() {
     #[cfg(si5324_as_synthesizer)]
     {
-        #[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
-        let si5324_ext_input = si5324::Input::Ckin1;
-        #[cfg(all(soc_platform = "kasli", not(hw_rev = "v2.0")))]
-        let si5324_ext_input = si5324::Input::Ckin2;
-        #[cfg(soc_platform = "metlino")]
-        let si5324_ext_input = si5324::Input::Ckin2;
         match get_rtio_clock_cfg() {
-            RtioClock::Internal => setup_si5324_as_synthesizer(),
-            RtioClock::External => si5324::bypass(si5324_ext_input).expect("cannot bypass Si5324")
+            RtioClock::Internal =>
+                setup_si5324_as_synthesizer().expect("cannot initialize Si5324"),
+            RtioClock::External =>
+                si5324::bypass(si5324_ext_input()).expect("cannot bypass Si5324")
         }
+        restore_si5324_profile();
     }
 
     #[cfg(has_drtio)]
@@ -190,6 +223,175 @@ pub fn init() {
         let result = crg::init();
         if !result {
             error!("RTIO clock failed");
+            alerts::push(Severity::Critical, Source::Clock, String::from("RTIO clock failed"));
+        }
+    }
+
+    // wait for the frequency counter to complete a full measurement
+    clock::spin_us(2 * (1 << 23) * 1_000_000 / csr::CONFIG_CLOCK_FREQUENCY as u64);
+    let frequency = measured_frequency();
+    info!("RTIO clock frequency: {}.{:03}MHz", frequency / 1_000_000, frequency / 1_000 % 1_000);
+}
+
+/// RTIO clock frequency in Hz, as counted against the system clock during
+/// the last measurement period (about 67ms at 125MHz).
+pub fn measured_frequency() -> u32 {
+    let count = unsafe { csr::rtio_core::rtio_clk_count_read() } as u64;
+    (count * 4 * csr::CONFIG_CLOCK_FREQUENCY as u64 >> 23) as u32
+}
+
+/// Latches the RTIO counter, and returns it with the uptime and wall clock
+/// time at which it was latched, in microseconds, to correlate RTIO
+/// timestamps with the system time.
+pub fn counter_correlation() -> (u64, u64, Option<u64>) {
+    let before = clock::get_us();
+    let counter = unsafe {
+        csr::rtio_core::counter_update_write(1);
+        csr::rtio_core::counter_read()
+    };
+    let after = clock::get_us();
+    let uptime_us = before + (after - before) / 2;
+    let unix_us = clock::get_unix_us().map(|time| time - (clock::get_us() - uptime_us));
+    (counter, uptime_us, unix_us)
+}
+
+#[cfg(si5324_as_synthesizer)]
+static mut SI5324_STATUS: Option<si5324::Status> = None;
+
+/// Most recent Si5324 status seen by the monitor thread.
+#[cfg(si5324_as_synthesizer)]
+pub fn si5324_status() -> Option<si5324::Status> {
+    unsafe { SI5324_STATUS }
+}
+
+#[cfg(si5324_as_synthesizer)]
+fn log_si5324_transition(previous: &si5324::Status, current: &si5324::Status) {
+    macro_rules! transition {
+        ($field:ident, $asserted:tt, $cleared:tt) => {
+            if previous.$field != current.$field {
+                if current.$field {
+                    warn!($asserted);
+                    alerts::push(Severity::Warning, Source::Clock, String::from($asserted))
+                } else {
+                    info!($cleared);
+                    alerts::push(Severity::Info, Source::Clock, String::from($cleared))
+                }
+            }
+        }
+    }
+    transition!(losx, "Si5324 lost its crystal reference", "Si5324 crystal reference recovered");
+    transition!(los1, "Si5324 lost the CKIN1 signal", "Si5324 CKIN1 signal recovered");
+    transition!(los2, "Si5324 lost the CKIN2 signal", "Si5324 CKIN2 signal recovered");
+    if !current.bypass {
+        transition!(lol, "Si5324 lost lock", "Si5324 regained lock");
+        transition!(holdover, "Si5324 entered holdover", "Si5324 left holdover");
+    }
+}
+
+#[cfg(si5324_as_synthesizer)]
+static mut CLOCK_FALLBACK: bool = false;
+
+/// Whether the RTIO clock was switched to the internal reference after the
+/// external clock was lost.
+#[cfg(si5324_as_synthesizer)]
+pub fn clock_fallback_active() -> bool {
+    unsafe { CLOCK_FALLBACK }
+}
+
+#[cfg(not(si5324_as_synthesizer))]
+pub fn clock_fallback_active() -> bool {
+    false
+}
+
+#[cfg(si5324_as_synthesizer)]
+fn clock_fallback_enabled() -> bool {
+    config::read("rtio_clock_fallback", |result| {
+        match result {
+            Ok(b"1") => true,
+            _ => false
+        }
+    })
+}
+
+#[cfg(si5324_as_synthesizer)]
+fn external_clock_lost(status: &si5324::Status) -> bool {
+    status.bypass && match si5324_ext_input() {
+        si5324::Input::Ckin1 => status.los1,
+        si5324::Input::Ckin2 => status.los2
+    }
+}
+
+#[cfg(si5324_as_synthesizer)]
+fn fall_back_to_internal_clock(io: &Io) -> Result<(), &'static str> {
+    if cfg!(si5324_ext_ref) {
+        return Err("the Si5324 synthesizer is referenced to the external clock")
+    }
+    nrt_bus::with_i2c(io, nrt_bus::MAIN_BUS, setup_si5324_as_synthesizer)??;
+    #[cfg(has_rtio_crg)]
+    {
+        #[cfg(has_rtio_clock_switch)]
+        let result = crg::init(RtioClock::Internal);
+        #[cfg(not(has_rtio_clock_switch))]
+        let result = crg::init();
+        if !result {
+            return Err("RTIO clock failed")
+        }
+    }
+    unsafe {
+        csr::rtio_core::reset_phy_write(1);
+    }
+    Ok(())
+}
+
+#[cfg(si5324_as_synthesizer)]
+pub fn si5324_monitor_thread(io: Io) {
+    let mut fallback_failed = false;
+    let mut fallback_deferred = false;
+    loop {
+        match nrt_bus::with_i2c(&io, nrt_bus::MAIN_BUS, si5324::status)
+                .and_then(|result| result) {
+            Ok(status) => {
+                match si5324_status() {
+                    Some(previous) => log_si5324_transition(&previous, &status),
+                    None => info!("Si5324 status: {:?}", status)
+                }
+                unsafe { SI5324_STATUS = Some(status) }
+
+                if !external_clock_lost(&status) {
+                    fallback_failed = false;
+                    fallback_deferred = false;
+                } else if !fallback_failed && !clock_fallback_active() &&
+                        clock_fallback_enabled() && kernel::running() {
+                    // Resetting the clock and the RTIO PHYs under a running
+                    // kernel would corrupt its timeline.
+                    if !fallback_deferred {
+                        error!("external RTIO clock lost, falling back once the kernel stops");
+                        alerts::push(Severity::Critical, Source::Clock,
+                            String::from("external RTIO clock lost, fallback deferred until the kernel stops"));
+                        fallback_deferred = true;
+                    }
+                } else if !fallback_failed && !clock_fallback_active() &&
+                        clock_fallback_enabled() {
+                    error!("!!! external RTIO clock lost, falling back to internal clock !!!");
+                    match fall_back_to_internal_clock(&io) {
+                        Ok(()) => {
+                            unsafe { CLOCK_FALLBACK = true }
+                            error!("!!! RTIO is now running from the internal clock; \
+                                    timestamps are no longer referenced to the external clock !!!");
+                            alerts::push(Severity::Critical, Source::Clock,
+                                String::from("external RTIO clock lost, running from the internal clock"));
+                        }
+                        Err(e) => {
+                            error!("clock fallback failed: {}", e);
+                            alerts::push(Severity::Critical, Source::Clock,
+                                format!("external RTIO clock lost, fallback failed: {}", e));
+                            fallback_failed = true;
+                        }
+                    }
+                }
+            }
+            Err(e) => error!("failed to read Si5324 status: {}", e)
         }
+        io.sleep(1000).unwrap();
     }
 }
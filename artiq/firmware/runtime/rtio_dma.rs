@@ -1,20 +1,208 @@
 use core::mem;
 use alloc::{Vec, String, BTreeMap};
+use proto_artiq::kernel_proto::DmaSegmentError;
 
 const ALIGNMENT: usize = 64;
 
+// A position in a trace named while recording it. `offset` is in bytes into
+// the full records of the trace, i.e. as recorded.
+#[derive(Debug, Clone, Copy)]
+struct Marker {
+    offset: usize,
+    timestamp: u64
+}
+
 #[derive(Debug)]
 struct Entry {
     trace: Vec<u8>,
+    // The lengths of the records as recorded, which compact records and
+    // trimmed data do not keep.
+    lengths: Vec<u8>,
     padding_len: usize,
-    duration: u64
+    duration: u64,
+    markers: BTreeMap<String, Marker>
+}
+
+impl Entry {
+    // The records of the trace, without the alignment padding and the end
+    // marker.
+    fn records(&self) -> &[u8] {
+        &self.trace[self.padding_len..self.trace.len() - 1]
+    }
+
+    // The records of the trace as recorded.
+    fn full_records(&self) -> Vec<u8> {
+        decompress(self.records(), &self.lengths)
+    }
+}
+
+// Records are encoded as in gateware/rtio/dma.py. Full records hold their
+// length, channel (3 bytes), timestamp (8 bytes), address and data, with
+// multi-byte fields little-endian. Traces are stored with compact records
+// wherever possible, which take their channel from a dictionary of channel
+// slots and their timestamp relative to the previous record: length with
+// the MSB set, slot, timestamp delta (2 bytes), address and data. Both omit
+// the most significant bytes of their data when zero.
+const HEADER_LENGTH: usize = 13;
+const COMPACT_HEADER_LENGTH: usize = 5;
+const COMPACT_FLAG: u8 = 0x80;
+const CHANNEL_SLOTS: usize = 16;
+
+fn channel_slot(channel: u32) -> usize {
+    (channel ^ (channel >> 16)) as usize & (CHANNEL_SLOTS - 1)
+}
+
+fn read_le(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+fn write_le(bytes: &mut [u8], value: u64) {
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (value >> (8 * i)) as u8;
+    }
+}
+
+fn trim_data(data: &[u8]) -> &[u8] {
+    let mut length = data.len();
+    while length > 0 && data[length - 1] == 0 {
+        length -= 1
+    }
+    &data[..length]
+}
+
+// Compacts a sequence of full records in place and returns their lengths.
+// No record grows, so the output never overtakes the input.
+fn compress(records: &mut Vec<u8>) -> Vec<u8> {
+    let mut lengths = Vec::new();
+    let mut channels = [None; CHANNEL_SLOTS];
+    let mut last_timestamp = None;
+    let mut read = 0;
+    let mut written = 0;
+    while read < records.len() {
+        let mut record = [0; HEADER_LENGTH + 64];
+        let length = records[read] as usize;
+        record[..length].copy_from_slice(&records[read..read + length]);
+        read += length;
+        lengths.push(length as u8);
+
+        let channel = read_le(&record[1..4]) as u32;
+        let timestamp = read_le(&record[4..12]);
+        let data = trim_data(&record[HEADER_LENGTH..length]);
+        let slot = channel_slot(channel);
+        let delta = match last_timestamp {
+            Some(last) if timestamp >= last && timestamp - last <= 0xffff => Some(timestamp - last),
+            _ => None
+        };
+        let header_length = match delta {
+            Some(delta) if channels[slot] == Some(channel) => {
+                records[written] = COMPACT_FLAG | (COMPACT_HEADER_LENGTH + data.len()) as u8;
+                records[written + 1] = slot as u8;
+                write_le(&mut records[written + 2..written + 4], delta);
+                records[written + 4] = record[12];
+                COMPACT_HEADER_LENGTH
+            }
+            _ => {
+                records[written] = (HEADER_LENGTH + data.len()) as u8;
+                records[written + 1..written + HEADER_LENGTH].copy_from_slice(&record[1..HEADER_LENGTH]);
+                channels[slot] = Some(channel);
+                HEADER_LENGTH
+            }
+        };
+        written += header_length;
+        records[written..written + data.len()].copy_from_slice(data);
+        written += data.len();
+        last_timestamp = Some(timestamp);
+    }
+    records.truncate(written);
+    lengths
+}
+
+// Expands a sequence of records into full records of the given lengths,
+// restoring the data bytes trimmed by `compress`.
+fn decompress(records: &[u8], lengths: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(records.len());
+    let mut channels = [0; CHANNEL_SLOTS];
+    let mut last_timestamp: u64 = 0;
+    let mut position = 0;
+    for &full_length in lengths.iter() {
+        let length = (records[position] & !COMPACT_FLAG) as usize;
+        let record = &records[position..position + length];
+        position += length;
+
+        let (channel, timestamp, address, data) = if record[0] & COMPACT_FLAG != 0 {
+            let slot = record[1] as usize & (CHANNEL_SLOTS - 1);
+            (channels[slot], last_timestamp.wrapping_add(read_le(&record[2..4])),
+             record[4], &record[COMPACT_HEADER_LENGTH..])
+        } else {
+            let channel = read_le(&record[1..4]) as u32;
+            channels[channel_slot(channel)] = channel;
+            (channel, read_le(&record[4..12]), record[12], &record[HEADER_LENGTH..])
+        };
+        last_timestamp = timestamp;
+
+        let mut header = [0; HEADER_LENGTH];
+        header[0] = full_length;
+        write_le(&mut header[1..4], channel as u64);
+        write_le(&mut header[4..12], timestamp);
+        header[12] = address;
+        output.extend_from_slice(&header);
+        output.extend_from_slice(data);
+        for _ in HEADER_LENGTH + data.len()..full_length as usize {
+            output.push(0)
+        }
+    }
+    output
+}
+
+// Adds `offset` to the timestamps of a sequence of full records.
+fn shift_records(records: &mut [u8], offset: u64) {
+    let mut position = 0;
+    while position < records.len() {
+        let length = records[position] as usize;
+        let timestamp = read_le(&records[position + 4..position + 12]);
+        write_le(&mut records[position + 4..position + 12], timestamp.wrapping_add(offset));
+        position += length;
+    }
+}
+
+// Takes the full records of a trace, without the end marker, and stores
+// them compacted and aligned for the gateware.
+fn make_entry(mut trace: Vec<u8>, duration: u64, markers: BTreeMap<String, Marker>) -> Entry {
+    let mut lengths = compress(&mut trace);
+    lengths.shrink_to_fit();
+    trace.shrink_to_fit();
+    trace.push(0);
+    let data_len = trace.len();
+
+    // Realign.
+    trace.reserve(ALIGNMENT - 1);
+    let padding = ALIGNMENT - trace.as_ptr() as usize % ALIGNMENT;
+    let padding = if padding == ALIGNMENT { 0 } else { padding };
+    for _ in 0..padding {
+        // Vec guarantees that this will not reallocate
+        trace.push(0)
+    }
+    for i in 1..data_len + 1 {
+        trace[data_len + padding - i] = trace[data_len - i]
+    }
+
+    Entry {
+        trace: trace,
+        lengths: lengths,
+        padding_len: padding,
+        duration: duration,
+        markers: markers
+    }
 }
 
 #[derive(Debug)]
 pub struct Manager {
     entries: BTreeMap<String, Entry>,
     recording_name: String,
-    recording_trace: Vec<u8>
+    recording_trace: Vec<u8>,
+    recording_markers: BTreeMap<String, Marker>,
+    // The last segment retrieved by the kernel.
+    segment: Option<Entry>
 }
 
 impl Manager {
@@ -23,12 +211,15 @@ impl Manager {
             entries: BTreeMap::new(),
             recording_name: String::new(),
             recording_trace: Vec::new(),
+            recording_markers: BTreeMap::new(),
+            segment: None
         }
     }
 
     pub fn record_start(&mut self, name: &str) {
         self.recording_name = String::from(name);
         self.recording_trace = Vec::new();
+        self.recording_markers = BTreeMap::new();
 
         // or we could needlessly OOM replacing a large trace
         self.entries.remove(name);
@@ -38,31 +229,123 @@ impl Manager {
         self.recording_trace.extend_from_slice(data)
     }
 
+    /// Names the current position of the trace being recorded, which is
+    /// at `timestamp` on its timeline.
+    pub fn record_mark(&mut self, name: &str, timestamp: u64) {
+        self.recording_markers.insert(String::from(name), Marker {
+            offset: self.recording_trace.len(),
+            timestamp: timestamp
+        });
+    }
+
     pub fn record_stop(&mut self, duration: u64) {
         let mut trace = Vec::new();
         mem::swap(&mut self.recording_trace, &mut trace);
-        trace.push(0);
-        let data_len = trace.len();
-
-        // Realign.
-        trace.reserve(ALIGNMENT - 1);
-        let padding = ALIGNMENT - trace.as_ptr() as usize % ALIGNMENT;
-        let padding = if padding == ALIGNMENT { 0 } else { padding };
-        for _ in 0..padding {
-            // Vec guarantees that this will not reallocate
-            trace.push(0)
-        }
-        for i in 1..data_len + 1 {
-            trace[data_len + padding - i] = trace[data_len - i]
-        }
-
         let mut name = String::new();
         mem::swap(&mut self.recording_name, &mut name);
-        self.entries.insert(name, Entry {
-            trace: trace,
-            padding_len: padding,
-            duration: duration
-        });
+        let mut markers = BTreeMap::new();
+        mem::swap(&mut self.recording_markers, &mut markers);
+        self.entries.insert(name, make_entry(trace, duration, markers));
+    }
+
+    /// Appends the trace called `trace` to the trace called `name`, shifted
+    /// to start when the latter ends, or replaces `name` with a copy of it if
+    /// `first`. Returns false if `trace` does not exist.
+    ///
+    /// The markers of both traces are kept, those of `trace` moved along with
+    /// its records, unless `name` already has a marker of the same name.
+    pub fn chain(&mut self, name: &str, trace: &str, first: bool) -> bool {
+        let (mut records, duration, mut markers) = match self.entries.get(name) {
+            Some(entry) if !first =>
+                (entry.full_records(), entry.duration, entry.markers.clone()),
+            _ => (Vec::new(), 0, BTreeMap::new())
+        };
+        let appended_duration = match self.entries.get(trace) {
+            Some(entry) => {
+                let start = records.len();
+                records.extend_from_slice(&entry.full_records());
+                shift_records(&mut records[start..], duration);
+                for (marker_name, marker) in entry.markers.iter() {
+                    if !markers.contains_key(marker_name) {
+                        markers.insert(marker_name.clone(), Marker {
+                            offset: start + marker.offset,
+                            timestamp: marker.timestamp + duration
+                        });
+                    }
+                }
+                entry.duration
+            }
+            None => return false
+        };
+        self.entries.insert(String::from(name),
+                            make_entry(records, duration + appended_duration, markers));
+        true
+    }
+
+    /// Extracts the part of the trace called `name` that starts at the
+    /// marker called `marker`, or if it is empty at the record `offset`
+    /// bytes into the full records of the trace, and lasts `duration`, or
+    /// until the end of the trace if `duration` is negative. It consists
+    /// of the records from there on whose timestamps are within that time,
+    /// shifted so that it starts at zero, and remains available until the
+    /// next call.
+    pub fn segment(&mut self, name: &str, marker: &str, offset: usize,
+                   duration: i64) -> Result<(&[u8], u64), DmaSegmentError> {
+        let segment = {
+            let entry = match self.entries.get(name) {
+                Some(entry) => entry,
+                None => return Err(DmaSegmentError::TraceNotFound)
+            };
+            let records = entry.full_records();
+
+            let start = if marker.is_empty() {
+                // Only record boundaries are valid offsets.
+                let mut position = 0;
+                while position < offset && position < records.len() {
+                    position += records[position] as usize;
+                }
+                if position != offset {
+                    return Err(DmaSegmentError::InvalidOffset)
+                }
+                let timestamp = if offset < records.len() {
+                    read_le(&records[offset + 4..offset + 12])
+                } else {
+                    entry.duration
+                };
+                Marker { offset: offset, timestamp: timestamp }
+            } else {
+                match entry.markers.get(marker) {
+                    Some(&marker) => marker,
+                    None => return Err(DmaSegmentError::MarkerNotFound)
+                }
+            };
+
+            let end = if duration < 0 {
+                entry.duration
+            } else {
+                start.timestamp + duration as u64
+            };
+            if end < start.timestamp || end > entry.duration {
+                return Err(DmaSegmentError::InvalidDuration)
+            }
+
+            let mut segment = Vec::new();
+            let mut position = start.offset;
+            while position < records.len() {
+                let length = records[position] as usize;
+                let timestamp = read_le(&records[position + 4..position + 12]);
+                if timestamp >= start.timestamp && timestamp < end {
+                    segment.extend_from_slice(&records[position..position + length]);
+                }
+                position += length;
+            }
+            shift_records(&mut segment, start.timestamp.wrapping_neg());
+            make_entry(segment, end - start.timestamp, BTreeMap::new())
+        };
+
+        self.segment = Some(segment);
+        let segment = self.segment.as_ref().unwrap();
+        Ok((&segment.trace[segment.padding_len..], segment.duration))
     }
 
     pub fn erase(&mut self, name: &str) {
@@ -77,3 +360,67 @@ impl Manager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::Vec;
+    use super::*;
+
+    fn record(channel: u32, timestamp: u64, words: &[u32]) -> Vec<u8> {
+        let mut record = vec![0; HEADER_LENGTH + 4 * words.len()];
+        record[0] = record.len() as u8;
+        write_le(&mut record[1..4], channel as u64);
+        write_le(&mut record[4..12], timestamp);
+        for (i, &word) in words.iter().enumerate() {
+            write_le(&mut record[HEADER_LENGTH + 4 * i..HEADER_LENGTH + 4 * i + 4],
+                     word as u64);
+        }
+        record
+    }
+
+    fn segment_records(manager: &mut Manager, name: &str, marker: &str, offset: usize,
+                       duration: i64) -> (Vec<u8>, u64) {
+        let duration = manager.segment(name, marker, offset, duration).unwrap().1;
+        (manager.segment.as_ref().unwrap().full_records(), duration)
+    }
+
+    #[test]
+    fn record_mark_segment() {
+        // Records whose data has trailing zero bytes, and is thus trimmed,
+        // before and after the marker.
+        let first = [record(1, 100, &[1, 0]), record(1, 110, &[0]), record(2, 120, &[0x100])];
+        let second = [record(1, 200, &[0, 0]), record(2, 210, &[5]), record(1, 300, &[7, 0, 0])];
+
+        let mut manager = Manager::new();
+        manager.record_start("trace");
+        for record in first.iter() {
+            manager.record_append(record);
+        }
+        manager.record_mark("second", 200);
+        for record in second.iter() {
+            manager.record_append(record);
+        }
+        manager.record_stop(400);
+
+        let mut expected = Vec::new();
+        for (record, &timestamp) in second.iter().zip([0, 10, 100].iter()) {
+            let mut record = record.clone();
+            write_le(&mut record[4..12], timestamp);
+            expected.extend_from_slice(&record);
+        }
+        assert_eq!(segment_records(&mut manager, "trace", "second", 0, -1),
+                   (expected.clone(), 200));
+
+        // The same segment at the offset of the marker as recorded.
+        let offset = first.iter().map(|record| record.len()).sum();
+        assert_eq!(segment_records(&mut manager, "trace", "", offset, -1),
+                   (expected.clone(), 200));
+        assert!(manager.segment("trace", "", offset + 1, -1).is_err());
+
+        // Chaining keeps the marker pointing at the same records.
+        assert!(manager.chain("chained", "trace", true));
+        assert!(manager.chain("chained", "trace", false));
+        let (records, duration) = segment_records(&mut manager, "chained", "second", 0, 200);
+        assert_eq!((records, duration), (expected, 200));
+    }
+}
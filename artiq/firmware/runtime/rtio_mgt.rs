@@ -1,36 +1,273 @@
 use core::cell::RefCell;
+use alloc::{Vec, String, BTreeMap};
+use io::{Cursor, ProtoRead};
 use urc::Urc;
-use board_misoc::csr;
+use board_misoc::{csr, config};
 #[cfg(has_drtio)]
 use board_misoc::clock;
 use board_artiq::drtio_routing;
-use sched::Io;
+use sched::{Io, Priority};
 use sched::Mutex;
+use proto_artiq::mgmt_proto::{SedStats, RtioErrorCounts, SatelliteRtioCounters, DrtioLatency};
+use rtio_clocking;
+use hw_watchdog;
+
+/// Counters of the events that indicate a marginal DRTIO link, since boot.
+/// Errors while the link is being initialized are not counted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkStats {
+    /// Aux packets received corrupted.
+    pub crc_errors: u32,
+    /// Aux transactions that the satellite did not answer in time.
+    pub aux_timeouts: u32,
+    /// Times the link went down after it was initialized.
+    pub link_drops: u32,
+    /// Errors reported by the DRTIO core, e.g. truncated packets.
+    pub protocol_errors: u32,
+    /// RTIO errors reported by the destinations reached through the link.
+    pub remote_rtio_errors: u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtioError {
+    Underflow,
+    Overflow,
+    Unreachable,
+    Collision,
+    Busy,
+    SequenceError
+}
+
+// Counters of the RTIO errors of each destination that had any, so that
+// chronic but non-fatal error sources can be located.
+static mut ERROR_COUNTS: Option<Vec<RtioErrorCounts>> = None;
+// Not cleared with the counters, for the statistics of the kernel runs.
+static mut ERRORS_SINCE_BOOT: u32 = 0;
+
+fn error_counts_mut() -> &'static mut Vec<RtioErrorCounts> {
+    unsafe {
+        if ERROR_COUNTS.is_none() {
+            ERROR_COUNTS = Some(Vec::new())
+        }
+        ERROR_COUNTS.as_mut().unwrap()
+    }
+}
+
+fn destination_counts(destination: u8) -> &'static mut RtioErrorCounts {
+    let counts = error_counts_mut();
+    let index = match counts.iter().position(|c| c.destination == destination) {
+        Some(index) => index,
+        None => {
+            counts.push(RtioErrorCounts {
+                destination:     destination,
+                underflows:      0,
+                overflows:       0,
+                unreachable:     0,
+                collisions:      0,
+                busy_errors:     0,
+                sequence_errors: 0,
+                last_channel:    0,
+                satellite:       SatelliteRtioCounters::default()
+            });
+            counts.len() - 1
+        }
+    };
+    &mut counts[index]
+}
+
+/// Counts an error involving `channel`, whose upper bits are the
+/// destination.
+pub fn count_error(error: RtioError, channel: u32) {
+    let counts = destination_counts((channel >> 16) as u8);
+    {
+        let counter = match error {
+            RtioError::Underflow => &mut counts.underflows,
+            RtioError::Overflow => &mut counts.overflows,
+            RtioError::Unreachable => &mut counts.unreachable,
+            RtioError::Collision => &mut counts.collisions,
+            RtioError::Busy => &mut counts.busy_errors,
+            RtioError::SequenceError => &mut counts.sequence_errors
+        };
+        *counter = counter.saturating_add(1);
+    }
+    counts.last_channel = channel;
+    unsafe { ERRORS_SINCE_BOOT = ERRORS_SINCE_BOOT.wrapping_add(1) }
+}
+
+/// Number of RTIO errors counted since boot, of all destinations.
+pub fn errors_since_boot() -> u32 {
+    unsafe { ERRORS_SINCE_BOOT }
+}
+
+/// Counts the RTIO error that ended a kernel, if `name` is one, using the
+/// channel its message refers to.
+pub fn count_exception(name: &str, message: &str, param: &[i64; 3]) {
+    let error = match name.rsplit('.').next() {
+        Some("RTIOUnderflow") => RtioError::Underflow,
+        Some("RTIOOverflow") => RtioError::Overflow,
+        Some("RTIODestinationUnreachable") => RtioError::Unreachable,
+        _ => return
+    };
+    for (index, &channel) in param.iter().enumerate() {
+        if message.contains(&format!("{{rtio_channel_info:{}}}", index)) {
+            count_error(error, channel as u32);
+            return
+        }
+    }
+}
+
+/// Records the counters read from the satellite of `destination`, which
+/// counts since its boot or the last clear.
+pub fn set_satellite_counters(destination: u8, satellite: SatelliteRtioCounters) {
+    let any = satellite.underflows != 0 || satellite.overflows != 0 ||
+              satellite.buffer_space_timeouts != 0 || satellite.protocol_errors != 0 ||
+              satellite.tsc_resyncs != 0;
+    let known = error_counts_mut().iter().any(|c| c.destination == destination);
+    if any || known {
+        destination_counts(destination).satellite = satellite;
+    }
+}
+
+/// Error counters of the destinations that had errors, by destination.
+pub fn error_counts(clear: bool) -> Vec<RtioErrorCounts> {
+    let counts = error_counts_mut();
+    let mut result = counts.clone();
+    result.sort_by_key(|c| c.destination);
+    if clear {
+        counts.clear();
+    }
+    result
+}
 
 #[cfg(has_drtio)]
 pub mod drtio {
     use super::*;
+    use alloc::{Vec, String};
+    use core::cmp::min;
+    use log;
     use drtioaux;
+    use alerts::{self, Severity, Source};
+    use fault_injection;
+    use board_misoc::sfp;
+    use nrt_bus;
+    use proto_artiq::mgmt_proto::DrtioLinkStats;
+    use proto_artiq::health_record::RecordSet;
+
+    const NO_STATS: LinkStats = LinkStats {
+        crc_errors: 0,
+        aux_timeouts: 0,
+        link_drops: 0,
+        protocol_errors: 0,
+        remote_rtio_errors: 0
+    };
+
+    static mut UP_LINKS: [bool; csr::DRTIO.len()] = [false; csr::DRTIO.len()];
+    static mut LINK_STATS: [LinkStats; csr::DRTIO.len()] = [NO_STATS; csr::DRTIO.len()];
+
+    // With the drtio_retrain_threshold config entry set, links that see that
+    // many errors (corrupted aux packets, aux timeouts and protocol errors)
+    // within the window are retrained by turning their transmitter off, so
+    // that both ends reinitialize the link.
+    const RETRAIN_WINDOW_MS: u64 = 10_000;
+    const RETRAIN_TX_OFF_MS: u64 = 100;
+
+    // Once a link is up, time is synchronized again periodically; the
+    // satellites then only measure the offset of their TSC.
+    const TSC_SYNC_INTERVAL_MS: u64 = 10_000;
+
+    // Satellites keep their log messages until the master pulls them. A
+    // destination whose log was empty is pulled half as often as before, up
+    // to the maximum interval, and again every second once it logs.
+    const LOG_PULL_INTERVAL_MS: u64 = 1_000;
+    const LOG_PULL_MAX_INTERVAL_MS: u64 = 16_000;
+    // Uptime of the next pull and pull interval of each destination, and
+    // the sequence number of the log chunk last received from it, which is
+    // acknowledged in the next request.
+    static mut LOG_PULL_AT_MS: [u64; drtio_routing::DEST_COUNT] = [0; drtio_routing::DEST_COUNT];
+    static mut LOG_PULL_INTERVALS_MS: [u64; drtio_routing::DEST_COUNT] =
+        [LOG_PULL_INTERVAL_MS; drtio_routing::DEST_COUNT];
+    static mut LOG_RECEIVED: [Option<u16>; drtio_routing::DEST_COUNT] = [None; drtio_routing::DEST_COUNT];
+
+    // Round-trip time of each link in picoseconds, measured with echo
+    // requests on the RT link when it came up, which the satellites answer
+    // with a fixed latency. The shortest of the samples is kept, as the
+    // clock domain crossings of the measurement add a cycle at times.
+    static mut LINK_RTT_PS: [Option<u32>; csr::DRTIO.len()] = [None; csr::DRTIO.len()];
+    const RTT_SAMPLES: usize = 16;
+    const RTT_TIMEOUT_MS: u64 = 10;
+
+    static mut ROUTING_TABLE_CHANGED: bool = false;
+
+    // Uptime at which the satellite of each remote destination last reported
+    // it up to the destination survey.
+    static mut DESTINATION_SEEN_MS: [u64; drtio_routing::DEST_COUNT] = [0; drtio_routing::DEST_COUNT];
+    // Number of times each destination came up, for moninj to subscribe
+    // again, and whether its satellite reported changes of the probes
+    // subscribed to.
+    static mut DESTINATION_EPOCHS: [u32; drtio_routing::DEST_COUNT] = [0; drtio_routing::DEST_COUNT];
+    static mut MONITOR_CHANGES: [bool; drtio_routing::DEST_COUNT] = [false; drtio_routing::DEST_COUNT];
+
+    // Routes that replace those of the routing table when their first link
+    // goes down. Destinations that fail over swap their two routes, so that
+    // they can fail back later.
+    static mut BACKUP_ROUTING_TABLE: drtio_routing::RoutingTable =
+        drtio_routing::RoutingTable([[drtio_routing::INVALID_HOP; drtio_routing::MAX_HOPS];
+                                     drtio_routing::DEST_COUNT]);
 
     pub fn startup(io: &Io, aux_mutex: &Mutex,
             routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
             up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
+        unsafe { BACKUP_ROUTING_TABLE = drtio_routing::config_backup_routing_table(); }
         let aux_mutex = aux_mutex.clone();
         let routing_table = routing_table.clone();
         let up_destinations = up_destinations.clone();
-        io.spawn(4096, move |io| {
-            let routing_table = routing_table.borrow();
+        io.spawn_named("drtio", Priority::Normal, 8192, move |io| {
             link_thread(io, &aux_mutex, &routing_table, &up_destinations);
         });
     }
 
-    fn link_rx_up(linkno: u8) -> bool {
+    pub fn link_count() -> usize {
+        csr::DRTIO.len()
+    }
+
+    pub fn link_rx_up(linkno: u8) -> bool {
         let linkno = linkno as usize;
         unsafe {
             (csr::DRTIO[linkno].rx_up_read)() == 1
         }
     }
 
+    pub fn link_stats(linkno: u8) -> LinkStats {
+        unsafe { LINK_STATS[linkno as usize] }
+    }
+
+    pub fn link_rtt_ps(linkno: u8) -> Option<u32> {
+        unsafe { LINK_RTT_PS[linkno as usize] }
+    }
+
+    // Health record of a link, for its alerts.
+    fn link_records(linkno: u8) -> RecordSet {
+        let stats = link_stats(linkno);
+        let mut records = RecordSet::new();
+        records.drtio_link(linkno, &DrtioLinkStats {
+            up:                 link_rx_up(linkno),
+            crc_errors:         stats.crc_errors,
+            aux_timeouts:       stats.aux_timeouts,
+            link_drops:         stats.link_drops,
+            protocol_errors:    stats.protocol_errors,
+            remote_rtio_errors: stats.remote_rtio_errors
+        });
+        records
+    }
+
+    fn count<F: FnOnce(&mut LinkStats)>(linkno: u8, f: F) {
+        unsafe {
+            if UP_LINKS[linkno as usize] {
+                f(&mut LINK_STATS[linkno as usize])
+            }
+        }
+    }
+
     fn recv_aux_timeout(io: &Io, linkno: u8, timeout: u32) -> Result<drtioaux::Packet, &'static str> {
         let max_time = clock::get_ms() + timeout as u64;
         loop {
@@ -38,12 +275,16 @@ pub mod drtio {
                 return Err("link went down");
             }
             if clock::get_ms() > max_time {
+                count(linkno, |stats| stats.aux_timeouts += 1);
                 return Err("timeout");
             }
             match drtioaux::recv(linkno) {
                 Ok(Some(packet)) => return Ok(packet),
                 Ok(None) => (),
-                Err(_) => return Err("aux packet error")
+                Err(_) => {
+                    count(linkno, |stats| stats.crc_errors += 1);
+                    return Err("aux packet error")
+                }
             }
             io.relinquish().unwrap();
         }
@@ -51,9 +292,62 @@ pub mod drtio {
 
     pub fn aux_transact(io: &Io, aux_mutex: &Mutex,
             linkno: u8, request: &drtioaux::Packet) -> Result<drtioaux::Packet, &'static str> {
+        aux_transact_timeout(io, aux_mutex, linkno, request, 200)
+    }
+
+    pub fn aux_transact_timeout(io: &Io, aux_mutex: &Mutex, linkno: u8,
+            request: &drtioaux::Packet, timeout: u32) -> Result<drtioaux::Packet, &'static str> {
         let _lock = aux_mutex.lock(io).unwrap();
         drtioaux::send(linkno, request).unwrap();
-        recv_aux_timeout(io, linkno, 200)
+        recv_aux_timeout(io, linkno, timeout)
+    }
+
+    static mut NEXT_TRANSFER: u8 = 0;
+
+    /// Sends `message` to `destination` as a segmented message of `kind`,
+    /// waiting up to `timeout` ms for the reply to each segment, which
+    /// covers the handling of the message for the last one.
+    pub fn aux_send_segmented(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8,
+            kind: u8, message: &[u8], timeout: u32) -> Result<(), &'static str> {
+        const ATTEMPTS: usize = 3;
+
+        if message.len() > drtioaux::MAX_SEGMENTED_SIZE {
+            return Err("segmented message too large")
+        }
+        let transfer = unsafe {
+            NEXT_TRANSFER = NEXT_TRANSFER.wrapping_add(1);
+            NEXT_TRANSFER
+        };
+        for seq in 0..drtioaux::segment_count(message) {
+            let request = drtioaux::segment(destination, kind, transfer, seq as u16, message);
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                let error = match aux_transact_timeout(io, aux_mutex, linkno, &request, timeout) {
+                    Ok(drtioaux::Packet::SegmentReply { seq: reply_seq, status })
+                            if reply_seq as usize == seq =>
+                        match status {
+                            drtioaux::SEGMENT_OK => break,
+                            drtioaux::SEGMENT_CORRUPTED => "segment corrupted",
+                            drtioaux::SEGMENT_OUT_OF_ORDER => return Err("segment out of order"),
+                            drtioaux::SEGMENT_TOO_LARGE => return Err("segmented message too large"),
+                            _ => return Err("segmented message rejected")
+                        },
+                    Ok(packet) => {
+                        error!("received unexpected aux packet: {:?}", packet);
+                        return Err("unexpected aux packet")
+                    }
+                    Err(e) => e
+                };
+                // The destination recognizes a segment sent again after a
+                // lost reply.
+                if attempt == ATTEMPTS {
+                    return Err(error)
+                }
+                warn!("[DEST#{}] segment {} failed ({}), retrying", destination, seq, error);
+            }
+        }
+        Ok(())
     }
 
     fn ping_remote(io: &Io, aux_mutex: &Mutex, linkno: u8) -> u32 {
@@ -102,6 +396,31 @@ pub mod drtio {
         }
     }
 
+    fn measure_rtt(io: &Io, linkno: u8) -> Result<u32, &'static str> {
+        let mut rtt = u16::max_value();
+        for _ in 0..RTT_SAMPLES {
+            unsafe {
+                (csr::DRTIO[linkno as usize].echo_write)(1);
+            }
+            let max_time = clock::get_ms() + RTT_TIMEOUT_MS;
+            while unsafe { (csr::DRTIO[linkno as usize].echo_read)() } == 1 {
+                if clock::get_ms() > max_time {
+                    return Err("no echo reply");
+                }
+                io.relinquish().unwrap();
+            }
+            rtt = min(rtt, unsafe { (csr::DRTIO[linkno as usize].rtt_read)() });
+        }
+        if rtt == u16::max_value() {
+            return Err("round-trip time out of range");
+        }
+        let frequency = rtio_clocking::measured_frequency() as u64;
+        if frequency == 0 {
+            return Err("RTIO clock not running");
+        }
+        Ok((rtt as u64 * 1_000_000_000_000 / frequency) as u32)
+    }
+
     fn load_routing_table(io: &Io, aux_mutex: &Mutex, linkno: u8, routing_table: &drtio_routing::RoutingTable)
             -> Result<(), &'static str> {
         for i in 0..drtio_routing::DEST_COUNT {
@@ -144,7 +463,10 @@ pub mod drtio {
         match drtioaux::recv(linkno) {
             Ok(Some(packet)) => warn!("[LINK#{}] unsolicited aux packet: {:?}", linkno, packet),
             Ok(None) => (),
-            Err(_) => warn!("[LINK#{}] aux packet error", linkno)
+            Err(_) => {
+                count(linkno, |stats| stats.crc_errors += 1);
+                warn!("[LINK#{}] aux packet error", linkno)
+            }
         }
     }
 
@@ -156,7 +478,10 @@ pub mod drtio {
             (csr::DRTIO[linkidx].protocol_error_write)(errors);
         }
         if errors != 0 {
+            count(linkno, |stats| stats.protocol_errors += errors.count_ones());
             error!("[LINK#{}] error(s) found (0x{:02x}):", linkno, errors);
+            alerts::push(Severity::Warning, Source::Drtio,
+                format!("link {}: protocol error(s) 0x{:02x}", linkno, errors));
             if errors & 1 != 0 {
                 error!("[LINK#{}] received packet of an unknown type", linkno);
             }
@@ -169,12 +494,70 @@ pub mod drtio {
         }
     }
 
+    fn link_errors(linkno: u8) -> u32 {
+        let stats = link_stats(linkno);
+        stats.crc_errors + stats.aux_timeouts + stats.protocol_errors
+    }
+
+    // SFP port of each link, after the SATA link if there is one. SFP0
+    // carries Ethernet.
+    #[cfg(soc_platform = "kasli")]
+    fn link_sfp(linkno: u8) -> Option<usize> {
+        #[cfg(has_drtio_sata)]
+        let linkno = linkno.checked_sub(1)?;
+        Some(linkno as usize + 1)
+    }
+
+    #[cfg(not(soc_platform = "kasli"))]
+    fn link_sfp(_linkno: u8) -> Option<usize> {
+        None
+    }
+
+    // Turns the transmitter of the link on or off, in the transceiver and, so
+    // that the remote end also sees a loss of signal, in the SFP module.
+    fn set_link_tx(io: &Io, linkno: u8, enable: bool) {
+        unsafe {
+            let txenable = csr::drtio_transceiver::txenable_read();
+            csr::drtio_transceiver::txenable_write(
+                if enable { txenable | 1 << linkno } else { txenable & !(1 << linkno) });
+        }
+        if let Some(index) = link_sfp(linkno) {
+            match nrt_bus::with_i2c(io, nrt_bus::MAIN_BUS, || sfp::set_tx_enable(index, enable)) {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) | Err(e) =>
+                    debug!("[LINK#{}] cannot set the transmitter of SFP{}: {}", linkno, index, e)
+            }
+        }
+    }
+
+    // Whether the SFP module of the link reports a loss of signal, if known.
+    fn link_los(io: &Io, linkno: u8) -> Option<bool> {
+        let index = link_sfp(linkno)?;
+        nrt_bus::with_i2c(io, nrt_bus::MAIN_BUS, || sfp::los(index)).ok()?.ok()
+    }
+
+    fn retrain(io: &Io, linkno: u8) {
+        set_link_tx(io, linkno, false);
+        io.sleep(RETRAIN_TX_OFF_MS).unwrap();
+        set_link_tx(io, linkno, true);
+    }
+
     fn destination_set_up(routing_table: &drtio_routing::RoutingTable,
             up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
             destination: u8, up: bool) {
         let mut up_destinations = up_destinations.borrow_mut();
         up_destinations[destination as usize] = up;
         if up {
+            destination_seen(destination);
+            unsafe {
+                DESTINATION_EPOCHS[destination as usize] =
+                    DESTINATION_EPOCHS[destination as usize].wrapping_add(1);
+                // The satellite may have rebooted and numbers its log chunks
+                // afresh; pull its log soon, it may hold the reason.
+                LOG_RECEIVED[destination as usize] = None;
+                LOG_PULL_AT_MS[destination as usize] = 0;
+                LOG_PULL_INTERVALS_MS[destination as usize] = LOG_PULL_INTERVAL_MS;
+            }
             drtio_routing::interconnect_enable(routing_table, 0, destination);
             info!("[DEST#{}] destination is up", destination);
         } else {
@@ -188,6 +571,38 @@ pub mod drtio {
         up_destinations[destination as usize]
     }
 
+    fn destination_seen(destination: u8) {
+        unsafe { DESTINATION_SEEN_MS[destination as usize] = clock::get_ms() }
+    }
+
+    pub fn destination_epoch(destination: u8) -> u32 {
+        unsafe { DESTINATION_EPOCHS[destination as usize] }
+    }
+
+    /// Whether the satellite of `destination` reported changes of the
+    /// monitored probes since the last call.
+    pub fn take_monitor_changes(destination: u8) -> bool {
+        unsafe {
+            let changes = MONITOR_CHANGES[destination as usize];
+            MONITOR_CHANGES[destination as usize] = false;
+            changes
+        }
+    }
+
+    /// Time in milliseconds since the satellite of `destination` last
+    /// reported it up, 0 for local destinations, or `None` if it is down.
+    pub fn destination_age_ms(routing_table: &drtio_routing::RoutingTable,
+            up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+            destination: u8) -> Option<u64> {
+        if !destination_up(up_destinations, destination) {
+            None
+        } else if routing_table.0[destination as usize][0] == 0 {
+            Some(0)
+        } else {
+            Some(clock::get_ms() - unsafe { DESTINATION_SEEN_MS[destination as usize] })
+        }
+    }
+
     fn destination_survey(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
             up_links: &[bool],
             up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
@@ -207,16 +622,40 @@ pub mod drtio {
                         let reply = aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::DestinationStatusRequest {
                             destination: destination
                         });
+                        match reply {
+                            // Errors are reported by a destination that is up.
+                            Ok(drtioaux::Packet::DestinationOkReply) |
+                            Ok(drtioaux::Packet::DestinationOkChangesReply) |
+                            Ok(drtioaux::Packet::DestinationSequenceErrorReply { .. }) |
+                            Ok(drtioaux::Packet::DestinationCollisionReply { .. }) |
+                            Ok(drtioaux::Packet::DestinationBusyReply { .. }) =>
+                                destination_seen(destination),
+                            _ => ()
+                        }
                         match reply {
                             Ok(drtioaux::Packet::DestinationDownReply) =>
                                 destination_set_up(routing_table, up_destinations, destination, false),
                             Ok(drtioaux::Packet::DestinationOkReply) => (),
-                            Ok(drtioaux::Packet::DestinationSequenceErrorReply { channel }) =>
-                                error!("[DEST#{}] RTIO sequence error involving channel 0x{:04x}", destination, channel),
-                            Ok(drtioaux::Packet::DestinationCollisionReply { channel }) =>
-                                error!("[DEST#{}] RTIO collision involving channel 0x{:04x}", destination, channel),
-                            Ok(drtioaux::Packet::DestinationBusyReply { channel }) =>
-                                error!("[DEST#{}] RTIO busy error involving channel 0x{:04x}", destination, channel),
+                            Ok(drtioaux::Packet::DestinationOkChangesReply) =>
+                                unsafe { MONITOR_CHANGES[destination as usize] = true },
+                            Ok(drtioaux::Packet::DestinationSequenceErrorReply { channel }) => {
+                                count(linkno, |stats| stats.remote_rtio_errors += 1);
+                                count_error(RtioError::SequenceError, (destination as u32) << 16 | channel as u32);
+                                error!("[DEST#{}] RTIO sequence error involving channel 0x{:04x}{}", destination, channel,
+                                       channel_name_suffix((destination as u32) << 16 | channel as u32))
+                            }
+                            Ok(drtioaux::Packet::DestinationCollisionReply { channel }) => {
+                                count(linkno, |stats| stats.remote_rtio_errors += 1);
+                                count_error(RtioError::Collision, (destination as u32) << 16 | channel as u32);
+                                error!("[DEST#{}] RTIO collision involving channel 0x{:04x}{}", destination, channel,
+                                       channel_name_suffix((destination as u32) << 16 | channel as u32))
+                            }
+                            Ok(drtioaux::Packet::DestinationBusyReply { channel }) => {
+                                count(linkno, |stats| stats.remote_rtio_errors += 1);
+                                count_error(RtioError::Busy, (destination as u32) << 16 | channel as u32);
+                                error!("[DEST#{}] RTIO busy error involving channel 0x{:04x}{}", destination, channel,
+                                       channel_name_suffix((destination as u32) << 16 | channel as u32))
+                            }
                             Ok(packet) => error!("[DEST#{}] received unexpected aux packet: {:?}", destination, packet),
                             Err(e) => error!("[DEST#{}] communication failed ({})", destination, e)
                         }
@@ -230,7 +669,8 @@ pub mod drtio {
                         });
                         match reply {
                             Ok(drtioaux::Packet::DestinationDownReply) => (),
-                            Ok(drtioaux::Packet::DestinationOkReply) => {
+                            Ok(drtioaux::Packet::DestinationOkReply) |
+                            Ok(drtioaux::Packet::DestinationOkChangesReply) => {
                                 destination_set_up(routing_table, up_destinations, destination, true);
                                 init_buffer_space(destination as u8, linkno);
                             },
@@ -243,21 +683,244 @@ pub mod drtio {
         }
     }
 
+    // Re-emits a line of a satellite log, formatted as
+    // `[timestamp] LEVEL(target): message`, at its original level.
+    fn emit_remote_log(destination: u8, line: &str) {
+        let record = line.find("] ").and_then(|start| {
+            let line = &line[start + 2..];
+            let open = line.find('(')?;
+            let close = line.find("): ")?;
+            let level = line[..open].trim().parse::<log::Level>().ok()?;
+            Some((level, &line[open + 1..close], &line[close + 3..]))
+        });
+        match record {
+            Some((level, target, message)) =>
+                log!(target: "satman", level, "[DEST#{}] {}: {}", destination, target, message),
+            None => info!(target: "satman", "[DEST#{}] {}", destination, line)
+        }
+    }
+
+    // Returns whether the satellite had any log messages. The messages
+    // received are emitted even if a later chunk cannot be pulled, since
+    // the satellite dropped them once they were acknowledged.
+    fn pull_remote_log(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8) -> Result<bool, &'static str> {
+        let mut messages = Vec::new();
+        let result = loop {
+            let received = unsafe { LOG_RECEIVED[destination as usize] };
+            let reply = aux_transact(io, aux_mutex, linkno,
+                &drtioaux::Packet::LogRequest {
+                    destination: destination,
+                    acknowledged: received.is_some(),
+                    sequence: received.unwrap_or(0)
+                });
+            match reply {
+                Ok(drtioaux::Packet::LogReply { sequence, more, length, data }) => {
+                    if received != Some(sequence) {
+                        let length = min(length as usize, drtioaux::LOG_CHUNK_SIZE);
+                        for (i, row) in data.iter().enumerate() {
+                            let start = i * row.len();
+                            if start >= length {
+                                break
+                            }
+                            messages.extend_from_slice(&row[..min(length - start, row.len())]);
+                        }
+                        unsafe { LOG_RECEIVED[destination as usize] = Some(sequence) }
+                    }
+                    if !more {
+                        break Ok(())
+                    }
+                }
+                Ok(packet) => {
+                    error!("[DEST#{}] received unexpected aux packet: {:?}", destination, packet);
+                    break Err("unexpected aux packet")
+                }
+                Err(e) => break Err(e)
+            }
+        };
+        for line in String::from_utf8_lossy(&messages).lines() {
+            emit_remote_log(destination, line);
+        }
+        result.map(|()| !messages.is_empty())
+    }
+
+    fn pull_remote_logs(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+            up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
+        for destination in 0..drtio_routing::DEST_COUNT {
+            let hop = routing_table.0[destination][0];
+            if hop == 0 || hop as usize > csr::DRTIO.len() ||
+                    !destination_up(up_destinations, destination as u8) ||
+                    clock::get_ms() < unsafe { LOG_PULL_AT_MS[destination] } {
+                continue
+            }
+            let interval = match pull_remote_log(io, aux_mutex, hop - 1, destination as u8) {
+                Ok(true) => LOG_PULL_INTERVAL_MS,
+                Ok(false) => min(unsafe { LOG_PULL_INTERVALS_MS[destination] } * 2,
+                                 LOG_PULL_MAX_INTERVAL_MS),
+                Err(e) => {
+                    warn!("[DEST#{}] cannot pull log ({})", destination, e);
+                    LOG_PULL_INTERVAL_MS
+                }
+            };
+            unsafe {
+                LOG_PULL_INTERVALS_MS[destination] = interval;
+                LOG_PULL_AT_MS[destination] = clock::get_ms() + interval;
+            }
+        }
+    }
+
+    /// Replaces the routing table with the one in the `routing_table` config
+    /// entry, and the backup routes with those in `routing_table_backup`.
+    /// The link thread then sends it to the satellites, and brings the
+    /// remote destinations up again with their new routes.
+    pub fn reload_routing_table(io: &Io,
+            routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>) -> Result<(), &'static str> {
+        let new_table = drtio_routing::config_routing_table(csr::DRTIO.len());
+        let mut routing_table = io.until_ok(|| routing_table.try_borrow_mut())
+                                  .map_err(|_| "interrupted")?;
+        *routing_table = new_table;
+        unsafe {
+            BACKUP_ROUTING_TABLE = drtio_routing::config_backup_routing_table();
+            ROUTING_TABLE_CHANGED = true;
+        }
+        Ok(())
+    }
+
+    fn send_routing_table(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+            up_links: &[bool]) {
+        for linkno in 0..csr::DRTIO.len() {
+            if !up_links[linkno] {
+                continue
+            }
+            let linkno = linkno as u8;
+            if let Err(e) = load_routing_table(io, aux_mutex, linkno, routing_table) {
+                error!("[LINK#{}] failed to load routing table ({})", linkno, e);
+            }
+            if let Err(e) = set_rank(io, aux_mutex, linkno, 1) {
+                error!("[LINK#{}] failed to set rank ({})", linkno, e);
+            }
+        }
+    }
+
+    fn apply_routing_table(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+            up_links: &[bool],
+            up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
+        send_routing_table(io, aux_mutex, routing_table, up_links);
+        // The destination survey enables them again through their new routes.
+        for destination in 0..drtio_routing::DEST_COUNT {
+            let destination = destination as u8;
+            if destination_up(up_destinations, destination) {
+                destination_set_up(routing_table, up_destinations, destination, false);
+            }
+        }
+        info!("routing table applied");
+    }
+
+    fn route_link(route: &[u8; drtio_routing::MAX_HOPS], up_links: &[bool]) -> Option<u8> {
+        let hop = route[0];
+        if hop != 0 && hop as usize <= csr::DRTIO.len() && up_links[hop as usize - 1] {
+            Some(hop - 1)
+        } else {
+            None
+        }
+    }
+
+    // Switches the remote destinations whose link is down to their backup
+    // route, if its link is up. The routing table must then be sent to the
+    // satellites, and the destination survey brings the destinations up again
+    // through their new route.
+    fn failover(routing_table: &mut drtio_routing::RoutingTable, up_links: &[bool],
+            up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) -> bool {
+        let backup_table = unsafe { &mut BACKUP_ROUTING_TABLE };
+        let mut switched = false;
+        for destination in 0..drtio_routing::DEST_COUNT {
+            let hop = routing_table.0[destination][0];
+            if hop == 0 || hop as usize > csr::DRTIO.len() || up_links[hop as usize - 1] {
+                continue
+            }
+            if let Some(backup_linkno) = route_link(&backup_table.0[destination], up_links) {
+                error!("[DEST#{}] link {} is down, failing over to link {}",
+                       destination, hop - 1, backup_linkno);
+                alerts::push(Severity::Critical, Source::Drtio,
+                    format!("destination {}: link {} is down, failing over to link {}",
+                            destination, hop - 1, backup_linkno));
+                let primary = routing_table.0[destination];
+                routing_table.0[destination] = backup_table.0[destination];
+                backup_table.0[destination] = primary;
+                if destination_up(up_destinations, destination as u8) {
+                    destination_set_up(routing_table, up_destinations, destination as u8, false);
+                }
+                switched = true;
+            }
+        }
+        switched
+    }
+
     pub fn link_thread(io: Io, aux_mutex: &Mutex,
-            routing_table: &drtio_routing::RoutingTable,
+            routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
             up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
-        let mut up_links = [false; csr::DRTIO.len()];
+        let up_links = unsafe { &mut UP_LINKS };
+        let retrain_threshold = config::read_str("drtio_retrain_threshold",
+            |r| r.ok().and_then(|s| s.parse().ok())).unwrap_or(0u32);
+        // Start time and error count of the current error window of each link.
+        let mut error_windows = [(0u64, 0u32); csr::DRTIO.len()];
+        // Uptime of the last time synchronization of each link.
+        let mut tsc_synced_at = [0u64; csr::DRTIO.len()];
         loop {
+            // Skipped while another thread is using the routing table, and
+            // retried at the next iteration.
+            let switched = match routing_table.try_borrow_mut() {
+                Ok(mut routing_table) => failover(&mut routing_table, &up_links[..], up_destinations),
+                Err(_) => false
+            };
+            // Not held while sleeping, so that the table can be reloaded.
+            let routing_table = routing_table.borrow();
+            if unsafe { ROUTING_TABLE_CHANGED } {
+                unsafe { ROUTING_TABLE_CHANGED = false; }
+                apply_routing_table(&io, aux_mutex, &routing_table, &up_links[..], up_destinations);
+            } else if switched {
+                // Repeaters on the backup routes need their new entries.
+                send_routing_table(&io, aux_mutex, &routing_table, &up_links[..]);
+            }
             for linkno in 0..csr::DRTIO.len() {
                 let linkno = linkno as u8;
                 if up_links[linkno as usize] {
                     /* link was previously up */
-                    if link_rx_up(linkno) {
+                    if link_rx_up(linkno) && !fault_injection::take_link_drop(linkno) {
                         process_unsolicited_aux(&io, aux_mutex, linkno);
                         process_local_errors(linkno);
+
+                        let now = clock::get_ms();
+                        let errors = link_errors(linkno);
+                        let (window_start, window_errors) = error_windows[linkno as usize];
+                        if now > window_start + RETRAIN_WINDOW_MS {
+                            error_windows[linkno as usize] = (now, errors);
+                        } else if retrain_threshold > 0 && errors - window_errors >= retrain_threshold {
+                            warn!("[LINK#{}] {} errors within {} ms, retraining link",
+                                  linkno, errors - window_errors, RETRAIN_WINDOW_MS);
+                            alerts::push_with_records(Severity::Warning, Source::Drtio,
+                                format!("link {}: {} errors within {} ms, retraining",
+                                        linkno, errors - window_errors, RETRAIN_WINDOW_MS),
+                                link_records(linkno));
+                            up_links[linkno as usize] = false;
+                            retrain(&io, linkno);
+                        } else if now > tsc_synced_at[linkno as usize] + TSC_SYNC_INTERVAL_MS {
+                            if let Err(e) = sync_tsc(&io, aux_mutex, linkno) {
+                                error!("[LINK#{}] failed to sync TSC ({})", linkno, e);
+                            }
+                            tsc_synced_at[linkno as usize] = clock::get_ms();
+                        }
                     } else {
-                        info!("[LINK#{}] link is down", linkno);
+                        if link_los(&io, linkno) == Some(true) {
+                            info!("[LINK#{}] link is down, SFP reports a loss of signal", linkno);
+                        } else {
+                            info!("[LINK#{}] link is down", linkno);
+                        }
+                        count(linkno, |stats| stats.link_drops += 1);
+                        alerts::push_with_records(Severity::Warning, Source::Drtio,
+                                                  format!("link {} is down", linkno),
+                                                  link_records(linkno));
                         up_links[linkno as usize] = false;
+                        unsafe { LINK_RTT_PS[linkno as usize] = None; }
                     }
                 } else {
                     /* link was previously down */
@@ -270,20 +933,33 @@ pub mod drtio {
                             if let Err(e) = sync_tsc(&io, aux_mutex, linkno) {
                                 error!("[LINK#{}] failed to sync TSC ({})", linkno, e);
                             }
-                            if let Err(e) = load_routing_table(&io, aux_mutex, linkno, routing_table) {
+                            tsc_synced_at[linkno as usize] = clock::get_ms();
+                            match measure_rtt(&io, linkno) {
+                                Ok(rtt_ps) => {
+                                    info!("[LINK#{}] round-trip time is {} ps", linkno, rtt_ps);
+                                    unsafe { LINK_RTT_PS[linkno as usize] = Some(rtt_ps); }
+                                }
+                                Err(e) => error!("[LINK#{}] failed to measure round-trip time ({})", linkno, e)
+                            }
+                            if let Err(e) = load_routing_table(&io, aux_mutex, linkno, &routing_table) {
                                 error!("[LINK#{}] failed to load routing table ({})", linkno, e);
                             }
                             if let Err(e) = set_rank(&io, aux_mutex, linkno, 1) {
                                 error!("[LINK#{}] failed to set rank ({})", linkno, e);
                             }
                             info!("[LINK#{}] link initialization completed", linkno);
+                            alerts::push(Severity::Info, Source::Drtio,
+                                         format!("link {} is up", linkno));
+                            error_windows[linkno as usize] = (clock::get_ms(), link_errors(linkno));
                         } else {
                             error!("[LINK#{}] ping failed", linkno);
                         }
                     }
                 }
             }
-            destination_survey(&io, aux_mutex, routing_table, &up_links, up_destinations);
+            destination_survey(&io, aux_mutex, &routing_table, &up_links[..], up_destinations);
+            pull_remote_logs(&io, aux_mutex, &routing_table, up_destinations);
+            drop(routing_table);
             io.sleep(200).unwrap();
         }
     }
@@ -324,6 +1000,182 @@ pub mod drtio {
         _routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
         _up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {}
     pub fn reset(_io: &Io, _aux_mutex: &Mutex) {}
+    pub fn link_count() -> usize { 0 }
+    pub fn link_rx_up(_linkno: u8) -> bool { false }
+    pub fn link_stats(_linkno: u8) -> LinkStats { LinkStats::default() }
+    pub fn link_rtt_ps(_linkno: u8) -> Option<u32> { None }
+    pub fn destination_age_ms(_routing_table: &drtio_routing::RoutingTable,
+            _up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+            _destination: u8) -> Option<u64> { Some(0) }
+    pub fn reload_routing_table(_io: &Io,
+            _routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>) -> Result<(), &'static str> {
+        Err("DRTIO is not supported")
+    }
+}
+
+// Latency of the RT link to each satellite, for the host to offset the
+// timing of their channels against those of the master. It is measured for
+// the satellites on a link of the master, as half the round-trip time of the
+// link; the latency added by repeaters is not known to the master. A
+// calibration, e.g. from measurements of the outputs, can be stored for each
+// destination in the `drtio_latency_<destination>` config key, in ps.
+
+fn measured_latency_ps(routing_table: &drtio_routing::RoutingTable, destination: u8) -> Option<u32> {
+    let hops = &routing_table.0[destination as usize];
+    if hops[0] == 0 || hops[0] as usize > drtio::link_count() || hops[1] != 0 {
+        return None
+    }
+    drtio::link_rtt_ps(hops[0] - 1).map(|rtt_ps| rtt_ps / 2)
+}
+
+fn calibrated_latency_ps(destination: u8) -> Option<u32> {
+    config::read_str(&format!("drtio_latency_{}", destination),
+        |r| r.ok().and_then(|s| s.trim().parse().ok()))
+}
+
+/// Latencies of the satellites of the routing table, in the order of their
+/// destination numbers.
+pub fn latencies(routing_table: &drtio_routing::RoutingTable) -> Vec<DrtioLatency> {
+    let mut latencies = Vec::new();
+    for destination in 0..drtio_routing::DEST_COUNT {
+        let hop = routing_table.0[destination][0];
+        if hop == 0 || hop as usize > drtio::link_count() {
+            continue
+        }
+        let destination = destination as u8;
+        latencies.push(DrtioLatency {
+            destination:   destination,
+            measured_ps:   measured_latency_ps(routing_table, destination),
+            calibrated_ps: calibrated_latency_ps(destination)
+        })
+    }
+    latencies
+}
+
+/// Stores `latency_ps`, or the latency measured if `None`, as the
+/// calibration of `destination`, and returns it.
+pub fn calibrate_latency(routing_table: &drtio_routing::RoutingTable, destination: u8,
+                         latency_ps: Option<u32>) -> Result<u32, &'static str> {
+    if destination as usize >= drtio_routing::DEST_COUNT {
+        return Err("invalid destination")
+    }
+    let hop = routing_table.0[destination as usize][0];
+    if hop == 0 || hop as usize > drtio::link_count() {
+        return Err("destination is not a satellite")
+    }
+    let latency_ps = match latency_ps {
+        Some(latency_ps) => latency_ps,
+        None => measured_latency_ps(routing_table, destination)
+            .ok_or("no latency measured for the destination")?
+    };
+    let key = format!("drtio_latency_{}", destination);
+    hw_watchdog::suspend(|| config::write(&key, format!("{}", latency_ps).as_bytes()))
+        .map_err(|_| "cannot write the config")?;
+    Ok(latency_ps)
+}
+
+// Names of the RTIO channels, from the device_map config entry written by
+// artiq_rtiomap: a count, then each channel number with its device name.
+static mut DEVICE_MAP: Option<BTreeMap<u32, String>> = None;
+
+fn read_device_map() -> BTreeMap<u32, String> {
+    let mut device_map = BTreeMap::new();
+    config::read("device_map", |result| {
+        let data = match result {
+            Ok(data) if data.len() > 0 => data,
+            _ => {
+                info!("no device map, RTIO errors will show channel numbers only");
+                return
+            }
+        };
+        let mut reader = Cursor::new(data);
+        let count = match reader.read_u32() {
+            Ok(count) => count,
+            Err(_) => {
+                warn!("device map is truncated");
+                return
+            }
+        };
+        for _ in 0..count {
+            match (reader.read_u32(), reader.read_string()) {
+                (Ok(channel), Ok(name)) => {
+                    if let Some(previous) = device_map.insert(channel, name) {
+                        warn!("device map has several names for channel {}, dropping `{}`",
+                              channel, previous);
+                    }
+                }
+                _ => {
+                    warn!("device map is truncated");
+                    return
+                }
+            }
+        }
+    });
+    device_map
+}
+
+/// Returns ` (name)` if the device map names the channel, or an empty string.
+pub fn channel_name_suffix(channel: u32) -> String {
+    match unsafe { DEVICE_MAP.as_ref() }.and_then(|map| map.get(&channel)) {
+        Some(name) => format!(" ({})", name),
+        None => String::new()
+    }
+}
+
+/// Replaces the `{rtio_channel_info:N}` placeholders of the message of an
+/// exception raised by the kernel with `{N}`, followed by the name of the
+/// channel in parameter N if the device map has it.
+pub fn resolve_channel_names(message: &str, param: &[i64; 3]) -> String {
+    let mut message = String::from(message);
+    for (index, &channel) in param.iter().enumerate() {
+        let placeholder = format!("{{rtio_channel_info:{}}}", index);
+        if message.contains(&placeholder) {
+            let info = format!("{{{}}}{}", index, channel_name_suffix(channel as u32));
+            message = message.replace(&placeholder, &info);
+        }
+    }
+    message
+}
+
+pub fn sed_stats(clear: bool) -> SedStats {
+    unsafe {
+        let lane_count = csr::rtio_core::sed_lane_count_read() as usize;
+        let mut lane_writes = Vec::with_capacity(lane_count);
+        let mut lane_full = Vec::with_capacity(lane_count);
+        for lane in 0..lane_count {
+            csr::rtio_core::sed_lane_sel_write(lane as _);
+            lane_writes.push(csr::rtio_core::sed_lane_writes_read());
+            lane_full.push(csr::rtio_core::sed_lane_full_read());
+        }
+        let stats = SedStats {
+            lane_writes: lane_writes,
+            lane_full: lane_full,
+            collisions: csr::rtio_core::collision_count_read(),
+            busy_errors: csr::rtio_core::busy_count_read(),
+            sequence_errors: csr::rtio_core::sequence_error_count_read()
+        };
+        if clear {
+            csr::rtio_core::sed_stats_clear_write(1);
+        }
+        stats
+    }
+}
+
+/// Size of the channel groups, and the output events counted for each of
+/// them since boot or the last clear.
+pub fn channel_events(clear: bool) -> (u16, Vec<u32>) {
+    unsafe {
+        let group_count = csr::rtio_core::channel_group_count_read() as usize;
+        let mut events = Vec::with_capacity(group_count);
+        for group in 0..group_count {
+            csr::rtio_core::channel_group_sel_write(group as _);
+            events.push(csr::rtio_core::channel_group_events_read());
+        }
+        if clear {
+            csr::rtio_core::channel_events_clear_write(1);
+        }
+        (1 << csr::rtio_core::channel_group_shift_read(), events)
+    }
 }
 
 fn async_error_thread(io: Io) {
@@ -332,16 +1184,24 @@ fn async_error_thread(io: Io) {
             io.until(|| csr::rtio_core::async_error_read() != 0).unwrap();
             let errors = csr::rtio_core::async_error_read();
             if errors & 1 != 0 {
-                error!("RTIO collision involving channel {}",
-                       csr::rtio_core::collision_channel_read());
+                let channel = csr::rtio_core::collision_channel_read() as u32;
+                count_error(RtioError::Collision, channel);
+                error!("RTIO collision involving channel {}{}",
+                       channel, channel_name_suffix(channel));
             }
             if errors & 2 != 0 {
-                error!("RTIO busy error involving channel {}",
-                       csr::rtio_core::busy_channel_read());
+                let channel = csr::rtio_core::busy_channel_read() as u32;
+                count_error(RtioError::Busy, channel);
+                error!("RTIO busy error involving channel {}{}",
+                       channel, channel_name_suffix(channel));
             }
             if errors & 4 != 0 {
-                error!("RTIO sequence error involving channel {}",
-                       csr::rtio_core::sequence_error_channel_read());
+                let channel = csr::rtio_core::sequence_error_channel_read() as u32;
+                count_error(RtioError::SequenceError, channel);
+                let lanes_full = csr::rtio_core::sequence_error_lanes_full_read() as u32;
+                let lane_count = csr::rtio_core::sed_lane_count_read() as usize;
+                error!("RTIO sequence error involving channel {}{} (full SED lanes: {:0width$b})",
+                       channel, channel_name_suffix(channel), lanes_full, width = lane_count);
             }
             csr::rtio_core::async_error_write(errors);
         }
@@ -351,11 +1211,12 @@ fn async_error_thread(io: Io) {
 pub fn startup(io: &Io, aux_mutex: &Mutex,
         routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
         up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
+    unsafe { DEVICE_MAP = Some(read_device_map()); }
     drtio::startup(io, aux_mutex, routing_table, up_destinations);
     unsafe {
         csr::rtio_core::reset_phy_write(1);
     }
-    io.spawn(4096, async_error_thread);
+    io.spawn_named("rtio_errors", Priority::Normal, 4096, async_error_thread);
 }
 
 pub fn reset(io: &Io, aux_mutex: &Mutex) {
@@ -0,0 +1,196 @@
+use alloc::Vec;
+use core::cell::RefCell;
+use board_misoc::{clock, config};
+use board_artiq::drtio_routing;
+use sched::{Io, Mutex};
+use urc::Urc;
+
+// Outputs driven by the firmware to a safe state, e.g. shutters closed and
+// AOMs off, when the host connection of a running kernel is lost or a kernel
+// watchdog expires. The `safe_state` config entry lists them as
+// `channel=level` pairs separated by commas, with the destination in the
+// upper bits of the channel as in the device database, e.g. `5=0,65538=1`.
+// The levels are held with the moninj overrides of the channels, so the
+// outputs must support them (e.g. TTL outputs). When the host connection is
+// lost, they are applied after `safe_state_delay_ms` (0 by default); on a
+// watchdog expiry, an interlock trip or before a power-off, immediately. The overrides are
+// released when a host opens a new kernel session.
+
+// moninj overrides of TTL outputs.
+const OVERRIDE_EN: u8 = 0;
+const OVERRIDE_LEVEL: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    HostLost,
+    Watchdog,
+    PowerOff,
+    Interlock
+}
+
+enum State {
+    Idle,
+    Pending { at: u64, cause: Cause },
+    Applied(Vec<u32>),
+    Releasing(Vec<u32>)
+}
+
+static mut STATE: State = State::Idle;
+
+fn state() -> &'static mut State {
+    unsafe { &mut STATE }
+}
+
+fn outputs() -> Vec<(u32, u8)> {
+    config::read_str("safe_state", |r| {
+        let mut outputs = Vec::new();
+        for entry in r.unwrap_or("").split(',').filter(|entry| !entry.is_empty()) {
+            let mut parts = entry.splitn(2, '=');
+            let channel = parts.next().and_then(|s| s.trim().parse::<u32>().ok());
+            let level = parts.next().and_then(|s| s.trim().parse::<u8>().ok());
+            match (channel, level) {
+                (Some(channel), Some(level)) if level <= 1 => outputs.push((channel, level)),
+                _ => warn!("invalid entry {:?} in `safe_state` config key", entry)
+            }
+        }
+        outputs
+    })
+}
+
+fn delay_ms() -> u64 {
+    match config::read_str("safe_state_delay_ms", |r| r.map(|s| s.parse())) {
+        Ok(Ok(delay_ms)) => delay_ms,
+        Ok(Err(_)) => {
+            warn!("invalid safe_state_delay_ms, applying the safe state without delay");
+            0
+        }
+        Err(_) => 0
+    }
+}
+
+/// Schedules the outputs to be driven to their safe state. A pending
+/// application is only brought forward, never delayed.
+pub fn trigger(cause: Cause) {
+    let at = match cause {
+        Cause::HostLost => clock::get_ms() + delay_ms(),
+        Cause::Watchdog | Cause::PowerOff | Cause::Interlock => clock::get_ms()
+    };
+    match *state() {
+        State::Pending { at: pending_at, .. } if pending_at <= at => (),
+        State::Applied(_) => (),
+        _ => *state() = State::Pending { at: at, cause: cause }
+    }
+}
+
+/// Cancels a pending application, and releases the overrides of the outputs
+/// that are in their safe state.
+pub fn release() {
+    let previous = core::mem::replace(state(), State::Idle);
+    match previous {
+        State::Pending { .. } => info!("host connected, safe state cancelled"),
+        State::Applied(channels) | State::Releasing(channels) =>
+            *state() = State::Releasing(channels),
+        State::Idle => ()
+    }
+}
+
+/// Number of outputs in their safe state, once it is applied.
+pub fn applied() -> Option<usize> {
+    match *state() {
+        State::Applied(ref channels) => Some(channels.len()),
+        _ => None
+    }
+}
+
+#[cfg(has_rtio_moninj)]
+fn inject_local(channel: u16, overrd: u8, value: u8) -> Result<(), &'static str> {
+    use board_misoc::csr;
+    unsafe {
+        csr::rtio_moninj::inj_chan_sel_write(channel as _);
+        csr::rtio_moninj::inj_override_sel_write(overrd);
+        csr::rtio_moninj::inj_value_write(value);
+    }
+    Ok(())
+}
+
+#[cfg(not(has_rtio_moninj))]
+fn inject_local(_channel: u16, _overrd: u8, _value: u8) -> Result<(), &'static str> {
+    Err("no local moninj")
+}
+
+// Unlike moninj, does not wait for a link that is down: the satellite then
+// applies its own safe state, if any (see its `idle_kernel`).
+#[cfg(has_drtio)]
+fn inject_remote(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8,
+                 channel: u16, overrd: u8, value: u8) -> Result<(), &'static str> {
+    use drtioaux;
+    use rtio_mgt::drtio;
+
+    if !drtio::link_rx_up(linkno) {
+        return Err("link is down")
+    }
+    let _lock = aux_mutex.lock(io).map_err(|_| "interrupted")?;
+    drtioaux::send(linkno, &drtioaux::Packet::InjectionRequest {
+        destination: destination,
+        channel: channel,
+        overrd: overrd,
+        value: value
+    }).map_err(|_| "aux packet error")
+}
+
+#[cfg(not(has_drtio))]
+fn inject_remote(_io: &Io, _aux_mutex: &Mutex, _linkno: u8, _destination: u8,
+                 _channel: u16, _overrd: u8, _value: u8) -> Result<(), &'static str> {
+    Err("no DRTIO")
+}
+
+fn inject(io: &Io, aux_mutex: &Mutex, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+          channel: u32, overrd: u8, value: u8) -> Result<(), &'static str> {
+    let destination = (channel >> 16) as u8;
+    let hop = routing_table.borrow().0[destination as usize][0];
+    if hop == 0 {
+        inject_local(channel as u16, overrd, value)
+    } else {
+        inject_remote(io, aux_mutex, hop - 1, destination, channel as u16, overrd, value)
+    }
+}
+
+/// Applies a pending safe state when it is due, and releases the overrides
+/// after `release`.
+pub fn poll(io: &Io, aux_mutex: &Mutex, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>) {
+    let cause = match *state() {
+        State::Pending { at, cause } if clock::get_ms() >= at => cause,
+        State::Releasing(_) => {
+            if let State::Releasing(channels) = core::mem::replace(state(), State::Idle) {
+                for channel in channels {
+                    if let Err(e) = inject(io, aux_mutex, routing_table, channel, OVERRIDE_EN, 0) {
+                        warn!("cannot release the safe state of channel {}: {}", channel, e);
+                    }
+                }
+                info!("safe state released");
+            }
+            return
+        }
+        _ => return
+    };
+
+    let outputs = outputs();
+    let mut channels = Vec::new();
+    for (channel, level) in outputs {
+        let result = inject(io, aux_mutex, routing_table, channel, OVERRIDE_LEVEL, level)
+            .and_then(|()| inject(io, aux_mutex, routing_table, channel, OVERRIDE_EN, 1));
+        match result {
+            Ok(()) => channels.push(channel),
+            Err(e) => error!("cannot drive channel {} to its safe state: {}", channel, e)
+        }
+    }
+    if !channels.is_empty() {
+        warn!("{} outputs driven to their safe state after {}", channels.len(), match cause {
+            Cause::HostLost => "the loss of the host connection",
+            Cause::Watchdog => "a watchdog expiry",
+            Cause::PowerOff => "a request to prepare for power-off",
+            Cause::Interlock => "an interlock trip"
+        });
+    }
+    *state() = State::Applied(channels);
+}
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
 use core::mem;
+use core::cmp::{min, max};
 use core::result;
 use core::cell::{Cell, RefCell};
 use alloc::Vec;
@@ -11,9 +12,11 @@ use smoltcp::Error as NetworkError;
 use smoltcp::wire::IpEndpoint;
 use smoltcp::socket::{SocketHandle, SocketRef};
 
-use io::{Read, Write};
-use board_misoc::clock;
+use io::{Read, Write, Error as IoError};
+use board_misoc::{clock, config};
+use mgmt_proto::ThreadStats;
 use urc::Urc;
+use low_latency;
 
 #[derive(Fail, Debug)]
 pub enum Error {
@@ -48,36 +51,134 @@ enum WaitResult {
     Interrupted
 }
 
+/// Scheduling class of a thread. When several threads are runnable, those
+/// with a higher priority are resumed first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High
+}
+
+/// Number of times a runnable thread may be passed over in favor of higher
+/// priority threads before it is resumed regardless of its priority.
+const STARVATION_LIMIT: u32 = 8;
+
+// Run time and wakeups of the threads, accumulated by thread name, so that
+// threads that are respawned, e.g. for each connection, share an entry.
+static mut THREAD_STATS: Option<Vec<ThreadStats>> = None;
+static mut STATS_SINCE_US: u64 = 0;
+
+fn thread_stats_mut() -> &'static mut Vec<ThreadStats> {
+    unsafe {
+        if THREAD_STATS.is_none() {
+            THREAD_STATS = Some(Vec::new())
+        }
+        THREAD_STATS.as_mut().unwrap()
+    }
+}
+
+fn thread_stats_index(name: &'static str) -> usize {
+    let stats = thread_stats_mut();
+    match stats.iter().position(|entry| entry.name == name) {
+        Some(index) => index,
+        None => {
+            stats.push(ThreadStats {
+                name:           name,
+                threads:        0,
+                wakeups:        0,
+                run_time_us:    0,
+                longest_run_us: 0
+            });
+            stats.len() - 1
+        }
+    }
+}
+
+/// Time in microseconds over which the statistics were accumulated, and
+/// the statistics of each thread name. Clears them if `clear` is set.
+pub fn thread_stats(clear: bool) -> (u64, Vec<ThreadStats>) {
+    let now = clock::get_us();
+    let stats = thread_stats_mut();
+    let result = (now - unsafe { STATS_SINCE_US }, stats.clone());
+    if clear {
+        for entry in stats.iter_mut() {
+            entry.wakeups = 0;
+            entry.run_time_us = 0;
+            entry.longest_run_us = 0;
+        }
+        unsafe { STATS_SINCE_US = now }
+    }
+    result
+}
+
 #[derive(Debug)]
 struct Thread {
     generator:   Generator<WaitResult, WaitRequest, OwnedStack>,
     waiting_for: WaitRequest,
-    interrupted: bool
+    interrupted: bool,
+    priority:    Priority,
+    starved:     u32,
+    wake_result: Option<WaitResult>,
+    // Index of the entry of the thread in THREAD_STATS.
+    stats:       usize
 }
 
 impl Thread {
-    unsafe fn new<F>(io: &Io, stack_size: usize, f: F) -> ThreadHandle
+    unsafe fn new<F>(io: &Io, name: &'static str, priority: Priority, stack_size: usize, f: F)
+                    -> ThreadHandle
             where F: 'static + FnOnce(Io) + Send {
         let spawned = io.spawned.clone();
         let sockets = io.sockets.clone();
+        let stats = thread_stats_index(name);
+        thread_stats_mut()[stats].threads += 1;
 
         let stack = OwnedStack::new(stack_size);
         ThreadHandle::new(Thread {
-            generator: Generator::unsafe_new(stack, |yielder, _| {
+            generator: Generator::unsafe_new(stack, move |yielder, _| {
                 f(Io {
                     yielder: Some(yielder),
                     spawned: spawned,
-                    sockets: sockets
+                    sockets: sockets,
+                    priority: priority,
+                    name: name
                 })
             }),
             waiting_for: WaitRequest {
                 event:   None,
                 timeout: None
             },
-            interrupted: false
+            interrupted: false,
+            priority: priority,
+            starved: 0,
+            wake_result: None,
+            stats: stats
         })
     }
 
+    /// Checks whether the thread can be resumed. The outcome is remembered
+    /// until the thread is resumed, so that wait conditions are evaluated
+    /// at most once per pass of the scheduler; see `Scheduler::run` for
+    /// when it is discarded.
+    fn poll(&mut self, now: u64) -> bool {
+        if self.wake_result.is_none() {
+            self.wake_result =
+                if self.interrupted {
+                    self.interrupted = false;
+                    Some(WaitResult::Interrupted)
+                } else if self.waiting_for.event.is_none() && self.waiting_for.timeout.is_none() {
+                    Some(WaitResult::Completed)
+                } else if self.waiting_for.timeout.map(|instant| now >= instant).unwrap_or(false) {
+                    Some(WaitResult::TimedOut)
+                } else if self.waiting_for.event.map(|event| unsafe { (*event)() }).unwrap_or(false) {
+                    Some(WaitResult::Completed)
+                } else {
+                    None
+                }
+        }
+        self.wake_result.is_some()
+    }
+
     pub fn terminated(&self) -> bool {
         // FIXME: https://github.com/nathan7/libfringe/pull/56
         match self.generator.state() {
@@ -135,7 +236,9 @@ impl Scheduler {
         Io {
             yielder: None,
             spawned: self.spawned.clone(),
-            sockets: self.sockets.clone()
+            sockets: self.sockets.clone(),
+            priority: Priority::Normal,
+            name: "main"
         }
     }
 
@@ -146,44 +249,75 @@ impl Scheduler {
         if self.threads.len() == 0 { return }
 
         let now = clock::get_ms();
-        let start_idx = self.run_idx;
-        loop {
-            self.run_idx = (self.run_idx + 1) % self.threads.len();
-
-            let result = {
-                let &mut Thread { ref mut generator, ref mut interrupted, ref waiting_for } =
-                    &mut *self.threads[self.run_idx].0.borrow_mut();
-                if *interrupted {
-                    *interrupted = false;
-                    generator.resume(WaitResult::Interrupted)
-                } else if waiting_for.event.is_none() && waiting_for.timeout.is_none() {
-                    generator.resume(WaitResult::Completed)
-                } else if waiting_for.timeout.map(|instant| now >= instant).unwrap_or(false) {
-                    generator.resume(WaitResult::TimedOut)
-                } else if waiting_for.event.map(|event| unsafe { (*event)() }).unwrap_or(false) {
-                    generator.resume(WaitResult::Completed)
-                } else if self.run_idx == start_idx {
-                    // We've checked every thread and none of them are runnable.
-                    break
-                } else {
-                    continue
-                }
-            };
-
-            match result {
-                None => {
-                    // The thread has terminated.
-                    self.threads.remove(self.run_idx);
-                    self.run_idx = 0
-                },
-                Some(wait_request) => {
-                    // The thread has suspended itself.
-                    let mut thread = self.threads[self.run_idx].0.borrow_mut();
-                    thread.waiting_for = wait_request
+        // Low priority threads wait for the end of the bounded-latency mode.
+        let low_latency = low_latency::active();
+
+        // Pick the first runnable thread in round-robin order among those with
+        // the highest priority, unless a thread has been starved for too long.
+        let mut chosen: Option<(usize, (bool, Priority))> = None;
+        for offset in 1..self.threads.len() + 1 {
+            let idx = (self.run_idx + offset) % self.threads.len();
+            let mut thread = self.threads[idx].0.borrow_mut();
+            if !thread.poll(now) {
+                continue
+            }
+            let starved = thread.starved >= STARVATION_LIMIT &&
+                !(low_latency && thread.priority == Priority::Low);
+            let rank = (starved, thread.priority);
+            match chosen {
+                Some((_, best)) if best >= rank => (),
+                _ => chosen = Some((idx, rank))
+            }
+        }
+        let chosen = match chosen {
+            Some((idx, _)) => idx,
+            // We've checked every thread and none of them are runnable.
+            None => return
+        };
+        for (idx, handle) in self.threads.iter().enumerate() {
+            let mut thread = handle.0.borrow_mut();
+            if idx == chosen {
+                thread.starved = 0
+            } else if thread.wake_result.is_some() {
+                thread.starved += 1;
+                // The event may no longer be there once the chosen thread
+                // has run (e.g. it took a mutex), so its wait condition is
+                // evaluated again. Timeouts and interruptions still hold.
+                if let Some(WaitResult::Completed) = thread.wake_result {
+                    thread.wake_result = None
                 }
             }
+        }
+        self.run_idx = chosen;
 
-            break
+        let start = clock::get_us();
+        let (result, stats) = {
+            let &mut Thread { ref mut generator, ref mut wake_result, ref stats, .. } =
+                &mut *self.threads[self.run_idx].0.borrow_mut();
+            (generator.resume(wake_result.take().unwrap()), *stats)
+        };
+        {
+            let run_time = clock::get_us() - start;
+            let entry = &mut thread_stats_mut()[stats];
+            entry.wakeups += 1;
+            entry.run_time_us += run_time;
+            if run_time > entry.longest_run_us as u64 {
+                entry.longest_run_us = run_time as u32;
+            }
+        }
+
+        match result {
+            None => {
+                // The thread has terminated.
+                thread_stats_mut()[stats].threads -= 1;
+                self.threads.remove(self.run_idx);
+                self.run_idx = 0
+            },
+            Some(wait_request) => {
+                // The thread has suspended itself.
+                let mut thread = self.threads[self.run_idx].0.borrow_mut();
+                thread.waiting_for = wait_request
+            }
         }
     }
 
@@ -197,12 +331,33 @@ pub struct Io<'a> {
     yielder: Option<&'a Yielder<WaitResult, WaitRequest, OwnedStack>>,
     spawned: Urc<RefCell<Vec<ThreadHandle>>>,
     sockets: Urc<RefCell<SocketSet>>,
+    priority: Priority,
+    name: &'static str
 }
 
 impl<'a> Io<'a> {
+    pub fn sockets(&self) -> &RefCell<SocketSet> {
+        &*self.sockets
+    }
+
+    /// Spawns a thread with the same priority and name as the calling thread.
     pub fn spawn<F>(&self, stack_size: usize, f: F) -> ThreadHandle
             where F: 'static + FnOnce(Io) + Send {
-        let handle = unsafe { Thread::new(self, stack_size, f) };
+        self.spawn_with_priority(self.priority, stack_size, f)
+    }
+
+    /// Spawns a thread with the same name as the calling thread.
+    pub fn spawn_with_priority<F>(&self, priority: Priority, stack_size: usize, f: F) -> ThreadHandle
+            where F: 'static + FnOnce(Io) + Send {
+        self.spawn_named(self.name, priority, stack_size, f)
+    }
+
+    /// Spawns a thread whose statistics are kept under `name`, which the
+    /// threads it spawns inherit.
+    pub fn spawn_named<F>(&self, name: &'static str, priority: Priority, stack_size: usize, f: F)
+                         -> ThreadHandle
+            where F: 'static + FnOnce(Io) + Send {
+        let handle = unsafe { Thread::new(self, name, priority, stack_size, f) };
         self.spawned.borrow_mut().push(handle.clone());
         handle
     }
@@ -302,6 +457,7 @@ macro_rules! until {
 type TcpSocketBuffer = ::smoltcp::socket::TcpSocketBuffer<'static>;
 type TcpSocketLower  = ::smoltcp::socket::TcpSocket<'static>;
 
+#[derive(Debug)]
 pub struct TcpSocketHandle(SocketHandle);
 
 pub struct TcpListener<'a> {
@@ -449,6 +605,25 @@ impl<'a> TcpStream<'a> {
         self.with_lower(|s| s.remote_endpoint())
     }
 
+    /// Fills `buf` like `read_exact`, but fails with `Error::TimedOut` if the
+    /// peer has not sent enough data by `deadline`, in milliseconds since boot.
+    pub fn read_exact_until(&mut self, mut buf: &mut [u8], deadline: u64)
+            -> result::Result<(), IoError<Error>> {
+        while !buf.is_empty() {
+            until!(self, TcpSocketLower,
+                   |s| s.can_recv() || !s.may_recv() || clock::get_ms() >= deadline)?;
+            if !self.can_recv() && self.may_recv() {
+                return Err(IoError::Other(Error::TimedOut))
+            }
+            let read_bytes = self.read(buf)?;
+            if read_bytes == 0 {
+                return Err(IoError::UnexpectedEnd)
+            }
+            buf = &mut { buf }[read_bytes..];
+        }
+        Ok(())
+    }
+
     pub fn timeout(&self) -> Option<u64> {
         self.with_lower(|s| s.timeout().as_ref().map(Duration::millis))
     }
@@ -465,6 +640,54 @@ impl<'a> TcpStream<'a> {
         self.with_lower(|mut s| s.set_keep_alive(value.map(Duration::from_millis)))
     }
 
+    /// Sets the keep-alive interval and the timeout after which an unresponsive
+    /// peer is dropped from the `<service>_keepalive_ms` and `<service>_timeout_ms`
+    /// config entries, or from the given defaults. 0 disables either of them.
+    pub fn set_liveness_from_config(&self, service: &str, keep_alive: u64, timeout: u64) {
+        fn read_ms(key: &str, default: u64) -> Option<u64> {
+            let value = config::read_str(key, |r| r.map(|s| s.parse::<u64>()));
+            match value {
+                Ok(Ok(0)) => None,
+                Ok(Ok(value)) => Some(value),
+                Ok(Err(_)) => {
+                    warn!("invalid value for {}, using {} ms", key, default);
+                    Some(default)
+                }
+                Err(_) => Some(default)
+            }
+        }
+
+        let keep_alive = read_ms(&format!("{}_keepalive_ms", service), keep_alive);
+        let timeout = read_ms(&format!("{}_timeout_ms", service), timeout);
+        if let (Some(keep_alive), Some(timeout)) = (keep_alive, timeout) {
+            if timeout <= keep_alive {
+                warn!("{} timeout ({} ms) does not exceed the keep-alive interval ({} ms); \
+                       idle connections will be dropped", service, timeout, keep_alive);
+            }
+        }
+        self.set_keep_alive(keep_alive);
+        self.set_timeout(timeout);
+    }
+
+    /// Writes all of `buf`, each time waiting until half of the send buffer
+    /// (or the rest of `buf`) is free, so that large amounts of data are
+    /// copied into the socket in a few large blocks rather than in as many
+    /// small pieces as there are acknowledgements from the peer.
+    pub fn write_all_in_bursts(&mut self, mut buf: &[u8]) -> Result<(), IoError<Error>> {
+        let burst = max(self.with_lower(|s| s.send_capacity()) / 2, 1);
+        while buf.len() > 0 {
+            let wanted = min(burst, buf.len());
+            until!(self, TcpSocketLower,
+                   |s| s.send_capacity() - s.send_queue() >= wanted || !s.may_send())?;
+            let sent = self.with_lower(|mut s| s.send_slice(buf)).unwrap_or(0);
+            if sent == 0 {
+                return Err(IoError::UnexpectedEnd)
+            }
+            buf = &buf[sent..];
+        }
+        Ok(())
+    }
+
     pub fn close(&self) -> Result<(), Error> {
         self.with_lower(|mut s| s.close());
         until!(self, TcpSocketLower, |s| !s.is_open())?;
@@ -527,6 +750,29 @@ impl<'a> Write for TcpStream<'a> {
         }
     }
 
+    fn write_all_vectored(&mut self, bufs: &[&[u8]]) -> Result<(), IoError<Self::WriteError>> {
+        // Queue as many buffers as fit with a single borrow of the socket,
+        // and only go through the slow path for the rest.
+        let (count, partial) = self.with_lower(|mut s| {
+            let mut count = 0;
+            for buf in bufs {
+                let sent = s.send_slice(buf).unwrap_or(0);
+                if sent < buf.len() {
+                    return (count, sent)
+                }
+                count += 1;
+            }
+            (count, 0)
+        });
+        if count < bufs.len() {
+            self.write_all(&bufs[count][partial..])?;
+            for buf in &bufs[count + 1..] {
+                self.write_all(buf)?
+            }
+        }
+        Ok(())
+    }
+
     fn flush(&mut self) -> Result<(), Self::FlushError> {
         until!(self, TcpSocketLower, |s|  s.send_queue() == 0 || !s.may_send())?;
         if self.with_lower(|s| s.send_queue()) == 0 {
@@ -543,3 +789,65 @@ impl<'a> Drop for TcpStream<'a> {
         self.io.sockets.borrow_mut().release(self.handle)
     }
 }
+
+type UdpSocketBuffer = ::smoltcp::socket::UdpSocketBuffer<'static, 'static>;
+type UdpSocketLower  = ::smoltcp::socket::UdpSocket<'static, 'static>;
+
+pub struct UdpSocket<'a> {
+    io:     &'a Io<'a>,
+    handle: SocketHandle
+}
+
+impl<'a> UdpSocket<'a> {
+    /// Creates a socket buffering up to `packets` datagrams and
+    /// `buffer_size` bytes in each direction.
+    pub fn new(io: &'a Io<'a>, packets: usize, buffer_size: usize) -> UdpSocket<'a> {
+        use smoltcp::socket::UdpPacketMetadata;
+
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; packets],
+                                             vec![0; buffer_size]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; packets],
+                                             vec![0; buffer_size]);
+        let handle = io.sockets
+            .borrow_mut()
+            .add(UdpSocketLower::new(rx_buffer, tx_buffer));
+        UdpSocket {
+            io:     io,
+            handle: handle
+        }
+    }
+
+    fn with_lower<F, R>(&self, f: F) -> R
+            where F: FnOnce(SocketRef<UdpSocketLower>) -> R {
+        let mut sockets = self.io.sockets.borrow_mut();
+        let result = f(sockets.get(self.handle));
+        result
+    }
+
+    pub fn bind<T: Into<IpEndpoint>>(&self, endpoint: T) -> Result<(), Error> {
+        let endpoint = endpoint.into();
+        self.with_lower(|mut s| s.bind(endpoint))
+            .map_err(|err| err.into())
+    }
+
+    /// Receives a datagram if there is one, without waiting.
+    pub fn recv_slice(&self, buf: &mut [u8]) -> Option<(usize, IpEndpoint)> {
+        self.with_lower(|mut s| s.recv_slice(buf).ok())
+    }
+
+    pub fn can_send(&self) -> bool {
+        self.with_lower(|s| s.can_send())
+    }
+
+    pub fn send_slice(&self, buf: &[u8], endpoint: IpEndpoint) -> Result<(), Error> {
+        self.with_lower(|mut s| s.send_slice(buf, endpoint))
+            .map_err(|err| err.into())
+    }
+}
+
+impl<'a> Drop for UdpSocket<'a> {
+    fn drop(&mut self) {
+        self.with_lower(|mut s| s.close());
+        self.io.sockets.borrow_mut().release(self.handle)
+    }
+}
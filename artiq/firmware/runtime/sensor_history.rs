@@ -0,0 +1,98 @@
+use core::cmp::{min, max};
+use alloc::Vec;
+use board_misoc::{clock, config, sfp};
+use sched::Io;
+use mgmt_proto::{SensorSample, SENSOR_SAMPLE_SFP_PORTS};
+use {nrt_bus, sysmon};
+
+// Periodic samples of the FPGA temperature and supply voltages and of the
+// SFP optical power, kept in RAM over a configurable span so that the
+// trajectory leading to a failure can be downloaded afterwards. The
+// interval between samples is set in seconds by `sensor_history_interval`
+// (0 disables the history) and the span in minutes by
+// `sensor_history_span`.
+
+const DEFAULT_INTERVAL_S: u32 = 60;
+const DEFAULT_SPAN_MIN: u32 = 24 * 60;
+const MAX_SAMPLES: usize = 4096;
+
+struct History {
+    interval_ms: u32,
+    samples:     Vec<SensorSample>,
+    // Index of the oldest sample once the buffer is full.
+    next:        usize
+}
+
+static mut HISTORY: Option<History> = None;
+
+fn config_u32(key: &str, default: u32) -> u32 {
+    config::read_str(key, |r| r.ok().and_then(|s| s.parse().ok())).unwrap_or(default)
+}
+
+fn sample(io: &Io) -> SensorSample {
+    let mut sfp_power = [None; SENSOR_SAMPLE_SFP_PORTS];
+    let _ = nrt_bus::with_i2c(io, nrt_bus::MAIN_BUS, || {
+        for (index, power) in sfp_power.iter_mut().enumerate().take(sfp::count()) {
+            *power = match sfp::diagnostics(index) {
+                Ok(diagnostics) => diagnostics.map(|d| (d.tx_power, d.rx_power)),
+                Err(_) => None
+            }
+        }
+    });
+    SensorSample {
+        timestamp_ms: clock::get_ms(),
+        fpga:         sysmon::fpga_sensors(),
+        sfp_power:    sfp_power
+    }
+}
+
+/// Interval between samples in milliseconds, and the samples, oldest first.
+pub fn samples() -> (u32, Vec<SensorSample>) {
+    match unsafe { HISTORY.as_ref() } {
+        None => (0, Vec::new()),
+        Some(history) => {
+            let mut samples = Vec::with_capacity(history.samples.len());
+            samples.extend_from_slice(&history.samples[history.next..]);
+            samples.extend_from_slice(&history.samples[..history.next]);
+            (history.interval_ms, samples)
+        }
+    }
+}
+
+/// Number of SFP ports in the samples.
+pub fn sfp_ports() -> u8 {
+    min(sfp::count(), SENSOR_SAMPLE_SFP_PORTS) as u8
+}
+
+pub fn thread(io: Io) {
+    if sysmon::fpga_sensors().is_none() && sfp::count() == 0 {
+        return
+    }
+    let interval_s = config_u32("sensor_history_interval", DEFAULT_INTERVAL_S);
+    if interval_s == 0 {
+        return
+    }
+    let span_min = config_u32("sensor_history_span", DEFAULT_SPAN_MIN);
+    let capacity = min(max(span_min as u64 * 60 / interval_s as u64, 1),
+                       MAX_SAMPLES as u64) as usize;
+    info!("keeping {} sensor samples, one every {} s", capacity, interval_s);
+
+    unsafe {
+        HISTORY = Some(History {
+            interval_ms: interval_s.saturating_mul(1000),
+            samples:     Vec::with_capacity(capacity),
+            next:        0
+        })
+    }
+    loop {
+        let sample = sample(&io);
+        let history = unsafe { HISTORY.as_mut().unwrap() };
+        if history.samples.len() < capacity {
+            history.samples.push(sample)
+        } else {
+            history.samples[history.next] = sample;
+            history.next = (history.next + 1) % capacity;
+        }
+        io.sleep(interval_s as u64 * 1000).unwrap();
+    }
+}
@@ -1,19 +1,38 @@
-use core::{mem, str, cell::{Cell, RefCell}, fmt::Write as FmtWrite};
+use core::{mem, str, slice, cell::{Cell, RefCell}, fmt::Write as FmtWrite};
 use alloc::{Vec, String};
 use byteorder::{ByteOrder, NetworkEndian};
+use log::Level;
 
-use io::{Read, Write, Error as IoError};
-use board_misoc::{ident, cache, config};
-use {mailbox, rpc_queue, kernel};
+use io::{Read, Write, BufWriter, Cursor, Error as IoError};
+use board_misoc::{ident, cache, config, clock, boot_stats, flash_storage};
+use logger_artiq::BufferLogger;
+use {mailbox, rpc_queue, kernel, profiler, sysmon, board_identity, status_led, dds_sync,
+     features};
 use urc::Urc;
-use sched::{ThreadHandle, Io, Mutex, TcpListener, TcpStream, Error as SchedError};
+use sched::{ThreadHandle, Io, Mutex, TcpListener, TcpStream, TcpSocketHandle, Priority,
+            Error as SchedError};
 use rtio_clocking;
+use rtio_mgt;
 use rtio_dma::Manager as DmaManager;
 use cache::Cache;
+use image_cache::{self, ImageCache};
+use watchdog::{WatchdogSet, Expired};
+use symbols::SymbolTable;
 use kern_hwreq;
+use hw_watchdog;
+use auth;
+use safe_state;
+use kernel_stats::{self, Outcome};
+use shutdown;
+#[cfg(has_rtio_moninj)]
+use interlock;
+use low_latency;
+use alerts::{self, Severity, Source};
+use audit::{self, Service, Event};
 use board_artiq::drtio_routing;
 
 use rpc_proto as rpc;
+use proto_artiq::lz4;
 use session_proto as host;
 use kernel_proto as kern;
 
@@ -25,8 +44,16 @@ pub enum Error<T> {
     KernelNotFound,
     #[fail(display = "invalid kernel CPU pointer: {:#08x}", _0)]
     InvalidPointer(usize),
+    #[fail(display = "watchdog for {} expired", _0)]
+    WatchdogExpired(String),
+    #[fail(display = "terminated on request: {}", _0)]
+    Terminated(String),
     #[fail(display = "RTIO clock failure")]
     ClockFailure,
+    #[fail(display = "did not finish within {} s", _0)]
+    TimedOut(u64),
+    #[fail(display = "uncaught exception")]
+    UncaughtException(String),
     #[fail(display = "protocol error: {}", _0)]
     Protocol(#[cause] host::Error<T>),
     #[fail(display = "{}", _0)]
@@ -55,24 +82,53 @@ macro_rules! unexpected {
      ($($arg:tt)*) => (return Err(Error::Unexpected(format!($($arg)*))));
 }
 
+// Hand-over point between the listener and a kernel session that lost
+// its host connection and is waiting for it to come back.
+#[derive(Debug, Default)]
+struct Resumption {
+    detached: bool,
+    stream: Option<TcpSocketHandle>,
+    // Issued to the host in the `Hello` reply, and sent back to resume.
+    token: Option<[u8; host::TOKEN_SIZE]>
+}
+
 // Persistent state
 #[derive(Debug)]
 struct Congress {
-    cache: Cache,
+    cache: Urc<RefCell<Cache>>,
+    image_cache: ImageCache,
     dma_manager: DmaManager,
-    finished_cleanly: Cell<bool>
+    finished_cleanly: Urc<Cell<bool>>,
+    resumption: Urc<RefCell<Resumption>>
 }
 
 impl Congress {
-    fn new() -> Congress {
+    fn new(cache: Urc<RefCell<Cache>>) -> Congress {
         Congress {
-            cache: Cache::new(),
+            cache: cache,
+            image_cache: ImageCache::new(),
             dma_manager: DmaManager::new(),
-            finished_cleanly: Cell::new(true)
+            finished_cleanly: Urc::new(Cell::new(true)),
+            resumption: Urc::new(RefCell::new(Resumption::default()))
         }
     }
 }
 
+// Values appended to datasets by the kernel with `dataset_append`, sent to
+// the host in one `DatasetAppend` message once the oldest has waited for
+// `DATASET_BATCH_MS`, the batch reaches `DATASET_BATCH_SIZE` bytes, or the
+// kernel sends anything else to the host, which must not overtake them.
+#[derive(Debug, Default)]
+struct DatasetBatch {
+    data: Vec<u8>,
+    count: u32,
+    // When the oldest value of the batch was appended.
+    since: u64
+}
+
+const DATASET_BATCH_MS: u64 = 100;
+const DATASET_BATCH_SIZE: usize = 16384;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum KernelState {
     Absent,
@@ -86,7 +142,25 @@ enum KernelState {
 struct Session<'a> {
     congress: &'a mut Congress,
     kernel_state: KernelState,
-    log_buffer: String
+    watchdog_set: WatchdogSet,
+    log_buffer: String,
+    // An async RPC spanning several queue chunks is being forwarded.
+    async_rpc_continued: bool,
+    // The remaining chunks of an async RPC cut off by a disconnection are
+    // being dropped.
+    async_rpc_discarded: bool,
+    // The host may reconnect to this session within the grace window;
+    // the last synchronous RPC request is kept in `pending_rpc` to replay it.
+    resumable: bool,
+    pending_rpc: Vec<u8>,
+    symbols: SymbolTable,
+    // Optional parts of the protocol that the host supports, see host::capability.
+    capabilities: u32,
+    // Report of an exception that ended a kernel without a host to send it to.
+    exception_report: Option<String>,
+    dataset_batch: DatasetBatch,
+    // Statistics of the kernel being run.
+    run: Option<kernel_stats::Run>
 }
 
 impl<'a> Session<'a> {
@@ -94,7 +168,17 @@ impl<'a> Session<'a> {
         Session {
             congress: congress,
             kernel_state: KernelState::Absent,
-            log_buffer: String::new()
+            watchdog_set: WatchdogSet::new(),
+            log_buffer: String::new(),
+            async_rpc_continued: false,
+            async_rpc_discarded: false,
+            resumable: false,
+            pending_rpc: Vec::new(),
+            symbols: SymbolTable::new(),
+            capabilities: host::capability::DEFAULT,
+            exception_report: None,
+            dataset_batch: DatasetBatch::default(),
+            run: None
         }
     }
 
@@ -118,20 +202,111 @@ impl<'a> Session<'a> {
 impl<'a> Drop for Session<'a> {
     fn drop(&mut self) {
         unsafe { kernel::stop() }
+        if let Some(run) = self.run.take() {
+            run.finish(Outcome::Stopped);
+        }
     }
 }
 
 fn host_read<R>(reader: &mut R) -> Result<host::Request, Error<R::ReadError>>
     where R: Read + ?Sized
 {
-    let request = host::Request::read_from(reader)?;
+    // Kernels that do not fit in the heap are skipped rather than allocated.
+    let request = host::Request::read_from(reader, ::heap_stats().largest_free)?;
     match &request {
         &host::Request::LoadKernel(_) => debug!("comm<-host LoadLibrary(...)"),
+        &host::Request::LoadKernelDelta { base, hash, length, ref patch } =>
+            debug!("comm<-host LoadLibraryDelta(base: {:#010x}, hash: {:#010x}, length: {}, \
+                    patch: {} bytes)", base, hash, length, patch.len()),
         _ => debug!("comm<-host {:?}", request)
     }
     Ok(request)
 }
 
+fn watchdog_expired(expired: &Expired) -> Error<SchedError> {
+    safe_state::trigger(safe_state::Cause::Watchdog);
+    alerts::push(Severity::Warning, Source::Watchdog,
+                 format!("watchdog for {} expired after {} ms", expired.scope, expired.elapsed_ms));
+    Error::WatchdogExpired(String::from(expired.scope))
+}
+
+// Termination of the running kernel requested over mgmt. The kernel is given
+// the chance to end cleanly: its next synchronous RPC raises
+// TerminationRequested, with the reason as message, instead of reaching the
+// host. If it has not ended after `kernel_termination_grace_ms` (1000 by
+// default), e.g. because it makes no RPC, it is stopped like on a watchdog
+// expiry and the host is sent the reason.
+struct Termination {
+    reason: String,
+    // `reason` as the format string of an exception message.
+    message: String,
+    deadline: u64,
+    raised: bool
+}
+
+const DEFAULT_TERMINATION_GRACE_MS: u64 = 1000;
+
+static mut TERMINATION: Option<Termination> = None;
+
+/// Requests the termination of the running kernel. Returns false if no
+/// kernel is running.
+pub fn request_termination(reason: &str) -> bool {
+    let grace_ms = match config::read_str("kernel_termination_grace_ms",
+                                          |r| r.map(|s| s.parse())) {
+        Ok(Ok(grace_ms)) => grace_ms,
+        Ok(Err(_)) => {
+            warn!("invalid kernel_termination_grace_ms, using {} ms",
+                  DEFAULT_TERMINATION_GRACE_MS);
+            DEFAULT_TERMINATION_GRACE_MS
+        }
+        Err(_) => DEFAULT_TERMINATION_GRACE_MS
+    };
+    terminate(reason, grace_ms)
+}
+
+/// Stops the running kernel without a grace period, e.g. on an interlock
+/// trip. Returns false if no kernel is running.
+pub fn request_stop(reason: &str) -> bool {
+    terminate(reason, 0)
+}
+
+fn terminate(reason: &str, grace_ms: u64) -> bool {
+    if !kernel::running() {
+        return false
+    }
+    warn!("termination of the kernel requested: {}", reason);
+    unsafe {
+        TERMINATION = Some(Termination {
+            reason: String::from(reason),
+            message: reason.replace('{', "{{").replace('}', "}}"),
+            deadline: clock::get_ms() + grace_ms,
+            raised: false
+        })
+    }
+    true
+}
+
+fn termination_pending() -> bool {
+    unsafe { TERMINATION.as_ref().map_or(false, |termination| !termination.raised) }
+}
+
+// The reason of the requested termination, once the kernel is past its
+// grace period.
+fn termination_due() -> Option<String> {
+    unsafe {
+        match TERMINATION {
+            Some(ref termination) if clock::get_ms() >= termination.deadline =>
+                Some(termination.reason.clone()),
+            _ => None
+        }
+    }
+}
+
+fn terminated(reason: String) -> Error<SchedError> {
+    unsafe { TERMINATION = None }
+    Error::Terminated(reason)
+}
+
 fn host_write<W>(writer: &mut W, reply: host::Reply) -> Result<(), IoError<W::WriteError>>
     where W: Write + ?Sized
 {
@@ -139,9 +314,58 @@ fn host_write<W>(writer: &mut W, reply: host::Reply) -> Result<(), IoError<W::Wr
     reply.write_to(writer)
 }
 
+// Messages shorter than this are not worth compressing.
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+// Writes `message`, made of one or more serialized replies, in a `Compressed`
+// message if the host supports it and that makes it shorter.
+fn write_compressible<W>(writer: &mut W, capabilities: u32, message: &[u8])
+                        -> Result<(), IoError<W::WriteError>>
+    where W: Write + ?Sized
+{
+    if capabilities & host::capability::COMPRESSION != 0 && message.len() >= COMPRESSION_THRESHOLD {
+        let compressed = lz4::compress(message);
+        if compressed.len() < message.len() {
+            debug!("comm->host Compressed {{ {} bytes into {} }}", message.len(), compressed.len());
+            return host::Reply::Compressed {
+                length: message.len() as u32,
+                data:   &compressed
+            }.write_to(writer)
+        }
+    }
+    writer.write_all(message)
+}
+
+// Sends the statistics of the kernel run if the host supports them.
+fn host_write_run_stats<W>(writer: &mut W, capabilities: u32,
+                           record: Option<&kernel_stats::Record>)
+                          -> Result<(), IoError<W::WriteError>>
+    where W: Write + ?Sized
+{
+    match record {
+        Some(record) if capabilities & host::capability::RUN_STATS != 0 =>
+            host_write(writer, host::Reply::KernelRunStats(record.as_proto())),
+        _ => Ok(())
+    }
+}
+
+fn host_write_compressible<W>(writer: &mut W, capabilities: u32, reply: host::Reply)
+                             -> Result<(), IoError<W::WriteError>>
+    where W: Write + ?Sized
+{
+    if capabilities & host::capability::COMPRESSION == 0 {
+        return host_write(writer, reply)
+    }
+    debug!("comm->host {:?}", reply);
+    let mut message = Vec::new();
+    reply.write_to(&mut message).unwrap();
+    write_compressible(writer, capabilities, &message)
+}
+
 pub fn kern_send(io: &Io, request: &kern::Message) -> Result<(), Error<SchedError>> {
     match request {
-        &kern::LoadRequest(_) => debug!("comm->kern LoadRequest(...)"),
+        &kern::LoadRequest { relocated, .. } =>
+            debug!("comm->kern LoadRequest(..., cached: {})", relocated.is_some()),
         &kern::DmaRetrieveReply { trace, duration } => {
             if trace.map(|data| data.len() > 100).unwrap_or(false) {
                 debug!("comm->kern DmaRetrieveReply {{ trace: ..., duration: {:?} }}", duration)
@@ -149,6 +373,8 @@ pub fn kern_send(io: &Io, request: &kern::Message) -> Result<(), Error<SchedErro
                 debug!("comm->kern {:?}", request)
             }
         }
+        &kern::DmaSegmentReply(Ok((trace, duration))) if trace.len() > 100 =>
+            debug!("comm->kern DmaSegmentReply(Ok((..., {:?})))", duration),
         _ => debug!("comm->kern {:?}", request)
     }
     unsafe { mailbox::send(request as *const _ as usize) }
@@ -168,7 +394,11 @@ fn kern_recv_notrace<R, F>(io: &Io, f: F) -> Result<R, Error<SchedError>>
 fn kern_recv_dotrace(reply: &kern::Message) {
     match reply {
         &kern::Log(_) => debug!("comm<-kern Log(...)"),
+        &kern::ExceptionDump(_) => debug!("comm<-kern ExceptionDump(...)"),
         &kern::LogSlice(_) => debug!("comm<-kern LogSlice(...)"),
+        &kern::LogRecord { .. } => debug!("comm<-kern LogRecord(...)"),
+        &kern::ProfileSamples { samples, dropped } =>
+            debug!("comm<-kern ProfileSamples([_; {}], {})", samples.len(), dropped),
         &kern::DmaRecordAppend(data) => {
             if data.len() > 100 {
                 debug!("comm<-kern DmaRecordAppend([_; {:#x}])", data.len())
@@ -199,14 +429,44 @@ unsafe fn kern_load(io: &Io, session: &mut Session, library: &[u8])
     if session.running() {
         unexpected!("attempted to load a new kernel while a kernel was running")
     }
+    if sysmon::throttled() {
+        return Err(Error::Load(String::from("FPGA over temperature, kernel loading disabled")))
+    }
+    if shutdown::requested() {
+        return Err(Error::Load(String::from("the core device is being powered off")))
+    }
+    #[cfg(has_rtio_moninj)]
+    {
+        if let Some(channel) = interlock::tripped() {
+            return Err(Error::Load(format!("interlock on channel {} tripped, kernel loading disabled",
+                                           channel)))
+        }
+    }
 
+    status_led::kernel_starting();
+    TERMINATION = None;
     kernel::start();
 
-    kern_send(io, &kern::LoadRequest(&library))?;
+    let hash = image_cache::hash(library);
+    let cached;
+    {
+        let relocated = session.congress.image_cache.get(hash, library);
+        cached = relocated.is_some();
+        kern_send(io, &kern::LoadRequest { library: library, relocated: relocated })?;
+    }
     kern_recv(io, |reply| {
         match reply {
-            kern::LoadReply(Ok(())) => {
+            kern::LoadReply(Ok(image_len)) => {
+                if !cached {
+                    // The kernel CPU waits for an acknowledgement before touching
+                    // the image again, so it can be copied as relocated.
+                    cache::flush_cpu_dcache();
+                    let image = slice::from_raw_parts(
+                        kern::KERNELCPU_PAYLOAD_ADDRESS as *const u8, *image_len);
+                    session.congress.image_cache.insert(hash, library, image);
+                }
                 session.kernel_state = KernelState::Loaded;
+                session.symbols = SymbolTable::from_elf(library);
                 Ok(())
             }
             kern::LoadReply(Err(error)) => {
@@ -219,40 +479,113 @@ unsafe fn kern_load(io: &Io, session: &mut Session, library: &[u8])
     })
 }
 
-fn kern_run(session: &mut Session) -> Result<(), Error<SchedError>> {
+// `source` is "host" or the config key of the kernel, for its statistics.
+fn kern_run(session: &mut Session, source: &str) -> Result<(), Error<SchedError>> {
     if session.kernel_state != KernelState::Loaded {
         unexpected!("attempted to run a kernel while not in Loaded state")
     }
 
     session.kernel_state = KernelState::Running;
+    session.run = Some(kernel_stats::Run::start(source));
     // TODO: make this a separate request
     kern_acknowledge()
 }
 
+fn host_load(io: &Io, stream: &mut TcpStream, session: &mut Session,
+             kernel: &[u8]) -> Result<(), Error<SchedError>> {
+    match unsafe { kern_load(io, session, kernel) } {
+        Ok(()) => host_write(stream, host::Reply::LoadCompleted)?,
+        Err(error) => {
+            let mut description = String::new();
+            write!(&mut description, "{}", error).unwrap();
+            host_write(stream, host::Reply::LoadFailed(&description))?;
+            kern_acknowledge()?;
+        }
+    }
+    Ok(())
+}
+
+// Replies to the host's `Hello` and returns the capabilities enabled for
+// the rest of the connection, i.e. those supported on both sides. The
+// session can only be resumed if it has a `token`.
+fn negotiate(stream: &mut TcpStream, version: u32, capabilities: u32,
+             token: Option<[u8; host::TOKEN_SIZE]>) -> Result<u32, Error<SchedError>> {
+    let mut capabilities = capabilities & host::capability::ALL;
+    if token.is_none() {
+        capabilities &= !host::capability::RESUME_TOKEN;
+    }
+    if version != host::PROTOCOL_VERSION {
+        info!("host uses session protocol version {} (ours is {}), capabilities {:#x}",
+              version, host::PROTOCOL_VERSION, capabilities);
+    }
+    let features = if capabilities & host::capability::FEATURES != 0 {
+        Some(features::word())
+    } else {
+        None
+    };
+    let token = if capabilities & host::capability::RESUME_TOKEN != 0 {
+        token
+    } else {
+        None
+    };
+    host_write(stream, host::Reply::Hello {
+        version: host::PROTOCOL_VERSION,
+        capabilities: capabilities,
+        features: features,
+        token: token
+    })?;
+    Ok(capabilities)
+}
+
 fn process_host_message(io: &Io,
                         stream: &mut TcpStream,
                         session: &mut Session) -> Result<(), Error<SchedError>> {
     match host_read(stream)? {
+        host::Request::Hello { version, capabilities } => {
+            let token = auth::session_token();
+            session.congress.resumption.borrow_mut().token = Some(token);
+            session.capabilities = negotiate(stream, version, capabilities, Some(token))?
+        }
+
         host::Request::SystemInfo => {
             host_write(stream, host::Reply::SystemInfo {
                 ident: ident::read(&mut [0; 64]),
-                finished_cleanly: session.congress.finished_cleanly.get()
+                finished_cleanly: session.congress.finished_cleanly.get(),
+                extended: session.capabilities & host::capability::SYSTEM_INFO_EXTENDED != 0,
+                board: board_identity::get(),
+                boot_stats: boot_stats::current().map(|stats| (stats.boot_count, stats.uptime_s))
             })?;
             session.congress.finished_cleanly.set(true)
         }
 
         host::Request::LoadKernel(kernel) =>
-            match unsafe { kern_load(io, session, &kernel) } {
-                Ok(()) => host_write(stream, host::Reply::LoadCompleted)?,
-                Err(error) => {
-                    let mut description = String::new();
-                    write!(&mut description, "{}", error).unwrap();
-                    host_write(stream, host::Reply::LoadFailed(&description))?;
-                    kern_acknowledge()?;
+            host_load(io, stream, session, &kernel)?,
+        host::Request::LoadKernelTooLarge { length } => {
+            error!("not enough memory to load the kernel from the host ({} bytes): heap {}",
+                   length, ::heap_stats());
+            host_write(stream, host::Reply::LoadFailed("not enough memory"))?
+        }
+        host::Request::LoadKernelDelta { base, hash, length, patch } => {
+            let kernel = session.congress.image_cache.library(base)
+                .ok_or("base kernel not cached")
+                .and_then(|base| image_cache::apply_delta(base, &patch, length as usize))
+                .and_then(|kernel| {
+                    if image_cache::hash(&kernel) == hash {
+                        Ok(kernel)
+                    } else {
+                        Err("patched kernel does not match")
+                    }
+                });
+            match kernel {
+                Ok(kernel) => host_load(io, stream, session, &kernel)?,
+                Err(reason) => {
+                    debug!("cannot apply kernel patch: {}", reason);
+                    host_write(stream, host::Reply::LoadBaseMissing)?
                 }
-            },
+            }
+        }
         host::Request::RunKernel =>
-            match kern_run(session) {
+            match kern_run(session, "host") {
                 Ok(()) => (),
                 Err(_) => host_write(stream, host::Reply::KernelStartupFailed)?
             },
@@ -286,6 +619,7 @@ fn process_host_message(io: &Io,
             })?;
             kern_send(io, &kern::RpcRecvReply(Ok(0)))?;
 
+            session.pending_rpc.clear();
             session.kernel_state = KernelState::Running
         }
 
@@ -315,6 +649,7 @@ fn process_host_message(io: &Io,
             };
             kern_send(io, &kern::RpcRecvReply(Err(exn)))?;
 
+            session.pending_rpc.clear();
             session.kernel_state = KernelState::Running
         }
     }
@@ -323,7 +658,7 @@ fn process_host_message(io: &Io,
 }
 
 fn process_kern_message(io: &Io, aux_mutex: &Mutex,
-                        routing_table: &drtio_routing::RoutingTable,
+                        routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
                         up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
                         mut stream: Option<&mut TcpStream>,
                         session: &mut Session) -> Result<bool, Error<SchedError>> {
@@ -334,7 +669,8 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                 // We're standing by; ignore the message.
                 return Ok(false)
             }
-            (_, KernelState::Running) => (),
+            (_, KernelState::Running) |
+            (&kern::ProfileSamples { .. }, KernelState::RpcWait) => (),
             _ => {
                 unexpected!("unexpected request {:?} from kernel CPU in {:?} state",
                             request, session.kernel_state)
@@ -348,6 +684,15 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
         }
 
         match request {
+            &kern::ExceptionDump(args) => {
+                let dump = format!("{}", args);
+                for line in dump.lines() {
+                    error!(target: "kernel", "{}", line);
+                }
+                ::save_kernel_exception(&dump);
+                kern_acknowledge()
+            }
+
             &kern::Log(args) => {
                 use core::fmt::Write;
                 session.log_buffer
@@ -363,6 +708,18 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                 kern_acknowledge()
             }
 
+            &kern::LogRecord { level, tag, text } => {
+                let level = match level {
+                    0 | 1 => Level::Error,
+                    2 => Level::Warn,
+                    3 => Level::Info,
+                    4 => Level::Debug,
+                    _ => Level::Trace
+                };
+                log!(target: &format!("kernel::{}", tag), level, "{}", text);
+                kern_acknowledge()
+            }
+
             &kern::DmaRecordStart(name) => {
                 session.congress.dma_manager.record_start(name);
                 kern_acknowledge()
@@ -371,6 +728,10 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                 session.congress.dma_manager.record_append(data);
                 kern_acknowledge()
             }
+            &kern::DmaRecordMark { name, timestamp } => {
+                session.congress.dma_manager.record_mark(name, timestamp);
+                kern_acknowledge()
+            }
             &kern::DmaRecordStop { duration } => {
                 session.congress.dma_manager.record_stop(duration);
                 cache::flush_l2_cache();
@@ -388,20 +749,83 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                     })
                 })
             }
+            &kern::DmaSegmentRequest { name, marker, offset, duration } => {
+                let segment = session.congress.dma_manager.segment(name, marker, offset, duration);
+                cache::flush_l2_cache();
+                kern_send(io, &kern::DmaSegmentReply(segment))
+            }
+            &kern::DmaChainRequest { name, trace, first } => {
+                let found = session.congress.dma_manager.chain(name, trace, first);
+                cache::flush_l2_cache();
+                kern_send(io, &kern::DmaChainReply { found: found })
+            }
 
+            &kern::RpcSend { async: false, .. } if termination_pending() => {
+                kern_acknowledge()?;
+                kern_recv(io, |reply| {
+                    match reply {
+                        &kern::RpcRecvRequest(_) => Ok(()),
+                        other => unexpected!(
+                            "expected (ignored) root value slot from kernel CPU, not {:?}", other)
+                    }
+                })?;
+
+                let termination = unsafe { TERMINATION.as_mut().unwrap() };
+                termination.raised = true;
+                info!("raising TerminationRequested in the kernel");
+                kern_send(io, &kern::RpcRecvReply(Err(kern::Exception {
+                    name:     "0:artiq.coredevice.exceptions.TerminationRequested",
+                    message:  &termination.message,
+                    param:    [0; 3],
+                    file:     "<runtime>",
+                    line:     0,
+                    column:   0,
+                    function: "terminate_kernel"
+                })))
+            }
             &kern::RpcSend { async, service, tag, data } => {
                 match stream {
                     None => unexpected!("unexpected RPC in flash kernel"),
                     Some(ref mut stream) => {
-                        host_write(stream, host::Reply::RpcRequest { async: async })?;
-                        rpc::send_args(stream, service, tag, data)?;
+                        let capabilities = session.capabilities;
+                        if session.resumable && !async {
+                            let request = &mut session.pending_rpc;
+                            request.clear();
+                            host::Reply::RpcRequest { async: false }.write_to(request).unwrap();
+                            rpc::send_args(request, service, tag, data).unwrap();
+                            debug!("comm->host RpcRequest {{ async: false }} (retained)");
+                            write_compressible(&mut **stream, capabilities, request)?;
+                        } else if capabilities & host::capability::COMPRESSION != 0 {
+                            let mut request = Vec::new();
+                            host::Reply::RpcRequest { async: async }.write_to(&mut request).unwrap();
+                            rpc::send_args(&mut request, service, tag, data).unwrap();
+                            debug!("comm->host RpcRequest {{ async: {} }}", async);
+                            write_compressible(&mut **stream, capabilities, &request)?;
+                        } else {
+                            host_write(stream, host::Reply::RpcRequest { async: async })?;
+                            let mut buffer = [0; 256];
+                            let mut writer = BufWriter::new(&mut **stream, &mut buffer);
+                            rpc::send_args(&mut writer, service, tag, data)?;
+                            writer.flush()?;
+                        }
                         if !async {
-                            session.kernel_state = KernelState::RpcWait
+                            session.kernel_state = KernelState::RpcWait;
+                            low_latency::rpc();
                         }
                         kern_acknowledge()
                     }
                 }
             },
+            &kern::ProfilerRequest => {
+                kern_send(io, &kern::ProfilerReply {
+                    interval_cycles: profiler::kernel_interval_cycles()
+                })
+            }
+            &kern::ProfileSamples { samples, dropped } => {
+                profiler::record_kernel(samples, dropped);
+                kern_acknowledge()
+            }
+
             &kern::RpcFlush => {
                 // See ksupport/lib.rs for the reason this request exists.
                 // We do not need to do anything here because of how the main loop is
@@ -409,8 +833,53 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                 kern_acknowledge()
             },
 
+            &kern::WatchdogSetRequest { ms, scope } => {
+                let id = match session.watchdog_set.set_ms(ms, scope) {
+                    Ok(id) => id,
+                    Err(()) => unexpected!("too many nested watchdogs")
+                };
+                kern_send(io, &kern::WatchdogSetReply { id: id })
+            }
+            &kern::WatchdogClear { id } => {
+                session.watchdog_set.clear(id);
+                kern_acknowledge()
+            }
+
+            &kern::FlashStorageGetRequest { key } => {
+                // The kernel CPU does not read the flash.
+                let value = flash_storage::read(key, |value| value.map(|value| value.to_vec()));
+                kern_send(io, &kern::FlashStorageGetReply {
+                    value: value.as_ref().map(|value| &value[..])
+                })
+            }
+
+            &kern::FlashStoragePutRequest { key, value } => {
+                let mut bytes = vec![0; value.len() * 4];
+                for (chunk, &word) in bytes.chunks_mut(4).zip(value.iter()) {
+                    NetworkEndian::write_i32(chunk, word)
+                }
+                let result = hw_watchdog::suspend(|| flash_storage::write(key, &bytes));
+                match result {
+                    Ok(()) => debug!("stored {} words under {} in flash", value.len(), key),
+                    Err(e) => warn!("cannot store {} in flash: {}", key, e)
+                }
+                kern_send(io, &kern::FlashStoragePutReply {
+                    error: result.err().map(|e| e.as_str())
+                })
+            }
+
+            &kern::LowLatencyRequest { duration_ms } => {
+                low_latency::request(duration_ms);
+                kern_acknowledge()
+            }
+
+            &kern::DdsSyncReport { channel, chip_select, in_delay, window, io_update_delay } => {
+                dds_sync::record(channel, chip_select, in_delay, window, io_update_delay);
+                kern_acknowledge()
+            }
+
             &kern::CacheGetRequest { key } => {
-                let value = session.congress.cache.get(key);
+                let value = session.congress.cache.borrow_mut().get(key);
                 kern_send(io, &kern::CacheGetReply {
                     // Zing! This transmute is only safe because we dynamically track
                     // whether the kernel has borrowed any values from the cache.
@@ -419,46 +888,86 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             }
 
             &kern::CachePutRequest { key, value } => {
-                let succeeded = session.congress.cache.put(key, value).is_ok();
-                kern_send(io, &kern::CachePutReply { succeeded: succeeded })
+                let result = session.congress.cache.borrow_mut().put(key, value);
+                kern_send(io, &kern::CachePutReply {
+                    succeeded: result.is_ok(),
+                    quota_exceeded: result == Err(::cache::Error::QuotaExceeded)
+                })
             }
 
             &kern::RunFinished => {
                 unsafe { kernel::stop() }
                 session.kernel_state = KernelState::Absent;
-                unsafe { session.congress.cache.unborrow() }
+                unsafe { session.congress.cache.borrow_mut().unborrow() }
+                let record = session.run.take().map(|run| run.finish(Outcome::Finished));
 
                 match stream {
                     None => return Ok(true),
-                    Some(ref mut stream) =>
+                    Some(ref mut stream) => {
+                        host_write_run_stats(stream, session.capabilities, record)?;
                         host_write(stream, host::Reply::KernelFinished).map_err(|e| e.into())
+                    }
                 }
             }
             &kern::RunException {
                 exception: kern::Exception { name, message, param, file, line, column, function },
+                context,
                 backtrace
             } => {
                 unsafe { kernel::stop() }
                 session.kernel_state = KernelState::Absent;
-                unsafe { session.congress.cache.unborrow() }
+                unsafe { session.congress.cache.borrow_mut().unborrow() }
+
+                let symbols: Vec<&str> = backtrace.iter()
+                    .map(|&addr| session.symbols.lookup(addr).unwrap_or(""))
+                    .collect();
+                rtio_mgt::count_exception(name, message, &param);
+                let record = session.run.take().map(|run| run.finish(Outcome::Exception));
+                let message = rtio_mgt::resolve_channel_names(message, &param);
+                let context_messages: Vec<String> = context.iter()
+                    .map(|exception| rtio_mgt::resolve_channel_names(exception.message,
+                                                                     &exception.param))
+                    .collect();
+                let context: Vec<kern::Exception> = context.iter().zip(context_messages.iter())
+                    .map(|(exception, message)| kern::Exception { message: message, ..*exception })
+                    .collect();
 
                 match stream {
                     None => {
+                        let mut report = String::new();
+                        for exception in context.iter() {
+                            report.push_str(&format!("during handling of {}: {} {:?}\n",
+                                                     exception.name, exception.message, exception.param));
+                        }
+                        report.push_str(&format!("{}: {} {:?}\n", name, message, param));
+                        report.push_str(&format!("at {}:{}:{} in {}\n", file, line, column, function));
+                        for (&addr, &symbol) in backtrace.iter().zip(symbols.iter()).rev() {
+                            report.push_str(&format!("  RA=+{:#x} in {}\n", addr,
+                                                     if symbol.is_empty() { "<unknown>" } else { symbol }));
+                        }
                         error!("exception in flash kernel");
-                        error!("{}: {} {:?}", name, message, param);
-                        error!("at {}:{}:{} in {}", file, line, column, function);
+                        for line in report.lines() {
+                            error!("{}", line);
+                        }
+                        session.exception_report = Some(report);
                         return Ok(true)
                     },
                     Some(ref mut stream) => {
-                        host_write(stream, host::Reply::KernelException {
+                        host_write_run_stats(&mut **stream, session.capabilities, record)?;
+                        host_write_compressible(&mut **stream, session.capabilities,
+                                                host::Reply::KernelException {
                             name:      name,
-                            message:   message,
+                            message:   &message,
                             param:     param,
                             file:      file,
                             line:      line,
                             column:    column,
                             function:  function,
-                            backtrace: backtrace
+                            backtrace: backtrace,
+                            details:   session.capabilities &
+                                           host::capability::EXCEPTION_DETAILS != 0,
+                            symbols:   &symbols,
+                            context:   &context
                         }).map_err(|e| e.into())
                     }
                 }
@@ -469,63 +978,308 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
     })
 }
 
+impl<'a> rpc::RpcWrite for TcpStream<'a> {}
+
+fn read_notification(chunk: &[u8]) -> Result<(u32, &str, &[u8]), Error<SchedError>> {
+    let length = (NetworkEndian::read_u32(&chunk[0..]) & !rpc_queue::NOTIFICATION) as usize;
+    let dropped = NetworkEndian::read_u32(&chunk[4..]);
+    let tag_len = NetworkEndian::read_u32(&chunk[8..]) as usize;
+    if tag_len > rpc_queue::NOTIFICATION_MAX {
+        unexpected!("malformed notification (tag of {} bytes)", tag_len)
+    }
+    let data_off = 12 + tag_len;
+    let data_len = NetworkEndian::read_u32(&chunk[data_off..]) as usize;
+    if length != rpc_queue::NOTIFICATION_FIELDS + tag_len + data_len ||
+            tag_len + data_len > rpc_queue::NOTIFICATION_MAX {
+        unexpected!("malformed notification ({} bytes)", length)
+    }
+    let tag = str::from_utf8(&chunk[12..12 + tag_len])
+        .map_err(|_| Error::Unexpected(String::from("non-UTF-8 notification tag")))?;
+    Ok((dropped, tag, &chunk[data_off + 4..data_off + 4 + data_len]))
+}
+
+fn dataset_batch_due(session: &Session) -> bool {
+    let batch = &session.dataset_batch;
+    batch.count > 0 &&
+        (batch.data.len() >= DATASET_BATCH_SIZE || clock::get_ms() >= batch.since + DATASET_BATCH_MS)
+}
+
+fn flush_dataset_batch(stream: &mut TcpStream,
+                       session: &mut Session) -> Result<(), Error<SchedError>> {
+    if session.dataset_batch.count == 0 {
+        return Ok(())
+    }
+    host_write_compressible(stream, session.capabilities, host::Reply::DatasetAppend {
+        count: session.dataset_batch.count,
+        data:  &session.dataset_batch.data
+    })?;
+    session.dataset_batch.data.clear();
+    session.dataset_batch.count = 0;
+    Ok(())
+}
+
 fn process_kern_queued_rpc(stream: &mut TcpStream,
-                           _session: &mut Session) -> Result<(), Error<SchedError>> {
-    rpc_queue::dequeue(|slice| {
+                           session: &mut Session) -> Result<(), Error<SchedError>> {
+    rpc_queue::dequeue(|chunk| {
+        let header = NetworkEndian::read_u32(chunk);
+        if header & rpc_queue::DATASET_APPEND != 0 {
+            if session.capabilities & host::capability::DATASET_APPEND == 0 {
+                debug!("dropping dataset value, not supported by the host");
+                return Ok(())
+            }
+            // The chunk holds the key and the value as they are sent.
+            let length = (header & !rpc_queue::DATASET_APPEND) as usize;
+            let batch = &mut session.dataset_batch;
+            if batch.count == 0 {
+                batch.since = clock::get_ms();
+            }
+            batch.data.extend_from_slice(&chunk[4..4 + length]);
+            batch.count += 1;
+            return Ok(())
+        }
+        flush_dataset_batch(stream, session)?;
+
+        if header & rpc_queue::NOTIFICATION != 0 {
+            let (dropped, tag, data) = read_notification(chunk)?;
+            if session.capabilities & host::capability::NOTIFICATIONS == 0 {
+                debug!("dropping notification {:?}, not supported by the host", tag);
+                return Ok(())
+            }
+            return host_write(stream, host::Reply::Notification {
+                dropped: dropped,
+                tag:     tag,
+                data:    data
+            }).map_err(|e| e.into())
+        }
+
         debug!("comm<-kern (async RPC)");
-        let length = NetworkEndian::read_u32(slice) as usize;
-        host_write(stream, host::Reply::RpcRequest { async: true })?;
-        debug!("{:?}", &slice[4..][..length]);
-        stream.write_all(&slice[4..][..length])?;
+        let length = (header & !(rpc_queue::CONTINUED | rpc_queue::REFERENCE)) as usize;
+        if session.async_rpc_discarded {
+            session.async_rpc_discarded = header & rpc_queue::CONTINUED != 0;
+            return Ok(())
+        }
+        if !session.async_rpc_continued {
+            host_write(stream, host::Reply::RpcRequest { async: true })?;
+        }
+        session.async_rpc_continued = header & rpc_queue::CONTINUED != 0;
+        if header & rpc_queue::REFERENCE != 0 {
+            // Send the kernel buffer directly; the kernel waits for the queue
+            // to drain before touching it again.
+            let address = NetworkEndian::read_u32(&chunk[4..]) as usize;
+            let length = NetworkEndian::read_u32(&chunk[8..]) as usize;
+            debug!("<{} bytes at {:#08x}>", length, address);
+            stream.write_all(unsafe { slice::from_raw_parts(address as *const u8, length) })?;
+        } else {
+            debug!("{:?}", &chunk[4..][..length]);
+            stream.write_all(&chunk[4..][..length])?;
+        }
         Ok(())
     })
 }
 
-fn host_kernel_worker(io: &Io, aux_mutex: &Mutex,
-                      routing_table: &drtio_routing::RoutingTable,
-                      up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
-                      stream: &mut TcpStream,
-                      congress: &mut Congress) -> Result<(), Error<SchedError>> {
-    let mut session = Session::new(congress);
+// Returns false once the host has closed the connection.
+fn host_kernel_step(io: &Io, aux_mutex: &Mutex,
+                    routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+                    up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+                    stream: &mut TcpStream,
+                    session: &mut Session) -> Result<bool, Error<SchedError>> {
+    if stream.can_recv() {
+        process_host_message(io, stream, session)?
+    } else if !stream.may_recv() {
+        return Ok(false)
+    }
 
-    loop {
-        if stream.can_recv() {
-            process_host_message(io, stream, &mut session)?
-        } else if !stream.may_recv() {
-            return Ok(())
+    while !rpc_queue::empty() {
+        process_kern_queued_rpc(stream, session)?
+    }
+
+    if dataset_batch_due(session) || kern_needs_host() {
+        flush_dataset_batch(stream, session)?
+    }
+
+    if mailbox::receive() != 0 {
+        process_kern_message(io, aux_mutex,
+            routing_table, up_destinations,
+            Some(stream), session)?;
+    }
+
+    if session.kernel_state == KernelState::Running {
+        if let Some(expired) = session.watchdog_set.expired() {
+            flush_dataset_batch(stream, session)?;
+            host_write(stream, host::Reply::WatchdogExpired {
+                scope:      expired.scope,
+                timeout_ms: expired.timeout_ms,
+                elapsed_ms: expired.elapsed_ms
+            })?;
+            return Err(watchdog_expired(&expired))
         }
 
-        while !rpc_queue::empty() {
-            process_kern_queued_rpc(stream, &mut session)?
+        if !rtio_clocking::crg::check() {
+            flush_dataset_batch(stream, session)?;
+            host_write(stream, host::Reply::ClockFailure)?;
+            return Err(Error::ClockFailure)
         }
+    }
 
-        if mailbox::receive() != 0 {
+    if session.running() {
+        if let Some(reason) = termination_due() {
+            flush_dataset_batch(stream, session)?;
+            host_write(stream, host::Reply::KernelTerminated { reason: &reason })?;
+            return Err(terminated(reason))
+        }
+    }
+
+    io.relinquish()?;
+    Ok(true)
+}
+
+fn host_lost(error: &Error<SchedError>) -> bool {
+    match error {
+        &Error::Protocol(host::Error::Io(IoError::UnexpectedEnd)) |
+        &Error::Protocol(host::Error::Io(IoError::Other(SchedError::TimedOut))) |
+        &Error::Protocol(host::Error::Io(IoError::Other(SchedError::Network(_)))) => true,
+        _ => false
+    }
+}
+
+// Whether the pending kernel CPU message can only be handled with a host
+// connection, and so has to wait until the host reconnects.
+fn kern_needs_host() -> bool {
+    let message = mailbox::receive();
+    if message == 0 || !kernel::validate(message) {
+        return false
+    }
+    match unsafe { &*(message as *const kern::Message) } {
+        &kern::RpcSend { .. } | &kern::RunFinished | &kern::RunException { .. } => true,
+        _ => false
+    }
+}
+
+// Keeps the kernel running without a host connection for up to `grace_ms`,
+// and returns the connection of the host if it reconnects in time.
+fn wait_for_host(io: &Io, aux_mutex: &Mutex,
+                 routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+                 up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+                 session: &mut Session,
+                 grace_ms: u64) -> Result<Option<TcpSocketHandle>, Error<SchedError>> {
+    let resumption = session.congress.resumption.clone();
+    let deadline = clock::get_ms() + grace_ms;
+
+    resumption.borrow_mut().detached = true;
+    let result = (|| loop {
+        if let Some(handle) = resumption.borrow_mut().stream.take() {
+            return Ok(Some(handle))
+        }
+        if clock::get_ms() > deadline {
+            return Ok(None)
+        }
+
+        if mailbox::receive() != 0 && !kern_needs_host() {
             process_kern_message(io, aux_mutex,
                 routing_table, up_destinations,
-                Some(stream), &mut session)?;
+                None, session)?;
         }
 
-        if session.kernel_state == KernelState::Running {
-            if !rtio_clocking::crg::check() {
-                host_write(stream, host::Reply::ClockFailure)?;
-                return Err(Error::ClockFailure)
-            }
+        if let Some(expired) = session.watchdog_set.expired() {
+            return Err(watchdog_expired(&expired))
+        }
+        if let Some(reason) = termination_due() {
+            return Err(terminated(reason))
+        }
+        if !rtio_clocking::crg::check() {
+            return Err(Error::ClockFailure)
         }
 
         io.relinquish()?
+    })();
+    resumption.borrow_mut().detached = false;
+    result
+}
+
+fn resume_session(stream: &mut TcpStream, session: &mut Session) -> Result<(), Error<SchedError>> {
+    host_write(stream, host::Reply::SessionResumed)?;
+    if session.async_rpc_continued {
+        warn!("async RPC interrupted by the disconnection was lost");
+        session.async_rpc_continued = false;
+        session.async_rpc_discarded = true;
+    }
+    if session.kernel_state == KernelState::RpcWait {
+        debug!("comm->host RpcRequest {{ async: false }} (replayed)");
+        stream.write_all(&session.pending_rpc)?;
     }
+    Ok(())
 }
 
+fn host_kernel_worker<'a>(io: &'a Io<'a>, aux_mutex: &Mutex,
+                          routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+                          up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+                          stream: &mut TcpStream<'a>,
+                          congress: &mut Congress) -> Result<(), Error<SchedError>> {
+    // Not resumed with the token of a previous session.
+    congress.resumption.borrow_mut().token = None;
+    let mut session = Session::new(congress);
+    let grace_ms = config::read_str("session_grace_ms", |r| r.ok().and_then(|s| s.parse().ok()))
+                          .unwrap_or(0);
+    session.resumable = grace_ms > 0;
+
+    loop {
+        let connected = match host_kernel_step(io, aux_mutex, routing_table, up_destinations,
+                                               stream, &mut session) {
+            Ok(connected) => connected,
+            Err(ref err) if session.resumable && session.running() && host_lost(err) => false,
+            Err(err) => {
+                if session.running() && host_lost(&err) {
+                    safe_state::trigger(safe_state::Cause::HostLost);
+                }
+                return Err(err)
+            }
+        };
+        if connected {
+            continue
+        }
+        if !(session.resumable && session.running()) {
+            if session.running() {
+                safe_state::trigger(safe_state::Cause::HostLost);
+            }
+            return Ok(())
+        }
+
+        warn!("host connection lost, waiting {} ms for it to reconnect", grace_ms);
+        match wait_for_host(io, aux_mutex, routing_table, up_destinations,
+                            &mut session, grace_ms)? {
+            Some(handle) => {
+                *stream = TcpStream::from_handle(io, handle);
+                info!("host reconnected from {}, resuming session", stream.remote_endpoint());
+                resume_session(stream, &mut session)?
+            }
+            None => {
+                warn!("host did not reconnect, terminating kernel");
+                safe_state::trigger(safe_state::Cause::HostLost);
+                return Ok(())
+            }
+        }
+    }
+}
+
+// Runs the kernel stored under `config_key`, stopping it after `timeout_s`
+// seconds unless it is 0. An uncaught exception is returned as an error
+// with its report.
 fn flash_kernel_worker(io: &Io, aux_mutex: &Mutex,
-                       routing_table: &drtio_routing::RoutingTable,
+                       routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
                        up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
                        congress: &mut Congress,
-                       config_key: &str) -> Result<(), Error<SchedError>> {
+                       config_key: &str, timeout_s: u64) -> Result<(), Error<SchedError>> {
     let mut session = Session::new(congress);
 
     config::read(config_key, |result| {
         match result {
             Ok(kernel) if kernel.len() > 0 => unsafe {
+                let heap = ::heap_stats();
+                if kernel.len() > heap.largest_free {
+                    error!("not enough memory to load {} ({} bytes): heap {}",
+                           config_key, kernel.len(), heap);
+                    return Err(Error::Load(String::from("not enough memory")))
+                }
                 // kernel CPU cannot access the SPI flash address space directly,
                 // so make a copy.
                 kern_load(io, &mut session, Vec::from(kernel).as_ref())
@@ -533,16 +1287,50 @@ fn flash_kernel_worker(io: &Io, aux_mutex: &Mutex,
             _ => Err(Error::KernelNotFound)
         }
     })?;
-    kern_run(&mut session)?;
+    kern_run(&mut session, config_key)?;
+    let deadline = clock::get_ms() + timeout_s * 1000;
 
     loop {
+        if timeout_s != 0 && clock::get_ms() > deadline {
+            unsafe { kernel::stop() }
+            session.kernel_state = KernelState::Absent;
+            unsafe { session.congress.cache.borrow_mut().unborrow() }
+            return Err(Error::TimedOut(timeout_s))
+        }
+
         if !rpc_queue::empty() {
-            unexpected!("unexpected background RPC in flash kernel")
+            rpc_queue::dequeue(|chunk| {
+                let header = NetworkEndian::read_u32(chunk);
+                if header & rpc_queue::DATASET_APPEND != 0 {
+                    debug!("dropping dataset value from flash kernel");
+                    return Ok(())
+                }
+                if header & rpc_queue::NOTIFICATION == 0 {
+                    unexpected!("unexpected background RPC in flash kernel")
+                }
+                // There is no host to deliver notifications to.
+                let (_, tag, _) = read_notification(chunk)?;
+                debug!("dropping notification {:?} from flash kernel", tag);
+                Ok(())
+            })?
+        }
+
+        if let Some(expired) = session.watchdog_set.expired() {
+            return Err(watchdog_expired(&expired))
+        }
+        if let Some(reason) = termination_due() {
+            unsafe { kernel::stop() }
+            session.kernel_state = KernelState::Absent;
+            unsafe { session.congress.cache.borrow_mut().unborrow() }
+            return Err(terminated(reason))
         }
 
         if mailbox::receive() != 0 {
             if process_kern_message(io, aux_mutex, routing_table, up_destinations, None, &mut session)? {
-                return Ok(())
+                return match session.exception_report.take() {
+                    Some(report) => Err(Error::UncaughtException(report)),
+                    None => Ok(())
+                }
             }
         }
 
@@ -554,6 +1342,69 @@ fn flash_kernel_worker(io: &Io, aux_mutex: &Mutex,
     }
 }
 
+const IDLE_KERNEL_SLOTS: usize = 8;
+
+// Finds the first non-empty `idle_kernelN` key at or after `start`,
+// wrapping around, so that several idle kernels run in round-robin order.
+fn next_idle_kernel(start: usize) -> Option<usize> {
+    (0..IDLE_KERNEL_SLOTS)
+        .map(|offset| (start + offset) % IDLE_KERNEL_SLOTS)
+        .find(|&slot| {
+            config::read(&format!("idle_kernel{}", slot), |result| {
+                match result {
+                    Ok(kernel) => kernel.len() > 0,
+                    Err(_) => false
+                }
+            })
+        })
+}
+
+fn read_only_worker(io: &Io, stream: &mut TcpStream,
+                    finished_cleanly: &Cell<bool>) -> Result<(), Error<SchedError>> {
+    let mut capabilities = host::capability::DEFAULT;
+    loop {
+        match host_read(stream)? {
+            host::Request::Hello { version, capabilities: requested } =>
+                capabilities = negotiate(stream, version, requested, None)?,
+
+            host::Request::SystemInfo =>
+                host_write(stream, host::Reply::SystemInfo {
+                    ident: ident::read(&mut [0; 64]),
+                    finished_cleanly: finished_cleanly.get(),
+                    extended: capabilities & host::capability::SYSTEM_INFO_EXTENDED != 0,
+                    board: board_identity::get(),
+                    boot_stats: boot_stats::current()
+                        .map(|stats| (stats.boot_count, stats.uptime_s))
+                })?,
+
+            host::Request::GetLog =>
+                BufferLogger::with(|logger| -> Result<(), Error<SchedError>> {
+                    let mut buffer = io.until_ok(|| logger.buffer())?;
+                    host_write_compressible(stream, capabilities,
+                                            host::Reply::Log(buffer.extract()))?;
+                    Ok(())
+                })?,
+
+            host::Request::GetHealth =>
+                host_write(stream, host::Reply::Health {
+                    rtio_clock_ok: rtio_clocking::crg::check(),
+                    rtio_frequency: rtio_clocking::measured_frequency(),
+                    clock_fallback: rtio_clocking::clock_fallback_active(),
+                    uptime_ms: clock::get_ms()
+                })?,
+
+            #[cfg(has_rtio_analyzer)]
+            host::Request::GetAnalyzerDump => {
+                host_write(stream, host::Reply::AnalyzerDump)?;
+                ::analyzer::dump(io, stream)?;
+            }
+
+            // Anything that would affect the kernel session.
+            _ => host_write(stream, host::Reply::Unavailable)?
+        }
+    }
+}
+
 fn respawn<F>(io: &Io, handle: &mut Option<ThreadHandle>, f: F)
         where F: 'static + FnOnce(Io) + Send {
     match handle.take() {
@@ -569,26 +1420,174 @@ fn respawn<F>(io: &Io, handle: &mut Option<ThreadHandle>, f: F)
     *handle = Some(io.spawn(16384, f))
 }
 
+/// Line added to the panic log when the core device panics while the
+/// startup kernel runs, so that the kernel is not run again at the next
+/// boot, which would likely panic the same way.
+pub const STARTUP_KERNEL_PANIC: &'static str = "panic while running the startup kernel";
+const DEFAULT_STARTUP_KERNEL_TIMEOUT_S: u64 = 60;
+
+static mut STARTUP_KERNEL_RUNNING: bool = false;
+
+pub fn startup_kernel_running() -> bool {
+    unsafe { STARTUP_KERNEL_RUNNING }
+}
+
+#[cfg(has_spiflash)]
+fn startup_kernel_crashed() -> bool {
+    board_misoc::panic_log::read().map_or(false, |log| log.contains(STARTUP_KERNEL_PANIC))
+}
+
+#[cfg(not(has_spiflash))]
+fn startup_kernel_crashed() -> bool {
+    false
+}
+
+// Time in seconds after which the startup kernel is stopped, from the
+// `startup_kernel_timeout` config entry; 0 lets it run indefinitely.
+fn startup_kernel_timeout() -> u64 {
+    match config::read_str("startup_kernel_timeout", |r| r.map(|s| s.parse())) {
+        Ok(Ok(timeout)) => timeout,
+        Ok(Err(_)) => {
+            warn!("invalid startup_kernel_timeout, using {} s", DEFAULT_STARTUP_KERNEL_TIMEOUT_S);
+            DEFAULT_STARTUP_KERNEL_TIMEOUT_S
+        }
+        Err(_) => DEFAULT_STARTUP_KERNEL_TIMEOUT_S
+    }
+}
+
+static mut HOST_CONNECTED: bool = false;
+
+/// Whether a host holds a kernel session, as opposed to a startup or idle
+/// kernel running, or nothing at all.
+pub fn host_connected() -> bool {
+    unsafe { HOST_CONNECTED }
+}
+
+// Kernel images uploaded over mgmt are kept in the configuration under
+// `kernel_image/<name>`, and run on request when no host holds a session,
+// e.g. for autonomous operation or recovery routines.
+const MAX_KERNEL_IMAGE_NAME_LENGTH: usize = 32;
+
+static mut STORED_KERNEL_REQUEST: Option<String> = None;
+
+/// Configuration key of the stored kernel image `name`, if the name is
+/// valid.
+pub fn kernel_image_key(name: &str) -> Option<String> {
+    if name.is_empty() || name.len() > MAX_KERNEL_IMAGE_NAME_LENGTH ||
+            name.contains('/') {
+        return None
+    }
+    Some(format!("kernel_image/{}", name))
+}
+
+/// Names of the stored kernel images, with their sizes.
+pub fn kernel_images() -> Result<Vec<(String, u32)>, config::Error> {
+    let mut images = Vec::new();
+    config::iter(|key, size| {
+        if key.starts_with(b"kernel_image/") && size > 0 {
+            images.push((String::from_utf8_lossy(&key[13..]).into_owned(), size as u32))
+        }
+    })?;
+    Ok(images)
+}
+
+/// Requests to run the stored kernel image `name` in place of the startup
+/// or idle kernel.
+pub fn run_stored_kernel(name: &str) -> Result<(), &'static str> {
+    let key = kernel_image_key(name).ok_or("invalid kernel image name")?;
+    if !config::read(&key, |result| result.map(|image| image.len() > 0).unwrap_or(false)) {
+        return Err("no such kernel image")
+    }
+    if host_connected() {
+        return Err("a host holds a kernel session")
+    }
+    if shutdown::requested() {
+        return Err("the core device is being powered off")
+    }
+    unsafe { STORED_KERNEL_REQUEST = Some(String::from(name)) }
+    Ok(())
+}
+
+// Time a new connection has to send its magic.
+const MAGIC_TIMEOUT_MS: u64 = 10_000;
+
+// Reads the magic of a new connection and authenticates its host. Returns
+// the kind of session requested if the host may proceed.
+// Returns the kind of session requested by the host, with the token of the
+// session to resume for `SessionKind::Resume`.
+fn admit(stream: &mut TcpStream) -> Option<(host::SessionKind, [u8; host::TOKEN_SIZE])> {
+    let mut magic = [0; host::MAGIC_SIZE];
+    if let Err(err) = stream.read_exact_until(&mut magic, clock::get_ms() + MAGIC_TIMEOUT_MS) {
+        warn!("no magic from {}: {}", stream.remote_endpoint(), err);
+        return None
+    }
+    let kind = match host::read_magic(&mut Cursor::new(&magic[..])) {
+        Ok(kind) => kind,
+        Err(_) => {
+            warn!("wrong magic from {}", stream.remote_endpoint());
+            return None
+        }
+    };
+    if stream.write_all("E".as_bytes()).is_err() {
+        warn!("cannot send endian byte");
+        return None
+    }
+    match auth::handshake(stream) {
+        Ok(true) => {
+            let mut token = [0; host::TOKEN_SIZE];
+            if kind == host::SessionKind::Resume {
+                if let Err(err) = stream.read_exact_until(&mut token,
+                                                          clock::get_ms() + MAGIC_TIMEOUT_MS) {
+                    warn!("no session token from {}: {}", stream.remote_endpoint(), err);
+                    return None
+                }
+            }
+            Some((kind, token))
+        }
+        Ok(false) => {
+            audit::record(Service::Session, Event::Disconnected, stream.remote_endpoint(),
+                          "authentication failed");
+            None
+        }
+        Err(err) => {
+            warn!("authentication of {} aborted: {}", stream.remote_endpoint(), err);
+            None
+        }
+    }
+}
+
 pub fn thread(io: Io, aux_mutex: &Mutex,
         routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
-        up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
-    let listener = TcpListener::new(&io, 65535);
+        up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+        cache: &Urc<RefCell<Cache>>) {
+    let listener = TcpListener::new(&io, ::socket_buffer_size("session", 65535));
     listener.listen(1381).expect("session: cannot listen");
     info!("accepting network sessions");
 
-    let congress = Urc::new(RefCell::new(Congress::new()));
+    let congress = Urc::new(RefCell::new(Congress::new(cache.clone())));
+    let finished_cleanly = congress.borrow().finished_cleanly.clone();
+    let resumption = congress.borrow().resumption.clone();
 
     let mut kernel_thread = None;
+    let mut idle_slot = 0;
     {
         let aux_mutex = aux_mutex.clone();
         let routing_table = routing_table.clone();
         let up_destinations = up_destinations.clone();
         let congress = congress.clone();
         respawn(&io, &mut kernel_thread, move |io| {
-            let routing_table = routing_table.borrow();
             let mut congress = congress.borrow_mut();
+            if startup_kernel_crashed() {
+                error!("the core device panicked while running the startup kernel at the \
+                        previous boot; not running it until the panic log is cleared");
+                return
+            }
             info!("running startup kernel");
-            match flash_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, &mut congress, "startup_kernel") {
+            unsafe { STARTUP_KERNEL_RUNNING = true }
+            let result = flash_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations,
+                                             &mut congress, "startup_kernel", startup_kernel_timeout());
+            unsafe { STARTUP_KERNEL_RUNNING = false }
+            match result {
                 Ok(()) =>
                     info!("startup kernel finished"),
                 Err(Error::KernelNotFound) =>
@@ -596,34 +1595,99 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
                 Err(err) => {
                     congress.finished_cleanly.set(false);
                     error!("startup kernel aborted: {}", err);
+                    match err {
+                        Error::UncaughtException(report) =>
+                            ::save_startup_kernel_failure(&format!("uncaught exception\n{}", report)),
+                        err =>
+                            ::save_startup_kernel_failure(&format!("{}\n", err))
+                    }
                 }
             }
         })
     }
 
+    let admitted = Urc::new(RefCell::new(Vec::new()));
     loop {
         if listener.can_accept() {
             let mut stream = listener.accept().expect("session: cannot accept");
-            stream.set_timeout(Some(2250));
-            stream.set_keep_alive(Some(500));
+            stream.set_liveness_from_config("session", 500, 2250);
 
-            match host::read_magic(&mut stream) {
-                Ok(()) => (),
-                Err(_) => {
-                    warn!("wrong magic from {}", stream.remote_endpoint());
-                    stream.close().expect("session: cannot close");
-                    continue
+            // The host is authenticated by a thread of its own, so that a host
+            // that does not answer does not hold back other connections.
+            let admitted = admitted.clone();
+            let stream = stream.into_handle();
+            io.spawn_with_priority(Priority::Low, 8192, move |io| {
+                let mut stream = TcpStream::from_handle(&io, stream);
+                match admit(&mut stream) {
+                    Some((kind, token)) =>
+                        admitted.borrow_mut().push((kind, token, stream.into_handle())),
+                    None => stream.close().expect("session: cannot close")
                 }
+            });
+        }
+
+        let next_admitted = {
+            let mut admitted = admitted.borrow_mut();
+            if admitted.is_empty() { None } else { Some(admitted.remove(0)) }
+        };
+        if let Some((kind, token, stream)) = next_admitted {
+            let mut stream = TcpStream::from_handle(&io, stream);
+
+            if shutdown::requested() && kind != host::SessionKind::ReadOnly {
+                warn!("refusing the connection from {}, the core device is being powered off",
+                      stream.remote_endpoint());
+                audit::record(Service::Session, Event::Disconnected, stream.remote_endpoint(),
+                              "refused, powering off");
+                stream.close().expect("session: cannot close");
+                continue
             }
-            match stream.write_all("E".as_bytes()) {
-                Ok(()) => (),
-                Err(_) => {
-                    warn!("cannot send endian byte");
+
+            if kind == host::SessionKind::Resume {
+                let mut resumption = resumption.borrow_mut();
+                let own_session = resumption.token
+                    .map_or(false, |expected| auth::constant_time_eq(&expected, &token));
+                if own_session && resumption.detached && resumption.stream.is_none() {
+                    audit::record(Service::Session, Event::Connected, stream.remote_endpoint(),
+                                  "resuming the detached session");
+                    resumption.stream = Some(stream.into_handle());
+                } else {
+                    // The host tries again until its timeout: the session may
+                    // not have noticed yet that its connection was lost.
+                    if own_session {
+                        warn!("no detached session to resume for {}", stream.remote_endpoint());
+                    } else {
+                        warn!("wrong session token from {}", stream.remote_endpoint());
+                    }
+                    let _ = host_write(&mut stream, host::Reply::Unavailable);
                     stream.close().expect("session: cannot close");
-                    continue
                 }
+                continue
+            }
+
+            if kind == host::SessionKind::ReadOnly {
+                info!("new read-only connection from {}", stream.remote_endpoint());
+                let mut connection = audit::Connection::open(Service::Session,
+                                                             stream.remote_endpoint(), "read-only");
+                let finished_cleanly = finished_cleanly.clone();
+                let stream = stream.into_handle();
+                io.spawn_with_priority(Priority::Low, 16384, move |io| {
+                    let mut stream = TcpStream::from_handle(&io, stream);
+                    match read_only_worker(&io, &mut stream, &finished_cleanly) {
+                        Ok(()) => (),
+                        Err(Error::Protocol(host::Error::Io(IoError::UnexpectedEnd))) =>
+                            info!("read-only connection closed"),
+                        Err(err) => {
+                            error!("read-only session aborted: {}", err);
+                            connection.end(format!("aborted: {}", err))
+                        }
+                    }
+                });
+                continue
             }
             info!("new connection from {}", stream.remote_endpoint());
+            let mut connection = audit::Connection::open(Service::Session,
+                                                         stream.remote_endpoint(), "");
+            safe_state::release();
 
             let aux_mutex = aux_mutex.clone();
             let routing_table = routing_table.clone();
@@ -631,35 +1695,79 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
             let congress = congress.clone();
             let stream = stream.into_handle();
             respawn(&io, &mut kernel_thread, move |io| {
-                let routing_table = routing_table.borrow();
                 let mut congress = congress.borrow_mut();
                 let mut stream = TcpStream::from_handle(&io, stream);
-                match host_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, &mut stream, &mut *congress) {
+                unsafe { HOST_CONNECTED = true }
+                let result = host_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations,
+                                                &mut stream, &mut *congress);
+                unsafe { HOST_CONNECTED = false }
+                match result {
                     Ok(()) => (),
                     Err(Error::Protocol(host::Error::Io(IoError::UnexpectedEnd))) =>
                         info!("connection closed"),
                     Err(Error::Protocol(host::Error::Io(
-                            IoError::Other(SchedError::Interrupted)))) =>
-                        info!("kernel interrupted"),
+                            IoError::Other(SchedError::Interrupted)))) => {
+                        info!("kernel interrupted");
+                        connection.end(String::from("interrupted by another connection"))
+                    }
                     Err(err) => {
                         congress.finished_cleanly.set(false);
                         error!("session aborted: {}", err);
+                        connection.end(format!("aborted: {}", err))
                     }
                 }
             });
         }
 
-        if kernel_thread.as_ref().map_or(true, |h| h.terminated()) {
-            info!("no connection, starting idle kernel");
+        if let Some(name) = unsafe { STORED_KERNEL_REQUEST.take() } {
+            if !host_connected() && !shutdown::requested() {
+                info!("running stored kernel {}", name);
+                let aux_mutex = aux_mutex.clone();
+                let routing_table = routing_table.clone();
+                let up_destinations = up_destinations.clone();
+                let congress = congress.clone();
+                respawn(&io, &mut kernel_thread, move |io| {
+                    let mut congress = congress.borrow_mut();
+                    let config_key = kernel_image_key(&name).unwrap();
+                    match flash_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations,
+                                              &mut *congress, &config_key, 0) {
+                        Ok(()) =>
+                            info!("stored kernel {} finished", name),
+                        Err(Error::Protocol(host::Error::Io(
+                                IoError::Other(SchedError::Interrupted)))) =>
+                            info!("stored kernel {} interrupted", name),
+                        Err(Error::KernelNotFound) =>
+                            warn!("stored kernel {} was removed", name),
+                        Err(err) => {
+                            congress.finished_cleanly.set(false);
+                            error!("stored kernel {} aborted: {}", name, err);
+                        }
+                    }
+                })
+            }
+        }
+
+        if kernel_thread.as_ref().map_or(true, |h| h.terminated()) && !shutdown::requested() {
+            let config_key = match next_idle_kernel(idle_slot) {
+                Some(slot) => {
+                    info!("no connection, starting idle kernel {}", slot);
+                    idle_slot = slot + 1;
+                    format!("idle_kernel{}", slot)
+                }
+                None => {
+                    info!("no connection, starting idle kernel");
+                    String::from("idle_kernel")
+                }
+            };
 
             let aux_mutex = aux_mutex.clone();
             let routing_table = routing_table.clone();
             let up_destinations = up_destinations.clone();
             let congress = congress.clone();
             respawn(&io, &mut kernel_thread, move |io| {
-                let routing_table = routing_table.borrow();
                 let mut congress = congress.borrow_mut();
-                match flash_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, &mut *congress, "idle_kernel") {
+                match flash_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations,
+                                          &mut *congress, &config_key, 0) {
                     Ok(()) =>
                         info!("idle kernel finished, standing by"),
                     Err(Error::Protocol(host::Error::Io(
@@ -675,6 +1783,8 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
             })
         }
 
+        safe_state::poll(&io, aux_mutex, routing_table);
+
         let _ = io.relinquish();
     }
 }
@@ -0,0 +1,51 @@
+use board_misoc::sfp::{self, Event, Monitor};
+use sched::Priority;
+use {nrt_bus, timers};
+use status_led::{self, Alarm};
+use alerts::{self, Severity, Source};
+use mgmt_proto::SfpDiagnostics;
+use health_record::RecordSet;
+
+const POLL_INTERVAL_MS: u64 = 5000;
+
+pub fn init() {
+    if sfp::count() == 0 {
+        return
+    }
+
+    let mut monitor = Monitor::new();
+    timers::register("sfp_monitor", POLL_INTERVAL_MS * 1000, Priority::Low, move |io| {
+        let result = nrt_bus::with_i2c(io, nrt_bus::MAIN_BUS, || {
+            monitor.poll(|index, event| {
+                let severity = match event {
+                    Event::Removed | Event::AlarmRaised(_) => {
+                        warn!("SFP{}: {}", index, event);
+                        Severity::Warning
+                    }
+                    Event::Inserted | Event::AlarmCleared(_) => {
+                        info!("SFP{}: {}", index, event);
+                        Severity::Info
+                    }
+                };
+                // The diagnostics at the time of the event, if any.
+                let mut records = RecordSet::new();
+                if let Ok(sfp::Status { diagnostics: Some(d), alarms, .. }) = sfp::status(index) {
+                    records.sfp(index as u8, &SfpDiagnostics {
+                        temperature: d.temperature,
+                        voltage:     d.voltage,
+                        tx_bias:     d.tx_bias,
+                        tx_power:    d.tx_power,
+                        rx_power:    d.rx_power,
+                        alarms:      alarms
+                    });
+                }
+                alerts::push_with_records(severity, Source::Sfp,
+                                          format!("SFP{}: {}", index, event), records);
+            });
+            status_led::set_alarm(Alarm::Sfp, monitor.alarm());
+        });
+        if let Err(e) = result {
+            debug!("SFP polling skipped: {}", e)
+        }
+    })
+}
@@ -0,0 +1,93 @@
+use board_misoc::{clock, boot_stats, sfp};
+use logger_artiq::BufferLogger;
+use sched::{Io, Error as SchedError};
+use {kernel, session, safe_state, audit, nrt_bus};
+
+// Preparation of the core device for a power-off, e.g. by a rack power
+// controller: the running kernel is terminated, the outputs listed in the
+// `safe_state` config key are driven to their safe state, the uptime and
+// the audit log entry of the request are saved to flash, the log records
+// pending for the syslog server are sent, and the transmitters of the SFP
+// ports other than SFP0, which carries Ethernet, are disabled. Afterwards,
+// no kernel is started and no new kernel session is accepted, until the core
+// device is restarted.
+
+// Time given to the kernel to end after its termination is requested; the
+// session stops it after `kernel_termination_grace_ms`.
+const KERNEL_TIMEOUT_MS: u64 = 5000;
+const SAFE_STATE_TIMEOUT_MS: u64 = 1000;
+const SYSLOG_TIMEOUT_MS: u64 = 1000;
+
+static mut REQUESTED: bool = false;
+
+/// Whether the core device is being prepared for a power-off.
+pub fn requested() -> bool {
+    unsafe { REQUESTED }
+}
+
+pub struct Report {
+    pub kernel_stopped:     bool,
+    pub safe_state_outputs: u32,
+    pub sfp_tx_disabled:    u8
+}
+
+fn syslog_sent() -> bool {
+    BufferLogger::with(|logger| logger.syslog_buffer().map(|buffer| buffer.is_empty()))
+        .unwrap_or(true)
+}
+
+/// Prepares the core device for a power-off. May be called again, e.g. if
+/// the host lost the reply.
+pub fn prepare(io: &Io) -> Result<Report, SchedError> {
+    unsafe { REQUESTED = true }
+    warn!("preparing for power-off");
+
+    let kernel_stopped = session::request_termination("the core device is being powered off");
+    if kernel_stopped {
+        let deadline = clock::get_ms() + KERNEL_TIMEOUT_MS;
+        io.until(|| !kernel::running() || clock::get_ms() >= deadline)?;
+        if kernel::running() {
+            error!("the kernel did not end")
+        }
+    }
+
+    // Applied by the session thread.
+    safe_state::trigger(safe_state::Cause::PowerOff);
+    let deadline = clock::get_ms() + SAFE_STATE_TIMEOUT_MS;
+    io.until(|| safe_state::applied().is_some() || clock::get_ms() >= deadline)?;
+    let safe_state_outputs = match safe_state::applied() {
+        Some(outputs) => outputs as u32,
+        None => {
+            error!("the safe state was not applied in time");
+            0
+        }
+    };
+
+    let mut sfp_tx_disabled = 0;
+    let result = nrt_bus::with_i2c(io, nrt_bus::MAIN_BUS, || {
+        for index in 1..sfp::tx_count() {
+            match sfp::set_tx_enable(index, false) {
+                Ok(()) => sfp_tx_disabled |= 1 << index,
+                Err(e) => debug!("SFP{}: cannot disable the transmitter: {}", index, e)
+            }
+        }
+    });
+    if let Err(e) = result {
+        warn!("cannot disable the SFP transmitters: {}", e)
+    }
+
+    boot_stats::save_uptime();
+    audit::save_restart();
+
+    info!("ready for power-off");
+    let deadline = clock::get_ms() + SYSLOG_TIMEOUT_MS;
+    io.until(|| syslog_sent() || clock::get_ms() >= deadline)?;
+    // Lets the main loop drive the TX_DISABLE lines.
+    io.relinquish()?;
+
+    Ok(Report {
+        kernel_stopped:     kernel_stopped,
+        safe_state_outputs: safe_state_outputs,
+        sfp_tx_disabled:    sfp_tx_disabled
+    })
+}
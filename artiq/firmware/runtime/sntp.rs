@@ -0,0 +1,133 @@
+use byteorder::{ByteOrder, NetworkEndian};
+use smoltcp::socket::{SocketSet, SocketHandle, UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+use smoltcp::wire::{IpAddress, IpEndpoint};
+use board_misoc::{clock, config};
+use rtc;
+
+// Simple Network Time Protocol client (RFC 4330) that sets the wall clock
+// used for log and health snapshot timestamps, and the RTC if any.
+
+const PORT: u16 = 123;
+const PACKET_SIZE: usize = 48;
+
+// Seconds between 1900-01-01 (NTP era 0) and 1970-01-01.
+const UNIX_EPOCH: u64 = 2_208_988_800;
+
+const TIMEOUT_MS: u64 = 2_000;
+const RETRY_MS:   u64 = 10_000;
+
+pub struct Sntp {
+    handle:      SocketHandle,
+    server:      IpEndpoint,
+    interval_ms: u64,
+    // Local time at which the pending request was sent.
+    sent_at:     Option<u64>,
+    next_at:     u64,
+    synced:      bool
+}
+
+fn ntp_to_unix_us(timestamp: &[u8]) -> u64 {
+    let seconds = NetworkEndian::read_u32(&timestamp[0..]) as u64;
+    let fraction = NetworkEndian::read_u32(&timestamp[4..]) as u64;
+    (seconds.wrapping_sub(UNIX_EPOCH) * 1_000_000) + (fraction * 1_000_000 >> 32)
+}
+
+impl Sntp {
+    /// Returns a client if the `sntp_server` config entry holds an IP address.
+    pub fn new(sockets: &mut SocketSet<'static, 'static, 'static>) -> Option<Sntp> {
+        let server = match config::read_str("sntp_server", |r| r.map(|s| s.parse::<IpAddress>())) {
+            Ok(Ok(server)) => server,
+            Ok(Err(())) => {
+                error!("invalid SNTP server address");
+                return None
+            }
+            Err(_) => return None
+        };
+        let interval_s = config::read_str("sntp_interval_s", |r| r.map(|s| s.parse::<u64>()));
+        let interval_s = match interval_s {
+            Ok(Ok(interval_s)) if interval_s > 0 => interval_s,
+            _ => 3600
+        };
+
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 1], vec![0; 128]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 1], vec![0; 128]);
+        let handle = sockets.add(UdpSocket::new(rx_buffer, tx_buffer));
+        sockets.get::<UdpSocket>(handle).bind(PORT).expect("sntp: cannot bind");
+
+        info!("synchronizing wall clock with {} every {} s", server, interval_s);
+        Some(Sntp {
+            handle:      handle,
+            server:      IpEndpoint::new(server, PORT),
+            interval_ms: interval_s * 1000,
+            sent_at:     None,
+            next_at:     0,
+            synced:      false
+        })
+    }
+
+    pub fn poll(&mut self, sockets: &mut SocketSet<'static, 'static, 'static>) {
+        let mut socket = sockets.get::<UdpSocket>(self.handle);
+        let now = clock::get_ms();
+
+        let mut packet = [0; PACKET_SIZE];
+        while socket.can_recv() {
+            let (length, endpoint) = match socket.recv_slice(&mut packet) {
+                Ok(received) => received,
+                Err(_) => break
+            };
+            let sent_at = match self.sent_at {
+                Some(sent_at) if endpoint == self.server && length == PACKET_SIZE => sent_at,
+                _ => continue
+            };
+            // Server mode, non-zero stratum, and our transmit time echoed as
+            // the originate timestamp.
+            let mut originate = [0; 8];
+            NetworkEndian::write_u64(&mut originate, sent_at);
+            if packet[0] & 0x7 != 4 || packet[1] == 0 || packet[24..32] != originate {
+                continue
+            }
+
+            let received_us = clock::get_us();
+            let server_receive = ntp_to_unix_us(&packet[32..40]);
+            let server_transmit = ntp_to_unix_us(&packet[40..48]);
+            let round_trip = (received_us - sent_at)
+                .saturating_sub(server_transmit.saturating_sub(server_receive));
+            let unix_us = server_transmit + round_trip / 2 + (clock::get_us() - received_us);
+            if let Some(previous) = clock::get_unix_us() {
+                debug!("wall clock adjusted by {} us", unix_us as i64 - previous as i64);
+            }
+            clock::set_unix_us(unix_us);
+            rtc::synchronized();
+            if !self.synced {
+                info!("wall clock synchronized, round trip {} us", round_trip);
+                self.synced = true;
+            }
+            self.sent_at = None;
+            self.next_at = now + self.interval_ms;
+        }
+
+        if self.sent_at.is_some() && now > self.next_at {
+            warn!("no answer from SNTP server {}", self.server.addr);
+            self.sent_at = None;
+            self.next_at = now + RETRY_MS;
+        }
+        if self.sent_at.is_none() && now >= self.next_at && socket.can_send() {
+            // Client mode, version 4; the local time stands in for the
+            // transmit timestamp and only serves to match the answer.
+            let sent_at = clock::get_us();
+            let mut request = [0; PACKET_SIZE];
+            request[0] = 0x23;
+            NetworkEndian::write_u64(&mut request[40..], sent_at);
+            match socket.send_slice(&request, self.server) {
+                Ok(()) => {
+                    self.sent_at = Some(sent_at);
+                    self.next_at = now + TIMEOUT_MS;
+                }
+                Err(err) => {
+                    debug!("cannot send SNTP request: {}", err);
+                    self.next_at = now + RETRY_MS;
+                }
+            }
+        }
+    }
+}
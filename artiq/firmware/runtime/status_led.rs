@@ -0,0 +1,349 @@
+#[cfg(soc_platform = "kasli")]
+use alloc::{Vec, String};
+#[cfg(has_leds)]
+use board_misoc::csr;
+#[cfg(soc_platform = "kasli")]
+use board_misoc::io_expander::{self, IoExpander};
+use board_misoc::config;
+use sched::Io;
+use {kernel, nrt_bus, rtio_mgt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Booting,
+    Idle,
+    Kernel,
+    LinkDown,
+    Alarm
+}
+
+const STATE_NAMES: [(&'static str, State); 5] = [
+    ("booting", State::Booting),
+    ("idle", State::Idle),
+    ("kernel", State::Kernel),
+    ("link_down", State::LinkDown),
+    ("alarm", State::Alarm)
+];
+
+/// Conditions that show the alarm pattern while any of them is active.
+#[derive(Debug, Clone, Copy)]
+pub enum Alarm {
+    FpgaTemperature = 0,
+    FpgaSupply      = 1,
+    Fan             = 2,
+    PowerRail       = 3,
+    Sfp             = 4
+}
+
+const ALARM_NAMES: [&'static str; 5] = [
+    "fpga_temperature",
+    "fpga_supply",
+    "fan",
+    "power_rail",
+    "sfp"
+];
+
+static mut ALARMS: u8 = 0;
+
+pub fn set_alarm(alarm: Alarm, active: bool) {
+    unsafe {
+        if active {
+            ALARMS |= 1 << alarm as u8
+        } else {
+            ALARMS &= !(1 << alarm as u8)
+        }
+    }
+}
+
+/// Calls `f` with the name of each active alarm.
+pub fn active_alarms<F: FnMut(&'static str)>(mut f: F) {
+    for (i, name) in ALARM_NAMES.iter().enumerate() {
+        if unsafe { ALARMS } & (1 << i) != 0 {
+            f(name)
+        }
+    }
+}
+
+const STEP_MS: u64 = 125;
+
+// Levels of the outputs at each step, repeated.
+#[derive(Debug, Clone, Copy)]
+struct Pattern {
+    bits:   u32,
+    length: u32
+}
+
+impl Pattern {
+    // Parses a string of up to 32 "0" and "1", one per step.
+    fn parse(s: &str) -> Option<Pattern> {
+        if s.is_empty() || s.len() > 32 {
+            return None
+        }
+        let mut bits = 0;
+        for (i, c) in s.chars().enumerate() {
+            match c {
+                '0' => (),
+                '1' => bits |= 1 << i,
+                _ => return None
+            }
+        }
+        Some(Pattern { bits: bits, length: s.len() as u32 })
+    }
+
+    fn level(&self, step: u32) -> bool {
+        self.bits & (1 << (step % self.length)) != 0
+    }
+}
+
+struct Patterns {
+    booting:   Pattern,
+    idle:      Pattern,
+    kernel:    Pattern,
+    link_down: Pattern,
+    alarm:     Pattern
+}
+
+impl Patterns {
+    fn default() -> Patterns {
+        Patterns {
+            booting:   Pattern { bits: 0b1, length: 1 },
+            idle:      Pattern { bits: 0b1, length: 16 },
+            kernel:    Pattern { bits: 0b1, length: 1 },
+            link_down: Pattern { bits: 0b101, length: 8 },
+            alarm:     Pattern { bits: 0b01, length: 2 }
+        }
+    }
+
+    fn get(&self, state: State) -> Pattern {
+        match state {
+            State::Booting  => self.booting,
+            State::Idle     => self.idle,
+            State::Kernel   => self.kernel,
+            State::LinkDown => self.link_down,
+            State::Alarm    => self.alarm
+        }
+    }
+
+    fn get_mut(&mut self, state: State) -> &mut Pattern {
+        match state {
+            State::Booting  => &mut self.booting,
+            State::Idle     => &mut self.idle,
+            State::Kernel   => &mut self.kernel,
+            State::LinkDown => &mut self.link_down,
+            State::Alarm    => &mut self.alarm
+        }
+    }
+
+    // Applies "state:pattern,state:pattern,...".
+    fn parse_overrides(&mut self, s: &str) -> Result<(), ()> {
+        for item in s.split(',') {
+            let mut fields = item.trim().splitn(2, ':');
+            let name = fields.next().ok_or(())?;
+            let &(_, state) = STATE_NAMES.iter().find(|&&(n, _)| n == name).ok_or(())?;
+            *self.get_mut(state) = Pattern::parse(fields.next().ok_or(())?).ok_or(())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(has_leds)]
+fn set_leds(mask: u8, level: bool) {
+    unsafe {
+        let leds = csr::leds::out_read();
+        csr::leds::out_write(if level { leds | mask } else { leds & !mask })
+    }
+}
+
+#[cfg(not(has_leds))]
+fn set_leds(_mask: u8, _level: bool) {}
+
+#[cfg(has_leds)]
+fn parse_led(index: &str) -> Result<u8, &'static str> {
+    match index.parse() {
+        Ok(index) if index < 8 => Ok(1 << index),
+        _ => Err("invalid LED index")
+    }
+}
+
+#[cfg(not(has_leds))]
+fn parse_led(_index: &str) -> Result<u8, &'static str> {
+    Err("no LEDs")
+}
+
+struct StatusLed {
+    // Mask of the LEDs of the leds CSR.
+    leds:      u8,
+    // Pins of the expanders declared in the io_expanders config entry.
+    #[cfg(soc_platform = "kasli")]
+    expanders: Vec<(String, IoExpander, u16)>,
+    patterns:  Patterns
+}
+
+impl StatusLed {
+    #[cfg(soc_platform = "kasli")]
+    fn add_expander_pin(&mut self, name: &str, pin: &str) -> Result<(), &'static str> {
+        let pin: u8 = match pin.parse() {
+            Ok(pin) if pin < 16 => pin,
+            _ => return Err("invalid pin")
+        };
+        if let Some(&mut (_, _, ref mut pins)) =
+                self.expanders.iter_mut().find(|expander| expander.0 == name) {
+            *pins |= 1 << pin;
+            return Ok(())
+        }
+        match io_expander::find_configured(name) {
+            Some((expander, outputs)) if outputs & (1 << pin) != 0 => {
+                self.expanders.push((String::from(name), expander, 1 << pin));
+                Ok(())
+            }
+            Some(_) => Err("not an output of the I/O expander"),
+            None => Err("no such I/O expander")
+        }
+    }
+
+    #[cfg(not(soc_platform = "kasli"))]
+    fn add_expander_pin(&mut self, _name: &str, _pin: &str) -> Result<(), &'static str> {
+        Err("no I/O expanders")
+    }
+
+    #[cfg(soc_platform = "kasli")]
+    fn is_empty(&self) -> bool {
+        self.leds == 0 && self.expanders.is_empty()
+    }
+
+    #[cfg(not(soc_platform = "kasli"))]
+    fn is_empty(&self) -> bool {
+        self.leds == 0
+    }
+
+    #[cfg(soc_platform = "kasli")]
+    fn set_expanders(&mut self, level: bool) {
+        let mut written = false;
+        for &mut (ref name, ref mut expander, pins) in self.expanders.iter_mut() {
+            for pin in 0..16 {
+                if pins & (1 << pin) != 0 {
+                    expander.set(pin / 8, pin % 8, level);
+                }
+            }
+            match expander.service() {
+                Ok(true) => written = true,
+                Ok(false) => (),
+                Err(e) => error!("cannot set status LED on I/O expander {}: {}", name, e)
+            }
+        }
+        if written {
+            nrt_bus::restore_switches(nrt_bus::MAIN_BUS)
+        }
+    }
+
+    #[cfg(not(soc_platform = "kasli"))]
+    fn set_expanders(&mut self, _level: bool) {}
+
+    // Expanders share the I2C switches with kernels, and are not written
+    // in the middle of a kernel I2C transaction.
+    fn set(&mut self, level: bool) {
+        set_leds(self.leds, level);
+        if !nrt_bus::kernel_holds(nrt_bus::MAIN_BUS) {
+            self.set_expanders(level)
+        }
+    }
+}
+
+static mut STATUS_LED: Option<StatusLed> = None;
+
+/// Reads the outputs and patterns from the `status_led` and
+/// `status_led_patterns` config entries, and shows the booting pattern.
+/// Must be called after the I/O expanders are initialized.
+pub fn init() {
+    let mut status_led = StatusLed {
+        leds: 0,
+        #[cfg(soc_platform = "kasli")]
+        expanders: Vec::new(),
+        patterns: Patterns::default()
+    };
+    config::read_str("status_led", |r| {
+        if let Ok(s) = r {
+            for description in s.split(',') {
+                let mut fields = description.trim().splitn(2, ':');
+                let result = match (fields.next(), fields.next()) {
+                    (Some("leds"), Some(index)) =>
+                        parse_led(index).map(|mask| status_led.leds |= mask),
+                    (Some(name), Some(pin)) => status_led.add_expander_pin(name, pin),
+                    _ => Err("invalid description")
+                };
+                if let Err(e) = result {
+                    error!("cannot use {} as status LED: {}", description.trim(), e)
+                }
+            }
+        }
+    });
+    if status_led.is_empty() {
+        return
+    }
+
+    config::read_str("status_led_patterns", |r| {
+        if let Ok(s) = r {
+            if status_led.patterns.parse_overrides(s).is_err() {
+                error!("invalid status_led_patterns, using the defaults for the remaining states")
+            }
+        }
+    });
+
+    let level = status_led.patterns.booting.level(0);
+    status_led.set(level);
+    unsafe { STATUS_LED = Some(status_led) }
+}
+
+/// Shows the kernel pattern while the I2C buses are still free. Must be
+/// called before the kernel CPU is started.
+pub fn kernel_starting() {
+    if let Some(status_led) = unsafe { STATUS_LED.as_mut() } {
+        let level = status_led.patterns.kernel.level(0);
+        status_led.set(level)
+    }
+}
+
+pub fn thread(io: Io) {
+    if unsafe { STATUS_LED.is_none() } {
+        return
+    }
+
+    // Only links that came up since boot are expected to be up.
+    let mut links_seen = vec![false; rtio_mgt::drtio::link_count()];
+    let mut last = None;
+    let mut step = 0;
+    loop {
+        let mut link_down = false;
+        for (linkno, seen) in links_seen.iter_mut().enumerate() {
+            if rtio_mgt::drtio::link_rx_up(linkno as u8) {
+                *seen = true
+            } else if *seen {
+                link_down = true
+            }
+        }
+        let kernel_running = kernel::running();
+        let state =
+            if unsafe { ALARMS } != 0 {
+                State::Alarm
+            } else if link_down {
+                State::LinkDown
+            } else if kernel_running {
+                State::Kernel
+            } else {
+                State::Idle
+            };
+
+        let status_led = unsafe { STATUS_LED.as_mut().unwrap() };
+        if last.map_or(true, |(last_state, _)| last_state != state) {
+            step = 0;
+        }
+        let level = status_led.patterns.get(state).level(step);
+        if last != Some((state, level)) {
+            status_led.set(level);
+            last = Some((state, level));
+        }
+
+        step = step.wrapping_add(1);
+        io.sleep(STEP_MS).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+use alloc::Vec;
+use byteorder::{ByteOrder, NetworkEndian};
+use smoltcp::socket::{SocketSet, SocketHandle, UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+use smoltcp::wire::IpEndpoint;
+use board_misoc::clock;
+use stream_queue;
+
+// UDP endpoint sending the sample records that kernels push with
+// `stream_push` to the hosts subscribed to it, for live plotting. The
+// stream is lossy: records are dropped when the queue from the kernel CPU
+// is full, and the datagrams tell how many were.
+//
+// Hosts subscribe by sending a datagram to port 1385, and must send one
+// again within 10 s to stay subscribed; a datagram starting with `U`
+// unsubscribes. Each datagram sent holds the magic `ARST`, a sequence
+// number (u32), the number of records dropped since the previous datagram
+// (u32) and the number of records (u16), then for each record its stream
+// number (u16), number of samples (u16) and samples (i32), all big-endian.
+
+const PORT: u16 = 1385;
+const MAGIC: &'static [u8] = b"ARST";
+const MAX_SUBSCRIBERS: usize = 4;
+const SUBSCRIPTION_TIMEOUT_MS: u64 = 10_000;
+const HEADER_SIZE: usize = 14;
+const MAX_DATAGRAM_SIZE: usize = 1400;
+// Records dequeued per poll, so that the main loop is not held up.
+const MAX_RECORDS_PER_POLL: usize = 256;
+
+pub struct Stream {
+    handle:      SocketHandle,
+    // Endpoint of each subscriber, and when it last subscribed.
+    subscribers: [Option<(IpEndpoint, u64)>; MAX_SUBSCRIBERS],
+    datagram:    Vec<u8>,
+    records:     u16,
+    sequence:    u32,
+    dropped:     u32
+}
+
+impl Stream {
+    pub fn new(sockets: &mut SocketSet<'static, 'static, 'static>) -> Stream {
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 64]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 16],
+                                             vec![0; 16 * MAX_DATAGRAM_SIZE]);
+        let handle = sockets.add(UdpSocket::new(rx_buffer, tx_buffer));
+        sockets.get::<UdpSocket>(handle).bind(PORT).expect("stream: cannot bind");
+
+        let mut datagram = Vec::with_capacity(MAX_DATAGRAM_SIZE);
+        datagram.extend_from_slice(MAGIC);
+        datagram.resize(HEADER_SIZE, 0);
+        Stream {
+            handle:      handle,
+            subscribers: [None; MAX_SUBSCRIBERS],
+            datagram:    datagram,
+            records:     0,
+            sequence:    0,
+            dropped:     0
+        }
+    }
+
+    fn subscribe(&mut self, endpoint: IpEndpoint, now: u64) {
+        if let Some(subscriber) = self.subscribers.iter_mut()
+                .find(|s| s.map_or(false, |(e, _)| e == endpoint)) {
+            *subscriber = Some((endpoint, now));
+            return
+        }
+        match self.subscribers.iter_mut().find(|s| s.is_none()) {
+            Some(subscriber) => {
+                info!("stream: {} subscribed", endpoint);
+                *subscriber = Some((endpoint, now))
+            }
+            None => warn!("stream: too many subscribers, ignoring {}", endpoint)
+        }
+    }
+
+    fn unsubscribe(&mut self, endpoint: IpEndpoint) {
+        for subscriber in self.subscribers.iter_mut() {
+            if subscriber.map_or(false, |(e, _)| e == endpoint) {
+                info!("stream: {} unsubscribed", endpoint);
+                *subscriber = None
+            }
+        }
+    }
+
+    fn expire(&mut self, now: u64) {
+        for subscriber in self.subscribers.iter_mut() {
+            if let Some((endpoint, at)) = *subscriber {
+                if now > at + SUBSCRIPTION_TIMEOUT_MS {
+                    info!("stream: subscription of {} expired", endpoint);
+                    *subscriber = None
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, socket: &mut UdpSocket) {
+        if self.records == 0 {
+            return
+        }
+        NetworkEndian::write_u32(&mut self.datagram[4..8], self.sequence);
+        NetworkEndian::write_u32(&mut self.datagram[8..12], self.dropped);
+        NetworkEndian::write_u16(&mut self.datagram[12..14], self.records);
+        let mut sent = false;
+        for &(endpoint, _) in self.subscribers.iter().filter_map(|s| s.as_ref()) {
+            match socket.send_slice(&self.datagram, endpoint) {
+                Ok(()) => sent = true,
+                Err(err) => debug!("stream: cannot send to {}: {}", endpoint, err)
+            }
+        }
+        if sent {
+            self.sequence = self.sequence.wrapping_add(1);
+            self.dropped = 0;
+        } else {
+            self.dropped = self.dropped.saturating_add(self.records as u32);
+        }
+        self.datagram.truncate(HEADER_SIZE);
+        self.records = 0;
+    }
+
+    pub fn poll(&mut self, sockets: &mut SocketSet<'static, 'static, 'static>) {
+        let now = clock::get_ms();
+        let mut socket = sockets.get::<UdpSocket>(self.handle);
+        let mut request = [0; 1];
+        while socket.can_recv() {
+            match socket.recv_slice(&mut request) {
+                Ok((length, endpoint)) if length > 0 && request[0] == b'U' =>
+                    self.unsubscribe(endpoint),
+                Ok((_, endpoint)) => self.subscribe(endpoint, now),
+                Err(_) => break
+            }
+        }
+        self.expire(now);
+
+        if self.subscribers.iter().all(|s| s.is_none()) {
+            // Nobody is listening; keep the queue from filling up.
+            stream_queue::dequeue(MAX_RECORDS_PER_POLL, |_, _, _| ());
+            return
+        }
+        // Leave the records in the queue until the socket catches up.
+        if !socket.can_send() {
+            return
+        }
+        stream_queue::dequeue(MAX_RECORDS_PER_POLL, |stream, dropped, samples| {
+            if self.datagram.len() + 4 + samples.len() > MAX_DATAGRAM_SIZE {
+                self.send(&mut socket);
+            }
+            self.dropped = self.dropped.saturating_add(dropped);
+            let mut header = [0; 4];
+            NetworkEndian::write_u16(&mut header[0..2], stream);
+            NetworkEndian::write_u16(&mut header[2..4], (samples.len() / 4) as u16);
+            self.datagram.extend_from_slice(&header);
+            self.datagram.extend_from_slice(samples);
+            self.records += 1;
+        });
+        self.send(&mut socket);
+    }
+}
@@ -0,0 +1,68 @@
+use core::str;
+use alloc::{Vec, String};
+use dyld;
+
+#[derive(Debug)]
+struct Function {
+    start:    u32,
+    end:      u32,
+    name_off: usize,
+    name_len: usize
+}
+
+// Function symbols of the loaded kernel, kept after the ELF file itself is
+// dropped so that kernel backtraces can be symbolized on the core device.
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    functions: Vec<Function>,
+    names:     String
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    pub fn from_elf(library: &[u8]) -> SymbolTable {
+        let mut table = SymbolTable::new();
+        let result = dyld::for_each_function(library, |addr, size, name| {
+            if let Ok(name) = str::from_utf8(name) {
+                table.functions.push(Function {
+                    start:    addr,
+                    end:      addr + size,
+                    name_off: table.names.len(),
+                    name_len: name.len()
+                });
+                table.names.push_str(name);
+            }
+        });
+        if let Err(error) = result {
+            warn!("cannot read kernel symbols: {}", error);
+            return SymbolTable::new()
+        }
+
+        table.functions.sort_by_key(|function| function.start);
+        table.functions.shrink_to_fit();
+        table.names.shrink_to_fit();
+        debug!("read {} kernel symbols", table.functions.len());
+        table
+    }
+
+    /// Returns the name of the function a backtrace entry, which is a return
+    /// address relative to the kernel image, points into.
+    pub fn lookup(&self, return_address: usize) -> Option<&str> {
+        // The return address may be just past the end of the calling function.
+        let addr = (return_address as u32).wrapping_sub(1);
+        let index = match self.functions.binary_search_by_key(&addr, |f| f.start) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1
+        };
+        let function = &self.functions[index];
+        if addr < function.end {
+            Some(&self.names[function.name_off..function.name_off + function.name_len])
+        } else {
+            None
+        }
+    }
+}
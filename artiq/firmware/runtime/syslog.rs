@@ -0,0 +1,108 @@
+use alloc::String;
+use smoltcp::socket::{SocketSet, SocketHandle, UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+use smoltcp::wire::{IpAddress, IpEndpoint};
+use board_misoc::{config, net_settings::NetAddresses};
+use logger_artiq::BufferLogger;
+use mdns;
+
+// Sender of the log records of the syslog sink to a remote syslog server, as
+// RFC 5424 messages over UDP, one per datagram. The records are filtered by
+// the `syslog_log_level` config key, independently of the UART and of the
+// buffer read over mgmt, e.g. to collect trace messages without forwarding
+// them to the host through the log buffer.
+
+const DEFAULT_PORT: u16 = 514;
+const PACKETS: usize = 32;
+const BUFFER_SIZE: usize = 8192;
+
+static mut SYSLOG_BUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+
+pub struct Syslog {
+    handle:      SocketHandle,
+    destination: IpEndpoint,
+    hostname:    String
+}
+
+/// Address of the syslog server, from the `syslog_addr` and `syslog_port`
+/// config keys, if set.
+pub fn destination() -> Option<Result<IpEndpoint, &'static str>> {
+    let address = match config::read_str("syslog_addr", |r| r.map(|s| s.parse::<IpAddress>())) {
+        Ok(Ok(address)) => address,
+        Ok(Err(())) => return Some(Err("invalid syslog_addr")),
+        Err(_) => return None
+    };
+    let port = match config::read_str("syslog_port", |r| r.map(|s| s.parse::<u16>())) {
+        Ok(Ok(port)) => port,
+        Ok(Err(_)) => return Some(Err("invalid syslog_port")),
+        Err(_) => DEFAULT_PORT
+    };
+    Some(Ok(IpEndpoint::new(address, port)))
+}
+
+impl Syslog {
+    /// Returns a sender if the `syslog_addr` config entry is set, and
+    /// enables the syslog sink of the logger.
+    pub fn new(sockets: &mut SocketSet<'static, 'static, 'static>,
+               addresses: &NetAddresses) -> Option<Syslog> {
+        let destination = match destination()? {
+            Ok(destination) => destination,
+            Err(e) => {
+                error!("{}", e);
+                return None
+            }
+        };
+
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 1], vec![0; 16]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; PACKETS],
+                                             vec![0; BUFFER_SIZE]);
+        let handle = sockets.add(UdpSocket::new(rx_buffer, tx_buffer));
+        sockets.get::<UdpSocket>(handle).bind(destination.port).expect("syslog: cannot bind");
+
+        BufferLogger::with(|logger| logger.set_syslog_buffer(unsafe { &mut SYSLOG_BUFFER[..] }));
+        info!("sending log records to syslog server {}", destination);
+        Some(Syslog {
+            handle:      handle,
+            destination: destination,
+            hostname:    mdns::hostname(addresses)
+        })
+    }
+
+    pub fn poll(&mut self, sockets: &mut SocketSet<'static, 'static, 'static>) {
+        let mut socket = sockets.get::<UdpSocket>(self.handle);
+        // Nothing may be logged while the records are borrowed; they would
+        // be lost.
+        BufferLogger::with(|logger| {
+            let mut buffer = match logger.syslog_buffer() {
+                Ok(buffer) => buffer,
+                Err(()) => return
+            };
+            if buffer.is_empty() {
+                return
+            }
+            let mut message = String::new();
+            for line in buffer.extract().lines() {
+                // The records have `-` in place of the host name, after the
+                // priority, version and timestamp.
+                let mut fields = line.splitn(4, ' ');
+                match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                    (Some(header), Some(timestamp), Some(_), Some(rest)) => {
+                        message.clear();
+                        message.push_str(header);
+                        message.push(' ');
+                        message.push_str(timestamp);
+                        message.push(' ');
+                        message.push_str(&self.hostname);
+                        message.push(' ');
+                        message.push_str(rest);
+                    }
+                    _ => continue
+                }
+                // Records that the socket cannot take are dropped.
+                if socket.can_send() {
+                    let _ = socket.send_slice(message.as_bytes(), self.destination);
+                }
+            }
+            buffer.clear();
+        })
+    }
+}
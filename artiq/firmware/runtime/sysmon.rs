@@ -0,0 +1,183 @@
+#[cfg(has_xadc)]
+use board_misoc::{csr, config, boot};
+#[cfg(has_xadc)]
+use sched::Priority;
+#[cfg(has_xadc)]
+use timers;
+#[cfg(has_xadc)]
+use status_led::{self, Alarm};
+#[cfg(has_xadc)]
+use alerts::{self, Severity, Source};
+#[cfg(has_xadc)]
+use fault_injection;
+#[cfg(has_xadc)]
+use health_record::RecordSet;
+
+use mgmt_proto::FpgaSensors;
+
+static mut THROTTLED: bool = false;
+
+/// Whether kernel loading and the DRTIO SFP transmitters are disabled
+/// because the FPGA is too hot.
+pub fn throttled() -> bool {
+    unsafe { THROTTLED }
+}
+
+#[cfg(has_xadc)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    None,
+    Throttle,
+    Reboot
+}
+
+#[cfg(has_xadc)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Level {
+    Normal,
+    Warning,
+    Critical
+}
+
+// Cooling below a threshold by this much, in m°C, is required to end its
+// alert, so that a temperature hovering around it does not flood the log.
+#[cfg(has_xadc)]
+const HYSTERESIS: i32 = 5_000;
+
+#[cfg(has_xadc)]
+const INTERVAL_MS: u64 = 1000;
+
+// XADC codes to m°C and mV, see UG480.
+#[cfg(has_xadc)]
+fn temperature(code: u16) -> i32 {
+    code as i32 * 503_975 / 4096 - 273_150
+}
+
+#[cfg(has_xadc)]
+fn voltage(code: u16) -> i32 {
+    code as i32 * 3000 / 4096
+}
+
+/// FPGA die temperature, in m°C.
+#[cfg(has_xadc)]
+pub fn fpga_temperature() -> i32 {
+    fault_injection::fpga_temperature()
+        .unwrap_or_else(|| temperature(unsafe { csr::xadc::temperature_read() }))
+}
+
+/// Raw XADC codes of the FPGA die temperature and supply voltages.
+#[cfg(has_xadc)]
+pub fn fpga_sensors() -> Option<FpgaSensors> {
+    unsafe {
+        Some(FpgaSensors {
+            temperature: match fault_injection::fpga_temperature() {
+                Some(temperature) => ((temperature + 273_150) as i64 * 4096 / 503_975) as u16,
+                None => csr::xadc::temperature_read()
+            },
+            vccint: csr::xadc::vccint_read(),
+            vccaux: csr::xadc::vccaux_read(),
+            vccbram: csr::xadc::vccbram_read()
+        })
+    }
+}
+
+#[cfg(not(has_xadc))]
+pub fn fpga_sensors() -> Option<FpgaSensors> {
+    None
+}
+
+#[cfg(has_xadc)]
+pub fn config_int(key: &str, default: i32) -> i32 {
+    config::read_str(key, |r| r.ok().and_then(|s| s.parse().ok())).unwrap_or(default)
+}
+
+#[cfg(has_xadc)]
+pub fn init() {
+    let warning = config_int("fpga_temp_warn", 85) * 1000;
+    let critical = config_int("fpga_temp_crit", 100) * 1000;
+    let tolerance = config_int("fpga_rail_tolerance_pct", 5);
+    let action = config::read_str("fpga_overtemp_action", |r| match r {
+        Ok("throttle") => Action::Throttle,
+        Ok("reboot") => Action::Reboot,
+        _ => Action::None
+    });
+
+    let mut level = Level::Normal;
+    let mut rails_ok = [true; 3];
+    timers::register("sysmon", INTERVAL_MS * 1000, Priority::Low, move |_| {
+        let (temperature, rails) = unsafe {
+            (fpga_temperature(),
+             [("VCCINT", voltage(csr::xadc::vccint_read()), 1000),
+              ("VCCAUX", voltage(csr::xadc::vccaux_read()), 1800),
+              ("VCCBRAM", voltage(csr::xadc::vccbram_read()), 1000)])
+        };
+
+        let new_level =
+            if temperature >= critical ||
+                    (level == Level::Critical && temperature > critical - HYSTERESIS) {
+                Level::Critical
+            } else if temperature >= warning ||
+                    (level != Level::Normal && temperature > warning - HYSTERESIS) {
+                Level::Warning
+            } else {
+                Level::Normal
+            };
+        if new_level != level {
+            match new_level {
+                Level::Critical => {
+                    error!("!!! FPGA die temperature critical: {} °C !!!", temperature / 1000);
+                    match action {
+                        Action::None => (),
+                        Action::Throttle => {
+                            error!("disabling kernel loading and the DRTIO SFP transmitters");
+                            unsafe { THROTTLED = true }
+                        }
+                        Action::Reboot => {
+                            error!("restarting");
+                            unsafe { boot::reset() }
+                        }
+                    }
+                }
+                Level::Warning if level == Level::Normal =>
+                    warn!("FPGA die temperature high: {} °C", temperature / 1000),
+                Level::Warning | Level::Normal => {
+                    if level == Level::Critical && throttled() {
+                        info!("FPGA die temperature no longer critical, \
+                               re-enabling kernel loading and the DRTIO SFP transmitters");
+                        unsafe { THROTTLED = false }
+                    }
+                    if new_level == Level::Normal {
+                        info!("FPGA die temperature back to normal: {} °C", temperature / 1000)
+                    }
+                }
+            }
+            let (severity, state) = match new_level {
+                Level::Critical => (Severity::Critical, "critical"),
+                Level::Warning => (Severity::Warning, "high"),
+                Level::Normal => (Severity::Info, "back to normal")
+            };
+            let mut records = RecordSet::new();
+            if let Some(sensors) = fpga_sensors() {
+                records.fpga(&sensors);
+            }
+            alerts::push_with_records(severity, Source::Temperature,
+                format!("FPGA die temperature {}: {} °C", state, temperature / 1000), records);
+            level = new_level;
+            status_led::set_alarm(Alarm::FpgaTemperature, level != Level::Normal);
+        }
+
+        for (ok, &(name, voltage, nominal)) in rails_ok.iter_mut().zip(rails.iter()) {
+            let in_range = (voltage - nominal).abs() * 100 <= nominal * tolerance;
+            if in_range != *ok {
+                if in_range {
+                    info!("FPGA supply {} back in range: {} mV", name, voltage)
+                } else {
+                    warn!("FPGA supply {} out of range: {} mV (nominal {} mV)",
+                          name, voltage, nominal)
+                }
+                *ok = in_range;
+            }
+        }
+        status_led::set_alarm(Alarm::FpgaSupply, rails_ok.iter().any(|&ok| !ok));
+    })
+}
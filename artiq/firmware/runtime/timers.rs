@@ -0,0 +1,106 @@
+use alloc::Vec;
+use alloc::boxed::Box;
+use board_misoc::clock;
+use sched::{Io, Priority};
+
+// Periodic housekeeping of the comms CPU. Monitors register a handler with
+// an interval and a priority instead of running a thread of their own that
+// sleeps between polls. The handlers of each priority are called in turn by
+// a dispatcher thread of that priority, so that e.g. the low priority
+// handlers are held back like the other low priority threads. Intervals are
+// in microseconds: the dispatchers wait for the next handler with a wait
+// condition on the clock rather than with `Io::sleep`, whose granularity is
+// a millisecond. A handler may suspend the dispatcher, e.g. to take the I2C
+// bus, which delays the other handlers of its priority; a handler that
+// falls behind by more than its interval skips the missed calls instead of
+// running in a burst.
+
+const STACK_SIZE: usize = 4096;
+
+struct Timer {
+    name:        &'static str,
+    interval_us: u64,
+    priority:    Priority,
+    due_us:      u64,
+    handler:     Box<FnMut(&Io)>
+}
+
+// Timers are registered into PENDING, and taken over by the dispatcher of
+// their priority, which owns them from then on, so that handlers can
+// register timers themselves.
+static mut PENDING: Option<Vec<Timer>> = None;
+
+fn pending() -> &'static mut Vec<Timer> {
+    unsafe {
+        if PENDING.is_none() {
+            PENDING = Some(Vec::new())
+        }
+        PENDING.as_mut().unwrap()
+    }
+}
+
+/// Calls `handler` every `interval_us`, starting one interval from now.
+pub fn register<F>(name: &'static str, interval_us: u64, priority: Priority, handler: F)
+        where F: 'static + FnMut(&Io) {
+    debug!("timer {} registered, every {} us", name, interval_us);
+    pending().push(Timer {
+        name:        name,
+        interval_us: interval_us,
+        priority:    priority,
+        due_us:      clock::get_us() + interval_us,
+        handler:     Box::new(handler)
+    })
+}
+
+fn dispatcher(io: Io, priority: Priority) {
+    let mut timers: Vec<Timer> = Vec::new();
+    loop {
+        {
+            let pending = pending();
+            let mut index = 0;
+            while index < pending.len() {
+                if pending[index].priority == priority {
+                    timers.push(pending.remove(index))
+                } else {
+                    index += 1
+                }
+            }
+        }
+
+        let now = clock::get_us();
+        let due = timers.iter().enumerate()
+            .filter(|&(_, timer)| timer.due_us <= now)
+            .min_by_key(|&(_, timer)| timer.due_us)
+            .map(|(index, _)| index);
+        match due {
+            Some(index) => {
+                let timer = &mut timers[index];
+                (timer.handler)(&io);
+                timer.due_us += timer.interval_us;
+                let now = clock::get_us();
+                if timer.due_us <= now {
+                    trace!("timer {} late by {} us", timer.name, now - timer.due_us);
+                    timer.due_us = now + timer.interval_us;
+                }
+                io.relinquish().unwrap();
+            }
+            None => {
+                let next_us = timers.iter().map(|timer| timer.due_us).min();
+                io.until(|| {
+                    next_us.map_or(false, |next_us| clock::get_us() >= next_us) ||
+                        pending().iter().any(|timer| timer.priority == priority)
+                }).unwrap();
+            }
+        }
+    }
+}
+
+/// Spawns the dispatchers. Timers can be registered before or after.
+pub fn start(io: &Io) {
+    io.spawn_named("timers_low", Priority::Low, STACK_SIZE,
+                   |io| dispatcher(io, Priority::Low));
+    io.spawn_named("timers_normal", Priority::Normal, STACK_SIZE,
+                   |io| dispatcher(io, Priority::Normal));
+    io.spawn_named("timers_high", Priority::High, STACK_SIZE,
+                   |io| dispatcher(io, Priority::High));
+}
@@ -0,0 +1,124 @@
+use alloc::String;
+use board_misoc::{uart, boot, config, sfp, net_settings};
+use sched::Io;
+use {kernel, mdns, profiler, rtio_mgt};
+
+// Minimal command line on the UART, for bring-up and for devices that
+// cannot be reached over the network. Commands are refused while a kernel
+// runs, since some of them use the I2C bus.
+
+const PROMPT: &'static str = "artiq> ";
+const MAX_LINE_LENGTH: usize = 80;
+const POLL_INTERVAL_MS: u64 = 20;
+
+const HELP: &'static str = "\
+commands:
+  help     show this message
+  net      show the network configuration
+  config   list the configuration keys
+  sfp      show the diagnostics of the SFP transceivers
+  drtio    show the state of the DRTIO links
+  reboot   restart the core device";
+
+fn net() {
+    let addresses = net_settings::get_adresses();
+    println!("hostname: {}", mdns::hostname(&addresses));
+    println!("{}", addresses);
+}
+
+fn config_keys() {
+    let result = config::iter(|key, size| {
+        println!("{} ({} bytes)", String::from_utf8_lossy(key), size)
+    });
+    if let Err(err) = result {
+        println!("cannot read configuration: {}", err)
+    }
+}
+
+fn sfp() {
+    if sfp::count() == 0 {
+        println!("no SFP ports")
+    }
+    for index in 0..sfp::count() {
+        match sfp::diagnostics(index) {
+            Ok(Some(d)) =>
+                println!("SFP{}: {} °C, {} mV, TX bias {} µA, TX {} µW, RX {} µW",
+                         index, d.temperature / 256, d.voltage / 10, d.tx_bias as u32 * 2,
+                         d.tx_power / 10, d.rx_power / 10),
+            Ok(None) => println!("SFP{}: no diagnostics", index),
+            Err(e) => println!("SFP{}: {}", index, e)
+        }
+    }
+}
+
+fn drtio() {
+    let link_count = rtio_mgt::drtio::link_count();
+    if link_count == 0 {
+        println!("no DRTIO links")
+    }
+    for linkno in 0..link_count {
+        let linkno = linkno as u8;
+        let stats = rtio_mgt::drtio::link_stats(linkno);
+        println!("link {}: {}, {} drops, {} CRC errors, {} aux timeouts, {} protocol errors",
+                 linkno, if rtio_mgt::drtio::link_rx_up(linkno) { "up" } else { "down" },
+                 stats.link_drops, stats.crc_errors, stats.aux_timeouts, stats.protocol_errors)
+    }
+}
+
+fn run(line: &str) {
+    let command = line.trim();
+    if command.is_empty() {
+        return
+    }
+    if kernel::running() {
+        println!("a kernel is running, commands are disabled");
+        return
+    }
+    match command {
+        "help" => println!("{}", HELP),
+        "net" => net(),
+        "config" => config_keys(),
+        "sfp" => sfp(),
+        "drtio" => drtio(),
+        "reboot" => {
+            profiler::stop();
+            warn!("restarting from the UART shell");
+            unsafe { boot::reset() }
+        }
+        _ => println!("unknown command {}, try help", command)
+    }
+}
+
+/// Reads commands from the UART, unless the `uart_shell` config entry is
+/// "0". The prompt is only shown once Enter is pressed, so that it does not
+/// get in the way of the log.
+pub fn thread(io: Io) {
+    if config::read_str("uart_shell", |r| r == Ok("0")) {
+        return
+    }
+
+    let mut line = String::new();
+    loop {
+        while let Some(c) = uart::read() {
+            match c {
+                b'\r' | b'\n' => {
+                    println!("");
+                    run(&line);
+                    line.clear();
+                    print!("{}", PROMPT);
+                }
+                0x08 | 0x7f => {
+                    if line.pop().is_some() {
+                        print!("\x08 \x08")
+                    }
+                }
+                0x20...0x7e if line.len() < MAX_LINE_LENGTH => {
+                    line.push(c as char);
+                    print!("{}", c as char)
+                }
+                _ => ()
+            }
+        }
+        io.sleep(POLL_INTERVAL_MS).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+use alloc::{Vec, String};
+use board_misoc::clock;
+
+#[derive(Debug)]
+struct Watchdog {
+    scope:     String,
+    set_at:    u64,
+    threshold: u64
+}
+
+#[derive(Debug)]
+pub struct Expired<'a> {
+    pub scope:      &'a str,
+    pub timeout_ms: u64,
+    pub elapsed_ms: u64
+}
+
+pub const MAX_WATCHDOGS: usize = 16;
+
+#[derive(Debug)]
+pub struct WatchdogSet {
+    watchdogs: Vec<Option<Watchdog>>
+}
+
+impl WatchdogSet {
+    pub fn new() -> WatchdogSet {
+        WatchdogSet {
+            watchdogs: (0..MAX_WATCHDOGS).map(|_| None).collect()
+        }
+    }
+
+    pub fn set_ms(&mut self, interval: u64, scope: &str) -> Result<usize, ()> {
+        for (index, slot) in self.watchdogs.iter_mut().enumerate() {
+            if slot.is_none() {
+                let now = clock::get_ms();
+                *slot = Some(Watchdog {
+                    scope:     String::from(scope),
+                    set_at:    now,
+                    threshold: now + interval
+                });
+                return Ok(index)
+            }
+        }
+
+        Err(())
+    }
+
+    pub fn clear(&mut self, index: usize) {
+        if index < MAX_WATCHDOGS {
+            self.watchdogs[index] = None
+        }
+    }
+
+    pub fn expired(&self) -> Option<Expired> {
+        let now = clock::get_ms();
+        self.watchdogs
+            .iter()
+            .filter_map(|slot| slot.as_ref())
+            .filter(|watchdog| now > watchdog.threshold)
+            .min_by_key(|watchdog| watchdog.threshold)
+            .map(|watchdog| Expired {
+                scope:      &watchdog.scope,
+                timeout_ms: watchdog.threshold - watchdog.set_at,
+                elapsed_ms: now - watchdog.set_at
+            })
+    }
+}
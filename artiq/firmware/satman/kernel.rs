@@ -0,0 +1,363 @@
+use core::{ptr, slice, str, fmt};
+use log::Level;
+use board_misoc::{csr, config, clock};
+use board_artiq::{mailbox, rpc_queue, drtio_routing};
+use proto_artiq::kernel_proto as kern;
+use proto_artiq::kernel_proto::{KERNELCPU_EXEC_ADDRESS, KERNELCPU_LAST_ADDRESS,
+                                KSUPPORT_HEADER_SIZE};
+use repeater::Repeater;
+
+// Idle kernel run from the local configuration while no DRTIO master is
+// connected, e.g. to hold the outputs in a safe state or to run keep-alive
+// sequences while the master reboots. It drives the RTIO channels through the
+// kernel CPU port of the RTIO interconnect and has none of the services of
+// the master: RPCs, the cache, DMA playback and the I2C and SPI buses fail.
+
+const CONFIG_KEY: &str = "idle_kernel";
+const MAX_WATCHDOGS: usize = 16;
+// Time for the kernel CPU to take the load request.
+const LOAD_TIMEOUT_MS: u64 = 1000;
+// Minimum time between two starts of the idle kernel, so that a kernel that
+// finishes or fails at once does not flood the log.
+const RESTART_INTERVAL_MS: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Absent,
+    Loading,
+    Running,
+    // Stopped, to be started again.
+    Restarting
+}
+
+static mut STATE: State = State::Absent;
+static mut STARTED_AT: u64 = 0;
+// Deadlines of the watchdogs set by the kernel, in ms.
+static mut WATCHDOGS: [Option<u64>; MAX_WATCHDOGS] = [None; MAX_WATCHDOGS];
+
+// Partial line of kernel log output.
+struct LineBuffer {
+    data: [u8; 256],
+    len:  usize
+}
+
+static mut LOG_LINE: LineBuffer = LineBuffer { data: [0; 256], len: 0 };
+
+impl LineBuffer {
+    fn flush(&mut self) {
+        if self.len > 0 {
+            match str::from_utf8(&self.data[..self.len]) {
+                Ok(line) => info!(target: "kernel", "{}", line),
+                Err(_) => info!(target: "kernel", "{:?}", &self.data[..self.len])
+            }
+            self.len = 0;
+        }
+    }
+}
+
+impl fmt::Write for LineBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if byte == b'\n' || self.len == self.data.len() {
+                self.flush();
+            }
+            if byte != b'\n' {
+                self.data[self.len] = byte;
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+unsafe fn start_cpu() {
+    extern {
+        static _binary____ksupport_ksupport_elf_start: u8;
+        static _binary____ksupport_ksupport_elf_end: u8;
+    }
+    let ksupport_start = &_binary____ksupport_ksupport_elf_start as *const _;
+    let ksupport_end   = &_binary____ksupport_ksupport_elf_end as *const _;
+    ptr::copy_nonoverlapping(ksupport_start,
+                             (KERNELCPU_EXEC_ADDRESS - KSUPPORT_HEADER_SIZE) as *mut u8,
+                             ksupport_end as usize - ksupport_start as usize);
+
+    csr::kernel_cpu::reset_write(0);
+    rpc_queue::init();
+}
+
+unsafe fn stop_cpu() {
+    csr::kernel_cpu::reset_write(1);
+    mailbox::acknowledge();
+    rpc_queue::init();
+}
+
+// The kernel CPU cannot access the SPI flash address space, so the kernel
+// is copied to the memory between the satman stack and the kernel CPU area.
+unsafe fn kernel_buffer() -> &'static mut [u8] {
+    extern {
+        static mut _fkernel_elf: u8;
+        static mut _ekernel_elf: u8;
+    }
+    let start = &mut _fkernel_elf as *mut u8;
+    let end = &mut _ekernel_elf as *mut u8;
+    slice::from_raw_parts_mut(start, end as usize - start as usize)
+}
+
+fn send(message: &kern::Message) {
+    unsafe { mailbox::send(message as *const _ as usize) }
+    while !mailbox::acknowledged() {}
+}
+
+/// Loads the idle kernel from the configuration and starts it. Returns false
+/// if there is none.
+pub fn start() -> bool {
+    let buffer = unsafe { kernel_buffer() };
+    let length = config::read(CONFIG_KEY, |result| {
+        match result {
+            Ok(kernel) if kernel.len() > buffer.len() => {
+                error!("idle kernel is too large ({} bytes, {} available)",
+                       kernel.len(), buffer.len());
+                0
+            }
+            Ok(kernel) => {
+                buffer[..kernel.len()].copy_from_slice(kernel);
+                kernel.len()
+            }
+            Err(_) => 0
+        }
+    });
+    if length == 0 {
+        return false
+    }
+
+    info!("no master, starting idle kernel");
+    unsafe {
+        STARTED_AT = clock::get_ms();
+        stop_cpu();
+        WATCHDOGS = [None; MAX_WATCHDOGS];
+        // The DRTIO link is down, so the kernel CPU can take the RTIO core.
+        csr::cri_con::selected_write(1);
+        start_cpu();
+
+        let request = kern::LoadRequest { library: &buffer[..length], relocated: None };
+        mailbox::send(&request as *const _ as usize);
+        let deadline = clock::get_ms() + LOAD_TIMEOUT_MS;
+        while !mailbox::acknowledged() {
+            if clock::get_ms() > deadline {
+                error!("kernel CPU did not take the idle kernel");
+                stop();
+                return true
+            }
+        }
+        STATE = State::Loading;
+    }
+    true
+}
+
+/// Stops the idle kernel, if any, and gives the RTIO core back to the DRTIO link.
+pub fn stop() {
+    unsafe {
+        if STATE == State::Loading || STATE == State::Running {
+            info!("stopping idle kernel");
+            LOG_LINE.flush();
+        }
+        STATE = State::Absent;
+        stop_cpu();
+        csr::cri_con::selected_write(0);
+    }
+}
+
+/// Whether the idle kernel is running or about to be started again.
+pub fn running() -> bool {
+    unsafe { STATE != State::Absent }
+}
+
+fn destination_up(routing_table: &drtio_routing::RoutingTable, rank: u8,
+                  repeaters: &[Repeater], destination: u8) -> bool {
+    let hop = routing_table.0[destination as usize][rank as usize] as usize;
+    if hop == 0 {
+        true
+    } else if hop <= repeaters.len() {
+        repeaters[hop - 1].is_up()
+    } else {
+        false
+    }
+}
+
+/// Handles the requests of the idle kernel. The kernel is started again once
+/// it finishes or fails, as on the master, but at most once per
+/// `RESTART_INTERVAL_MS`.
+pub fn service(routing_table: &drtio_routing::RoutingTable, rank: u8, repeaters: &[Repeater]) {
+    if !running() { return }
+
+    if unsafe { STATE == State::Restarting } {
+        if clock::get_ms() >= unsafe { STARTED_AT } + RESTART_INTERVAL_MS {
+            unsafe { STATE = State::Absent }
+            start();
+        }
+        return
+    }
+
+    // There is no host to deliver async RPCs and notifications to.
+    while !rpc_queue::empty() {
+        rpc_queue::dequeue(|_chunk| -> Result<(), ()> { Ok(()) }).unwrap();
+    }
+
+    let now = clock::get_ms();
+    let expired = unsafe { WATCHDOGS.iter().any(|w| w.map(|at| now > at).unwrap_or(false)) };
+    if expired {
+        error!("watchdog for idle kernel expired");
+        restart();
+        return
+    }
+
+    let ptr = mailbox::receive();
+    if ptr == 0 { return }
+    if ptr < KERNELCPU_EXEC_ADDRESS || ptr > KERNELCPU_LAST_ADDRESS {
+        error!("invalid kernel CPU pointer: {:#08x}", ptr);
+        restart();
+        return
+    }
+    let request = unsafe { &*(ptr as *const kern::Message) };
+    match process(request, routing_table, rank, repeaters) {
+        Ok(true) => restart(),
+        Ok(false) => (),
+        Err(message) => {
+            error!("idle kernel aborted: {}", message);
+            restart()
+        }
+    }
+}
+
+fn restart() {
+    stop();
+    unsafe { STATE = State::Restarting }
+}
+
+// Returns true once the kernel has terminated.
+fn process(request: &kern::Message, routing_table: &drtio_routing::RoutingTable, rank: u8,
+           repeaters: &[Repeater]) -> Result<bool, &'static str> {
+    use core::fmt::Write;
+
+    if unsafe { STATE } == State::Loading {
+        return match request {
+            &kern::LoadReply(Ok(_)) => {
+                unsafe { STATE = State::Running }
+                mailbox::acknowledge();
+                Ok(false)
+            }
+            &kern::LoadReply(Err(ref error)) => {
+                error!("cannot load idle kernel: {}", error);
+                stop();
+                Ok(false)
+            }
+            _ => Err("unexpected kernel CPU reply to load request")
+        }
+    }
+
+    match request {
+        &kern::RtioInitRequest => mailbox::acknowledge(),
+        &kern::RtioDestinationStatusRequest { destination } =>
+            send(&kern::RtioDestinationStatusReply {
+                up:     destination_up(routing_table, rank, repeaters, destination),
+                age_ms: 0
+            }),
+
+        &kern::ProfilerRequest =>
+            send(&kern::ProfilerReply { interval_cycles: 0 }),
+        &kern::ProfileSamples { .. } => mailbox::acknowledge(),
+
+        &kern::WatchdogSetRequest { ms, .. } => {
+            let slot = unsafe { WATCHDOGS.iter().position(|w| w.is_none()) };
+            match slot {
+                Some(id) => {
+                    unsafe { WATCHDOGS[id] = Some(clock::get_ms() + ms) }
+                    send(&kern::WatchdogSetReply { id: id })
+                }
+                None => return Err("too many watchdogs")
+            }
+        }
+        &kern::WatchdogClear { id } => {
+            if let Some(watchdog) = unsafe { WATCHDOGS.get_mut(id) } {
+                *watchdog = None
+            }
+            mailbox::acknowledge()
+        }
+
+        &kern::Log(args) => {
+            unsafe { LOG_LINE.write_fmt(args).unwrap() }
+            mailbox::acknowledge()
+        }
+        &kern::LogSlice(arg) => {
+            unsafe { LOG_LINE.write_str(arg).unwrap() }
+            mailbox::acknowledge()
+        }
+        &kern::LogRecord { level, tag, text } => {
+            let level = match level {
+                0 | 1 => Level::Error,
+                2 => Level::Warn,
+                3 => Level::Info,
+                4 => Level::Debug,
+                _ => Level::Trace
+            };
+            log!(target: "kernel", level, "{}: {}", tag, text);
+            mailbox::acknowledge()
+        }
+        &kern::ExceptionDump(args) => {
+            error!(target: "kernel", "{}", args);
+            mailbox::acknowledge()
+        }
+
+        &kern::RpcFlush => mailbox::acknowledge(),
+        &kern::RpcSend { .. } => return Err("idle kernel attempted RPC"),
+
+        &kern::CacheGetRequest { .. } =>
+            send(&kern::CacheGetReply { value: &[] }),
+        &kern::CachePutRequest { .. } =>
+            send(&kern::CachePutReply { succeeded: false, quota_exceeded: false }),
+
+        &kern::DmaRecordStart(_) | &kern::DmaRecordAppend(_) |
+        &kern::DmaRecordMark { .. } | &kern::DmaRecordStop { .. } |
+        &kern::DmaEraseRequest { .. } => mailbox::acknowledge(),
+        &kern::DmaRetrieveRequest { .. } =>
+            send(&kern::DmaRetrieveReply { trace: None, duration: 0 }),
+        &kern::DmaSegmentRequest { .. } =>
+            send(&kern::DmaSegmentReply(Err(kern::DmaSegmentError::TraceNotFound))),
+        &kern::DmaChainRequest { .. } =>
+            send(&kern::DmaChainReply { found: false }),
+
+        &kern::I2cStartRequest { .. } | &kern::I2cRestartRequest { .. } |
+        &kern::I2cStopRequest { .. } =>
+            send(&kern::I2cBasicReply { succeeded: false }),
+        &kern::I2cWriteRequest { .. } =>
+            send(&kern::I2cWriteReply { succeeded: false, ack: false }),
+        &kern::I2cReadRequest { .. } =>
+            send(&kern::I2cReadReply { succeeded: false, data: 0 }),
+        &kern::I2cWriteBufferRequest { .. } =>
+            send(&kern::I2cWriteBufferReply { succeeded: false, acked: 0 }),
+        &kern::I2cReadBufferRequest { .. } =>
+            send(&kern::I2cReadBufferReply { succeeded: false, data: &[] }),
+        &kern::SpiSetConfigRequest { .. } | &kern::SpiWriteRequest { .. } |
+        &kern::SpiWriteBufferRequest { .. } =>
+            send(&kern::SpiBasicReply { succeeded: false }),
+        &kern::SpiReadRequest { .. } =>
+            send(&kern::SpiReadReply { succeeded: false, data: 0 }),
+        &kern::SpiReadBufferRequest { .. } | &kern::SpiTransferRequest { .. } =>
+            send(&kern::SpiReadBufferReply { succeeded: false, data: &[] }),
+
+        &kern::RunFinished => {
+            unsafe { LOG_LINE.flush() }
+            info!("idle kernel finished");
+            return Ok(true)
+        }
+        &kern::RunException { exception: kern::Exception { name, message, .. }, .. } => {
+            unsafe { LOG_LINE.flush() }
+            error!("idle kernel raised {}: {}", name, message);
+            return Ok(true)
+        }
+        &kern::RunAborted => return Err("kernel CPU aborted"),
+
+        _ => return Err("unexpected request from kernel CPU")
+    }
+    Ok(false)
+}
@@ -6,9 +6,13 @@ extern crate log;
 #[macro_use]
 extern crate board_misoc;
 extern crate board_artiq;
+extern crate logger_artiq;
+#[cfg(has_kernel_cpu)]
+extern crate proto_artiq;
 
+use core::cmp::min;
 use core::convert::TryFrom;
-use board_misoc::{csr, irq, ident, clock, uart_logger, i2c};
+use board_misoc::{csr, irq, ident, clock, boot, i2c, sfp};
 #[cfg(has_si5324)]
 use board_artiq::si5324;
 #[cfg(has_wrpll)]
@@ -19,6 +23,14 @@ use board_artiq::drtio_routing;
 use board_artiq::hmc830_7043;
 
 mod repeater;
+mod remote_log;
+mod segment;
+#[cfg(has_spiflash)]
+mod update;
+#[cfg(has_kernel_cpu)]
+mod kernel;
+mod sfp_monitor;
+mod subscription;
 #[cfg(has_jdcg)]
 mod jdcg;
 #[cfg(any(has_ad9154, has_jdcg))]
@@ -52,6 +64,119 @@ fn drtiosat_tsc_loaded() -> bool {
     }
 }
 
+// Offsets of the TSC from the time of the master, in coarse RTIO cycles,
+// found at the synchronizations with the master. The first one after the
+// uplink comes up loads the TSC, which is not counted after boot since it
+// only sets the free-running counter. The master then synchronizes again
+// periodically, and these only measure the offset, so that kernels do not
+// see the time jump; a TSC found off is loaded at the next one.
+struct TscStats {
+    syncs:       u32,
+    offset:      i32,
+    max_offset:  u32,
+    interval_ms: u32,
+    phase_fs:    Option<i32>,
+    loaded_at:   Option<u64>
+}
+
+static mut TSC_STATS: TscStats = TscStats {
+    syncs: 0, offset: 0, max_offset: 0, interval_ms: 0, phase_fs: None, loaded_at: None
+};
+
+// Errors seen by the satellite itself, since boot or the last clear by the
+// master, which merges them into the counters of the destination.
+struct RtioCounters {
+    underflows:            u32,
+    overflows:             u32,
+    buffer_space_timeouts: u32,
+    protocol_errors:       u32,
+    tsc_resyncs:           u32
+}
+
+static mut RTIO_COUNTERS: RtioCounters = RtioCounters {
+    underflows: 0, overflows: 0, buffer_space_timeouts: 0, protocol_errors: 0, tsc_resyncs: 0
+};
+
+fn rtio_counters() -> &'static mut RtioCounters {
+    unsafe { &mut RTIO_COUNTERS }
+}
+
+// Calls `f` with the address, value and whether it is part of the profile of
+// each register of the clocking chip `chip`. Returns false if the gateware
+// has no such chip.
+fn clock_registers<F: FnMut(u8, u8, bool)>(chip: u8, _f: F) -> Result<bool, &'static str> {
+    match chip {
+        #[cfg(has_si5324)]
+        drtioaux::CLOCK_CHIP_SI5324 => si5324::snapshot(_f).map(|()| true),
+        #[cfg(has_wrpll)]
+        drtioaux::CLOCK_CHIP_SI549_MAIN => wrpll::snapshot(wrpll::Dcxo::Main, _f).map(|()| true),
+        #[cfg(has_wrpll)]
+        drtioaux::CLOCK_CHIP_SI549_HELPER => wrpll::snapshot(wrpll::Dcxo::Helper, _f).map(|()| true),
+        _ => Ok(false)
+    }
+}
+
+// Programs the clocking chips from the register profiles saved in the
+// configuration, if any, after their default setup.
+fn restore_clock_profiles() {
+    #[cfg(has_si5324)]
+    board_misoc::config::read("si5324_profile", |result| match result {
+        Ok(profile) if profile.len() > 0 => match si5324::restore(profile) {
+            Ok(()) => info!("Si5324 programmed from the si5324_profile config key"),
+            Err(e) => error!("cannot apply the si5324_profile config key: {}", e)
+        },
+        _ => ()
+    });
+    #[cfg(has_wrpll)]
+    {
+        for &(dcxo, key) in [(wrpll::Dcxo::Main, "si549_main_profile"),
+                             (wrpll::Dcxo::Helper, "si549_helper_profile")].iter() {
+            board_misoc::config::read(key, |result| match result {
+                Ok(profile) if profile.len() > 0 => match wrpll::restore(dcxo, profile) {
+                    Ok(()) => info!("{:?} Si549 programmed from the {} config key", dcxo, key),
+                    Err(e) => error!("cannot apply the {} config key: {}", key, e)
+                },
+                _ => ()
+            });
+        }
+    }
+}
+
+#[cfg(has_wrpll)]
+fn rtio_clock_phase_fs() -> Option<i32> { wrpll::main_phase_fs() }
+#[cfg(not(has_wrpll))]
+fn rtio_clock_phase_fs() -> Option<i32> { None }
+
+// Returns whether the TSC was loaded, rather than measured.
+fn record_tsc_sync() -> bool {
+    let (measured, offset) = unsafe {
+        (csr::drtiosat::tsc_measure_read() != 0, csr::drtiosat::tsc_offset_read() as i32)
+    };
+    let now = clock::get_ms();
+    let stats = unsafe { &mut TSC_STATS };
+    if let Some(loaded_at) = stats.loaded_at {
+        stats.syncs += 1;
+        stats.offset = offset;
+        stats.max_offset = stats.max_offset.max((offset as i64).abs() as u32);
+        stats.interval_ms = (now - loaded_at) as u32;
+        stats.phase_fs = rtio_clock_phase_fs();
+        if offset != 0 {
+            if measured {
+                warn!("TSC off by {} cycles, {} ms after it was loaded, loading it again",
+                      offset, stats.interval_ms);
+            } else {
+                warn!("TSC corrected by {} cycles, {} ms after it was loaded",
+                      offset, stats.interval_ms);
+            }
+            rtio_counters().tsc_resyncs += 1;
+        }
+    }
+    if !measured {
+        stats.loaded_at = Some(now);
+    }
+    unsafe { csr::drtiosat::tsc_measure_write((!measured || offset == 0) as u8) }
+    !measured
+}
 
 #[cfg(has_drtio_routing)]
 macro_rules! forward {
@@ -73,6 +198,32 @@ macro_rules! forward {
     ($routing_table:expr, $destination:expr, $rank:expr, $repeaters:expr, $packet:expr) => {}
 }
 
+#[cfg(soc_platform = "kasli")]
+fn serial() -> Option<[u8; 6]> {
+    board_misoc::i2c_eeprom::EEPROM::new().read_eui48().ok()
+}
+
+#[cfg(not(soc_platform = "kasli"))]
+fn serial() -> Option<[u8; 6]> {
+    None
+}
+
+// Handles a segmented message from the master; returns false if it failed.
+fn process_segmented(kind: u8, message: &[u8]) -> bool {
+    match kind {
+        #[cfg(has_spiflash)]
+        drtioaux::SEGMENTED_FIRMWARE_BLOCK if message.len() >= 4 => {
+            let offset = (message[0] as usize) << 24 | (message[1] as usize) << 16 |
+                         (message[2] as usize) << 8 | message[3] as usize;
+            update::write(offset, &message[4..])
+        }
+        _ => {
+            error!("unsupported segmented message (kind {}, {} bytes)", kind, message.len());
+            false
+        }
+    }
+}
+
 fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
         _routing_table: &mut drtio_routing::RoutingTable, _rank: &mut u8,
         packet: drtioaux::Packet) -> Result<(), drtioaux::Error<!>> {
@@ -130,7 +281,9 @@ fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
                     drtioaux::send(0,
                         &drtioaux::Packet::DestinationBusyReply { channel })?;
                 }
-                else {
+                else if subscription::changes_pending() {
+                    drtioaux::send(0, &drtioaux::Packet::DestinationOkChangesReply)?;
+                } else {
                     drtioaux::send(0, &drtioaux::Packet::DestinationOkReply)?;
                 }
             }
@@ -239,9 +392,62 @@ fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
             }
             drtioaux::send(0, &drtioaux::Packet::InjectionStatusReply { value: value })
         },
+        drtioaux::Packet::MonitorBatchRequest { destination: _destination, count, channels, probes } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let mut values = [0; drtioaux::MONITOR_BATCH_SIZE];
+            #[cfg(has_rtio_moninj)]
+            for ((value, &channel), &probe) in values.iter_mut().zip(channels.iter())
+                                                    .zip(probes.iter()).take(count as usize) {
+                unsafe {
+                    csr::rtio_moninj::mon_chan_sel_write(channel as _);
+                    csr::rtio_moninj::mon_probe_sel_write(probe);
+                    csr::rtio_moninj::mon_value_update_write(1);
+                    *value = csr::rtio_moninj::mon_value_read() as u32;
+                }
+            }
+            #[cfg(not(has_rtio_moninj))]
+            let _ = (channels, probes);
+            drtioaux::send(0, &drtioaux::Packet::MonitorBatchReply { count: count, values: values })
+        },
+        drtioaux::Packet::InjectionStatusBatchRequest { destination: _destination, count, channels, overrds } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let mut values = [0; drtioaux::MONITOR_BATCH_SIZE];
+            #[cfg(has_rtio_moninj)]
+            for ((value, &channel), &overrd) in values.iter_mut().zip(channels.iter())
+                                                     .zip(overrds.iter()).take(count as usize) {
+                unsafe {
+                    csr::rtio_moninj::inj_chan_sel_write(channel as _);
+                    csr::rtio_moninj::inj_override_sel_write(overrd);
+                    *value = csr::rtio_moninj::inj_value_read() as u32;
+                }
+            }
+            #[cfg(not(has_rtio_moninj))]
+            let _ = (channels, overrds);
+            drtioaux::send(0, &drtioaux::Packet::MonitorBatchReply { count: count, values: values })
+        },
+        drtioaux::Packet::MonitorSubscribeRequest { destination: _destination, offset, count, channels, probes } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let count = min(count as usize, drtioaux::MONITOR_BATCH_SIZE);
+            let succeeded = subscription::subscribe(offset as usize, &channels[..count], &probes[..count]);
+            drtioaux::send(0, &drtioaux::Packet::MonitorSubscribeReply { succeeded: succeeded })
+        },
+        drtioaux::Packet::MonitorChangesRequest { destination: _destination } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let mut indices = [0; drtioaux::MONITOR_BATCH_SIZE];
+            let mut values = [0; drtioaux::MONITOR_BATCH_SIZE];
+            let (count, more) = subscription::take_changes(&mut indices, &mut values);
+            drtioaux::send(0, &drtioaux::Packet::MonitorChangesReply {
+                entries: subscription::count() as u16,
+                more: more,
+                count: count,
+                indices: indices,
+                values: values
+            })
+        },
 
         drtioaux::Packet::I2cStartRequest { destination: _destination, busno } => {
             forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            sfp_monitor::i2c_used();
             let succeeded = i2c::start(busno).is_ok();
             drtioaux::send(0, &drtioaux::Packet::I2cBasicReply { succeeded: succeeded })
         }
@@ -333,6 +539,227 @@ fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
                 &drtioaux::Packet::JdacBasicReply { succeeded: succeeded, retval: retval })
         }
 
+        drtioaux::Packet::Si549TrimRequest { destination: _destination, ppb: _ppb } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            #[cfg(has_wrpll)]
+            let succeeded = match wrpll::set_main_trim(_ppb) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("Si549 trim failed: {}", e);
+                    false
+                }
+            };
+            #[cfg(not(has_wrpll))]
+            let succeeded = false;
+            drtioaux::send(0,
+                &drtioaux::Packet::Si549TrimReply { succeeded: succeeded })
+        }
+
+        drtioaux::Packet::WrpllStatsRequest { destination: _destination } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            #[cfg(has_wrpll)]
+            let reply = {
+                let stats = wrpll::stats();
+                drtioaux::Packet::WrpllStatsReply {
+                    available: true,
+                    locked: stats.locked,
+                    lock_time_ms: stats.lock_time_ms,
+                    unlock_count: stats.unlock_count,
+                    samples: stats.samples,
+                    helper_rms_mtags: (stats.helper_rms * 1000.0) as u32,
+                    main_rms_mtags: (stats.main_rms * 1000.0) as u32
+                }
+            };
+            #[cfg(not(has_wrpll))]
+            let reply = drtioaux::Packet::WrpllStatsReply {
+                available: false, locked: false, lock_time_ms: 0, unlock_count: 0,
+                samples: 0, helper_rms_mtags: 0, main_rms_mtags: 0
+            };
+            drtioaux::send(0, &reply)
+        }
+
+        drtioaux::Packet::IdentRequest { destination: _destination, item, offset } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let mut buffer = [0; 64];
+            let ident: &[u8] = match item {
+                drtioaux::IDENT_FIRMWARE => csr::CONFIG_IDENTIFIER_STR.as_bytes(),
+                drtioaux::IDENT_GATEWARE => ident::read(&mut buffer).as_bytes(),
+                drtioaux::IDENT_SERIAL => match serial() {
+                    Some(serial) => {
+                        buffer[..6].copy_from_slice(&serial);
+                        &buffer[..6]
+                    }
+                    None => &[]
+                },
+                #[cfg(has_rtio_topology)]
+                drtioaux::IDENT_TOPOLOGY => csr::CONFIG_RTIO_TOPOLOGY.as_bytes(),
+                _ => &[]
+            };
+            let start = min(offset as usize, ident.len());
+            let end = min(start + 32, ident.len());
+            let mut data = [0; 32];
+            data[..end - start].copy_from_slice(&ident[start..end]);
+            drtioaux::send(0, &drtioaux::Packet::IdentReply {
+                length: ident.len() as u8,
+                data: data
+            })
+        }
+
+        drtioaux::Packet::FirmwareUpdateStartRequest { destination: _destination, length: _length } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            #[cfg(has_spiflash)]
+            let succeeded = update::start(_length as usize);
+            #[cfg(not(has_spiflash))]
+            let succeeded = false;
+            drtioaux::send(0, &drtioaux::Packet::FirmwareUpdateReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::FirmwareUpdateDataRequest {
+                destination: _destination, offset: _offset, length: _length, data: _data } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            #[cfg(has_spiflash)]
+            let succeeded = {
+                let mut chunk = [0; drtioaux::FIRMWARE_CHUNK_SIZE];
+                for (i, row) in _data.iter().enumerate() {
+                    chunk[i * 32..(i + 1) * 32].copy_from_slice(row);
+                }
+                let length = min(_length as usize, chunk.len());
+                update::write(_offset as usize, &chunk[..length])
+            };
+            #[cfg(not(has_spiflash))]
+            let succeeded = false;
+            drtioaux::send(0, &drtioaux::Packet::FirmwareUpdateReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::FirmwareUpdateFinishRequest { destination: _destination } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            #[cfg(has_spiflash)]
+            let succeeded = update::finish();
+            #[cfg(not(has_spiflash))]
+            let succeeded = false;
+            drtioaux::send(0, &drtioaux::Packet::FirmwareUpdateReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::SegmentRequest {
+                destination: _destination, kind, transfer, seq, last, crc, length, data } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let mut bytes = [0; drtioaux::SEGMENT_SIZE];
+            for (i, row) in data.iter().enumerate() {
+                bytes[i * 32..(i + 1) * 32].copy_from_slice(row);
+            }
+            let length = min(length as usize, bytes.len());
+            let status = segment::receive(kind, transfer, seq, last, crc, &bytes[..length],
+                                          process_segmented);
+            drtioaux::send(0, &drtioaux::Packet::SegmentReply { seq: seq, status: status })
+        }
+        drtioaux::Packet::RebootRequest { destination: _destination } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            drtioaux::send(0, &drtioaux::Packet::FirmwareUpdateReply { succeeded: true })?;
+            // let the reply go out before the transceivers stop
+            clock::spin_us(10_000);
+            warn!("restarting");
+            unsafe { boot::reset() }
+        }
+
+        drtioaux::Packet::SfpStatusRequest { destination: _destination, port } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let status = sfp_monitor::status(port as usize);
+            let present = status.map_or(false, |status| status.present);
+            let alarms = status.map_or(0, |status| status.alarms);
+            let reply = match status.and_then(|status| status.diagnostics) {
+                Some(d) => drtioaux::Packet::SfpStatusReply {
+                    count: sfp::count() as u8, present: present, diagnostics: true,
+                    temperature: d.temperature, voltage: d.voltage, tx_bias: d.tx_bias,
+                    tx_power: d.tx_power, rx_power: d.rx_power, alarms: alarms
+                },
+                None => drtioaux::Packet::SfpStatusReply {
+                    count: sfp::count() as u8, present: present, diagnostics: false,
+                    temperature: 0, voltage: 0, tx_bias: 0, tx_power: 0, rx_power: 0,
+                    alarms: alarms
+                }
+            };
+            drtioaux::send(0, &reply)
+        }
+
+        drtioaux::Packet::TscStatsRequest { destination: _destination } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let stats = unsafe { &TSC_STATS };
+            drtioaux::send(0, &drtioaux::Packet::TscStatsReply {
+                syncs: stats.syncs,
+                offset: stats.offset,
+                max_offset: stats.max_offset,
+                interval_ms: stats.interval_ms,
+                phase_fs: stats.phase_fs
+            })
+        }
+
+        drtioaux::Packet::RtioCountersRequest { destination: _destination, clear } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let counters = rtio_counters();
+            let reply = drtioaux::Packet::RtioCountersReply {
+                underflows: counters.underflows,
+                overflows: counters.overflows,
+                buffer_space_timeouts: counters.buffer_space_timeouts,
+                protocol_errors: counters.protocol_errors,
+                tsc_resyncs: counters.tsc_resyncs
+            };
+            if clear {
+                *counters = RtioCounters {
+                    underflows: 0, overflows: 0, buffer_space_timeouts: 0, protocol_errors: 0,
+                    tsc_resyncs: 0
+                };
+            }
+            drtioaux::send(0, &reply)
+        }
+
+        drtioaux::Packet::ClockRegistersRequest { destination: _destination, chip, offset } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let mut registers = [0; drtioaux::CLOCK_REGISTERS_SIZE];
+            let mut values = [0; drtioaux::CLOCK_REGISTERS_SIZE];
+            let mut configuration = 0;
+            let mut count = 0;
+            let mut index = 0;
+            let result = clock_registers(chip, |register, value, profile| {
+                if index >= offset as usize && count < drtioaux::CLOCK_REGISTERS_SIZE {
+                    registers[count] = register;
+                    values[count] = value;
+                    if profile {
+                        configuration |= 1 << count;
+                    }
+                    count += 1;
+                }
+                index += 1;
+            });
+            let available = match result {
+                Ok(available) => available,
+                Err(e) => {
+                    error!("cannot read the registers of clocking chip {}: {}", chip, e);
+                    false
+                }
+            };
+            drtioaux::send(0, &drtioaux::Packet::ClockRegistersReply {
+                available: available,
+                count: count as u8,
+                configuration: configuration,
+                registers: registers,
+                values: values
+            })
+        }
+
+        drtioaux::Packet::LogRequest { destination: _destination, acknowledged, sequence } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let mut chunk = [0; drtioaux::LOG_CHUNK_SIZE];
+            let acknowledged = if acknowledged { Some(sequence) } else { None };
+            let (sequence, length, more) = remote_log::next_chunk(acknowledged, &mut chunk);
+            let mut data = [[0; 32]; 16];
+            for (row, bytes) in data.iter_mut().zip(chunk.chunks(32)) {
+                row.copy_from_slice(bytes);
+            }
+            drtioaux::send(0, &drtioaux::Packet::LogReply {
+                sequence: sequence,
+                more: more,
+                length: length as u16,
+                data: data
+            })
+        }
+
         _ => {
             warn!("received unexpected aux packet");
             Ok(())
@@ -340,11 +767,19 @@ fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
     }
 }
 
+// The master polls the destination status of every satellite several times
+// per second, so a link that is up but carries no valid aux packet for this
+// long has a desynchronized aux receiver.
+const AUX_STALL_TIMEOUT_MS: u64 = 10_000;
+
+/// Returns true if a valid aux packet was received.
 fn process_aux_packets(repeaters: &mut [repeater::Repeater],
-        routing_table: &mut drtio_routing::RoutingTable, rank: &mut u8) {
+        routing_table: &mut drtio_routing::RoutingTable, rank: &mut u8) -> bool {
+    let mut received = false;
     let result =
         drtioaux::recv(0).and_then(|packet| {
             if let Some(packet) = packet {
+                received = true;
                 process_aux_packet(repeaters, routing_table, rank, packet)
             } else {
                 Ok(())
@@ -354,6 +789,7 @@ fn process_aux_packets(repeaters: &mut [repeater::Repeater],
         Ok(()) => (),
         Err(e) => warn!("aux packet error ({})", e)
     }
+    received
 }
 
 fn drtiosat_process_errors() {
@@ -361,18 +797,22 @@ fn drtiosat_process_errors() {
     unsafe {
         errors = csr::drtiosat::protocol_error_read();
     }
+    let counters = rtio_counters();
     if errors & 1 != 0 {
         error!("received packet of an unknown type");
+        counters.protocol_errors += 1;
     }
     if errors & 2 != 0 {
         error!("received truncated packet");
+        counters.protocol_errors += 1;
     }
     if errors & 4 != 0 {
         let destination;
         unsafe {
             destination = csr::drtiosat::buffer_space_timeout_dest_read();
         }
-        error!("timeout attempting to get buffer space from CRI, destination=0x{:02x}", destination)
+        error!("timeout attempting to get buffer space from CRI, destination=0x{:02x}", destination);
+        counters.buffer_space_timeouts += 1;
     }
     if errors & 8 != 0 {
         let channel;
@@ -385,9 +825,11 @@ fn drtiosat_process_errors() {
         }
         error!("write underflow, channel={}, timestamp={}, counter={}, slack={}",
                channel, timestamp_event, timestamp_counter, timestamp_event-timestamp_counter);
+        counters.underflows += 1;
     }
     if errors & 16 != 0 {
         error!("write overflow");
+        counters.overflows += 1;
     }
     unsafe {
         csr::drtiosat::protocol_error_write(errors);
@@ -415,6 +857,10 @@ fn hardware_tick(ts: &mut u64) {
     if now > *ts {
         #[cfg(has_grabber)]
         board_artiq::grabber::tick();
+        #[cfg(has_wrpll)]
+        wrpll::tick();
+        sfp_monitor::tick();
+        log::logger().flush();
         *ts = now + 200;
     }
 }
@@ -445,10 +891,18 @@ const SI5324_SETTINGS: si5324::FrequencySettings
     crystal_ref: true
 };
 
+static mut LOG_BUFFER: [u8; remote_log::BUFFER_SIZE] = [0; remote_log::BUFFER_SIZE];
+
 #[no_mangle]
 pub extern fn main() -> i32 {
+    unsafe {
+        logger_artiq::BufferLogger::new(&mut LOG_BUFFER[..]).register(startup);
+    }
+    0
+}
+
+fn startup() {
     clock::init();
-    uart_logger::ConsoleLogger::register();
 
     info!("ARTIQ satellite manager starting...");
     info!("software ident {}", csr::CONFIG_IDENTIFIER_STR);
@@ -456,6 +910,10 @@ pub extern fn main() -> i32 {
 
     #[cfg(has_i2c)]
     i2c::init().expect("I2C initialization failed");
+    sfp::disable_unused(|index, result| match result {
+        Ok(()) => info!("SFP{} unused, TX disabled", index),
+        Err(e) => debug!("cannot disable the transmitter of SFP{}: {}", index, e)
+    });
     #[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
     let (mut io_expander0, mut io_expander1);
     #[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
@@ -476,15 +934,8 @@ pub extern fn main() -> i32 {
             io_expander1.service().unwrap();
         }
 
-        // Actively drive TX_DISABLE to false on SFP0..3
-        io_expander0.set_oe(0, 1 << 1).unwrap();
-        io_expander0.set_oe(1, 1 << 1).unwrap();
-        io_expander1.set_oe(0, 1 << 1).unwrap();
-        io_expander1.set_oe(1, 1 << 1).unwrap();
-        io_expander0.set(0, 1, false);
-        io_expander0.set(1, 1, false);
-        io_expander1.set(0, 1, false);
-        io_expander1.set(1, 1, false);
+        // Actively drive TX_DISABLE on SFP0..3, to true on the unused ports
+        sfp::init_expanders(&mut io_expander0, &mut io_expander1).unwrap();
         io_expander0.service().unwrap();
         io_expander1.service().unwrap();
     }
@@ -493,6 +944,7 @@ pub extern fn main() -> i32 {
     si5324::setup(&SI5324_SETTINGS, si5324::Input::Ckin1).expect("cannot initialize Si5324");
     #[cfg(has_wrpll)]
     wrpll::init();
+    restore_clock_profiles();
 
     unsafe {
         csr::drtio_transceiver::stable_clkin_write(1);
@@ -529,17 +981,32 @@ pub extern fn main() -> i32 {
 
     let mut hardware_tick_ts = 0;
 
+    // Firmware on trial checks in once it has kept the uplink up for a while,
+    // so that an update which breaks DRTIO is rolled back.
+    #[cfg(has_spiflash)]
+    let mut trial_ms = update::setup_trial();
+
     loop {
         #[cfg(has_jdcg)]
         unsafe {
             // Hide from uplink until RTM is ready
             csr::drtio_transceiver::txenable_write(0xfffffffeu32 as _);
         }
+        #[cfg(has_kernel_cpu)]
+        {
+            if !drtiosat_link_rx_up() && kernel::start() {
+                // Take the local RTIO channels out of reset for the idle kernel.
+                drtiosat_reset(false);
+                drtiosat_reset_phy(false);
+            }
+        }
         while !drtiosat_link_rx_up() {
             drtiosat_process_errors();
             for mut rep in repeaters.iter_mut() {
                 rep.service(&routing_table, rank);
             }
+            #[cfg(has_kernel_cpu)]
+            kernel::service(&routing_table, rank, &repeaters);
             #[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
             {
                 io_expander0.service().expect("I2C I/O expander #0 service failed");
@@ -548,6 +1015,16 @@ pub extern fn main() -> i32 {
             hardware_tick(&mut hardware_tick_ts);
         }
 
+        #[cfg(has_kernel_cpu)]
+        {
+            if kernel::running() {
+                kernel::stop();
+                // Clear the events left by the idle kernel.
+                drtiosat_reset_phy(true);
+                drtiosat_reset(true);
+            }
+        }
+
         info!("uplink is up, switching to recovered clock");
         #[cfg(has_si5324)]
         {
@@ -560,12 +1037,25 @@ pub extern fn main() -> i32 {
         drtioaux::reset(0);
         drtiosat_reset(false);
         drtiosat_reset_phy(false);
+        // The master subscribes again once it sees the destination up.
+        subscription::clear();
+
+        #[cfg(has_spiflash)]
+        let check_in_at = trial_ms.map(|trial_ms| clock::get_ms() + trial_ms);
 
         #[cfg(has_jdcg)]
         let mut was_up = false;
+        let mut aux_stall_at = clock::get_ms() + AUX_STALL_TIMEOUT_MS;
         while drtiosat_link_rx_up() {
             drtiosat_process_errors();
-            process_aux_packets(&mut repeaters, &mut routing_table, &mut rank);
+            if process_aux_packets(&mut repeaters, &mut routing_table, &mut rank) {
+                aux_stall_at = clock::get_ms() + AUX_STALL_TIMEOUT_MS;
+            } else if clock::get_ms() > aux_stall_at {
+                error!("no valid aux packet for {} ms, resetting aux receiver",
+                       AUX_STALL_TIMEOUT_MS);
+                drtioaux::reset(0);
+                aux_stall_at = clock::get_ms() + AUX_STALL_TIMEOUT_MS;
+            }
             for mut rep in repeaters.iter_mut() {
                 rep.service(&routing_table, rank);
             }
@@ -575,20 +1065,32 @@ pub extern fn main() -> i32 {
                 io_expander1.service().expect("I2C I/O expander #1 service failed");
             }
             hardware_tick(&mut hardware_tick_ts);
+            subscription::tick();
+            #[cfg(has_spiflash)]
+            {
+                if trial_ms.is_some() && check_in_at.map(|at| clock::get_ms() > at) == Some(true) {
+                    update::check_in();
+                    trial_ms = None;
+                }
+            }
             if drtiosat_tsc_loaded() {
-                info!("TSC loaded from uplink");
-                #[cfg(has_jdcg)]
-                {
-                    // We assume that the RTM on repeater0 is up.
-                    // Uplink should not send a TSC load command unless the link is
-                    // up, and we are hiding when the RTM is down.
-                    if let Err(e) = jdcg::jesd204sync::sysref_rtio_align() {
-                        error!("failed to align SYSREF with TSC ({})", e);
-                    }
-                    if let Err(e) = jdcg::jesd204sync::resync_dacs() {
-                        error!("DAC resync failed after SYSREF/TSC realignment ({})", e);
+                if record_tsc_sync() {
+                    info!("TSC loaded from uplink");
+                    #[cfg(has_jdcg)]
+                    {
+                        // We assume that the RTM on repeater0 is up.
+                        // Uplink should not send a TSC load command unless the link is
+                        // up, and we are hiding when the RTM is down.
+                        if let Err(e) = jdcg::jesd204sync::sysref_rtio_align() {
+                            error!("failed to align SYSREF with TSC ({})", e);
+                        }
+                        if let Err(e) = jdcg::jesd204sync::resync_dacs() {
+                            error!("DAC resync failed after SYSREF/TSC realignment ({})", e);
+                        }
                     }
                 }
+                // Measurements are passed on as well, for the satellites
+                // downstream to measure their offsets.
                 for rep in repeaters.iter() {
                     if let Err(e) = rep.sync_tsc() {
                         error!("failed to sync TSC ({})", e);
@@ -633,6 +1135,8 @@ pub extern fn main() -> i32 {
         drtiosat_reset_phy(true);
         drtiosat_reset(true);
         drtiosat_tsc_loaded();
+        // The master loads the TSC again when the uplink comes back.
+        unsafe { csr::drtiosat::tsc_measure_write(0) }
         info!("uplink is down, switching to local oscillator clock");
         #[cfg(has_si5324)]
         si5324::siphaser::select_recovered_clock(false).expect("failed to switch clocks");
@@ -642,24 +1146,9 @@ pub extern fn main() -> i32 {
 }
 
 #[no_mangle]
-pub extern fn exception(vect: u32, _regs: *const u32, pc: u32, ea: u32) {
+pub extern fn exception(vect: u32, regs: *const u32, pc: u32, ea: u32, sr: u32) {
+    print!("{}", unsafe { board_misoc::exception::Dump::capture(vect, regs, pc, ea, sr) });
     let vect = irq::Exception::try_from(vect).expect("unknown exception");
-
-    fn hexdump(addr: u32) {
-        let addr = (addr - addr % 4) as *const u32;
-        let mut ptr  = addr;
-        println!("@ {:08p}", ptr);
-        for _ in 0..4 {
-            print!("+{:04x}: ", ptr as usize - addr as usize);
-            print!("{:08x} ",   unsafe { *ptr }); ptr = ptr.wrapping_offset(1);
-            print!("{:08x} ",   unsafe { *ptr }); ptr = ptr.wrapping_offset(1);
-            print!("{:08x} ",   unsafe { *ptr }); ptr = ptr.wrapping_offset(1);
-            print!("{:08x}\n",  unsafe { *ptr }); ptr = ptr.wrapping_offset(1);
-        }
-    }
-
-    hexdump(pc);
-    hexdump(ea);
     panic!("exception {:?} at PC 0x{:x}, EA 0x{:x}", vect, pc, ea)
 }
 
@@ -0,0 +1,58 @@
+use core::cmp::min;
+use logger_artiq::BufferLogger;
+
+// Log messages are kept in the buffer of the logger until the master asks
+// for them; they are then moved into the outbox and sent in chunks. A chunk
+// leaves the outbox only once the master acknowledged it.
+
+pub const BUFFER_SIZE: usize = 1 << 13;
+
+static mut OUTBOX: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+static mut OUTBOX_LENGTH: usize = 0;
+static mut OUTBOX_SENT: usize = 0;
+// Sequence number and length of the chunk waiting for an acknowledgement.
+static mut PENDING: Option<(u16, usize)> = None;
+static mut SEQUENCE: u16 = 0;
+
+/// Fills `data` with the log bytes to send to the master, after removing the
+/// chunk with sequence number `acknowledged`, if it is the one sent last.
+/// Returns the sequence number of the chunk, the number of bytes and whether
+/// more are waiting.
+pub fn next_chunk(acknowledged: Option<u16>, data: &mut [u8]) -> (u16, usize, bool) {
+    unsafe {
+        if let Some((sequence, length)) = PENDING {
+            if acknowledged == Some(sequence) {
+                OUTBOX_SENT += length;
+                PENDING = None;
+            }
+        }
+
+        let (sequence, length) = match PENDING {
+            Some(pending) => pending,
+            None => {
+                if OUTBOX_SENT == OUTBOX_LENGTH {
+                    OUTBOX_SENT = 0;
+                    OUTBOX_LENGTH = 0;
+                    BufferLogger::with(|logger| {
+                        if let Ok(mut buffer) = logger.buffer() {
+                            {
+                                let messages = buffer.extract().as_bytes();
+                                let length = min(messages.len(), OUTBOX.len());
+                                OUTBOX[..length].copy_from_slice(&messages[..length]);
+                                OUTBOX_LENGTH = length;
+                            }
+                            buffer.clear();
+                        }
+                    });
+                }
+                SEQUENCE = SEQUENCE.wrapping_add(1);
+                let pending = (SEQUENCE, min(data.len(), OUTBOX_LENGTH - OUTBOX_SENT));
+                PENDING = Some(pending);
+                pending
+            }
+        };
+
+        data[..length].copy_from_slice(&OUTBOX[OUTBOX_SENT..OUTBOX_SENT + length]);
+        (sequence, length, OUTBOX_SENT + length < OUTBOX_LENGTH)
+    }
+}
@@ -183,8 +183,15 @@ impl Repeater {
         if self.state != RepeaterState::Up {
             return Err(drtioaux::Error::LinkDown);
         }
+        // Programming flash takes longer than other requests.
+        let timeout = match *request {
+            drtioaux::Packet::FirmwareUpdateStartRequest { .. } |
+            drtioaux::Packet::FirmwareUpdateDataRequest { .. } |
+            drtioaux::Packet::FirmwareUpdateFinishRequest { .. } => 5000,
+            _ => 200
+        };
         drtioaux::send(self.auxno, request).unwrap();
-        let reply = self.recv_aux_timeout(200)?;
+        let reply = self.recv_aux_timeout(timeout)?;
         drtioaux::send(0, &reply).unwrap();
         Ok(())
     }
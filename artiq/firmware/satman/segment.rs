@@ -0,0 +1,62 @@
+use board_artiq::drtioaux;
+
+// Segmented messages from the master, reassembled here before they are
+// handled; see `drtioaux_proto`.
+
+static mut BUFFER: [u8; drtioaux::MAX_SEGMENTED_SIZE] = [0; drtioaux::MAX_SEGMENTED_SIZE];
+static mut LENGTH: usize = 0;
+static mut KIND: u8 = 0;
+static mut TRANSFER: Option<u8> = None;
+// Sequence number of the next expected segment.
+static mut NEXT_SEQ: u16 = 0;
+// Status of the last segment accepted, sent again with its duplicates.
+static mut LAST_STATUS: u8 = drtioaux::SEGMENT_OK;
+
+/// Adds a segment to the message being reassembled and calls `handle` with
+/// the kind and contents of the message once the last segment is received.
+/// Returns the status of the segment for `SegmentReply`.
+pub fn receive<F>(kind: u8, transfer: u8, seq: u16, last: bool, crc: u32, data: &[u8],
+                  handle: F) -> u8
+    where F: FnOnce(u8, &[u8]) -> bool
+{
+    unsafe {
+        if drtioaux::segment_crc(data) != crc {
+            warn!("corrupted segment {} of transfer {}", seq, transfer);
+            return drtioaux::SEGMENT_CORRUPTED
+        }
+
+        // A segment is sent again when its reply was lost.
+        if TRANSFER == Some(transfer) && NEXT_SEQ > 0 && seq == NEXT_SEQ - 1 {
+            return LAST_STATUS
+        }
+        if seq == 0 {
+            TRANSFER = Some(transfer);
+            KIND = kind;
+            LENGTH = 0;
+            NEXT_SEQ = 0;
+        }
+        if TRANSFER != Some(transfer) || seq != NEXT_SEQ || kind != KIND {
+            error!("unexpected segment {} of transfer {} (expected segment {} of {:?})",
+                   seq, transfer, NEXT_SEQ, TRANSFER);
+            return drtioaux::SEGMENT_OUT_OF_ORDER
+        }
+        if LENGTH + data.len() > BUFFER.len() {
+            error!("segmented message of transfer {} exceeds {} bytes", transfer, BUFFER.len());
+            TRANSFER = None;
+            return drtioaux::SEGMENT_TOO_LARGE
+        }
+
+        BUFFER[LENGTH..LENGTH + data.len()].copy_from_slice(data);
+        LENGTH += data.len();
+        NEXT_SEQ += 1;
+        LAST_STATUS =
+            if !last {
+                drtioaux::SEGMENT_OK
+            } else if handle(KIND, &BUFFER[..LENGTH]) {
+                drtioaux::SEGMENT_OK
+            } else {
+                drtioaux::SEGMENT_REJECTED
+            };
+        LAST_STATUS
+    }
+}
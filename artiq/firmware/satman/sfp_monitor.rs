@@ -0,0 +1,45 @@
+use board_misoc::{clock, sfp};
+use board_misoc::sfp::{Event, Monitor, Status};
+
+// The SFP ports are polled with the same logic as on the master. Their state
+// is kept for the master, which reads it over the aux channel, and their
+// events are logged, so that the master also gets them with the remote log.
+
+const POLL_INTERVAL_MS: u64 = 5000;
+// Kernels on the master may use the I2C switches of the satellite through the
+// aux channel; the SFP ports are not read until they have been left alone for
+// this long.
+const I2C_QUIET_MS: u64 = 10_000;
+
+static mut MONITOR: Option<Monitor> = None;
+static mut POLL_AT: u64 = 0;
+
+/// Notes that the master is using the I2C buses.
+pub fn i2c_used() {
+    unsafe { POLL_AT = clock::get_ms() + I2C_QUIET_MS }
+}
+
+pub fn tick() {
+    if sfp::count() == 0 {
+        return
+    }
+    let now = clock::get_ms();
+    unsafe {
+        if now < POLL_AT {
+            return
+        }
+        POLL_AT = now + POLL_INTERVAL_MS;
+        if MONITOR.is_none() {
+            MONITOR = Some(Monitor::new());
+        }
+        MONITOR.as_mut().unwrap().poll(|index, event| match event {
+            Event::Removed | Event::AlarmRaised(_) => warn!("SFP{}: {}", index, event),
+            Event::Inserted | Event::AlarmCleared(_) => info!("SFP{}: {}", index, event)
+        });
+    }
+}
+
+/// Last state of SFP port `index`, if it has been read.
+pub fn status(index: usize) -> Option<Status> {
+    unsafe { MONITOR.as_ref().and_then(|monitor| monitor.status(index)) }
+}
@@ -0,0 +1,116 @@
+use board_artiq::drtioaux::{MONITOR_BATCH_SIZE, MONITOR_SUBSCRIPTION_SIZE};
+use board_misoc::clock;
+
+// Probes monitored by the clients of the master, which subscribes to them so
+// that it does not have to poll them. They are sampled while the uplink is
+// up; the changed values are flagged until the master fetches them, and it
+// learns that there are some from the reply to its destination survey. A
+// value that changed several times in between is reported once.
+const SAMPLE_INTERVAL_MS: u64 = 5;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    channel: u16,
+    probe:   u8,
+    value:   u32,
+    changed: bool
+}
+
+static mut ENTRIES: [Entry; MONITOR_SUBSCRIPTION_SIZE] =
+    [Entry { channel: 0, probe: 0, value: 0, changed: false }; MONITOR_SUBSCRIPTION_SIZE];
+static mut COUNT: usize = 0;
+static mut PENDING: bool = false;
+static mut SAMPLE_AT: u64 = 0;
+
+#[cfg(has_rtio_moninj)]
+fn read_probe(channel: u16, probe: u8) -> u32 {
+    use board_misoc::csr;
+
+    unsafe {
+        csr::rtio_moninj::mon_chan_sel_write(channel as _);
+        csr::rtio_moninj::mon_probe_sel_write(probe);
+        csr::rtio_moninj::mon_value_update_write(1);
+        csr::rtio_moninj::mon_value_read() as u32
+    }
+}
+
+#[cfg(not(has_rtio_moninj))]
+fn read_probe(_channel: u16, _probe: u8) -> u32 { 0 }
+
+/// Sets the entries from `offset` on and ends the list after them. Fails if
+/// this would leave a gap or exceed `MONITOR_SUBSCRIPTION_SIZE`.
+pub fn subscribe(offset: usize, channels: &[u16], probes: &[u8]) -> bool {
+    unsafe {
+        if offset > COUNT || offset + channels.len() > MONITOR_SUBSCRIPTION_SIZE {
+            return false
+        }
+        for (i, (&channel, &probe)) in channels.iter().zip(probes.iter()).enumerate() {
+            ENTRIES[offset + i] = Entry {
+                channel: channel,
+                probe:   probe,
+                value:   read_probe(channel, probe),
+                changed: true
+            };
+        }
+        COUNT = offset + channels.len();
+        PENDING = ENTRIES[..COUNT].iter().any(|entry| entry.changed);
+    }
+    true
+}
+
+/// Forgets the subscription, when the master may have lost track of it.
+pub fn clear() {
+    unsafe {
+        COUNT = 0;
+        PENDING = false;
+    }
+}
+
+pub fn count() -> usize {
+    unsafe { COUNT }
+}
+
+pub fn changes_pending() -> bool {
+    unsafe { PENDING }
+}
+
+pub fn tick() {
+    let now = clock::get_ms();
+    unsafe {
+        if now < SAMPLE_AT {
+            return
+        }
+        SAMPLE_AT = now + SAMPLE_INTERVAL_MS;
+        for entry in ENTRIES[..COUNT].iter_mut() {
+            let value = read_probe(entry.channel, entry.probe);
+            if value != entry.value {
+                entry.value = value;
+                entry.changed = true;
+                PENDING = true;
+            }
+        }
+    }
+}
+
+/// Takes up to `MONITOR_BATCH_SIZE` changed entries, returning how many were
+/// taken and whether more are left.
+pub fn take_changes(indices: &mut [u16; MONITOR_BATCH_SIZE],
+                    values: &mut [u32; MONITOR_BATCH_SIZE]) -> (u8, bool) {
+    let mut count = 0;
+    unsafe {
+        for (index, entry) in ENTRIES[..COUNT].iter_mut().enumerate() {
+            if !entry.changed {
+                continue
+            }
+            if count == MONITOR_BATCH_SIZE {
+                return (count as u8, true)
+            }
+            indices[count] = index as u16;
+            values[count] = entry.value;
+            entry.changed = false;
+            count += 1;
+        }
+        PENDING = false;
+    }
+    (count as u8, false)
+}
@@ -0,0 +1,109 @@
+use board_misoc::{config, firmware};
+
+// Firmware updates received from the master over the aux channel. The image
+// is programmed into the inactive bank as it arrives, since the satellite
+// has no memory to hold it whole, and is booted on trial once complete.
+
+struct Update {
+    bank:   firmware::Bank,
+    length: usize,
+    // Offset of the next expected chunk.
+    offset: usize
+}
+
+static mut UPDATE: Option<Update> = None;
+
+pub fn start(length: usize) -> bool {
+    unsafe { UPDATE = None; }
+    match firmware::begin_update(length) {
+        Ok(bank) => {
+            info!("receiving {} byte firmware image into bank {}", length, bank);
+            unsafe {
+                UPDATE = Some(Update { bank: bank, length: length, offset: 0 });
+            }
+            true
+        }
+        Err(err) => {
+            error!("cannot start firmware update: {}", err);
+            false
+        }
+    }
+}
+
+pub fn write(offset: usize, data: &[u8]) -> bool {
+    let update = match unsafe { UPDATE.as_mut() } {
+        Some(update) => update,
+        None => {
+            error!("firmware data received without an update in progress");
+            return false
+        }
+    };
+    // A chunk is sent again when its reply was lost.
+    if offset + data.len() == update.offset {
+        return true
+    }
+    if offset != update.offset || offset + data.len() > update.length {
+        error!("unexpected firmware data at offset {} (expected {})", offset, update.offset);
+        return false
+    }
+    match firmware::write_update(update.bank, offset, data) {
+        Ok(()) => {
+            update.offset += data.len();
+            true
+        }
+        Err(err) => {
+            error!("cannot write firmware: {}", err);
+            false
+        }
+    }
+}
+
+pub fn finish() -> bool {
+    let update = match unsafe { UPDATE.take() } {
+        Some(update) => update,
+        None => {
+            error!("firmware update finished without an update in progress");
+            return false
+        }
+    };
+    if update.offset != update.length {
+        error!("firmware image incomplete ({} of {} bytes)", update.offset, update.length);
+        return false
+    }
+    match firmware::finish_update(update.bank, update.length) {
+        Ok(()) => {
+            warn!("firmware written to bank {}, it will be tried at the next boot", update.bank);
+            true
+        }
+        Err(err) => {
+            error!("cannot finish firmware update: {}", err);
+            false
+        }
+    }
+}
+
+/// Returns how long the uplink must stay up before the firmware checks in,
+/// if it runs on trial.
+pub fn setup_trial() -> Option<u64> {
+    match firmware::trial() {
+        Some(bank) if firmware::trial_started() => {
+            let trial_ms = config::read_str("fw_trial_s", |r| r.ok().and_then(|s| s.parse().ok()))
+                                  .unwrap_or(10) * 1000;
+            warn!("running firmware bank {} on trial, checking in after {} ms with the uplink up",
+                  bank, trial_ms);
+            Some(trial_ms)
+        }
+        _ => {
+            info!("running firmware bank {}", firmware::active());
+            None
+        }
+    }
+}
+
+pub fn check_in() {
+    match firmware::check_in() {
+        Ok(Some(bank)) => info!("firmware bank {} checked in and is now active", bank),
+        Ok(None) => (),
+        Err(err) => error!("firmware check-in failed: {}", err)
+    }
+}
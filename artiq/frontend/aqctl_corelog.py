@@ -5,12 +5,16 @@ import asyncio
 import struct
 import logging
 import re
+import json
 
 from sipyco.pc_rpc import Server
 from sipyco import common_args
 from sipyco.logging_tools import log_with_name
 
-from artiq.coredevice.comm_mgmt import Request, Reply
+from artiq.coredevice.comm_mgmt import CommMgmt, Request, Reply
+
+
+logger = logging.getLogger(__name__)
 
 
 def get_argparser():
@@ -20,6 +24,8 @@ def get_argparser():
     common_args.simple_network_args(parser, 1068)
     parser.add_argument("--simulation", action="store_true",
                         help="Simulation - does not connect to device")
+    parser.add_argument("--auth-key", default=None,
+                        help="key to authenticate with the core device")
     parser.add_argument("core_addr", metavar="CORE_ADDR",
                         help="hostname or IP address of the core device")
     return parser
@@ -36,19 +42,33 @@ async def get_logs_sim(host):
         log_with_name("firmware.simulation", logging.INFO, "hello " + host)
 
 
-async def get_logs(host):
-    reader, writer = await asyncio.open_connection(host, 1380)
-    writer.write(b"ARTIQ management\n")
-    writer.write(struct.pack("B", Request.PullLog.value))
+async def get_logs(host, auth_key):
+    # Connect and authenticate like artiq_coremgmt, then hand the socket
+    # over to asyncio.
+    comm = CommMgmt(host, auth_key=auth_key)
+    await asyncio.get_event_loop().run_in_executor(None, comm.open)
+    reader, writer = await asyncio.open_connection(sock=comm.socket)
+    writer.write(struct.pack("B", Request.PullLogCounted.value))
     await writer.drain()
 
     while True:
-        length, = struct.unpack(">l", await reader.readexactly(4))
+        lost, length = struct.unpack(">Ll", await reader.readexactly(8))
+        if lost:
+            logger.warning("%d core device log records were lost", lost)
         log = await reader.readexactly(length)
 
         for line in log.decode("utf-8").splitlines():
-            m = re.match(r"^\[.+?\] (TRACE|DEBUG| INFO| WARN|ERROR)\((.+?)\): (.+)$", line)
-            levelname = m.group(1)
+            if line.startswith("{"):
+                # set with artiq_coremgmt log set_format json
+                record = json.loads(line)
+                levelname = "{:>5}".format(record["level"])
+                module = record["module"]
+                text = record["message"]
+            else:
+                m = re.match(r"^\[.+?\] (TRACE|DEBUG| INFO| WARN|ERROR)\((.+?)\): (.+)$", line)
+                levelname = m.group(1)
+                module = m.group(2)
+                text = m.group(3)
             if levelname == 'TRACE':
                 level = logging.TRACE
             elif levelname == 'DEBUG':
@@ -59,8 +79,7 @@ async def get_logs(host):
                 level = logging.WARN
             elif levelname == 'ERROR':
                 level = logging.ERROR
-            name = 'firmware.' + m.group(2).replace('::', '.')
-            text = m.group(3)
+            name = 'firmware.' + module.replace('::', '.')
             log_with_name(name, level, text)
 
 
@@ -71,7 +90,7 @@ def main():
     loop = asyncio.get_event_loop()
     try:
         get_logs_task = asyncio.ensure_future(
-            get_logs_sim(args.core_addr) if args.simulation else get_logs(args.core_addr))
+            get_logs_sim(args.core_addr) if args.simulation else get_logs(args.core_addr, args.auth_key))
         try:
             server = Server({"corelog": PingTarget()}, None, True)
             loop.run_until_complete(server.start(common_args.bind_address_from_args(args), args.port))
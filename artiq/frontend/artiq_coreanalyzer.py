@@ -8,6 +8,7 @@ from sipyco import common_args
 from artiq.master.databases import DeviceDB
 from artiq.master.worker_db import DeviceManager
 from artiq.coredevice.comm_analyzer import (get_analyzer_dump,
+                                            AnalyzerStream, write_stream_dump,
                                             decode_dump, decoded_dump_to_vcd)
 
 
@@ -27,6 +28,15 @@ def get_argparser():
                         help="format and write contents to VCD file")
     parser.add_argument("-d", "--write-dump", type=str, default=None,
                         help="write raw dump file")
+    parser.add_argument("-s", "--stream", default=False, action="store_true",
+                        help="stream the messages recorded from now on into "
+                             "the dump file given with -d until interrupted "
+                             "with Ctrl-C, instead of reading the analyzer "
+                             "buffer")
+    parser.add_argument("--no-compression",
+                        dest="compression", default=True, action="store_false",
+                        help="do not compress the messages read from the "
+                             "analyzer buffer")
 
     parser.add_argument("-u", "--vcd-uniform-interval", action="store_true",
                         help="emit uniform time intervals between timed VCD "
@@ -44,14 +54,28 @@ def main():
             and args.write_vcd is None and args.write_dump is None):
         print("No action selected, use -p, -w and/or -d. See -h for help.")
         sys.exit(1)
+    if args.stream and (args.write_dump is None or args.read_dump):
+        print("Streaming requires -d and cannot be combined with -r.")
+        sys.exit(1)
 
     device_mgr = DeviceManager(DeviceDB(args.device_db))
-    if args.read_dump:
+    if args.stream:
+        core_addr = device_mgr.get_desc("core")["arguments"]["host"]
+        stream = AnalyzerStream(core_addr)
+        try:
+            print("Streaming, press Ctrl-C to stop.")
+            with open(args.write_dump, "wb") as f:
+                write_stream_dump(stream, f)
+        finally:
+            stream.close()
+        with open(args.write_dump, "rb") as f:
+            dump = f.read()
+    elif args.read_dump:
         with open(args.read_dump, "rb") as f:
             dump = f.read()
     else:
         core_addr = device_mgr.get_desc("core")["arguments"]["host"]
-        dump = get_analyzer_dump(core_addr)
+        dump = get_analyzer_dump(core_addr, compression=args.compression)
     decoded_dump = decode_dump(dump)
     if args.print_decoded:
         print("Log channel:", decoded_dump.log_channel)
@@ -63,7 +87,7 @@ def main():
             decoded_dump_to_vcd(f, device_mgr.get_device_db(),
                                 decoded_dump,
                                 uniform_interval=args.vcd_uniform_interval)
-    if args.write_dump:
+    if args.write_dump and not args.stream:
         with open(args.write_dump, "wb") as f:
             f.write(dump)
 
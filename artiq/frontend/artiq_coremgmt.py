@@ -1,15 +1,34 @@
 #!/usr/bin/env python3
 
 import argparse
+import datetime
+import io
+import math
+import os
 import struct
+import sys
+import time
+import zlib
 
 from sipyco import common_args
 
 from artiq import __version__ as artiq_version
 from artiq.master.databases import DeviceDB
+from artiq.coredevice.comm import Feature
 from artiq.coredevice.comm_kernel import CommKernel
-from artiq.coredevice.comm_mgmt import CommMgmt
+from artiq.coredevice.comm_mgmt import CommMgmt, clock_profile
+from artiq.coredevice.kasli_i2c import port_mapping
 from artiq.coredevice.profiler import CallgrindWriter
+from artiq.frontend.bit2bin import bit2bin
+
+
+def print_health_record(record, indent=""):
+    fields = ("{}={}".format(name, "{:.6g}".format(value)
+                             if isinstance(value, float) else value)
+              for name, value in record.items()
+              if name not in ("kind", "index"))
+    print("{}{}[{}]: {}".format(indent, record["kind"], record["index"],
+                                " ".join(fields)), flush=True)
 
 
 def get_argparser():
@@ -25,6 +44,15 @@ def get_argparser():
     parser.add_argument("-D", "--device", default=None,
                         help="use specified core device address instead of "
                              "reading device database")
+    parser.add_argument("--auth-key", default=None,
+                        help="key to authenticate with the core device "
+                             "(default: the auth_key argument of the core "
+                             "device in the device database)")
+    parser.add_argument("--chunk-length", default=None, type=int,
+                        help="read large replies (log, config backup, "
+                             "storage, sensors, audit log, kernel run "
+                             "statistics) in chunks of this many bytes, "
+                             "resuming after a lost connection")
 
     tools = parser.add_subparsers(dest="tool")
     tools.required = True
@@ -42,6 +70,27 @@ def get_argparser():
                                         help="set minimum level for messages to be logged")
     p_set_level.add_argument("level", metavar="LEVEL", type=str,
                              help="log level (one of: OFF ERROR WARN INFO DEBUG TRACE)")
+    p_set_level.add_argument("-m", "--module", default=None, type=str,
+                             help="only set the level of this firmware module "
+                                  "and its submodules (e.g. runtime::session); "
+                                  "saved and applied at boot")
+
+    subparsers.add_parser("lost",
+                          help="show the number of log records lost since "
+                               "boot")
+
+    p_panic = subparsers.add_parser("panic",
+                                    help="show the report of the last firmware "
+                                         "panic or kernel CPU exception saved "
+                                         "in flash")
+    p_panic.add_argument("--clear", default=False, action="store_true",
+                         help="erase the report after showing it")
+
+    p_reset_level = subparsers.add_parser("reset_level",
+                                          help="make a firmware module follow "
+                                               "the global log level again")
+    p_reset_level.add_argument("module", metavar="MODULE", type=str,
+                               help="firmware module path")
 
     p_set_uart_level = subparsers.add_parser("set_uart_level",
                                              help="set minimum level for messages to be logged "
@@ -49,6 +98,27 @@ def get_argparser():
     p_set_uart_level.add_argument("level", metavar="LEVEL", type=str,
                                   help="log level (one of: OFF ERROR WARN INFO DEBUG TRACE)")
 
+    p_set_sink_level = subparsers.add_parser("set_sink_level",
+                                             help="set minimum level for messages to be sent "
+                                                  "to a log sink; saved and applied at boot")
+    p_set_sink_level.add_argument("sink", metavar="SINK", type=str,
+                                  choices=["uart", "buffer", "syslog"],
+                                  help="log sink (uart, buffer for the log read by this "
+                                       "tool and aqctl_corelog, or syslog)")
+    p_set_sink_level.add_argument("level", metavar="LEVEL", type=str,
+                                  help="log level (one of: OFF ERROR WARN INFO DEBUG TRACE)")
+
+    subparsers.add_parser("sinks",
+                          help="show the levels of the log sinks and the "
+                               "syslog server")
+
+    p_set_format = subparsers.add_parser("set_format",
+                                         help="set the format of the log buffer, "
+                                              "read by this tool and aqctl_corelog")
+    p_set_format.add_argument("format", metavar="FORMAT", type=str,
+                              choices=["text", "json"],
+                              help="log format (text, or json for JSON lines)")
+
     # configuration
     t_config = tools.add_parser("config",
                                 help="read and change core device configuration")
@@ -80,11 +150,35 @@ def get_argparser():
                           default=[], type=str,
                           help="key to be removed from core device config")
 
+    subparsers.add_parser("list",
+                          help="list all keys in core device config")
+
+    p_backup = subparsers.add_parser("backup",
+                                     help="save the entire core device config "
+                                          "to a file")
+    p_backup.add_argument("file", metavar="FILE", type=argparse.FileType("wb"),
+                          help="output file")
+
+    p_restore = subparsers.add_parser("restore",
+                                      help="replace the entire core device "
+                                           "config with a backup")
+    p_restore.add_argument("file", metavar="FILE", type=argparse.FileType("rb"),
+                           help="backup file")
+
     subparsers.add_parser("erase", help="fully erase core device config")
 
     # booting
     t_boot = tools.add_parser("reboot",
                               help="reboot the currently running firmware")
+    t_boot.add_argument("--bootloader", default=False, action="store_true",
+                        help="stay in the bootloader and wait for a firmware "
+                             "image over the network instead of booting "
+                             "from flash")
+
+    tools.add_parser("poweroff",
+                     help="prepare the core device to be powered off: "
+                          "terminate the kernel, apply the safe state and "
+                          "disable the SFP transmitters")
 
     t_hotswap = tools.add_parser("hotswap",
                                   help="load the specified firmware in RAM")
@@ -92,9 +186,497 @@ def get_argparser():
     t_hotswap.add_argument("image", metavar="IMAGE", type=argparse.FileType("rb"),
                            help="runtime image to be executed")
 
+    t_flash = tools.add_parser("flash",
+                               help="write firmware into the inactive flash "
+                                    "bank and boot it on trial, with "
+                                    "automatic rollback")
+    t_flash.add_argument("image", metavar="IMAGE", type=argparse.FileType("rb"),
+                         nargs="?", default=None,
+                         help="firmware image (.fbi) to be written")
+    t_flash.add_argument("-s", "--satellite", metavar="DESTINATION",
+                         default=None, type=int,
+                         help="write the firmware of the DRTIO satellite "
+                              "at this destination, through the master")
+    t_flash.add_argument("-g", "--gateware", default=False,
+                         action="store_true",
+                         help="write a gateware image (.bit or .bin) into "
+                              "the gateware update slot instead, and boot "
+                              "it on trial at the next power-up, with "
+                              "fallback to the golden gateware")
+    t_flash.add_argument("-r", "--reconfigure", default=False,
+                         action="store_true",
+                         help="with --gateware, reconfigure the FPGA with "
+                              "the written gateware right away")
+    t_flash.add_argument("--golden", default=False, action="store_true",
+                         help="instead of writing an image, stop booting the "
+                              "gateware update, so that the golden gateware "
+                              "is booted from the next power-up")
+
+    # clocking
+    t_clock = tools.add_parser("clock",
+                               help="adjust clock synthesizers")
+
+    subparsers = t_clock.add_subparsers(dest="action")
+    subparsers.required = True
+
+    p_trim = subparsers.add_parser("trim",
+                                   help="offset the Si549 frequency of a "
+                                        "WRPLL satellite")
+    p_trim.add_argument("destination", metavar="DESTINATION", type=int,
+                        help="DRTIO destination of the satellite")
+    p_trim.add_argument("ppb", metavar="PPB", type=int,
+                        help="frequency offset from the calibrated value, "
+                             "in parts per billion")
+
+    p_wrpll_stats = subparsers.add_parser("wrpll_stats",
+                                          help="show synchronization statistics "
+                                               "of a WRPLL satellite")
+    p_wrpll_stats.add_argument("destination", metavar="DESTINATION", type=int,
+                               help="DRTIO destination of the satellite")
+
+    subparsers.add_parser("frequency",
+                          help="show the measured RTIO clock frequency and "
+                               "compare it with the device database")
+
+    subparsers.add_parser("tsc_stats",
+                          help="show the offsets of the timestamp "
+                               "counters of the DRTIO satellites")
+
+    p_registers = subparsers.add_parser("registers",
+                                        help="dump the registers of the clocking "
+                                             "chips, and save them as profiles "
+                                             "programmed at boot")
+    p_registers.add_argument("destination", metavar="DESTINATION", type=int,
+                             nargs="?", default=0,
+                             help="DRTIO destination (default: %(default)s)")
+    p_registers.add_argument("--save", default=False, action="store_true",
+                             help="save the configuration registers of the "
+                                  "chips of the core device as profiles in "
+                                  "its configuration")
+    p_registers.add_argument("--save-dir", default=None, metavar="DIR",
+                             help="write the profiles to files named after "
+                                  "their config keys, e.g. for artiq_mkfs")
+
+    p_counter = subparsers.add_parser("counter",
+                                      help="show the RTIO counter and the "
+                                           "system time at which it was read")
+    p_counter.add_argument("-n", "--samples", default=1, type=int,
+                           help="number of readings (default: %(default)s)")
+    p_counter.add_argument("-i", "--interval", default=1.0, type=float,
+                           help="interval between readings, in seconds "
+                                "(default: %(default)s)")
+
+    # health
+    t_health = tools.add_parser("health",
+                                help="show a snapshot of the core device state")
+    t_health.add_argument("--records", default=False, action="store_true",
+                          help="show the self-describing health records, "
+                               "including those this tool does not know")
+
+    # sensor history
+    t_sensors = tools.add_parser("sensors",
+                                 help="show the history of the FPGA sensors "
+                                      "and of the SFP optical power")
+    t_sensors.add_argument("-f", "--file", default=None,
+                           help="write the samples to a CSV file instead")
+
+    # fault injection
+    t_inject = tools.add_parser("inject",
+                                help="inject synthetic faults to test "
+                                     "monitoring (requires gateware built "
+                                     "with --with-fault-injection)")
+
+    subparsers = t_inject.add_subparsers(dest="action")
+    subparsers.required = True
+
+    p_sfp_alarm = subparsers.add_parser("sfp_alarm",
+                                        help="make an SFP port report alarms")
+    p_sfp_alarm.add_argument("port", metavar="PORT", type=int,
+                             help="SFP port")
+    p_sfp_alarm.add_argument("alarms", metavar="ALARM", nargs="*",
+                             help="alarms to report, none to stop: {}".format(
+                                 ", ".join(
+                                     '"{}"'.format(alarm)
+                                     for alarm in CommMgmt._sfp_alarms)))
+
+    p_link_drop = subparsers.add_parser("link_drop",
+                                        help="make a DRTIO link go down once")
+    p_link_drop.add_argument("linkno", metavar="LINK", type=int,
+                             help="DRTIO link number")
+
+    p_temperature = subparsers.add_parser("temperature",
+                                          help="replace the FPGA die "
+                                               "temperature reading")
+    p_temperature.add_argument("temperature", metavar="CELSIUS", type=float)
+    p_temperature.add_argument("-d", "--duration", default=60, type=int,
+                               help="seconds to inject the temperature for "
+                                    "(default: %(default)s)")
+
+    subparsers.add_parser("clear", help="stop injecting all faults")
+
+    # identity
+    tools.add_parser("identity",
+                     help="show the serial number and revision of the core device")
+
+    # compatibility
+    tools.add_parser("compat",
+                     help="check that the firmware matches the gateware")
+
+    tools.add_parser("features",
+                     help="show the features of the core device")
+
+    # grabber
+    tools.add_parser("grabber",
+                     help="show the frame statistics of the grabbers")
+
+    # self test
+    t_selftest = tools.add_parser("selftest",
+                                  help="run the self test of the core device "
+                                       "and show its report; exits with "
+                                       "status 1 if a check fails")
+    t_selftest.add_argument("--last", default=False, action="store_true",
+                            help="show the report of the last self test "
+                                 "instead of running it, e.g. the one run "
+                                 "at boot")
+    t_selftest.add_argument("--eem", default=False, action="store_true",
+                            help="test the Urukul, Sampler and Zotino cards "
+                                 "and the loopbacks of the eem_loopback "
+                                 "config key instead; the cards must be "
+                                 "initialized again afterwards")
+
+    # DDS synchronization
+    tools.add_parser("ddssync",
+                     help="show the last SYNC_IN and IO_UPDATE alignment of "
+                          "the AD9910 channels re-aligned by kernels")
+
+    # alerts
+    t_alerts = tools.add_parser("alerts",
+                                help="print the alerts of the core device as they are "
+                                     "raised, acknowledging them")
+    t_alerts.add_argument("--records", default=False, action="store_true",
+                          help="also print the health records of the "
+                               "condition of each alert")
+
+    # EEPROM
+    t_eeprom = tools.add_parser("eeprom",
+                                help="read and write a 24xx I2C EEPROM, "
+                                     "e.g. of an EEM card")
+    t_eeprom.add_argument("-b", "--busno", default=0, type=int,
+                          help="I2C bus number (default: %(default)s)")
+    t_eeprom.add_argument("-p", "--port", default=None,
+                          choices=sorted(port_mapping),
+                          help="port of the I2C switches of Kasli the "
+                               "EEPROM is behind, e.g. EEM3")
+    t_eeprom.add_argument("-a", "--address", default=0x50,
+                          type=lambda s: int(s, 0),
+                          help="7-bit I2C address (default: 0x50)")
+    t_eeprom.add_argument("-s", "--size", default=256, type=int,
+                          help="size of the EEPROM in bytes "
+                               "(default: %(default)s)")
+    t_eeprom.add_argument("--page-size", default=8, type=int,
+                          help="size of a write page in bytes "
+                               "(default: %(default)s)")
+
+    subparsers = t_eeprom.add_subparsers(dest="action")
+    subparsers.required = True
+
+    p_read = subparsers.add_parser("read",
+                                   help="read bytes, to a file or as "
+                                        "hexadecimal")
+    p_read.add_argument("offset", metavar="OFFSET", type=lambda s: int(s, 0))
+    p_read.add_argument("length", metavar="LENGTH", type=lambda s: int(s, 0))
+    p_read.add_argument("-f", "--file", default=None,
+                        help="file to write the bytes to")
+
+    p_write = subparsers.add_parser("write",
+                                    help="write the bytes of a file")
+    p_write.add_argument("offset", metavar="OFFSET", type=lambda s: int(s, 0))
+    p_write.add_argument("file", metavar="FILE",
+                         help="file to read the bytes from")
+
+    # flash storage
+    t_storage = tools.add_parser("storage",
+                                 help="inspect and erase the flash storage "
+                                      "area of kernels")
+
+    subparsers = t_storage.add_subparsers(dest="action")
+    subparsers.required = True
+
+    subparsers.add_parser("list", help="list the stored keys and values")
+    subparsers.add_parser("erase", help="remove all stored keys")
+    p_health = subparsers.add_parser("health",
+                                     help="show the health of the flash "
+                                          "areas written at run time")
+    p_health.add_argument("-s", "--scrub", default=False,
+                          action="store_true",
+                          help="scrub the areas first")
+
+    # raw bus transactions
+    t_i2c = tools.add_parser("i2c",
+                             help="perform a raw I2C transaction, for "
+                                  "hardware bring-up (needs the "
+                                  "bus_passthrough config key set to 1)")
+    t_i2c.add_argument("-b", "--busno", default=0, type=int,
+                       help="I2C bus number (default: %(default)s)")
+    t_i2c.add_argument("-p", "--port", default=None,
+                       choices=sorted(port_mapping),
+                       help="port of the I2C switches of Kasli the device "
+                            "is behind, e.g. EEM3")
+    t_i2c.add_argument("address", metavar="ADDRESS",
+                       type=lambda s: int(s, 0),
+                       help="7-bit I2C address of the device")
+    t_i2c.add_argument("-w", "--write", default=b"", type=bytes.fromhex,
+                       help="bytes to write, in hexadecimal")
+    t_i2c.add_argument("-r", "--read", default=0, type=int,
+                       help="number of bytes to read after writing "
+                            "(default: %(default)s)")
+
+    t_spi = tools.add_parser("spi",
+                             help="perform a raw transaction on the "
+                                  "non-realtime SPI bus (needs the "
+                                  "bus_passthrough config key set to 1)")
+    t_spi.add_argument("-b", "--busno", default=0, type=int,
+                       help="SPI bus number (default: %(default)s)")
+    t_spi.add_argument("--flags", default=0, type=lambda s: int(s, 0),
+                       help="configuration flags, as in "
+                            "artiq.coredevice.spi2 (default: %(default)s)")
+    t_spi.add_argument("--length", default=8, type=int,
+                       help="length of a word in bits (default: %(default)s)")
+    t_spi.add_argument("--div", default=6, type=int,
+                       help="clock divider (default: %(default)s)")
+    t_spi.add_argument("--cs", default=1, type=lambda s: int(s, 0),
+                       help="chip select mask (default: %(default)s)")
+    t_spi.add_argument("words", metavar="WORD", nargs="+",
+                       type=lambda s: int(s, 0),
+                       help="words to shift out, left-aligned as in "
+                            "artiq.coredevice.spi2")
+
+    # real-time clock
+    t_rtc = tools.add_parser("rtc",
+                             help="read and set the battery-backed RTC "
+                                  "of the core device")
+
+    subparsers = t_rtc.add_subparsers(dest="action")
+    subparsers.required = True
+
+    subparsers.add_parser("read", help="show the time of the RTC, in UTC")
+
+    p_set = subparsers.add_parser("set",
+                                  help="set the RTC and the wall clock")
+    p_set.add_argument("time", metavar="TIME", nargs="?", default=None,
+                       help="UTC date and time in ISO 8601 format, e.g. "
+                            "2024-06-01T12:00:00 (default: the time of "
+                            "this computer)")
+
+    t_kernels = tools.add_parser("kernels",
+                                 help="manage the kernel images stored in "
+                                      "the core device flash")
+
+    subparsers = t_kernels.add_subparsers(dest="action")
+    subparsers.required = True
+
+    subparsers.add_parser("list", help="list the stored kernel images")
+
+    p_upload = subparsers.add_parser("upload",
+                                     help="store a compiled kernel image")
+    p_upload.add_argument("name", metavar="NAME", type=str,
+                          help="name of the kernel image")
+    p_upload.add_argument("file", metavar="FILE", type=str,
+                          help="compiled kernel, e.g. from artiq_compile")
+
+    p_remove = subparsers.add_parser("remove",
+                                     help="remove a stored kernel image")
+    p_remove.add_argument("name", metavar="NAME", type=str,
+                          help="name of the kernel image")
+
+    p_run = subparsers.add_parser("run",
+                                  help="run a stored kernel image in place "
+                                       "of the startup or idle kernel")
+    p_run.add_argument("name", metavar="NAME", type=str,
+                       help="name of the kernel image")
+
+    t_terminate = tools.add_parser("terminate",
+                                   help="request the termination of the "
+                                        "running kernel")
+    t_terminate.add_argument("reason", metavar="REASON", type=str,
+                             help="reason reported in the kernel and to "
+                                  "the host")
+
+    # satellites
+    tools.add_parser("satellites",
+                     help="show the versions of the DRTIO satellites")
+
+    t_topology = tools.add_parser("topology",
+                                  help="show the RTIO topology of the gateware "
+                                       "and check the device database against it")
+    t_topology.add_argument("destinations", metavar="DESTINATION", type=int,
+                            nargs="*",
+                            help="destinations to show (default: those of "
+                                 "the channels of the device database)")
+
+    tools.add_parser("eems",
+                     help="show the cards detected on the EEM connectors "
+                          "at boot and those expected by the gateware")
+
+    t_latency = tools.add_parser("latency",
+                                 help="show and calibrate the latency of "
+                                      "the RT links to the DRTIO satellites")
+
+    subparsers = t_latency.add_subparsers(dest="action")
+    subparsers.required = True
+
+    subparsers.add_parser("show",
+                          help="show the latencies measured and calibrated")
+
+    p_calibrate = subparsers.add_parser("calibrate",
+                                        help="store the latency calibration "
+                                             "of a satellite")
+    p_calibrate.add_argument("destination", metavar="DESTINATION", type=int,
+                             help="destination of the satellite")
+    p_calibrate.add_argument("latency", metavar="LATENCY", type=float,
+                             nargs="?", default=None,
+                             help="one-way latency in ns (default: the "
+                                  "latency measured)")
+
+    p_clear = subparsers.add_parser("clear",
+                                    help="remove the latency calibration "
+                                         "of a satellite")
+    p_clear.add_argument("destination", metavar="DESTINATION", type=int,
+                         help="destination of the satellite")
+
+    tools.add_parser("audit",
+                     help="show who connected to the core device and the "
+                          "commands of note they sent, e.g. reboots")
+
+    tools.add_parser("runs",
+                     help="show the duration, minimum slack and RTIO errors "
+                          "of the last kernel runs")
+
+    tools.add_parser("moninj",
+                     help="show the rate limits of moninj and how often "
+                          "they were hit")
+
+    # cache
+    t_cache = tools.add_parser("cache",
+                               help="inspect and clear the kernel cache")
+
+    subparsers = t_cache.add_subparsers(dest="action")
+    subparsers.required = True
+
+    subparsers.add_parser("list",
+                          help="list the cache entries with their sizes")
+
+    p_remove = subparsers.add_parser("remove",
+                                     help="remove a cache entry")
+    p_remove.add_argument("key", metavar="KEY", type=str,
+                          help="key of the entry to remove")
+
+    subparsers.add_parser("clear", help="remove all cache entries")
+
+    # SED
+    t_sed = tools.add_parser("sed",
+                             help="show the SED lane usage and the "
+                                  "asynchronous RTIO error counters")
+    t_sed.add_argument("--clear", default=False, action="store_true",
+                       help="clear the counters after reading them")
+
+    # output events
+    t_events = tools.add_parser("events",
+                                help="show the output events written to "
+                                     "each group of local RTIO channels")
+    t_events.add_argument("--clear", default=False, action="store_true",
+                          help="clear the counters after reading them")
+
+    # threads
+    t_top = tools.add_parser("top",
+                             help="show the CPU time used by each thread of "
+                                  "the comms CPU")
+    t_top.add_argument("--clear", default=False, action="store_true",
+                       help="clear the statistics after reading them")
+
+    # RTIO errors
+    t_rtio_errors = tools.add_parser("rtio_errors",
+                                     help="show the RTIO error counters of "
+                                          "each destination")
+    t_rtio_errors.add_argument("--clear", default=False, action="store_true",
+                               help="clear the counters after reading them")
+
+    # routing
+    t_routing = tools.add_parser("routing",
+                                 help="manage the DRTIO routing table")
+    subparsers = t_routing.add_subparsers(dest="action")
+    subparsers.required = True
+    subparsers.add_parser("reload",
+                          help="apply the routing table stored in the "
+                               "configuration without rebooting")
+
+    # eye scan
+    t_eyescan = tools.add_parser("eyescan",
+                                 help="measure the eye of the transceiver of "
+                                      "a DRTIO link")
+    t_eyescan.add_argument("linkno", metavar="LINK", type=int,
+                           help="DRTIO link to scan")
+    t_eyescan.add_argument("--horizontal-step", default=4, type=int,
+                           help="horizontal offset step, in 1/128 UI "
+                                "(default: %(default)s)")
+    t_eyescan.add_argument("--vertical-step", default=8, type=int,
+                           help="vertical offset step, out of 127 "
+                                "(default: %(default)s)")
+    t_eyescan.add_argument("--prescale", default=0, type=int,
+                           help="sample each point 2**(1 + PRESCALE) times "
+                                "longer (default: %(default)s)")
+    t_eyescan.add_argument("--csv", default=None, metavar="FILE",
+                           help="also write the points to a CSV file")
+
+    # network
+    t_net = tools.add_parser("net",
+                             help="inspect the network interface")
+
+    subparsers = t_net.add_subparsers(dest="action")
+    subparsers.required = True
+
+    subparsers.add_parser("stats",
+                          help="show Ethernet counters and TCP sockets")
+
+    # analyzer
+    t_analyzer = tools.add_parser("analyzer",
+                                  help="configure the RTIO analyzer")
+
+    subparsers = t_analyzer.add_subparsers(dest="action")
+    subparsers.required = True
+
+    def analyzer_trigger(value):
+        if value in ("none", "exception"):
+            return value
+        return int(value, 0)
+
+    p_trigger = subparsers.add_parser("trigger",
+                                      help="show or set the capture triggers")
+    p_trigger.add_argument("--start", metavar="TRIGGER", type=analyzer_trigger,
+                           help="start recording at the first event on this "
+                                "RTIO channel, or at the first RTIO exception "
+                                "('exception'); 'none' records from the "
+                                "beginning")
+    p_trigger.add_argument("--stop", metavar="TRIGGER", type=analyzer_trigger,
+                           help="stop recording after the first event on this "
+                                "RTIO channel, or after the first RTIO "
+                                "exception ('exception'); 'none' records "
+                                "until the trace is read")
+
+    p_filter = subparsers.add_parser("filter",
+                                     help="show or set the recorded channels")
+    p_filter.add_argument("--include", metavar="CHANNEL", type=int, nargs="+",
+                          help="record the events of these RTIO channels only")
+    p_filter.add_argument("--exclude", metavar="CHANNEL", type=int, nargs="*",
+                          help="record the events of all RTIO channels but "
+                               "these; without channels, record all")
+
     # profiling
     t_profile = tools.add_parser("profile",
                                  help="account for communications CPU time")
+    t_profile.add_argument("--kernel", default=False, action="store_true",
+                           help="sample the kernel CPU instead of the "
+                                "communications CPU")
 
     subparsers = t_profile.add_subparsers(dest="action")
     subparsers.required = True
@@ -116,7 +698,9 @@ def get_argparser():
     p_save.add_argument("output", metavar="OUTPUT", type=argparse.FileType("w"),
                         help="file to save profile to, in Callgrind format")
     p_save.add_argument("firmware", metavar="FIRMWARE", type=str,
-                        help="path to firmware ELF file")
+                        help="path to firmware ELF file, or to the kernel "
+                             "ELF file (as produced by artiq_compile) "
+                             "with --kernel")
     p_save.add_argument("--no-compression",
                         dest="compression", default=True, action="store_false",
                         help="disable profile compression")
@@ -134,25 +718,73 @@ def get_argparser():
     p_allocator = subparsers.add_parser("allocator",
                                         help="show heap layout")
 
+    subparsers.add_parser("heap",
+                          help="show heap usage and the largest free block")
+
     return parser
 
 
+def gateware_image(file):
+    """Converts a .bit or .bin gateware file into an image with the length
+    and CRC32 header of firmware images."""
+    if file.name.endswith(".bit"):
+        bitstream = io.BytesIO()
+        bit2bin(file, bitstream)
+        bitstream = bitstream.getvalue()
+    else:
+        bitstream = file.read()
+    return struct.pack(">II", len(bitstream), zlib.crc32(bitstream)) + bitstream
+
+
 def main():
     args = get_argparser().parse_args()
     common_args.init_logger_from_args(args)
 
+    auth_key = args.auth_key
     if args.device is None:
         ddb = DeviceDB(args.device_db)
-        core_addr = ddb.get("core", resolve_alias=True)["arguments"]["host"]
+        core_args = ddb.get("core", resolve_alias=True)["arguments"]
+        core_addr = core_args["host"]
+        if auth_key is None:
+            auth_key = core_args.get("auth_key")
     else:
         core_addr = args.device
-    mgmt = CommMgmt(core_addr)
+    mgmt = CommMgmt(core_addr, auth_key=auth_key,
+                    chunk_length=args.chunk_length)
 
     if args.tool == "log":
         if args.action == "set_level":
-            mgmt.set_log_level(args.level)
+            if args.module is None:
+                mgmt.set_log_level(args.level)
+            else:
+                mgmt.set_module_log_level(args.module, args.level)
+        if args.action == "reset_level":
+            mgmt.remove_module_log_level(args.module)
+        if args.action == "panic":
+            report = mgmt.get_panic_log()
+            if report is None:
+                print("No panic report")
+            else:
+                print(report, end="")
+                if args.clear:
+                    mgmt.clear_panic_log()
+        if args.action == "lost":
+            print(mgmt.get_lost_log_records())
         if args.action == "set_uart_level":
             mgmt.set_uart_log_level(args.level)
+        if args.action == "set_sink_level":
+            mgmt.set_log_sink_level(args.sink, args.level)
+        if args.action == "sinks":
+            sinks = mgmt.get_log_sinks()
+            for sink, level in sinks["levels"].items():
+                print("{:7} {}".format(sink, level))
+            if sinks["syslog"] is None:
+                print("no syslog server")
+            else:
+                print("syslog server {}, {} records dropped".format(
+                    sinks["syslog"], sinks["syslog_lost"]))
+        if args.action == "set_format":
+            mgmt.set_log_format(args.format)
         if args.action == "clear":
             mgmt.clear_log()
         if args.action == None:
@@ -174,22 +806,735 @@ def main():
         if args.action == "remove":
             for key in args.key:
                 mgmt.config_remove(key)
+        if args.action == "list":
+            for key, size in mgmt.config_list():
+                print("{}\t{} bytes".format(key, size))
+        if args.action == "backup":
+            args.file.write(mgmt.config_backup())
+        if args.action == "restore":
+            mgmt.config_restore(args.file.read())
         if args.action == "erase":
             mgmt.config_erase()
 
     if args.tool == "reboot":
-        mgmt.reboot()
+        if args.bootloader:
+            mgmt.reboot_to_bootloader()
+        else:
+            mgmt.reboot()
+
+    if args.tool == "poweroff":
+        report = mgmt.prepare_power_off()
+        if report["kernel_stopped"]:
+            print("kernel terminated")
+        print("{} outputs in their safe state"
+              .format(report["safe_state_outputs"]))
+        print("SFP transmitters disabled: {}".format(
+            ", ".join("SFP{}".format(i) for i in report["sfp_tx_disabled"])
+            or "none"))
+        print("ready for power-off")
 
     if args.tool == "hotswap":
         mgmt.hotswap(args.image.read())
 
+    if args.tool == "flash":
+        if args.golden:
+            if args.image is not None or args.satellite is not None:
+                raise ValueError("--golden takes no image or satellite")
+            mgmt.disable_gateware_update()
+        elif args.image is None:
+            raise ValueError("no image to write")
+        elif args.gateware:
+            if args.satellite is not None:
+                raise ValueError("gateware cannot be written to satellites")
+            mgmt.flash_gateware(gateware_image(args.image),
+                                reconfigure=args.reconfigure)
+        elif args.satellite is None:
+            mgmt.flash_firmware(args.image.read())
+        else:
+            mgmt.flash_satellite_firmware(args.satellite, args.image.read())
+
+    if args.tool == "clock":
+        if args.action == "trim":
+            mgmt.si549_trim(args.destination, args.ppb)
+        if args.action == "wrpll_stats":
+            stats = mgmt.wrpll_stats(args.destination)
+            print("locked: {}".format(stats["locked"]))
+            print("lock time: {} ms".format(stats["lock_time_ms"]))
+            print("unlock count: {}".format(stats["unlock_count"]))
+            print("helper tag error RMS: {:.3f} tags".format(stats["helper_rms_tags"]))
+            print("main tag error RMS: {:.3f} tags".format(stats["main_rms_tags"]))
+            print("samples: {}".format(stats["samples"]))
+        if args.action == "frequency":
+            measured = mgmt.get_rtio_frequency()
+            print("measured RTIO clock frequency: {:.3f} MHz".format(measured/1e6))
+            if args.device is None:
+                core_args = ddb.get("core", resolve_alias=True)["arguments"]
+                expected = 1/(core_args["ref_period"]*core_args.get("ref_multiplier", 8))
+                print("expected RTIO clock frequency: {:.3f} MHz".format(expected/1e6))
+                if abs(measured - expected) > 1e-3*expected:
+                    print("WARNING: RTIO clock frequency does not match "
+                          "the device database; check the rtio_clock "
+                          "configuration and the clock reference")
+                    sys.exit(1)
+        if args.action == "tsc_stats":
+            period = 1/mgmt.get_rtio_frequency()
+            for satellite in mgmt.get_tsc_stats():
+                if satellite["syncs"] is None:
+                    print("DEST#{}: no answer".format(satellite["destination"]))
+                    continue
+                line = "DEST#{}: {} syncs, last offset {} cycles ({:.0f} ns), " \
+                       "max {} cycles".format(
+                           satellite["destination"], satellite["syncs"],
+                           satellite["offset"],
+                           satellite["offset"]*period*1e9,
+                           satellite["max_offset"])
+                if satellite["interval_ms"]:
+                    drift = satellite["offset"]*period/(satellite["interval_ms"]*1e-3)
+                    line += ", drift {:.1f} ppb over {} ms".format(
+                        drift*1e9, satellite["interval_ms"])
+                if satellite["phase_fs"] is not None:
+                    line += ", clock phase {:.3f} ns".format(
+                        satellite["phase_fs"]*1e-6)
+                print(line)
+        if args.action == "registers":
+            chips = mgmt.get_clock_registers(args.destination)
+            if not chips:
+                print("no clocking chip with readable registers")
+            for chip, registers in sorted(chips.items()):
+                print("{}:".format(chip))
+                for address, value, profile in registers:
+                    print("  {:3}  0x{:02x}{}".format(
+                        address, value, "" if profile else "  (status/control)"))
+            if args.save:
+                if args.destination != 0:
+                    raise ValueError("satellite profiles are kept in their "
+                                     "own flash, use --save-dir")
+                for chip, registers in chips.items():
+                    key, value = clock_profile(chip, registers)
+                    mgmt.config_write(key, value)
+                    print("saved {}".format(key))
+            if args.save_dir is not None:
+                for chip, registers in chips.items():
+                    key, value = clock_profile(chip, registers)
+                    with open(os.path.join(args.save_dir, key), "wb") as f:
+                        f.write(value)
+                    print("wrote {}".format(os.path.join(args.save_dir, key)))
+        if args.action == "counter":
+            readings = []
+            for i in range(args.samples):
+                if i:
+                    time.sleep(args.interval)
+                reading = mgmt.get_rtio_counter()
+                readings.append(reading)
+                print("RTIO counter {} mu at {}.{:06} s of uptime{}".format(
+                    reading["counter"], reading["uptime_us"] // 1000000,
+                    reading["uptime_us"] % 1000000,
+                    "" if reading["time"] is None
+                    else ", Unix time {:.6f} s".format(reading["time"])))
+            if len(readings) > 1 and args.device is None:
+                ref_period = ddb.get("core", resolve_alias=True)["arguments"]["ref_period"]
+                elapsed_mu = readings[-1]["counter"] - readings[0]["counter"]
+                elapsed_us = readings[-1]["uptime_us"] - readings[0]["uptime_us"]
+                print("RTIO counter rate against the system timer: "
+                      "{:+.3f} ppm".format((elapsed_mu*ref_period/(elapsed_us*1e-6) - 1)*1e6))
+
+    if args.tool == "health" and args.records:
+        for record in mgmt.get_health_records():
+            print_health_record(record)
+
+    if args.tool == "health" and not args.records:
+        health = mgmt.get_health_snapshot()
+        print("uptime:           {:.0f} s".format(health["uptime"]))
+        if health["boot_count"] is not None:
+            print("boots:            {}, {:.1f} h of uptime over all boots".format(
+                health["boot_count"], health["total_uptime"]/3600))
+        if health["time"] is not None:
+            print("time:             {} UTC".format(
+                datetime.datetime.utcfromtimestamp(health["time"])))
+        print("firmware version: {}".format(health["firmware_version"]))
+        print("gateware version: {}".format(health["gateware_version"]))
+        print("RTIO clock:       {:.3f} MHz{}".format(
+            health["rtio_frequency"]/1e6,
+            "" if health["rtio_clock_ok"] else " (PLL not locked)"))
+        heap = health["heap"]
+        print("heap:             {} of {} bytes used, largest free block {} bytes"
+              .format(heap["used"], heap["total"], heap["largest_free"]))
+        fpga = health["fpga"]
+        if fpga is not None:
+            print("FPGA:             {:.1f} C, VCCINT {:.3f} V, VCCAUX {:.3f} V, "
+                  "VCCBRAM {:.3f} V".format(fpga["temperature"], fpga["vccint"],
+                                            fpga["vccaux"], fpga["vccbram"]))
+        for linkno, link in enumerate(health["drtio_links"]):
+            print("DRTIO link {}:     {}, {} CRC errors, {} aux timeouts, "
+                  "{} drops, {} protocol errors, {} remote RTIO errors".format(
+                      linkno, "up" if link["up"] else "down",
+                      link["crc_errors"], link["aux_timeouts"],
+                      link["link_drops"], link["protocol_errors"],
+                      link["remote_rtio_errors"]))
+        def print_sfp(name, sfp):
+            if sfp is None:
+                print("{:18}no diagnostics".format(name + ":"))
+            else:
+                print("{:18}{:.1f} C, {:.3f} V, TX bias {:.2f} mA, "
+                      "TX {:.1f} uW, RX {:.1f} uW{}".format(
+                          name + ":", sfp["temperature"], sfp["voltage"],
+                          sfp["tx_bias"]*1e3, sfp["tx_power"]*1e6,
+                          sfp["rx_power"]*1e6,
+                          "".join(", " + alarm + " alarm" for alarm in sfp["alarms"])))
+        for port, sfp in enumerate(health["sfp"]):
+            print_sfp("SFP{}".format(port), sfp)
+        for rail in health["power"]:
+            if rail["voltage"] is None:
+                line = "{}: not readable".format(rail["name"])
+            else:
+                line = "{}: {:.3f} V, {:.3f} A".format(
+                    rail["name"], rail["voltage"], rail["current"])
+            print("{:18}{}, min {:.3f} V, {} brownouts".format(
+                "power:", line, rail["min_voltage"], rail["brownouts"]))
+        for sfp in health["satellite_sfp"]:
+            print_sfp("DEST#{} SFP{}".format(sfp["destination"], sfp["port"]),
+                      sfp["diagnostics"])
+
+    if args.tool == "routing":
+        if args.action == "reload":
+            mgmt.reload_routing_table()
+
+    if args.tool == "identity":
+        identity = mgmt.get_board_identity()
+        print("serial:   {}".format(identity["serial"]))
+        if identity["name"] is None:
+            print("no Sinara identification data in the EEPROM")
+        else:
+            print("board:    {} (ID {})".format(identity["name"], identity["board_id"]))
+            print("revision: {}".format(identity["hw_rev"]))
+            print("variant:  {}".format(identity["variant"]))
+            print("vendor:   {}".format(identity["vendor"]))
+
+    if args.tool == "compat":
+        compat = mgmt.get_compatibility()
+        print("firmware: 0x{:08x}".format(compat["firmware"]))
+        if compat["gateware"]:
+            print("gateware: 0x{:08x}".format(compat["gateware"]))
+        else:
+            print("gateware: no descriptor")
+        if compat["compatible"]:
+            print("compatible")
+        else:
+            print("MISMATCH: only networking and management are running, "
+                  "flash matching firmware and gateware")
+
+    if args.tool == "features":
+        features = mgmt.get_features()
+        print("flags: {}".format(
+            ", ".join(flag.name.lower() for flag in Feature
+                      if flag in features["flags"]) or "none"))
+        print("SFP ports: {}".format(features["sfp_ports"]))
+        if features["analyzer_size"]:
+            print("analyzer trace buffer: {} KiB"
+                  .format(features["analyzer_size"]//1024))
+
+    if args.tool == "grabber":
+        for n, grabber in enumerate(mgmt.get_grabber_stats()):
+            print("grabber{}: {}, frame size {}x{}, {} frames, {} dropped, "
+                  "{} clock glitches".format(
+                      n, "aligned" if grabber["aligned"] else "not aligned",
+                      grabber["frame_size"][0], grabber["frame_size"][1],
+                      grabber["frames"], grabber["dropped_frames"],
+                      grabber["clock_glitches"]))
+
+    if args.tool == "selftest":
+        if args.eem:
+            report = mgmt.run_eem_self_test()
+        elif args.last:
+            report = mgmt.get_self_test_report()
+        else:
+            report = mgmt.run_self_test()
+        print("self test at {}.{:03} s of uptime".format(
+            report["timestamp_ms"] // 1000, report["timestamp_ms"] % 1000))
+        width = max([8] + [len(check["subsystem"])
+                           for check in report["checks"]])
+        for check in report["checks"]:
+            print("{:{}} {:8} {}".format(check["subsystem"], width,
+                                         check["outcome"].upper(),
+                                         check["detail"]))
+        if any(check["outcome"] == "fail" for check in report["checks"]):
+            sys.exit(1)
+
+    if args.tool == "ddssync":
+        for report in mgmt.get_dds_sync_reports():
+            print("SPI channel 0x{:06x} CS {}: SYNC_IN delay {}, window {}, "
+                  "IO_UPDATE delay {}, at {}.{:03} s of uptime".format(
+                      report["channel"], report["chip_select"],
+                      report["in_delay"], report["window"],
+                      report["io_update_delay"],
+                      report["timestamp_ms"] // 1000,
+                      report["timestamp_ms"] % 1000))
+
+    if args.tool == "eeprom":
+        port = None if args.port is None else port_mapping[args.port]
+        eeprom = (args.busno, port, args.address, args.size, args.page_size)
+        if args.action == "read":
+            data = mgmt.eeprom_read(*eeprom, args.offset, args.length)
+            if args.file is None:
+                for i in range(0, len(data), 16):
+                    print("{:04x}: {}".format(args.offset + i,
+                                              data[i:i+16].hex()))
+            else:
+                with open(args.file, "wb") as f:
+                    f.write(data)
+        if args.action == "write":
+            with open(args.file, "rb") as f:
+                mgmt.eeprom_write(*eeprom, args.offset, f.read())
+
+    if args.tool == "rtc":
+        if args.action == "read":
+            unix_s = mgmt.get_rtc()
+            if unix_s is None:
+                print("RTC has not kept the time")
+            else:
+                print(datetime.datetime.fromtimestamp(
+                    unix_s, datetime.timezone.utc).isoformat())
+        if args.action == "set":
+            if args.time is None:
+                unix_s = time.time()
+            else:
+                unix_s = datetime.datetime.fromisoformat(args.time).replace(
+                    tzinfo=datetime.timezone.utc).timestamp()
+            mgmt.set_rtc(round(unix_s))
+
+
+        port = None if args.port is None else port_mapping[args.port]
+        data = mgmt.i2c_transfer(args.busno, port, args.address,
+                                 args.write, args.read)
+        if data:
+            print(data.hex())
+
+    if args.tool == "spi":
+        for word in mgmt.spi_transfer(args.busno, args.flags, args.length,
+                                      args.div, args.cs, args.words):
+            print("0x{:08x}".format(word))
+
+    if args.tool == "inject":
+        if args.action == "sfp_alarm":
+            for alarm in args.alarms:
+                if alarm not in CommMgmt._sfp_alarms:
+                    raise SystemExit("Unknown SFP alarm: {}".format(alarm))
+            mgmt.inject_sfp_alarms(args.port, args.alarms)
+        if args.action == "link_drop":
+            mgmt.inject_drtio_link_drop(args.linkno)
+        if args.action == "temperature":
+            mgmt.inject_fpga_temperature(args.temperature, args.duration)
+        if args.action == "clear":
+            mgmt.clear_faults()
+
+    if args.tool == "sensors":
+        samples = mgmt.get_sensor_history()
+        sfp_ports = max((len(sample["sfp"]) for sample in samples), default=0)
+        columns = ["uptime_s", "fpga_temperature_c", "vccint_v", "vccaux_v",
+                   "vccbram_v"]
+        for port in range(sfp_ports):
+            columns += ["sfp{}_tx_power_uw".format(port),
+                        "sfp{}_rx_power_uw".format(port)]
+        rows = []
+        for sample in samples:
+            fpga = sample["fpga"]
+            row = [sample["timestamp"]]
+            if fpga is None:
+                row += [None]*4
+            else:
+                row += [fpga["temperature"], fpga["vccint"], fpga["vccaux"],
+                        fpga["vccbram"]]
+            for sfp in sample["sfp"]:
+                if sfp is None:
+                    row += [None]*2
+                else:
+                    row += [sfp["tx_power"]*1e6, sfp["rx_power"]*1e6]
+            rows.append(["" if value is None else "{:.3f}".format(value)
+                         for value in row])
+        if args.file is None:
+            print(" ".join("{:>20}".format(column) for column in columns))
+            for row in rows:
+                print(" ".join("{:>20}".format(value) for value in row))
+        else:
+            with open(args.file, "w") as f:
+                f.write(",".join(columns) + "\n")
+                for row in rows:
+                    f.write(",".join(row) + "\n")
+
+    if args.tool == "kernels":
+        if args.action == "list":
+            for name, size in mgmt.kernel_image_list():
+                print("{}: {} bytes".format(name, size))
+        if args.action == "upload":
+            with open(args.file, "rb") as f:
+                mgmt.kernel_image_write(args.name, f.read())
+        if args.action == "remove":
+            mgmt.kernel_image_remove(args.name)
+        if args.action == "run":
+            mgmt.kernel_image_run(args.name)
+
+    if args.tool == "terminate":
+        if not mgmt.terminate_kernel(args.reason):
+            print("no kernel is running")
+
+    if args.tool == "storage":
+        if args.action == "list":
+            storage = mgmt.flash_storage_read()
+            print("{} of {} bytes used".format(storage["used"],
+                                               storage["size"]))
+            for key, value in sorted(storage["records"].items()):
+                print("{}: {}".format(key, value.hex()))
+        if args.action == "erase":
+            mgmt.flash_storage_erase()
+        if args.action == "health":
+            health = mgmt.get_flash_health(args.scrub)
+            if health["scrubbed_ms"] is None:
+                print("not scrubbed since boot")
+            else:
+                print("{} scrubs, last at {:.1f} s of uptime".format(
+                    health["scrubs"], health["scrubbed_ms"]/1000))
+                for name, area in sorted(health["areas"].items()):
+                    print("{}: {} values checked, {} corrupted".format(
+                        name, area["checked"], area["corrupted"]))
+            print("{} erase/program failures since boot, {} spare sectors "
+                  "free".format(health["failures"], health["spares_free"]))
+            for sector, spare in health["remapped"]:
+                print("sector 0x{:06x} remapped to 0x{:06x}".format(
+                    sector, spare))
+
+    if args.tool == "alerts":
+        dropped = 0
+        for alert in mgmt.subscribe_alerts(records=args.records):
+            if alert["dropped"] != dropped:
+                print("{} alerts dropped by the core device".format(
+                    alert["dropped"] - dropped))
+                dropped = alert["dropped"]
+            print("[{:10}.{:03}] {:8} {:11} {}".format(
+                alert["timestamp_ms"] // 1000, alert["timestamp_ms"] % 1000,
+                alert["severity"].upper(), alert["source"], alert["message"]),
+                flush=True)
+            for record in alert.get("records", []):
+                print_health_record(record, indent="    ")
+
+    if args.tool == "eems":
+        eems = mgmt.get_eem_presence()
+        if eems is None:
+            print("core device has no EEM connectors")
+        missing = 0
+        for eem in eems or []:
+            if eem["expected"] is None and not eem["detected"]:
+                continue
+            if eem["detected"] is None:
+                detected = "not probed"
+            elif eem["detected"]:
+                detected = eem["name"] or "unidentified card"
+            else:
+                detected = "no card"
+            mismatch = eem["expected"] is not None and eem["detected"] is False
+            missing += mismatch
+            print("EEM{:<3} {:12} {}{}".format(
+                eem["eem"], eem["expected"] or "-", detected,
+                " (MISSING)" if mismatch else ""))
+        if missing:
+            sys.exit(1)
+
+    if args.tool == "latency":
+        if args.action == "show":
+            def ns(latency):
+                return "-" if latency is None else "{:.3f} ns".format(latency*1e9)
+            print("DEST  measured     calibrated")
+            for satellite in mgmt.get_drtio_latency():
+                print("{:<5} {:12} {}".format(satellite["destination"],
+                                              ns(satellite["measured"]),
+                                              ns(satellite["calibrated"])))
+        if args.action == "calibrate":
+            if args.latency is not None and args.latency < 0:
+                print("latency must not be negative", file=sys.stderr)
+                sys.exit(1)
+            mgmt.set_drtio_latency(args.destination,
+                None if args.latency is None else args.latency*1e-9)
+        if args.action == "clear":
+            mgmt.config_remove("drtio_latency_{}".format(args.destination))
+
+    if args.tool == "audit":
+        dropped, entries = mgmt.get_audit_log()
+        if dropped:
+            print("{} older entries dropped".format(dropped))
+        for entry in entries:
+            if entry["unix_time"] is not None:
+                when = datetime.datetime.fromtimestamp(
+                    entry["unix_time"], datetime.timezone.utc).strftime(
+                    "%Y-%m-%d %H:%M:%S UTC")
+            elif entry["timestamp_ms"]:
+                when = "{:10}.{:03} s".format(entry["timestamp_ms"] // 1000,
+                                              entry["timestamp_ms"] % 1000)
+            else:
+                when = "before restart"
+            event = entry["event"]
+            if event == "disconnected":
+                event = "disconnected after {:.1f} s".format(
+                    entry["duration_ms"]/1000)
+            detail = ": " + entry["detail"] if entry["detail"] else ""
+            print("{:23} {:7} {:22} {}{}".format(
+                when, entry["service"], entry["peer"], event, detail))
+
+    if args.tool == "runs":
+        dropped, runs = mgmt.get_kernel_run_stats()
+        if dropped:
+            print("{} older runs dropped".format(dropped))
+        for run in runs:
+            if run["min_slack_mu"] is None:
+                slack = "-"
+            else:
+                slack = "{} mu".format(run["min_slack_mu"])
+            print("#{:<5} {:24} {:>12.6f} s {:9} {:>10} events, "
+                  "min slack {:>14}, {} RTIO errors".format(
+                      run["sequence"], run["source"], run["duration"],
+                      run["outcome"], run["output_events"], slack,
+                      run["rtio_errors"]))
+
+    if args.tool == "moninj":
+        limits = mgmt.get_moninj_limits()
+        if limits is None:
+            print("core device has no moninj")
+        else:
+            def rate(value):
+                return "{}/s".format(value) if value else "unlimited"
+            print("requests per connection:         {}".format(
+                rate(limits["request_rate"])))
+            print("reads per channel and connection: {}".format(
+                rate(limits["probe_rate"])))
+            print("requests throttled: {}".format(
+                limits["requests_throttled"]))
+            print("reads postponed:    {}".format(limits["reads_postponed"]))
+
+    if args.tool == "topology":
+        channels = dict()
+        if args.device is None:
+            for name, desc in ddb.get_device_db().items():
+                if not isinstance(desc, dict) or desc.get("type") != "local":
+                    continue
+                channel = desc.get("arguments", {}).get("channel")
+                if isinstance(channel, int):
+                    channels.setdefault(channel >> 16, []).append((channel & 0xffff, name))
+        destinations = args.destinations or sorted(set(channels) | {0})
+        mismatches = 0
+        for destination in destinations:
+            topology = mgmt.get_rtio_topology(destination)
+            if topology is None:
+                print("DEST#{}: gateware does not describe its topology".format(destination))
+                continue
+            print("DEST#{}: {}, {} downstream DRTIO links, {} RTIO channels{}".format(
+                destination, topology["role"], topology["links"], topology["channels"],
+                "" if topology["log"] is None else ", log channel {}".format(topology["log"])))
+            for peripheral in topology["peripherals"]:
+                print("  {} on EEM {}: channels {}-{}".format(
+                    peripheral["type"], ",".join(str(port) for port in peripheral["ports"]),
+                    peripheral["channels"].start, peripheral["channels"].stop - 1))
+            for channel, name in sorted(channels.get(destination, [])):
+                if channel >= topology["channels"]:
+                    print("  mismatch: {} uses channel {}, beyond the {} channels "
+                          "of the gateware".format(name, channel, topology["channels"]))
+                    mismatches += 1
+                elif channel == topology["log"]:
+                    print("  mismatch: {} uses channel {}, the log channel".format(
+                        name, channel))
+                    mismatches += 1
+        if mismatches:
+            sys.exit(1)
+
+    if args.tool == "satellites":
+        health = mgmt.get_health_snapshot()
+        print("master:  firmware {}, gateware {}".format(
+            health["firmware_version"], health["gateware_version"]))
+        for satellite in mgmt.get_satellites():
+            route = " ".join(str(hop) for hop in satellite["route"])
+            if satellite["firmware_version"] is None:
+                print("DEST#{}: route {}, no answer".format(
+                    satellite["destination"], route))
+                continue
+            print("DEST#{}: route {}, firmware {}, gateware {}, serial {}".format(
+                satellite["destination"], route,
+                satellite["firmware_version"], satellite["gateware_version"],
+                satellite["serial"] or "unknown"))
+            if satellite["firmware_version"] != health["firmware_version"]:
+                print("  WARNING: firmware version differs from the master")
+
+    if args.tool == "cache":
+        if args.action == "list":
+            entries = mgmt.get_cache_entries()
+            for entry in entries:
+                print("{}: {} bytes{}".format(
+                    entry["key"], entry["size"],
+                    ", in use" if entry["borrowed"] else ""))
+            print("total: {} bytes in {} entries".format(
+                sum(entry["size"] for entry in entries), len(entries)))
+        if args.action == "remove":
+            mgmt.remove_cache_entry(args.key)
+        if args.action == "clear":
+            mgmt.clear_cache()
+
+    if args.tool == "sed":
+        stats = mgmt.get_sed_stats(args.clear)
+        total = sum(stats["lane_writes"])
+        for lane, (writes, full) in enumerate(zip(stats["lane_writes"],
+                                                  stats["lane_full"])):
+            print("lane {}: {} events ({:.1f}%), full {} times".format(
+                lane, writes, 100*writes/total if total else 0, full))
+        print("collisions:      {}".format(stats["collisions"]))
+        print("busy errors:     {}".format(stats["busy_errors"]))
+        print("sequence errors: {}".format(stats["sequence_errors"]))
+
+    if args.tool == "events":
+        names = dict()
+        if args.device is None:
+            for name, desc in ddb.get_device_db().items():
+                if not isinstance(desc, dict) or desc.get("type") != "local":
+                    continue
+                channel = desc.get("arguments", {}).get("channel")
+                if isinstance(channel, int) and channel >> 16 == 0:
+                    names.setdefault(channel, []).append(name)
+        group_size, events = mgmt.get_channel_events(args.clear)
+        for group, count in enumerate(events):
+            first = group*group_size
+            channels = range(first, first + group_size)
+            group_names = [name for channel in channels
+                           for name in sorted(names.get(channel, []))]
+            print("channel{} {}: {} events{}".format(
+                "s" if group_size > 1 else "",
+                "{}-{}".format(first, first + group_size - 1)
+                if group_size > 1 else first,
+                count,
+                " ({})".format(", ".join(group_names)) if group_names else ""))
+
+    if args.tool == "top":
+        elapsed_us, threads = mgmt.get_thread_stats(args.clear)
+        busy_us = sum(t["run_time_us"] for t in threads)
+        print("over {:.1f} s, {:.1f}% in threads, {:.1f}% networking and idle".format(
+            elapsed_us/1e6, 100*busy_us/elapsed_us if elapsed_us else 0,
+            100 - 100*busy_us/elapsed_us if elapsed_us else 0))
+        print("{:16} {:>7} {:>6} {:>10} {:>12}".format(
+            "THREAD", "RUNNING", "CPU%", "WAKEUPS", "LONGEST (ms)"))
+        for t in sorted(threads, key=lambda t: t["run_time_us"], reverse=True):
+            print("{:16} {:>7} {:>6.1f} {:>10} {:>12.3f}".format(
+                t["name"], t["threads"],
+                100*t["run_time_us"]/elapsed_us if elapsed_us else 0,
+                t["wakeups"], t["longest_run_us"]/1e3))
+
+    if args.tool == "rtio_errors":
+        counts = mgmt.get_rtio_error_counts(args.clear)
+        if not counts:
+            print("no RTIO errors")
+        for c in counts:
+            print("destination {}: {} underflows, {} overflows, "
+                  "{} unreachable, {} collisions, {} busy errors, "
+                  "{} sequence errors, last on channel 0x{:06x}".format(
+                      c["destination"], c["underflows"], c["overflows"],
+                      c["unreachable"], c["collisions"], c["busy_errors"],
+                      c["sequence_errors"], c["last_channel"]))
+            s = c["satellite"]
+            if any(s.values()):
+                print("  on the satellite: {} write underflows, "
+                      "{} write overflows, {} buffer space timeouts, "
+                      "{} protocol errors, {} TSC resyncs".format(
+                          s["underflows"], s["overflows"],
+                          s["buffer_space_timeouts"], s["protocol_errors"],
+                          s["tsc_resyncs"]))
+
+    if args.tool == "eyescan":
+        points = mgmt.drtio_eye_scan(args.linkno, args.horizontal_step,
+                                     args.vertical_step, args.prescale)
+        if args.csv is not None:
+            with open(args.csv, "w") as f:
+                f.write("horizontal,vertical,errors,bits,ber\n")
+                for p in points:
+                    f.write("{},{},{},{},{}\n".format(
+                        p["horizontal"], p["vertical"], p["errors"],
+                        p["bits"], p["ber"]))
+        # One character per point, with the top row at the highest voltage:
+        # the decade of the bit error ratio, or "." if there was no error.
+        rows = dict()
+        for p in points:
+            rows.setdefault(p["vertical"], []).append(p)
+        for vertical in sorted(rows, reverse=True):
+            line = ""
+            for p in rows[vertical]:
+                if p["ber"]:
+                    line += str(min(9, int(-math.log10(p["ber"]))))
+                else:
+                    line += "."
+            print("{:4} {}".format(vertical, line))
+        print("digits: -log10(BER), '.': no error in {} bits".format(
+            points[0]["bits"] if points else 0))
+
+    if args.tool == "net":
+        if args.action == "stats":
+            stats = mgmt.get_net_stats()
+            print("received:           {} packets, {} bytes".format(
+                stats["rx_packets"], stats["rx_bytes"]))
+            print("sent:               {} packets, {} bytes".format(
+                stats["tx_packets"], stats["tx_bytes"]))
+            print("RX preamble errors: {}".format(stats["rx_preamble_errors"]))
+            print("RX CRC errors:      {}".format(stats["rx_crc_errors"]))
+            print("RX dropped:         {}".format(stats["rx_dropped"]))
+            print("network errors:     {}".format(stats["network_errors"]))
+            print("TCP retransmits:    {}".format(stats["tcp_retransmissions"]))
+            print("TCP sockets:")
+            for socket in stats["sockets"]:
+                print("  {:<12} {:<24} {:<24} rx queue {}, tx queue {}".format(
+                    socket["state"], socket["local"], socket["remote"],
+                    socket["recv_queue"], socket["send_queue"]))
+
+    if args.tool == "analyzer":
+        if args.action == "trigger":
+            if args.start is not None or args.stop is not None:
+                trigger = mgmt.get_analyzer_trigger()
+                start = trigger["start"] if args.start is None else args.start
+                stop = trigger["stop"] if args.stop is None else args.stop
+                mgmt.set_analyzer_trigger(None if start == "none" else start,
+                                          None if stop == "none" else stop)
+            trigger = mgmt.get_analyzer_trigger()
+            for name, fired in (("start", "started"), ("stop", "stopped")):
+                condition = trigger[name]
+                if condition is None:
+                    condition = "none"
+                elif condition != "exception":
+                    condition = "channel {}".format(condition)
+                print("{}: {}{}".format(name, condition,
+                                        " (fired)" if trigger[fired] else ""))
+        if args.action == "filter":
+            if args.include is not None and args.exclude is not None:
+                print("--include and --exclude cannot be combined.")
+                sys.exit(1)
+            if args.include is not None:
+                mgmt.set_analyzer_filter(args.include, exclude=False)
+            elif args.exclude is not None:
+                mgmt.set_analyzer_filter(args.exclude, exclude=True)
+            channels, exclude = mgmt.get_analyzer_filter()
+            if exclude and not channels:
+                print("recording all channels")
+            else:
+                print("recording {} channels {}".format(
+                    "all but" if exclude else "only",
+                    ", ".join(str(channel) for channel in channels)))
+
     if args.tool == "profile":
         if args.action == "start":
-            mgmt.start_profiler(args.interval, args.hits_size, args.edges_size)
+            if args.kernel:
+                mgmt.start_kernel_profiler(args.interval, args.hits_size,
+                                           args.edges_size)
+            else:
+                mgmt.start_profiler(args.interval, args.hits_size, args.edges_size)
         elif args.action == "stop":
-            mgmt.stop_profiler()
+            if args.kernel:
+                mgmt.stop_kernel_profiler()
+            else:
+                mgmt.stop_profiler()
         elif args.action == "save":
-            hits, edges = mgmt.get_profile()
+            if args.kernel:
+                hits, edges = mgmt.get_kernel_profile()
+            else:
+                hits, edges = mgmt.get_profile()
             writer = CallgrindWriter(args.output, args.firmware, "or1k-linux",
                                      args.compression, args.demangle)
             writer.header()
@@ -201,6 +1546,12 @@ def main():
     if args.tool == "debug":
         if args.action == "allocator":
             mgmt.debug_allocator()
+        if args.action == "heap":
+            stats = mgmt.allocator_stats()
+            print("total:        {} bytes".format(stats["total"]))
+            print("used:         {} bytes".format(stats["used"]))
+            print("largest free: {} bytes".format(stats["largest_free"]))
+            print("free blocks:  {}".format(stats["free_blocks"]))
 
 
 if __name__ == "__main__":
@@ -0,0 +1,42 @@
+#!/usr/bin/env python3
+
+import argparse
+
+from sipyco import common_args
+
+from artiq import __version__ as artiq_version
+from artiq.coredevice.discovery import discover
+
+
+def get_argparser():
+    parser = argparse.ArgumentParser(description="ARTIQ core device "
+                                                 "discovery tool")
+    parser.add_argument("--version", action="version",
+                        version="ARTIQ v{}".format(artiq_version),
+                        help="print the ARTIQ version number")
+
+    common_args.verbosity_args(parser)
+    parser.add_argument("-t", "--timeout", default=1.0, type=float,
+                        help="time to wait for answers, in seconds "
+                             "(default: %(default)s)")
+    return parser
+
+
+def main():
+    args = get_argparser().parse_args()
+    common_args.init_logger_from_args(args)
+
+    devices = discover(args.timeout)
+    if not devices:
+        print("no core device found")
+    for device in devices:
+        properties = device["properties"]
+        print("{}: {} ({})".format(device["name"], device["host"],
+                                   ", ".join(device["addresses"]) or "no address"))
+        print("  variant: {}, serial: {}, version: {}".format(
+            properties.get("variant", "?"), properties.get("serial", "?"),
+            properties.get("version", "?")))
+
+
+if __name__ == "__main__":
+    main()
@@ -29,6 +29,7 @@ Valid actions:
     * rtm_gateware: write RTM gateware bitstream to flash
     * bootloader: write bootloader to flash
     * storage: write storage image to flash
+    * boot_key: write boot key image (from artiq_sign bootkey) to flash
     * firmware: write firmware to flash
     * load: load main gateware bitstream into device (volatile but fast)
     * rtm_load: load RTM gateware bitstream into device
@@ -69,6 +70,7 @@ Prerequisites:
                         help="add a pre-initialization OpenOCD command. "
                              "Useful for selecting a board when several are connected.")
     parser.add_argument("-f", "--storage", help="write file to storage area")
+    parser.add_argument("-k", "--boot-key", help="write file to boot key area")
     parser.add_argument("-d", "--dir", help="look for board binaries in this directory")
     parser.add_argument("--srcbuild", help="board binaries directory is laid out as a source build tree",
                         default=False, action="store_true")
@@ -307,37 +309,53 @@ def main():
     args = get_argparser().parse_args()
     common_args.init_logger_from_args(args)
 
+    # The number and size of the firmware banks, and the areas the firmware
+    # reserves after them, must match the table in
+    # artiq/firmware/libboard_misoc/firmware.rs.
     config = {
         "kasli": {
             "programmer":   partial(ProgrammerXC7, board="kasli", proxy="bscan_spi_xc7a100t.bit"),
             "gateware":     ("spi0", 0x000000),
             "bootloader":   ("spi0", 0x400000),
-            "storage":      ("spi0", 0x440000),
+            "storage":      ("spi0", 0x430000),
             "firmware":     ("spi0", 0x450000),
+            "banks":        (2, 0x200000),
+            "reserved":     ("spi0", 0x850000),
         },
         "sayma": {
             "programmer":   ProgrammerAMCRTM,
             "gateware":     ("spi0", 0x000000),
             "bootloader":   ("spi1", 0x000000),
-            "storage":      ("spi1", 0x040000),
+            "storage":      ("spi1", 0x030000),
             "firmware":     ("spi1", 0x050000),
+            "banks":        (1, 0x1b0000),
             "rtm_gateware": ("spi1", 0x200000),
+            "reserved":     ("spi1", 0x600000),
         },
         "metlino": {
             "programmer":   ProgrammerAMC,
             "gateware":     ("spi0", 0x000000),
             "bootloader":   ("spi1", 0x000000),
-            "storage":      ("spi1", 0x040000),
+            "storage":      ("spi1", 0x030000),
             "firmware":     ("spi1", 0x050000),
+            "banks":        (1, 0x1b0000),
+            "reserved":     ("spi1", 0x600000),
         },
         "kc705": {
             "programmer":   partial(ProgrammerXC7, board="kc705", proxy="bscan_spi_xc7k325t.bit"),
             "gateware":     ("spi0", 0x000000),
             "bootloader":   ("spi0", 0xaf0000),
-            "storage":      ("spi0", 0xb30000),
+            "storage":      ("spi0", 0xb20000),
             "firmware":     ("spi0", 0xb40000),
+            "banks":        (2, 0x1c0000),
+            "reserved":     ("spi0", 0xec0000),
         },
     }[args.target]
+    # The boot key is in the second sector of the reserved areas, and the
+    # state of the firmware banks in the two sectors after the flash health
+    # table and its spares.
+    config["boot_key"] = (config["reserved"][0], config["reserved"][1] + 0x10000)
+    config["boot_state"] = (config["reserved"][0], config["reserved"][1] + 0xb0000)
 
     bin_dir = args.dir
     if bin_dir is None:
@@ -424,6 +442,20 @@ def main():
         atexit.register(lambda: os.unlink(bin_filename))
         return bin_filename
 
+    def erased_image(size):
+        handle, filename = tempfile.mkstemp(prefix="artiq_", suffix="_erased.bin")
+        with open(handle, "wb") as f:
+            f.write(b"\xff"*size)
+        atexit.register(lambda: os.unlink(filename))
+        return filename
+
+    def check_size(filename, area, next_area):
+        size = os.path.getsize(filename)
+        limit = config[next_area][1] - config[area][1]
+        if size > limit:
+            raise ValueError("{} is {} bytes, larger than the {} flash area ({} bytes)"
+                             .format(filename, size, area, limit))
+
     for action in args.action:
         if action == "gateware":
             gateware_bin = convert_gateware(
@@ -436,10 +468,13 @@ def main():
                                     rtm_gateware_bin)
         elif action == "bootloader":
             bootloader_bin = artifact_path(variant_dir, "software", "bootloader", "bootloader.bin")
+            check_size(bootloader_bin, "bootloader", "storage")
             programmer.write_binary(*config["bootloader"], bootloader_bin)
         elif action == "storage":
             storage_img = args.storage
             programmer.write_binary(*config["storage"], storage_img)
+        elif action == "boot_key":
+            programmer.write_binary(*config["boot_key"], args.boot_key)
         elif action == "firmware":
             if variant.endswith("satellite"):
                 firmware = "satman"
@@ -447,7 +482,16 @@ def main():
                 firmware = "runtime"
 
             firmware_fbi = artifact_path(variant_dir, "software", firmware, firmware + ".fbi")
+            size = os.path.getsize(firmware_fbi)
+            bank_size = config["banks"][1]
+            if size > bank_size:
+                raise ValueError("{} is {} bytes, larger than a firmware bank ({} bytes)"
+                                 .format(firmware_fbi, size, bank_size))
             programmer.write_binary(*config["firmware"], firmware_fbi)
+            if config["banks"][0] > 1:
+                # Boot the firmware just written to bank A, whichever bank
+                # was active, and the golden gateware, which it matches.
+                programmer.write_binary(*config["boot_state"], erased_image(0x20000))
         elif action == "load":
             if args.target == "sayma":
                 gateware_bit = artifact_path(variant_dir, "gateware", "top.bit")
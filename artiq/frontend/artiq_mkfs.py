@@ -2,6 +2,7 @@
 
 import argparse
 import struct
+import zlib
 
 
 def get_argparser():
@@ -15,33 +16,56 @@ def get_argparser():
     parser.add_argument("-f", nargs=2, action="append", default=[],
                         metavar=("KEY", "FILENAME"),
                         help="add file contents")
+    parser.add_argument("--sector-size", type=lambda s: int(s, 0),
+                        default=0x10000,
+                        help="flash sector size (default: %(default)#x)")
 
     return parser
 
 
-def write_record(f, key, value):
-    key_size = len(key) + 1
-    value_size = len(value)
-    record_size = key_size + value_size + 4
-    f.write(struct.pack(">l", record_size))
-    f.write(key.encode())
-    f.write(b"\x00")
-    f.write(value)
+def write_header(f, sequence):
+    header = b"ACF1" + struct.pack(">L", sequence)
+    f.write(header)
+    f.write(struct.pack(">L", zlib.crc32(header)))
+
+
+def write_raw_record(f, key, value):
+    body = key + b"\x00" + value
+    size = struct.pack(">L", len(body) + 8)
+    f.write(size)
+    f.write(struct.pack(">L", zlib.crc32(size + body)))
+    f.write(body)
 
 
-def write_end_marker(f):
-    f.write(b"\xff\xff\xff\xff")
+CHUNK_SIZE = 4096
+
+
+def write_record(f, key, value):
+    key = key.encode()
+    if len(value) <= CHUNK_SIZE:
+        write_raw_record(f, key, value)
+    else:
+        for i in range(0, len(value), CHUNK_SIZE):
+            write_raw_record(f, key + b"\xff" + str(i // CHUNK_SIZE).encode(),
+                             value[i:i+CHUNK_SIZE])
+        write_raw_record(f, key + b"\xff",
+                         struct.pack(">LL", len(value), zlib.crc32(value)))
 
 
 def main():
     args = get_argparser().parse_args()
     with open(args.output, "wb") as fo:
+        write_header(fo, 1)
         for key, string in args.s:
             write_record(fo, key, string.encode())
         for key, filename in args.f:
             with open(filename, "rb") as fi:
                 write_record(fo, key, fi.read())
-        write_end_marker(fo)
+        if fo.tell() > args.sector_size:
+            raise ValueError("records do not fit in one flash sector")
+        # Erase the rest of the storage area, including the second sector, so
+        # that a stale header from a previous configuration cannot take over.
+        fo.write(b"\xff" * (2 * args.sector_size - fo.tell()))
 
 if __name__ == "__main__":
     main()
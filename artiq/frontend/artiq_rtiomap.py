@@ -0,0 +1,59 @@
+#!/usr/bin/env python3
+
+import argparse
+import struct
+
+from artiq.master.databases import DeviceDB
+
+
+def get_argparser():
+    parser = argparse.ArgumentParser(description="ARTIQ RTIO channel name "
+                                                 "map generation tool")
+
+    parser.add_argument("--device-db", default="device_db.py",
+                        help="device database file (default: '%(default)s')")
+    parser.add_argument("file", metavar="FILE", type=str,
+                        help="target file, to be written into the "
+                             "device_map configuration key")
+
+    return parser
+
+
+def get_channel_map(device_db):
+    channel_map = dict()
+    for name, desc in sorted(device_db.items()):
+        if not isinstance(desc, dict) or desc["type"] != "local":
+            continue
+        arguments = desc.get("arguments", {})
+        # the channel of a DDS on a bus is not an RTIO channel
+        if "channel" not in arguments or "bus_channel" in arguments:
+            continue
+        channel = arguments["channel"]
+        if not isinstance(channel, int):
+            continue
+        if channel in channel_map:
+            print("warning: channel {} is used by both {} and {}, "
+                  "keeping the former".format(channel, channel_map[channel],
+                                              name))
+            continue
+        channel_map[channel] = name
+    return channel_map
+
+
+def serialize_channel_map(channel_map):
+    data = struct.pack(">I", len(channel_map))
+    for channel, name in sorted(channel_map.items()):
+        name = name.encode("utf-8")
+        data += struct.pack(">II", channel, len(name)) + name
+    return data
+
+
+def main():
+    args = get_argparser().parse_args()
+    device_db = DeviceDB(args.device_db).get_device_db()
+    with open(args.file, "wb") as f:
+        f.write(serialize_channel_map(get_channel_map(device_db)))
+
+
+if __name__ == "__main__":
+    main()
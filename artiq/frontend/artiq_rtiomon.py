@@ -13,6 +13,12 @@ def get_argparser():
                         help="hostname or IP address of the core device")
     parser.add_argument("channel", metavar="CHANNEL", type=lambda x: int(x, 0), nargs="+",
                        help="channel(s) to monitor")
+    parser.add_argument("-i", "--interval", default=None, type=int,
+                        help="probe interval in milliseconds "
+                             "(default: set by the core device)")
+    parser.add_argument("--udp", default=False, action="store_true",
+                        help="connect over UDP (the core device must have "
+                             "the moninj_udp config key set to 1)")
     return parser
 
 
@@ -24,8 +30,10 @@ def main():
         comm = CommMonInj(
             lambda channel, probe, value: print("0x{:06x}: {}".format(channel, value)),
             lambda channel, override, value: None)
-        loop.run_until_complete(comm.connect(args.core_addr))
+        loop.run_until_complete(comm.connect(args.core_addr, udp=args.udp))
         try:
+            if args.interval is not None:
+                comm.set_probe_interval(args.interval)
             for channel in args.channel:
                 comm.monitor_probe(True, channel, 0)
             loop.run_forever()
@@ -0,0 +1,249 @@
+#!/usr/bin/env python3
+
+import argparse
+import hashlib
+import os
+import struct
+import sys
+import zlib
+
+
+def get_argparser():
+    parser = argparse.ArgumentParser(description="ARTIQ firmware signing tool")
+
+    action = parser.add_subparsers(dest="action")
+    action.required = True
+
+    p_keygen = action.add_parser("keygen", help="generate a new private key")
+    p_keygen.add_argument("key", metavar="KEY", type=str,
+                          help="file to write the private key to")
+
+    p_pubkey = action.add_parser("pubkey", help="show the public key")
+    p_pubkey.add_argument("key", metavar="KEY", type=str,
+                          help="private key file")
+
+    p_sign = action.add_parser("sign", help="sign a firmware image")
+    p_sign.add_argument("key", metavar="KEY", type=str,
+                        help="private key file")
+    p_sign.add_argument("image", metavar="IMAGE", type=str,
+                        help="firmware image (.fbi file) to be signed")
+    p_sign.add_argument("output", metavar="OUTPUT", type=str,
+                        help="file to write the signed image to")
+
+    p_verify = action.add_parser("verify", help="check the signature of a "
+                                                "firmware image")
+    p_verify.add_argument("key", metavar="KEY", type=str,
+                          help="private key file")
+    p_verify.add_argument("image", metavar="IMAGE", type=str,
+                          help="signed firmware image")
+
+    p_bootkey = action.add_parser("bootkey", help="create the boot key image "
+                                  "to be written with artiq_flash")
+    p_bootkey.add_argument("key", metavar="KEY", type=str,
+                           help="private key file")
+    p_bootkey.add_argument("output", metavar="OUTPUT", type=str,
+                           help="file to write the boot key image to")
+    p_bootkey.add_argument("--required", default=False, action="store_true",
+                           help="make the bootloader refuse firmware that is "
+                                "not correctly signed")
+
+    return parser
+
+
+# Ed25519 (RFC 8032), after the reference implementation in its section 6.
+# It does not run in constant time, which is acceptable for signing images on
+# a trusted host.
+
+_p = 2**255 - 19
+_d = -121665 * pow(121666, _p - 2, _p) % _p
+_q = 2**252 + 27742317777372353535851937790883648493
+_sqrt_m1 = pow(2, (_p - 1) // 4, _p)
+
+
+def _point_add(P, Q):
+    A = (P[1] - P[0]) * (Q[1] - Q[0]) % _p
+    B = (P[1] + P[0]) * (Q[1] + Q[0]) % _p
+    C = 2 * P[3] * Q[3] * _d % _p
+    D = 2 * P[2] * Q[2] % _p
+    E, F, G, H = B - A, D - C, D + C, B + A
+    return (E * F, G * H, F * G, E * H)
+
+
+def _point_mul(s, P):
+    Q = (0, 1, 1, 0)
+    while s > 0:
+        if s & 1:
+            Q = _point_add(Q, P)
+        P = _point_add(P, P)
+        s >>= 1
+    return Q
+
+
+def _point_equal(P, Q):
+    if (P[0] * Q[2] - Q[0] * P[2]) % _p != 0:
+        return False
+    return (P[1] * Q[2] - Q[1] * P[2]) % _p == 0
+
+
+def _recover_x(y, sign):
+    if y >= _p:
+        return None
+    x2 = (y * y - 1) * pow(_d * y * y + 1, _p - 2, _p)
+    if x2 == 0:
+        return None if sign else 0
+    x = pow(x2, (_p + 3) // 8, _p)
+    if (x * x - x2) % _p != 0:
+        x = x * _sqrt_m1 % _p
+    if (x * x - x2) % _p != 0:
+        return None
+    if (x & 1) != sign:
+        x = _p - x
+    return x
+
+
+_g_y = 4 * pow(5, _p - 2, _p) % _p
+_g_x = _recover_x(_g_y, 0)
+_G = (_g_x, _g_y, 1, _g_x * _g_y % _p)
+
+
+def _point_compress(P):
+    zinv = pow(P[2], _p - 2, _p)
+    x = P[0] * zinv % _p
+    y = P[1] * zinv % _p
+    return int.to_bytes(y | ((x & 1) << 255), 32, "little")
+
+
+def _point_decompress(s):
+    if len(s) != 32:
+        return None
+    y = int.from_bytes(s, "little")
+    sign = y >> 255
+    y &= (1 << 255) - 1
+    x = _recover_x(y, sign)
+    if x is None:
+        return None
+    return (x, y, 1, x * y % _p)
+
+
+def _sha512_modq(s):
+    return int.from_bytes(hashlib.sha512(s).digest(), "little") % _q
+
+
+def _secret_expand(secret):
+    if len(secret) != 32:
+        raise ValueError("private keys are 32 bytes long")
+    h = hashlib.sha512(secret).digest()
+    a = int.from_bytes(h[:32], "little")
+    a &= (1 << 254) - 8
+    a |= 1 << 254
+    return a, h[32:]
+
+
+def public_key(secret):
+    a, _ = _secret_expand(secret)
+    return _point_compress(_point_mul(a, _G))
+
+
+def sign(secret, message):
+    a, prefix = _secret_expand(secret)
+    A = _point_compress(_point_mul(a, _G))
+    r = _sha512_modq(prefix + message)
+    R = _point_compress(_point_mul(r, _G))
+    h = _sha512_modq(R + A + message)
+    s = (r + h * a) % _q
+    return R + int.to_bytes(s, 32, "little")
+
+
+def verify(public, message, signature):
+    if len(public) != 32 or len(signature) != 64:
+        return False
+    A = _point_decompress(public)
+    if A is None:
+        return False
+    R = _point_decompress(signature[:32])
+    if R is None:
+        return False
+    s = int.from_bytes(signature[32:], "little")
+    if s >= _q:
+        return False
+    h = _sha512_modq(signature[:32] + public + message)
+    return _point_equal(_point_mul(s, _G),
+                        _point_add(R, _point_mul(h, A)))
+
+
+# See libboard_misoc/firmware.rs and libboard_misoc/boot_key.rs.
+SIGNATURE_MAGIC = b"ASIG"
+BOOT_KEY_MAGIC = b"AKEY"
+BOOT_KEY_REQUIRED = 1
+
+
+def split_image(image):
+    """Returns the firmware in a firmware image (.fbi file), and its
+    signature or ``None``."""
+    if len(image) < 8:
+        raise ValueError("firmware image too short")
+    length, crc = struct.unpack(">LL", image[:8])
+    firmware = image[8:8+length]
+    if len(firmware) != length or zlib.crc32(firmware) != crc:
+        raise ValueError("invalid firmware image header or CRC")
+    trailer = image[8+length:]
+    if not trailer:
+        return firmware, None
+    if len(trailer) != 4 + 64 or trailer[:4] != SIGNATURE_MAGIC:
+        raise ValueError("invalid data after the firmware")
+    return firmware, trailer[4:]
+
+
+def sign_image(secret, image):
+    """Returns the firmware image signed with ``secret``, replacing any
+    previous signature."""
+    firmware, _ = split_image(image)
+    return image[:8+len(firmware)] + SIGNATURE_MAGIC + sign(secret, firmware)
+
+
+def boot_key_image(public, required):
+    record = BOOT_KEY_MAGIC + public + struct.pack(
+        ">L", BOOT_KEY_REQUIRED if required else 0)
+    return record + struct.pack(">L", zlib.crc32(record))
+
+
+def read_key(filename):
+    with open(filename, "rb") as f:
+        return f.read()
+
+
+def main():
+    args = get_argparser().parse_args()
+
+    if args.action == "keygen":
+        fd = os.open(args.key, os.O_WRONLY | os.O_CREAT | os.O_EXCL, 0o600)
+        with open(fd, "wb") as f:
+            f.write(os.urandom(32))
+    elif args.action == "pubkey":
+        print(public_key(read_key(args.key)).hex())
+    elif args.action == "sign":
+        secret = read_key(args.key)
+        with open(args.image, "rb") as f:
+            image = f.read()
+        with open(args.output, "wb") as f:
+            f.write(sign_image(secret, image))
+    elif args.action == "verify":
+        public = public_key(read_key(args.key))
+        with open(args.image, "rb") as f:
+            firmware, signature = split_image(f.read())
+        if signature is None:
+            print("not signed")
+            sys.exit(1)
+        elif verify(public, firmware, signature):
+            print("signature valid")
+        else:
+            print("signature INVALID")
+            sys.exit(1)
+    elif args.action == "bootkey":
+        public = public_key(read_key(args.key))
+        with open(args.output, "wb") as f:
+            f.write(boot_key_image(public, args.required))
+
+
+if __name__ == "__main__":
+    main()
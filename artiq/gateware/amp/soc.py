@@ -18,7 +18,7 @@ class AMPSoC:
         self.add_cpulevel_sdram_if(self.kernel_cpu.wb_sdram)
         self.csr_devices.append("kernel_cpu")
 
-        mailbox_size = 3
+        mailbox_size = 5
         self.submodules.mailbox = Mailbox(mailbox_size)
         self.add_wb_slave(self.mem_map["mailbox"], 4*mailbox_size,
                           self.mailbox.i1)
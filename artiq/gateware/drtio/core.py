@@ -2,7 +2,7 @@ from types import SimpleNamespace
 
 from migen import *
 from migen.genlib.resetsync import AsyncResetSynchronizer
-from migen.genlib.cdc import PulseSynchronizer
+from migen.genlib.cdc import PulseSynchronizer, MultiReg
 from misoc.interconnect.csr import *
 
 from artiq.gateware.rtio import cri, rtlink
@@ -31,6 +31,8 @@ class TransceiverInterface(AutoCSR):
     def __init__(self, channel_interfaces):
         self.stable_clkin = CSRStorage()
         self.txenable = CSRStorage(len(channel_interfaces))
+        # near-end PMA loopback, for self tests
+        self.loopback = CSRStorage(len(channel_interfaces))
         self.clock_domains.cd_rtio = ClockDomain()
         for i in range(len(channel_interfaces)):
             name = "rtio_rx" + str(i)
@@ -79,6 +81,12 @@ class DRTIOSatellite(Module):
         self.reset = CSRStorage(reset=1)
         self.reset_phy = CSRStorage(reset=1)
         self.tsc_loaded = CSR()
+        # when set, a TSC value received from the master is only compared
+        # with the TSC, which keeps counting undisturbed
+        self.tsc_measure = CSRStorage()
+        # TSC value received from the master minus the value of the TSC at
+        # the last load or measurement, in coarse RTIO cycles
+        self.tsc_offset = CSRStatus(32)
         # master interface in the rtio domain
         self.cri = cri.Interface()
         self.async_errors = Record(async_errors_layout)
@@ -130,24 +138,37 @@ class DRTIOSatellite(Module):
             rt_packet_satellite.RTPacketSatellite(link_layer_sync, interface=self.cri))
         self.comb += self.rt_packet.reset.eq(self.cd_rio.rst)
 
+        tsc_measure = Signal()
+        self.specials += MultiReg(self.tsc_measure.storage, tsc_measure, "rtio")
         self.comb += [
-            tsc.load.eq(self.rt_packet.tsc_load),
+            tsc.load.eq(self.rt_packet.tsc_load & ~tsc_measure),
             tsc.load_value.eq(self.rt_packet.tsc_load_value)
         ]
 
+        tsc_offset = Signal(32)
+        self.sync.rtio += If(self.rt_packet.tsc_load,
+            tsc_offset.eq(self.rt_packet.tsc_load_value - tsc.coarse_ts - 1))
+
         ps_tsc_load = PulseSynchronizer("rtio", "sys")
         self.submodules += ps_tsc_load
         self.comb += ps_tsc_load.i.eq(self.rt_packet.tsc_load)
         self.sync += [
             If(self.tsc_loaded.re, self.tsc_loaded.w.eq(0)),
-            If(ps_tsc_load.o, self.tsc_loaded.w.eq(1))
+            If(ps_tsc_load.o,
+                self.tsc_loaded.w.eq(1),
+                # tsc_offset was registered with the pulse and holds until
+                # the next TSC value, which the master sends only after the
+                # firmware acknowledged this one
+                self.tsc_offset.status.eq(tsc_offset)
+            )
         ]
 
         self.submodules.rt_errors = rt_errors_satellite.RTErrorsSatellite(
             self.rt_packet, tsc, self.async_errors)
 
     def get_csrs(self):
-        return ([self.reset, self.reset_phy, self.tsc_loaded] +
+        return ([self.reset, self.reset_phy, self.tsc_loaded,
+                 self.tsc_measure, self.tsc_offset] +
                 self.link_layer.get_csrs() + self.link_stats.get_csrs() +
                 self.rt_errors.get_csrs())
 
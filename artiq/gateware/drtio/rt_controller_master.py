@@ -1,7 +1,7 @@
 """Real-time controller for master"""
 
 from migen import *
-from migen.genlib.cdc import MultiReg
+from migen.genlib.cdc import MultiReg, PulseSynchronizer
 from migen.genlib.misc import WaitTimer
 
 from misoc.interconnect.csr import *
@@ -16,6 +16,8 @@ class _CSRs(AutoCSR):
         self.protocol_error = CSR(3)
 
         self.set_time = CSR()
+        self.echo = CSR()
+        self.rtt = CSRStatus(16)
         self.underflow_margin = CSRStorage(16, reset=300)
 
         self.force_destination = CSRStorage()
@@ -60,6 +62,37 @@ class RTController(Module):
             If(self.csrs.set_time.re, rt_packet.set_time_stb.eq(1))
         ]
 
+        # round-trip time measurement, in RTIO clock cycles from an echo
+        # request being sent to its reply being received, saturating
+        echo_received = PulseSynchronizer("rtio_rx", "rtio")
+        echo_done = PulseSynchronizer("rtio", "sys")
+        self.submodules += echo_received, echo_done
+        rtt = Signal(16)
+        rtt_counting = Signal()
+        self.comb += [
+            echo_received.i.eq(rt_packet.echo_received_now),
+            echo_done.i.eq(echo_received.o)
+        ]
+        self.sync.rtio += [
+            If(rtt_counting & (rtt != 2**16 - 1), rtt.eq(rtt + 1)),
+            If(rt_packet.echo_sent_now,
+                rtt.eq(0),
+                rtt_counting.eq(1)
+            ),
+            If(echo_received.o, rtt_counting.eq(0))
+        ]
+        self.specials += MultiReg(rtt, self.csrs.rtt.status)
+        echo_pending = Signal()
+        self.comb += self.csrs.echo.w.eq(echo_pending)
+        self.sync += [
+            If(rt_packet.echo_ack, rt_packet.echo_stb.eq(0)),
+            If(echo_done.o, echo_pending.eq(0)),
+            If(self.csrs.echo.re,
+                rt_packet.echo_stb.eq(1),
+                echo_pending.eq(1)
+            )
+        ]
+
         # chan_sel forcing
         chan_sel = Signal(24)
         self.comb += chan_sel.eq(Mux(self.csrs.force_destination.storage,
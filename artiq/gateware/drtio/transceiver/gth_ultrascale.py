@@ -30,6 +30,7 @@ class GTHSingle(Module):
         # # #
 
         self.txenable = Signal()
+        self.loopback = Signal()
         nwords = dw//10
         self.submodules.encoder = encoder = ClockDomainsRenamer("rtio_tx")(
             Encoder(nwords, True))
@@ -488,6 +489,7 @@ class GTHSingle(Module):
 
             # TX data
             i_TXINHIBIT=~self.txenable,
+            i_LOOPBACK=Cat(0, self.loopback, 0),
             i_TXCTRL0=Cat(*[txdata[10*i+8] for i in range(nwords)]),
             i_TXCTRL1=Cat(*[txdata[10*i+9] for i in range(nwords)]),
             i_TXDATA=Cat(*[txdata[10*i:10*i+8] for i in range(nwords)]),
@@ -676,7 +678,10 @@ class GTH(Module, TransceiverInterface):
 
         TransceiverInterface.__init__(self, channel_interfaces)
         for n, gth in enumerate(self.gths):
-            self.comb += gth.txenable.eq(self.txenable.storage[n])
+            self.comb += [
+                gth.txenable.eq(self.txenable.storage[n]),
+                gth.loopback.eq(self.loopback.storage[n])
+            ]
         self.clock_domains.cd_rtiox = ClockDomain(reset_less=True)
         if create_buf:
             # GTH PLLs recover on their own from an interrupted clock input,
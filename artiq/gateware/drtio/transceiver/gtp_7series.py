@@ -2,7 +2,9 @@ from functools import reduce
 from operator import or_
 
 from migen import *
+from migen.genlib.cdc import MultiReg, PulseSynchronizer
 from migen.genlib.resetsync import AsyncResetSynchronizer
+from misoc.interconnect.csr import *
 
 from misoc.cores.code_8b10b import Encoder, Decoder
 
@@ -20,6 +22,7 @@ class GTPSingle(Module):
 
         self.stable_clkin = Signal()
         self.txenable = Signal()
+        self.loopback = Signal()
         self.submodules.encoder = encoder = ClockDomainsRenamer("rtio_tx")(
             Encoder(2, True))
         self.submodules.decoders = decoders = [ClockDomainsRenamer("rtio_rx")(
@@ -31,6 +34,11 @@ class GTPSingle(Module):
         self.txoutclk = Signal()
         self.rxoutclk = Signal()
 
+        # DRP port of the channel, in the rtio_tx domain. Available once the
+        # RX is initialized, which uses it before.
+        self.drp = Record([("addr", 9), ("di", 16), ("do", 16),
+                           ("en", 1), ("rdy", 1), ("we", 1)])
+
         # # #
 
         # TX generates RTIO clock, init must be in system domain
@@ -45,6 +53,30 @@ class GTPSingle(Module):
             tx_init.plllock.eq(qpll_channel.lock)
         ]
 
+        drpaddr = Signal(9)
+        drpdi = Signal(16)
+        drpdo = Signal(16)
+        drpen = Signal()
+        drprdy = Signal()
+        drpwe = Signal()
+        self.comb += [
+            If(rx_init.done,
+                drpaddr.eq(self.drp.addr),
+                drpdi.eq(self.drp.di),
+                drpen.eq(self.drp.en),
+                drpwe.eq(self.drp.we)
+            ).Else(
+                drpaddr.eq(rx_init.drpaddr),
+                drpdi.eq(rx_init.drpdi),
+                drpen.eq(rx_init.drpen),
+                drpwe.eq(rx_init.drpwe)
+            ),
+            rx_init.drpdo.eq(drpdo),
+            rx_init.drprdy.eq(drprdy & ~rx_init.done),
+            self.drp.do.eq(drpdo),
+            self.drp.rdy.eq(drprdy & rx_init.done)
+        ]
+
         txdata = Signal(20)
         rxdata = Signal(20)
         rxphaligndone = Signal()
@@ -116,14 +148,15 @@ class GTPSingle(Module):
 
             # RX Margin Analysis Attributes
             p_ES_CONTROL                             =0b000000,
-            p_ES_ERRDET_EN                           ="FALSE",
-            p_ES_EYE_SCAN_EN                         ="FALSE",
+            p_ES_ERRDET_EN                           ="TRUE",
+            p_ES_EYE_SCAN_EN                         ="TRUE",
             p_ES_HORZ_OFFSET                         =0x010,
             p_ES_PMA_CFG                             =0b0000000000,
             p_ES_PRESCALE                            =0b00000,
             p_ES_QUALIFIER                           =0x00000000000000000000,
-            p_ES_QUAL_MASK                           =0x00000000000000000000,
-            p_ES_SDATA_MASK                          =0x00000000000000000000,
+            # statistical eye scan over the 20 data bits (ug482)
+            p_ES_QUAL_MASK                           =0xFFFFFFFFFFFFFFFFFFFF,
+            p_ES_SDATA_MASK                          =0xFFFFFFFFFF00000FFFFF,
             p_ES_VERT_OFFSET                         =0b000000000,
 
             # FPGA RX Interface Attributes
@@ -366,17 +399,17 @@ class GTPSingle(Module):
             i_TSTIN                          =0b11111111111111111111,
 
             # Channel - DRP Ports
-            i_DRPADDR=rx_init.drpaddr,
+            i_DRPADDR=drpaddr,
             i_DRPCLK=ClockSignal("rtio_tx"),
-            i_DRPDI=rx_init.drpdi,
-            o_DRPDO=rx_init.drpdo,
-            i_DRPEN=rx_init.drpen,
-            o_DRPRDY=rx_init.drprdy,
-            i_DRPWE=rx_init.drpwe,
+            i_DRPDI=drpdi,
+            o_DRPDO=drpdo,
+            i_DRPEN=drpen,
+            o_DRPRDY=drprdy,
+            i_DRPWE=drpwe,
             # FPGA TX Interface Datapath Configuration
             i_TX8B10BEN                      =0,
             # Loopback Ports
-            i_LOOPBACK                       =0,
+            i_LOOPBACK                       =Cat(0, self.loopback, 0),
             # PCI Express Ports
             #o_PHYSTATUS                      =,
             i_RXRATE                         =0,
@@ -720,6 +753,47 @@ class GTPTXPhaseAlignement(Module):
                 ]
 
 
+class GTPDRPAccess(Module, AutoCSR):
+    """CPU access to the DRP of the channels, used for eye scans.
+
+    Write the channel, address, data and direction, then ``start``, and wait
+    for ``done``. The access never completes if the RX of the channel is not
+    initialized."""
+    def __init__(self, gtps):
+        self.channel = CSRStorage(bits_for(len(gtps)-1))
+        self.addr = CSRStorage(9)
+        self.di = CSRStorage(16)
+        self.we = CSRStorage()
+        self.start = CSR()
+        self.do = CSRStatus(16)
+        self.done = CSRStatus()
+
+        # # #
+
+        start = PulseSynchronizer("sys", "rtio")
+        done = PulseSynchronizer("rtio", "sys")
+        self.submodules += start, done
+        self.comb += start.i.eq(self.start.re)
+
+        do = Signal(16)
+        for n, gtp in enumerate(gtps):
+            selected = Signal()
+            self.comb += [
+                selected.eq(self.channel.storage == n),
+                gtp.drp.addr.eq(self.addr.storage),
+                gtp.drp.di.eq(self.di.storage),
+                gtp.drp.we.eq(self.we.storage),
+                gtp.drp.en.eq(start.o & selected)
+            ]
+            self.sync.rtio += If(selected & gtp.drp.rdy, do.eq(gtp.drp.do))
+        self.sync.rtio += done.i.eq(reduce(or_, [gtp.drp.rdy for gtp in gtps]))
+        self.specials += MultiReg(do, self.do.status)
+        self.sync += [
+            If(self.start.re, self.done.status.eq(0)),
+            If(done.o, self.done.status.eq(1))
+        ]
+
+
 class GTP(Module, TransceiverInterface):
     def __init__(self, qpll_channel, data_pads, sys_clk_freq, rtio_clk_freq, master=0):
         self.nchannels = nchannels = len(data_pads)
@@ -746,12 +820,14 @@ class GTP(Module, TransceiverInterface):
             channel_interfaces.append(channel_interface)
 
         self.submodules.tx_phase_alignment = GTPTXPhaseAlignement(self.gtps)
+        self.submodules.drp = GTPDRPAccess(self.gtps)
 
         TransceiverInterface.__init__(self, channel_interfaces)
         for n, gtp in enumerate(self.gtps):
             self.comb += [
                   gtp.stable_clkin.eq(self.stable_clkin.storage),
-                  gtp.txenable.eq(self.txenable.storage[n])
+                  gtp.txenable.eq(self.txenable.storage[n]),
+                  gtp.loopback.eq(self.loopback.storage[n])
             ]
 
         self.comb += [
@@ -3,7 +3,7 @@ from migen.build.generic_platform import *
 from migen.genlib.io import DifferentialOutput
 
 from artiq.gateware import rtio
-from artiq.gateware.rtio.phy import spi2, ad53xx_monitor, grabber
+from artiq.gateware.rtio.phy import spi2, ad53xx_monitor, ad9910_monitor, sampler_monitor, grabber
 from artiq.gateware.suservo import servo, pads as servo_pads
 from artiq.gateware.rtio.phy import servo as rtservo, fastino, phaser
 
@@ -163,13 +163,18 @@ class Urukul(_EEM):
         return ios
 
     @classmethod
-    def add_std(cls, target, eem, eem_aux, ttl_out_cls, sync_gen_cls=None, iostandard=default_iostandard):
+    def add_std(cls, target, eem, eem_aux, ttl_out_cls, sync_gen_cls=None, dds_monitor=False,
+                iostandard=default_iostandard):
         cls.add_extension(target, eem, eem_aux, iostandard=iostandard)
 
         phy = spi2.SPIMaster(target.platform.request("urukul{}_spi_p".format(eem)),
             target.platform.request("urukul{}_spi_n".format(eem)))
         target.submodules += phy
         target.rtio_channels.append(rtio.Channel.from_phy(phy, ififo_depth=4))
+        if dds_monitor:
+            monitor = ad9910_monitor.AD9910Monitor(phy.rtlink)
+            target.submodules += monitor
+            phy.probes.extend(monitor.probes)
 
         pads = target.platform.request("urukul{}_dds_reset_sync_in".format(eem))
         pad = Signal(reset=0)
@@ -255,22 +260,26 @@ class Sampler(_EEM):
     def add_std(cls, target, eem, eem_aux, ttl_out_cls, iostandard=default_iostandard):
         cls.add_extension(target, eem, eem_aux, iostandard=iostandard)
 
-        phy = spi2.SPIMaster(
+        adc_phy = spi2.SPIMaster(
                 target.platform.request("sampler{}_adc_spi_p".format(eem)),
                 target.platform.request("sampler{}_adc_spi_n".format(eem)))
-        target.submodules += phy
-        target.rtio_channels.append(rtio.Channel.from_phy(phy, ififo_depth=4))
-        phy = spi2.SPIMaster(
+        target.submodules += adc_phy
+        target.rtio_channels.append(rtio.Channel.from_phy(adc_phy, ififo_depth=4))
+        pgia_phy = spi2.SPIMaster(
                 target.platform.request("sampler{}_pgia_spi_p".format(eem)),
                 target.platform.request("sampler{}_pgia_spi_n".format(eem)))
-        target.submodules += phy
+        target.submodules += pgia_phy
 
-        target.rtio_channels.append(rtio.Channel.from_phy(phy, ififo_depth=4))
+        target.rtio_channels.append(rtio.Channel.from_phy(pgia_phy, ififo_depth=4))
         pads = target.platform.request("sampler{}_cnv".format(eem))
-        phy = ttl_out_cls(pads.p, pads.n)
-        target.submodules += phy
+        cnv_phy = ttl_out_cls(pads.p, pads.n)
+        target.submodules += cnv_phy
 
-        target.rtio_channels.append(rtio.Channel.from_phy(phy))
+        target.rtio_channels.append(rtio.Channel.from_phy(cnv_phy))
+
+        adc_monitor = sampler_monitor.SamplerMonitor(adc_phy.rtlink, pgia_phy.rtlink, cnv_phy.rtlink)
+        target.submodules += adc_monitor
+        adc_phy.probes.extend(adc_monitor.probes)
         sdr = target.platform.request("sampler{}_sdr".format(eem))
         target.specials += DifferentialOutput(1, sdr.p, sdr.n)
 
@@ -31,7 +31,7 @@ def peripheral_urukul(module, peripheral, **kwargs):
     else:
         sync_gen_cls = None
     eem.Urukul.add_std(module, port, port_aux, ttl_serdes_7series.Output_8X,
-        sync_gen_cls, **kwargs)
+        sync_gen_cls, dds_monitor=peripheral.get("dds", "ad9910") == "ad9910", **kwargs)
 
 
 def peripheral_novogorny(module, peripheral, **kwargs):
@@ -125,5 +125,11 @@ peripheral_processors = {
 
 
 def add_peripherals(module, peripherals, **kwargs):
+    # Channel range of each peripheral, for the topology reported by the
+    # firmware (see build_soc.get_topology).
+    module.rtio_peripherals = []
     for peripheral in peripherals:
+        first = len(module.rtio_channels)
         peripheral_processors[peripheral["type"]](module, peripheral, **kwargs)
+        module.rtio_peripherals.append(
+            (peripheral["type"], peripheral["ports"], first, len(module.rtio_channels)))
@@ -0,0 +1,108 @@
+from migen import *
+from migen.genlib.fifo import SyncFIFO
+from migen.genlib.fsm import FSM
+from migen.genlib.record import Record, layout_len
+
+from misoc.interconnect.csr import *
+from misoc.interconnect import wishbone
+
+
+_descriptor_layout = [
+    ("source", 30),
+    ("destination", 30),
+    ("words", 14),
+    ("last", 1)
+]
+
+
+# Copies frames between main memory and the SRAM slots of the Ethernet MAC
+# as a bus master, so that the comms CPU builds and parses frames in cached
+# memory instead of storing and loading every word of a slot over the bus.
+#
+# Transfers are queued as descriptors: the firmware sets source, destination
+# (byte addresses, word-aligned) and length (in bytes, rounded up to whole
+# words), then writes push while ready is set. A frame may be gathered from,
+# or scattered to, several descriptors, all but the last of which must be a
+# multiple of 4 bytes long; the last one has last set, and completed counts
+# these once their copy is done. Descriptors are carried out in order.
+class EthmacDMA(Module, AutoCSR):
+    def __init__(self, depth=16):
+        self.bus = wishbone.Interface()
+
+        self.source = CSRStorage(32, alignment_bits=2)
+        self.destination = CSRStorage(32, alignment_bits=2)
+        self.length = CSRStorage(16)
+        self.last = CSRStorage()
+        self.push = CSR()
+        self.ready = CSRStatus()
+        self.completed = CSRStatus(16)
+
+        # # #
+
+        self.submodules.fifo = fifo = SyncFIFO(layout_len(_descriptor_layout), depth)
+        push = Record(_descriptor_layout)
+        self.comb += [
+            push.source.eq(self.source.storage),
+            push.destination.eq(self.destination.storage),
+            push.words.eq((self.length.storage + 3) >> 2),
+            push.last.eq(self.last.storage),
+            fifo.din.eq(push.raw_bits()),
+            fifo.we.eq(self.push.re),
+            self.ready.status.eq(fifo.writable)
+        ]
+
+        pop = Record(_descriptor_layout)
+        self.comb += pop.raw_bits().eq(fifo.dout)
+
+        source = Signal(30)
+        destination = Signal(30)
+        words = Signal(14)
+        last = Signal()
+        data = Signal(32)
+
+        complete = Signal()
+        self.sync += If(complete, self.completed.status.eq(self.completed.status + 1))
+
+        self.submodules.fsm = fsm = FSM()
+        fsm.act("IDLE",
+            If(fifo.readable,
+                fifo.re.eq(1),
+                If(pop.words == 0,
+                    complete.eq(pop.last)
+                ).Else(
+                    NextValue(source, pop.source),
+                    NextValue(destination, pop.destination),
+                    NextValue(words, pop.words),
+                    NextValue(last, pop.last),
+                    NextState("READ")
+                )
+            )
+        )
+        fsm.act("READ",
+            self.bus.cyc.eq(1),
+            self.bus.stb.eq(1),
+            self.bus.adr.eq(source),
+            If(self.bus.ack,
+                NextValue(data, self.bus.dat_r),
+                NextState("WRITE")
+            )
+        )
+        fsm.act("WRITE",
+            self.bus.cyc.eq(1),
+            self.bus.stb.eq(1),
+            self.bus.we.eq(1),
+            self.bus.sel.eq(0xf),
+            self.bus.adr.eq(destination),
+            self.bus.dat_w.eq(data),
+            If(self.bus.ack,
+                NextValue(source, source + 1),
+                NextValue(destination, destination + 1),
+                NextValue(words, words - 1),
+                If(words == 1,
+                    complete.eq(last),
+                    NextState("IDLE")
+                ).Else(
+                    NextState("READ")
+                )
+            )
+        )
@@ -0,0 +1,37 @@
+from migen import *
+from migen.genlib.cdc import MultiReg
+
+from misoc.interconnect.csr import *
+
+
+# Drives the PWM input of a 4-wire fan and counts the pulses of its
+# tachometer output (usually two per revolution). The duty cycle is
+# duty/256.
+class FanController(Module, AutoCSR):
+    def __init__(self, pads, sys_clk_freq, pwm_freq=25e3):
+        # full speed until the firmware takes over
+        self.duty = CSRStorage(8, reset=255)
+        self.tach_count = CSRStatus(32)
+
+        prescaler_max = int(sys_clk_freq/pwm_freq/256) - 1
+        prescaler = Signal(max=prescaler_max + 1)
+        counter = Signal(8)
+        self.sync += [
+            If(prescaler == prescaler_max,
+                prescaler.eq(0),
+                counter.eq(counter + 1)
+            ).Else(
+                prescaler.eq(prescaler + 1)
+            ),
+            pads.pwm.eq(counter < self.duty.storage)
+        ]
+
+        tach = Signal()
+        tach_r = Signal()
+        self.specials += MultiReg(pads.tach, tach)
+        self.sync += [
+            tach_r.eq(tach),
+            If(tach & ~tach_r,
+                self.tach_count.status.eq(self.tach_count.status + 1)
+            )
+        ]
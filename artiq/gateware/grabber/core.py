@@ -1,5 +1,5 @@
 from migen import *
-from migen.genlib.cdc import MultiReg
+from migen.genlib.cdc import MultiReg, PulseSynchronizer
 from misoc.interconnect.csr import *
 
 
@@ -49,12 +49,17 @@ assert len(set(bitseq)) == 24
 
 
 class Parser(Module, AutoCSR):
-    """Parses 28 bit encoded words and track pixel coordinates."""
+    """Parses 28 bit encoded words and track pixel coordinates.
+
+    Counts the frames, and the frames with a size different from the
+    previous one (e.g. truncated by a loss of sync), which are dropped."""
     def __init__(self, width):
         self.cl = cl = Signal(28)
 
         self.last_x = CSRStatus(width)
         self.last_y = CSRStatus(width)
+        self.frame_count = CSRStatus(32)
+        self.dropped_frame_count = CSRStatus(32)
 
         self.pix = pix = Record([
             ("x", width),
@@ -65,6 +70,8 @@ class Parser(Module, AutoCSR):
             ("stb", 1),
             ("eop", 1),
         ])
+        # High while the camera transmits a frame (FVAL).
+        self.in_frame = Signal()
 
         # # #
 
@@ -80,6 +87,7 @@ class Parser(Module, AutoCSR):
             Cat(dval, fval, lval).eq(cl[14:17]),
             pix.stb.eq(dval & fval & lval),
             pix.eop.eq(~fval & last_fval),
+            self.in_frame.eq(fval),
             Cat(pix.a, pix.b, pix.c).eq(Cat(cl[i] for i in bitseq))
         ]
         self.sync.cl += [
@@ -110,6 +118,36 @@ class Parser(Module, AutoCSR):
             MultiReg(last_y, self.last_y.status)
         ]
 
+        # After a reset of the cl domain, the first frame may be partial
+        # and is not used as a reference.
+        skip = Signal(max=3, reset=2)
+        frame_x = Signal(width)
+        dropped = Signal()
+        self.sync.cl += [
+            dropped.eq(0),
+            If(pix.eop,
+                frame_x.eq(last_x),
+                If(skip != 0,
+                    skip.eq(skip - 1)
+                ).Elif((pix.y != last_y) | (last_x != frame_x),
+                    dropped.eq(1)
+                )
+            )
+        ]
+
+        frame_ps = PulseSynchronizer("cl", "sys")
+        dropped_ps = PulseSynchronizer("cl", "sys")
+        self.submodules += frame_ps, dropped_ps
+        self.comb += [
+            frame_ps.i.eq(pix.eop),
+            dropped_ps.i.eq(dropped)
+        ]
+        self.sync += [
+            If(frame_ps.o, self.frame_count.status.eq(self.frame_count.status + 1)),
+            If(dropped_ps.o,
+                self.dropped_frame_count.status.eq(self.dropped_frame_count.status + 1))
+        ]
+
 
 class ROI(Module):
     """ROI Engine. For each frame, accumulates pixels values within a
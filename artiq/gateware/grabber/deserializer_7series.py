@@ -1,5 +1,5 @@
 from migen import *
-from migen.genlib.cdc import MultiReg
+from migen.genlib.cdc import MultiReg, PulseSynchronizer
 from migen.genlib.resetsync import AsyncResetSynchronizer
 
 from misoc.interconnect.csr import *
@@ -15,6 +15,8 @@ class Deserializer(Module, AutoCSR):
         self.phase_shift = CSR()
         self.phase_shift_done = CSRStatus(reset=1)
         self.clk_sampled = CSRStatus(7)
+        # samples of the CL clock with another pattern than the expected one
+        self.clk_glitch_count = CSRStatus(32)
 
         self.q_clk = Signal(7)
         self.q = Signal(7*len(pins.sdi_p))
@@ -113,6 +115,14 @@ class Deserializer(Module, AutoCSR):
         ]
         self.specials += MultiReg(self.q_clk, self.clk_sampled.status)
 
+        glitch = Signal()
+        self.sync.cl += glitch.eq(self.q_clk != 0b1100011)
+        glitch_ps = PulseSynchronizer("cl", "sys")
+        self.submodules += glitch_ps
+        self.comb += glitch_ps.i.eq(glitch)
+        self.sync += If(glitch_ps.o,
+            self.clk_glitch_count.status.eq(self.clk_glitch_count.status + 1))
+
         self.specials += MultiReg(mmcm_locked, self.pll_locked.status)
         pll_reset.attr.add("no_retiming")
         self.sync += pll_reset.eq(self.pll_reset.storage)
@@ -0,0 +1,144 @@
+from migen import *
+from migen.genlib.cdc import MultiReg
+
+from misoc.interconnect.csr import *
+
+
+# Packets of the configuration logic of 7-series FPGAs (UG470, chapter 5).
+_DUMMY = 0xffffffff
+_SYNC = 0xaa995566
+_NOOP = 0x20000000
+
+_CMD = 0x04
+_WBSTAR = 0x10
+_BOOTSTS = 0x16
+
+_IPROG = 0x0000000f
+_DESYNC = 0x0000000d
+
+
+def _type1_write(register):
+    return 0x30000001 | register << 13
+
+
+def _type1_read(register):
+    return 0x28000001 | register << 13
+
+
+# The ICAPE2 takes and gives the bits of each byte in reverse order.
+def _bitswap(word):
+    return Cat(*[word[8*(i//8) + 7 - i % 8] for i in range(32)])
+
+
+# Accesses the configuration logic of 7-series FPGAs over the ICAPE2
+# (UG470, chapter 7). After configuration, the boot status and the warm boot
+# start address are read back, so that the firmware can tell whether the FPGA
+# runs the golden image at the start of the flash or the image it was
+# reconfigured with, and whether a reconfiguration failed and fell back to
+# the golden image. Writing reconfigure sends the IPROG command, which
+# reconfigures the FPGA from reconfigure_address; asserting
+# reconfigure_golden reconfigures it from the golden image, as at power-up.
+class ICAP(Module, AutoCSR):
+    def __init__(self):
+        self.boot_status = CSRStatus(16)
+        self.warm_boot_address = CSRStatus(32)
+        self.reconfigure_address = CSRStorage(32)
+        self.reconfigure = CSR()
+
+        self.reconfigure_golden = Signal()
+
+        # # #
+
+        # The ICAPE2 clock must not exceed 100MHz.
+        self.clock_domains.cd_icap = ClockDomain(reset_less=True)
+        icap_clk = Signal()
+        self.sync += icap_clk.eq(~icap_clk)
+        self.comb += self.cd_icap.clk.eq(icap_clk)
+
+        # Never cleared; the FPGA is reconfigured.
+        reconfigure_pending = Signal()
+        self.sync += If(self.reconfigure.re, reconfigure_pending.eq(1))
+
+        start_user = Signal()
+        start_golden = Signal()
+        address = Signal(32)
+        self.specials += [
+            MultiReg(reconfigure_pending, start_user, "icap"),
+            MultiReg(self.reconfigure_golden, start_golden, "icap"),
+            MultiReg(self.reconfigure_address.storage, address, "icap")
+        ]
+
+        boot_status = Signal(16)
+        warm_boot_address = Signal(32)
+        self.specials += [
+            MultiReg(boot_status, self.boot_status.status),
+            MultiReg(warm_boot_address, self.warm_boot_address.status)
+        ]
+
+        csib = Signal(reset=1)
+        rdwrb = Signal()
+        i = Signal(32)
+        o = Signal(32)
+        self.specials += Instance("ICAPE2",
+            p_ICAP_WIDTH="X32",
+            i_CLK=ClockSignal("icap"),
+            i_CSIB=csib,
+            i_RDWRB=rdwrb,
+            i_I=i,
+            o_O=o)
+
+        # Steps of each sequence: the word to write, or the register to
+        # capture the output into while reading, with CSIB and RDWRB.
+        def write(word):
+            return (0, 0, word, None)
+
+        def read_back(register, target):
+            return [
+                write(_DUMMY), write(_SYNC), write(_NOOP),
+                write(_type1_read(register)), write(_NOOP), write(_NOOP),
+                (1, 0, None, None), (1, 1, None, None)
+            ] + [(0, 1, None, target)]*8 + [
+                (1, 1, None, None), (1, 0, None, None),
+                write(_type1_write(_CMD)), write(_DESYNC), write(_NOOP), write(_NOOP)
+            ]
+
+        def iprog(start_address):
+            return [
+                write(_DUMMY), write(_SYNC), write(_NOOP),
+                write(_type1_write(_WBSTAR)), write(start_address),
+                write(_type1_write(_CMD)), write(_IPROG), write(_NOOP)
+            ]
+
+        fsm = ClockDomainsRenamer("icap")(FSM(reset_state="WAIT"))
+        self.submodules += fsm
+
+        def sequence(name, steps, end):
+            for n, (step_csib, step_rdwrb, word, target) in enumerate(steps):
+                actions = [csib.eq(step_csib), rdwrb.eq(step_rdwrb)]
+                if word is not None:
+                    if not isinstance(word, Signal):
+                        word = Constant(word, 32)
+                    actions.append(i.eq(_bitswap(word)))
+                if target is not None:
+                    actions.append(NextValue(target, _bitswap(o)[:len(target)]))
+                following = name + str(n + 1) if n + 1 < len(steps) else end
+                fsm.act(name + str(n), *actions, NextState(following))
+
+        # Lets the configuration logic finish the start-up sequence.
+        wait = Signal(max=1024, reset=1023)
+        fsm.act("WAIT",
+            NextValue(wait, wait - 1),
+            If(wait == 0, NextState("BOOTSTS0"))
+        )
+        sequence("BOOTSTS", read_back(_BOOTSTS, boot_status), "WBSTAR0")
+        sequence("WBSTAR", read_back(_WBSTAR, warm_boot_address), "IDLE")
+        fsm.act("IDLE",
+            If(start_golden,
+                NextState("GOLDEN0")
+            ).Elif(start_user,
+                NextState("USER0")
+            )
+        )
+        sequence("GOLDEN", iprog(0), "DONE")
+        sequence("USER", iprog(address), "DONE")
+        fsm.act("DONE")
@@ -1,5 +1,7 @@
 from artiq.gateware.rtio.tsc import TSC
-from artiq.gateware.rtio.cri import KernelInitiator, CRIInterconnectShared, RoutingTableAccess
+from artiq.gateware.rtio.cri import (KernelInitiator, NRTInitiator,
+                                    SlackMonitor, CRIInterconnectShared,
+                                    RoutingTableAccess, CRIDomainBridge)
 from artiq.gateware.rtio.channel import Channel, LogChannel
 from artiq.gateware.rtio.core import Core
 from artiq.gateware.rtio.analyzer import Analyzer
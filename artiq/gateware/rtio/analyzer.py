@@ -1,10 +1,13 @@
+from functools import reduce
+from operator import or_
+
 from migen import *
 from migen.genlib.record import Record, layout_len
 from misoc.interconnect.csr import *
 from misoc.interconnect import stream
 
 from artiq.gateware.rtio.cri import commands as cri_commands
-from artiq.coredevice.comm_analyzer import MessageType, ExceptionType
+from artiq.coredevice.comm_analyzer import MessageType, ExceptionType, TriggerMode
 
 
 __all__ = ["Analyzer"]
@@ -43,12 +46,30 @@ assert layout_len(stopped_layout) == message_len
 
 
 class MessageEncoder(Module, AutoCSR):
-    def __init__(self, tsc, cri, enable):
+    def __init__(self, tsc, cri, enable, filter_size=8):
         self.source = stream.Endpoint([("data", message_len)])
 
         self.overflow = CSRStatus()
         self.overflow_reset = CSR()
 
+        # Recording starts with the first message that matches the start
+        # trigger, and stops after the first message that matches the stop
+        # trigger. Both are rearmed when the analyzer is enabled.
+        self.trigger_start_mode = CSRStorage(2)
+        self.trigger_start_channel = CSRStorage(len(cri.chan_sel))
+        self.trigger_stop_mode = CSRStorage(2)
+        self.trigger_stop_channel = CSRStorage(len(cri.chan_sel))
+        self.trigger_state = CSRStatus(2)  # bit 0: started, bit 1: stopped
+
+        # Input and output messages are recorded only for the channels of the
+        # filter, or, with filter_exclude, for all but them. Exceptions are
+        # always recorded. Entries are loaded one at a time with filter_write.
+        self.filter_exclude = CSRStorage(reset=1)
+        self.filter_index = CSRStorage(bits_for(filter_size - 1))
+        self.filter_channel = CSRStorage(len(cri.chan_sel))
+        self.filter_valid = CSRStorage()
+        self.filter_write = CSR()
+
         # # #
 
         read_wait_event = cri.i_status[2]
@@ -106,7 +127,55 @@ class MessageEncoder(Module, AutoCSR):
             stopped.rtio_counter.eq(tsc.full_ts_cri),
         ]
 
+        filter_entries = [(Signal(len(cri.chan_sel)), Signal())
+                          for _ in range(filter_size)]
+        for i, (channel, valid) in enumerate(filter_entries):
+            self.sync += \
+                If(self.filter_write.re & (self.filter_index.storage == i),
+                    channel.eq(self.filter_channel.storage),
+                    valid.eq(self.filter_valid.storage)
+                )
+        filter_match = Signal()
+        input_output_recorded = Signal()
+        self.comb += [
+            filter_match.eq(reduce(or_, [valid & (channel == cri.chan_sel)
+                                         for channel, valid in filter_entries])),
+            input_output_recorded.eq(input_output_stb &
+                                     (filter_match ^ self.filter_exclude.storage))
+        ]
+
+        def trigger_hit(mode, channel):
+            return (((mode.storage == TriggerMode.channel.value) &
+                     input_output_stb & (cri.chan_sel == channel.storage)) |
+                    ((mode.storage == TriggerMode.exception.value) &
+                     exception_stb))
+
         enable_r = Signal()
+        started = Signal()
+        stopped = Signal()
+        start_hit = Signal()
+        stop_hit = Signal()
+        recording = Signal()
+        self.comb += [
+            start_hit.eq(trigger_hit(self.trigger_start_mode,
+                                     self.trigger_start_channel)),
+            stop_hit.eq(trigger_hit(self.trigger_stop_mode,
+                                    self.trigger_stop_channel)),
+            recording.eq(enable & ~stopped &
+                         ((self.trigger_start_mode.storage == TriggerMode.none.value) |
+                          started | start_hit)),
+            self.trigger_state.status.eq(Cat(started, stopped))
+        ]
+        self.sync += [
+            If(enable & ~enable_r,
+                started.eq(0),
+                stopped.eq(0)
+            ).Elif(enable,
+                If(start_hit, started.eq(1)),
+                If(recording & stop_hit, stopped.eq(1))
+            )
+        ]
+
         stopping = Signal()
         self.sync += [
             enable_r.eq(enable),
@@ -119,8 +188,8 @@ class MessageEncoder(Module, AutoCSR):
                     self.source.data.eq(input_output.raw_bits())
                 ),
                 self.source.eop.eq(0),
-                self.source.stb.eq(enable &
-                                  (input_output_stb | exception_stb)),
+                self.source.stb.eq(recording &
+                                  (input_output_recorded | exception_stb)),
 
                 If(self.overflow_reset.re, self.overflow.status.eq(0)),
                 If(self.source.stb & ~self.source.ack,
@@ -1,9 +1,9 @@
 from functools import reduce
-from operator import and_
+from operator import and_, or_
 
 from migen import *
 from migen.genlib.resetsync import AsyncResetSynchronizer
-from migen.genlib.cdc import BlindTransfer
+from migen.genlib.cdc import BlindTransfer, MultiReg
 from misoc.interconnect.csr import *
 
 from artiq.gateware.rtio import cri
@@ -22,6 +22,34 @@ class Core(Module, AutoCSR):
         self.collision_channel = CSRStatus(16)
         self.busy_channel = CSRStatus(16)
         self.sequence_error_channel = CSRStatus(16)
+        # lanes that were full when the sequence error was recorded
+        self.sequence_error_lanes_full = CSRStatus(lane_count)
+        self.rtio_clk_count = CSRStatus(24)
+        # RTIO counter latched for the comms CPU, which must not race with
+        # kernels reading the one of the kernel initiator
+        self.counter = CSRStatus(64)
+        self.counter_update = CSR()
+
+        # SED diagnostics, counted since boot or the last clear
+        self.sed_lane_count = CSRStatus(8, reset=lane_count)
+        self.sed_lane_sel = CSRStorage(bits_for(lane_count - 1))
+        self.sed_lane_writes = CSRStatus(32)
+        self.sed_lane_full = CSRStatus(32)
+        self.collision_count = CSRStatus(32)
+        self.busy_count = CSRStatus(32)
+        self.sequence_error_count = CSRStatus(32)
+        self.sed_stats_clear = CSR()
+
+        # Output events accepted into the SED lanes for each group of
+        # 2**channel_group_shift consecutive channels, counted since boot or
+        # the last clear
+        channel_group_shift = max(bits_for(len(channels) - 1) - 6, 0)
+        channel_group_count = ((len(channels) - 1) >> channel_group_shift) + 1
+        self.channel_group_count = CSRStatus(8, reset=channel_group_count)
+        self.channel_group_shift = CSRStatus(4, reset=channel_group_shift)
+        self.channel_group_sel = CSRStorage(6)
+        self.channel_group_events = CSRStatus(32)
+        self.channel_events_clear = CSR()
 
         # Clocking/Reset
         # Create rsys, rio and rio_phy domains based on sys and rtio
@@ -61,6 +89,7 @@ class Core(Module, AutoCSR):
                                  max(rtlink.get_fine_ts_width(channel.interface.i)
                                      for channel in channels))
         assert tsc.glbl_fine_ts_width >= chan_fine_ts_width
+        self.sync += If(self.counter_update.re, self.counter.status.eq(tsc.full_ts_sys))
 
         # Outputs/Inputs
         quash_channels = [n for n, c in enumerate(channels) if isinstance(c, LogChannel)]
@@ -106,7 +135,9 @@ class Core(Module, AutoCSR):
             If(outputs.sequence_error, 
                 o_sequence_error.eq(1),
                 If(~o_sequence_error,
-                    self.sequence_error_channel.status.eq(outputs.sequence_error_channel)
+                    self.sequence_error_channel.status.eq(outputs.sequence_error_channel),
+                    self.sequence_error_lanes_full.status.eq(
+                        Cat(~lane.writable for lane in outputs.lane_dist.output))
                 )
             )
         ]
@@ -118,3 +149,83 @@ class Core(Module, AutoCSR):
             o_busy_sync.i.eq(outputs.busy),
             o_busy_sync.data_i.eq(outputs.busy_channel)
         ]
+
+        # SED diagnostics
+        # For each lane, the events written into it and the number of times
+        # it became full, which stalls the CPU until the lane drains.
+        lane_writes = []
+        lane_full = []
+        for lane in outputs.lane_dist.output:
+            writes = Signal(32)
+            full = Signal(32)
+            writable_r = Signal(reset=1)
+            self.sync += [
+                writable_r.eq(lane.writable),
+                If(self.sed_stats_clear.re,
+                    writes.eq(0),
+                    full.eq(0)
+                ).Else(
+                    If(lane.we, writes.eq(writes + 1)),
+                    If(writable_r & ~lane.writable, full.eq(full + 1))
+                )
+            ]
+            lane_writes.append(writes)
+            lane_full.append(full)
+        self.comb += [
+            self.sed_lane_writes.status.eq(Array(lane_writes)[self.sed_lane_sel.storage]),
+            self.sed_lane_full.status.eq(Array(lane_full)[self.sed_lane_sel.storage])
+        ]
+        for event, count in [(o_collision_sync.o, self.collision_count.status),
+                             (o_busy_sync.o, self.busy_count.status),
+                             (outputs.sequence_error, self.sequence_error_count.status)]:
+            self.sync += [
+                If(self.sed_stats_clear.re,
+                    count.eq(0)
+                ).Elif(event,
+                    count.eq(count + 1)
+                )
+            ]
+
+        # Output events per channel group
+        # Events go into one lane at a time, and all lanes see the channel.
+        event_we = reduce(or_, [lane.we for lane in outputs.lane_dist.output])
+        event_group = outputs.lane_dist.output[0].payload.channel[channel_group_shift:]
+        group_events = []
+        for group in range(channel_group_count):
+            events = Signal(32)
+            self.sync += [
+                If(self.channel_events_clear.re,
+                    events.eq(0)
+                ).Elif(event_we & (event_group == group),
+                    events.eq(events + 1)
+                )
+            ]
+            group_events.append(events)
+        self.comb += self.channel_group_events.status.eq(
+            Array(group_events)[self.channel_group_sel.storage])
+
+        # RTIO clock frequency measurement
+        # rtio_clk_count holds the number of RTIO clock cycles divided by 4
+        # during the last 2**23 system clock cycles.
+        rtio_div = Signal(2, reset_less=True)
+        self.sync.rtio += rtio_div.eq(rtio_div + 1)
+        rtio_div_msb = Signal(reset_less=True)
+        rtio_div_msb.attr.add("no_retiming")
+        self.sync.rtio += rtio_div_msb.eq(rtio_div[-1])
+        rtio_div_sys = Signal()
+        rtio_div_sys_r = Signal()
+        self.specials += MultiReg(rtio_div_msb, rtio_div_sys)
+
+        timer = Signal(23)
+        timer_tick = Signal()
+        rtio_count = Signal(24)
+        self.sync += [
+            Cat(timer, timer_tick).eq(timer + 1),
+            rtio_div_sys_r.eq(rtio_div_sys),
+            If(timer_tick,
+                self.rtio_clk_count.status.eq(rtio_count),
+                rtio_count.eq(0)
+            ).Elif(rtio_div_sys & ~rtio_div_sys_r,
+                rtio_count.eq(rtio_count + 1)
+            )
+        ]
@@ -2,7 +2,7 @@
 
 from migen import *
 from migen.genlib.record import *
-from migen.genlib.cdc import MultiReg
+from migen.genlib.cdc import MultiReg, PulseSynchronizer
 
 from misoc.interconnect.csr import *
 
@@ -123,7 +123,85 @@ class KernelInitiator(Module, AutoCSR):
             self.o_data.dat_w.eq(0),
             self.o_data.we.eq(self.target.re),
         ]
-        self.sync += If(self.counter_update.re, self.counter.status.eq(tsc.full_ts_cri))
+        self.sync += If(self.counter_update.re, self.counter.status.eq(tsc.full_ts_sys))
+
+
+class NRTInitiator(KernelInitiator):
+    """Kernel initiator of the comms CPU, for RTIO transactions without
+    timing requirements while no kernel is running, e.g. the self-test of
+    the EEM cards.
+
+    It is inserted between the kernel initiator, connected to
+    ``kernel_cri``, and the interconnect, connected to ``shared_cri``.
+    While ``enable`` is set, the commands of the comms CPU are issued to the
+    interconnect instead of those of the kernel CPU.
+    """
+    def __init__(self, tsc):
+        KernelInitiator.__init__(self, tsc)
+        self.enable = CSRStorage()
+
+        self.kernel_cri = Interface()
+        self.shared_cri = Interface()
+
+        # # #
+
+        for name, size, direction in layout:
+            shared = getattr(self.shared_cri, name)
+            if direction == DIR_M_TO_S:
+                self.comb += shared.eq(Mux(self.enable.storage,
+                                           getattr(self.cri, name),
+                                           getattr(self.kernel_cri, name)))
+            else:
+                self.comb += [
+                    getattr(self.cri, name).eq(shared),
+                    getattr(self.kernel_cri, name).eq(shared)
+                ]
+
+
+
+class SlackMonitor(Module, AutoCSR):
+    """Measures the minimum slack of the output events written to ``cri``,
+    i.e. the difference between their timestamp and the RTIO counter when
+    they are written, for the statistics of the kernel runs.
+
+    ``reset`` restarts the measurement, and ``update`` latches the minimum
+    slack, in machine units, and the number of events since the reset in
+    ``min_slack`` and ``events``.
+    """
+    def __init__(self, tsc, cri):
+        self.reset = CSR()
+        self.update = CSR()
+        self.min_slack = CSRStatus(64)
+        self.events = CSRStatus(32)
+
+        # # #
+
+        slack_max = 2**63 - 1
+
+        # Registered once to ease timing of the 64-bit subtraction.
+        write = Signal()
+        slack = Signal((64, True))
+        self.sync += [
+            write.eq(cri.cmd == commands["write"]),
+            slack.eq(cri.o_timestamp - tsc.full_ts_sys)
+        ]
+
+        min_slack = Signal((64, True), reset=slack_max)
+        events = Signal(32)
+        self.sync += [
+            If(write,
+                If(slack < min_slack, min_slack.eq(slack)),
+                If(events != 2**32 - 1, events.eq(events + 1))
+            ),
+            If(self.reset.re,
+                min_slack.eq(slack_max),
+                events.eq(0)
+            ),
+            If(self.update.re,
+                self.min_slack.status.eq(min_slack),
+                self.events.status.eq(events)
+            )
+        ]
 
 
 class CRIDecoder(Module):
@@ -253,3 +331,86 @@ class RoutingTableAccess(Module, AutoCSR):
             rtp_csr.we.eq(self.hop.re),
             self.hop.w.eq(rtp_csr.dat_r)
         ]
+
+
+class CRIDomainBridge(Module):
+    """Connects a CRI master in the sys domain to a CRI target in the rtio
+    domain, for systems where the RTIO core is synchronous (DRTIO satellites).
+
+    Commands are transferred one at a time. The sys side reports "wait" (for
+    outputs) or "wait for status" (for inputs) until the rtio side has seen
+    the command complete. The payload is not latched: the master must hold it
+    stable until the command has completed, as KernelInitiator does.
+    """
+    def __init__(self):
+        self.sys_cri = Interface()
+        self.rtio_cri = Interface()
+
+        # # #
+
+        cmd = Signal(2)
+        pending = Signal()
+        o_status = Signal(3)
+        i_status = Signal(4)
+        i_data = Signal(32)
+        i_timestamp = Signal(64)
+
+        start = PulseSynchronizer("sys", "rtio")
+        done = PulseSynchronizer("rtio", "sys")
+        self.submodules += start, done
+
+        # sys side
+        self.sync += [
+            If(done.o, pending.eq(0)),
+            If(self.sys_cri.cmd != commands["nop"],
+                pending.eq(1),
+                cmd.eq(self.sys_cri.cmd)
+            )
+        ]
+        self.comb += [
+            start.i.eq(self.sys_cri.cmd != commands["nop"]),
+            If(pending,
+                self.sys_cri.o_status.eq(1),
+                self.sys_cri.i_status.eq(4)
+            ).Else(
+                self.sys_cri.o_status.eq(o_status),
+                self.sys_cri.i_status.eq(i_status)
+            ),
+            self.sys_cri.i_data.eq(i_data),
+            self.sys_cri.i_timestamp.eq(i_timestamp)
+        ]
+
+        # rtio side
+        for name, size, direction in layout:
+            if direction == DIR_M_TO_S and name != "cmd":
+                self.comb += getattr(self.rtio_cri, name).eq(getattr(self.sys_cri, name))
+
+        fsm = ClockDomainsRenamer("rtio")(FSM())
+        self.submodules += fsm
+
+        fsm.act("IDLE",
+            If(start.o, NextState("COMMAND"))
+        )
+        fsm.act("COMMAND",
+            self.rtio_cri.cmd.eq(cmd),
+            NextState("WAIT")
+        )
+        fsm.act("WAIT",
+            If(cmd == commands["read"],
+                If(~self.rtio_cri.i_status[2],
+                    done.i.eq(1),
+                    NextState("IDLE")
+                )
+            ).Else(
+                If(~self.rtio_cri.o_status[0],
+                    done.i.eq(1),
+                    NextState("IDLE")
+                )
+            )
+        )
+        self.sync.rtio += If(done.i,
+            o_status.eq(self.rtio_cri.o_status),
+            i_status.eq(self.rtio_cri.i_status),
+            i_data.eq(self.rtio_cri.i_data),
+            i_timestamp.eq(self.rtio_cri.i_timestamp)
+        )
@@ -151,6 +151,24 @@ record_layout = [
 ]
 
 
+# Compact records have the MSB of their length set. Their channel is taken
+# from a dictionary of the last channel of each slot, which full records
+# update, and their timestamp is relative to that of the previous record.
+# Records may omit the most significant bytes of their data when zero.
+compact_record_layout = [
+    ("length", 8),  # of whole record (header+data), with the MSB set
+    ("slot", 8),
+    ("timestamp_delta", 16),
+    ("address", 8),
+    ("data", 512)  # variable length
+]
+channel_slots = 16
+
+
+def channel_slot(channel):
+    return (channel ^ (channel >> 16)) & (channel_slots - 1)
+
+
 class RecordConverter(Module):
     def __init__(self, stream_slicer):
         self.source = stream.Endpoint(record_layout)
@@ -159,19 +177,47 @@ class RecordConverter(Module):
 
         hdrlen = (layout_len(record_layout) - 512)//8
         record_raw = Record(record_layout)
+        compact_hdrlen = (layout_len(compact_record_layout) - 512)//8
+        compact_raw = Record(compact_record_layout)
+        compact = Signal()
+        length = Signal(7)
+        channels = Array(Signal(24) for _ in range(channel_slots))
+        last_timestamp = Signal(64)
         self.comb += [
             record_raw.raw_bits().eq(stream_slicer.source),
-            self.source.channel.eq(record_raw.channel),
-            self.source.timestamp.eq(record_raw.timestamp),
-            self.source.address.eq(record_raw.address),
-            Case(record_raw.length,
-                {hdrlen+i: self.source.data.eq(record_raw.data[:i*8])
-                 for i in range(1, 512//8+1)}),
+            compact_raw.raw_bits().eq(
+                stream_slicer.source[:layout_len(compact_record_layout)]),
+            compact.eq(record_raw.length[7]),
+            length.eq(record_raw.length[:7]),
+            If(compact,
+                self.source.channel.eq(channels[compact_raw.slot[:log2_int(channel_slots)]]),
+                self.source.timestamp.eq(last_timestamp + compact_raw.timestamp_delta),
+                self.source.address.eq(compact_raw.address),
+                Case(length,
+                    {compact_hdrlen+i: self.source.data.eq(compact_raw.data[:i*8])
+                     for i in range(1, 512//8+1)})
+            ).Else(
+                self.source.channel.eq(record_raw.channel),
+                self.source.timestamp.eq(record_raw.timestamp),
+                self.source.address.eq(record_raw.address),
+                Case(length,
+                    {hdrlen+i: self.source.data.eq(record_raw.data[:i*8])
+                     for i in range(1, 512//8+1)})
+            )
         ]
 
         fsm = FSM(reset_state="FLOWING")
         self.submodules += fsm
 
+        self.sync += [
+            If(fsm.ongoing("FLOWING") & self.source.stb & self.source.ack,
+                last_timestamp.eq(self.source.timestamp),
+                If(~compact,
+                    channels[channel_slot(record_raw.channel)].eq(record_raw.channel)
+                )
+            )
+        ]
+
         fsm.act("FLOWING",
             If(stream_slicer.source_stb,
                 If(record_raw.length == 0,
@@ -181,7 +227,7 @@ class RecordConverter(Module):
                 )
             ),
             If(self.source.ack,
-                stream_slicer.source_consume.eq(record_raw.length)
+                stream_slicer.source_consume.eq(length)
             )
         )
         fsm.act("END_MARKER_FOUND",
@@ -219,18 +265,30 @@ class RecordSlicer(Module):
 class TimeOffset(Module, AutoCSR):
     def __init__(self):
         self.time_offset = CSRStorage(64)
+        self.period = CSRStorage(64)
         self.source = stream.Endpoint(record_layout)
         self.sink = stream.Endpoint(record_layout)
 
+        # start the first pass at time_offset
+        self.load = Signal()
+        # start the next pass one period later
+        self.advance = Signal()
+
         # # #
 
+        offset = Signal(64)
+        self.sync += [
+            If(self.load, offset.eq(self.time_offset.storage)),
+            If(self.advance, offset.eq(offset + self.period.storage))
+        ]
+
         self.sync += [
             If(self.source.ack, self.source.stb.eq(0)),
             If(~self.source.stb,
                 self.sink.payload.connect(self.source.payload,
                                           omit={"timestamp"}),
                 self.source.payload.timestamp.eq(self.sink.payload.timestamp
-                                                 + self.time_offset.storage),
+                                                 + offset),
                 self.source.eop.eq(self.sink.eop),
                 self.source.stb.eq(self.sink.stb)
             )
@@ -326,6 +384,13 @@ class CRIMaster(Module, AutoCSR):
 class DMA(Module):
     def __init__(self, membus):
         self.enable = CSR()
+        # Number of times the trace is played, each pass starting one period
+        # (time_offset.period) after the previous one; 0 plays it until
+        # stopped. Errors end the playback after the current pass.
+        self.passes = CSRStorage(32, reset=1)
+        # ends the playback after the current pass
+        self.stop = CSR()
+        self.passes_done = CSRStatus(32)
 
         flow_enable = Signal()
         self.submodules.dma = DMAReader(membus, flow_enable)
@@ -340,11 +405,26 @@ class DMA(Module):
             self.time_offset.source.connect(self.cri_master.sink)
         ]
 
+        remaining = Signal(32)
+        stopping = Signal()
+        self.sync += [
+            If(self.enable.re,
+                stopping.eq(0)
+            ).Elif(self.stop.re,
+                stopping.eq(1)
+            )
+        ]
+
         fsm = FSM(reset_state="IDLE")
         self.submodules += fsm
 
         fsm.act("IDLE",
-            If(self.enable.re, NextState("FLOWING"))
+            self.time_offset.load.eq(1),
+            If(self.enable.re,
+                NextValue(remaining, self.passes.storage),
+                NextValue(self.passes_done.status, 0),
+                NextState("FLOWING")
+            )
         )
         fsm.act("FLOWING",
             self.enable.w.eq(1),
@@ -366,10 +446,19 @@ class DMA(Module):
         )
         fsm.act("WAIT_CRI_MASTER",
             self.enable.w.eq(1),
-            If(~self.cri_master.busy, NextState("IDLE"))
+            If(~self.cri_master.busy,
+                NextValue(self.passes_done.status, self.passes_done.status + 1),
+                If((remaining != 1) & ~stopping & (self.cri_master.error.w == 0),
+                    If(remaining != 0, NextValue(remaining, remaining - 1)),
+                    self.time_offset.advance.eq(1),
+                    NextState("FLOWING")
+                ).Else(
+                    NextState("IDLE")
+                )
+            )
         )
 
     def get_csrs(self):
-        return ([self.enable] +
+        return ([self.enable, self.passes, self.stop, self.passes_done] +
                 self.dma.get_csrs() + self.time_offset.get_csrs() +
                 self.cri_master.get_csrs())
@@ -0,0 +1,70 @@
+from migen import *
+
+from artiq.coredevice.spi2 import SPI_CONFIG_ADDR, SPI_DATA_ADDR
+from artiq.coredevice.urukul import CS_DDS_MULTI, CS_DDS_CH0
+
+
+_AD9910_REG_PROFILE0 = 0x0e
+_AD9910_REG_PROFILE7 = 0x15
+
+
+class AD9910Monitor(Module):
+    """Follows the writes to the AD9910 profile registers on the SPI bus of
+    an Urukul, and provides the last written frequency, phase and amplitude
+    words of each DDS channel as probes 3*channel (FTW), 3*channel + 1 (POW)
+    and 3*channel + 2 (ASF).
+
+    The registers are not read back from the AD9910: the probes are the
+    values last written by kernels, and miss whatever changed the chip
+    otherwise, such as a reset, an IO update that was never issued, or the
+    profile pins selecting another profile."""
+    def __init__(self, spi_rtlink, nchannels=4):
+        self.probes = [Signal(32) for i in range(3*nchannels)]
+
+        spi_oif = spi_rtlink.o
+
+        cs = Signal(8)
+        length = Signal(8)
+        self.sync.rio_phy += [
+            If(spi_oif.stb & (spi_oif.address == SPI_CONFIG_ADDR),
+                cs.eq(spi_oif.data[24:]),
+                length.eq(spi_oif.data[8:16])
+            )
+        ]
+
+        # A 64-bit register write is an 8-bit instruction transfer followed
+        # by two 32-bit data transfers (see AD9910.write64).
+        instruction = Signal()
+        data_high = Signal()
+        high = Signal(32)
+        update = Signal()
+        self.sync.rio_phy += [
+            update.eq(0),
+            If(spi_oif.stb & (spi_oif.address == SPI_DATA_ADDR),
+                instruction.eq(0),
+                data_high.eq(0),
+                If(length == 8 - 1,
+                    instruction.eq(
+                        (spi_oif.data[24:] >= _AD9910_REG_PROFILE0) &
+                        (spi_oif.data[24:] <= _AD9910_REG_PROFILE7))
+                ).Elif(length == 32 - 1,
+                    If(instruction,
+                        data_high.eq(1),
+                        high.eq(spi_oif.data)
+                    ),
+                    update.eq(data_high)
+                )
+            )
+        ]
+
+        low = Signal(32)
+        self.sync.rio_phy += If(spi_oif.stb, low.eq(spi_oif.data))
+        for i in range(nchannels):
+            ftw, pow_, asf = self.probes[3*i:3*i + 3]
+            self.sync.rio_phy += [
+                If(update & ((cs == CS_DDS_CH0 + i) | (cs == CS_DDS_MULTI)),
+                    ftw.eq(low),
+                    pow_.eq(high[:16]),
+                    asf.eq(high[16:30])
+                )
+            ]
@@ -61,10 +61,23 @@ class Serializer(Module):
 
 
 class Grabber(Module):
+    """Grabber RTIO PHY.
+
+    On the config channel, ROI boundaries are written at addresses
+    ``4*n+k`` (``k`` selecting x0, y0, x1 or y1 of ROI engine ``n``) into
+    a staging set. Writing to :attr:`COMMIT` makes the ROI engines use the
+    staging set from the next time no frame is being received, so that a
+    frame is never processed with a mix of old and new boundaries. Writing
+    with :attr:`READ` set in the address produces an input event with the
+    boundary currently used by the ROI engines."""
+    COMMIT = 0x40
+    READ = 0x80
+
     def __init__(self, pins, roi_engine_count=16, res_width=12, count_shift=0):
+        assert roi_engine_count <= 16
         self.config = rtlink.Interface(
-            rtlink.OInterface(res_width,
-                              bits_for(4*roi_engine_count-1)))
+            rtlink.OInterface(res_width, 8),
+            rtlink.IInterface(res_width, timestamped=False))
         self.gate_data = rtlink.Interface(
             rtlink.OInterface(roi_engine_count),
             rtlink.IInterface(1+ROI.count_len(res_width, count_shift),
@@ -80,6 +93,19 @@ class Grabber(Module):
         self.submodules.serializer = Serializer(self.synchronizer.update, self.synchronizer.counts,
                                                 self.gate_data.i)
 
+        commit_ps = PulseSynchronizer("rtio", "cl")
+        self.submodules += commit_ps
+        self.comb += commit_ps.i.eq(self.config.o.stb &
+                                    (self.config.o.address == self.COMMIT))
+        commit_pending = Signal()
+        commit = Signal()
+        self.comb += commit.eq((commit_ps.o | commit_pending) & ~self.parser.in_frame)
+        self.sync.cl += [
+            If(commit_ps.o, commit_pending.eq(1)),
+            If(commit, commit_pending.eq(0))
+        ]
+
+        active_boundaries = []
         for n, roi_engine in enumerate(self.roi_engines):
             for offset, target in enumerate([roi_engine.cfg.x0, roi_engine.cfg.y0,
                                              roi_engine.cfg.x1, roi_engine.cfg.y1]):
@@ -87,7 +113,20 @@ class Grabber(Module):
                 roi_boundary.attr.add("no_retiming")
                 self.sync.rtio += If(self.config.o.stb & (self.config.o.address == 4*n+offset),
                     roi_boundary.eq(self.config.o.data))
-                self.specials += MultiReg(roi_boundary, target, "cl")
+                staged = Signal.like(target)
+                self.specials += MultiReg(roi_boundary, staged, "cl")
+                self.sync.cl += If(commit, target.eq(staged))
+
+                target.attr.add("no_retiming")
+                active = Signal.like(target)
+                self.specials += MultiReg(target, active, "rtio")
+                active_boundaries.append(active)
+
+        read_address = self.config.o.address[:6]
+        self.sync.rio_phy += [
+            self.config.i.stb.eq(self.config.o.stb & self.config.o.address[7]),
+            self.config.i.data.eq(Array(active_boundaries)[read_address])
+        ]
 
         self.sync.rio += If(self.gate_data.o.stb,
             self.serializer.gate.eq(self.gate_data.o.data))
@@ -0,0 +1,47 @@
+from migen import *
+
+from artiq.coredevice.spi2 import SPI_DATA_ADDR
+
+
+class SamplerMonitor(Module):
+    """Follows the acquisitions of a Sampler, and provides the last sampled
+    ADC code of each channel as probes 0 to 7 and the last PGIA gain setting
+    as probe 8.
+
+    A conversion is started by a CNV pulse, after which the ADC words are
+    read two channels at a time from channel 7 down (see Sampler.sample_mu).
+
+    The gateware does not start conversions of its own: the ADC is only
+    reachable through its RTIO channels, which belong to the kernel, so the
+    probes change only when a kernel samples, and keep their values in
+    between.
+    """
+    def __init__(self, adc_rtlink, pgia_rtlink, cnv_rtlink, nchannels=8):
+        self.probes = [Signal(16) for i in range(nchannels)] + [Signal(16)]
+
+        cnv_oif = cnv_rtlink.o
+        if hasattr(cnv_oif, "address"):
+            ttl_level_adr = cnv_oif.address == 0
+        else:
+            ttl_level_adr = 1
+
+        word = Signal(max=nchannels//2 + 1)
+        words = {
+            i: [self.probes[nchannels - 1 - 2*i].eq(adc_rtlink.i.data[16:32]),
+                self.probes[nchannels - 2 - 2*i].eq(adc_rtlink.i.data[:16])]
+            for i in range(nchannels//2)}
+        self.sync.rio_phy += [
+            If(cnv_oif.stb & ttl_level_adr & cnv_oif.data[0],
+                word.eq(0)
+            ).Elif(adc_rtlink.i.stb & (word != nchannels//2),
+                Case(word, words),
+                word.eq(word + 1)
+            )
+        ]
+
+        pgia_oif = pgia_rtlink.o
+        self.sync.rio_phy += [
+            If(pgia_oif.stb & (pgia_oif.address == SPI_DATA_ADDR),
+                self.probes[nchannels].eq(pgia_oif.data[16:32])
+            )
+        ]
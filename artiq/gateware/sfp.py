@@ -0,0 +1,18 @@
+from migen import *
+from migen.genlib.cdc import MultiReg
+
+from misoc.interconnect.csr import *
+
+
+# TX_DISABLE and LOS lines of the SFP ports wired to the FPGA (Kasli v1).
+# `ctls` maps the index of each SFP port to its sfp_ctl pads; bit i of the
+# CSRs is SFP port i, and reads zero for ports without lines. The
+# transmitters are enabled until the firmware takes over.
+class SFPControl(Module, AutoCSR):
+    def __init__(self, ctls):
+        self.tx_disable = CSRStorage(4)
+        self.los = CSRStatus(4)
+
+        for i, pads in ctls.items():
+            self.comb += pads.tx_disable.eq(self.tx_disable.storage[i])
+            self.specials += MultiReg(pads.los, self.los.status[i])
@@ -6,6 +6,7 @@ from migen import *
 from migen.genlib.resetsync import AsyncResetSynchronizer
 from migen.genlib.cdc import MultiReg
 from migen.genlib.io import DifferentialOutput
+from migen.build.generic_platform import ConstraintError
 
 from misoc.interconnect.csr import *
 from misoc.cores import gpio
@@ -18,6 +19,12 @@ from artiq.gateware.amp import AMPSoC
 from artiq.gateware import rtio
 from artiq.gateware.rtio.phy import ttl_simple, ttl_serdes_7series, edge_counter
 from artiq.gateware import eem
+from artiq.gateware.xadc import XADC
+from artiq.gateware.watchdog import Watchdog
+from artiq.gateware.icap import ICAP
+from artiq.gateware.ethmac_dma import EthmacDMA
+from artiq.gateware.fan import FanController
+from artiq.gateware.sfp import SFPControl
 from artiq.gateware.drtio.transceiver import gtp_7series
 from artiq.gateware.drtio.siphaser import SiPhaser7Series
 from artiq.gateware.drtio.wrpll import WRPLL, DDMTDSamplerGTP
@@ -126,6 +133,10 @@ class StandaloneBase(MiniSoC, AMPSoC):
         AMPSoC.__init__(self)
         add_identifier(self, gateware_identifier_str=gateware_identifier_str)
 
+        self.submodules.ethmac_dma = EthmacDMA()
+        self.add_wb_master(self.ethmac_dma.bus)
+        self.csr_devices.append("ethmac_dma")
+
         if self.platform.hw_rev == "v2.0":
             self.submodules.error_led = gpio.GPIOOut(Cat(
                 self.platform.request("error_led")))
@@ -136,6 +147,23 @@ class StandaloneBase(MiniSoC, AMPSoC):
         self.submodules.i2c = gpio.GPIOTristate([i2c.scl, i2c.sda])
         self.csr_devices.append("i2c")
         self.config["I2C_BUS_COUNT"] = 1
+
+        self.submodules.xadc = XADC(self.clk_freq)
+        self.csr_devices.append("xadc")
+        self.submodules.watchdog = Watchdog(self.clk_freq)
+        self.csr_devices.append("watchdog")
+        self.interrupt_devices.append("watchdog")
+        self.submodules.icap = ICAP()
+        self.csr_devices.append("icap")
+        self.comb += self.icap.reconfigure_golden.eq(self.watchdog.reconfigure)
+
+        try:
+            fan = self.platform.request("fan")
+        except ConstraintError:
+            pass
+        else:
+            self.submodules.fan = FanController(fan, self.clk_freq)
+            self.csr_devices.append("fan")
         self.config["HAS_SI5324"] = None
         self.config["SI5324_SOFT_RESET"] = None
 
@@ -146,13 +174,19 @@ class StandaloneBase(MiniSoC, AMPSoC):
         self.submodules.rtio_tsc = rtio.TSC("async", glbl_fine_ts_width=3)
         self.submodules.rtio_core = rtio.Core(self.rtio_tsc, rtio_channels)
         self.csr_devices.append("rtio_core")
-        self.submodules.rtio = rtio.KernelInitiator(self.rtio_tsc)
+        self.submodules.rtio_nrt = rtio.NRTInitiator(self.rtio_tsc)
+        self.csr_devices.append("rtio_nrt")
+        self.submodules.rtio = rtio.KernelInitiator(self.rtio_tsc,
+                                                    cri=self.rtio_nrt.kernel_cri)
+        self.submodules.rtio_slack = rtio.SlackMonitor(self.rtio_tsc,
+                                                       self.rtio_nrt.kernel_cri)
+        self.csr_devices.append("rtio_slack")
         self.submodules.rtio_dma = ClockDomainsRenamer("sys_kernel")(
             rtio.DMA(self.get_native_sdram_if()))
         self.register_kernel_cpu_csrdevice("rtio")
         self.register_kernel_cpu_csrdevice("rtio_dma")
         self.submodules.cri_con = rtio.CRIInterconnectShared(
-            [self.rtio.cri, self.rtio_dma.cri],
+            [self.rtio_nrt.shared_cri, self.rtio_dma.cri],
             [self.rtio_core.cri])
         self.register_kernel_cpu_csrdevice("cri_con")
 
@@ -308,6 +342,10 @@ class MasterBase(MiniSoC, AMPSoC):
         AMPSoC.__init__(self)
         add_identifier(self, gateware_identifier_str=gateware_identifier_str)
 
+        self.submodules.ethmac_dma = EthmacDMA()
+        self.add_wb_master(self.ethmac_dma.bus)
+        self.csr_devices.append("ethmac_dma")
+
         platform = self.platform
 
         if platform.hw_rev == "v2.0":
@@ -317,6 +355,23 @@ class MasterBase(MiniSoC, AMPSoC):
         self.submodules.i2c = gpio.GPIOTristate([i2c.scl, i2c.sda])
         self.csr_devices.append("i2c")
         self.config["I2C_BUS_COUNT"] = 1
+
+        self.submodules.xadc = XADC(self.clk_freq)
+        self.csr_devices.append("xadc")
+        self.submodules.watchdog = Watchdog(self.clk_freq)
+        self.csr_devices.append("watchdog")
+        self.interrupt_devices.append("watchdog")
+        self.submodules.icap = ICAP()
+        self.csr_devices.append("icap")
+        self.comb += self.icap.reconfigure_golden.eq(self.watchdog.reconfigure)
+
+        try:
+            fan = self.platform.request("fan")
+        except ConstraintError:
+            pass
+        else:
+            self.submodules.fan = FanController(fan, self.clk_freq)
+            self.csr_devices.append("fan")
         self.config["HAS_SI5324"] = None
         self.config["SI5324_SOFT_RESET"] = None
         self.config["SI5324_AS_SYNTHESIZER"] = None
@@ -325,13 +380,16 @@ class MasterBase(MiniSoC, AMPSoC):
         drtio_data_pads = []
         if enable_sata:
             drtio_data_pads.append(platform.request("sata"))
+            self.config["HAS_DRTIO_SATA"] = None
         drtio_data_pads += [platform.request("sfp", i) for i in range(1, 3)]
         if self.platform.hw_rev == "v2.0":
             drtio_data_pads.append(platform.request("sfp", 3))
 
         if self.platform.hw_rev in ("v1.0", "v1.1"):
             sfp_ctls = [platform.request("sfp_ctl", i) for i in range(1, 3)]
-            self.comb += [sc.tx_disable.eq(0) for sc in sfp_ctls]
+            self.submodules.sfp_ctl = SFPControl(dict(zip(range(1, 3), sfp_ctls)))
+            self.csr_devices.append("sfp_ctl")
+            self.config["SFP_CTL_PORTS"] = 0b110
 
         self.submodules.drtio_transceiver = gtp_7series.GTP(
             qpll_channel=self.drtio_qpll_channel,
@@ -339,6 +397,7 @@ class MasterBase(MiniSoC, AMPSoC):
             sys_clk_freq=self.clk_freq,
             rtio_clk_freq=rtio_clk_freq)
         self.csr_devices.append("drtio_transceiver")
+        self.config["HAS_DRTIO_EYE_SCAN"] = None
         self.sync += self.disable_cdr_clk_ibuf.eq(
             ~self.drtio_transceiver.stable_clkin.storage)
 
@@ -413,13 +472,19 @@ class MasterBase(MiniSoC, AMPSoC):
         self.submodules.rtio_core = rtio.Core(self.rtio_tsc, rtio_channels)
         self.csr_devices.append("rtio_core")
 
-        self.submodules.rtio = rtio.KernelInitiator(self.rtio_tsc)
+        self.submodules.rtio_nrt = rtio.NRTInitiator(self.rtio_tsc)
+        self.csr_devices.append("rtio_nrt")
+        self.submodules.rtio = rtio.KernelInitiator(self.rtio_tsc,
+                                                    cri=self.rtio_nrt.kernel_cri)
+        self.submodules.rtio_slack = rtio.SlackMonitor(self.rtio_tsc,
+                                                       self.rtio_nrt.kernel_cri)
+        self.csr_devices.append("rtio_slack")
         self.submodules.rtio_dma = ClockDomainsRenamer("sys_kernel")(
             rtio.DMA(self.get_native_sdram_if()))
         self.register_kernel_cpu_csrdevice("rtio")
         self.register_kernel_cpu_csrdevice("rtio_dma")
         self.submodules.cri_con = rtio.CRIInterconnectShared(
-            [self.rtio.cri, self.rtio_dma.cri],
+            [self.rtio_nrt.shared_cri, self.rtio_dma.cri],
             [self.rtio_core.cri] + self.drtio_cri,
             enable_routing=True)
         self.register_kernel_cpu_csrdevice("cri_con")
@@ -466,18 +531,23 @@ class MasterBase(MiniSoC, AMPSoC):
         self.drtio_qpll_channel, self.ethphy_qpll_channel = qpll.channels
 
 
-class SatelliteBase(BaseSoC):
+class SatelliteBase(BaseSoC, AMPSoC):
     mem_map = {
-        "drtioaux": 0x50000000,
+        "rtio":          0x20000000,
+        "drtioaux":      0x50000000,
+        "mailbox":       0x70000000
     }
     mem_map.update(BaseSoC.mem_map)
 
-    def __init__(self, rtio_clk_freq=125e6, enable_sata=False, *, with_wrpll=False, gateware_identifier_str=None, **kwargs):
+    def __init__(self, rtio_clk_freq=125e6, enable_sata=False, *, with_wrpll=False, with_kernel_cpu=False, gateware_identifier_str=None, **kwargs):
         BaseSoC.__init__(self,
                  cpu_type="or1k",
                  sdram_controller_type="minicon",
                  l2_size=128*1024,
                  **kwargs)
+        # The kernel CPU runs the idle kernel while there is no master.
+        if with_kernel_cpu:
+            AMPSoC.__init__(self)
         add_identifier(self, gateware_identifier_str=gateware_identifier_str)
 
         platform = self.platform
@@ -510,7 +580,9 @@ class SatelliteBase(BaseSoC):
 
         if self.platform.hw_rev in ("v1.0", "v1.1"):
             sfp_ctls = [platform.request("sfp_ctl", i) for i in range(3)]
-            self.comb += [sc.tx_disable.eq(0) for sc in sfp_ctls]
+            self.submodules.sfp_ctl = SFPControl(dict(enumerate(sfp_ctls)))
+            self.csr_devices.append("sfp_ctl")
+            self.config["SFP_CTL_PORTS"] = 0b111
         self.submodules.drtio_transceiver = gtp_7series.GTP(
             qpll_channel=qpll.channels[0],
             data_pads=drtio_data_pads,
@@ -639,8 +711,18 @@ class SatelliteBase(BaseSoC):
 
         self.submodules.local_io = SyncRTIO(self.rtio_tsc, rtio_channels)
         self.comb += self.drtiosat.async_errors.eq(self.local_io.async_errors)
+
+        cri_masters = [self.drtiosat.cri]
+        if hasattr(self, "kernel_cpu"):
+            # Local kernel initiator, for the idle kernel when there is no master
+            self.submodules.rtio_bridge = rtio.CRIDomainBridge()
+            self.submodules.rtio = rtio.KernelInitiator(self.rtio_tsc,
+                                                        cri=self.rtio_bridge.sys_cri)
+            self.register_kernel_cpu_csrdevice("rtio")
+            cri_masters.append(self.rtio_bridge.rtio_cri)
+
         self.submodules.cri_con = rtio.CRIInterconnectShared(
-            [self.drtiosat.cri],
+            cri_masters,
             [self.local_io.cri] + self.drtio_cri,
             mode="sync", enable_routing=True)
         self.csr_devices.append("cri_con")
@@ -701,6 +783,10 @@ def main():
                         help="variant: {} (default: %(default)s)".format(
                             "/".join(sorted(VARIANTS.keys()))))
     parser.add_argument("--with-wrpll", default=False, action="store_true")
+    parser.add_argument("--with-kernel-cpu", default=False, action="store_true",
+                        help="satellite: add a kernel CPU to run an idle kernel "
+                             "without a master")
+    build_artiq_soc_args(parser)
     parser.add_argument("--gateware-identifier-str", default=None,
                         help="Override ROM identifier")
     args = parser.parse_args()
@@ -708,6 +794,8 @@ def main():
     argdict = dict()
     if args.with_wrpll:
         argdict["with_wrpll"] = True
+    if args.with_kernel_cpu:
+        argdict["with_kernel_cpu"] = True
     argdict["gateware_identifier_str"] = args.gateware_identifier_str
 
     variant = args.variant.lower()
@@ -717,7 +805,8 @@ def main():
         raise SystemExit("Invalid variant (-V/--variant)")
 
     soc = cls(**soc_kasli_argdict(args), **argdict)
-    build_artiq_soc(soc, builder_argdict(args))
+    build_artiq_soc(soc, builder_argdict(args),
+                    fault_injection=args.with_fault_injection)
 
 
 if __name__ == "__main__":
@@ -136,10 +136,14 @@ def main():
     builder_args(parser)
     soc_kasli_args(parser)
     parser.set_defaults(output_dir="artiq_kasli")
+    build_artiq_soc_args(parser)
     parser.add_argument("description", metavar="DESCRIPTION",
                         help="JSON system description file")
     parser.add_argument("--gateware-identifier-str", default=None,
                         help="Override ROM identifier")
+    parser.add_argument("--with-kernel-cpu", default=False, action="store_true",
+                        help="satellite: add a kernel CPU to run an idle kernel "
+                             "without a master")
     args = parser.parse_args()
     description = jsondesc.load(args.description)
 
@@ -160,9 +164,16 @@ def main():
     else:
         raise ValueError("Invalid base")
 
-    soc = cls(description, gateware_identifier_str=args.gateware_identifier_str, **soc_kasli_argdict(args))
+    argdict = dict()
+    if args.with_kernel_cpu:
+        if cls is not GenericSatellite:
+            raise ValueError("Only satellites can have a kernel CPU for an idle kernel")
+        argdict["with_kernel_cpu"] = True
+
+    soc = cls(description, gateware_identifier_str=args.gateware_identifier_str, **soc_kasli_argdict(args), **argdict)
     args.variant = description["variant"]
-    build_artiq_soc(soc, builder_argdict(args))
+    build_artiq_soc(soc, builder_argdict(args),
+                    fault_injection=args.with_fault_injection)
 
 
 if __name__ == "__main__":
@@ -16,6 +16,11 @@ from misoc.integration.builder import builder_args, builder_argdict
 
 from artiq.gateware.amp import AMPSoC
 from artiq.gateware import rtio, nist_clock, nist_qc2
+from artiq.gateware.xadc import XADC
+from artiq.gateware.watchdog import Watchdog
+from artiq.gateware.icap import ICAP
+from artiq.gateware.fan import FanController
+from artiq.gateware.ethmac_dma import EthmacDMA
 from artiq.gateware.rtio.phy import ttl_simple, ttl_serdes_7series, dds, spi2
 from artiq.build_soc import *
 
@@ -131,6 +136,10 @@ class _StandaloneBase(MiniSoC, AMPSoC):
         AMPSoC.__init__(self)
         add_identifier(self, gateware_identifier_str=gateware_identifier_str)
 
+        self.submodules.ethmac_dma = EthmacDMA()
+        self.add_wb_master(self.ethmac_dma.bus)
+        self.csr_devices.append("ethmac_dma")
+
         if isinstance(self.platform.toolchain, XilinxVivadoToolchain):
             self.platform.toolchain.bitstream_commands.extend([
                 "set_property BITSTREAM.GENERAL.COMPRESS True [current_design]",
@@ -156,6 +165,25 @@ class _StandaloneBase(MiniSoC, AMPSoC):
         self.csr_devices.append("i2c")
         self.config["I2C_BUS_COUNT"] = 1
 
+        self.submodules.xadc = XADC(self.clk_freq)
+        self.csr_devices.append("xadc")
+        self.submodules.watchdog = Watchdog(self.clk_freq)
+        self.csr_devices.append("watchdog")
+        self.interrupt_devices.append("watchdog")
+        # Only for the watchdog to reconfigure the FPGA from flash: there is
+        # no room in the flash for a gateware update slot, so the ICAP has
+        # no CSRs.
+        self.submodules.icap = ICAP()
+        self.comb += self.icap.reconfigure_golden.eq(self.watchdog.reconfigure)
+
+        try:
+            fan = self.platform.request("fan")
+        except ConstraintError:
+            pass
+        else:
+            self.submodules.fan = FanController(fan, self.clk_freq)
+            self.csr_devices.append("fan")
+
         self.config["HAS_DDS"] = None
 
     def add_rtio(self, rtio_channels):
@@ -412,6 +440,7 @@ def main():
     builder_args(parser)
     soc_kc705_args(parser)
     parser.set_defaults(output_dir="artiq_kc705")
+    build_artiq_soc_args(parser)
     parser.add_argument("-V", "--variant", default="nist_clock",
                         help="variant: "
                              "nist_clock/nist_qc2/sma_spi "
@@ -427,7 +456,8 @@ def main():
         raise SystemExit("Invalid variant (-V/--variant)")
 
     soc = cls(gateware_identifier_str=args.gateware_identifier_str, **soc_kc705_argdict(args))
-    build_artiq_soc(soc, builder_argdict(args))
+    build_artiq_soc(soc, builder_argdict(args),
+                    fault_injection=args.with_fault_injection)
 
 
 if __name__ == "__main__":
@@ -14,6 +14,7 @@ from misoc.targets.metlino import *
 from artiq.gateware.amp import AMPSoC
 from artiq.gateware import eem
 from artiq.gateware import rtio
+from artiq.gateware.ethmac_dma import EthmacDMA
 from artiq.gateware.rtio.phy import ttl_simple, ttl_serdes_ultrascale
 from artiq.gateware.drtio.transceiver import gth_ultrascale
 from artiq.gateware.drtio import *
@@ -53,6 +54,10 @@ class Master(MiniSoC, AMPSoC):
         AMPSoC.__init__(self)
         add_identifier(self, gateware_identifier_str=gateware_identifier_str)
 
+        self.submodules.ethmac_dma = EthmacDMA()
+        self.add_wb_master(self.ethmac_dma.bus)
+        self.csr_devices.append("ethmac_dma")
+
         platform = self.platform
         rtio_clk_freq = 150e6
 
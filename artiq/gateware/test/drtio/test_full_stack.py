@@ -310,3 +310,28 @@ class TestFullStack(unittest.TestCase):
             self.assertEqual((yield dut.master.rt_packet.packet_cnt_rx), 1)
 
         run_simulation(dut, test(), self.clocks)
+
+    def test_rtt(self):
+        dut = DUT(2)
+        csrs = dut.master.rt_controller.csrs
+
+        def test():
+            while not (yield from dut.master.link_layer.rx_up.read()):
+                yield
+
+            rtts = []
+            for i in range(3):
+                yield from csrs.echo.write(1)
+                yield
+                while (yield from csrs.echo.read()):
+                    yield
+                for _ in range(4):
+                    yield
+                rtts.append((yield from csrs.rtt.read()))
+
+            self.assertGreater(min(rtts), 0)
+            self.assertLess(max(rtts), 2**16 - 1)
+            # the satellite replies with a fixed latency
+            self.assertLessEqual(max(rtts) - min(rtts), 1)
+
+        run_simulation(dut, test(), self.clocks)
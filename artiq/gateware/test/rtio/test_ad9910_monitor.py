@@ -0,0 +1,63 @@
+import unittest
+
+from migen import *
+
+from artiq.coredevice.spi2 import SPI_CONFIG_ADDR, SPI_DATA_ADDR, SPI_END
+from artiq.coredevice.urukul import CS_DDS_MULTI, CS_DDS_CH0
+from artiq.gateware.rtio import rtlink
+from artiq.gateware.rtio.phy.ad9910_monitor import AD9910Monitor
+
+
+class TB(Module):
+    def __init__(self):
+        self.rtlink = rtlink.Interface(rtlink.OInterface(32, address_width=1))
+        self.submodules.dut = ClockDomainsRenamer({"rio_phy": "sys"})(
+            AD9910Monitor(self.rtlink))
+
+    def output(self, address, data):
+        yield self.rtlink.o.address.eq(address)
+        yield self.rtlink.o.data.eq(data)
+        yield self.rtlink.o.stb.eq(1)
+        yield
+        yield self.rtlink.o.stb.eq(0)
+        yield
+
+    def set_config(self, flags, length, cs):
+        yield from self.output(SPI_CONFIG_ADDR,
+                               flags | ((length - 1) << 8) | (cs << 24))
+
+    # Same transfers as AD9910.write64.
+    def write64(self, cs, addr, data_high, data_low):
+        yield from self.set_config(0, 8, cs)
+        yield from self.output(SPI_DATA_ADDR, addr << 24)
+        yield from self.set_config(0, 32, cs)
+        yield from self.output(SPI_DATA_ADDR, data_high)
+        yield from self.set_config(SPI_END, 32, cs)
+        yield from self.output(SPI_DATA_ADDR, data_low)
+        yield
+
+    def read_channel(self, channel):
+        return [(yield probe) for probe in self.dut.probes[3*channel:3*channel + 3]]
+
+
+class TestAD9910Monitor(unittest.TestCase):
+    def test_profile_write(self):
+        tb = TB()
+
+        def gen():
+            # Profile 7 of channel 1: ASF, POW, FTW.
+            yield from tb.write64(CS_DDS_CH0 + 1, 0x15, (0x1234 << 16) | 0x5678, 0x9abcdef0)
+            self.assertEqual((yield from tb.read_channel(1)), [0x9abcdef0, 0x5678, 0x1234])
+            for channel in 0, 2, 3:
+                self.assertEqual((yield from tb.read_channel(channel)), [0, 0, 0])
+
+            # Other registers are ignored.
+            yield from tb.write64(CS_DDS_CH0 + 1, 0x0d, 0xffffffff, 0xffffffff)
+            self.assertEqual((yield from tb.read_channel(1)), [0x9abcdef0, 0x5678, 0x1234])
+
+            # Writes to all channels at once.
+            yield from tb.write64(CS_DDS_MULTI, 0x0e, (0x3fff << 16) | 0x8000, 0x40000000)
+            for channel in range(4):
+                self.assertEqual((yield from tb.read_channel(channel)), [0x40000000, 0x8000, 0x3fff])
+
+        run_simulation(tb, gen())
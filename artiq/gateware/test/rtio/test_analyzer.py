@@ -0,0 +1,169 @@
+import unittest
+
+from migen import *
+from migen.sim import passive
+
+from artiq.coredevice.comm_analyzer import MessageType, TriggerMode
+from artiq.gateware.rtio.analyzer import MessageEncoder
+from artiq.gateware.rtio.cri import Interface, commands
+
+
+class _TSC:
+    def __init__(self):
+        self.full_ts_cri = Signal(64)
+
+
+class TB(Module):
+    def __init__(self):
+        self.enable = Signal()
+        self.cri = Interface()
+        self.submodules.dut = MessageEncoder(_TSC(), self.cri, self.enable)
+        self.messages = []
+
+    def write(self, channel, underflow=False):
+        yield self.cri.chan_sel.eq(channel)
+        yield self.cri.cmd.eq(commands["write"])
+        yield
+        yield self.cri.cmd.eq(commands["nop"])
+        if underflow:
+            yield self.cri.o_status.eq(0b010)
+        yield
+        yield self.cri.o_status.eq(0)
+        yield
+
+    def start(self):
+        yield self.dut.source.ack.eq(1)
+        yield self.enable.eq(1)
+        yield
+        yield
+
+    def stop(self):
+        yield self.enable.eq(0)
+        for _ in range(4):
+            yield
+
+    def take_messages(self):
+        messages, self.messages = self.messages, []
+        return messages
+
+    @passive
+    def collect(self):
+        while True:
+            if (yield self.dut.source.stb):
+                data = yield self.dut.source.data
+                self.messages.append((MessageType(data & 0b11),
+                                      (data >> 2) & (2**30 - 1)))
+            yield
+
+
+def output(channel):
+    return MessageType.output, channel
+
+
+def exception(channel):
+    return MessageType.exception, channel
+
+
+stopped = MessageType.stopped, 0
+
+
+class TestTrigger(unittest.TestCase):
+    def test_channel(self):
+        tb = TB()
+        dut = tb.dut
+
+        def gen():
+            yield dut.trigger_start_mode.storage.eq(TriggerMode.channel.value)
+            yield dut.trigger_start_channel.storage.eq(2)
+            yield dut.trigger_stop_mode.storage.eq(TriggerMode.channel.value)
+            yield dut.trigger_stop_channel.storage.eq(3)
+            yield from tb.start()
+            for channel in 1, 3, 2, 1, 3, 2, 1:
+                yield from tb.write(channel)
+            self.assertEqual((yield dut.trigger_state.status), 0b11)
+            yield from tb.stop()
+            self.assertEqual(tb.take_messages(),
+                             [output(2), output(1), output(3), stopped])
+
+            # Enabling the analyzer again rearms the triggers.
+            yield from tb.start()
+            self.assertEqual((yield dut.trigger_state.status), 0b00)
+            for channel in 1, 2, 1:
+                yield from tb.write(channel)
+            self.assertEqual((yield dut.trigger_state.status), 0b01)
+            yield from tb.stop()
+            self.assertEqual(tb.take_messages(),
+                             [output(2), output(1), stopped])
+
+        run_simulation(tb, [gen(), tb.collect()])
+
+    def test_exception(self):
+        tb = TB()
+        dut = tb.dut
+
+        def gen():
+            yield dut.trigger_start_mode.storage.eq(TriggerMode.exception.value)
+            yield from tb.start()
+            yield from tb.write(1)
+            yield from tb.write(4, underflow=True)
+            yield from tb.write(1)
+            yield from tb.stop()
+            self.assertEqual(tb.take_messages(),
+                             [exception(4), output(1), stopped])
+
+            yield dut.trigger_start_mode.storage.eq(TriggerMode.none.value)
+            yield dut.trigger_stop_mode.storage.eq(TriggerMode.exception.value)
+            yield from tb.start()
+            yield from tb.write(1)
+            yield from tb.write(4, underflow=True)
+            yield from tb.write(1)
+            yield from tb.stop()
+            self.assertEqual(tb.take_messages(),
+                             [output(1), output(4), exception(4), stopped])
+
+        run_simulation(tb, [gen(), tb.collect()])
+
+
+class TestFilter(unittest.TestCase):
+    def test_filter(self):
+        tb = TB()
+        dut = tb.dut
+
+        def set_entry(index, channel, valid):
+            yield dut.filter_index.storage.eq(index)
+            yield dut.filter_channel.storage.eq(channel)
+            yield dut.filter_valid.storage.eq(valid)
+            yield dut.filter_write.re.eq(1)
+            yield
+            yield dut.filter_write.re.eq(0)
+            yield
+
+        def record():
+            yield from tb.start()
+            for channel in 1, 2, 3:
+                yield from tb.write(channel)
+            yield from tb.write(1, underflow=True)
+            yield from tb.stop()
+            return tb.take_messages()
+
+        def gen():
+            # An empty exclusion filter records everything.
+            self.assertEqual((yield from record()),
+                             [output(1), output(2), output(3), output(1),
+                              exception(1), stopped])
+
+            yield from set_entry(0, 2, 1)
+            yield from set_entry(5, 3, 1)
+            self.assertEqual((yield from record()),
+                             [output(1), output(1), exception(1), stopped])
+
+            # Exceptions are recorded even for channels left out.
+            yield dut.filter_exclude.storage.eq(0)
+            self.assertEqual((yield from record()),
+                             [output(2), output(3), exception(1), stopped])
+
+            yield from set_entry(5, 3, 0)
+            self.assertEqual((yield from record()),
+                             [output(2), exception(1), stopped])
+
+        run_simulation(tb, [gen(), tb.collect()])
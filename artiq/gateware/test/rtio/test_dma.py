@@ -31,6 +31,28 @@ def encode_record(channel, timestamp, address, data):
     return encode_n(len(r)+1, 1, 1) + r
 
 
+def encode_compact_record(state, channel, timestamp, address, data):
+    channels, last_timestamp = state
+    slot = dma.channel_slot(channel)
+    r = []
+    if (channels.get(slot) == channel and last_timestamp is not None
+            and 0 <= timestamp - last_timestamp <= 0xffff):
+        r += encode_n(slot, 1, 1)
+        r += encode_n(timestamp - last_timestamp, 2, 2)
+        r += encode_n(address, 1, 1)
+        r += encode_n(data, 0, 64)
+        r = encode_n(0x80 | (len(r)+1), 1, 1) + r
+    else:
+        r += encode_n(channel, 3, 3)
+        r += encode_n(timestamp, 8, 8)
+        r += encode_n(address, 1, 1)
+        r += encode_n(data, 0, 64)
+        r = encode_n(len(r)+1, 1, 1) + r
+        channels[slot] = channel
+    state[1] = timestamp
+    return r
+
+
 def pack(x, size):
     r = []
     for i in range((len(x)+size-1)//size):
@@ -51,8 +73,18 @@ def encode_sequence(writes, ws):
     return pack(sequence, ws)
 
 
-def do_dma(dut, address):
+def encode_compact_sequence(writes, ws):
+    state = [dict(), None]
+    sequence = [b for write in writes
+                for b in encode_compact_record(state, *write)]
+    sequence.append(0)
+    return pack(sequence, ws)
+
+
+def do_dma(dut, address, passes=1, period=0):
     yield from dut.dma.base_address.write(address)
+    yield from dut.passes.write(passes)
+    yield from dut.time_offset.period.write(period)
     yield from dut.enable.write(1)
     yield
     while ((yield from dut.enable.read())):
@@ -80,6 +112,20 @@ test_writes2 = [
 ]
 
 
+# slots of channels 0x10 and 0x20 collide
+test_writes_compact = [
+    (0x10, 0x10000, 0x00, 0x01),
+    (0x11, 0x10008, 0x00, 0x01),
+    (0x10, 0x10010, 0x00, 0x00),
+    (0x11, 0x10020, 0x01, 0x1234),
+    (0x10, 0x30000, 0x00, 0x01),
+    (0x20, 0x30010, 0x00, 0x01),
+    (0x10, 0x30020, 0x00, 0x00),
+    (0x20, 0x30030, 0x02, 0x77777788),
+    (0x20, 0x30030, 0x00, 0x00),
+]
+
+
 prng = random.Random(0)
 
 
@@ -100,6 +146,16 @@ class TB(Module):
         self.submodules.dut = dma.DMA(bus)
 
 
+class CompactTB(Module):
+    def __init__(self, ws):
+        sequence = encode_compact_sequence(test_writes_compact, ws)
+
+        bus = wishbone.Interface(ws*8)
+        self.submodules.memory = wishbone.SRAM(
+            256, init=sequence, bus=bus)
+        self.submodules.dut = dma.DMA(bus)
+
+
 test_writes_full_stack = [
     (0, 32, 0, 1),
     (1, 40, 0, 1),
@@ -133,6 +189,27 @@ class FullStackTB(Module):
 
 
 class TestDMA(unittest.TestCase):
+    def rtio_sim(self, tb, received):
+        dut_cri = tb.dut.cri
+        while True:
+            cmd = yield dut_cri.cmd
+            if cmd == cri.commands["nop"]:
+                pass
+            elif cmd == cri.commands["write"]:
+                channel = yield dut_cri.chan_sel
+                timestamp = yield dut_cri.o_timestamp
+                address = yield dut_cri.o_address
+                data = yield dut_cri.o_data
+                received.append((channel, timestamp, address, data))
+
+                yield dut_cri.o_status.eq(1)
+                for i in range(prng.randrange(10)):
+                    yield
+                yield dut_cri.o_status.eq(0)
+            else:
+                self.fail("unexpected RTIO command")
+            yield
+
     def test_dma_noerror(self):
         tb = TB(64)
 
@@ -143,29 +220,68 @@ class TestDMA(unittest.TestCase):
         received = []
         @passive
         def rtio_sim():
-            dut_cri = tb.dut.cri
-            while True:
-                cmd = yield dut_cri.cmd
-                if cmd == cri.commands["nop"]:
-                    pass
-                elif cmd == cri.commands["write"]:
-                    channel = yield dut_cri.chan_sel
-                    timestamp = yield dut_cri.o_timestamp
-                    address = yield dut_cri.o_address
-                    data = yield dut_cri.o_data
-                    received.append((channel, timestamp, address, data))
-
-                    yield dut_cri.o_status.eq(1)
-                    for i in range(prng.randrange(10)):
-                        yield
-                    yield dut_cri.o_status.eq(0)
-                else:
-                    self.fail("unexpected RTIO command")
-                yield
+            yield from self.rtio_sim(tb, received)
 
         run_simulation(tb, [do_writes(), rtio_sim()])
         self.assertEqual(received, test_writes1 + test_writes2)
 
+    def test_dma_compact(self):
+        tb = CompactTB(64)
+
+        received = []
+        @passive
+        def rtio_sim():
+            yield from self.rtio_sim(tb, received)
+
+        run_simulation(tb, [do_dma(tb.dut, 0, passes=2), rtio_sim()])
+        self.assertEqual(received, 2*test_writes_compact)
+
+    def test_dma_repeat(self):
+        tb = TB(64)
+        period = 0x1000
+
+        def do_writes():
+            yield from do_dma(tb.dut, 512, passes=3, period=period)
+            self.assertEqual((yield from tb.dut.passes_done.read()), 3)
+
+        received = []
+        @passive
+        def rtio_sim():
+            yield from self.rtio_sim(tb, received)
+
+        run_simulation(tb, [do_writes(), rtio_sim()])
+        self.assertEqual(received,
+            [(channel, timestamp + i*period, address, data)
+             for i in range(3)
+             for channel, timestamp, address, data in test_writes2])
+
+    def test_dma_stop(self):
+        tb = TB(64)
+
+        def do_writes():
+            yield from tb.dut.dma.base_address.write(512)
+            yield from tb.dut.passes.write(0)
+            yield from tb.dut.time_offset.period.write(0x1000)
+            yield from tb.dut.enable.write(1)
+            for _ in range(500):
+                yield
+            yield from tb.dut.stop.write(1)
+            while ((yield from tb.dut.enable.read())):
+                yield
+
+        received = []
+        @passive
+        def rtio_sim():
+            yield from self.rtio_sim(tb, received)
+
+        run_simulation(tb, [do_writes(), rtio_sim()])
+        passes = len(received)//len(test_writes2)
+        self.assertGreater(passes, 1)
+        self.assertEqual(received,
+            [(channel, timestamp + i*0x1000, address, data)
+             for i in range(passes)
+             for channel, timestamp, address, data in test_writes2])
+
     def test_full_stack(self):
         tb = FullStackTB(64)
 
@@ -0,0 +1,34 @@
+import unittest
+
+from migen import *
+
+from artiq.gateware.rtio.cri import NRTInitiator, commands
+
+
+class _TSC:
+    def __init__(self):
+        self.full_ts_sys = Signal(64)
+
+
+class TestNRTInitiator(unittest.TestCase):
+    def test_switch(self):
+        dut = NRTInitiator(_TSC())
+
+        def gen():
+            yield dut.kernel_cri.chan_sel.eq(0x12)
+            yield dut.kernel_cri.cmd.eq(commands["write"])
+            yield dut.target.storage.eq(0x3400)
+            yield dut.shared_cri.o_status.eq(2)
+            yield
+            self.assertEqual((yield dut.shared_cri.chan_sel), 0x12)
+            self.assertEqual((yield dut.shared_cri.cmd), commands["write"])
+            self.assertEqual((yield dut.kernel_cri.o_status), 2)
+            self.assertEqual((yield dut.o_status.status), 2)
+
+            yield dut.enable.storage.eq(1)
+            yield
+            self.assertEqual((yield dut.shared_cri.chan_sel), 0x34)
+            self.assertEqual((yield dut.shared_cri.cmd), commands["nop"])
+            self.assertEqual((yield dut.kernel_cri.o_status), 2)
+
+        run_simulation(dut, gen())
@@ -0,0 +1,68 @@
+import unittest
+
+from migen import *
+
+from artiq.coredevice.spi2 import SPI_DATA_ADDR
+from artiq.gateware.rtio import rtlink
+from artiq.gateware.rtio.phy.sampler_monitor import SamplerMonitor
+
+
+class TB(Module):
+    def __init__(self):
+        self.adc = rtlink.Interface(rtlink.OInterface(32, address_width=1),
+                                    rtlink.IInterface(32))
+        self.pgia = rtlink.Interface(rtlink.OInterface(32, address_width=1))
+        self.cnv = rtlink.Interface(rtlink.OInterface(1))
+        self.submodules.dut = ClockDomainsRenamer({"rio_phy": "sys"})(
+            SamplerMonitor(self.adc, self.pgia, self.cnv))
+
+    def pulse(self, stb, *assignments):
+        for assignment in assignments:
+            yield assignment
+        yield stb.eq(1)
+        yield
+        yield stb.eq(0)
+        yield
+
+    # Same transfers as Sampler.sample_mu.
+    def sample(self, words):
+        yield from self.pulse(self.cnv.o.stb, self.cnv.o.data.eq(1))
+        yield from self.pulse(self.cnv.o.stb, self.cnv.o.data.eq(0))
+        for word in words:
+            yield from self.pulse(self.adc.i.stb, self.adc.i.data.eq(word))
+        yield
+
+    def set_gain(self, gains):
+        yield from self.pulse(self.pgia.o.stb,
+                              self.pgia.o.address.eq(SPI_DATA_ADDR),
+                              self.pgia.o.data.eq(gains << 16))
+        yield
+
+    def read_probes(self):
+        return [(yield probe) for probe in self.dut.probes]
+
+
+class TestSamplerMonitor(unittest.TestCase):
+    def test_acquisition(self):
+        tb = TB()
+
+        def gen():
+            yield from tb.sample([0x00070006, 0x00050004, 0x00030002, 0x00010000])
+            self.assertEqual((yield from tb.read_probes()), list(range(8)) + [0])
+
+            # Only the channels read since the last CNV pulse change.
+            yield from tb.sample([0x00170016, 0x00150014])
+            self.assertEqual((yield from tb.read_probes()),
+                             [0, 1, 2, 3, 0x14, 0x15, 0x16, 0x17, 0])
+
+            # Input words beyond the last channel are ignored.
+            yield from tb.sample([0x00270026, 0x00250024, 0x00230022, 0x00210020,
+                                  0xffffffff])
+            self.assertEqual((yield from tb.read_probes()),
+                             list(range(0x20, 0x28)) + [0])
+
+            yield from tb.set_gain(0xa5c3)
+            self.assertEqual((yield from tb.read_probes()),
+                             list(range(0x20, 0x28)) + [0xa5c3])
+
+        run_simulation(tb, gen())
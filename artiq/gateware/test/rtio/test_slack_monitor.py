@@ -0,0 +1,55 @@
+import unittest
+
+from migen import *
+
+from artiq.gateware.rtio.cri import SlackMonitor, Interface, commands
+
+
+class _TSC:
+    def __init__(self):
+        self.full_ts_sys = Signal(64)
+
+
+class TestSlackMonitor(unittest.TestCase):
+    def test_min_slack(self):
+        tsc = _TSC()
+        cri = Interface()
+        dut = SlackMonitor(tsc, cri)
+
+        def write(timestamp, counter):
+            yield tsc.full_ts_sys.eq(counter)
+            yield cri.o_timestamp.eq(timestamp)
+            yield cri.cmd.eq(commands["write"])
+            yield
+            yield cri.cmd.eq(commands["nop"])
+            yield
+
+        def read():
+            yield dut.update.re.eq(1)
+            yield
+            yield dut.update.re.eq(0)
+            yield
+            min_slack = yield dut.min_slack.status
+            if min_slack >= 2**63:
+                min_slack -= 2**64
+            events = yield dut.events.status
+            return min_slack, events
+
+        def gen():
+            yield from write(1000, 200)
+            yield from write(1500, 1400)
+            yield from write(2000, 1600)
+            yield
+            self.assertEqual((yield from read()), (100, 3))
+
+            yield from write(2000, 2050)
+            yield
+            self.assertEqual((yield from read()), (-50, 4))
+
+            yield dut.reset.re.eq(1)
+            yield
+            yield dut.reset.re.eq(0)
+            yield
+            self.assertEqual((yield from read()), (2**63 - 1, 0))
+
+        run_simulation(dut, gen())
@@ -0,0 +1,72 @@
+import unittest
+
+from migen import *
+from misoc.interconnect import wishbone
+
+from artiq.gateware.ethmac_dma import EthmacDMA
+
+
+class TB(Module):
+    def __init__(self):
+        self.submodules.dut = EthmacDMA()
+        self.submodules.memory = wishbone.SRAM(
+            256, init=list(range(64)), bus=self.dut.bus)
+
+
+class TestEthmacDMA(unittest.TestCase):
+    def test_gather(self):
+        tb = TB()
+        dut = tb.dut
+
+        def push(source, destination, length, last):
+            while not (yield dut.ready.status):
+                yield
+            yield dut.source.storage.eq(source >> 2)
+            yield dut.destination.storage.eq(destination >> 2)
+            yield dut.length.storage.eq(length)
+            yield dut.last.storage.eq(last)
+            yield dut.push.re.eq(1)
+            yield
+            yield dut.push.re.eq(0)
+            yield
+
+        def gen():
+            # A frame gathered from two segments, the last one not a
+            # multiple of a word long.
+            yield from push(0x10, 0x80, 8, 0)
+            yield from push(0x40, 0x88, 6, 1)
+            # An empty frame.
+            yield from push(0x00, 0x90, 0, 1)
+            for _ in range(100):
+                yield
+            self.assertEqual((yield dut.completed.status), 2)
+            destination = []
+            for i in range(0x80//4, 0x80//4 + 6):
+                destination.append((yield tb.memory.mem[i]))
+            self.assertEqual(destination, [4, 5, 16, 17, 36, 37])
+
+        run_simulation(tb, gen())
+
+    def test_queue(self):
+        tb = TB()
+        dut = tb.dut
+
+        def gen():
+            for i in range(4):
+                yield dut.source.storage.eq(0)
+                yield dut.destination.storage.eq((0x80 >> 2) + 8*i)
+                yield dut.length.storage.eq(32)
+                yield dut.last.storage.eq(1)
+                yield dut.push.re.eq(1)
+                yield
+            yield dut.push.re.eq(0)
+            completed = []
+            for _ in range(400):
+                completed.append((yield dut.completed.status))
+                yield
+            self.assertEqual(sorted(completed), completed)
+            self.assertEqual(completed[-1], 4)
+            for i in range(0x80//4, 0x80//4 + 32):
+                self.assertEqual((yield tb.memory.mem[i]), i % 8)
+
+        run_simulation(tb, gen())
@@ -0,0 +1,57 @@
+from migen import *
+
+from misoc.interconnect.csr import *
+from misoc.interconnect.csr_eventmanager import *
+
+
+# Watchdog of the comms CPU. While timeout_ms is not zero, the firmware must
+# write feed at least every timeout_ms milliseconds. Otherwise an interrupt
+# is raised, so that the firmware can record where it was stuck, and if it
+# is still not fed after timeout_ms milliseconds more, reconfigure is
+# asserted, which reconfigures the FPGA as at power-up when connected to
+# ICAP.reconfigure_golden.
+class Watchdog(Module, AutoCSR):
+    def __init__(self, sys_clk_freq):
+        self.timeout_ms = CSRStorage(16)
+        self.feed = CSR()
+
+        self.reconfigure = Signal()
+
+        self.submodules.ev = EventManager()
+        self.ev.expired = EventSourcePulse()
+        self.ev.finalize()
+
+        # # #
+
+        prescaler_max = int(sys_clk_freq/1000) - 1
+        prescaler = Signal(max=prescaler_max + 1)
+        tick = Signal()
+        self.sync += [
+            tick.eq(0),
+            If(prescaler == prescaler_max,
+                prescaler.eq(0),
+                tick.eq(1)
+            ).Else(
+                prescaler.eq(prescaler + 1)
+            )
+        ]
+
+        elapsed = Signal(16)
+        expired = Signal()
+        timeout = Signal()
+        self.comb += [
+            timeout.eq(tick & (elapsed == self.timeout_ms.storage)),
+            self.ev.expired.trigger.eq(timeout & ~expired)
+        ]
+        self.sync += [
+            If(self.feed.re | self.timeout_ms.re | (self.timeout_ms.storage == 0),
+                elapsed.eq(0),
+                expired.eq(0)
+            ).Elif(timeout,
+                elapsed.eq(0),
+                expired.eq(1),
+                If(expired, self.reconfigure.eq(1))
+            ).Elif(tick,
+                elapsed.eq(elapsed + 1)
+            )
+        ]
@@ -0,0 +1,60 @@
+from migen import *
+
+from misoc.interconnect.csr import *
+
+
+# Continuously measures the die temperature and the internal supply voltages
+# with the XADC of 7-series FPGAs, and exposes the last 12-bit results.
+# Temperature in °C is code*503.975/4096 - 273.15, voltages in V are
+# code*3/4096 (UG480).
+class XADC(Module, AutoCSR):
+    def __init__(self, sys_clk_freq=125e6):
+        self.temperature = CSRStatus(12)
+        self.vccint = CSRStatus(12)
+        self.vccaux = CSRStatus(12)
+        self.vccbram = CSRStatus(12)
+
+        # The ADC clock must not exceed 26MHz.
+        clk_div = 8
+        assert sys_clk_freq/clk_div <= 26e6
+
+        channel = Signal(7)
+        channel_r = Signal(7)
+        eoc = Signal()
+        data = Signal(16)
+        drdy = Signal()
+
+        self.specials += Instance("XADC",
+            # averaging of 16 samples, calibration enabled
+            p_INIT_40=0x9000,
+            # continuous sequence mode, alarms disabled
+            p_INIT_41=0x2ef0,
+            p_INIT_42=clk_div << 8,
+            # sequence: calibration, temperature, VCCINT, VCCAUX, VCCBRAM
+            p_INIT_48=0x4701, p_INIT_49=0x0000,
+            # averaging for the same channels
+            p_INIT_4A=0x4700, p_INIT_4B=0x0000,
+
+            i_VP=0, i_VN=0, i_VAUXP=0, i_VAUXN=0,
+            i_CONVST=0, i_CONVSTCLK=0,
+            i_RESET=ResetSignal(),
+            o_CHANNEL=channel, o_EOC=eoc,
+
+            # read the result of each conversion through the DRP
+            i_DCLK=ClockSignal(), i_DADDR=channel, i_DEN=eoc,
+            i_DWE=0, i_DI=0,
+            o_DO=data, o_DRDY=drdy)
+
+        results = {
+            0: self.temperature,
+            1: self.vccint,
+            2: self.vccaux,
+            6: self.vccbram
+        }
+        self.sync += [
+            If(eoc, channel_r.eq(channel)),
+            If(drdy,
+                Case(channel_r, {n: csr.status.eq(data[4:])
+                                 for n, csr in results.items()})
+            )
+        ]
@@ -292,6 +292,13 @@ def set_watchdog_factory(f):
 
 
 def watchdog(timeout):
+    """Context manager that bounds the execution time of its body to
+    ``timeout`` seconds.
+
+    On the host, the worker is terminated when the timeout expires. In a
+    kernel, the core device terminates the kernel and raises
+    :class:`artiq.coredevice.exceptions.WatchdogExpired` on the host.
+    """
     return _watchdog_factory(timeout)
 
 
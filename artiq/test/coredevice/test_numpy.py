@@ -2,7 +2,6 @@ from artiq.experiment import *
 import numpy
 import scipy.special
 from artiq.test.hardware_testbench import ExperimentCase
-from artiq.compiler.targets import CortexA9Target
 from artiq.compiler import math_fns
 
 
@@ -97,11 +96,6 @@ class CompareHostDeviceTest(ExperimentCase):
         names = [
             a for a, _ in math_fns.unary_fp_intrinsics + math_fns.unary_fp_runtime_calls
         ]
-        exp = self.create(_RunOnDevice)
-        if exp.core.target_cls != CortexA9Target:
-            names.remove("exp2")
-            names.remove("log2")
-            names.remove("trunc")
         for name in names:
             op = "numpy.{}(a)".format(name)
             # Avoid 0.5, as numpy.rint's rounding mode currently doesn't match.
@@ -110,8 +104,6 @@ class CompareHostDeviceTest(ExperimentCase):
 
     def test_unary_scipy_fns(self):
         names = [name for name, _ in math_fns.scipy_special_unary_runtime_calls]
-        if self.create(_RunOnDevice).core.target_cls != CortexA9Target:
-            names.remove("gamma")
         for name in names:
             op = "scipy.special.{}(a)".format(name)
             self._test_unaryop(op, 0.5)
@@ -119,10 +111,6 @@ class CompareHostDeviceTest(ExperimentCase):
 
     def test_binary_math_fns(self):
         names = [name for name, _ in math_fns.binary_fp_runtime_calls]
-        exp = self.create(_RunOnDevice)
-        if exp.core.target_cls != CortexA9Target:
-            names.remove("fmax")
-            names.remove("fmin")
         for name in names:
             code = "numpy.{}(a, b)".format(name)
             # Avoid 0.5, as numpy.rint's rounding mode currently doesn't match.
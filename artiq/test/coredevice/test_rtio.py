@@ -196,6 +196,30 @@ class LoopbackCount(EnvExperiment):
         self.set_dataset("count", self.loop_in.count(now_mu()))
 
 
+class LoopbackTimestamps(EnvExperiment):
+    def build(self, npulses):
+        self.setattr_device("core")
+        self.setattr_device("loop_in")
+        self.setattr_device("loop_out")
+        self.npulses = npulses
+
+    @kernel
+    def run(self):
+        timestamps = [int64(0)]*(self.npulses + 1)
+        self.core.reset()
+        self.loop_in.input()
+        self.loop_out.output()
+        delay(5*us)
+        with parallel:
+            self.loop_in.gate_rising(10*us)
+            with sequential:
+                for i in range(self.npulses):
+                    delay(25*ns)
+                    self.loop_out.pulse(25*ns)
+        self.set_dataset("count", self.loop_in.timestamps_mu(now_mu(), timestamps))
+        self.set_dataset("timestamps", timestamps)
+
+
 class IncorrectPulseTiming(Exception):
     pass
 
@@ -486,6 +510,13 @@ class CoredeviceTest(ExperimentCase):
         count = self.dataset_mgr.get("count")
         self.assertEqual(count, npulses)
 
+    def test_loopback_timestamps(self):
+        npulses = 4
+        self.execute(LoopbackTimestamps, npulses=npulses)
+        self.assertEqual(self.dataset_mgr.get("count"), npulses)
+        timestamps = list(self.dataset_mgr.get("timestamps")[:npulses])
+        self.assertEqual(sorted(timestamps), timestamps)
+
     def test_loopback_gate_timing(self):
         self.execute(LoopbackGateTiming)
 
@@ -626,6 +657,27 @@ class _DMA(EnvExperiment):
             self.core_dma.playback(self.trace_name)
         self.delta = now_mu() - start
 
+    @kernel
+    def record_marked(self):
+        with self.core_dma.record(self.trace_name):
+            delay(100*ns)
+            self.ttl_out.on()
+            delay(100*ns)
+            self.ttl_out.off()
+            delay(100*ns)
+            self.core_dma.mark("second")
+            self.ttl_out.on()
+            delay(100*ns)
+            self.ttl_out.off()
+            delay(100*ns)
+
+    @kernel
+    def playback_segment(self, marker, duration_mu):
+        self.core.break_realtime()
+        start = now_mu()
+        self.core_dma.playback_segment(self.trace_name, marker, 0, duration_mu)
+        self.delta = now_mu() - start
+
     @kernel
     def playback_many(self, n, add_delay=False):
         handle = self.core_dma.get_handle(self.trace_name)
@@ -745,6 +797,30 @@ class DMATest(ExperimentCase):
         with self.assertRaises(RTIOUnderflow):
             exp.playback_many(20000)
 
+    def test_dma_segment(self):
+        core_host = self.device_mgr.get_desc("core")["arguments"]["host"]
+
+        exp = self.create(_DMA)
+        channel = exp.ttl_out.channel
+        exp.record_marked()
+
+        get_analyzer_dump(core_host)  # clear analyzer buffer
+        exp.playback_segment("second", np.int64(50))
+        self.assertEqual(exp.delta, 50)
+        dump = decode_dump(get_analyzer_dump(core_host))
+        self.assertEqual(len(dump.messages), 2)
+        self.assertIsInstance(dump.messages[0], OutputMessage)
+        self.assertEqual(dump.messages[0].channel, channel)
+        self.assertEqual(dump.messages[0].data, 1)
+
+        exp.playback_segment("second", np.int64(-1))
+        self.assertEqual(exp.delta, 200)
+
+        with self.assertRaises(exceptions.DMAError):
+            exp.playback_segment("missing", np.int64(-1))
+        with self.assertRaises(exceptions.DMAError):
+            exp.playback_segment("second", np.int64(1000))
+
     def test_handle_invalidation(self):
         exp = self.create(_DMA)
         for mode in [0, 1]:
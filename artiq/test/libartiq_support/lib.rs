@@ -60,7 +60,13 @@ pub mod eh_artiq;
 
 use std::{str, process};
 
-fn terminate(exception: &eh_artiq::Exception, mut _backtrace: &mut [usize]) -> ! {
+fn terminate(exception: &eh_artiq::Exception, context: &[Option<eh_artiq::Exception>],
+             mut _backtrace: &mut [usize]) -> ! {
+    for exception in context.iter().filter_map(|exception| exception.as_ref()) {
+        println!("During handling of {}: {}",
+                 str::from_utf8(exception.name.as_ref()).unwrap(),
+                 str::from_utf8(exception.message.as_ref()).unwrap());
+    }
     println!("Uncaught {}: {} ({}, {}, {})",
              str::from_utf8(exception.name.as_ref()).unwrap(),
              str::from_utf8(exception.message.as_ref()).unwrap(),
@@ -0,0 +1,8 @@
+# RUN: %python -m artiq.compiler.testbench.llvmgen %s >%t
+# RUN: OutputCheck %s --file-to-check=%t
+
+def f():
+    # CHECK: call i32 @"?watchdog_set
+    with watchdog(0.5):
+        # CHECK: call void @"?watchdog_clear
+        pass
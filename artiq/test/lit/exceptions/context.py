@@ -0,0 +1,10 @@
+# RUN: %not %python -m artiq.compiler.testbench.jit %s >%t
+# RUN: OutputCheck %s --file-to-check=%t
+# REQUIRES: exceptions
+
+# CHECK-L: During handling of 0:ZeroDivisionError: cannot divide by zero
+# CHECK-L: Uncaught 0:ValueError
+try:
+    1/0
+except ZeroDivisionError:
+    raise ValueError()
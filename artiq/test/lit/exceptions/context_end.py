@@ -0,0 +1,14 @@
+# RUN: %not %python -m artiq.compiler.testbench.jit %s >%t
+# RUN: OutputCheck %s --file-to-check=%t
+# REQUIRES: exceptions
+
+def f():
+    try:
+        1/0
+    except ZeroDivisionError:
+        return
+
+# CHECK-NOT-L: During handling
+# CHECK-L: Uncaught 0:ValueError
+f()
+raise ValueError()
@@ -0,0 +1,6 @@
+# RUN: %python -m artiq.compiler.testbench.inferencer +diag %s >%t
+# RUN: OutputCheck %s --file-to-check=%t
+
+# CHECK-L: ${LINE:+1}: error: the 'watchdog' context manager must be the only one in a 'with' statement
+with watchdog(1.0), sequential:
+    pass
@@ -13,8 +13,8 @@ class TestFrontends(unittest.TestCase):
             ],
             "artiq": [
                 "client", "compile", "coreanalyzer", "coremgmt",
-                "flash", "master", "mkfs", "route",
-                "rtiomon", "run", "session", "browser", "dashboard"
+                "flash", "master", "mkfs", "route", "rtiomap",
+                "rtiomon", "run", "session", "sign", "browser", "dashboard"
             ]
         }
 
@@ -0,0 +1,54 @@
+import struct
+import unittest
+import zlib
+
+from artiq.frontend import artiq_sign
+
+
+# RFC 8032, section 7.1
+VECTORS = [
+    ("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+     "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+     "",
+     "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555"
+     "fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"),
+    ("c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+     "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+     "af82",
+     "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac"
+     "18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a"),
+]
+
+
+def make_image(firmware):
+    return struct.pack(">LL", len(firmware), zlib.crc32(firmware)) + firmware
+
+
+class TestSign(unittest.TestCase):
+    def test_vectors(self):
+        for secret, public, message, signature in VECTORS:
+            secret, public, message, signature = map(
+                bytes.fromhex, (secret, public, message, signature))
+            self.assertEqual(artiq_sign.public_key(secret), public)
+            self.assertEqual(artiq_sign.sign(secret, message), signature)
+            self.assertTrue(artiq_sign.verify(public, message, signature))
+            self.assertFalse(artiq_sign.verify(public, message + b"\x00",
+                                               signature))
+
+    def test_image(self):
+        secret = bytes(range(32))
+        public = artiq_sign.public_key(secret)
+        image = make_image(b"firmware")
+
+        self.assertEqual(artiq_sign.split_image(image), (b"firmware", None))
+        signed = artiq_sign.sign_image(secret, image)
+        firmware, signature = artiq_sign.split_image(signed)
+        self.assertEqual(firmware, b"firmware")
+        self.assertTrue(artiq_sign.verify(public, firmware, signature))
+        # signing again replaces the signature
+        self.assertEqual(artiq_sign.sign_image(secret, signed), signed)
+
+        with self.assertRaises(ValueError):
+            artiq_sign.split_image(image[:-1])
+        with self.assertRaises(ValueError):
+            artiq_sign.split_image(signed[:-1])